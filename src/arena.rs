@@ -0,0 +1,66 @@
+//! Scratch memory for data that shading needs only for a moment, like the spans of a ray inside CSG solids.
+//!
+//! Every thread bump allocates out of an arena of its own, and frees all of it at once when the outermost user is
+//! done, instead of going to the allocator for every small `Vec`. The memory is kept for the next sample, so once
+//! the arena has grown to what a path needs, tracing doesn't allocate at all. Materials need none of it, as
+//! `Texture::value` returns its `SurfaceMaterial` by value.
+//!
+//! ```
+//! # extern crate rayer;
+//! # extern crate bumpalo;
+//! # use bumpalo::collections::Vec;
+//! # use rayer::arena;
+//! let sum = arena::with(|arena| {
+//!     let mut squares = Vec::new_in(arena);
+//!     squares.extend((1..=4).map(|i| i*i));
+//!     squares.iter().sum::<i32>()
+//! });
+//! assert_eq!(sum, 30);
+//! ```
+
+use bumpalo::Bump;
+use std::cell::RefCell;
+
+thread_local! {
+    static ARENA: RefCell<Bump> = RefCell::new(Bump::new());
+}
+
+/// Run `f` with the arena of this thread. Calls nest, sharing the arena, and what they allocated is freed when the
+/// outermost one returns. Nothing allocated in the arena can be returned, so nothing outlives the reset.
+pub fn with<R, F: FnOnce(&Bump) -> R>(f: F) -> R {
+    ARENA.with(|arena| {
+        let result = f(&arena.borrow());
+        // Only the outermost call gets to borrow it mutably
+        if let Ok(mut arena) = arena.try_borrow_mut() {
+            arena.reset();
+        }
+        result
+    })
+}
+
+/// The bytes this thread's arena holds on to for the next sample.
+pub fn capacity() -> usize {
+    ARENA.with(|arena| arena.borrow().allocated_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bumpalo::collections::Vec;
+
+    #[test]
+    fn test_nested() {
+        let (outer, inner) = with(|arena| {
+            let outer = Vec::from_iter_in(0..100u64, arena);
+            let inner = with(|arena| Vec::from_iter_in(0..100u64, arena).iter().sum::<u64>());
+            // The nested call doesn't free what the outer one still uses
+            (outer.iter().sum::<u64>(), inner)
+        });
+        assert_eq!((outer, inner), (4950, 4950));
+        // Freed, but kept for the next sample
+        let kept = capacity();
+        assert!(kept > 0);
+        with(|arena| Vec::from_iter_in(0..100u64, arena).len());
+        assert_eq!(capacity(), kept);
+    }
+}
@@ -0,0 +1,151 @@
+//! Baking the light falling on a mesh into a texture, laid out by the texture coordinates of the mesh like the
+//! lightmaps of games.
+//!
+//! The triangles are drawn into the texture by their texture coordinates, and every texel whose center they cover
+//! stands for the point of the mesh there. What is baked is up to the caller, who traces from those points instead of
+//! from a camera. Texels along the edges of the layout are padded with the values next to them, so that filtering the
+//! texture doesn't bleed the background into the seams.
+
+use euclid::*;
+
+use hitable::wavefront::ObjMesh;
+
+/// Texels around the layout that take the value of the nearest texel in it.
+pub const PADDING: u32 = 4;
+
+/// A texel covered by the layout, with the point of the mesh it stands for.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Texel {
+    /// Index of the texel in the texture, in row major order with the top row, where v is 1, first.
+    pub pixel: usize,
+    pub p: Point3D<f32, UnknownUnit>,
+    /// The normal of the triangle, on the side of its vertex normals, so out of the object.
+    pub normal: Vector3D<f32, UnknownUnit>,
+}
+
+/// The texels of a `width` by `height` texture covered by the triangles of `mesh`, in the order of the triangles.
+/// A texel covered by several triangles goes to the first, and triangles with a corner without texture coordinates
+/// are left out. Texture coordinates repeating outside of 0 to 1 are cut off rather than wrapped around.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::bake::*;
+/// # use rayer::hitable::wavefront::ObjMesh;
+/// let quad = ObjMesh::parse(b"v 0 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n", |_, _| {}).unwrap();
+/// let texels = texels(&quad, 4, 4);
+/// assert_eq!(texels.len(), 16);
+/// // The top left texel is near the corner at v = 1
+/// let corner = texels.iter().find(|texel| texel.pixel == 0).unwrap();
+/// assert_eq!((corner.p.x, corner.p.y), (0.25, 1.75));
+/// ```
+pub fn texels(mesh: &ObjMesh, width: u32, height: u32) -> Vec<Texel> {
+    let mut taken = vec![false; (width*height) as usize];
+    let mut texels = Vec::new();
+    for &[a, b, c] in mesh.triangles.iter() {
+        let (uv_a, uv_b, uv_c) = match (a.uv, b.uv, c.uv) {
+            (Some(uv_a), Some(uv_b), Some(uv_c)) => (mesh.uvs[uv_a as usize], mesh.uvs[uv_b as usize], mesh.uvs[uv_c as usize]),
+            _ => continue,
+        };
+        let vert = (mesh.positions[a.position as usize], mesh.positions[b.position as usize], mesh.positions[c.position as usize]);
+        let face = (vert.1 - vert.0).cross(vert.2 - vert.0);
+        let shading = [a, b, c].iter().map(|corner| corner.normal.map_or(face, |i| mesh.normals[i as usize]))
+            .fold(vec3(0.0, 0.0, 0.0), |sum, normal| sum + normal);
+        let normal = if face.dot(shading) < 0.0 { -face.normalize() } else { face.normalize() };
+        if !normal.x.is_finite() {
+            continue;
+        }
+        // Corners in texels, down from the top
+        let corner = |uv: Vector2D<f32, UnknownUnit>| Point2D::<f32, UnknownUnit>::new(uv.x*width as f32, (1.0 - uv.y)*height as f32);
+        let (t0, t1, t2) = (corner(uv_a), corner(uv_b), corner(uv_c));
+        let area = (t1 - t0).cross(t2 - t0);
+        if area == 0.0 {
+            continue;
+        }
+        let low = t0.min(t1).min(t2);
+        let high = t0.max(t1).max(t2);
+        let (x0, y0) = (low.x.floor().max(0.0) as u32, low.y.floor().max(0.0) as u32);
+        let (x1, y1) = (high.x.ceil().min(width as f32) as u32, high.y.ceil().min(height as f32) as u32);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let pixel = (y*width + x) as usize;
+                let center = point2(x as f32 + 0.5, y as f32 + 0.5);
+                // Barycentric coordinates of the center, all positive inside whichever way the triangle winds
+                let u = (t2 - t0).cross(center - t0)/-area;
+                let v = (t1 - t0).cross(center - t0)/area;
+                let w = 1.0 - u - v;
+                if taken[pixel] || u < 0.0 || v < 0.0 || w < 0.0 {
+                    continue;
+                }
+                taken[pixel] = true;
+                let p = (vert.0.to_vector()*w + vert.1.to_vector()*u + vert.2.to_vector()*v).to_point();
+                texels.push(Texel { pixel, p, normal });
+            }
+        }
+    }
+    texels
+}
+
+/// The texels of a `width` by `height` texture that are `texels` or fewer steps outside of the covered ones, each with
+/// the covered texel it takes its value from.
+pub fn padding(covered: &[bool], width: u32, height: u32, texels: u32) -> Vec<(usize, usize)> {
+    let mut sources: Vec<Option<usize>> = covered.iter().enumerate().map(|(pixel, &covered)| if covered { Some(pixel) } else { None }).collect();
+    let mut padding = Vec::new();
+    for _ in 0..texels {
+        let mut grown = Vec::new();
+        for pixel in 0..sources.len() {
+            if sources[pixel].is_some() {
+                continue;
+            }
+            let (x, y) = (pixel as u32 % width, pixel as u32/width);
+            let neighbours = [
+                (x > 0, pixel.wrapping_sub(1)),
+                (x + 1 < width, pixel + 1),
+                (y > 0, pixel.wrapping_sub(width as usize)),
+                (y + 1 < height, pixel + width as usize),
+            ];
+            if let Some(source) = neighbours.iter().filter(|&&(inside, _)| inside).filter_map(|&(_, neighbour)| sources[neighbour]).next() {
+                grown.push((pixel, source));
+            }
+        }
+        if grown.is_empty() {
+            break;
+        }
+        for &(pixel, source) in grown.iter() {
+            sources[pixel] = Some(source);
+        }
+        padding.extend(grown);
+    }
+    padding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_texels() {
+        // Half of the layout, with the second triangle over the first and one without texture coordinates
+        let mesh = ObjMesh::parse(b"v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n\
+            vt 0 0\nvt 1 0\nvt 0 1\n\
+            vn 0 0 -1\n\
+            f 1/1/1 2/2/1 3/3/1\nf 1/1 3/3 4/2\nf 1 2 4\n", |_, _| {}).unwrap();
+        let texels = texels(&mesh, 8, 4);
+        assert_eq!(texels.len(), 1 + 3 + 5 + 7);
+        assert!(texels.iter().all(|texel| texel.normal == vec3(0.0, 0.0, -1.0)));
+        assert!(texels.iter().all(|texel| texel.p.z == 0.0));
+        let bottom_left = texels.iter().find(|texel| texel.pixel == 24).unwrap();
+        assert_eq!((bottom_left.p.x, bottom_left.p.y), (0.0625, 0.125));
+    }
+
+    #[test]
+    fn test_padding() {
+        let mut covered = vec![false; 5*3];
+        covered[7] = true;
+        let padding = padding(&covered, 5, 3, 2);
+        // Only the corners of the texture are more than 2 steps away
+        assert_eq!(padding.len(), 10);
+        assert!(padding.iter().all(|&(_, source)| source == 7));
+        assert!(padding.iter().any(|&(pixel, _)| pixel == 5));
+        assert!(!padding.iter().any(|&(pixel, _)| pixel == 0));
+    }
+}
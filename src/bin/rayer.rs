@@ -12,8 +12,8 @@ extern crate pbr;
 extern crate rayon;
 extern crate tempfile;
 
-use clap::{Arg, Command};
-use crossbeam_channel::{unbounded, Sender};
+use clap::{Arg, ArgMatches, Command, ErrorKind, ValueSource};
+use crossbeam_channel::{select, unbounded, Sender};
 use euclid::*;
 use image::codecs::hdr::*;
 use num_traits::Float;
@@ -22,91 +22,255 @@ use palette::pixel::Srgb;
 use palette::white_point::E;
 use pbr::ProgressBar;
 use rayon::prelude::*;
-use std::collections::HashMap;
 use std::io::Write;
-use std::path::Path;
-use std::str::FromStr;
-use std::sync::Arc;
+use std::net::{TcpListener, TcpStream};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use rayer::*;
 
-use color::HasReflectance;
-use hitable::Hitable;
+use cli::{whole_number, positive, decimal, pair, parsed, grading_args, grading_from_matches, settings_args, settings_from_matches, CameraOverrides};
+
+use hitable::{Hitable, HitRecord, ShadingRate, TMin};
 use hitable::bvh::*;
 use hitable::sphere::*;
 use hitable::triangle::*;
+use hitable::quad::Quad;
 use hitable::instance::*;
+use hitable::heightfield::Heightfield;
+use hitable::csg;
+use hitable::medium;
+use hitable::subdivision;
+use hitable::curve::{ControlPoint, Curves, CurveKind};
+use hitable::point_cloud::{PointCloud, Surfel};
+use light_paths::{PassTracker, PathPass, PathPasses};
 use material::*;
 use random::*;
+use sampler::*;
+use scene::*;
 use texture::Texture;
 
-fn color<H: Hitable>(r: ray::Ray, world: &H, render_sky: bool) -> Xyz<E, f32> {
-    let refl = reflectance(r, world, render_sky);
-    color::xyz_from_wavelength(r.wl) * refl
+/// The light of a scene besides its emitting surfaces, and the sensor recording it.
+#[derive(Clone, Copy)]
+struct Lighting<'a> {
+    sensor: &'a color::Sensor,
+    /// What rays leaving the scene see, darkness without one.
+    sky: Option<sky::Sky>,
+    /// The lights diffuse surfaces aim at.
+    lights: &'a light_bvh::LightBVH,
+}
+
+/// The light arriving along `r` as the sensor of `lighting` records it, split by the way it came, and whether `r` hit
+/// anything at all.
+/// With `skip_caustics` the light a light tracer covers is left out, see `CausticTracker`, and `watchdog` drops
+/// radiance that isn't a finite number.
+fn color<H: Hitable>(r: ray::Ray, world: &H, t_min: TMin, default_rate: ShadingRate, lighting: Lighting, skip_caustics: bool, watchdog: &render::Watchdog) -> (PathPasses<Xyz<E, f32>>, bool) {
+    let (refl, hit) = reflectance(r, world, t_min, default_rate, lighting, skip_caustics, watchdog);
+    let response = lighting.sensor.xyz(r.wl);
+    (refl.map(|refl| response * refl), hit)
 }
 
-fn reflectance<H: Hitable>(r: ray::Ray, world: &H, render_sky: bool) -> f32 {
+fn reflectance<H: Hitable>(r: ray::Ray, world: &H, t_min: TMin, default_rate: ShadingRate, lighting: Lighting, skip_caustics: bool, watchdog: &render::Watchdog) -> (PathPasses<f32>, bool) {
+    let Lighting { sky, lights, .. } = lighting;
     let mut r = r;
-    let mut res = 0.0;
+    let mut res = PathPasses::default();
     let mut attenuation_acc = 1.0;
-    for _ in 0..50 {
-        let rec = world.hit(r, f32::sqrt(f32::epsilon()), f32::max_value());
+    let mut caustics = light_tracing::CausticTracker::default();
+    let mut passes = PassTracker::default();
+    // Diffuse surfaces aim at the sun and the emitting surfaces among the lights themselves, so the ray leaving one
+    // must not find them again
+    let mut aimed_at_sun = false;
+    let mut aimed_at_surfaces = false;
+    for depth in 0.. {
+        let rec = world.hit(r, t_min.t_min(r), f32::max_value());
         match rec {
             Some(rec) => {
+                let rate = rec.shading_rate.unwrap_or(default_rate);
                 let mat = rec.texture.value(rec.uv);
+                let transmittance = r.transmittance(rec.t);
+                attenuation_acc *= watchdog.check(transmittance, || render::NonFinite::at("transmittance", transmittance, r, &rec, &mat));
                 let mat_res = mat.scatter(r, rec);
-                res += mat_res.emittance*attenuation_acc;
+                let aimed = aimed_at_surfaces && mat_res.emittance != 0.0 && lights.emits_at(r, rec.t);
+                if !(aimed || skip_caustics && caustics.is_caustic()) {
+                    let emittance = watchdog.check(mat_res.emittance, || render::NonFinite::at("emission", mat_res.emittance, r, &rec, &mat));
+                    res.add(passes.pass(), emittance*attenuation_acc);
+                }
+                if let Some((_, ray)) = mat_res.reflection {
+                    passes.scatter(mat.is_diffuse(), rec.reflects(ray.direction));
+                }
+                aimed_at_sun = false;
+                aimed_at_surfaces = false;
+                if let (Some((albedo, _)), true) = (mat_res.reflection, mat.is_diffuse()) {
+                    // The light tracer covers the light of surfaces reaching this one once the path is a caustic
+                    let surfaces = !(skip_caustics && caustics.is_caustic());
+                    let (direct, aimed) = direct_light(r, &rec, world, t_min, sky, lights, surfaces);
+                    let direct = watchdog.check(albedo*direct, || render::NonFinite::at("direct light", albedo*direct, r, &rec, &mat));
+                    res.add(passes.pass(), attenuation_acc*direct);
+                    aimed_at_sun = aimed;
+                    aimed_at_surfaces = surfaces;
+                }
+                if skip_caustics {
+                    caustics.scatter(depth, mat.is_diffuse());
+                }
+                if depth+1 >= rate.max_depth {
+                    return (res, true);
+                }
                 match mat_res.reflection {
-                    None => { return res; },
+                    None => { return (res, true); },
                     Some((attenuation, ray)) => {
                         r = ray;
-                        attenuation_acc *= attenuation;
+                        attenuation_acc *= watchdog.check(attenuation, || render::NonFinite::at("reflection", attenuation, r, &rec, &mat));
+                        if attenuation_acc < rate.roulette_threshold {
+                            let survival = attenuation_acc / rate.roulette_threshold;
+                            if sample_1d() >= survival {
+                                return (res, true);
+                            }
+                            attenuation_acc /= survival;
+                        }
                     }
                 }
             },
             None => {
-                if render_sky {
-                    let unit_direction = r.direction.normalize();
-                    let t: f32 = (unit_direction.y + 1.0)*0.5;
-                    let rgb = Rgb::with_wp(1.0, 1.0, 1.0)*(1.0-t) + Rgb::with_wp(0.5, 0.7, 1.0)*t;
-                    res += rgb.reflect(r.wl)*attenuation_acc;
+                if let Some(sky) = sky {
+                    let radiance = sky.radiance(r, !aimed_at_sun);
+                    res.add(passes.pass(), watchdog.check(radiance, || render::NonFinite::missed("sky", radiance, r))*attenuation_acc);
                 }
-                return res;
+                return (res, depth > 0);
             }
         }
     }
-    return res;
+    (res, true)
 }
 
-pub struct Scene {
-    objects: Vec<Arc<dyn Hitable>>,
-    look_from: Point3D<f32, UnknownUnit>,
-    look_at: Point3D<f32, UnknownUnit>,
-    focus_dist: f32,
-    aperture: f32,
-    vfov: f32,
-    render_sky: bool,
+/// The light reaching the diffuse surface at `rec` straight from the sun of `sky` and from `lights`, which paths aim at
+/// instead of finding them by chance, divided by π so times the albedo it is the light the surface reflects.
+/// Beyond a handful of lights it aims at one of them, picked by how much it is likely to light the surface.
+/// Lights with a surface are left out unless `surfaces` is set.
+/// Also whether it aimed at the sun, which the ray leaving the surface mustn't find again.
+fn direct_light<H: Hitable>(r: ray::Ray, rec: &HitRecord, world: &H, t_min: TMin, sky: Option<sky::Sky>, lights: &light_bvh::LightBVH, surfaces: bool) -> (f32, bool) {
+    let normal = rec.facing_normal().normalize();
+    let received = |direction: Vector3D<f32, UnknownUnit>, distance: f32, light: f32| {
+        let cos = direction.dot(normal);
+        let shadow = rec.spawn_ray(r, direction).with_kind(ray::RayKind::Shadow);
+        if cos > 0.0 && !world.is_occluded(shadow, t_min.t_min(shadow), distance.min(f32::max_value())) {
+            light*cos
+        } else {
+            0.0
+        }
+    };
+    let mut res = 0.0;
+    let sun = sky.filter(sky::Sky::has_sun);
+    if let Some(sky) = sun {
+        let sample = sample_2d();
+        if let Some((direction, light)) = sky.sample_sun(sample.x, sample.y, r.wl) {
+            res += received(direction, f32::max_value(), light);
+        }
+    }
+    for light in lights.directional() {
+        if let Some(sample) = light.sample(rec.p, sample_2d(), r.wl) {
+            res += received(sample.direction, sample.distance, sample.irradiance);
+        }
+    }
+    let mut aim = |light: &light_bvh::TreeLight, probability: f32| {
+        if let (light_bvh::TreeLight::Surface(_), false) = (light, surfaces) {
+            return;
+        }
+        if let Some(sample) = light.sample(rec.p, sample_2d(), r.wl) {
+            res += received(sample.direction, sample.distance, sample.irradiance)/probability;
+        }
+    };
+    if lights.lights().len() <= light_bvh::AIM_AT_ALL {
+        for light in lights.lights() {
+            aim(light, 1.0);
+        }
+    } else if let Some((i, probability)) = lights.sample(rec.p, normal, sample_1d()) {
+        aim(&lights.lights()[i], probability);
+    }
+    (res/std::f32::consts::PI, sun.is_some())
 }
 
-fn just_earth() -> Scene {
-    let image = Arc::new(image::open("data/earth.jpg").unwrap().to_rgb8());
+/// Follow a camera ray through the surfaces that aren't diffuse, like glass and mirrors, to the first diffuse one,
+/// where photon mapping gathers the light.
+/// Returns the light seen on the way, whether the camera ray hit anything,
+/// and the ray hitting that diffuse surface with its hit and the attenuation up to it.
+fn visible_point<'a, H: Hitable>(r: ray::Ray, world: &'a H, t_min: TMin, default_rate: ShadingRate, sky: Option<sky::Sky>) -> (f32, bool, Option<(ray::Ray, HitRecord<'a>, f32)>) {
+    let mut r = r;
+    let mut res = 0.0;
+    let mut attenuation_acc = 1.0;
+    for depth in 0.. {
+        let rec = match world.hit(r, t_min.t_min(r), f32::max_value()) {
+            Some(rec) => rec,
+            None => {
+                if let Some(sky) = sky {
+                    res += sky.radiance(r, true)*attenuation_acc;
+                }
+                return (res, depth > 0, None);
+            }
+        };
+        attenuation_acc *= r.transmittance(rec.t);
+        let mat = rec.texture.value(rec.uv);
+        if mat.is_diffuse() {
+            return (res, true, Some((r, rec, attenuation_acc)));
+        }
+        let mat_res = mat.scatter(r, rec);
+        res += mat_res.emittance*attenuation_acc;
+        if depth+1 >= rec.shading_rate.unwrap_or(default_rate).max_depth {
+            return (res, true, None);
+        }
+        match mat_res.reflection {
+            None => { return (res, true, None); },
+            Some((attenuation, ray)) => {
+                r = ray;
+                attenuation_acc *= attenuation;
+            }
+        }
+    }
+    (res, true, None)
+}
+
+fn just_earth(loader: &Loader) -> Scene {
+    let image = loader.images(&["data/earth.jpg"]).unwrap().remove(0);
     let texture: Arc<dyn Texture> = Arc::new(texture::ImageTexture::new(&image));
     let objects: Vec<Arc<dyn Hitable>> = vec![
         Arc::new(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture)),
     ];
 
-    let look_from = Point3D::new(3.0, -1.0, -1.5);
-    let look_at = Point3D::new(0.0, 0.0, 0.0);
-    let aperture = 0.0;
-    let vfov = 35.0;
-    let focus_dist = (look_from-look_at).length();
-    let render_sky = true;
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(3.0, -1.0, -1.5), Point3D::new(0.0, 0.0, 0.0), 35.0)
+        .scene()
+}
+
+fn scanned_globe(loader: &Loader) -> Scene {
+    use std::f32::consts::PI;
+    let image = loader.images(&["data/earth.jpg"]).unwrap().remove(0);
+    let earth = texture::ImageTexture::new(&image);
+    // Points spread evenly over the globe along a spiral, as a scanner might have left them, with small gaps between
+    let count = 60_000;
+    let radius = (4.0*PI/count as f32).sqrt()*0.45;
+    let surfels = (0..count).map(|i| {
+        let y = 1.0 - 2.0*(i as f32 + 0.5)/count as f32;
+        let angle = i as f32*2.399_963;
+        let normal = vec3((1.0 - y*y).sqrt()*angle.cos(), y, (1.0 - y*y).sqrt()*angle.sin());
+        let u = 1.0 - (f32::atan2(normal.z, normal.x) + PI)/(2.0*PI);
+        let v = (y.asin() + PI*0.5)/PI;
+        Surfel { center: Point3D::origin() + normal, normal, radius, color: earth.color(vec2(u, v)) }
+    }).collect();
+    let objects: Vec<Arc<dyn Hitable>> = vec![
+        Arc::new(PointCloud::new(surfels)),
+    ];
 
-    Scene { objects, look_from, look_at, aperture, vfov, focus_dist, render_sky }
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(3.0, -1.0, -1.5), Point3D::new(0.0, 0.0, 0.0), 35.0)
+        .scene()
 }
 
-fn three_spheres() -> Scene {
+fn three_spheres(_: &Loader) -> Scene {
     let mat1 = Arc::new(Lambertian::new(Rgb::with_wp(0.1, 0.2, 0.5)));
     let mat2 = Arc::new(Lambertian::new(Rgb::with_wp(0.8, 0.8, 0.0)));
     let mat3 = Arc::new(Metal::new(Rgb::with_wp(0.8, 0.6, 0.2), 1.0));
@@ -120,33 +284,23 @@ fn three_spheres() -> Scene {
         Arc::new(Sphere::new(Point3D::new(-0.75, 0.0, -1.0), -0.20, mat4)),
     ];
 
-    let look_from = Point3D::new(-4.0, 0.7, 3.0);
-    let look_at = Point3D::new(-1.0, 0.0, -1.0);
-    let aperture = 0.1;
-    let vfov = 15.0;
-    let focus_dist = (look_from-look_at).length();
-    let render_sky = true;
-
-    Scene { objects, look_from, look_at, aperture, vfov, focus_dist, render_sky }
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(-4.0, 0.7, 3.0), Point3D::new(-1.0, 0.0, -1.0), 15.0)
+        .aperture(0.1)
+        .scene()
 }
 
-fn many_spheres() -> Scene {
+fn many_spheres(loader: &Loader) -> Scene {
     let glass = Arc::new(Dielectric::SF66);
-    let image = Arc::new(image::open("data/earth.jpg").unwrap().to_rgb8());
+    let image = loader.images(&["data/earth.jpg"]).unwrap().remove(0);
     let ground: Arc<dyn Texture> = Arc::new(texture::ImageTexture::new(&image));
     let sphere0_mat = Arc::new(Lambertian::new(Rgb::with_wp(0.4, 0.2, 0.1)));
     let sphere1_mat = Arc::new(Metal::new(Rgb::with_wp(0.7, 0.6, 0.5), 0.0));
     let mut objects: Vec<Arc<dyn Hitable>> = vec![
-        Arc::new(Triangle::new(
-            (point3(-20.0, 0.0, -30.0), point3(-20.0, 0.0, 30.0), point3(20.0, 0.0, 30.0)),
-            (vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)),
-            (vec2(0.0, 0.0), vec2(0.0, 1.0), vec2(1.0, 1.0)),
-            ground.clone(),
-        )),
-        Arc::new(Triangle::new(
-            (point3(-20.0, 0.0, -30.0), point3(20.0, 0.0, -30.0), point3(20.0, 0.0, 30.0)),
-            (vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)),
-            (vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0)),
+        Arc::new(Quad::uniform(
+            [point3(-20.0, 0.0, -30.0), point3(20.0, 0.0, -30.0), point3(20.0, 0.0, 30.0), point3(-20.0, 0.0, 30.0)],
+            vec3(0.0, 1.0, 0.0),
             ground,
         )),
         Arc::new(Sphere::new(point3(0.0, 1.0, 0.0), 1.0, glass.clone())),
@@ -184,34 +338,25 @@ fn many_spheres() -> Scene {
         }
     }
 
-    let look_from = Point3D::new(13.0, 2.0, 3.0);
-    let look_at = Point3D::new(0.0, 0.0, 0.0);
-    let aperture = 0.1;
-    let vfov = 30.0;
-    let focus_dist = 10.0;
-    let render_sky = true;
-
-    Scene { objects, look_from, look_at, aperture, vfov, focus_dist, render_sky }
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(13.0, 2.0, 3.0), Point3D::new(0.0, 0.0, 0.0), 30.0)
+        .aperture(0.1)
+        .focus_dist(10.0)
+        .scene()
 }
 
-fn simple_light() -> Scene {
+fn simple_light(loader: &Loader) -> Scene {
     let glass = Arc::new(Dielectric::SF66);
     let ground = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
     let light = Arc::new(light::DiffuseLight::new(Rgb::with_wp(5.0, 5.0, 5.0)));
-    let image = Arc::new(image::open("data/earth.jpg").unwrap().to_rgb8());
+    let image = loader.images(&["data/earth.jpg"]).unwrap().remove(0);
     let sphere0_mat: Arc<dyn Texture> = Arc::new(texture::ImageTexture::new(&image));
     let sphere1_mat = Arc::new(Metal::new(Rgb::with_wp(0.7, 0.6, 0.5), 0.0));
     let objects: Vec<Arc<dyn Hitable>> = vec![
-        Arc::new(Triangle::new(
-            (point3(-20.0, 0.0, -30.0), point3(-20.0, 0.0, 30.0), point3(20.0, 0.0, 30.0)),
-            (vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)),
-            (vec2(0.0, 0.0), vec2(0.0, 1.0), vec2(1.0, 1.0)),
-            ground.clone(),
-        )),
-        Arc::new(Triangle::new(
-            (point3(-20.0, 0.0, -30.0), point3(20.0, 0.0, -30.0), point3(20.0, 0.0, 30.0)),
-            (vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)),
-            (vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0)),
+        Arc::new(Quad::uniform(
+            [point3(-20.0, 0.0, -30.0), point3(20.0, 0.0, -30.0), point3(20.0, 0.0, 30.0), point3(-20.0, 0.0, 30.0)],
+            vec3(0.0, 1.0, 0.0),
             ground,
         )),
         Arc::new(Sphere::new(point3(0.0, 1.0, 0.0), 1.0, glass.clone())),
@@ -221,53 +366,110 @@ fn simple_light() -> Scene {
         Arc::new(Sphere::new(point3(0.0, 6.0, 2.0), 2.0, light.clone())),
     ];
 
-    let look_from = Point3D::new(0.0, 2.0, -10.0);
-    let look_at = Point3D::new(0.0, 1.0, 0.0);
-    let aperture = 0.1;
-    let vfov = 30.0;
-    let focus_dist = 10.0;
-    let render_sky = false;
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 2.0, -10.0), Point3D::new(0.0, 1.0, 0.0), 30.0)
+        .aperture(0.1)
+        .focus_dist(10.0)
+        .no_environment()
+        .flare(flare::LensFlare::default())
+        .scene()
+}
 
-    Scene { objects, look_from, look_at, aperture, vfov, focus_dist, render_sky }
+fn glass_catalog(loader: &Loader) -> Scene {
+    let ground = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+    let light = Arc::new(light::DiffuseLight::new(Rgb::with_wp(8.0, 8.0, 8.0)));
+    let glasses: Vec<Arc<dyn Texture>> = vec![
+        Arc::new(Dielectric::BK7),
+        Arc::new(presets::wine_glass()),
+        Arc::new(presets::amethyst()),
+        Arc::new(presets::bottle_glass()),
+        Arc::new(Dielectric::SF66),
+    ];
+    // The interference in the film changes with the angle and the wavelength, which previews can look up instead
+    let bubble = loader.materials(vec![("soap bubble", Arc::new(presets::soap_bubble()))]).remove(0);
+    let mut objects: Vec<Arc<dyn Hitable>> = vec![
+        Arc::new(Quad::uniform(
+            [point3(-20.0, 0.0, -30.0), point3(20.0, 0.0, -30.0), point3(20.0, 0.0, 30.0), point3(-20.0, 0.0, 30.0)],
+            vec3(0.0, 1.0, 0.0),
+            ground,
+        )),
+        Arc::new(Sphere::new(point3(0.0, 8.0, -4.0), 2.0, light)),
+        Arc::new(Sphere::new(point3(0.0, 1.0, 2.5), 1.0, bubble)),
+    ];
+    for (i, glass) in glasses.into_iter().enumerate() {
+        let x = (i as f32 - 2.0)*2.2;
+        objects.push(Arc::new(Sphere::new(point3(x, 1.0, 0.0), 1.0, glass)));
+    }
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 3.0, 12.0), Point3D::new(0.0, 1.0, 0.0), 40.0)
+        .aperture(0.05)
+        .scene()
 }
 
-fn bunny() -> Scene {
+fn bunny(loader: &Loader) -> Scene {
     let light = Arc::new(light::DiffuseLight::new(Rgb::with_wp(5.0, 5.0, 5.0)));
     let ground = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
     let bunny0_mat = Arc::new(Dielectric::SF66);
-    let bunny0 = Mesh::from_obj(Path::new("data/bunny.obj"), bunny0_mat).unwrap();
+    let bunny0 = loader.meshes(vec![("data/bunny.obj", bunny0_mat)]).unwrap().remove(0);
+    // Light bounces around inside the glass a lot, while the floor doesn't need much detail.
+    let bunny0_rate = ShadingRate { max_depth: 64, roulette_threshold: 0.0 };
+    let ground_rate = ShadingRate { max_depth: 4, roulette_threshold: 0.1 };
     let objects: Vec<Arc<dyn Hitable>> = vec![
-        Arc::new(Triangle::new(
-            (point3(-20.0, 0.0, -30.0), point3(-20.0, 0.0, 30.0), point3(20.0, 0.0, 30.0)),
-            (vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)),
-            (vec2(0.0, 0.0), vec2(0.0, 1.0), vec2(1.0, 1.0)),
-            ground.clone(),
-        )),
-        Arc::new(Triangle::new(
-            (point3(-20.0, 0.0, -30.0), point3(20.0, 0.0, -30.0), point3(20.0, 0.0, 30.0)),
-            (vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)),
-            (vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0)),
+        Arc::new(with_shading_rate(Quad::uniform(
+            [point3(-20.0, 0.0, -30.0), point3(20.0, 0.0, -30.0), point3(20.0, 0.0, 30.0), point3(-20.0, 0.0, 30.0)],
+            vec3(0.0, 1.0, 0.0),
             ground,
-        )),
-        Arc::new(bunny0),
+        ), ground_rate)),
+        Arc::new(with_shading_rate(bunny0, bunny0_rate)),
         Arc::new(Sphere::new(point3(0.0, 6.0, -2.0), 2.0, light.clone())),
     ];
 
-    let look_from = Point3D::new(0.0, 2.0, 10.0);
-    let look_at = Point3D::new(0.0, 1.0, 0.0);
-    let aperture = 0.1;
-    let vfov = 30.0;
-    let focus_dist = 10.0;
-    let render_sky = false;
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 2.0, 10.0), Point3D::new(0.0, 1.0, 0.0), 30.0)
+        .aperture(0.1)
+        .focus_dist(10.0)
+        .no_environment()
+        .scene()
+}
 
-    Scene { objects, look_from, look_at, aperture, vfov, focus_dist, render_sky }
+/// Spheres in a uniform white environment, in which materials that neither emit nor absorb light vanish.
+/// From the left: white diffuse, a mirror, rough metal, glass and coated white, which `tests/furnace.rs` checks for that.
+/// The spheres see each other near their edges, so a sphere absorbing light would darken the others too.
+fn furnace(_: &Loader) -> Scene {
+    let white = Rgb::with_wp(1.0, 1.0, 1.0);
+    let materials: Vec<Arc<dyn Texture>> = vec![
+        Arc::new(Lambertian::new(white)),
+        Arc::new(Metal::new(white, 0.0)),
+        Arc::new(Metal::new(white, 0.3)),
+        Arc::new(Dielectric::BK7),
+        Arc::new(coated::Coated::new(Arc::new(Lambertian::new(white)), 1.5).with_roughness(0.1)),
+    ];
+    let objects: Vec<Arc<dyn Hitable>> = materials.into_iter()
+        .enumerate()
+        .map(|(i, material)| Arc::new(Sphere::new(Point3D::new(2.0*i as f32 - 4.0, 0.0, 0.0), 0.9, material)) as Arc<dyn Hitable>)
+        .collect();
+
+    // Far away, so the view is nearly orthographic and every sphere looks the same, two units high
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 0.0, 50.0), Point3D::new(0.0, 0.0, 0.0), 2.0*(1.0f32/50.0).atan().to_degrees())
+        .focus_dist(50.0)
+        .environment(sky::Sky::Uniform { radiance: 1.0 })
+        .settings(settings::SettingsOverrides { width: Some(160), height: Some(32), ..Default::default() })
+        .scene()
 }
 
-fn cornell() -> Scene {
+/// The walls and the ceiling light of the Cornell box, spanning 0 to 555 on every axis.
+fn cornell_box() -> Vec<Arc<dyn Hitable>> {
     let red = Arc::new(Lambertian::new(Rgb::with_wp(0.65, 0.05, 0.05)));
     let white = Arc::new(Lambertian::new(Rgb::with_wp(0.73, 0.73, 0.73)));
     let green = Arc::new(Lambertian::new(Rgb::with_wp(0.12, 0.45, 0.15)));
-    let light = Arc::new(light::DiffuseLight::new(Rgb::with_wp(15.0, 15.0, 15.0)));
+    // Facing down into the box, it would only light the gap to the ceiling from its back
+    let light = Arc::new(light::DiffuseLight::new(Rgb::with_wp(15.0, 15.0, 15.0)).one_sided());
     let up = vec3(0.0, 1.0, 0.0);
     let down = vec3(0.0, -1.0, 0.0);
     let right = vec3(-1.0, 0.0, 0.0);
@@ -310,11 +512,14 @@ fn cornell() -> Scene {
         right,
         green
     ));
-    let mut objects: Vec<Arc<dyn Hitable>> =
-        triangles
+    triangles
         .iter()
         .map(|t| Arc::new(t.clone()) as Arc<dyn Hitable>)
-        .collect();
+        .collect()
+}
+
+fn cornell(loader: &Loader) -> Scene {
+    let mut objects = cornell_box();
 
     let cube_mat = Arc::new(Dielectric::SF66);
     objects.push(Arc::new(
@@ -332,7 +537,10 @@ fn cornell() -> Scene {
     ));
 
     let buddha_mat = Arc::new(Metal::new(Rgb::with_wp(0.7, 0.6, 0.5), 0.5));
-    let buddha = Mesh::from_obj(Path::new("data/buddha.obj"), buddha_mat).unwrap();
+    let bunny_mat = Arc::new(Lambertian::new(Rgb::with_wp(0.7, 0.1, 0.05)));
+    let mut meshes = loader.meshes(vec![("data/buddha.obj", buddha_mat), ("data/bunny.obj", bunny_mat)]).unwrap();
+    let bunny = meshes.pop().unwrap();
+    let buddha = meshes.pop().unwrap();
     objects.push(Arc::new(
         translate(
             scale(
@@ -343,8 +551,6 @@ fn cornell() -> Scene {
         )
     ));
 
-    let bunny_mat = Arc::new(Lambertian::new(Rgb::with_wp(0.7, 0.1, 0.05)));
-    let bunny = Mesh::from_obj(Path::new("data/bunny.obj"), bunny_mat).unwrap();
     objects.push(Arc::new(
         translate(
             scale(
@@ -355,61 +561,1702 @@ fn cornell() -> Scene {
         )
     ));
 
-    let look_from = Point3D::new(278.0, 278.0, -800.0);
-    let look_at = Point3D::new(278.0, 278.0, 0.0);
-    let aperture = 0.0;
-    let vfov = 40.0;
-    let focus_dist = 10.0;
-    let render_sky = false;
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(278.0, 278.0, -800.0), Point3D::new(278.0, 278.0, 0.0), 40.0)
+        .focus_dist(10.0)
+        .no_environment()
+        // The box is as wide as it is high
+        .settings(settings::SettingsOverrides { width: Some(600), height: Some(600), ..Default::default() })
+        .scene()
+}
+
+fn cornell_glass(_: &Loader) -> Scene {
+    let mut objects = cornell_box();
+
+    // The tinted glass colors the caustics on the floor more the longer light travels through it.
+    objects.push(Arc::new(Sphere::new(point3(160.0, 90.0, 170.0), 90.0, Arc::new(presets::wine_glass()))));
+    objects.push(Arc::new(Sphere::new(point3(390.0, 90.0, 200.0), 90.0, Arc::new(presets::bottle_glass()))));
+    objects.push(Arc::new(
+        translate(
+            rotate_y(
+                axis_aligned_cuboid(
+                    point3(0.0, 0.0, 0.0),
+                    point3(120.0, 240.0, 120.0),
+                    Arc::new(presets::amethyst())
+                ),
+                25.0
+            ),
+            vec3(250.0, 0.0, 360.0)
+        )
+    ));
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(278.0, 278.0, -800.0), Point3D::new(278.0, 278.0, 0.0), 40.0)
+        .focus_dist(10.0)
+        .no_environment()
+        // The box is as wide as it is high
+        .settings(settings::SettingsOverrides { width: Some(600), height: Some(600), ..Default::default() })
+        .scene()
+}
+
+/// Wisps of smoke through a smoky quartz, a few Cornell box units across.
+fn smoke_wisps(p: Point3D<f32, UnknownUnit>) -> f32 {
+    let p = p*0.04;
+    let swirl = (p.x + 2.0*(p.y*0.7 + p.z).sin()).sin()*(p.y*1.3 - p.z).cos();
+    (0.5 + 0.5*swirl).powi(3)*2.0
+}
+
+/// Silt settling in water filling a 240 unit high tank, denser towards the bottom.
+fn silt(p: Point3D<f32, UnknownUnit>) -> f32 {
+    let depth = (1.0 - p.y/240.0).max(0.0).min(1.0);
+    depth*depth*4.0
+}
+
+fn murky_glass(_: &Loader) -> Scene {
+    let mut objects = cornell_box();
+
+    let quartz = Dielectric::QUARTZ.with_absorption(Rgb::with_wp(0.45, 0.4, 0.35), 60.0);
+    let water = Dielectric::BK7.with_absorption(Rgb::with_wp(0.55, 0.6, 0.35), 120.0);
+    let smoky_quartz = murky::MurkyDielectric::new(quartz, Arc::new(smoke_wisps as fn(Point3D<f32, UnknownUnit>) -> f32), 5.0);
+    let murky_water = murky::MurkyDielectric::new(water, Arc::new(silt as fn(Point3D<f32, UnknownUnit>) -> f32), 5.0);
+    objects.push(Arc::new(Sphere::new(point3(160.0, 110.0, 190.0), 110.0, Arc::new(smoky_quartz))));
+    objects.push(Arc::new(axis_aligned_cuboid(
+        point3(300.0, 0.0, 200.0),
+        point3(450.0, 240.0, 350.0),
+        Arc::new(murky_water)
+    )));
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(278.0, 278.0, -800.0), Point3D::new(278.0, 278.0, 0.0), 40.0)
+        .focus_dist(10.0)
+        .no_environment()
+        .scene()
+}
+
+fn fog_caustics(_: &Loader) -> Scene {
+    let mut objects = cornell_box();
+
+    // A glass ball hanging under the light focuses it into a cone down to the floor, which shows in the fog
+    let (center, radius) = (point3(278.0, 380.0, 280.0), 80.0);
+    objects.push(Arc::new(Sphere::new(center, radius, Arc::new(Dielectric::BK7))));
+    let fog: Arc<dyn Texture> = Arc::new(Isotropic::new(Rgb::with_wp(0.9, 0.9, 0.9)));
+    // Up to the walls and the light, around the ball
+    let room = csg::difference(
+        csg::Cuboid::new(point3(1.0, 1.0, 1.0), point3(554.0, 553.0, 554.0), fog.clone()),
+        Sphere::new(center, radius, fog.clone()),
+    );
+    objects.push(Arc::new(medium::Medium::new(room, 0.0015, fog)));
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(278.0, 278.0, -800.0), Point3D::new(278.0, 278.0, 0.0), 40.0)
+        .focus_dist(10.0)
+        .no_environment()
+        // The box is as wide as it is high
+        .settings(settings::SettingsOverrides { width: Some(600), height: Some(600), ..Default::default() })
+        .scene()
+}
+
+fn dispersion_prism(_: &Loader) -> Scene {
+    let white = Arc::new(Lambertian::new(Rgb::with_wp(0.73, 0.73, 0.73)));
+    let light = Arc::new(light::DiffuseLight::new(Rgb::with_wp(400.0, 400.0, 400.0)));
+    let glass: Arc<dyn Texture> = Arc::new(Dielectric::SF66);
+    let mut triangles: Vec<Triangle> = Vec::new();
+    triangles.extend(uniform_polygon(
+        &[point3(-20.0, 0.0, -20.0), point3(-20.0, 0.0, 20.0),
+          point3(20.0, 0.0, 20.0), point3(20.0, 0.0, -20.0)],
+        vec3(0.0, 1.0, 0.0),
+        white
+    ));
+
+    // An equilateral prism lying on its side, raised a bit so its base doesn't touch the floor.
+    let h = f32::sqrt(3.0);
+    let base = 0.01;
+    let front = [point3(-1.0, base, 1.5), point3(1.0, base, 1.5), point3(0.0, base + h, 1.5)];
+    let back = [point3(-1.0, base, -1.5), point3(1.0, base, -1.5), point3(0.0, base + h, -1.5)];
+    triangles.extend(uniform_polygon(&front, vec3(0.0, 0.0, 1.0), glass.clone()));
+    triangles.extend(uniform_polygon(&back, vec3(0.0, 0.0, -1.0), glass.clone()));
+    triangles.extend(uniform_polygon(
+        &[back[0], back[1], front[1], front[0]],
+        vec3(0.0, -1.0, 0.0),
+        glass.clone()
+    ));
+    triangles.extend(uniform_polygon(
+        &[back[0], back[2], front[2], front[0]],
+        vec3(-h/2.0, 0.5, 0.0),
+        glass.clone()
+    ));
+    triangles.extend(uniform_polygon(
+        &[back[1], back[2], front[2], front[1]],
+        vec3(h/2.0, 0.5, 0.0),
+        glass
+    ));
+    let mut objects: Vec<Arc<dyn Hitable>> =
+        triangles
+        .iter()
+        .map(|t| Arc::new(t.clone()) as Arc<dyn Hitable>)
+        .collect();
+    // A small, bright light low on the left, so the refracted beam fans out on the floor to the right.
+    objects.push(Arc::new(Sphere::new(point3(-8.0, 1.5, 0.0), 0.25, light)));
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(2.0, 7.0, 9.0), Point3D::new(2.0, 0.5, 0.0), 40.0)
+        .no_environment()
+        .scene()
+}
+
+fn instanced_bunnies(loader: &Loader) -> Scene {
+    let ground = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+    // Every instance shares the BVH of one of these two meshes.
+    let mut meshes = loader.meshes(vec![
+        ("data/bunny.obj", Arc::new(Dielectric::BK7)),
+        ("data/bunny.obj", Arc::new(Lambertian::new(Rgb::with_wp(0.7, 0.1, 0.05)))),
+    ]).unwrap();
+    let clay = meshes.pop().unwrap();
+    let glass = meshes.pop().unwrap();
+    let mut objects: Vec<Arc<dyn Hitable>> = vec![Arc::new(Quad::uniform(
+        [point3(-30.0, 0.0, -30.0), point3(-30.0, 0.0, 30.0), point3(30.0, 0.0, 30.0), point3(30.0, 0.0, -30.0)],
+        vec3(0.0, 1.0, 0.0),
+        ground
+    ))];
+    for i in 0..5 {
+        for j in 0..5 {
+            let mesh = if (i + j) % 2 == 0 { glass.clone() } else { clay.clone() };
+            let offset = vec3((i as f32 - 2.0)*3.0, 0.0, (j as f32 - 2.0)*3.0);
+            let instance = rotate_y(mesh, ((i*5 + j)*37) as f32);
+            if j == 4 {
+                // The front row hops to the right and turns while the shutter is open
+                objects.push(Arc::new(translate(moving(instance, vec3(0.8, 0.3, 0.0), 20.0), offset)));
+            } else {
+                objects.push(Arc::new(translate(instance, offset)));
+            }
+        }
+    }
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 8.0, 18.0), Point3D::new(0.0, 0.5, 0.0), 40.0)
+        .aperture(0.1)
+        .scene()
+}
+
+fn worn_bunny(loader: &Loader) -> Scene {
+    let light = Arc::new(light::DiffuseLight::new(Rgb::with_wp(5.0, 5.0, 5.0)));
+    let ground = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+    // The wear nodes probe a second copy of the mesh, as the material can't refer to the mesh carrying it
+    let probe = loader.meshes(vec![("data/bunny.obj", ground.clone())]).unwrap().remove(0);
+    let albedo = graph::Node::wear(
+        graph::Node::Color(Rgb::with_wp(0.2, 0.35, 0.6)),
+        graph::ProbeGeometry::new(Arc::new(probe)),
+        0.05
+    );
+    let bunny_mat = Arc::new(graph::MaterialNode::Diffuse { albedo });
+    let bunny = loader.meshes(vec![("data/bunny.obj", bunny_mat)]).unwrap().remove(0);
+    let mut objects: Vec<Arc<dyn Hitable>> = vec![Arc::new(Quad::uniform(
+        [point3(-20.0, 0.0, -30.0), point3(-20.0, 0.0, 30.0), point3(20.0, 0.0, 30.0), point3(20.0, 0.0, -30.0)],
+        vec3(0.0, 1.0, 0.0),
+        ground
+    ))];
+    objects.push(Arc::new(bunny));
+    objects.push(Arc::new(Sphere::new(point3(0.0, 6.0, -2.0), 2.0, light)));
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 2.0, 10.0), Point3D::new(0.0, 1.0, 0.0), 30.0)
+        .focus_dist(10.0)
+        .scene()
+}
+
+fn fence(_: &Loader) -> Scene {
+    let ground = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+    let ball = Arc::new(Lambertian::new(Rgb::with_wp(0.7, 0.1, 0.05)));
+    // A lattice of slats, the squares between them cut out
+    let lattice = Arc::new(image::GrayImage::from_fn(64, 64, |x, y| {
+        image::Luma([if x % 16 < 3 || y % 16 < 3 { 255 } else { 0 }])
+    }));
+    let wood: Arc<dyn Texture> = Arc::new(texture::Masked::new(
+        Arc::new(Lambertian::new(Rgb::with_wp(0.45, 0.3, 0.15))),
+        texture::AlphaMask::new(&lattice, 0.5),
+    ));
+    let normal = vec3(0.0, 0.0, 1.0);
+    let corners = [point3(-2.0, 0.0, 1.0), point3(2.0, 0.0, 1.0), point3(2.0, 2.0, 1.0), point3(-2.0, 2.0, 1.0)];
+    let mut objects: Vec<Arc<dyn Hitable>> = vec![Arc::new(Quad::uniform(
+        [point3(-20.0, 0.0, -20.0), point3(-20.0, 0.0, 20.0), point3(20.0, 0.0, 20.0), point3(20.0, 0.0, -20.0)],
+        vec3(0.0, 1.0, 0.0),
+        ground
+    ))];
+    objects.push(Arc::new(Quad::uniform(corners, normal, wood)));
+    objects.push(Arc::new(Sphere::new(point3(0.0, 1.0, -1.0), 1.0, ball)));
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(1.0, 2.0, 6.0), Point3D::new(0.0, 1.0, 0.0), 40.0)
+        .scene()
+}
+
+fn hair(_: &Loader) -> Scene {
+    let ground: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.6, 0.6, 0.6)));
+    let skin: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.3, 0.2)));
+    let auburn: Arc<dyn Texture> = Arc::new(material::hair::Hair::new(Rgb::with_wp(0.55, 0.25, 0.1), 0.15, 0.05));
+    let copper: Arc<dyn Texture> = Arc::new(Metal::new(Rgb::with_wp(0.95, 0.64, 0.54), 0.1));
+    let normal = vec3(0.0, 1.0, 0.0);
+
+    // Strands growing from the upper half of a ball and falling over it
+    let center = point3(0.0, 1.0, 0.0);
+    let strands: Vec<Vec<ControlPoint>> = (0..4000).filter_map(|i| {
+        // Spread evenly over the sphere along a spiral
+        let y = 1.0 - (i as f32 + 0.5)/2000.0;
+        if y < 0.1 {
+            return None;
+        }
+        let angle = i as f32*2.399_963;
+        let n = vec3((1.0 - y*y).sqrt()*angle.cos(), y, (1.0 - y*y).sqrt()*angle.sin());
+        let length = 0.5 + 0.2*next_f32();
+        Some((0..5).map(|k| {
+            let s = k as f32/4.0;
+            let p = center + n*(0.6 + 0.25*s*length) + vec3(0.0, -s*s*length, 0.0)*(1.2 - y);
+            (p, 0.004*(1.0 - 0.7*s))
+        }).collect())
+    }).collect();
+
+    // A wire arching over the ground
+    let wire: Vec<ControlPoint> = (0..9).map(|k| {
+        let a = k as f32/8.0*std::f32::consts::PI;
+        (point3(1.6 + 0.2*(3.0*a).sin(), 0.9*a.sin(), -0.9*a.cos()), 0.03)
+    }).collect();
+
+    let objects: Vec<Arc<dyn Hitable>> = vec![
+        Arc::new(Quad::uniform(
+            [point3(-8.0, 0.0, -8.0), point3(8.0, 0.0, -8.0), point3(8.0, 0.0, 8.0), point3(-8.0, 0.0, 8.0)],
+            normal,
+            ground,
+        )),
+        Arc::new(Sphere::new(center, 0.6, skin)),
+        Arc::new(Curves::catmull_rom(&strands, CurveKind::Flat, auburn)),
+        Arc::new(Curves::catmull_rom(&[wire], CurveKind::Round, copper)),
+    ];
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.5, 1.6, 4.5), Point3D::new(0.4, 0.9, 0.0), 35.0)
+        .scene()
+}
+
+fn pbr_tiles(_: &Loader) -> Scene {
+    use material::pbr::PbrMaterial;
+    use texture::TextureChannel;
+
+    // A texture set for 8x8 tiles with glowing grout: the metal tiles run along a diagonal,
+    // the roughness grows from left to right
+    let size = 256;
+    let tile = |x: u32, y: u32| (x*8/size, y*8/size);
+    let grout = |x: u32, y: u32| x % (size/8) < 2 || y % (size/8) < 2;
+    let base_color = image::RgbImage::from_fn(size, size, |x, y| {
+        let (i, j) = tile(x, y);
+        image::Rgb(if (i + j) % 2 == 0 { [190, 90, 60] } else { [225, 215, 190] })
+    });
+    let packed = image::RgbImage::from_fn(size, size, |x, y| {
+        let (i, j) = tile(x, y);
+        let roughness = (i*255/7) as u8;
+        let metalness = if (i + j) % 3 == 0 { 255 } else { 0 };
+        image::Rgb([255, roughness, metalness])
+    });
+    let glow = image::GrayImage::from_fn(size, size, |x, y| image::Luma([if grout(x, y) { 255 } else { 0 }]));
+    let tiles: Arc<dyn Texture> = Arc::new(
+        PbrMaterial::new(TextureChannel::ColorMap(texture::ImageTexture::new(&Arc::new(base_color))))
+            .with_roughness(TextureChannel::from_channel(&packed, 1, 0.0, 1.0))
+            .with_metalness(TextureChannel::from_channel(&packed, 2, 0.0, 1.0))
+            .with_emission(TextureChannel::Map { image: Arc::new(glow), low: 0.0, high: 1.0 }.scaled(3.0))
+    );
+    let normal = vec3(0.0, 1.0, 0.0);
+    let objects: Vec<Arc<dyn Hitable>> = vec![
+        Arc::new(Quad::new(
+            [point3(-4.0, 0.0, -4.0), point3(4.0, 0.0, -4.0), point3(4.0, 0.0, 4.0), point3(-4.0, 0.0, 4.0)],
+            [normal; 4],
+            [vec2(0.0, 1.0), vec2(1.0, 1.0), vec2(1.0, 0.0), vec2(0.0, 0.0)],
+            tiles,
+        )),
+        Arc::new(Sphere::new(point3(-1.0, 0.7, 0.0), 0.7, Arc::new(Dielectric::BK7))),
+        Arc::new(Sphere::new(point3(1.0, 0.7, -0.5), 0.7, Arc::new(Lambertian::new(Rgb::with_wp(0.2, 0.3, 0.6))))),
+    ];
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 3.5, 6.5), Point3D::new(0.0, 0.3, 0.0), 40.0)
+        .scene()
+}
+
+fn mapped(_: &Loader) -> Scene {
+    use std::f32::consts::PI;
+    use texture::{ImageTexture, Triplanar, UvTransform};
+
+    // Bricks on the polygons, which have no texture coordinates of their own, projected along the axes
+    let bricks = Arc::new(image::RgbImage::from_fn(64, 64, |x, y| {
+        let shift = if (y/16) % 2 == 0 { 0 } else { 16 };
+        image::Rgb(if y % 16 < 2 || (x + shift) % 32 < 2 { [200, 195, 185] } else { [150, 60, 40] })
+    }));
+    let bricks: Arc<dyn Texture> = Arc::new(ImageTexture::new(&bricks));
+    let ground: Arc<dyn Texture> = Arc::new(Triplanar::new(bricks.clone(), 2.0, 4.0));
+    let block: Arc<dyn Texture> = Arc::new(Triplanar::new(bricks, 0.5, 4.0));
+    // Stripes tiled around the ball and turned into a spiral
+    let stripes = Arc::new(image::RgbImage::from_fn(16, 16, |x, _| {
+        image::Rgb(if x < 8 { [30, 60, 160] } else { [230, 200, 60] })
+    }));
+    let ball: Arc<dyn Texture> = Arc::new(
+        UvTransform::new(Arc::new(ImageTexture::new(&stripes))).scaled(8.0, 4.0).rotated(PI/6.0)
+    );
+
+    let mut objects: Vec<Arc<dyn Hitable>> = vec![Arc::new(Quad::uniform(
+        [point3(-20.0, 0.0, -20.0), point3(-20.0, 0.0, 20.0), point3(20.0, 0.0, 20.0), point3(20.0, 0.0, -20.0)],
+        vec3(0.0, 1.0, 0.0),
+        ground
+    ))];
+    objects.push(Arc::new(axis_aligned_cuboid(point3(-2.2, 0.0, -1.0), point3(-0.4, 1.8, 0.8), block)));
+    objects.push(Arc::new(Sphere::new(point3(1.2, 0.9, 0.0), 0.9, ball)));
 
-    Scene { objects, look_from, look_at, aperture, vfov, focus_dist, render_sky }
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(2.0, 3.0, 6.0), Point3D::new(-0.3, 0.8, 0.0), 40.0)
+        .scene()
+}
+
+fn solids(_: &Loader) -> Scene {
+    let checker = Arc::new(image::RgbImage::from_fn(64, 64, |x, y| {
+        image::Rgb(if (x/4 + y/4) % 2 == 0 { [230, 230, 230] } else { [40, 40, 40] })
+    }));
+    let ground: Arc<dyn Texture> = Arc::new(texture::ImageTexture::new(&checker));
+    let glass: Arc<dyn Texture> = Arc::new(Dielectric::BK7);
+    let red: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.7, 0.15, 0.1)));
+    let normal = vec3(0.0, 1.0, 0.0);
+    let corners = [point3(-8.0, 0.0, -8.0), point3(8.0, 0.0, -8.0), point3(8.0, 0.0, 8.0), point3(-8.0, 0.0, 8.0)];
+    let objects: Vec<Arc<dyn Hitable>> = vec![
+        Arc::new(Quad::new(corners, [normal; 4], [vec2(0.0, 1.0), vec2(1.0, 1.0), vec2(1.0, 0.0), vec2(0.0, 0.0)], ground)),
+        // A biconvex lens, 0.8 thick with a radius of 1.2, standing on its rim
+        Arc::new(csg::intersection(
+            Sphere::new(point3(-2.0, 1.2, -1.6), 2.0, glass.clone()),
+            Sphere::new(point3(-2.0, 1.2, 1.6), 2.0, glass.clone()),
+        )),
+        // A glass ball with a thin shell
+        Arc::new(csg::difference(
+            Sphere::new(point3(0.2, 0.8, 0.0), 0.8, glass.clone()),
+            Sphere::new(point3(0.2, 0.8, 0.0), 0.7, glass),
+        )),
+        // A block drilled through twice
+        Arc::new(csg::difference(
+            csg::Cuboid::new(point3(1.6, 0.0, -0.6), point3(2.8, 1.2, 0.6), red.clone()),
+            csg::union(
+                csg::Cylinder::new(point3(2.2, 0.6, -1.0), point3(2.2, 0.6, 1.0), 0.35, red.clone()),
+                csg::Cylinder::new(point3(1.4, 0.6, 0.0), point3(3.0, 0.6, 0.0), 0.25, red),
+            ),
+        )),
+    ];
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.5, 2.5, 7.0), Point3D::new(0.0, 0.8, 0.0), 40.0)
+        .scene()
+}
+
+fn terrain(_: &Loader) -> Scene {
+    let size = 128;
+    let height = |x: u32, y: u32| {
+        let (x, y) = (x as f32/size as f32*std::f32::consts::PI*2.0, y as f32/size as f32*std::f32::consts::PI*2.0);
+        let hills = 0.5 + 0.25*(x*1.5).sin()*(y*1.2).cos() + 0.15*(x*3.7 + 1.0).sin()*(y*4.1).sin() + 0.05*(x*11.0).cos()*(y*9.0).sin();
+        hills.max(0.0).min(1.0)
+    };
+    let heights = image::GrayImage::from_fn(size, size, |x, y| image::Luma([(height(x, y)*255.0) as u8]));
+    // Grass in the valleys, rock above and snow on the peaks, lined up with the heights through the texture coordinates
+    let colors = Arc::new(image::RgbImage::from_fn(size, size, |x, y| {
+        let h = height(x, y);
+        image::Rgb(if h < 0.55 { [70, 110, 45] } else if h < 0.75 { [110, 95, 80] } else { [235, 235, 240] })
+    }));
+    let ground: Arc<dyn Texture> = Arc::new(texture::ImageTexture::new(&colors));
+    let objects: Vec<Arc<dyn Hitable>> = vec![
+        Arc::new(Heightfield::from_image(&heights, point3(-10.0, 0.0, -10.0), vec3(20.0, 4.0, 20.0), ground)),
+    ];
+
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 9.0, 16.0), Point3D::new(0.0, 1.5, 0.0), 45.0)
+        .environment(sky::Sky::daylight(25.0, 60.0, 3.0))
+        .scene()
+}
+
+fn lamps(_: &Loader) -> Scene {
+    use delta_light::{DeltaLight, LightShape};
+
+    let grey = Arc::new(Lambertian::new(Rgb::with_wp(0.6, 0.6, 0.6)));
+    let objects: Vec<Arc<dyn Hitable>> = vec![
+        Arc::new(Sphere::new(Point3D::new(0.0, -1000.0, 0.0), 1000.0, grey.clone())),
+        Arc::new(Sphere::new(Point3D::new(-1.5, 0.5, 0.0), 0.5, Arc::new(Lambertian::new(Rgb::with_wp(0.8, 0.3, 0.2))))),
+        Arc::new(Sphere::new(Point3D::new(0.0, 0.7, 0.0), 0.7, grey)),
+        Arc::new(Sphere::new(Point3D::new(1.5, 0.5, 0.0), 0.5, Arc::new(Metal::new(Rgb::with_wp(0.9, 0.9, 0.9), 0.1)))),
+    ];
+    SceneBuilder::new()
+        .add_all(objects)
+        .camera(Point3D::new(0.0, 2.0, 6.0), Point3D::new(0.0, 0.5, 0.0), 40.0)
+        .no_environment()
+        // A warm spot from above, a blue lamp to the side and dim moonlight
+        .light(DeltaLight::new(
+            LightShape::Spot { position: point3(0.0, 5.0, 1.0), direction: vec3(0.0, -1.0, -0.2), inner: 0.3, outer: 0.4 },
+            Rgb::with_wp(1.0, 0.85, 0.6),
+            40.0,
+        ))
+        .light(DeltaLight::new(LightShape::Point { position: point3(3.0, 1.0, 2.0) }, Rgb::with_wp(0.3, 0.5, 1.0), 4.0))
+        .light(DeltaLight::new(
+            LightShape::Directional { direction: vec3(1.0, -1.0, -1.0), angular_radius: 0.05 },
+            Rgb::with_wp(0.6, 0.7, 1.0),
+            0.1,
+        ))
+        .scene()
+}
+
+fn lanterns(_: &Loader) -> Scene {
+    use delta_light::{DeltaLight, LightShape};
+
+    let grey = Arc::new(Lambertian::new(Rgb::with_wp(0.6, 0.6, 0.6)));
+    let mut objects: Vec<Arc<dyn Hitable>> = vec![
+        Arc::new(Sphere::new(Point3D::new(0.0, -1000.0, 0.0), 1000.0, grey.clone())),
+    ];
+    // Pillars in a ring around the middle of the plaza
+    for i in 0..8 {
+        let angle = i as f32*std::f32::consts::PI/4.0;
+        objects.push(Arc::new(Sphere::new(Point3D::new(4.0*angle.cos(), 0.6, 4.0*angle.sin()), 0.6, grey.clone())));
+    }
+    let colors = [Rgb::with_wp(1.0, 0.6, 0.3), Rgb::with_wp(1.0, 0.85, 0.5), Rgb::with_wp(0.9, 0.3, 0.2), Rgb::with_wp(0.5, 0.7, 1.0)];
+    // Lanterns standing on the plaza at uneven heights, far too many to aim at every one from every point
+    let lights = (0..16*16).map(|i| {
+        let (x, z) = (1.5*((i%16) as f32 - 7.5), 1.5*((i/16) as f32 - 7.5));
+        let height = 0.35 + 0.4*(0.5 + 0.5*(x*1.7 + z*2.3).sin());
+        DeltaLight::new(LightShape::Point { position: point3(x, height, z) }, colors[i % colors.len()], 0.2)
+    });
+    SceneBuilder::new()
+        .add_all(objects)
+        .lights(lights)
+        .camera(Point3D::new(0.0, 7.0, 14.0), Point3D::new(0.0, 0.0, 0.0), 45.0)
+        .no_environment()
+        .scene()
 }
 
 lazy_static! {
-    static ref SCENES: HashMap<&'static str, fn() -> Scene> = {
-        let mut scenes: HashMap<_, fn() -> Scene> = HashMap::new();
-        scenes.insert("just_earth", just_earth);
-        scenes.insert("three_spheres", three_spheres);
-        scenes.insert("many_spheres", many_spheres);
-        scenes.insert("simple_light", simple_light);
-        scenes.insert("bunny", bunny);
-        scenes.insert("cornell", cornell);
+    static ref SCENES: SceneRegistry = {
+        let mut scenes = SceneRegistry::new();
+        scenes.register("just_earth", "A textured globe under the sky", just_earth);
+        scenes.register("scanned_globe", "The globe as a point cloud of colored discs", scanned_globe);
+        scenes.register("three_spheres", "Diffuse, metal and hollow glass spheres side by side", three_spheres);
+        scenes.register("many_spheres", "The cover scene of Ray Tracing in One Weekend", many_spheres);
+        scenes.register("simple_light", "Spheres lit by an area light in the dark", simple_light);
+        scenes.register("bunny", "A dispersive glass bunny", bunny);
+        scenes.register("cornell", "The Cornell box with a glass cube, a buddha and a bunny", cornell);
+        scenes.register("cornell_glass", "The Cornell box with tinted glass casting colored caustics", cornell_glass);
+        scenes.register("glass_catalog", "Spheres of the preset glasses and a soap bubble", glass_catalog);
+        scenes.register("murky_glass", "The Cornell box with a smoky quartz ball and a tank of water, clouded where the smoke and silt are", murky_glass);
+        scenes.register("fog_caustics", "The Cornell box filled with fog, lit through a glass ball focusing a cone of light into it", fog_caustics);
+        scenes.register("dispersion_prism", "A flint glass prism splitting light into a spectrum", dispersion_prism);
+        scenes.register("worn_bunny", "A bunny with crevices darkened and edges worn by its material", worn_bunny);
+        scenes.register("fence", "A ball behind a lattice fence cut out of a single quad by an alpha mask", fence);
+        scenes.register("hair", "A ball of hair next to a copper wire, built from curves", hair);
+        scenes.register("pbr_tiles", "Tiles with metalness, roughness and glowing grout from texture maps", pbr_tiles);
+        scenes.register("mapped", "A brick block without texture coordinates mapped along the axes and a ball with tiled, turned stripes", mapped);
+        scenes.register("solids", "A lens, a hollow glass ball and a drilled block built with constructive solid geometry", solids);
+        scenes.register("lamps", "Spheres under a spot light, a lamp and moonlight without any surface", lamps);
+        scenes.register("lanterns", "A plaza under hundreds of lanterns, lit by one picked from a tree of them at every bounce", lanterns);
+        scenes.register("terrain", "Hills from a heightfield with grass, rock and snow textured by height, in the afternoon sun", terrain);
+        scenes.register("instanced_bunnies", "A grid of instances sharing two bunny meshes, the front row moving", instanced_bunnies);
+        scenes.register("furnace", "Spheres of different materials in a uniform white environment, to check they conserve energy", furnace);
         scenes
     };
 }
 
-fn main() {
-    let matches =
-        Command::new("Rayer")
+/// Blur diameter in pixels at which a pixel gets all of its extra defocus samples.
+const FULL_DEFOCUS_BLUR: f32 = 8.0;
+
+/// Extra samples for every pixel, growing with how blurred its first hit is,
+/// up to `max_extra` for pixels blurred by `FULL_DEFOCUS_BLUR` or more.
+/// Bokeh converges slowly, as a pixel averages light from a large part of the scene.
+fn defocus_samples<H: Hitable>(world: &H, keyframe: &camera::CameraKeyframe, width: u32, height: u32, max_extra: u32) -> Vec<u32> {
+    if max_extra == 0 || keyframe.aperture <= 0.0 {
+        return vec![0; (width*height) as usize];
+    }
+    // First hit depths through the pixel centers, seen through a pinhole
+    let pinhole = camera::CameraKeyframe { aperture: 0.0, ..*keyframe };
+    let cam = pinhole.to_camera(Vector3D::new(0.0, 1.0, 0.0), width as f32/height as f32, 0.0, 1.0);
+    let forward = (keyframe.look_at - keyframe.look_from).normalize();
+    let t_min = TMin::default();
+    (0..height*width)
+        .into_par_iter()
+        .map(|n| {
+            let u = ((n%width) as f32 + 0.5) / (width as f32);
+            let v = ((height-(n/width)) as f32 - 0.5) / (height as f32);
+            let r = cam.get_ray(u, v, 550.0);
+            match world.hit(r, t_min.t_min(r), f32::max_value()) {
+                Some(rec) => {
+                    let blur = keyframe.defocus_blur((rec.p - keyframe.look_from).dot(forward), height);
+                    (max_extra as f32*(blur/FULL_DEFOCUS_BLUR).min(1.0)).round() as u32
+                },
+                // The sky is smooth enough without
+                None => 0,
+            }
+        })
+        .collect()
+}
+
+/// The light a pass found at a pixel, its coverage, and where in the pixel it was sampled, in pixels right and down from its center.
+type PixelSample = (Xyz<E, f32>, f32, Vector2D<f32, UnknownUnit>);
+
+/// How the light reaching the camera is estimated.
+enum Integrator {
+    /// Paths from the camera, bouncing until they reach a light.
+    Path,
+    /// Photons traced from `lights` every iteration and gathered at diffuse surfaces, starting within `radius`.
+    Sppm { lights: Vec<sppm::Light>, photons: usize, radius: f32 },
+    /// Paths from the camera, leaving the caustics of `lights` to `paths` paths traced from them every pass.
+    Light { lights: Vec<sppm::Light>, paths: usize },
+    /// False colors for what the camera rays hit, instead of the light.
+    Debug(debug_view::DebugView),
+    /// Only the wireframe, over a transparent background.
+    Wireframe,
+    /// Ambient occlusion as grey, the share of the directions around what the camera sees that get further than
+    /// `distance` without hitting anything, with hits occluding less the further away they are by `falloff`, see `openness`.
+    Occlusion { distance: f32, falloff: f32 },
+}
+
+/// When the output is rewritten while rendering, which for large images can take longer than the samples in between.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum WriteInterval {
+    /// Whenever new samples arrived.
+    Always,
+    /// At most every so long.
+    Every(Duration),
+    /// Once every so many samples.
+    Samples(u64),
+    /// Only when all samples are in.
+    AtEnd,
+}
+
+/// Where the passes of a render are taken.
+enum Sampling<'a> {
+    /// Every pass, on this machine.
+    All,
+    /// These passes, on this machine, for a coordinator.
+    Passes(Range<u64>),
+    /// All passes, by the workers listening at `addresses` for `token`, rendering with the command line `args`.
+    Workers { addresses: &'a [String], token: &'a str, args: &'a [String] },
+}
+
+/// What the saver adds to the film.
+enum Update {
+    /// Samples of pass `index`, for the pixels `pixels`, and if asked for the light of the samples split by the way it
+    /// came and the ids of what they hit.
+    Tile { index: u64, pixels: Vec<u32>, samples: Vec<PixelSample>, passes: Option<Vec<PathPasses<Xyz<E, f32>>>>, ids: Option<Vec<ids::HitIds>> },
+    /// A pass is done, with what light paths splatted onto the film in it.
+    Pass { splats: Option<film::Splats> },
+    /// The film a worker rendered `passes` passes into.
+    Film { passes: u64, film: film::Film },
+}
+
+/// What `render` needs besides the world, the camera and the passes to take, the same for every frame of a run.
+struct RenderJob<'a> {
+    integrator: &'a Integrator,
+    settings: &'a settings::RenderSettings,
+    sampler: Arc<dyn Sampler>,
+    lens: LensSampling,
+    lighting: Lighting<'a>,
+    accumulation: film::Accumulation,
+    /// Width of the lines drawn over the image, if any.
+    wireframe: Option<f32>,
+    image_output: ImageOutput,
+    flare: Option<flare::LensFlare>,
+    /// Write the light split by the way it reached the camera next to the output, see `PathPass`.
+    light_passes: bool,
+    /// Write the ids of the objects and materials seen in every pixel next to the output, see `ids`, or into layers of
+    /// their own in EXR output.
+    id_passes: bool,
+    write_interval: WriteInterval,
+    handle: &'a render::RenderHandle,
+    budget: render::Budget,
+}
+
+/// How the saver writes images: their size and format, whether they keep the alpha, and the grading they get first.
+#[derive(Clone, Copy)]
+struct ImageOutput {
+    width: u32,
+    height: u32,
+    format: image::ImageFormat,
+    alpha: bool,
+    grading: color::ColorGrading,
+}
+
+impl ImageOutput {
+    /// Write `film` to `output_path`, through a temporary file next to it so it is never seen half written.
+    /// `flare` is applied before the grading, and EXR output gets `extra_layers` after the image and its statistics.
+    fn write(&self, film: &film::Film, output_path: &Path, flare: Option<&flare::LensFlare>, extra_layers: Vec<output::OutputLayer>) {
+        let ImageOutput { width, height, format, alpha, grading } = *self;
+        let output_suffix = format!(".{}", output_path.extension().unwrap().to_str().unwrap());
+        let output_dir = output_path.parent().unwrap();
+        let _span = trace::span("save", "encode");
+        let mut pixels: Vec<Rgb<E, f32>> = (0..(width*height) as usize).map(|i| film.color(i)).collect();
+        if let Some(flare) = flare {
+            flare.apply(&mut pixels, width, height);
+        }
+        grading.apply(&mut pixels);
+        let get_pixel = |x, y| pixels[(y*width+x) as usize];
+        let get_pixel_hdr = |x, y| {
+            let col = get_pixel(x, y);
+            image::Rgb([col.red, col.green, col.blue])
+        };
+        let get_pixel_ldr = |x, y| {
+            let col = get_pixel(x, y);
+            let col = Srgb::from(col.clamp());
+            let pixel =
+                [(col.red*255.99) as u8
+                ,(col.green*255.99) as u8
+                ,(col.blue*255.99) as u8
+                ];
+            image::Rgb(pixel)
+        };
+
+        let mut fout =
+            tempfile::Builder::new()
+            .suffix(&output_suffix)
+            .tempfile_in(output_dir).unwrap();
+
+        match format {
+            image::ImageFormat::Hdr => {
+                let buffer: Vec<_> =
+                    (0..(width*height))
+                    .map(|n| get_pixel_hdr(n%width, n/width))
+                    .collect();
+                let encoder = HdrEncoder::new(&fout);
+                encoder.encode(buffer.as_slice(), width as usize, height as usize).unwrap();
+            },
+            image::ImageFormat::OpenExr => {
+                let beauty = output::OutputLayer::new("beauty")
+                    .with_channel("R", pixels.iter().map(|col| col.red).collect())
+                    .with_channel("G", pixels.iter().map(|col| col.green).collect())
+                    .with_channel("B", pixels.iter().map(|col| col.blue).collect());
+                let beauty = if alpha {
+                    beauty.with_channel("A", (0..pixels.len()).map(|i| film.alpha(i)).collect())
+                } else {
+                    beauty
+                };
+                let stats = output::OutputLayer::new("stats")
+                    .with_channel("samples", (0..pixels.len()).map(|i| film.samples(i) as f32).collect())
+                    .with_channel("variance.R", (0..pixels.len()).map(|i| film.variance(i, 0)).collect())
+                    .with_channel("variance.G", (0..pixels.len()).map(|i| film.variance(i, 1)).collect())
+                    .with_channel("variance.B", (0..pixels.len()).map(|i| film.variance(i, 2)).collect());
+                let mut layers = vec![beauty, stats];
+                layers.extend(extra_layers);
+                output::write_exr(&mut fout, width, height, grading.color_space, layers).unwrap();
+            },
+            _ if alpha => {
+                // The color averaged over the transparent samples too is premultiplied already,
+                // but sRGB has to encode the straight color, which is premultiplied again after
+                let buffer = image::ImageBuffer::from_fn(width, height, |x, y| {
+                    let alpha = film.alpha((y*width + x) as usize);
+                    let col = if alpha > 0.0 { get_pixel(x, y)/alpha } else { get_pixel(x, y) };
+                    let col = Srgb::from(col.clamp());
+                    let pixel =
+                        [(col.red*alpha*255.99) as u8
+                        ,(col.green*alpha*255.99) as u8
+                        ,(col.blue*alpha*255.99) as u8
+                        ,(alpha*255.99) as u8
+                        ];
+                    image::Rgba(pixel)
+                });
+                image::DynamicImage::ImageRgba8(buffer).save_with_format(&mut fout, format).unwrap();
+            },
+            _ => {
+                let buffer = image::ImageBuffer::from_fn(width, height, get_pixel_ldr);
+                image::DynamicImage::ImageRgb8(buffer).save_with_format(&mut fout, format).unwrap();
+            }
+        }
+        fout.flush().unwrap();
+        fout.persist(output_path).unwrap();
+    }
+}
+
+/// What a bake writes into the texture.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum BakeMode {
+    /// The light a white diffuse surface reflects, as the path integrator finds it.
+    Light,
+    /// The ambient occlusion, see `Integrator::Occlusion`.
+    Occlusion { distance: f32, falloff: f32 },
+}
+
+/// A mesh in the scene to bake into a texture, and its texels.
+struct Bake {
+    mesh: Arc<dyn Hitable>,
+    texels: Vec<bake::Texel>,
+    mode: BakeMode,
+}
+
+/// Hand out chunks of passes to the worker at `address` until none are left, sending back the films it rendered,
+/// which have to be laid out like `layout`. The chunk it is rendering when it fails goes back for the others.
+fn run_worker(address: &str, token: &str, args: &[String], layout: film::Layout, chunks: &Mutex<Vec<Range<u64>>>, sender: &Sender<Update>, handle: &render::RenderHandle) -> Result<(), std::io::Error> {
+    let mut stream = TcpStream::connect(address)?;
+    distributed::Message::Token(token.to_string()).write_to(&mut stream)?;
+    distributed::Message::Args(args.to_vec()).write_to(&mut stream)?;
+    while handle.checkpoint() {
+        let passes = match chunks.lock().unwrap().pop() {
+            Some(passes) => passes,
+            None => return Ok(()),
+        };
+        let mut render_chunk = || {
+            distributed::Message::Passes(passes.clone()).write_to(&mut stream)?;
+            match distributed::Message::read_from(&mut stream, Some(layout))? {
+                Some(distributed::Message::Film(film)) => Ok(film),
+                Some(distributed::Message::Error(message)) => Err(std::io::Error::other(message)),
+                Some(_) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "expected the film of the passes")),
+                None => Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "the worker closed the connection")),
+            }
+        };
+        match render_chunk() {
+            Ok(film) => sender.send(Update::Film { passes: passes.end - passes.start, film }).unwrap(),
+            Err(error) => {
+                chunks.lock().unwrap().push(passes);
+                return Err(error);
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Render the passes `sampling` asks for through `cam` and return the film, writing it to `output` as they come in if
+/// there is one. Pixels with `extra_samples` take that many passes past the samples of the settings.
+/// The pixels of every pass are taken in tiles as the settings of `job` say.
+/// Cancelling with its handle keeps the passes finished so far, and the tiles of the others.
+fn render<H: Hitable>(world: &BVH<H>, job: &RenderJob, cam: &camera::Camera, extra_samples: Vec<u32>, sampling: Sampling, output: Option<&Path>) -> film::Film {
+    let RenderJob { integrator, settings, ref sampler, lens, lighting, accumulation, wireframe, image_output, ref flare, light_passes, id_passes, write_interval, handle, budget } = *job;
+    let Lighting { sensor, sky, .. } = lighting;
+    let ImageOutput { format, alpha, .. } = image_output;
+    let output = output.map(PathBuf::from);
+    // Rays are counted into the spending from every thread tracing them
+    let spending = render::Spending::new(budget);
+    let watchdog = render::Watchdog::new(settings.strict_nan);
+    let (bvh, world) = (world, &spending.count(world));
+    let (width, height, num_samples, filter) = (settings.width, settings.height, settings.samples, settings.filter);
+    let wavelengths = settings.wavelength_sampler();
+    let (wl_low, wl_high) = settings.wavelength_range;
+    let t_min = settings.t_min();
+    let default_rate = settings.shading_rate();
+    // Passes past the regular samples only trace the pixels that still have extra samples to take
+    let extra_samples = Arc::new(extra_samples);
+    let num_passes = num_samples + extra_samples.iter().cloned().max().unwrap_or(0) as u64;
+    let takes_sample = {
+        let extra_samples = extra_samples.clone();
+        move |index: u64, n: usize| index < num_samples || index - num_samples < extra_samples[n] as u64
+    };
+    let saver_takes_sample = takes_sample.clone();
+    let wireframe = wireframe.map(|width| debug_view::Wireframe { camera: cam, height, width });
+    // A ray through pixel `n` for pass `index`, weighted for its wavelength relative to uniform sampling, which the exposure was tuned for,
+    // and for the light the camera lets through along it, with where in the pixel it passes as the film's filter needs it
+    let camera_ray = |n: u32, index: u64| {
+        start_path(n, index, FIRST_PATH_DIMENSION);
+        let i = n%width;
+        let j = height-(n/width);
+        let (wl, wl_pdf) = wavelengths.sample(sampler.get_1d(n, index, WAVELENGTH_DIMENSION));
+        let pixel_sample = sampler.get_2d(n, index, PIXEL_DIMENSION);
+        let u = ((i as f32) + pixel_sample.x) / (width as f32);
+        let v = ((j as f32) + pixel_sample.y) / (height as f32);
+        let (r, transmission) = cam.get_ray_and_transmission(u, v, wl, lens.sample(sampler.as_ref(), n, index));
+        (r, transmission/(wl_pdf*(wl_high-wl_low)), vec2(pixel_sample.x - 0.5, 0.5 - pixel_sample.y))
+    };
+    // Materials are numbered in the order of the scene, and the ids of the nearest hit through the center of every pixel kept
+    let materials = if id_passes { ids::MaterialIds::new(world) } else { ids::MaterialIds::default() };
+    let nearest_ids: Vec<ids::HitIds> = if id_passes {
+        (0..width*height).map(|n| {
+            let (i, j) = (n%width, height-(n/width));
+            let r = ray::Ray { ti: 0.0, ..cam.get_ray_at_lens((i as f32 + 0.5)/width as f32, (j as f32 + 0.5)/height as f32, 550.0, vec2(0.0, 0.0)) };
+            materials.hit_ids(world.hit(r, t_min.t_min(r), f32::MAX).as_ref())
+        }).collect()
+    } else {
+        Vec::new()
+    };
+    if let (true, Some(ref output)) = (id_passes && format != image::ImageFormat::OpenExr, &output) {
+        for &kind in &[ids::IdKind::Object, ids::IdKind::Material] {
+            let buffer = image::ImageBuffer::from_fn(width, height, |x, y| {
+                let col = nearest_ids[(y*width + x) as usize].get(kind).map_or(Rgb::with_wp(0.0, 0.0, 0.0), |id| ids::id_color(kind, id));
+                image::Rgb([(col.red*255.99) as u8, (col.green*255.99) as u8, (col.blue*255.99) as u8])
+            });
+            let name = match kind {
+                ids::IdKind::Object => "object_id",
+                ids::IdKind::Material => "material_id",
+            };
+            let path = output::sibling_path(output, name).with_extension("png");
+            if let Err(error) = image::DynamicImage::ImageRgb8(buffer).save(&path) {
+                eprintln!("Couldn't write {}: {}", path.display(), error);
+            }
+        }
+    }
+    let (sender, receiver): (Sender<Update>, _) = unbounded();
+    // The variance channels are only written to EXR files
+    let layout = film::Layout { pixels: (width*height) as usize, accumulation, track_variance: format == image::ImageFormat::OpenExr };
+    let progress_total = match sampling {
+        Sampling::Passes(ref passes) => passes.end.min(num_passes).saturating_sub(passes.start),
+        Sampling::All | Sampling::Workers { .. } => num_passes,
+    };
+    let saver_handle = handle.clone();
+    let flare = flare.clone();
+    let saver = thread::spawn(move|| {
+        let takes_sample = saver_takes_sample;
+        let handle = saver_handle;
+        let mut done = 0;
+        let mut pb = ProgressBar::new(progress_total);
+        pb.format("╢▌▌░╟");
+        let new_film = || film::Film::new(layout.pixels, layout.accumulation, layout.track_variance).with_filter(filter, width);
+        let mut film = new_film();
+        let mut pass_films: Vec<(PathPass, film::Film)> = if light_passes {
+            PathPass::ALL.iter().map(|&pass| (pass, new_film())).collect()
+        } else {
+            Vec::new()
+        };
+        let mut id_coverage: Vec<ids::IdCoverage> = if id_passes {
+            vec![ids::IdCoverage::new(ids::IdKind::Object, (width*height) as usize), ids::IdCoverage::new(ids::IdKind::Material, (width*height) as usize)]
+        } else {
+            Vec::new()
+        };
+        // Ids of the objects and materials in EXR output, as the nearest hit and as the share of the samples they cover
+        let id_layers = |coverage: &[ids::IdCoverage]| {
+            if !id_passes || format != image::ImageFormat::OpenExr {
+                return Vec::new();
+            }
+            let nearest = |kind| nearest_ids.iter().map(|hit: &ids::HitIds| hit.get(kind).map_or(-1.0, |id| id as f32)).collect();
+            let mut layers = vec![output::OutputLayer::new("ids")
+                .with_channel("object", nearest(ids::IdKind::Object))
+                .with_channel("material", nearest(ids::IdKind::Material))];
+            layers.extend(coverage.iter().flat_map(|coverage| coverage.layers()));
+            layers
+        };
+        let mut last_write = Instant::now();
+        let mut unwritten = 0;
+        while let Ok(sample) = receiver.recv() {
+            let mut samples_pending = vec![sample];
+            while let Ok(sample) = receiver.try_recv() {
+                samples_pending.push(sample);
+            }
+            let passes: u64 = samples_pending.iter().map(|update| match *update {
+                Update::Tile { .. } => 0,
+                Update::Pass { .. } => 1,
+                Update::Film { passes, .. } => passes,
+            }).sum();
+            {
+                let _span = trace::span("save", "accumulate").with_arg("passes", passes);
+                for update in samples_pending.iter() {
+                    match *update {
+                        Update::Tile { index, ref pixels, ref samples, ref passes, ref ids } => {
+                            for (i, (&n, &(xyz, coverage, offset))) in pixels.iter().zip(samples).enumerate() {
+                                if takes_sample(index, n as usize) {
+                                    film.add_at(index, n as usize, offset, xyz, coverage);
+                                    if let Some(ref passes) = *passes {
+                                        for &mut (pass, ref mut pass_film) in pass_films.iter_mut() {
+                                            pass_film.add_at(index, n as usize, offset, passes[i].get(pass), coverage);
+                                        }
+                                    }
+                                    if let Some(ref ids) = *ids {
+                                        for id_coverage in id_coverage.iter_mut() {
+                                            id_coverage.add(n as usize, ids[i]);
+                                        }
+                                    }
+                                }
+                            }
+                        },
+                        Update::Pass { splats: Some(ref splats), .. } => {
+                            film.add_splats(splats);
+                            // The light tracer only adds light that reached the surface seen through other surfaces
+                            for &mut (pass, ref mut pass_film) in pass_films.iter_mut() {
+                                if pass == PathPass::IndirectDiffuse {
+                                    pass_film.add_splats(splats);
+                                }
+                            }
+                        },
+                        Update::Pass { splats: None, .. } => (),
+                        // Workers' films were checked against the layout as they were read
+                        Update::Film { film: ref rendered, .. } => if let Err(error) = film.merge(rendered) {
+                            eprintln!("Dropped the film of a worker: {}", error);
+                        },
+                    }
+                }
+            }
+
+            unwritten += passes;
+            done += passes;
+            pb.add(passes);
+            handle.report(render::RenderEvent::Progress { done, total: progress_total });
+            let due = match write_interval {
+                WriteInterval::Always => true,
+                WriteInterval::Every(interval) => last_write.elapsed() >= interval,
+                WriteInterval::Samples(samples) => unwritten >= samples,
+                WriteInterval::AtEnd => false,
+            };
+            if let (true, Some(ref output)) = (due, &output) {
+                image_output.write(&film, output, flare.as_ref(), id_layers(&id_coverage));
+                // The flare is left out of the passes, which add up to the image without it
+                for &(pass, ref pass_film) in pass_films.iter() {
+                    image_output.write(pass_film, &output::sibling_path(output, pass.name()), None, Vec::new());
+                }
+                last_write = Instant::now();
+                unwritten = 0;
+            }
+        }
+        // Whatever arrived since the last write
+        if let (true, Some(ref output)) = (unwritten > 0, &output) {
+            image_output.write(&film, output, flare.as_ref(), id_layers(&id_coverage));
+            for &(pass, ref pass_film) in pass_films.iter() {
+                image_output.write(pass_film, &output::sibling_path(output, pass.name()), None, Vec::new());
+            }
+        }
+        pb.finish_print("done");
+        handle.report(render::RenderEvent::Finished { cancelled: handle.is_cancelled() });
+        film
+    });
+    let passes = match sampling {
+        Sampling::All => 0..num_passes,
+        Sampling::Passes(passes) => passes.start..passes.end.min(num_passes),
+        Sampling::Workers { addresses, token, args } => {
+            // Workers that fail leave their chunk to the others, which connect again if they finished before
+            let chunks = Mutex::new(distributed::pass_chunks(0..num_passes, addresses.len()).into_iter().rev().collect::<Vec<_>>());
+            let mut addresses = addresses.to_vec();
+            while !addresses.is_empty() && !chunks.lock().unwrap().is_empty() && !handle.is_cancelled() {
+                // Every worker gets a thread of its own, as they mostly wait on the network
+                let results: Vec<_> = thread::scope(|scope| {
+                    let (chunks, sender) = (&chunks, &sender);
+                    let workers: Vec<_> = addresses.iter()
+                        .map(|address| scope.spawn(move || run_worker(address, token, args, layout, chunks, sender, handle)))
+                        .collect();
+                    workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+                });
+                addresses = addresses.into_iter().zip(results).filter_map(|(address, result)| match result {
+                    Ok(()) => Some(address),
+                    Err(error) => {
+                        eprintln!("Worker {} failed: {}", address, error);
+                        None
+                    },
+                }).collect();
+            }
+            if !chunks.lock().unwrap().is_empty() && !handle.is_cancelled() {
+                eprintln!("No worker left to render the remaining passes");
+                std::process::exit(1);
+            }
+            0..0
+        },
+    };
+    match *integrator {
+        Integrator::Path | Integrator::Light { .. } | Integrator::Debug(_) | Integrator::Wireframe | Integrator::Occlusion { .. } => {
+            let light_tracer = match *integrator {
+                Integrator::Light { ref lights, paths } if !lights.is_empty() => {
+                    Some((light_tracing::LightTracer { camera: cam, settings, lights, sensor: *sensor, caustics_only: true }, paths))
+                },
+                _ => None,
+            };
+            let skip_caustics = light_tracer.is_some();
+            let no_light = PathPasses::<f32>::default().map(|_| Xyz::with_wp(0.0, 0.0, 0.0));
+            let tiles = settings.tile_order.tiles(width, height, settings.tile_size);
+            // Tiles go to the saver as soon as they are done
+            render::render_passes(passes, &tiles, handle, &spending, |index, tile| {
+                let _span = trace::span("render", "tile").with_arg("pass", index).with_arg("x", tile.x as u64).with_arg("y", tile.y as u64);
+                let _paths = path_sampler(sampler.clone());
+                let pixels: Vec<u32> = tile.pixels(width).collect();
+                let results: Vec<(PixelSample, PathPasses<Xyz<E, f32>>, ids::HitIds)> = pixels.iter().map(|&n| {
+                    if !takes_sample(index, n as usize) {
+                        return ((Xyz::with_wp(0.0, 0.0, 0.0), 0.0, vec2(0.0, 0.0)), no_light, ids::HitIds::default());
+                    }
+                    let (r, weight, offset) = camera_ray(n, index);
+                    let hit_ids = if id_passes { materials.hit_ids(world.hit(r, t_min.t_min(r), f32::MAX).as_ref()) } else { ids::HitIds::default() };
+                    // Lines are drawn over whatever the integrator finds behind them
+                    if let Some(ref wireframe) = wireframe {
+                        if world.hit(r, t_min.t_min(r), f32::MAX).map_or(false, |rec| wireframe.covers(&rec)) {
+                            return ((debug_view::Wireframe::color().into_xyz(), 1.0, offset), no_light, hit_ids);
+                        }
+                    }
+                    if let Integrator::Wireframe = *integrator {
+                        return ((Xyz::with_wp(0.0, 0.0, 0.0), 0.0, offset), no_light, hit_ids);
+                    }
+                    if let Integrator::Occlusion { distance, falloff } = *integrator {
+                        return match world.hit(r, t_min.t_min(r), f32::MAX) {
+                            Some(rec) => {
+                                let open = openness(r, rec, world, t_min, distance, falloff);
+                                ((Rgb::with_wp(open, open, open).into_xyz(), 1.0, offset), no_light, hit_ids)
+                            },
+                            None => ((Xyz::with_wp(0.0, 0.0, 0.0), 0.0, offset), no_light, hit_ids),
+                        };
+                    }
+                    if let Integrator::Debug(view) = *integrator {
+                        // The boxes view looks into the BVH itself, so its rays aren't counted
+                        return match view.color(r, bvh, t_min) {
+                            Some(col) => ((col.into_xyz(), 1.0, offset), no_light, hit_ids),
+                            None => ((Xyz::with_wp(0.0, 0.0, 0.0), 0.0, offset), no_light, hit_ids),
+                        };
+                    }
+                    let (passes, hit) = color(r, world, t_min, default_rate, lighting, skip_caustics, &watchdog);
+                    // A transparent background hides the sky, which still lights the scene
+                    if alpha && !hit {
+                        return ((Xyz::with_wp(0.0, 0.0, 0.0), 0.0, offset), no_light, hit_ids);
+                    }
+                    let passes = passes.map(|col| col*(3.0*weight));
+                    ((passes.total(), if hit { 1.0 } else { 0.0 }, offset), passes, hit_ids)
+                }).collect();
+                let samples = results.iter().map(|result| result.0).collect();
+                let passes = if light_passes { Some(results.iter().map(|result| result.1).collect()) } else { None };
+                let ids = if id_passes { Some(results.iter().map(|result| result.2).collect()) } else { None };
+                sender.send(Update::Tile { index, pixels, samples, passes, ids }).unwrap();
+            }, |index| {
+                let splats = light_tracer.as_ref().map(|&(ref tracer, paths)| {
+                    let _span = trace::span("render", "light paths").with_arg("pass", index);
+                    let splats = film::Splats::new((width*height) as usize);
+                    tracer.trace(world, paths, &splats);
+                    splats
+                });
+                sender.send(Update::Pass { splats }).unwrap();
+            });
+        },
+        Integrator::Sppm { ref lights, photons, radius } => {
+            // Iterations depend on the radii the ones before left, so only the pixels run in parallel
+            let mut estimates = vec![sppm::PixelEstimate::new(radius); (width*height) as usize];
+            for index in passes {
+                if !handle.checkpoint() || spending.is_spent() {
+                    break;
+                }
+                let cell_size = estimates.iter().map(|estimate| estimate.radius).fold(0.0, f32::max);
+                let photon_map = {
+                    let _span = trace::span("render", "photons").with_arg("pass", index);
+                    sppm::PhotonMap::trace(world, lights, photons, cell_size, settings)
+                };
+                let results: Vec<(PixelSample, ids::HitIds)> =
+                    estimates.par_chunks_mut(width as usize)
+                    .enumerate()
+                    .flat_map_iter(|(row, estimates)| {
+                        let _span = trace::span("render", "row").with_arg("pass", index).with_arg("row", row as u64);
+                        let _paths = path_sampler(sampler.clone());
+                        estimates.iter_mut().enumerate().map(|(i, estimate)| {
+                            let n = row*width as usize + i;
+                            // The estimates are gathered around a point per pixel, which stays at the center of the filter
+                            let (r, weight, _) = camera_ray(n as u32, index);
+                            let hit_ids = if id_passes { materials.hit_ids(world.hit(r, t_min.t_min(r), f32::MAX).as_ref()) } else { ids::HitIds::default() };
+                            let (direct, covered, hit) = visible_point(r, world, t_min, default_rate, sky);
+                            let direct = if alpha && !covered { 0.0 } else { direct };
+                            let gathered = match hit {
+                                Some((r, rec, attenuation)) => {
+                                    // The attenuation of the camera path at its own wavelength stands in for the photons' wavelengths
+                                    let (reflected, count) = photon_map.reflected(r, rec, estimate.radius, sensor);
+                                    (reflected*(3.0*attenuation), count)
+                                },
+                                None => (Xyz::with_wp(0.0, 0.0, 0.0), 0),
+                            };
+                            // Every pass sends its own estimate, which the saver averages into the current one,
+                            // so the variance is taken over samples of a pass each
+                            let sample = estimate.add(sensor.xyz(r.wl)*(3.0*weight*direct), gathered, photon_map.emitted());
+                            ((sample, if covered { 1.0 } else { 0.0 }, vec2(0.0, 0.0)), hit_ids)
+                        }).collect::<Vec<_>>()
+                    }).collect();
+                let samples = results.iter().map(|result| result.0).collect();
+                let ids = if id_passes { Some(results.iter().map(|result| result.1).collect()) } else { None };
+                sender.send(Update::Tile { index, pixels: (0..width*height).collect(), samples, passes: None, ids }).unwrap();
+                sender.send(Update::Pass { splats: None }).unwrap();
+            }
+        },
+    }
+
+    drop(sender);
+
+    let film = saver.join().unwrap();
+    if budget != render::Budget::default() {
+        let rays = spending.rays();
+        let seconds = spending.elapsed().as_secs_f32();
+        eprintln!("Traced {} rays in {:.1}s, {:.0} per second{}", rays, seconds, rays as f32/seconds,
+            if spending.is_spent() { ", until the budget ran out" } else { "" });
+    }
+    if let Some(report) = watchdog.report() {
+        eprintln!("{}", report);
+    }
+    film
+}
+
+/// How open the surface at `rec` is along a ray leaving it in a direction drawn like the light a diffuse surface
+/// reflects: 1 if the ray gets further than `distance` without hitting anything, and otherwise less the closer the hit,
+/// by `1 - (1 - t/distance)^falloff` for a hit at distance t. A falloff of 0 makes every hit occlude fully.
+fn openness<H: Hitable>(r: ray::Ray, rec: HitRecord, world: &H, t_min: TMin, distance: f32, falloff: f32) -> f32 {
+    let ray = match Lambertian::new(Rgb::with_wp(1.0, 1.0, 1.0)).scatter(r, rec).reflection {
+        Some((_, ray)) => ray,
+        None => return 0.0,
+    };
+    let length = ray.direction.length();
+    if falloff == 0.0 {
+        return if world.is_occluded(ray, t_min.t_min(ray), distance/length) { 0.0 } else { 1.0 };
+    }
+    match world.hit(ray, t_min.t_min(ray), distance/length) {
+        Some(hit) => 1.0 - (1.0 - hit.t*length/distance).max(0.0).powf(falloff),
+        None => 1.0,
+    }
+}
+
+/// Bake what `target` asks for into a `settings.width` by `settings.height` texture, with `settings.samples` samples
+/// of every texel, and write it to `output` padded by `bake::PADDING` texels. Light is baked as `lighting` records it.
+/// Every sample starts as a ray coming back to the texel from just off the mesh, so the light is found the way a
+/// camera seeing the texel would find it.
+fn bake<H: Hitable>(
+    world: &BVH<H>,
+    target: &Bake,
+    settings: &settings::RenderSettings,
+    lighting: Lighting,
+    image_output: ImageOutput,
+    output: &Path,
+) {
+    let (width, height, samples) = (settings.width, settings.height, settings.samples);
+    let wavelengths = settings.wavelength_sampler();
+    let (wl_low, wl_high) = settings.wavelength_range;
+    let t_min = settings.t_min();
+    let default_rate = settings.shading_rate();
+    let hitable::AABB { bounds: [low, high] } = target.mesh.bbox();
+    let offset = 1e-3*(high - low).length();
+    let watchdog = render::Watchdog::new(settings.strict_nan);
+    let mut pb = ProgressBar::new(target.texels.len() as u64);
+    pb.format("╢▌▌░╟");
+    let pb = Mutex::new(pb);
+    let values: Vec<Xyz<E, f32>> = target.texels.par_iter().map(|texel| {
+        let _span = trace::span("bake", "texel").with_arg("pixel", texel.pixel as u64);
+        let mut sum = Xyz::with_wp(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            let (wl, wl_pdf) = wavelengths.sample(next_f32());
+            let probe = ray::Ray::new(texel.p + texel.normal*offset, -texel.normal, wl, 0.0);
+            sum = sum + match target.mode {
+                BakeMode::Light => {
+                    // Weighted for its wavelength like a camera ray
+                    let (passes, _) = color(probe, world, t_min, default_rate, lighting, false, &watchdog);
+                    passes.total()*(3.0/(wl_pdf*(wl_high - wl_low)))
+                },
+                BakeMode::Occlusion { distance, falloff } => match target.mesh.hit(probe, 0.0, 2.0*offset) {
+                    Some(rec) => {
+                        let open = openness(probe, rec, world, t_min, distance, falloff);
+                        Rgb::with_wp(open, open, open).into_xyz()
+                    },
+                    None => Xyz::with_wp(0.0, 0.0, 0.0),
+                },
+            };
+        }
+        pb.lock().unwrap().inc();
+        sum*(1.0/samples as f32)
+    }).collect();
+    pb.into_inner().unwrap().finish_print("done");
+    if let Some(report) = watchdog.report() {
+        eprintln!("{}", report);
+    }
+
+    let mut film = film::Film::new((width*height) as usize, film::Accumulation::Mean, false);
+    let mut texture = vec![None; (width*height) as usize];
+    for (texel, &value) in target.texels.iter().zip(values.iter()) {
+        film.add(0, texel.pixel, value, 1.0);
+        texture[texel.pixel] = Some(value);
+    }
+    let covered: Vec<bool> = texture.iter().map(Option::is_some).collect();
+    for (pixel, source) in bake::padding(&covered, width, height, bake::PADDING) {
+        film.add(0, pixel, texture[source].unwrap(), 1.0);
+    }
+    image_output.write(&film, output, None, Vec::new());
+}
+
+/// Parse a count given on the command line.
+/// Clap runs these parsers while reading the arguments, so mistakes are reported in its usual way before anything is loaded.
+fn pixel(value: &str) -> Result<(u32, u32), String> {
+    pair(value, whole_number)
+}
+
+fn length_and_radius(value: &str) -> Result<(f32, f32), String> {
+    pair(value, decimal)
+}
+
+/// Seconds like `30s`, or a number of samples.
+fn write_interval(value: &str) -> Result<WriteInterval, String> {
+    let value = value.trim();
+    let interval = match value.strip_suffix('s') {
+        Some(seconds) => decimal(seconds).ok().filter(|&seconds| seconds > 0.0)
+            .and_then(|seconds| Duration::try_from_secs_f32(seconds).ok()).map(WriteInterval::Every),
+        None => whole_number::<u64>(value).ok().filter(|&samples| samples > 0).map(WriteInterval::Samples),
+    };
+    interval.ok_or_else(|| format!("expected seconds like 30s or a number of samples above 0, got {:?}", value))
+}
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    match decimal(value).map(Duration::try_from_secs_f32) {
+        Ok(Ok(duration)) if duration > Duration::ZERO => Ok(duration),
+        _ => Err(format!("expected a number of seconds above 0, got {:?}", value)),
+    }
+}
+
+fn scene_name(name: &str) -> Result<&'static str, String> {
+    match SCENES.names().find(|&known| known == name) {
+        Some(known) => Ok(known),
+        None => Err(match SCENES.suggest(name) {
+            Some(suggestion) => format!("unknown scene {:?}, did you mean {:?}? --list-scenes shows them all", name, suggestion),
+            None => format!("unknown scene {:?}, --list-scenes shows the available ones", name),
+        }),
+    }
+}
+
+/// The output extensions and the formats they are written in.
+const OUTPUT_FORMATS: &[(&str, image::ImageFormat)] = &[
+    ("png", image::ImageFormat::Png),
+    ("jpg", image::ImageFormat::Jpeg),
+    ("jpeg", image::ImageFormat::Jpeg),
+    ("hdr", image::ImageFormat::Hdr),
+    ("exr", image::ImageFormat::OpenExr),
+];
+
+fn output_format(path: &str) -> Result<image::ImageFormat, String> {
+    let extensions: Vec<&str> = OUTPUT_FORMATS.iter().map(|&(extension, _)| extension).collect();
+    let extension = match Path::new(path).extension() {
+        Some(extension) => extension.to_string_lossy().to_lowercase(),
+        None => return Err(format!("{:?} needs an extension to tell the image format, one of {}", path, extensions.join(", "))),
+    };
+    OUTPUT_FORMATS.iter()
+        .find(|&&(known, _)| known == extension)
+        .map(|&(_, format)| format)
+        .ok_or_else(|| format!("can't write {:?} files, the extension should be one of {}", extension, extensions.join(", ")))
+}
+
+fn json_string(s: &str) -> String {
+    format!("\"{}\"", trace::escape(s))
+}
+
+/// Print the scenes and every option with the values it takes, for tools and GUIs building on the command line.
+fn print_capabilities(cli: &Command) {
+    let optional = |value: Option<&str>| value.map_or(String::from("null"), json_string);
+    let scenes: Vec<String> = SCENES.iter()
+        .map(|(name, entry)| format!("    {{\"name\": {}, \"description\": {}}}", json_string(name), json_string(entry.description)))
+        .collect();
+    let formats: Vec<String> = OUTPUT_FORMATS.iter().map(|&(extension, _)| json_string(extension)).collect();
+    let options: Vec<String> = cli.get_arguments()
+        .filter_map(|arg| arg.get_long().map(|long| (long, arg)))
+        .map(|(long, arg)| {
+            let values: Vec<String> = arg.get_possible_values().unwrap_or(&[]).iter().map(|value| json_string(value.get_name())).collect();
+            let default = arg.get_default_values().first().and_then(|value| value.to_str());
+            format!(
+                "    {{\"name\": {}, \"value\": {}, \"help\": {}, \"default\": {}, \"values\": [{}]}}",
+                json_string(long),
+                optional(arg.get_value_names().and_then(|names| names.first().cloned())),
+                optional(arg.get_help()),
+                optional(default),
+                values.join(", "),
+            )
+        })
+        .collect();
+    println!("{{");
+    println!("  \"scenes\": [\n{}\n  ],", scenes.join(",\n"));
+    println!("  \"formats\": [{}],", formats.join(", "));
+    println!("  \"options\": [\n{}\n  ]", options.join(",\n"));
+    println!("}}");
+}
+
+/// The options of a render, which workers read the options a coordinator sends them with too.
+fn command() -> Command<'static> {
+    Command::new("Rayer")
         .version("1.0")
         .arg(Arg::new("output")
              .long("output")
              .value_name("FILE")
-             .required(true)
+             .required_unless_present_any(&["list-scenes", "pick"])
+             .validator(output_format)
              .takes_value(true))
         .arg(Arg::new("cpuprofile")
              .long("cpuprofile")
              .value_name("FILE")
              .takes_value(true))
+        .arg(Arg::new("trace")
+             .long("trace")
+             .value_name("FILE")
+             .help("Write the time spent loading, building and rendering on every thread to a chrome://tracing JSON file")
+             .takes_value(true))
         .arg(Arg::new("scene")
              .long("scene")
              .value_name("SCENE_NAME")
              .default_value("many_spheres")
+             .validator(scene_name)
              .takes_value(true))
-        .arg(Arg::new("samples")
-             .long("samples")
+        .arg(Arg::new("list-scenes")
+             .long("list-scenes")
+             .help("Print the available scenes and exit"))
+        .arg(Arg::new("json")
+             .long("json")
+             .requires("list-scenes")
+             .help("List the scenes, output formats and options with the values they take as JSON"))
+        .args(CameraOverrides::args())
+        .arg(Arg::new("auto-frame")
+             .long("auto-frame")
+             .conflicts_with_all(&["look-at", "fov", "focus-dist"])
+             .help("Aim the camera at the middle of the scene and fit all of it into the image, for models of unknown size and position"))
+        .arg(Arg::new("pick")
+             .long("pick")
+             .value_name("X,Y")
+             .validator(pixel)
+             .help("Print what the camera sees at a pixel of the output image and exit"))
+        .args(settings_args())
+        .arg(Arg::new("seed")
+             .long("seed")
              .value_name("NUMBER")
-             .default_value("100")
+             .help("Draw random numbers from this seed, so the scene and the paths from the camera come out the same every run")
+             .validator(whole_number::<u64>)
+             .takes_value(true))
+        .arg(Arg::new("sampler")
+             .long("sampler")
+             .value_name("SAMPLER")
+             .possible_values(["random", "stratified", "multi-jittered", "sobol"])
+             .default_value("sobol")
+             .takes_value(true))
+        .arg(Arg::new("lens-sampling")
+             .long("lens-sampling")
+             .value_name("METHOD")
+             .help("Where camera rays start on the lens: the sampler's points mapped onto the disk, or a golden angle spiral per pixel")
+             .possible_values(["concentric", "spiral"])
+             .default_value("concentric")
+             .takes_value(true))
+        .arg(Arg::new("vignetting")
+             .long("vignetting")
+             .help("Darken the corners by the natural cos⁴ falloff of a lens"))
+        .arg(Arg::new("lens-barrel")
+             .long("lens-barrel")
+             .value_name("LENGTH,RADIUS")
+             .help("Cut off camera rays that miss the opening of a lens barrel LENGTH in front of the aperture, in scene units")
+             .validator(length_and_radius)
+             .takes_value(true))
+        .arg(Arg::new("aperture-shape")
+             .long("aperture-shape")
+             .value_name("BLADES|IMAGE")
+             .help("Shape out of focus highlights with an aperture of this many straight blades, or with a grayscale image of the opening stretched over the lens, white letting all light through")
+             .takes_value(true))
+        .arg(Arg::new("lens")
+             .long("lens")
+             .value_name("FILE|double-gauss")
+             .help("Trace camera rays through the surfaces of a real lens instead of a thin one, from a file with the curvature radius, thickness, refractive index or glass and aperture diameter of each surface in millimeters, or a 50 mm double Gauss lens")
              .takes_value(true))
-        .arg(Arg::new("width")
-             .long("width")
+        .arg(Arg::new("lens-scale")
+             .long("lens-scale")
+             .value_name("MILLIMETERS")
+             .help("Millimeters of the --lens to a unit of the scene, 1000 for scenes in meters")
+             .validator(decimal)
+             .default_value("1000")
+             .takes_value(true))
+        .arg(Arg::new("chromatic-aberration")
+             .long("chromatic-aberration")
+             .value_name("AMOUNT")
+             .help("Focus blue nearer than red, by AMOUNT times the focus distance between 486 and 656 nm, which colors the edges out of focus")
+             .validator(decimal)
+             .takes_value(true))
+        .arg(Arg::new("integrator")
+             .long("integrator")
+             .value_name("METHOD")
+             .help("How the light is found: paths from the camera, paths from the camera with the caustics traced from the lights, or stochastic progressive photon mapping, which finds caustics. Occlusion shows the ambient occlusion of what the camera sees as grey, a quick preview of a scene. The others show the normals, texture coordinates, distance or BVH boxes passed through of what the camera sees in false colors, or only the edges of its triangles")
+             .possible_values(["path", "light", "sppm", "occlusion", "normals", "uvs", "depth", "bbox", "wireframe"])
+             .default_value("path")
+             .takes_value(true))
+        .arg(Arg::new("wireframe")
+             .long("wireframe")
+             .value_name("WIDTH")
+             .help("Draw the edges of the triangles the camera sees over the image, WIDTH pixels wide or one by default. Not drawn by photon mapping")
+             .validator(decimal)
+             .min_values(0)
+             .max_values(1)
+             .takes_value(true))
+        .arg(Arg::new("photons")
+             .long("photons")
              .value_name("NUMBER")
+             .help("Photons to trace every photon mapping iteration or paths from the lights every pass, by default one per pixel")
+             .validator(whole_number::<usize>)
+             .takes_value(true))
+        .arg(Arg::new("photon-radius")
+             .long("photon-radius")
+             .value_name("DISTANCE")
+             .help("Radius photons are gathered within at first, by default a hundredth of the scene size")
+             .validator(decimal)
+             .takes_value(true))
+        .arg(Arg::new("upsampling")
+             .long("upsampling")
+             .value_name("METHOD")
+             .help("How RGB colors are turned into spectra")
+             .possible_values(["binned", "sigmoid"])
+             .default_value("binned")
+             .takes_value(true))
+        .arg(Arg::new("sensor")
+             .long("sensor")
+             .value_name("FILE")
+             .help("Record colors with the red, green and blue spectral sensitivities of a camera, from a CSV file of wavelength,red,green,blue lines")
+             .takes_value(true))
+        .arg(Arg::new("flare")
+             .long("flare")
+             .value_name("MODE")
+             .help("Add lens flare around bright spots: as the scene defines, always, or never")
+             .possible_values(["scene", "on", "off"])
+             .default_value("scene")
+             .takes_value(true))
+        .args(grading_args())
+        .arg(Arg::new("defocus-samples")
+             .long("defocus-samples")
+             .value_name("FACTOR")
+             .help("Give blurred out of focus pixels up to FACTOR times the samples on top")
+             .default_value("0")
+             .validator(decimal)
+             .takes_value(true))
+        .arg(Arg::new("median-of-means")
+             .long("median-of-means")
+             .value_name("BUFFERS")
+             .help("Average the passes in BUFFERS separate buffers and write their median, which keeps out fireflies")
+             .validator(whole_number::<u32>)
+             .takes_value(true))
+        .arg(Arg::new("write-interval")
+             .long("write-interval")
+             .value_name("INTERVAL")
+             .help("Rewrite the output while rendering at most every so many seconds, like 30s, or every so many samples. By default it is rewritten whenever samples finish")
+             .validator(write_interval)
+             .takes_value(true))
+        .arg(Arg::new("no-progressive")
+             .long("no-progressive")
+             .help("Only write the output once all samples are in")
+             .conflicts_with("write-interval"))
+        .arg(Arg::new("max-time")
+             .long("max-time")
+             .value_name("SECONDS")
+             .help("Stop sampling after SECONDS and write the image with the samples done by then. Every frame of an animation gets as long")
+             .validator(seconds)
+             .takes_value(true))
+        .arg(Arg::new("max-rays")
+             .long("max-rays")
+             .value_name("N")
+             .help("Stop sampling after tracing N rays, counting shadow rays, and write the image with the samples done by then")
+             .validator(positive::<u64>)
+             .takes_value(true))
+        .arg(Arg::new("alpha")
+             .long("alpha")
+             .help("Write an alpha channel of the pixels covered by objects, with a transparent background instead of the sky, to PNG or EXR output"))
+        .arg(Arg::new("light-passes")
+             .long("light-passes")
+             .help("Also write the light split by the way it reached the camera, as emission, diffuse_direct, diffuse_indirect, specular and transmission, each next to the output with the name of the pass before the extension"))
+        .arg(Arg::new("id-passes")
+             .long("id-passes")
+             .help("Also write the ids of the objects and materials seen in every pixel, as layers of EXR output with the share of the pixel each covers like Cryptomatte, or as false color images next to other output"))
+        .arg(Arg::new("bake")
+             .long("bake")
+             .value_name("OBJ")
+             .help("Instead of rendering from the camera, add the mesh in OBJ to the scene in white and bake what falls on it into a texture the size of the image, laid out by its texture coordinates")
+             .takes_value(true))
+        .arg(Arg::new("bake-mode")
+             .long("bake-mode")
+             .value_name("MODE")
+             .help("What --bake writes: the light the white mesh reflects, or the ambient occlusion, the share of the directions around it that get further than --occlusion-distance")
+             .possible_values(["light", "occlusion"])
+             .default_value("light")
              .takes_value(true))
-        .arg(Arg::new("height")
-             .long("height")
+        .arg(Arg::new("occlusion-distance")
+             .long("occlusion-distance")
+             .value_name("DISTANCE")
+             .help("How far surfaces still occlude for ambient occlusion, without a limit by default")
+             .validator(decimal)
+             .takes_value(true))
+        .arg(Arg::new("occlusion-falloff")
+             .long("occlusion-falloff")
+             .value_name("EXPONENT")
+             .help("How much less surfaces occlude the further away they are within --occlusion-distance, as a hit at a fraction F of it occludes by (1 - F)^EXPONENT. 0 lets every hit occlude fully")
+             .validator(decimal)
+             .default_value("0")
+             .takes_value(true))
+        .arg(Arg::new("preview")
+             .long("preview")
+             .help("Shade expensive materials with tables of their response baked when loading the scene"))
+        .arg(Arg::new("watch")
+             .long("watch")
+             .help("Render again whenever a file the scene, --lens or --sensor loads is saved, cancelling the render under way and keeping on writing to the same output")
+             .conflicts_with_all(&["frames", "bake"]))
+        .arg(Arg::new("subdivide")
+             .long("subdivide")
+             .value_name("LEVELS")
+             .help("Subdivide the meshes of the scene as they are loaded, splitting every triangle into four on each level and smoothing the surface with its normals, up to 6 levels")
+             .validator(|value| match whole_number::<u32>(value) {
+                 Ok(levels) if levels <= subdivision::MAX_LEVELS => Ok(levels),
+                 _ => Err(format!("expected a whole number up to {}, got {:?}", subdivision::MAX_LEVELS, value)),
+             })
+             .takes_value(true))
+        .arg(Arg::new("frames")
+             .long("frames")
              .value_name("NUMBER")
+             .help("Render an animation with the given number of frames, numbering the output files")
+             .validator(whole_number::<u32>)
              .takes_value(true))
-        .get_matches();
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(Command::new("worker")
+             .about("Render the passes a coordinator started with `distribute` hands out")
+             .arg(Arg::new("listen")
+                  .long("listen")
+                  .value_name("ADDRESS")
+                  .help("Address and port to wait for the coordinator on, only reachable from this machine by default")
+                  .default_value("127.0.0.1:7878")
+                  .takes_value(true))
+             .arg(token_arg()))
+        .subcommand(Command::new("distribute")
+             .about("Render on workers on other machines and write the merged image, with the options given after --")
+             .arg(Arg::new("workers")
+                  .long("workers")
+                  .value_name("ADDRESS,...")
+                  .help("Addresses and ports of the workers")
+                  .required(true)
+                  .use_value_delimiter(true)
+                  .takes_value(true))
+             .arg(token_arg())
+             .arg(Arg::new("options")
+                  .value_name("OPTIONS")
+                  .help("The options of the render, like --scene and --output")
+                  .multiple_values(true)
+                  .allow_hyphen_values(true)
+                  .last(true)))
+}
+
+fn token_arg() -> Arg<'static> {
+    Arg::new("token")
+        .long("token")
+        .value_name("SECRET")
+        .help("A secret shared by the coordinator and its workers, which only render for coordinators that know it")
+        .required(true)
+        .takes_value(true)
+}
+
+/// The options a coordinator can send to workers, those that only change what is rendered. Anything that writes
+/// files on the worker or keeps it from answering is left out.
+const WORKER_OPTIONS: &[&str] = &[
+    "output", "scene", "auto-frame", "seed", "sampler", "lens-sampling", "vignetting", "lens-barrel", "aperture-shape",
+    "lens", "lens-scale", "chromatic-aberration", "integrator", "wireframe", "photons", "photon-radius", "upsampling",
+    "sensor", "flare", "defocus-samples", "median-of-means", "write-interval", "no-progressive", "alpha",
+    "occlusion-distance", "occlusion-falloff", "preview", "subdivide",
+    "look-from", "look-at", "fov", "aperture", "focus-dist", "focal-length", "iso", "shutter", "f-number",
+    "exposure", "adapt-from", "adapt-to", "saturation", "color-space",
+    "width", "height", "samples", "wavelength-range", "wavelength-sampling", "max-depth", "roulette-threshold",
+    "epsilon-scale", "filter", "filter-radius", "tile-order", "tile-size", "strict-nan",
+];
+
+/// The first option on the command line of `matches` that workers don't take, see `WORKER_OPTIONS`.
+fn unsent_option(cli: &Command, matches: &ArgMatches) -> Option<String> {
+    cli.get_arguments()
+        .find(|arg| !WORKER_OPTIONS.contains(&arg.get_id()) && matches.value_source(arg.get_id()) == Some(ValueSource::CommandLine))
+        .map(|arg| arg.get_long().unwrap_or(arg.get_id()).to_string())
+}
+
+/// What a run of the command line does with the render.
+enum Target<'a> {
+    /// Render here and write the output.
+    File,
+    /// Have the workers at `addresses` that know `token` render it with the options `args`, and write the output.
+    Coordinator { addresses: &'a [String], token: &'a str, args: &'a [String] },
+    /// Render the passes a coordinator asks for over `stream`, and send back their films.
+    Worker(&'a mut TcpStream),
+}
+
+/// Wait for coordinators that know `token` on `address`, one at a time, rendering with the options they send.
+/// A render that fails, even by panicking, sends the coordinator the error and leaves the worker waiting for the next.
+fn serve(cli: &mut Command, address: &str, token: &str) {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(error) => cli.error(ErrorKind::Io, format!("can't listen on {}: {}", address, error)).exit(),
+    };
+    eprintln!("Waiting for a coordinator on {}", address);
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                eprintln!("Accepting a coordinator failed: {}", error);
+                continue;
+            },
+        };
+        // A coordinator that goes quiet mustn't keep the worker from the next one
+        if let Err(error) = stream.set_read_timeout(Some(distributed::READ_TIMEOUT)) {
+            eprintln!("Setting a timeout on the connection failed: {}", error);
+            continue;
+        }
+        match distributed::Message::read_from(&mut stream, None) {
+            Ok(Some(distributed::Message::Token(sent))) if distributed::same_token(&sent, token) => (),
+            Ok(_) => {
+                eprintln!("A coordinator without the token connected");
+                continue;
+            },
+            Err(error) => {
+                eprintln!("Receiving the token failed: {}", error);
+                continue;
+            },
+        }
+        let args = match distributed::Message::read_from(&mut stream, None) {
+            Ok(Some(distributed::Message::Args(args))) => args,
+            Ok(_) => {
+                eprintln!("The coordinator didn't send the options to render with");
+                continue;
+            },
+            Err(error) => {
+                eprintln!("Receiving the options failed: {}", error);
+                continue;
+            },
+        };
+        let mut cli = command();
+        let result = match cli.try_get_matches_from_mut(std::iter::once(String::from("rayer")).chain(args)) {
+            Ok(matches) => match unsent_option(&cli, &matches) {
+                Some(option) => Err(format!("the coordinator sent --{}, which workers don't take", option)),
+                None => panic::catch_unwind(AssertUnwindSafe(|| run(&mut cli, &matches, Target::Worker(&mut stream))))
+                    .unwrap_or_else(|panic| {
+                        let message = panic.downcast_ref::<String>().cloned()
+                            .or_else(|| panic.downcast_ref::<&str>().map(|message| message.to_string()))
+                            .unwrap_or_default();
+                        Err(format!("rendering panicked: {}", message))
+                    }),
+            },
+            Err(error) => Err(format!("the coordinator sent options that don't parse: {}", error)),
+        };
+        if let Err(message) = result {
+            eprintln!("Rendering for the coordinator failed: {}", message);
+            if let Err(error) = distributed::Message::Error(message).write_to(&mut stream) {
+                eprintln!("Sending the error failed: {}", error);
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut cli = command();
+    let matches = cli.get_matches_mut();
+    match matches.subcommand() {
+        Some(("worker", worker)) => serve(&mut cli, worker.value_of("listen").unwrap(), worker.value_of("token").unwrap()),
+        Some(("distribute", distribute)) => {
+            let addresses: Vec<String> = distribute.values_of("workers").unwrap().map(String::from).collect();
+            let args: Vec<String> = distribute.values_of("options").map_or(Vec::new(), |args| args.map(String::from).collect());
+            // The options are checked here, and workers send back the errors they still run into, like missing files
+            let mut cli = command();
+            let matches = cli.try_get_matches_from_mut(std::iter::once(String::from("rayer")).chain(args.iter().cloned()))
+                .unwrap_or_else(|error| error.exit());
+            if let Some(option) = unsent_option(&cli, &matches) {
+                cli.error(ErrorKind::ArgumentConflict, format!("--{} can't be sent to workers", option)).exit();
+            }
+            let token = distribute.value_of("token").unwrap();
+            if let Err(message) = run(&mut cli, &matches, Target::Coordinator { addresses: &addresses, token, args: &args }) {
+                cli.error(ErrorKind::InvalidValue, message).exit();
+            }
+        },
+        _ => if let Err(message) = run(&mut cli, &matches, Target::File) {
+            cli.error(ErrorKind::InvalidValue, message).exit();
+        },
+    }
+}
+
+fn run(cli: &mut Command, matches: &ArgMatches, mut target: Target) -> Result<(), String> {
+
+    if matches.is_present("json") {
+        print_capabilities(cli);
+        return Ok(());
+    }
+    if matches.is_present("list-scenes") {
+        let width = SCENES.names().map(|name| name.len()).max().unwrap_or(0);
+        for (name, entry) in SCENES.iter() {
+            println!("{:width$}  {}", name, entry.description, width = width);
+        }
+        return Ok(());
+    }
 
     let do_profile = match matches.value_of("cpuprofile") {
         Some(out_file) => {
@@ -418,150 +2265,455 @@ fn main() {
         },
         None => false
     };
+    if matches.is_present("trace") {
+        trace::enable();
+    }
+
+    let get_scene: fn(&Loader) -> Scene = SCENES.get(parsed(&matches, "scene", scene_name).unwrap()).unwrap().build;
+
+    // The scene changes the defaults, and the command line has the last word
+    let settings_overrides = settings_from_matches(&matches);
+    let settings_for = |scene: &Scene| settings_overrides.apply(scene.settings.apply(settings::RenderSettings::default()));
+
+    if let Some(seed) = parsed(&matches, "seed", whole_number::<u64>) {
+        random::seed(seed);
+    }
+    let auto_frame = matches.is_present("auto-frame");
+    let camera_overrides = CameraOverrides::from_matches(&matches);
+    if let Some((x, y)) = parsed(&matches, "pick", pixel) {
+        let mut scene = get_scene(&Loader::silent());
+        camera_overrides.apply(&mut scene);
+        let settings = settings_for(&scene);
+        if auto_frame {
+            scene.auto_frame(settings.aspect());
+        }
+        match scene.pick(x, y, settings.width, settings.height) {
+            Some(pick) => println!("{:?}", pick),
+            None => println!("Nothing hit"),
+        }
+        return Ok(());
+    }
 
     let output = Path::new(matches.value_of("output").unwrap());
-    let format = match output.extension().map(|ext| ext.to_str().unwrap()) {
-        None => panic!("Cannot know format without extension"),
-        Some("png") => image::ImageFormat::Png,
-        Some("jpg") => image::ImageFormat::Jpeg,
-        Some("jpeg") => image::ImageFormat::Jpeg,
-        Some("hdr") => image::ImageFormat::Hdr,
-        Some(ext) => panic!("Unknown extension: {:?}", ext),
-    };
-    let output_str = String::from(output.to_str().unwrap());
+    let format = parsed(&matches, "output", output_format).unwrap();
+    let alpha = matches.is_present("alpha");
+    if alpha && format != image::ImageFormat::Png && format != image::ImageFormat::OpenExr {
+        return Err("--alpha needs PNG or EXR output, the other formats have no alpha channel".to_string());
+    }
+    if matches.is_present("color-space") && format != image::ImageFormat::OpenExr {
+        return Err("--color-space needs EXR output, the other formats have no place to name their primaries".to_string());
+    }
 
-    let get_scene: fn() -> Scene = match matches.value_of("scene").unwrap() {
-        scene_name => match SCENES.get(scene_name) {
-            Some(&get_scene) => get_scene,
-            None => {
-                panic!("Invalid scene {:?}, available: {:?}", scene_name, SCENES.keys());
-            }
+    color::set_upsampling(match matches.value_of("upsampling").unwrap() {
+        "binned" => color::Upsampling::Binned,
+        "sigmoid" => color::Upsampling::Sigmoid,
+        name => panic!("Unknown upsampling: {:?}", name),
+    });
+    let frames = parsed(&matches, "frames", whole_number::<u32>);
+    let defocus_factor = parsed(&matches, "defocus-samples", decimal).unwrap();
+    let use_sppm = matches.value_of("integrator").unwrap() == "sppm";
+    let use_light_tracing = matches.value_of("integrator").unwrap() == "light";
+    let accumulation = match parsed(&matches, "median-of-means", whole_number::<u32>) {
+        Some(buffers) => film::Accumulation::MedianOfMeans { buffers },
+        None => film::Accumulation::Mean,
+    };
+    if let Target::Coordinator { .. } = target {
+        if use_sppm {
+            return Err("photon mapping iterations build on each other, so they can't be split between workers".to_string());
+        }
+        if frames.is_some() {
+            return Err("animations are rendered on a single machine, without --frames the workers render one image".to_string());
+        }
+    }
+    if use_sppm && accumulation != film::Accumulation::Mean {
+        return Err("--median-of-means needs independent passes, but photon mapping iterations build on each other".to_string());
+    }
+    let light_passes = matches.is_present("light-passes");
+    if light_passes {
+        match matches.value_of("integrator").unwrap() {
+            "path" | "light" => (),
+            _ => return Err("--light-passes needs --integrator path or light, which follow the light along its paths".to_string()),
+        }
+        if matches.is_present("wireframe") {
+            return Err("--light-passes can't split the lines of --wireframe by the light".to_string());
         }
+        if let Target::Coordinator { .. } = target {
+            return Err("--light-passes needs the samples themselves, but workers only send back the merged image".to_string());
+        }
+    }
+    let id_passes = matches.is_present("id-passes");
+    if let (true, Target::Coordinator { .. }) = (id_passes, &target) {
+        return Err("--id-passes needs the samples themselves, but workers only send back the merged image".to_string());
+    }
+    let bake_path = matches.value_of("bake");
+    if bake_path.is_some() {
+        if let Target::File = target {} else {
+            return Err("--bake writes the texture on this machine, it can't be split between workers".to_string());
+        }
+        if frames.is_some() {
+            return Err("--bake writes a single texture, which --frames can't animate".to_string());
+        }
+        if matches.value_of("integrator").unwrap() != "path" {
+            return Err("--bake follows paths from the mesh, so it needs --integrator path".to_string());
+        }
+        if light_passes || id_passes || matches.is_present("wireframe") {
+            return Err("--light-passes, --id-passes and --wireframe show what the camera sees, which --bake doesn't use".to_string());
+        }
+    }
+    let budget = render::Budget { time: parsed(&matches, "max-time", seconds), rays: parsed(&matches, "max-rays", positive::<u64>) };
+    if budget != render::Budget::default() {
+        if let Target::File = target {} else {
+            return Err("--max-time and --max-rays count on this machine, they can't be split between workers".to_string());
+        }
+        if bake_path.is_some() {
+            return Err("--max-time and --max-rays stop rendering from the camera, which --bake doesn't do".to_string());
+        }
+    }
+    let watch = matches.is_present("watch");
+    if watch {
+        if let Target::File = target {} else {
+            return Err("--watch renders on this machine, it can't be split between workers".to_string());
+        }
+    }
+    let occlusion_distance = parsed(&matches, "occlusion-distance", decimal).unwrap_or(f32::MAX);
+    let occlusion_falloff = parsed(&matches, "occlusion-falloff", decimal).unwrap();
+    if !(occlusion_distance > 0.0 && occlusion_falloff >= 0.0) {
+        return Err("--occlusion-distance has to be above 0 and --occlusion-falloff 0 or above".to_string());
+    }
+    let bake_mode = match matches.value_of("bake-mode").unwrap() {
+        "light" => BakeMode::Light,
+        "occlusion" => BakeMode::Occlusion { distance: occlusion_distance, falloff: occlusion_falloff },
+        mode => panic!("Unknown bake mode: {:?}", mode),
     };
-
-    let def_width: u32 = 800;
-    let def_height: u32 = 600;
-    let (width, height) = match (matches.value_of("width"), matches.value_of("height")) {
-        (None, None) => (def_width, def_height),
-        (Some(width_str), None) => {
-            let width = u32::from_str(width_str).unwrap();
-            let height = width*def_height/def_width;
-            (width, height)
-        },
-        (None, Some(height_str)) => {
-            let height = u32::from_str(height_str).unwrap();
-            let width = height*def_width/def_height;
-            (width, height)
-        },
-        (Some(width_str), Some(height_str)) => {
-            let width = u32::from_str(width_str).unwrap();
-            let height = u32::from_str(height_str).unwrap();
-            (width, height)
-        },
+    let write_interval = match parsed(&matches, "write-interval", write_interval) {
+        Some(interval) => interval,
+        None if matches.is_present("no-progressive") => WriteInterval::AtEnd,
+        None => WriteInterval::Always,
+    };
+    // `--integrator wireframe` has nothing else to show
+    let wireframe = match parsed(&matches, "wireframe", decimal) {
+        None if !matches.is_present("wireframe") && matches.value_of("integrator").unwrap() != "wireframe" => None,
+        width => Some(width.unwrap_or(1.0)),
+    };
+    let barrel = parsed(&matches, "lens-barrel", length_and_radius).map(|(length, radius)| camera::Barrel { length, radius });
+    let vignetting = camera::Vignetting { natural: matches.is_present("vignetting"), barrel };
+    let aperture_shape = match matches.value_of("aperture-shape") {
+        None => None,
+        Some(shape) => Some(match whole_number::<u32>(shape) {
+            Ok(blades) if blades >= 3 => camera::Aperture::Blades(blades),
+            Ok(_) => return Err("--aperture-shape needs at least 3 blades".to_string()),
+            Err(_) => {
+                let image = image::open(shape).map_err(|error| format!("{}: {}", shape, error))?;
+                camera::Aperture::image(image.into_luma8()).map_err(|error| format!("{}: {}", shape, error))?
+            },
+        }),
+    };
+    let chromatic_aberration = parsed(&matches, "chromatic-aberration", decimal).unwrap_or(0.0);
+    if matches.is_present("lens") && matches.value_of("integrator").unwrap() == "light" {
+        return Err("--lens can't be used with --integrator light, as light from the lights can't be traced back through it".to_string());
+    }
+    let lens_scale = parsed(&matches, "lens-scale", decimal).unwrap();
+    let options = RenderOptions {
+        get_scene, settings_overrides, auto_frame, camera_overrides, watch, output, format, alpha, frames, defocus_factor,
+        use_sppm, use_light_tracing, accumulation, light_passes, id_passes, bake_path, bake_mode, budget,
+        occlusion_distance, occlusion_falloff, write_interval, wireframe, vignetting, aperture_shape,
+        chromatic_aberration, lens_scale,
     };
-    let num_samples = u64::from_str(matches.value_of("samples").unwrap()).unwrap();
-
-    let Scene{ objects, look_from, look_at, aperture, vfov, focus_dist, render_sky } = get_scene();
-    let world = BVH::initialize(objects);
-    let up = Vector3D::new(0.0, 1.0, 0.0);
-
-    let cam = camera::Camera::new(look_from, look_at, up, vfov, width as f32/height as f32, aperture, focus_dist, 0.0, 1.0);
 
-    let wl_low = 390.0;
-    let wl_high = 700.0;
-    let (sender, receiver): (Sender<Vec<_>>, _) = unbounded();
-    let saver = thread::spawn(move|| {
-        let mut pb = ProgressBar::new(num_samples);
-        pb.format("╢▌▌░╟");
-        let mut buffer = Vec::with_capacity((width*height) as usize);
-        for _ in 0..width*height {
-            buffer.push(Xyz::with_wp(0.0, 0.0, 0.0));
+    let mut watcher: Option<watch::Watcher> = None;
+    loop {
+        let changed = render_scene(matches, &mut target, &options, &mut watcher)?;
+        let watcher = match watcher {
+            Some(ref watcher) => watcher,
+            None => break,
         };
-        let mut samples_done = 0;
-        let output_path = Path::new(output_str.as_str());
-        let output_suffix = format!(".{}", output_path.extension().unwrap().to_str().unwrap());
-        let output_dir = output_path.parent().unwrap();
-        while let Ok(sample) = receiver.recv() {
-            let mut samples_pending = vec![sample];
-            while let Ok(sample) = receiver.try_recv() {
-                samples_pending.push(sample);
-            }
-            for i in 0..width*height {
-                let mut acc = Xyz::with_wp(0.0, 0.0, 0.0);
-                for sample in samples_pending.iter() {
-                    acc = acc + sample[i as usize];
-                };
-                buffer[i as usize] = buffer[i as usize] + acc;
-            };
-            samples_done += samples_pending.len();
+        let changed = match changed {
+            Some(file) => watcher.settle(file),
+            None => {
+                eprintln!("Waiting for changes to the scene files");
+                watcher.wait()
+            },
+        };
+        let names: Vec<String> = changed.iter().map(|file| file.display().to_string()).collect();
+        eprintln!("Reloading after changes to {}", names.join(", "));
+    }
+    if do_profile {
+        cpuprofiler::PROFILER.lock().unwrap().stop().unwrap();
+    }
+    if let Some(trace_file) = matches.value_of("trace") {
+        trace::write(std::io::BufWriter::new(std::fs::File::create(trace_file).unwrap())).unwrap();
+    }
+    Ok(())
+}
 
-            let get_pixel = |x, y| {
-                let col = buffer[(y*width+x) as usize];
-                col.into_rgb()/(samples_done as f32)
-            };
-            let get_pixel_hdr = |x, y| {
-                let col = get_pixel(x, y);
-                image::Rgb([col.red, col.green, col.blue])
-            };
-            let get_pixel_ldr = |x, y| {
-                let col = get_pixel(x, y);
-                let col = Srgb::from(col.clamp());
-                let pixel =
-                    [(col.red*255.99) as u8
-                    ,(col.green*255.99) as u8
-                    ,(col.blue*255.99) as u8
-                    ];
-                image::Rgb(pixel)
-            };
+/// The options of a run of the command line that go into rendering the scene, parsed once for every time `--watch`
+/// renders it again.
+struct RenderOptions<'a> {
+    get_scene: fn(&Loader) -> Scene,
+    settings_overrides: settings::SettingsOverrides,
+    auto_frame: bool,
+    camera_overrides: CameraOverrides,
+    watch: bool,
+    output: &'a Path,
+    format: image::ImageFormat,
+    alpha: bool,
+    frames: Option<u32>,
+    defocus_factor: f32,
+    use_sppm: bool,
+    use_light_tracing: bool,
+    accumulation: film::Accumulation,
+    light_passes: bool,
+    id_passes: bool,
+    bake_path: Option<&'a str>,
+    bake_mode: BakeMode,
+    budget: render::Budget,
+    occlusion_distance: f32,
+    occlusion_falloff: f32,
+    write_interval: WriteInterval,
+    wireframe: Option<f32>,
+    vignetting: camera::Vignetting,
+    aperture_shape: Option<camera::Aperture>,
+    chromatic_aberration: f32,
+    lens_scale: f32,
+}
 
-            let mut fout =
-                tempfile::Builder::new()
-                .suffix(&output_suffix)
-                .tempfile_in(output_dir).unwrap();
+/// Load the scene, with the lens and sensor it is seen through, and render it for `target`. With `--watch` the files
+/// read go into `watcher`, and if one of them is saved while rendering the render is cancelled and the file returned.
+fn render_scene(matches: &ArgMatches, target: &mut Target, options: &RenderOptions, watcher: &mut Option<watch::Watcher>) -> Result<Option<PathBuf>, String> {
+    let RenderOptions {
+        get_scene, ref settings_overrides, auto_frame, ref camera_overrides, watch, output, format, alpha, frames,
+        defocus_factor, use_sppm, use_light_tracing, accumulation, light_passes, id_passes, bake_path, bake_mode, budget,
+        occlusion_distance, occlusion_falloff, write_interval, wireframe, vignetting, ref aperture_shape,
+        chromatic_aberration, lens_scale,
+    } = *options;
+    // Only scenes that load files get a progress bar
+    let loading = Mutex::new(None);
+    let loader = Loader::new(|progress: LoadProgress| {
+        let mut loading = loading.lock().unwrap();
+        let pb = loading.get_or_insert_with(|| ProgressBar::on(std::io::stderr(), 0));
+        pb.total = progress.total as u64;
+        if progress.fraction < 1.0 {
+            pb.message(&format!("Loading {} {:.0}% ", progress.step, progress.fraction*100.0));
+        } else {
+            pb.message(&format!("Loaded {} ", progress.step));
+        }
+        pb.set(progress.done as u64);
+    })
+        .preview(matches.is_present("preview"))
+        .subdivide(parsed(&matches, "subdivide", whole_number::<u32>).unwrap_or(0));
+    let sensor = match matches.value_of("sensor") {
+        Some(path) => match loader.sensor(path) {
+            Ok(response) => color::Sensor::Response(response.white_balanced()),
+            Err(error) => return Err(error.to_string()),
+        },
+        None => color::Sensor::Cie,
+    };
+    let lens_system = matches.value_of("lens").map(|lens| match lens {
+        "double-gauss" => Ok(lens_system::Lens::double_gauss()),
+        path => loader.lens(path).map_err(|error| error.to_string()),
+    }).transpose()?;
+    let lens_effects = |cam: camera::Camera| {
+        let cam = cam.with_vignetting(vignetting).with_chromatic_aberration(chromatic_aberration);
+        let cam = match aperture_shape {
+            Some(ref aperture) => cam.with_aperture(aperture.clone()),
+            None => cam,
+        };
+        match lens_system {
+            Some(ref lens) => cam.with_lens(lens, lens_scale),
+            None => Ok(cam),
+        }
+    };
+    let mut scene = {
+        let _span = trace::span("load", "scene");
+        get_scene(&loader)
+    };
+    // Watch the files as they are now, in case they change while the scene is built
+    if watch {
+        let files = loader.files();
+        *watcher = if files.is_empty() {
+            eprintln!("The scene reads no files, so there are no changes to watch for");
+            None
+        } else {
+            Some(watch::Watcher::new(&files).map_err(|error| error.to_string())?)
+        };
+    }
+    camera_overrides.apply(&mut scene);
+    let settings = settings_overrides.apply(scene.settings.apply(settings::RenderSettings::default()));
+    settings.check()?;
+    let (width, height, num_samples) = (settings.width, settings.height, settings.samples);
+    if auto_frame {
+        scene.auto_frame(settings.aspect());
+    }
+    let Scene{ objects, look_from, look_at, aperture, vfov, focus_dist, movements, render_sky, sky, lights: delta_lights, animation, flare, .. } = scene;
+    // Emitting objects are aimed at from diffuse surfaces along with the lights without a surface
+    let emitters = sppm::find_lights(&objects);
+    let light_tree = light_bvh::LightBVH::with_surfaces(delta_lights, emitters.clone());
+    let sky = if render_sky { Some(sky) } else { None };
+    drop(loader);
+    if let Some(mut pb) = loading.into_inner().unwrap() {
+        pb.finish_println("");
+    }
+    let flare = match matches.value_of("flare").unwrap() {
+        "scene" => flare,
+        "on" => flare.or_else(|| Some(flare::LensFlare::default())),
+        "off" => None,
+        mode => panic!("Unknown flare mode: {:?}", mode),
+    };
+    let grading = match camera_overrides.exposure() {
+        Some(camera) => grading_from_matches(&matches).with_camera(camera),
+        None => grading_from_matches(&matches),
+    };
+    let sampler: Arc<dyn Sampler> = match matches.value_of("sampler").unwrap() {
+        "random" => Arc::new(RandomSampler),
+        "stratified" => Arc::new(StratifiedSampler::new(num_samples as u32)),
+        "multi-jittered" => Arc::new(MultiJitteredSampler::new(num_samples as u32)),
+        "sobol" => Arc::new(SobolSampler),
+        name => panic!("Unknown sampler: {:?}", name),
+    };
+    let lens = match matches.value_of("lens-sampling").unwrap() {
+        "concentric" => LensSampling::Concentric,
+        "spiral" => LensSampling::Spiral { samples_per_pixel: num_samples as u32 },
+        name => panic!("Unknown lens sampling: {:?}", name),
+    };
+    // Photon mapping refines every pixel in every iteration
+    let max_defocus_samples = if use_sppm { 0 } else { (num_samples as f32*defocus_factor).round() as u32 };
+    // The mesh to bake is white, so the light it reflects is the light falling on it
+    let baking = bake_path.map(|path| {
+        let obj = hitable::wavefront::ObjMesh::load(Path::new(path)).map_err(|error| error.to_string())?;
+        let texels = bake::texels(&obj, width, height);
+        if texels.is_empty() {
+            return Err(format!("{} has no faces with texture coordinates to bake into", path));
+        }
+        let mesh: Mesh = Mesh::from_triangles(obj.to_triangles(Arc::new(Lambertian::new(Rgb::with_wp(1.0, 1.0, 1.0)))));
+        Ok(Bake { mesh: Arc::new(mesh), texels, mode: bake_mode })
+    }).transpose()?;
+    let mut objects = objects;
+    if let Some(ref baking) = baking {
+        objects.push(baking.mesh.clone());
+    }
+    let object_count = objects.len();
+    let lights = if use_sppm || use_light_tracing { emitters } else { Vec::new() };
+    // Objects without an id of their own go by their place in the scene
+    let objects = if id_passes {
+        objects.into_iter().enumerate().map(|(i, object)| Arc::new(with_id(object, i as u32)) as Arc<dyn Hitable>).collect()
+    } else {
+        objects
+    };
+    let world = BVH::initialize(objects);
+    eprintln!("Built BVH over {} objects with {:?} strategy", object_count, world.strategy());
+    let integrator = if use_sppm {
+        let photons = parsed(&matches, "photons", whole_number::<usize>).unwrap_or((width*height) as usize);
+        let radius = match parsed(&matches, "photon-radius", decimal) {
+            Some(radius) => radius,
+            None => {
+                let hitable::AABB { bounds: [low, high] } = world.bbox();
+                (high - low).length()*1e-2
+            },
+        };
+        eprintln!("Tracing {} photons per iteration from {} lights", photons, lights.len());
+        Integrator::Sppm { lights, photons, radius }
+    } else if use_light_tracing {
+        let paths = parsed(&matches, "photons", whole_number::<usize>).unwrap_or((width*height) as usize);
+        eprintln!("Tracing {} paths per pass from {} lights", paths, lights.len());
+        Integrator::Light { lights, paths }
+    } else if matches.value_of("integrator").unwrap() == "wireframe" {
+        Integrator::Wireframe
+    } else if matches.value_of("integrator").unwrap() == "occlusion" {
+        Integrator::Occlusion { distance: occlusion_distance, falloff: occlusion_falloff }
+    } else if let Some(view) = debug_view::DebugView::from_name(matches.value_of("integrator").unwrap()) {
+        Integrator::Debug(view)
+    } else {
+        Integrator::Path
+    };
+    // Front ends embedding the renderer cancel or pause it through the handle
+    let handle = render::RenderHandle::new();
+    let up = Vector3D::new(0.0, 1.0, 0.0);
+    let aspect = settings.aspect();
+    let start = camera::CameraKeyframe { look_from, look_at, vfov, aperture, focus_dist, movements };
+    let lighting = Lighting { sensor: &sensor, sky, lights: &light_tree };
+    let image_output = ImageOutput { width, height, format, alpha, grading };
+    let job = RenderJob {
+        integrator: &integrator,
+        settings: &settings,
+        sampler,
+        lens,
+        lighting,
+        accumulation,
+        wireframe,
+        image_output,
+        flare,
+        light_passes,
+        id_passes,
+        write_interval,
+        handle: &handle,
+        budget,
+    };
+    let mut changed = None;
 
-            match format {
-                image::ImageFormat::Hdr => {
-                    let buffer: Vec<_> =
-                        (0..(width*height))
-                        .map(|n| get_pixel_hdr(n%width, n/width))
-                        .collect();
-                    let encoder = HdrEncoder::new(&fout);
-                    encoder.encode(buffer.as_slice(), width as usize, height as usize).unwrap();
+    match (&baking, frames) {
+        (&Some(ref baking), _) => {
+            bake(&world, baking, &settings, lighting, image_output, output);
+        },
+        (&None, None) => {
+            let cam = lens_effects(start.to_camera(up, aspect, 0.0, 1.0)).map_err(|error| format!("--lens: {}", error))?;
+            let extra_samples = defocus_samples(&world, &start, width, height, max_defocus_samples);
+            let render_passes = |sampling, output| render(&world, &job, &cam, extra_samples.clone(), sampling, output);
+            match *target {
+                Target::File => {
+                    // A file saved while rendering cancels the render, to start over with the changes
+                    changed = thread::scope(|scope| {
+                        let (finished, done) = unbounded::<()>();
+                        let handle = &handle;
+                        let restart = watcher.as_ref().map(|watcher| scope.spawn(move || select! {
+                            recv(watcher.changes()) -> file => {
+                                handle.cancel();
+                                file.ok()
+                            },
+                            recv(done) -> _ => None,
+                        }));
+                        render_passes(Sampling::All, Some(output));
+                        drop(finished);
+                        restart.and_then(|restart| restart.join().unwrap())
+                    });
+                },
+                Target::Coordinator { addresses, token, args } => {
+                    render_passes(Sampling::Workers { addresses, token, args }, Some(output));
+                },
+                Target::Worker(ref mut stream) => loop {
+                    match distributed::Message::read_from(stream, None) {
+                        Ok(Some(distributed::Message::Passes(passes))) => {
+                            let film = render_passes(Sampling::Passes(passes), None);
+                            if let Err(error) = distributed::Message::Film(film).write_to(stream) {
+                                eprintln!("Sending the film failed: {}", error);
+                                break;
+                            }
+                        },
+                        Ok(None) => break,
+                        Ok(Some(_)) => {
+                            eprintln!("The coordinator didn't send passes to render");
+                            break;
+                        },
+                        Err(error) => {
+                            eprintln!("Receiving passes failed: {}", error);
+                            break;
+                        },
+                    }
                 },
-                _ => {
-                    let buffer = image::ImageBuffer::from_fn(width, height, get_pixel_ldr);
-                    image::DynamicImage::ImageRgb8(buffer).save_with_format(&mut fout, format).unwrap();
-                }
             }
-            fout.flush().unwrap();
-            fout.persist(&output_path).unwrap();
-            pb.add(samples_pending.len() as u64);
-        }
-        pb.finish_print("done");
-    });
-    let _res: () =
-        (0..num_samples)
-        .into_par_iter()
-        .map(|_| {
-            let sample: Vec<Xyz<E, f32>> =
-                (0..height*width)
-                .into_par_iter()
-                .map(|n| {
-                    let i = n%width;
-                    let j = height-(n/width);
-                    let wl = gen_range(wl_low, wl_high);
-                    let u = ((i as f32) + next_f32()) / (width as f32);
-                    let v = ((j as f32) + next_f32()) / (height as f32);
-                    let r = cam.get_ray(u, v, wl);
-                    color(r, &world, render_sky)*3.0
-                }).collect();
-            sender.send(sample).unwrap();
-        }).collect();
-
-    drop(sender);
-
-    saver.join().unwrap();
-    if do_profile {
-        cpuprofiler::PROFILER.lock().unwrap().stop().unwrap();
+        },
+        (&None, Some(frames)) => {
+            // Without a scene defined animation we just spin around the scene
+            let path = animation.unwrap_or(camera::CameraPath::Turntable(start));
+            let stem = output.file_stem().unwrap().to_str().unwrap();
+            let extension = output.extension().unwrap().to_str().unwrap();
+            for frame in 0..frames {
+                let frame_output = output.with_file_name(format!("{}_{:04}.{}", stem, frame, extension));
+                let keyframe = path.frame(frame, frames);
+                let cam = lens_effects(keyframe.to_camera(up, aspect, 0.0, 1.0)).map_err(|error| format!("--lens: {}", error))?;
+                let extra_samples = defocus_samples(&world, &keyframe, width, height, max_defocus_samples);
+                render(&world, &job, &cam, extra_samples, Sampling::All, Some(&frame_output));
+            }
+        },
     }
+    Ok(changed)
 }
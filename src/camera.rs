@@ -1,6 +1,109 @@
+use std::f32::consts::PI;
+use std::io::Error;
+use std::sync::Arc;
+use image::GrayImage;
 use ray::Ray;
 use euclid::*;
+use lens_system::{FocusedLens, Lens};
 use random::*;
+use sampler::sample_disk_concentric;
+
+/// Turning the camera about its viewing direction and moving its lens against the film,
+/// as a view camera or a tilt-shift lens does.
+/// The default leaves the camera as `look_from`, `look_at` and `up` place it.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Movements {
+    /// Degrees the camera is turned counterclockwise about its viewing direction, seen from behind the camera.
+    pub roll: f32,
+    /// Moves the view sideways and up without turning the camera, in image heights.
+    /// Looking level and shifting up keeps the verticals of a tall building parallel.
+    pub shift: Vector2D<f32, UnknownUnit>,
+    /// Degrees the plane of focus is tilted, away from the camera at the right for `x` and at the top for `y`.
+    /// Angles close to ±90° turn the plane to run along the view, like the ground or a table top for a miniature effect.
+    pub tilt: Vector2D<f32, UnknownUnit>,
+}
+
+impl Movements {
+    pub fn lerp(&self, other: &Movements, t: f32) -> Movements {
+        Movements {
+            roll: self.roll + (other.roll - self.roll)*t,
+            shift: self.shift.lerp(other.shift, t),
+            tilt: self.tilt.lerp(other.tilt, t),
+        }
+    }
+}
+
+/// The front opening of a lens barrel, which cuts off rays through the edge of the aperture that come in at a steep angle.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Barrel {
+    /// How far the opening lies in front of the aperture.
+    pub length: f32,
+    /// Radius of the opening, in the units of the scene like the aperture.
+    pub radius: f32,
+}
+
+/// Darkening towards the corners of the image, as real lenses give.
+/// The default lights the image evenly.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Vignetting {
+    /// Dim light coming in at an angle to the lens axis by the fourth power of the cosine of that angle,
+    /// the natural falloff of a lens as the aperture looks smaller and the film lies further away and at a slant.
+    pub natural: bool,
+    /// Block rays through the aperture that miss the opening of the barrel.
+    /// Off axis this narrows bokeh to a cat's eye, and stopping down lessens it as the rays pass closer to the axis.
+    pub barrel: Option<Barrel>,
+}
+
+/// The shape of the opening in the lens, which out of focus highlights take on.
+/// Without one the opening is round.
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    /// A regular polygon of this many straight blades, with a corner at the top, fitting in the round opening.
+    Blades(u32),
+    /// How much light every part of the opening lets through, from black for none to white for all,
+    /// with the image stretched over the square around it. Parts of the image outside the circle are never reached.
+    /// The image can't be empty, see `image`.
+    Image(Arc<GrayImage>),
+}
+
+impl Aperture {
+    /// An opening shaped like `image`, which needs at least one pixel.
+    pub fn image(image: GrayImage) -> Result<Aperture, String> {
+        if image.width() == 0 || image.height() == 0 {
+            return Err("the aperture image is empty".to_string());
+        }
+        Ok(Aperture::Image(Arc::new(image)))
+    }
+
+    /// The fraction of the light let through at a point of the unit disk, with `y` pointing up in the image.
+    pub fn transmission(&self, lens: Vector2D<f32, UnknownUnit>) -> f32 {
+        match *self {
+            Aperture::Blades(blades) => {
+                let sector = 2.0*PI/blades.max(3) as f32;
+                // The angle from the middle of the nearest blade
+                let angle = lens.x.atan2(lens.y).rem_euclid(sector) - 0.5*sector;
+                if lens.length()*angle.cos() <= (0.5*sector).cos() { 1.0 } else { 0.0 }
+            },
+            Aperture::Image(ref image) => {
+                let (width, height) = image.dimensions();
+                let x = ((lens.x + 1.0)*0.5*width as f32) as u32;
+                let y = ((1.0 - lens.y)*0.5*height as f32) as u32;
+                image.get_pixel(x.min(width - 1), y.min(height - 1)).0[0] as f32/255.0
+            },
+        }
+    }
+}
+
+/// How much nearer the plane of focus lies at the wavelength `wl`, as a fraction of one over the focus distance,
+/// for a lens whose focus differs by 1 between the blue F line and the red C line.
+/// Follows the dispersion of crown glass by Cauchy's equation, with no change at the yellow d line.
+fn focus_shift(wl: f32) -> f32 {
+    let inverse_square = |wl: f32| 1.0/(wl*wl);
+    (inverse_square(wl) - inverse_square(587.6))/(inverse_square(486.1) - inverse_square(656.3))
+}
 
 pub struct Camera {
     origin: Point3D<f32, UnknownUnit>,
@@ -9,41 +112,657 @@ pub struct Camera {
     vertical: Vector3D<f32, UnknownUnit>,
     u: Vector3D<f32, UnknownUnit>,
     v: Vector3D<f32, UnknownUnit>,
+    /// Points backwards along the lens axis.
+    w: Vector3D<f32, UnknownUnit>,
+    /// Normal of the plane of focus, scaled so the plane holds the points `p` relative to the origin with `p.dot(focus_normal) == -1`.
+    focus_normal: Vector3D<f32, UnknownUnit>,
     lens_radius: f32,
+    vignetting: Vignetting,
+    aperture: Option<Aperture>,
+    chromatic_aberration: f32,
+    lens_system: Option<FocusedLens>,
+    /// Millimeters of the lens system per unit of the scene.
+    millimeters: f32,
     t0: f32,
     t1: f32,
 }
 
 impl Camera {
-    pub fn new(look_from: Point3D<f32, UnknownUnit>, look_at: Point3D<f32, UnknownUnit>, up: Vector3D<f32, UnknownUnit>, vfov: f32, aspect: f32, aperture: f32, focus_dist: f32, t0: f32, t1: f32) -> Self {
+    /// A camera placed as `view` says, with `up` pointing up in the image and `aspect` its width over its height,
+    /// taking its rays between the times `t0` and `t1`.
+    pub fn new(view: &CameraKeyframe, up: Vector3D<f32, UnknownUnit>, aspect: f32, t0: f32, t1: f32) -> Self {
+        let CameraKeyframe { look_from, look_at, vfov, aperture, focus_dist, movements } = *view;
         let lens_radius = aperture*0.5;
         let theta = vfov.to_radians();
         let half_height = f32::tan(theta*0.5);
         let half_width = aspect * half_height;
         let origin = look_from;
         let w = (look_from - look_at).normalize();
-        let u = up.cross(w).normalize();
-        let v = w.cross(u);
-        let lower_left_corner = -u*half_width*focus_dist - v*half_height*focus_dist - w*focus_dist;
+        let (sin, cos) = movements.roll.to_radians().sin_cos();
+        let u_level = up.cross(w).normalize();
+        let v_level = w.cross(u_level);
+        let u = u_level*cos + v_level*sin;
+        let v = v_level*cos - u_level*sin;
+        let shift = (u*movements.shift.x + v*movements.shift.y)*2.0*half_height*focus_dist;
+        let lower_left_corner = -u*half_width*focus_dist - v*half_height*focus_dist - w*focus_dist + shift;
         let horizontal = u*2.0*half_width*focus_dist;
         let vertical = v*2.0*half_height*focus_dist;
+        // The plane of focus is `focus_dist` away along the viewing direction and then gets deeper by the tangent of the tilt
+        let focus_normal = (w + u*movements.tilt.x.to_radians().tan() + v*movements.tilt.y.to_radians().tan())/focus_dist;
         Camera {
             lower_left_corner,
             horizontal,
             vertical,
             origin,
-            u, v,
+            u, v, w,
+            focus_normal,
             lens_radius,
+            vignetting: Vignetting::default(),
+            aperture: None,
+            chromatic_aberration: 0.0,
+            lens_system: None,
+            millimeters: 1.0,
             t0, t1,
         }
     }
+
+    /// Darken the image towards its corners, see `transmission`.
+    pub fn with_vignetting(self, vignetting: Vignetting) -> Camera {
+        Camera { vignetting, ..self }
+    }
+
+    /// Give the opening of the lens a shape, see `transmission`.
+    /// Rays through the lens still start all over the disk, so a small opening takes more samples to lose its noise.
+    pub fn with_aperture(self, aperture: Aperture) -> Camera {
+        Camera { aperture: Some(aperture), ..self }
+    }
+
+    /// Focus every wavelength at a different distance, as a lens that isn't corrected for color does.
+    /// `amount` is how much more blue light at 486 nm is bent than red light at 656 nm, relative to the bending that
+    /// focuses at the focus distance: at 0.05 blue is in focus about 5% closer to the camera than red.
+    /// Out of focus edges get colored fringes, purple on one side of the plane of focus and green on the other.
+    pub fn with_chromatic_aberration(self, amount: f32) -> Camera {
+        Camera { chromatic_aberration: amount, ..self }
+    }
+
+    /// Trace the rays through the surfaces of a real lens instead of a thin one, with `millimeters` of the lens to a
+    /// unit of the scene. The front of the lens is at the camera, focused at the focus distance, with a film that
+    /// keeps the field of view for subjects far away. Its stop takes the place of the aperture, its own aberrations
+    /// that of `with_chromatic_aberration` and its own vignetting that of `with_vignetting` and `with_aperture`.
+    /// Shift and tilt are left out, and light traced from the lights can't be connected to the film through it.
+    pub fn with_lens(self, lens: &Lens, millimeters: f32) -> Result<Camera, Error> {
+        let focus_dist = 1.0/self.focus_normal.dot(self.w);
+        let vfov = 2.0*(0.5*self.vertical.length()/focus_dist).atan().to_degrees();
+        let aspect = self.horizontal.length()/self.vertical.length();
+        let lens_system = lens.focus(focus_dist*millimeters, vfov, aspect)?;
+        Ok(Camera { lens_system: Some(lens_system), millimeters, ..self })
+    }
+
+    /// The normal of the plane of focus at the wavelength `wl`, scaled like `focus_normal`.
+    fn focus_normal(&self, wl: f32) -> Vector3D<f32, UnknownUnit> {
+        self.focus_normal*(1.0 + self.chromatic_aberration*focus_shift(wl))
+    }
+
+    /// The height in the scene that a pixel of an image `height` pixels high covers at the depth of `p`,
+    /// measured along the viewing direction.
+    pub fn pixel_footprint(&self, p: Point3D<f32, UnknownUnit>, height: u32) -> f32 {
+        let depth = (self.origin - p).dot(self.w);
+        let view_height = self.vertical.length()/-self.lower_left_corner.dot(self.w);
+        depth.max(0.0)*view_height/height as f32
+    }
+
+    /// The fraction of the light along a ray from this camera that reaches the film.
+    /// Always 1 without vignetting or a shaped aperture, otherwise it depends on the angle of the ray to the lens axis and
+    /// where it passes the aperture.
+    pub fn transmission(&self, ray: &Ray) -> f32 {
+        let aperture = match self.aperture {
+            Some(ref aperture) if self.lens_radius > 0.0 => {
+                let offset = ray.origin - self.origin;
+                aperture.transmission(vec2(offset.dot(self.u), offset.dot(self.v))/self.lens_radius)
+            },
+            _ => 1.0,
+        };
+        if self.vignetting == Vignetting::default() || aperture == 0.0 {
+            return aperture;
+        }
+        let direction = ray.direction.normalize();
+        let cosine = -direction.dot(self.w);
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        if let Some(barrel) = self.vignetting.barrel {
+            // Where the ray crosses the plane of the opening, relative to the lens axis
+            let offset = ray.origin - self.origin;
+            let crossing = offset + direction*(barrel.length/cosine) + self.w*barrel.length;
+            if crossing.square_length() > barrel.radius*barrel.radius {
+                return 0.0;
+            }
+        }
+        if self.vignetting.natural {
+            aperture*cosine.powi(4)
+        } else {
+            aperture
+        }
+    }
 }
 
 impl Camera {
+    /// Get a ray from an independent random point on the lens.
+    /// Rejection sampling the disk is faster than the trigonometry of a mapping when there are no strata to keep.
     pub fn get_ray(&self, s: f32, t: f32, wl: f32) -> Ray {
-        let rd = rand_in_unit_disk()*self.lens_radius;
+        self.get_ray_at_lens(s, t, wl, rand_in_unit_disk())
+    }
+
+    /// Get a ray using a given sample in [0,1)² for the position on the lens.
+    pub fn get_ray_sampled(&self, s: f32, t: f32, wl: f32, lens: Vector2D<f32, UnknownUnit>) -> Ray {
+        self.get_ray_at_lens(s, t, wl, sample_disk_concentric(lens))
+    }
+
+    /// Get a ray through a given point of the unit disk, scaled to the lens.
+    pub fn get_ray_at_lens(&self, s: f32, t: f32, wl: f32, lens: Vector2D<f32, UnknownUnit>) -> Ray {
+        match self.get_ray_through_system(s, t, wl, lens) {
+            Some((ray, _)) => ray,
+            None => self.get_ray_through_lens(s, t, wl, lens*self.lens_radius),
+        }
+    }
+
+    /// Get a ray through a given point of the unit disk, with the fraction of the light along it that reaches the film.
+    /// That is the `transmission` for a thin lens, and through a lens system it also has the light of the part of the
+    /// exit pupil the point stands for.
+    pub fn get_ray_and_transmission(&self, s: f32, t: f32, wl: f32, lens: Vector2D<f32, UnknownUnit>) -> (Ray, f32) {
+        self.get_ray_through_system(s, t, wl, lens).unwrap_or_else(|| {
+            let ray = self.get_ray_through_lens(s, t, wl, lens*self.lens_radius);
+            (ray, self.transmission(&ray))
+        })
+    }
+
+    /// With a lens system, the ray through it and the light along it.
+    /// Rays the lens blocks come back as the ray through the center of a thin lens, with no light.
+    fn get_ray_through_system(&self, s: f32, t: f32, wl: f32, lens: Vector2D<f32, UnknownUnit>) -> Option<(Ray, f32)> {
+        let system = self.lens_system.as_ref()?;
+        Some(match system.ray(vec2(s, t), wl, lens) {
+            Some((p, d, weight)) => {
+                let origin = self.origin + (self.u*p.x + self.v*p.y - self.w*p.z)/self.millimeters;
+                let direction = self.u*d.x + self.v*d.y - self.w*d.z;
+                (Ray::new(origin, direction, wl, gen_range(self.t0, self.t1)), weight)
+            },
+            None => (self.get_ray_through_lens(s, t, wl, vec2(0.0, 0.0)), 0.0),
+        })
+    }
+
+    fn get_ray_through_lens(&self, s: f32, t: f32, wl: f32, rd: Vector2D<f32, UnknownUnit>) -> Ray {
         let ti = gen_range(self.t0, self.t1);
         let offset = self.u*rd.x + self.v*rd.y;
-        Ray::new(self.origin + offset, self.lower_left_corner + self.horizontal*s + self.vertical*t - offset, wl, ti)
+        let pinhole = self.lower_left_corner + self.horizontal*s + self.vertical*t;
+        // Rays through all of the lens meet where the ray through its center crosses the plane of focus.
+        // Tilted far enough the plane runs parallel to that ray, which is then in focus at infinity.
+        let along = -pinhole.dot(self.focus_normal(wl));
+        let direction = if along > 0.0 && along.is_finite() {
+            pinhole/along - offset
+        } else {
+            pinhole
+        };
+        Ray::new(self.origin + offset, direction, wl, ti)
+    }
+
+    /// Connect a point of the scene to the film through a given point of the unit disk on the lens, the reverse of `get_ray_at_lens`.
+    /// Returns where on the film the point shows up, in the coordinates passed to `get_ray`, which may lie outside the image,
+    /// the ray from the lens reaching the point at `t == 1`, and the importance of the camera along it: the density of
+    /// the film coordinates per solid angle at the lens, including the `transmission`. Nothing if the point is behind the camera,
+    /// or with a lens system, whose rays can't be found backwards.
+    pub fn connect(&self, p: Point3D<f32, UnknownUnit>, wl: f32, ti: f32, lens: Vector2D<f32, UnknownUnit>) -> Option<(Vector2D<f32, UnknownUnit>, Ray, f32)> {
+        if self.lens_system.is_some() {
+            return None;
+        }
+        let rd = lens*self.lens_radius;
+        let offset = self.u*rd.x + self.v*rd.y;
+        let direction = p - (self.origin + offset);
+        let focus_normal = self.focus_normal(wl);
+        // The ray through the center of the lens crossing the plane of focus where this one does
+        let along = (-1.0 - offset.dot(focus_normal))/direction.dot(focus_normal);
+        let focus = if along > 0.0 && along.is_finite() { offset + direction*along } else { direction };
+        let distance = -self.lower_left_corner.dot(self.w);
+        if !(focus.dot(self.w) < 0.0) || direction.dot(self.w) >= 0.0 {
+            return None;
+        }
+        let pinhole = focus*(distance/-focus.dot(self.w)) - self.lower_left_corner;
+        let film = vec2(pinhole.dot(self.horizontal)/self.horizontal.square_length(), pinhole.dot(self.vertical)/self.vertical.square_length());
+        let ray = Ray::new(self.origin + offset, direction, wl, ti);
+        let cosine = -direction.dot(self.w)/direction.length();
+        let area = self.horizontal.length()*self.vertical.length()/(distance*distance);
+        Some((film, ray, self.transmission(&ray)/(area*cosine*cosine*cosine)))
+    }
+}
+
+/// The parameters needed to place a camera in a scene.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CameraKeyframe {
+    pub look_from: Point3D<f32, UnknownUnit>,
+    pub look_at: Point3D<f32, UnknownUnit>,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focus_dist: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub movements: Movements,
+}
+
+impl CameraKeyframe {
+    /// Linearly interpolate between two keyframes, `t=0` being `self`.
+    pub fn lerp(&self, other: &CameraKeyframe, t: f32) -> CameraKeyframe {
+        let mix = |a: f32, b: f32| a + (b - a)*t;
+        CameraKeyframe {
+            look_from: self.look_from.lerp(other.look_from, t),
+            look_at: self.look_at.lerp(other.look_at, t),
+            vfov: mix(self.vfov, other.vfov),
+            aperture: mix(self.aperture, other.aperture),
+            focus_dist: mix(self.focus_dist, other.focus_dist),
+            movements: self.movements.lerp(&other.movements, t),
+        }
+    }
+
+    pub fn to_camera(&self, up: Vector3D<f32, UnknownUnit>, aspect: f32, t0: f32, t1: f32) -> Camera {
+        Camera::new(self, up, aspect, t0, t1)
+    }
+
+    /// Turn the camera around the point it looks at, by `yaw` radians about the vertical and `pitch` radians up.
+    /// It stops short of looking straight down or up, where the vertical can't tell which way is up in the image.
+    pub fn orbit(&self, yaw: f32, pitch: f32) -> CameraKeyframe {
+        let offset = self.look_from - self.look_at;
+        if offset.square_length() == 0.0 {
+            return *self;
+        }
+        let distance = offset.length();
+        let azimuth = f32::atan2(offset.x, offset.z) + yaw;
+        let limit = PI*0.5 - 1e-3;
+        // Rounding can take the ratio just past 1 looking straight down or up
+        let elevation = ((offset.y/distance).clamp(-1.0, 1.0).asin() + pitch).max(-limit).min(limit);
+        let (sin_azimuth, cos_azimuth) = azimuth.sin_cos();
+        let (sin_elevation, cos_elevation) = elevation.sin_cos();
+        let rotated = vec3(cos_elevation*sin_azimuth, sin_elevation, cos_elevation*cos_azimuth)*distance;
+        CameraKeyframe { look_from: self.look_at + rotated, ..*self }
+    }
+
+    /// Move the camera and the point it looks at across the view, by `right` and `up` times the height of the view at
+    /// that point, so a drag by a fraction of the image height moves the scene along by as much. Looking straight up
+    /// or down, right is taken to be along x.
+    pub fn pan(&self, right: f32, up: f32) -> CameraKeyframe {
+        let forward = self.look_at - self.look_from;
+        if forward.square_length() == 0.0 {
+            return *self;
+        }
+        let height = 2.0*forward.length()*(self.vfov.to_radians()*0.5).tan();
+        let side = forward.cross(vec3(0.0, 1.0, 0.0));
+        let side = if side.square_length() > 1e-12*forward.square_length() { side.normalize() } else { vec3(1.0, 0.0, 0.0) };
+        let above = side.cross(forward).normalize();
+        let offset = (side*right + above*up)*height;
+        CameraKeyframe { look_from: self.look_from + offset, look_at: self.look_at + offset, ..*self }
+    }
+
+    /// Move the camera towards the point it looks at, to `factor` times the distance, and the focus along with it.
+    pub fn zoom(&self, factor: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            look_from: self.look_at + (self.look_from - self.look_at)*factor,
+            focus_dist: self.focus_dist*factor,
+            ..*self
+        }
+    }
+
+    /// Diameter in pixels of the blur a point at `depth` along the viewing direction gets,
+    /// for an image `height` pixels high. Tilting the plane of focus is not taken into account.
+    pub fn defocus_blur(&self, depth: f32, height: u32) -> f32 {
+        if depth <= 0.0 {
+            return 0.0;
+        }
+        // The circle of confusion projected onto the focus plane, relative to the height of the view there
+        let circle = self.aperture*(depth - self.focus_dist).abs()/depth;
+        let view_height = 2.0*self.focus_dist*(self.vfov.to_radians()*0.5).tan();
+        circle/view_height*height as f32
+    }
+}
+
+/// Describes how the camera moves over the course of an animation.
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CameraPath {
+    /// Keyframes at increasing times, interpolated linearly. There has to be at least one, see `check`.
+    Keyframes(Vec<(f32, CameraKeyframe)>),
+    /// A full orbit of `look_from` around the vertical axis through `look_at`.
+    Turntable(CameraKeyframe),
+}
+
+impl CameraPath {
+    /// A path through `keyframes`, which `check` has to find fine.
+    pub fn keyframes(keyframes: Vec<(f32, CameraKeyframe)>) -> Result<CameraPath, String> {
+        let path = CameraPath::Keyframes(keyframes);
+        path.check()?;
+        Ok(path)
+    }
+
+    /// Whether the camera can follow the path: keyframes need at least one of them, at times in order.
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            CameraPath::Keyframes(ref keyframes) if keyframes.is_empty() => Err("the camera path has no keyframes".to_string()),
+            CameraPath::Keyframes(ref keyframes) => match keyframes.windows(2).find(|pair| pair[0].0 > pair[1].0 || pair[0].0.is_nan() || pair[1].0.is_nan()) {
+                Some(pair) => Err(format!("the keyframe at {} comes after the one at {}", pair[1].0, pair[0].0)),
+                None => Ok(()),
+            },
+            CameraPath::Turntable(_) => Ok(()),
+        }
+    }
+
+    /// Get the camera for a given time in the range [0,1].
+    /// Keyframe times are normalized to that range. Panics for a path `check` rejects.
+    pub fn at(&self, t: f32) -> CameraKeyframe {
+        match self {
+            &CameraPath::Keyframes(ref keyframes) => {
+                let (t_start, first) = keyframes[0];
+                let (t_end, last) = keyframes[keyframes.len()-1];
+                let t = t_start + (t_end - t_start)*t;
+                if t <= t_start {
+                    return first;
+                }
+                for (&(t0, k0), &(t1, k1)) in keyframes.iter().zip(keyframes[1..].iter()) {
+                    if t <= t1 {
+                        return k0.lerp(&k1, (t - t0) / (t1 - t0));
+                    }
+                }
+                last
+            },
+            &CameraPath::Turntable(start) => {
+                let angle = (t*360.0).to_radians();
+                let (sin, cos) = angle.sin_cos();
+                let offset = start.look_from - start.look_at;
+                let rotated = vec3(
+                    cos*offset.x + sin*offset.z,
+                    offset.y,
+                    -sin*offset.x + cos*offset.z,
+                );
+                CameraKeyframe { look_from: start.look_at + rotated, ..start }
+            },
+        }
+    }
+
+    /// Get the camera for frame `frame` out of `frames`.
+    /// A turntable loops, so its last frame stops one step short of the first.
+    pub fn frame(&self, frame: u32, frames: u32) -> CameraKeyframe {
+        let steps = match self {
+            &CameraPath::Keyframes(_) => frames.max(2) - 1,
+            &CameraPath::Turntable(_) => frames.max(1),
+        };
+        self.at(frame as f32 / steps as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(x: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            look_from: point3(x, 0.0, 10.0),
+            look_at: point3(0.0, 0.0, 0.0),
+            vfov: 30.0 + x,
+            aperture: 0.0,
+            focus_dist: 10.0,
+            movements: Movements::default(),
+        }
+    }
+
+    #[test]
+    fn test_keyframes() {
+        let path = CameraPath::keyframes(vec![(0.0, keyframe(0.0)), (1.0, keyframe(2.0)), (3.0, keyframe(6.0))]).unwrap();
+        assert_eq!(path.at(0.0), keyframe(0.0));
+        assert_eq!(path.at(1.0), keyframe(6.0));
+        assert_eq!(path.frame(0, 4), keyframe(0.0));
+        assert_eq!(path.frame(1, 4), keyframe(2.0));
+        assert_eq!(path.frame(3, 4), keyframe(6.0));
+        assert_eq!(CameraPath::keyframes(Vec::new()), Err("the camera path has no keyframes".to_string()));
+        assert_eq!(CameraPath::keyframes(vec![(1.0, keyframe(0.0)), (0.5, keyframe(2.0))]),
+                   Err("the keyframe at 0.5 comes after the one at 1".to_string()));
+        assert_eq!(CameraPath::keyframes(vec![(2.0, keyframe(0.0))]).unwrap().at(0.5), keyframe(0.0));
+    }
+
+    #[test]
+    fn test_navigation() {
+        let start = keyframe(0.0);
+        // Orbiting about the vertical is what a turntable does
+        let turned = start.orbit(PI*0.5, 0.0);
+        assert!((turned.look_from - CameraPath::Turntable(start).at(0.25).look_from).length() < 1e-4);
+        let above = start.orbit(0.0, PI*0.25);
+        assert!((above.look_from - point3(0.0, 10.0*0.5f32.sqrt(), 10.0*0.5f32.sqrt())).length() < 1e-4);
+        let top = start.orbit(0.0, PI);
+        assert!(top.look_from.z > 0.0 && (top.look_from.to_vector().length() - 10.0).abs() < 1e-4, "{:?}", top.look_from);
+        assert_eq!(CameraKeyframe { look_from: start.look_at, ..start }.orbit(0.5, 0.5), CameraKeyframe { look_from: start.look_at, ..start });
+        let down = CameraKeyframe { look_from: point3(0.0, 10.0, 0.0), ..start }.orbit(0.0, 0.0);
+        assert!(down.look_from.x.is_finite() && (down.look_from.to_vector().length() - 10.0).abs() < 1e-4, "{:?}", down.look_from);
+
+        let panned = start.pan(0.5, 0.25);
+        let height = 20.0*(15.0f32.to_radians()).tan();
+        assert!((panned.look_at - point3(0.5*height, 0.25*height, 0.0)).length() < 1e-4);
+        assert_eq!(panned.look_from - panned.look_at, start.look_from - start.look_at);
+        // Looking straight down there is no side to the vertical
+        let down = CameraKeyframe { look_from: point3(0.0, 10.0, 0.0), ..start }.pan(0.5, 0.0);
+        assert!((down.look_at - point3(0.5*height, 0.0, 0.0)).length() < 1e-4, "{:?}", down.look_at);
+        assert_eq!(CameraKeyframe { look_from: start.look_at, ..start }.pan(0.5, 0.5), CameraKeyframe { look_from: start.look_at, ..start });
+
+        let closer = start.zoom(0.5);
+        assert_eq!((closer.look_from, closer.look_at, closer.focus_dist), (point3(0.0, 0.0, 5.0), start.look_at, 5.0));
+    }
+
+    #[test]
+    fn test_defocus_blur() {
+        let camera = CameraKeyframe { aperture: 0.5, ..keyframe(0.0) };
+        assert_eq!(camera.defocus_blur(10.0, 600), 0.0);
+        assert_eq!(keyframe(0.0).defocus_blur(5.0, 600), 0.0);
+        let near = camera.defocus_blur(5.0, 600);
+        let far = camera.defocus_blur(20.0, 600);
+        assert!((near - 0.5/(20.0*(15.0f32).to_radians().tan())*600.0).abs() < 1e-3);
+        // Far away the blur approaches the aperture, close by it grows without bound
+        assert!(far < near);
+        assert!(camera.defocus_blur(1e6, 600) < near);
+        assert!(camera.defocus_blur(0.1, 600) > 10.0*near);
+    }
+
+    #[test]
+    fn test_pixel_footprint() {
+        let camera = keyframe(0.0).to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0);
+        let view_height = 2.0*(15.0f32).to_radians().tan();
+        let depth = |p: Point3D<f32, UnknownUnit>| (p - keyframe(0.0).look_from).dot((keyframe(0.0).look_at - keyframe(0.0).look_from).normalize());
+        for &p in &[keyframe(0.0).look_at, point3(1.0, 2.0, 3.0)] {
+            assert!((camera.pixel_footprint(p, 100) - depth(p)*view_height/100.0).abs() < 1e-5);
+        }
+        assert_eq!(camera.pixel_footprint(keyframe(0.0).look_from, 100), 0.0);
+    }
+
+    // Distance of `p` from the line the ray runs along
+    fn miss(ray: Ray, p: Point3D<f32, UnknownUnit>) -> f32 {
+        (p - ray.origin).cross(ray.direction.normalize()).length()
+    }
+
+    #[test]
+    fn test_roll() {
+        let movements = Movements { roll: 90.0, ..Movements::default() };
+        let camera = CameraKeyframe { movements, ..keyframe(0.0) }.to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0);
+        // Turned counterclockwise, the right of the image shows what was above
+        let right = camera.get_ray_at_lens(1.0, 0.5, 550.0, vec2(0.0, 0.0));
+        assert!(right.direction.y > 0.0 && right.direction.x.abs() < 1e-5, "{:?}", right.direction);
+        let top = camera.get_ray_at_lens(0.5, 1.0, 550.0, vec2(0.0, 0.0));
+        assert!(top.direction.x < 0.0 && top.direction.y.abs() < 1e-5, "{:?}", top.direction);
+    }
+
+    #[test]
+    fn test_shift() {
+        let level = keyframe(0.0).to_camera(vec3(0.0, 1.0, 0.0), 1.5, 0.0, 1.0);
+        let movements = Movements { shift: vec2(0.0, 0.5), ..Movements::default() };
+        let shifted = CameraKeyframe { movements, ..keyframe(0.0) }.to_camera(vec3(0.0, 1.0, 0.0), 1.5, 0.0, 1.0);
+        // Half an image height up the center is where the top edge was, while the camera still looks level
+        let center = shifted.get_ray_at_lens(0.5, 0.5, 550.0, vec2(0.0, 0.0));
+        let top = level.get_ray_at_lens(0.5, 1.0, 550.0, vec2(0.0, 0.0));
+        assert!((center.direction - top.direction).length() < 1e-5);
+        let bottom = shifted.get_ray_at_lens(0.5, 0.0, 550.0, vec2(0.0, 0.0));
+        assert!(bottom.direction.y.abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_tilt() {
+        let camera = CameraKeyframe { aperture: 1.0, ..keyframe(0.0) };
+        let untilted = camera.to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0);
+        let movements = Movements { tilt: vec2(0.0, 45.0), ..Movements::default() };
+        let tilted = CameraKeyframe { movements, ..camera }.to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0);
+        let half_height = (15.0f32).to_radians().tan();
+        for &(s, t) in [(0.5, 0.5), (0.5, 1.0), (0.0, 0.0), (0.8, 0.3)].iter() {
+            // At 45° the plane of focus is as much deeper as a point is higher than the center
+            let pinhole = untilted.get_ray_at_lens(s, t, 550.0, vec2(0.0, 0.0));
+            let y = (t - 0.5)*2.0*half_height;
+            let focus = pinhole.origin + pinhole.direction/(1.0 - y);
+            for &lens in [vec2(1.0, 0.0), vec2(0.0, -1.0), vec2(-0.6, 0.6)].iter() {
+                assert!(miss(tilted.get_ray_at_lens(s, t, 550.0, lens), focus) < 1e-4);
+                assert!(miss(untilted.get_ray_at_lens(s, t, 550.0, lens), pinhole.origin + pinhole.direction) < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vignetting() {
+        // A 90° field of view puts the top edge 45° off axis
+        let camera = CameraKeyframe { vfov: 90.0, aperture: 1.0, ..keyframe(0.0) }.to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0);
+        let even = camera.get_ray_at_lens(0.5, 1.0, 550.0, vec2(0.0, 0.0));
+        assert_eq!(camera.transmission(&even), 1.0);
+        let natural = camera.with_vignetting(Vignetting { natural: true, barrel: None });
+        let center = natural.get_ray_at_lens(0.5, 0.5, 550.0, vec2(0.0, 0.0));
+        assert!((natural.transmission(&center) - 1.0).abs() < 1e-5);
+        let top = natural.get_ray_at_lens(0.5, 1.0, 550.0, vec2(0.0, 0.0));
+        assert!((natural.transmission(&top) - 0.25).abs() < 1e-5, "{}", natural.transmission(&top));
+
+        let barrel = Barrel { length: 1.0, radius: 0.75 };
+        let camera = natural.with_vignetting(Vignetting { natural: false, barrel: Some(barrel) });
+        for &lens in [vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, -1.0), vec2(-0.6, 0.6)].iter() {
+            assert_eq!(camera.transmission(&camera.get_ray_at_lens(0.5, 0.5, 550.0, lens)), 1.0);
+        }
+        // Rays to the top edge go up by the length of the barrel, so only those through the bottom of the aperture get through
+        let ray = |y: f32| camera.get_ray_at_lens(0.5, 1.0, 550.0, vec2(0.0, y));
+        assert_eq!(camera.transmission(&ray(0.0)), 0.0);
+        assert_eq!(camera.transmission(&ray(-1.0)), 1.0);
+        assert_eq!(camera.transmission(&ray(1.0)), 0.0);
+    }
+
+    #[test]
+    fn test_aperture() {
+        let hexagon = Aperture::Blades(6);
+        assert_eq!(hexagon.transmission(vec2(0.0, 0.0)), 1.0);
+        // Out to the circle at the corners, in to cos 30° at the middle of the blades
+        assert_eq!(hexagon.transmission(vec2(0.0, 0.99)), 1.0);
+        assert_eq!(hexagon.transmission(vec2(0.0, -0.99)), 1.0);
+        assert_eq!(hexagon.transmission(vec2(0.85, 0.0)), 1.0);
+        assert_eq!(hexagon.transmission(vec2(0.9, 0.0)), 0.0);
+        assert_eq!(hexagon.transmission(vec2(0.45, 0.78)), 0.0);
+
+        use image::Luma;
+        let half = Aperture::image(GrayImage::from_fn(2, 2, |x, y| Luma([if y == 1 { 0 } else if x == 0 { 255 } else { 51 }]))).unwrap();
+        let camera = CameraKeyframe { aperture: 1.0, ..keyframe(0.0) }.to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0).with_aperture(half);
+        let through = |lens| camera.transmission(&camera.get_ray_at_lens(0.3, 0.6, 550.0, lens));
+        assert_eq!(through(vec2(-0.5, 0.5)), 1.0);
+        assert!((through(vec2(0.5, 0.5)) - 0.2).abs() < 1e-6);
+        assert_eq!(through(vec2(0.0, -0.5)), 0.0);
+        // Natural vignetting on top of it
+        let camera = camera.with_vignetting(Vignetting { natural: true, barrel: None });
+        assert!(camera.transmission(&camera.get_ray_at_lens(1.0, 1.0, 550.0, vec2(-0.5, 0.5))) < 1.0);
+        // A pinhole has nothing to shape
+        let pinhole = keyframe(0.0).to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0).with_aperture(Aperture::Blades(3));
+        assert_eq!(pinhole.transmission(&pinhole.get_ray_at_lens(0.5, 0.5, 550.0, vec2(0.0, -0.9))), 1.0);
+        assert!(Aperture::image(GrayImage::new(0, 4)).is_err());
+        assert!(Aperture::image(GrayImage::new(4, 0)).is_err());
+    }
+
+    #[test]
+    fn test_chromatic_aberration() {
+        assert!(focus_shift(587.6).abs() < 1e-6);
+        assert!((focus_shift(486.1) - focus_shift(656.3) - 1.0).abs() < 1e-5);
+
+        let camera = CameraKeyframe { aperture: 1.0, ..keyframe(0.0) }.to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0).with_chromatic_aberration(0.1);
+        for &(s, t) in [(0.5, 0.5), (0.2, 0.9)].iter() {
+            for &wl in [587.6, 486.1, 656.3, 420.0].iter() {
+                // The plane of focus moves from 10 to 10/(1 + 0.1*shift)
+                let pinhole = camera.get_ray_at_lens(s, t, wl, vec2(0.0, 0.0));
+                let focus = pinhole.point_at_parameter(1.0);
+                assert!((10.0 - focus.z - 10.0/(1.0 + 0.1*focus_shift(wl))).abs() < 1e-4, "{} nm", wl);
+                for &lens in [vec2(1.0, 0.0), vec2(-0.6, 0.6)].iter() {
+                    let ray = camera.get_ray_at_lens(s, t, wl, lens);
+                    assert!(miss(ray, focus) < 1e-4, "{} nm", wl);
+                    let (film, _, _) = camera.connect(ray.point_at_parameter(3.0), wl, 0.0, lens).unwrap();
+                    assert!((film - vec2(s, t)).length() < 1e-4);
+                }
+            }
+        }
+        // Blue is in focus nearer than red
+        assert!(focus_shift(450.0) > 0.0 && focus_shift(650.0) < 0.0);
+    }
+
+    #[test]
+    fn test_lens_system() {
+        let lens = Lens::double_gauss();
+        let camera = keyframe(0.0).to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0).with_lens(&lens, 1000.0).unwrap();
+        let (center, transmission) = camera.get_ray_and_transmission(0.5, 0.5, 550.0, vec2(0.0, 0.0));
+        assert!((center.origin - point3(0.0, 0.0, 10.0)).length() < 1e-3 && transmission > 0.0);
+        assert!(center.direction.normalize().z < -0.9999);
+        // The field of view is kept, up to the distortion of the lens
+        let right = camera.get_ray_at_lens(1.0, 0.5, 550.0, vec2(0.0, 0.0)).direction;
+        assert!((right.x/-right.z/(15.0f32).to_radians().tan() - 1.0).abs() < 0.05, "{:?}", right);
+        let top = camera.get_ray_at_lens(0.5, 1.0, 550.0, vec2(0.0, 0.0)).direction;
+        assert!(top.y > 0.0 && top.x.abs() < 1e-4);
+        // Rays from the lens meet where it is focused
+        let focus = center.origin + center.direction.normalize()*10.0;
+        for &lens in [vec2(0.5, 0.0), vec2(-0.3, -0.4)].iter() {
+            let (ray, transmission) = camera.get_ray_and_transmission(0.5, 0.5, 550.0, lens);
+            assert!(transmission > 0.0 && miss(ray, focus) < 5e-3, "{}", miss(ray, focus));
+        }
+        assert!(camera.connect(point3(0.0, 0.0, 0.0), 550.0, 0.0, vec2(0.0, 0.0)).is_none());
+        assert!(CameraKeyframe { focus_dist: 0.01, ..keyframe(0.0) }.to_camera(vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0).with_lens(&lens, 1000.0).is_err());
+    }
+
+    #[test]
+    fn test_connect() {
+        let movements = Movements { shift: vec2(0.1, 0.2), tilt: vec2(0.0, 30.0), ..Movements::default() };
+        let camera = CameraKeyframe { aperture: 1.0, movements, ..keyframe(1.0) }.to_camera(vec3(0.0, 1.0, 0.0), 1.5, 0.0, 1.0);
+        for &(s, t) in [(0.5, 0.5), (0.1, 0.9), (1.2, -0.1)].iter() {
+            for &lens in [vec2(0.0, 0.0), vec2(0.8, 0.0), vec2(-0.3, 0.5)].iter() {
+                let ray = camera.get_ray_at_lens(s, t, 550.0, lens);
+                let p = ray.point_at_parameter(3.0);
+                let (film, back, _) = camera.connect(p, 550.0, 0.0, lens).unwrap();
+                assert!((film - vec2(s, t)).length() < 1e-4, "{:?} for {:?}", film, (s, t));
+                assert!((back.point_at_parameter(1.0) - p).length() < 1e-4);
+                assert!(camera.connect(ray.point_at_parameter(-3.0), 550.0, 0.0, lens).is_none());
+            }
+        }
+
+        // The importance adds up to one over the directions the film sees
+        let camera = keyframe(0.0).to_camera(vec3(0.0, 1.0, 0.0), 1.5, 0.0, 1.0);
+        let steps = 400;
+        let step = 2.0/steps as f32;
+        let mut sum = 0.0;
+        for i in 0..steps {
+            for j in 0..steps {
+                // Points on a face of a cube around the camera, with the solid angle they cover
+                let q = vec3(-1.0 + step*(i as f32 + 0.5), -1.0 + step*(j as f32 + 0.5), -1.0);
+                let solid_angle = step*step/q.length().powi(3);
+                if let Some((film, _, importance)) = camera.connect(point3(0.0, 0.0, 10.0) + q, 550.0, 0.0, vec2(0.0, 0.0)) {
+                    if film.x >= 0.0 && film.x < 1.0 && film.y >= 0.0 && film.y < 1.0 {
+                        sum += importance*solid_angle;
+                    }
+                }
+            }
+        }
+        assert!((sum - 1.0).abs() < 0.01, "{}", sum);
+    }
+
+    #[test]
+    fn test_turntable() {
+        let path = CameraPath::Turntable(keyframe(0.0));
+        let half = path.at(0.5);
+        assert!((half.look_from - point3(0.0, 0.0, -10.0)).length() < 0.001);
+        assert_eq!(half.look_at, point3(0.0, 0.0, 0.0));
+        let quarter = path.frame(1, 4);
+        assert!((quarter.look_from - point3(10.0, 0.0, 0.0)).length() < 0.001);
     }
 }
@@ -0,0 +1,484 @@
+//! Parsers for command line values, and settings built from them, for front ends on top of the library.
+
+use clap::{Arg, ArgMatches};
+use euclid::*;
+use std::str::FromStr;
+
+use camera::CameraKeyframe;
+use color::{CameraExposure, Chromaticity, ColorGrading, ColorSpace, WavelengthSampling};
+use film::Filter;
+use scene::Scene;
+use settings::SettingsOverrides;
+use tiles::TileOrder;
+
+pub fn whole_number<T: FromStr>(value: &str) -> Result<T, String> {
+    T::from_str(value.trim()).map_err(|_| format!("expected a whole number of at least 0, got {:?}", value))
+}
+
+/// A whole number of at least 1.
+pub fn positive<T: FromStr + PartialOrd + From<u8>>(value: &str) -> Result<T, String> {
+    match whole_number::<T>(value) {
+        Ok(n) if n >= T::from(1) => Ok(n),
+        _ => Err(format!("expected a whole number of at least 1, got {:?}", value)),
+    }
+}
+
+pub fn decimal(value: &str) -> Result<f32, String> {
+    f32::from_str(value.trim()).map_err(|_| format!("expected a number, got {:?}", value))
+}
+
+/// A number above 0.
+pub fn above_zero(value: &str) -> Result<f32, String> {
+    match decimal(value) {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        _ => Err(format!("expected a number above 0, got {:?}", value)),
+    }
+}
+
+/// A time in seconds, as a number or a fraction like 1/125.
+pub fn shutter_time(value: &str) -> Result<f32, String> {
+    let time = match value.split('/').collect::<Vec<_>>()[..] {
+        [numerator, denominator] => decimal(numerator).and_then(|n| Ok(n/decimal(denominator)?)),
+        _ => decimal(value),
+    };
+    match time {
+        Ok(time) if time > 0.0 && time.is_finite() => Ok(time),
+        _ => Err(format!("expected a time in seconds above 0 like 0.5 or 1/125, got {:?}", value)),
+    }
+}
+
+/// Two values separated by a comma.
+pub fn pair<T>(value: &str, parse: fn(&str) -> Result<T, String>) -> Result<(T, T), String> {
+    match value.split(',').collect::<Vec<_>>()[..] {
+        [a, b] => Ok((parse(a)?, parse(b)?)),
+        _ => Err(format!("expected two values separated by a comma, got {:?}", value)),
+    }
+}
+
+/// Three coordinates separated by commas.
+pub fn point(value: &str) -> Result<Point3D<f32, UnknownUnit>, String> {
+    match value.split(',').collect::<Vec<_>>()[..] {
+        [x, y, z] => Ok(point3(decimal(x)?, decimal(y)?, decimal(z)?)),
+        _ => Err(format!("expected three numbers separated by commas, got {:?}", value)),
+    }
+}
+
+/// A range of wavelengths `low,high` in nanometers.
+pub fn wavelength_range(value: &str) -> Result<(f32, f32), String> {
+    match pair(value, decimal) {
+        Ok((low, high)) if low > 0.0 && low < high => Ok((low, high)),
+        _ => Err(format!("expected the shortest and longest wavelength in nm like 390,700, got {:?}", value)),
+    }
+}
+
+/// A white point as the name of a standard illuminant like `D65`, or its chromaticity `x,y`.
+pub fn white_point(value: &str) -> Result<Chromaticity, String> {
+    if let Some(white) = Chromaticity::named(value.trim()) {
+        return Ok(white);
+    }
+    match pair(value, decimal) {
+        Ok((x, y)) if x > 0.0 && y > 0.0 && x + y <= 1.0 => Ok(Chromaticity { x, y }),
+        _ => Err(format!("expected an illuminant like D65 or a chromaticity x,y, got {:?}", value)),
+    }
+}
+
+/// The value of an option, which clap already checked with the same `parse` function.
+pub fn parsed<T>(matches: &ArgMatches, name: &str, parse: fn(&str) -> Result<T, String>) -> Option<T> {
+    matches.value_of(name).map(|value| parse(value).unwrap_or_else(|message| panic!("--{} passed validation: {}", name, message)))
+}
+
+/// Changes to the camera a scene comes with, to look at it from elsewhere.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CameraOverrides {
+    pub look_from: Option<Point3D<f32, UnknownUnit>>,
+    pub look_at: Option<Point3D<f32, UnknownUnit>>,
+    /// Vertical field of view in degrees.
+    pub vfov: Option<f32>,
+    pub aperture: Option<f32>,
+    pub focus_dist: Option<f32>,
+    /// Focal length in millimeters of a full frame camera, with film 24 mm tall.
+    pub focal_length: Option<f32>,
+    pub iso: Option<f32>,
+    /// Shutter time in seconds.
+    pub shutter: Option<f32>,
+    pub f_number: Option<f32>,
+}
+
+impl CameraOverrides {
+    /// The options setting the overrides: `--look-from`, `--look-at`, `--fov`, `--aperture`, `--focus-dist`,
+    /// `--focal-length`, `--iso`, `--shutter` and `--f-number`.
+    pub fn args() -> Vec<Arg<'static>> {
+        vec![
+            Arg::new("look-from")
+                .long("look-from")
+                .value_name("X,Y,Z")
+                .allow_hyphen_values(true)
+                .validator(point)
+                .help("Place the camera at another point than the scene does"),
+            Arg::new("look-at")
+                .long("look-at")
+                .value_name("X,Y,Z")
+                .allow_hyphen_values(true)
+                .validator(point)
+                .help("Point the camera at another point than the scene does"),
+            Arg::new("fov")
+                .long("fov")
+                .value_name("DEGREES")
+                .validator(decimal)
+                .help("Vertical field of view of the camera"),
+            Arg::new("aperture")
+                .long("aperture")
+                .value_name("DIAMETER")
+                .validator(decimal)
+                .help("Diameter of the lens, 0 for a pinhole camera"),
+            Arg::new("focus-dist")
+                .long("focus-dist")
+                .value_name("DISTANCE")
+                .validator(decimal)
+                .help("Distance to the plane in focus. Moving the camera without it focuses where the camera looks"),
+            Arg::new("focal-length")
+                .long("focal-length")
+                .value_name("MM")
+                .validator(above_zero)
+                .conflicts_with("fov")
+                .help("Focal length of a full frame camera, setting the field of view. With --f-number it sets the aperture too, taking the scene to be in meters"),
+            Arg::new("iso")
+                .long("iso")
+                .value_name("ISO")
+                .validator(above_zero)
+                .help("Sensitivity of the film, exposing the image like a camera with --shutter and --f-number. Defaults to 100"),
+            Arg::new("shutter")
+                .long("shutter")
+                .value_name("SECONDS")
+                .validator(shutter_time)
+                .help("Shutter time for the exposure, like 1/125. Defaults to 1. Motion blur spans the shutter of the scene whatever it is"),
+            Arg::new("f-number")
+                .long("f-number")
+                .value_name("N")
+                .validator(above_zero)
+                .help("Focal length over the diameter of the aperture for the exposure. Defaults to 1"),
+        ]
+    }
+
+    pub fn from_matches(matches: &ArgMatches) -> CameraOverrides {
+        CameraOverrides {
+            look_from: parsed(matches, "look-from", point),
+            look_at: parsed(matches, "look-at", point),
+            vfov: parsed(matches, "fov", decimal),
+            aperture: parsed(matches, "aperture", decimal),
+            focus_dist: parsed(matches, "focus-dist", decimal),
+            focal_length: parsed(matches, "focal-length", above_zero),
+            iso: parsed(matches, "iso", above_zero),
+            shutter: parsed(matches, "shutter", shutter_time),
+            f_number: parsed(matches, "f-number", above_zero),
+        }
+    }
+
+    /// Overrides placing the camera as `keyframe` does, like a view found in a preview, to render it.
+    pub fn from_keyframe(keyframe: &CameraKeyframe) -> CameraOverrides {
+        CameraOverrides {
+            look_from: Some(keyframe.look_from),
+            look_at: Some(keyframe.look_at),
+            vfov: Some(keyframe.vfov),
+            aperture: Some(keyframe.aperture),
+            focus_dist: Some(keyframe.focus_dist),
+            ..Default::default()
+        }
+    }
+
+    /// The options giving these overrides, to print for the command line of the final render.
+    pub fn to_args(&self) -> Vec<String> {
+        let point = |p: Point3D<f32, UnknownUnit>| format!("{},{},{}", p.x, p.y, p.z);
+        let options = [
+            ("look-from", self.look_from.map(point)),
+            ("look-at", self.look_at.map(point)),
+            ("fov", self.vfov.map(|x| x.to_string())),
+            ("aperture", self.aperture.map(|x| x.to_string())),
+            ("focus-dist", self.focus_dist.map(|x| x.to_string())),
+            ("focal-length", self.focal_length.map(|x| x.to_string())),
+            ("iso", self.iso.map(|x| x.to_string())),
+            ("shutter", self.shutter.map(|x| x.to_string())),
+            ("f-number", self.f_number.map(|x| x.to_string())),
+        ];
+        options.iter()
+            .filter_map(|(name, value)| value.as_ref().map(|value| vec![format!("--{}", name), value.clone()]))
+            .flatten()
+            .collect()
+    }
+
+    /// The exposure of a camera with the ISO, shutter time and f-number given, if any are, with defaults for the others.
+    pub fn exposure(&self) -> Option<CameraExposure> {
+        if self.iso.is_none() && self.shutter.is_none() && self.f_number.is_none() {
+            return None;
+        }
+        let default = CameraExposure::default();
+        Some(CameraExposure {
+            iso: self.iso.unwrap_or(default.iso),
+            shutter: self.shutter.unwrap_or(default.shutter),
+            f_number: self.f_number.unwrap_or(default.f_number),
+        })
+    }
+
+    /// Change the camera of the scene. If the camera moves or turns without a new focus distance,
+    /// it focuses on the point it looks at. A focal length sets the field of view, and with an f-number the aperture
+    /// it has in meters, unless the aperture is given as well.
+    pub fn apply(&self, scene: &mut Scene) {
+        scene.look_from = self.look_from.unwrap_or(scene.look_from);
+        scene.look_at = self.look_at.unwrap_or(scene.look_at);
+        scene.vfov = match self.focal_length {
+            Some(focal_length) => 2.0*(12.0/focal_length).atan().to_degrees(),
+            None => self.vfov.unwrap_or(scene.vfov),
+        };
+        scene.aperture = match (self.aperture, self.focal_length, self.f_number) {
+            (Some(aperture), _, _) => aperture,
+            (None, Some(focal_length), Some(f_number)) => focal_length/1000.0/f_number,
+            _ => scene.aperture,
+        };
+        scene.focus_dist = match self.focus_dist {
+            Some(focus_dist) => focus_dist,
+            None if self.look_from.is_some() || self.look_at.is_some() => (scene.look_at - scene.look_from).length(),
+            None => scene.focus_dist,
+        };
+    }
+}
+
+/// The options adjusting the colors of the output: `--exposure`, `--adapt-from`, `--adapt-to` and `--saturation`.
+pub fn grading_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("exposure")
+            .long("exposure")
+            .value_name("EV")
+            .allow_hyphen_values(true)
+            .validator(decimal)
+            .help("Stops to brighten the image by, negative to darken it"),
+        Arg::new("adapt-from")
+            .long("adapt-from")
+            .value_name("WHITE")
+            .validator(white_point)
+            .help("White point of the light in the scene, like A or D65, or as x,y. Defaults to E, equal energy"),
+        Arg::new("adapt-to")
+            .long("adapt-to")
+            .value_name("WHITE")
+            .validator(white_point)
+            .help("White point the light in the scene is adapted to. Defaults to E, equal energy"),
+        Arg::new("saturation")
+            .long("saturation")
+            .value_name("FACTOR")
+            .validator(decimal)
+            .help("Saturation of the colors, 0 for grey, 1 to keep them"),
+        Arg::new("color-space")
+            .long("color-space")
+            .value_name("SPACE")
+            .possible_values(["srgb", "rec709", "display-p3", "acescg"])
+            .help("Primaries of the channels of EXR output: sRGB, the same as Rec.709, by default, Display P3, or ACEScg for ACES pipelines"),
+    ]
+}
+
+pub fn grading_from_matches(matches: &ArgMatches) -> ColorGrading {
+    let default = ColorGrading::default();
+    ColorGrading {
+        exposure: parsed(matches, "exposure", decimal).unwrap_or(default.exposure),
+        source_white: parsed(matches, "adapt-from", white_point).unwrap_or(default.source_white),
+        target_white: parsed(matches, "adapt-to", white_point).unwrap_or(default.target_white),
+        saturation: parsed(matches, "saturation", decimal).unwrap_or(default.saturation),
+        color_space: matches.value_of("color-space").map_or(default.color_space, |name| ColorSpace::from_name(name).unwrap()),
+    }
+}
+
+/// The options changing the `RenderSettings`: `--width`, `--height`, `--samples`, `--wavelength-range`,
+/// `--wavelength-sampling`, `--max-depth`, `--roulette-threshold`, `--epsilon-scale`, `--filter`, `--filter-radius`,
+/// `--tile-order`, `--tile-size` and `--strict-nan`.
+pub fn settings_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("width")
+            .long("width")
+            .value_name("NUMBER")
+            .validator(positive::<u32>)
+            .help("Width of the image in pixels. Alone it keeps the aspect ratio, 4:3 unless the scene has its own"),
+        Arg::new("height")
+            .long("height")
+            .value_name("NUMBER")
+            .validator(positive::<u32>)
+            .help("Height of the image in pixels. Alone it keeps the aspect ratio"),
+        Arg::new("samples")
+            .long("samples")
+            .value_name("NUMBER")
+            .validator(whole_number::<u64>)
+            .help("Passes over every pixel, 100 unless the scene asks for others"),
+        Arg::new("wavelength-range")
+            .long("wavelength-range")
+            .value_name("LOW,HIGH")
+            .validator(wavelength_range)
+            .help("Shortest and longest wavelength traced in nm, 390,700 by default"),
+        Arg::new("wavelength-sampling")
+            .long("wavelength-sampling")
+            .value_name("DISTRIBUTION")
+            .possible_values(["uniform", "luminance"])
+            .help("How the wavelength of each path is drawn: all alike, or more often where the eye is more sensitive, which leaves less color noise"),
+        Arg::new("max-depth")
+            .long("max-depth")
+            .value_name("BOUNCES")
+            .validator(whole_number::<u32>)
+            .help("Bounces of a path hitting objects without a limit of their own, 50 by default"),
+        Arg::new("roulette-threshold")
+            .long("roulette-threshold")
+            .value_name("THROUGHPUT")
+            .validator(decimal)
+            .help("End paths carrying less than this by russian roulette, 0 to never end them early"),
+        Arg::new("epsilon-scale")
+            .long("epsilon-scale")
+            .value_name("FACTOR")
+            .validator(decimal)
+            .help("Scale the distance rays skip past their origin, up against speckled self shadowing, down against light leaking through gaps"),
+        Arg::new("filter")
+            .long("filter")
+            .value_name("FILTER")
+            .possible_values(["box", "tent", "gaussian", "mitchell", "blackman-harris"])
+            .help("How samples are spread over the pixels around them. Wider filters than the box trade sharpness for less aliasing"),
+        Arg::new("filter-radius")
+            .long("filter-radius")
+            .value_name("PIXELS")
+            .validator(decimal)
+            .help("How far the filter reaches from the center of a pixel. Defaults to 0.5 for the box, 1 for the tent, 1.5 for the Gaussian and 2 for the others"),
+        Arg::new("tile-order")
+            .long("tile-order")
+            .value_name("ORDER")
+            .possible_values(["scanline", "spiral", "hilbert"])
+            .help("The order the tiles of every pass are rendered and show up in previews in: row by row, outwards from the center, or along a Hilbert curve. Photon mapping goes row by row"),
+        Arg::new("tile-size")
+            .long("tile-size")
+            .value_name("PIXELS")
+            .validator(positive::<u32>)
+            .help("Width and height of the tiles, 32 by default"),
+        Arg::new("strict-nan")
+            .long("strict-nan")
+            .help("Stop at the first radiance that isn't a finite number and tell where it came from, instead of dropping it and counting"),
+    ]
+}
+
+pub fn settings_from_matches(matches: &ArgMatches) -> SettingsOverrides {
+    SettingsOverrides {
+        width: parsed(matches, "width", whole_number),
+        height: parsed(matches, "height", whole_number),
+        samples: parsed(matches, "samples", whole_number),
+        wavelength_range: parsed(matches, "wavelength-range", wavelength_range),
+        wavelength_sampling: matches.value_of("wavelength-sampling").map(|name| WavelengthSampling::from_name(name).unwrap()),
+        max_depth: parsed(matches, "max-depth", whole_number),
+        roulette_threshold: parsed(matches, "roulette-threshold", decimal),
+        epsilon_scale: parsed(matches, "epsilon-scale", decimal),
+        filter: matches.value_of("filter").map(|name| Filter::from_name(name).unwrap()),
+        filter_radius: parsed(matches, "filter-radius", decimal),
+        tile_order: matches.value_of("tile-order").map(|name| TileOrder::from_name(name).unwrap()),
+        tile_size: parsed(matches, "tile-size", whole_number),
+        strict_nan: if matches.is_present("strict-nan") { Some(true) } else { None },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Command;
+    use camera::Movements;
+    use sky::Sky;
+
+    #[test]
+    fn test_values() {
+        assert_eq!(whole_number::<u32>(" 12"), Ok(12));
+        assert!(whole_number::<u32>("-1").is_err());
+        assert_eq!(pair("3,4", decimal), Ok((3.0, 4.0)));
+        assert_eq!(point("1,-2.5, 3"), Ok(point3(1.0, -2.5, 3.0)));
+        assert_eq!(point("1,2"), Err("expected three numbers separated by commas, got \"1,2\"".to_string()));
+        assert_eq!(point("1,x,3"), Err("expected a number, got \"x\"".to_string()));
+        assert_eq!(white_point("d50"), Ok(Chromaticity::D50));
+        assert_eq!(white_point("0.3,0.35"), Ok(Chromaticity { x: 0.3, y: 0.35 }));
+        assert!(white_point("0.8,0.8").is_err());
+    }
+
+    #[test]
+    fn test_camera_overrides() {
+        let cli = Command::new("test").args(CameraOverrides::args());
+        let matches = cli.clone().try_get_matches_from(vec!["test", "--look-from", "-3,4,0", "--fov", "25"]).unwrap();
+        let overrides = CameraOverrides::from_matches(&matches);
+        assert_eq!(overrides, CameraOverrides { look_from: Some(point3(-3.0, 4.0, 0.0)), vfov: Some(25.0), ..Default::default() });
+
+        let mut scene = Scene {
+            objects: Vec::new(),
+            look_from: point3(0.0, 0.0, 10.0),
+            look_at: point3(0.0, 0.0, 0.0),
+            focus_dist: 10.0,
+            aperture: 0.1,
+            vfov: 40.0,
+            movements: Movements::default(),
+            render_sky: true,
+            sky: Sky::Gradient,
+            lights: Vec::new(),
+            animation: None,
+            flare: None,
+            settings: SettingsOverrides::default(),
+        };
+        overrides.apply(&mut scene);
+        assert_eq!((scene.look_from, scene.look_at), (point3(-3.0, 4.0, 0.0), point3(0.0, 0.0, 0.0)));
+        assert_eq!((scene.vfov, scene.aperture, scene.focus_dist), (25.0, 0.1, 5.0));
+        CameraOverrides { look_at: Some(point3(0.0, 4.0, 0.0)), focus_dist: Some(2.0), ..Default::default() }.apply(&mut scene);
+        assert_eq!((scene.look_at, scene.focus_dist), (point3(0.0, 4.0, 0.0), 2.0));
+        assert_eq!(overrides.exposure(), None);
+
+        // A 50 mm lens at f/2 is 25 mm wide, and sees about 27 degrees high
+        let matches = cli.try_get_matches_from(vec!["test", "--focal-length", "50", "--f-number", "2", "--shutter", "1/4"]).unwrap();
+        let overrides = CameraOverrides::from_matches(&matches);
+        overrides.apply(&mut scene);
+        assert!((scene.aperture - 0.025).abs() < 1e-6 && (scene.vfov - 26.99).abs() < 0.01, "{} {}", scene.aperture, scene.vfov);
+        assert_eq!(overrides.exposure(), Some(CameraExposure { iso: 100.0, shutter: 0.25, f_number: 2.0 }));
+    }
+
+    #[test]
+    fn test_camera_args() {
+        let keyframe = CameraKeyframe {
+            look_from: point3(0.0, 1.0, 10.0),
+            look_at: point3(0.0, 1.0, 0.0),
+            vfov: 40.0,
+            aperture: 0.1,
+            focus_dist: 10.0,
+            movements: Movements::default(),
+        }.orbit(0.3, -0.2).pan(0.1, 0.0);
+        let overrides = CameraOverrides::from_keyframe(&keyframe);
+        let args = overrides.to_args();
+        assert_eq!(args[0], "--look-from");
+        assert_eq!(args.len(), 10);
+        let matches = Command::new("test").args(CameraOverrides::args()).try_get_matches_from(Some("test".to_string()).into_iter().chain(args)).unwrap();
+        assert_eq!(CameraOverrides::from_matches(&matches), overrides);
+    }
+
+    #[test]
+    fn test_grading() {
+        let cli = Command::new("test").args(grading_args());
+        let matches = cli.clone().try_get_matches_from(vec!["test", "--exposure", "-1.5", "--adapt-to", "D65"]).unwrap();
+        assert_eq!(grading_from_matches(&matches),
+            ColorGrading::default().with_exposure(-1.5).with_white_balance(Chromaticity::E, Chromaticity::D65));
+        let matches = cli.try_get_matches_from(vec!["test", "--color-space", "acescg"]).unwrap();
+        assert_eq!(grading_from_matches(&matches), ColorGrading::default().with_color_space(ColorSpace::AcesCg));
+    }
+
+    #[test]
+    fn test_settings() {
+        let cli = Command::new("test").args(settings_args());
+        let matches = cli.clone().try_get_matches_from(vec![
+            "test", "--width", "400", "--wavelength-range", "380,780", "--wavelength-sampling", "luminance",
+            "--filter", "gaussian", "--tile-order", "hilbert", "--strict-nan",
+        ]).unwrap();
+        let overrides = settings_from_matches(&matches);
+        assert_eq!(overrides, SettingsOverrides {
+            width: Some(400),
+            wavelength_range: Some((380.0, 780.0)),
+            wavelength_sampling: Some(WavelengthSampling::Luminance),
+            filter: Some(Filter::Gaussian { radius: 1.5, alpha: 2.0 }),
+            tile_order: Some(TileOrder::Hilbert),
+            strict_nan: Some(true),
+            ..Default::default()
+        });
+        assert!(cli.clone().try_get_matches_from(vec!["test", "--tile-size", "0"]).is_err());
+        assert!(cli.clone().try_get_matches_from(vec!["test", "--wavelength-range", "700,390"]).is_err());
+        assert!(cli.try_get_matches_from(vec!["test", "--filter", "lanczos"]).is_err());
+    }
+}
@@ -2,14 +2,17 @@ use std::marker::PhantomData;
 use std::ops::*;
 use std::fmt::Debug;
 use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::path::Path;
 use palette::*;
 use palette::white_point::E;
 
 use color::cie_1931::xyz_from_wavelength;
 use color::HasReflectance;
+use color::kahan::KahanXyz;
 
 pub trait BinData: Send + Sync {
-    type Spectrum: Clone + Copy + AsRef<[f32]> + AsMut<[f32]> + Send + Sync;
     const WL_0: f32;
     const BIN_WIDTH: f32;
 }
@@ -17,127 +20,261 @@ pub trait BinData: Send + Sync {
 #[derive(Debug)]
 pub struct Bin36;
 impl BinData for Bin36 {
-    type Spectrum = [f32; 36];
     const WL_0: f32 = 360.0;
     const BIN_WIDTH: f32 = 10.0;
 }
 
 /// The standard spectrum type used
-pub type ColorSpectrum = BinnedSpectrum<Bin36>;
+pub type ColorSpectrum = BinnedSpectrum<Bin36, 36>;
 
 /// A binned representation of the visible spectrum.
 /// Values outside this range are clamped to the nearest index.
-pub struct BinnedSpectrum<T: BinData> {
-    spectrum: T::Spectrum,
+pub struct BinnedSpectrum<T: BinData, const N: usize> {
+    spectrum: [f32; N],
     marker: PhantomData<T>
 }
 
-impl<T: BinData> PartialEq for BinnedSpectrum<T> {
+impl<T: BinData, const N: usize> PartialEq for BinnedSpectrum<T, N> {
     fn eq(&self, right: &Self) -> bool {
-        let mut res = true;
-        for (&x,&y) in self.spectrum.as_ref().iter().zip(right.spectrum.as_ref().iter()) {
-            res &= x==y;
-        }
-        return res;
+        self.spectrum == right.spectrum
     }
 }
 
-impl<T: BinData> Debug for BinnedSpectrum<T> {
+impl<T: BinData, const N: usize> Debug for BinnedSpectrum<T, N> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("BinnedSpectrum")
             .field("wl_0", &T::WL_0)
             .field("bin_width", &T::BIN_WIDTH)
-            .field("spectrum", &self.spectrum.as_ref())
+            .field("spectrum", &self.spectrum)
             .finish()
     }
 }
 
-impl<T: BinData> BinnedSpectrum<T> {
-    pub const fn new(spectrum: T::Spectrum) -> BinnedSpectrum<T> {
+impl<T: BinData, const N: usize> BinnedSpectrum<T, N> {
+    pub const fn new(spectrum: [f32; N]) -> BinnedSpectrum<T, N> {
         BinnedSpectrum{ spectrum, marker: PhantomData }
     }
+
+    /// Sample a function of the wavelength at the center of every bin.
+    pub fn from_fn<F: Fn(f32) -> f32>(f: F) -> BinnedSpectrum<T, N> {
+        let mut res = [0.0; N];
+        for (i, x) in res.iter_mut().enumerate() {
+            *x = f(T::WL_0 + T::BIN_WIDTH*(i as f32 + 0.5));
+        }
+        BinnedSpectrum::new(res)
+    }
+
+    /// Resample measured `(wavelength, value)` pairs, e.g. a reflectance curve.
+    /// The data is interpolated linearly and averaged over every bin.
+    /// Bins outside the measured range take the value of the nearest measurement.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # use rayer::color::*;
+    /// let spectrum = ColorSpectrum::from_samples(&[(400.0, 0.0), (700.0, 1.0)]);
+    /// assert_eq!(spectrum.reflect(300.0), 0.0);
+    /// assert!((spectrum.reflect(550.0) - 0.5).abs() < 0.02);
+    /// ```
+    pub fn from_samples(samples: &[(f32, f32)]) -> BinnedSpectrum<T, N> {
+        const SUBSAMPLES: usize = 8;
+        let mut samples = samples.to_vec();
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let interpolate = |wl: f32| {
+            match samples.iter().position(|&(x, _)| x >= wl) {
+                None => samples.last().map_or(0.0, |&(_, y)| y),
+                Some(0) => samples[0].1,
+                Some(i) => {
+                    let (x0, y0) = samples[i-1];
+                    let (x1, y1) = samples[i];
+                    y0 + (y1 - y0)*(wl - x0)/(x1 - x0)
+                }
+            }
+        };
+        let mut res = [0.0; N];
+        for (i, x) in res.iter_mut().enumerate() {
+            let start = T::WL_0 + T::BIN_WIDTH*(i as f32);
+            let sum: f32 = (0..SUBSAMPLES)
+                .map(|j| interpolate(start + T::BIN_WIDTH*(j as f32 + 0.5)/(SUBSAMPLES as f32)))
+                .sum();
+            *x = sum/(SUBSAMPLES as f32);
+        }
+        BinnedSpectrum::new(res)
+    }
+
+    /// Read measured data from a CSV file with a wavelength in nm and a value on every line.
+    /// Empty lines, lines starting with `#` and a header line are skipped.
+    pub fn from_csv(path: &Path) -> Result<BinnedSpectrum<T, N>, Error> {
+        let mut samples = Vec::new();
+        for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split(|c| c == ',' || c == ';' || c == '\t').map(str::trim);
+            match (fields.next().map(str::parse::<f32>), fields.next().map(str::parse::<f32>)) {
+                (Some(Ok(wl)), Some(Ok(value))) if wl.is_finite() => samples.push((wl, value)),
+                _ if i == 0 => continue,
+                _ => return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}:{}: expected a wavelength and a value", path.display(), i+1),
+                )),
+            }
+        }
+        if samples.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{}: no spectral data", path.display())));
+        }
+        Ok(BinnedSpectrum::from_samples(&samples))
+    }
+
+    /// Apply a function to every bin.
+    pub fn map<F: Fn(f32) -> f32>(self, f: F) -> BinnedSpectrum<T, N> {
+        let mut res = self.spectrum;
+        for x in res.iter_mut() {
+            *x = f(*x);
+        }
+        BinnedSpectrum::new(res)
+    }
 }
 
-impl<T> Copy for BinnedSpectrum<T> where
-    T: BinData
-{}
-impl<T> Clone for BinnedSpectrum<T> where
-    T: BinData
-{
-    fn clone(&self) -> BinnedSpectrum<T> {
+impl<T: BinData, const N: usize> Copy for BinnedSpectrum<T, N> {}
+impl<T: BinData, const N: usize> Clone for BinnedSpectrum<T, N> {
+    fn clone(&self) -> BinnedSpectrum<T, N> {
         *self
     }
 }
 
-impl<T: BinData> Add for BinnedSpectrum<T> {
-    type Output = BinnedSpectrum<T>;
-    fn add(self, other: BinnedSpectrum<T>) -> BinnedSpectrum<T> {
-        let mut res = self.spectrum.clone();
-        for (a, b) in other.spectrum.as_ref().iter().zip(res.as_mut().iter_mut()) {
+/// Spectra are written as the list of their bins.
+#[cfg(feature = "serde")]
+impl<T: BinData, const N: usize> ::serde::Serialize for BinnedSpectrum<T, N> {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.spectrum.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: BinData, const N: usize> ::serde::Deserialize<'de> for BinnedSpectrum<T, N> {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bins = Vec::<f32>::deserialize(deserializer)?;
+        if bins.len() != N {
+            return Err(::serde::de::Error::invalid_length(bins.len(), &format!("{} bins", N).as_str()));
+        }
+        let mut spectrum = [0.0; N];
+        spectrum.copy_from_slice(&bins);
+        Ok(BinnedSpectrum::new(spectrum))
+    }
+}
+
+impl<T: BinData, const N: usize> Add for BinnedSpectrum<T, N> {
+    type Output = BinnedSpectrum<T, N>;
+    fn add(self, other: BinnedSpectrum<T, N>) -> BinnedSpectrum<T, N> {
+        let mut res = self.spectrum;
+        for (a, b) in other.spectrum.iter().zip(res.iter_mut()) {
             *b = *a+*b;
         }
         BinnedSpectrum::new(res)
     }
 }
 
-impl<T: BinData> AddAssign for BinnedSpectrum<T> {
-    fn add_assign(&mut self, other: BinnedSpectrum<T>) {
-        for (a, b) in other.spectrum.as_ref().iter().zip(self.spectrum.as_mut().iter_mut()) {
+impl<T: BinData, const N: usize> AddAssign for BinnedSpectrum<T, N> {
+    fn add_assign(&mut self, other: BinnedSpectrum<T, N>) {
+        for (a, b) in other.spectrum.iter().zip(self.spectrum.iter_mut()) {
             *b = *a+*b;
         }
     }
 }
 
-impl<T: BinData> Mul<BinnedSpectrum<T>> for f32 {
-    type Output = BinnedSpectrum<T>;
-    fn mul(self, other: BinnedSpectrum<T>) -> BinnedSpectrum<T> {
-        let mut res = other.spectrum.clone();
-        for x in res.as_mut().iter_mut() {
+impl<T: BinData, const N: usize> Mul<BinnedSpectrum<T, N>> for f32 {
+    type Output = BinnedSpectrum<T, N>;
+    fn mul(self, other: BinnedSpectrum<T, N>) -> BinnedSpectrum<T, N> {
+        let mut res = other.spectrum;
+        for x in res.iter_mut() {
             *x *= self;
         }
         BinnedSpectrum::new(res)
     }
 }
 
-impl<T: BinData> HasReflectance for BinnedSpectrum<T> {
+impl<T: BinData, const N: usize> HasReflectance for BinnedSpectrum<T, N> {
     fn reflect(&self, wl: f32) -> f32 {
         let mut index: isize = ((wl-T::WL_0)/T::BIN_WIDTH) as isize;
         if index < 0 {
             index = 0;
         }
-        let len = self.spectrum.as_ref().len() as isize;
+        let len = N as isize;
         if index >= len {
             index = len-1;
         }
-        self.spectrum.as_ref()[index as usize]
+        self.spectrum[index as usize]
     }
 
     fn reflect_xyz(&self) -> Xyz<E, f32> {
-        let mut res = Xyz::with_wp(0.0, 0.0, 0.0);
-        let mut wl = T::WL_0;
-        for &v in self.spectrum.as_ref().iter() {
-            res = res+(xyz_from_wavelength(wl)*v);
-            wl += T::BIN_WIDTH;
+        let mut res = KahanXyz::new();
+        for (i, &v) in self.spectrum.iter().enumerate() {
+            let wl = T::WL_0 + T::BIN_WIDTH*(i as f32);
+            res.add(xyz_from_wavelength(wl)*v);
         }
-        res = res*3.0/(self.spectrum.as_ref().len() as f32);
-        return res;
+        return res.sum()*3.0/(N as f32);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_from_samples_constant() {
+        let spectrum = ColorSpectrum::from_samples(&[(380.0, 0.25), (500.0, 0.25), (780.0, 0.25)]);
+        assert_eq!(spectrum, ColorSpectrum::new([0.25; 36]));
+    }
+
+    #[test]
+    fn test_from_csv() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "wavelength,reflectance").unwrap();
+        writeln!(file, "# measured").unwrap();
+        writeln!(file, "400, 0.2").unwrap();
+        writeln!(file, "600, 0.6").unwrap();
+        let spectrum = ColorSpectrum::from_csv(file.path()).unwrap();
+        assert!((spectrum.reflect(380.0) - 0.2).abs() < 1e-6);
+        assert!((spectrum.reflect(500.0) - 0.4).abs() < 0.02);
+        assert!((spectrum.reflect(700.0) - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_from_csv_invalid() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "400, 0.2").unwrap();
+        writeln!(file, "600, oops").unwrap();
+        assert!(ColorSpectrum::from_csv(file.path()).is_err());
+    }
+
+    #[test]
+    fn test_nan_wavelengths() {
+        // Sorting the samples doesn't panic on them
+        ColorSpectrum::from_samples(&[(f32::NAN, 0.5), (400.0, 0.2), (600.0, 0.6)]);
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "400, 0.2").unwrap();
+        writeln!(file, "NaN, 0.6").unwrap();
+        assert!(ColorSpectrum::from_csv(file.path()).is_err());
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
     use super::*;
     use test::*;
 
     #[derive(Debug)]
     struct Bin10;
     impl BinData for Bin10 {
-        type Spectrum = [f32; 10];
         const WL_0: f32 = 380.0;
         const BIN_WIDTH: f32 = 34.0;
     }
 
-    type ColorSpectrum10 = BinnedSpectrum<Bin10>;
+    type ColorSpectrum10 = BinnedSpectrum<Bin10, 10>;
 
     #[bench]
     fn bench_add(bench: &mut Bencher) {
@@ -0,0 +1,252 @@
+use palette::*;
+use palette::white_point::E;
+
+use color::ColorSpace;
+
+/// Cone responses from XYZ, as the Bradford chromatic adaptation transform models them.
+const BRADFORD: [[f32; 3]; 3] = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+const BRADFORD_INVERSE: [[f32; 3]; 3] = [
+    [0.986_993, -0.147_054, 0.159_963],
+    [0.432_305, 0.518_360, 0.049_291],
+    [-0.008_529, 0.040_043, 0.968_487],
+];
+
+/// A white point as its CIE 1931 xy chromaticity.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Chromaticity {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Chromaticity {
+    /// The equal energy white the renders work in.
+    pub const E: Chromaticity = Chromaticity { x: 1.0/3.0, y: 1.0/3.0 };
+    pub const D50: Chromaticity = Chromaticity { x: 0.345_67, y: 0.358_50 };
+    pub const D55: Chromaticity = Chromaticity { x: 0.332_42, y: 0.347_43 };
+    pub const D65: Chromaticity = Chromaticity { x: 0.312_71, y: 0.329_02 };
+    pub const D75: Chromaticity = Chromaticity { x: 0.299_02, y: 0.314_85 };
+    /// Incandescent light.
+    pub const A: Chromaticity = Chromaticity { x: 0.447_57, y: 0.407_45 };
+    pub const F2: Chromaticity = Chromaticity { x: 0.372_08, y: 0.375_29 };
+    pub const F7: Chromaticity = Chromaticity { x: 0.312_92, y: 0.329_33 };
+    pub const F11: Chromaticity = Chromaticity { x: 0.380_52, y: 0.377_13 };
+
+    /// The standard illuminant with a name like `D65`, ignoring case.
+    pub fn named(name: &str) -> Option<Chromaticity> {
+        Some(match name.to_ascii_uppercase().as_str() {
+            "E" => Chromaticity::E,
+            "D50" => Chromaticity::D50,
+            "D55" => Chromaticity::D55,
+            "D65" => Chromaticity::D65,
+            "D75" => Chromaticity::D75,
+            "A" => Chromaticity::A,
+            "F2" => Chromaticity::F2,
+            "F7" => Chromaticity::F7,
+            "F11" => Chromaticity::F11,
+            _ => return None,
+        })
+    }
+
+    /// The color of this white with a luminance of 1.
+    pub(crate) fn xyz(self) -> [f32; 3] {
+        [self.x/self.y, 1.0, (1.0 - self.x - self.y)/self.y]
+    }
+}
+
+/// Adapt a color in XYZ from the white point `source` to `target` with the Bradford transform.
+pub(crate) fn adapt(xyz: [f32; 3], source: Chromaticity, target: Chromaticity) -> [f32; 3] {
+    let source = transform(&BRADFORD, source.xyz());
+    let target = transform(&BRADFORD, target.xyz());
+    let cone = transform(&BRADFORD, xyz);
+    let cone = [cone[0]*target[0]/source[0], cone[1]*target[1]/source[1], cone[2]*target[2]/source[2]];
+    transform(&BRADFORD_INVERSE, cone)
+}
+
+fn transform(matrix: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    let row = |r: &[f32; 3]| r[0]*v[0] + r[1]*v[1] + r[2]*v[2];
+    [row(&matrix[0]), row(&matrix[1]), row(&matrix[2])]
+}
+
+/// The settings of a photographic camera, exposing the image as they would expose film, with the luminance of the
+/// scene in candela per square meter. The defaults, ISO 100, 1 second and f/1, expose a white of 1 at about 0.83.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::color::CameraExposure;
+/// let sunny_16 = CameraExposure { iso: 100.0, shutter: 1.0/100.0, f_number: 16.0 };
+/// // Stopping the lens down by two stops is made up for by a shutter open four times as long
+/// let wide_open = CameraExposure { shutter: 1.0/400.0, f_number: 8.0, ..sunny_16 };
+/// assert!((sunny_16.stops() - wide_open.stops()).abs() < 1e-4);
+/// ```
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct CameraExposure {
+    /// The sensitivity of the film or sensor.
+    pub iso: f32,
+    /// How long the shutter is open, in seconds.
+    pub shutter: f32,
+    /// The focal length over the diameter of the aperture.
+    pub f_number: f32,
+}
+
+impl Default for CameraExposure {
+    fn default() -> CameraExposure {
+        CameraExposure { iso: 100.0, shutter: 1.0, f_number: 1.0 }
+    }
+}
+
+impl CameraExposure {
+    /// The stops to brighten the image by. A luminance of 120 N²/(t S) saturates the sensor, which is the standard
+    /// calibration of the exposure meters of cameras.
+    pub fn stops(&self) -> f32 {
+        (self.shutter*self.iso/(120.0*self.f_number*self.f_number)).log2()
+    }
+}
+
+/// Adjustments to the colors of the film before they are written out, for renders that come out too dim or tinted.
+///
+/// All of them are linear, so they work on colors premultiplied with their alpha as well.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # use rayer::color::{Chromaticity, ColorGrading};
+/// # use palette::Rgb;
+/// let grading = ColorGrading::default().with_exposure(1.0).with_saturation(0.0);
+/// let col = grading.grade(Rgb::with_wp(0.25, 0.25, 0.25));
+/// assert!((col.red - 0.5).abs() < 1e-5 && (col.blue - 0.5).abs() < 1e-5);
+/// ```
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct ColorGrading {
+    /// Stops to brighten by, each doubling the light.
+    pub exposure: f32,
+    /// The white point of the light in the scene.
+    pub source_white: Chromaticity,
+    /// The white point that `source_white` is adapted to.
+    pub target_white: Chromaticity,
+    /// 0 for grey, 1 to keep the colors, above 1 for more saturated ones.
+    pub saturation: f32,
+    /// The primaries the graded colors are written out with.
+    pub color_space: ColorSpace,
+}
+
+impl Default for ColorGrading {
+    /// Leaves the colors as they are.
+    fn default() -> ColorGrading {
+        ColorGrading {
+            exposure: 0.0,
+            source_white: Chromaticity::E,
+            target_white: Chromaticity::E,
+            saturation: 1.0,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+impl ColorGrading {
+    pub fn with_exposure(self, exposure: f32) -> ColorGrading {
+        ColorGrading { exposure, ..self }
+    }
+
+    /// Add the exposure of a photographic camera to the stops to brighten by.
+    pub fn with_camera(self, camera: CameraExposure) -> ColorGrading {
+        ColorGrading { exposure: self.exposure + camera.stops(), ..self }
+    }
+
+    /// Make what is white under the light `source_white` look as it would under `target_white`,
+    /// with the Bradford transform.
+    pub fn with_white_balance(self, source_white: Chromaticity, target_white: Chromaticity) -> ColorGrading {
+        ColorGrading { source_white, target_white, ..self }
+    }
+
+    pub fn with_saturation(self, saturation: f32) -> ColorGrading {
+        ColorGrading { saturation, ..self }
+    }
+
+    pub fn with_color_space(self, color_space: ColorSpace) -> ColorGrading {
+        ColorGrading { color_space, ..self }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == ColorGrading::default()
+    }
+
+    /// The color adapted to the target white point, saturated, exposed and converted to the output color space.
+    pub fn grade(&self, col: Rgb<E, f32>) -> Rgb<E, f32> {
+        let xyz = col.into_xyz();
+        let xyz = if self.source_white == self.target_white {
+            xyz
+        } else {
+            let [x, y, z] = adapt([xyz.x, xyz.y, xyz.z], self.source_white, self.target_white);
+            Xyz::with_wp(x, y, z)
+        };
+        let luminance = xyz.y;
+        let col = xyz.into_rgb();
+        let saturated = Rgb::with_wp(luminance, luminance, luminance) + (col - Rgb::with_wp(luminance, luminance, luminance))*self.saturation;
+        self.color_space.convert(saturated*self.exposure.exp2())
+    }
+
+    /// Grade every pixel of an image.
+    pub fn apply(&self, pixels: &mut [Rgb<E, f32>]) {
+        if self.is_identity() {
+            return;
+        }
+        for pixel in pixels.iter_mut() {
+            *pixel = self.grade(*pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Rgb<E, f32>, b: Rgb<E, f32>) -> bool {
+        (a.red - b.red).abs() < 1e-4 && (a.green - b.green).abs() < 1e-4 && (a.blue - b.blue).abs() < 1e-4
+    }
+
+    #[test]
+    fn test_grading() {
+        let col = Rgb::with_wp(0.8, 0.4, 0.1);
+        assert!(close(ColorGrading::default().grade(col), col));
+        assert!(close(ColorGrading::default().with_exposure(-2.0).grade(col), col*0.25));
+
+        let grey = ColorGrading::default().with_saturation(0.0).grade(col);
+        assert!((grey.red - grey.green).abs() < 1e-5 && (grey.green - grey.blue).abs() < 1e-5);
+        assert!((grey.into_xyz().y - col.into_xyz().y).abs() < 1e-4);
+        let saturated = ColorGrading::default().with_saturation(2.0).grade(col);
+        assert!(saturated.red > col.red && saturated.blue < col.blue);
+
+        // White under incandescent light comes out white, other colors shift the same way
+        let white = ColorGrading::default().with_white_balance(Chromaticity::A, Chromaticity::E);
+        let [x, y, z] = Chromaticity::A.xyz();
+        let lit = Xyz::<E, f32>::with_wp(x, y, z).into_rgb()*0.5;
+        assert!(close(white.grade(lit), Rgb::with_wp(0.5, 0.5, 0.5)), "{:?}", white.grade(lit));
+        let back = ColorGrading::default().with_white_balance(Chromaticity::E, Chromaticity::A);
+        assert!(close(back.grade(white.grade(col)), col));
+    }
+
+    #[test]
+    fn test_camera_exposure() {
+        let col = Rgb::with_wp(0.8, 0.4, 0.1);
+        // Luminances of 1.2 saturate at ISO 100, 1 s and f/1
+        let camera = CameraExposure::default();
+        let graded = ColorGrading::default().with_camera(camera).grade(col);
+        assert!(close(graded, col/1.2), "{:?}", graded);
+        let faster = ColorGrading::default().with_camera(CameraExposure { shutter: 0.25, ..camera }).grade(col);
+        assert!(close(faster, col/4.8), "{:?}", faster);
+    }
+
+    #[test]
+    fn test_named() {
+        assert_eq!(Chromaticity::named("d65"), Some(Chromaticity::D65));
+        assert_eq!(Chromaticity::named("D66"), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! Spectra of standard illuminants and common lamps, to light scenes with instead of RGB colors.
+
+use color::{Chromaticity, ColorSpectrum, HasReflectance, xyz_from_wavelength};
+use sky;
+
+/// The names `illuminant` knows.
+pub const ILLUMINANTS: [&str; 11] = ["E", "D50", "D55", "D65", "D75", "A", "F2", "F7", "F11", "LED-cool", "LED-warm"];
+
+/// The spectrum of the standard illuminant or lamp with a name from `ILLUMINANTS`, ignoring case, scaled to the
+/// luminance of a white of 1. The F-series are fluorescent tubes, with the spikes of the mercury lines, so two
+/// surfaces matching under daylight can look different under them. The LEDs are modelled, not measured: the blue of
+/// the die and the broad glow of its phosphor, mixed to the color of D65 for the cool one and of A for the warm one.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::color::{illuminant, HasReflectance};
+/// let tube = illuminant("F11").unwrap();
+/// assert!(tube.reflect(545.0) > 5.0*tube.reflect(500.0));
+/// ```
+pub fn illuminant(name: &str) -> Option<ColorSpectrum> {
+    let spectrum = |f: &dyn Fn(f32) -> f32| sampled(&(360..=720).map(|wl| (wl as f32, f(wl as f32))).collect::<Vec<_>>());
+    let daylight = |white: Chromaticity| spectrum(&|wl| sky::daylight(white.x, white.y, wl));
+    Some(match name.to_ascii_uppercase().as_str() {
+        "E" => spectrum(&|_| 1.0),
+        "D50" => daylight(Chromaticity::D50),
+        "D55" => daylight(Chromaticity::D55),
+        "D65" => daylight(Chromaticity::D65),
+        "D75" => daylight(Chromaticity::D75),
+        "A" => spectrum(&incandescent),
+        "F2" => sampled(&fluorescent(&F2)),
+        "F7" => sampled(&fluorescent(&F7)),
+        "F11" => sampled(&fluorescent(&F11)),
+        "LED-COOL" => spectrum(&|wl| 1.82*gaussian(wl, 450.0, 10.0) + gaussian(wl, 565.0, 50.0)),
+        "LED-WARM" => spectrum(&|wl| 0.48*gaussian(wl, 450.0, 10.0) + gaussian(wl, 600.0, 55.0)),
+        _ => return None,
+    })
+}
+
+/// Bin `samples` and scale them to a luminance of 1.
+fn sampled(samples: &[(f32, f32)]) -> ColorSpectrum {
+    let spectrum = ColorSpectrum::from_samples(samples);
+    let wavelengths = || (360..=720).map(|wl| wl as f32);
+    let weight: f32 = wavelengths().map(|wl| xyz_from_wavelength(wl).y).sum();
+    let luminance = wavelengths().map(|wl| spectrum.reflect(wl)*xyz_from_wavelength(wl).y).sum::<f32>()/weight;
+    (1.0/luminance)*spectrum
+}
+
+/// Illuminant A, a tungsten filament at 2856 K, as the CIE defines it relative to 560 nm.
+fn incandescent(wl: f32) -> f32 {
+    const C2: f32 = 1.435e7;
+    const T: f32 = 2848.0;
+    (560.0/wl).powi(5)*((C2/(T*560.0)).exp() - 1.0)/((C2/(T*wl)).exp() - 1.0)
+}
+
+fn gaussian(wl: f32, center: f32, width: f32) -> f32 {
+    (-0.5*((wl - center)/width).powi(2)).exp()
+}
+
+/// The 5 nm table of a fluorescent illuminant as `(wavelength, value)` pairs.
+fn fluorescent(table: &[f32; 69]) -> Vec<(f32, f32)> {
+    table.iter().enumerate().map(|(i, &value)| (380.0 + 5.0*i as f32, value)).collect()
+}
+
+/// CIE F2, a cool white fluorescent, from 380 nm to 720 nm in steps of 5 nm.
+static F2: [f32; 69] = [
+    1.18, 1.48, 1.84, 2.15, 3.44, 15.69, 3.85, 3.74, 4.19, 4.62, 5.06, 34.98, 11.81, 6.27, 6.63, 6.93, 7.19, 7.40, 7.54,
+    7.62, 7.65, 7.62, 7.62, 7.45, 7.28, 7.15, 7.05, 7.04, 7.16, 7.47, 8.04, 8.88, 10.01, 24.88, 16.64, 14.59, 16.16,
+    17.56, 18.62, 21.47, 22.79, 19.29, 18.66, 17.73, 16.54, 15.21, 13.80, 12.36, 10.95, 9.65, 8.40, 7.32, 6.31, 5.43,
+    4.68, 4.02, 3.45, 2.96, 2.55, 2.19, 1.89, 1.64, 1.53, 1.27, 1.10, 0.99, 0.88, 0.76, 0.68,
+];
+
+/// CIE F7, a broadband daylight fluorescent.
+static F7: [f32; 69] = [
+    2.56, 3.18, 3.84, 4.53, 6.15, 19.37, 7.37, 7.05, 7.71, 8.41, 9.15, 44.14, 17.52, 11.35, 12.00, 12.58, 13.08, 13.45,
+    13.71, 13.88, 13.95, 13.93, 13.82, 13.64, 13.43, 13.25, 13.08, 12.93, 12.78, 12.60, 12.44, 12.33, 12.26, 29.52,
+    17.05, 12.44, 12.58, 12.72, 12.83, 15.46, 16.75, 12.83, 12.67, 12.45, 12.19, 11.89, 11.60, 11.35, 11.12, 10.95,
+    10.76, 10.42, 10.11, 10.04, 10.02, 10.11, 9.87, 8.65, 7.27, 6.44, 5.83, 5.41, 5.04, 4.57, 4.12, 3.77, 3.46, 3.08,
+    2.73,
+];
+
+/// CIE F11, a narrow band tri-phosphor fluorescent.
+static F11: [f32; 69] = [
+    0.91, 0.63, 0.46, 0.37, 1.29, 12.68, 1.59, 1.79, 2.46, 3.33, 4.49, 33.94, 12.13, 6.95, 7.19, 7.12, 6.72, 6.13, 5.46,
+    4.79, 5.66, 14.29, 14.96, 8.97, 4.72, 2.33, 1.47, 1.10, 0.89, 0.83, 1.18, 4.90, 39.59, 72.84, 32.61, 7.52, 2.83,
+    1.96, 1.67, 4.43, 11.28, 14.76, 12.73, 9.74, 7.33, 9.72, 55.27, 42.58, 13.18, 13.16, 12.26, 5.11, 2.07, 2.34, 3.58,
+    3.01, 2.48, 2.14, 1.54, 1.33, 1.46, 1.94, 2.00, 1.20, 1.35, 4.10, 5.58, 2.51, 0.57,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The xy chromaticity of a spectrum, integrated in steps of 1 nm.
+    fn chromaticity(spectrum: &ColorSpectrum) -> (f32, f32) {
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for wl in 360..=720 {
+            let xyz = xyz_from_wavelength(wl as f32);
+            let value = spectrum.reflect(wl as f32);
+            x += xyz.x*value;
+            y += xyz.y*value;
+            z += xyz.z*value;
+        }
+        (x/(x + y + z), y/(x + y + z))
+    }
+
+    #[test]
+    fn test_chromaticities() {
+        let expected = |name: &str| match name {
+            "LED-cool" => Chromaticity::D65,
+            "LED-warm" => Chromaticity::A,
+            name => Chromaticity::named(name).unwrap(),
+        };
+        // Binning into 10 nm smears the lines of the fluorescents a little, which moves them the most.
+        for &name in ILLUMINANTS.iter() {
+            let (x, y) = chromaticity(&illuminant(name).unwrap());
+            let white = expected(name);
+            assert!((x - white.x).abs() < 0.006 && (y - white.y).abs() < 0.006, "{} is at {}, {}", name, x, y);
+        }
+    }
+
+    #[test]
+    fn test_luminance() {
+        let white = illuminant("e").unwrap();
+        assert!((white.reflect(550.0) - 1.0).abs() < 1e-3);
+        let tube = illuminant("F2").unwrap();
+        let weight: f32 = (360..=720).map(|wl| xyz_from_wavelength(wl as f32).y).sum();
+        let luminance: f32 = (360..=720).map(|wl| tube.reflect(wl as f32)*xyz_from_wavelength(wl as f32).y).sum();
+        assert!((luminance/weight - 1.0).abs() < 1e-3);
+        assert!(illuminant("F12").is_none());
+    }
+}
@@ -0,0 +1,90 @@
+use palette::*;
+use palette::white_point::E;
+
+/// Compensated (Kahan–Babuška) summation of f32 values.
+/// Keeps track of the rounding error of every addition,
+/// so long sums don't drift compared to the naive approach.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct KahanSum {
+    sum: f32,
+    compensation: f32,
+}
+
+impl KahanSum {
+    pub fn new() -> KahanSum {
+        KahanSum { sum: 0.0, compensation: 0.0 }
+    }
+
+    #[inline]
+    pub fn add(&mut self, x: f32) {
+        let t = self.sum + x;
+        let error = if self.sum.abs() >= x.abs() {
+            (self.sum - t) + x
+        } else {
+            (x - t) + self.sum
+        };
+        // Fold the error back into the sum, so the compensation itself stays small.
+        let compensation = self.compensation + error;
+        let sum = t + compensation;
+        self.compensation = compensation - (sum - t);
+        self.sum = sum;
+    }
+
+    #[inline]
+    pub fn sum(&self) -> f32 {
+        self.sum + self.compensation
+    }
+}
+
+/// Compensated summation of colors, component by component.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct KahanXyz {
+    x: KahanSum,
+    y: KahanSum,
+    z: KahanSum,
+}
+
+impl KahanXyz {
+    pub fn new() -> KahanXyz {
+        KahanXyz { x: KahanSum::new(), y: KahanSum::new(), z: KahanSum::new() }
+    }
+
+    #[inline]
+    pub fn add(&mut self, xyz: Xyz<E, f32>) {
+        self.x.add(xyz.x);
+        self.y.add(xyz.y);
+        self.z.add(xyz.z);
+    }
+
+    #[inline]
+    pub fn sum(&self) -> Xyz<E, f32> {
+        Xyz::with_wp(self.x.sum(), self.y.sum(), self.z.sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kahan_sum_does_not_drift() {
+        let mut naive = 0.0f32;
+        let mut kahan = KahanSum::new();
+        for _ in 0..1000000 {
+            naive += 0.1;
+            kahan.add(0.1);
+        }
+        assert!((naive - 100000.0).abs() > 1.0);
+        assert!((kahan.sum() - 100000.0).abs() < 0.01, "sum={:}", kahan.sum());
+    }
+
+    #[test]
+    fn test_kahan_sum_large_and_small() {
+        let mut kahan = KahanSum::new();
+        kahan.add(1.0);
+        kahan.add(1e8);
+        kahan.add(1.0);
+        kahan.add(-1e8);
+        assert_eq!(kahan.sum(), 2.0);
+    }
+}
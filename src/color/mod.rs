@@ -4,9 +4,25 @@ use std::fmt::Debug;
 
 mod binned_spectrum;
 mod cie_1931;
+mod grading;
+mod illuminants;
+mod kahan;
 mod rgb_base_colors;
+mod sensor;
+mod sigmoid_spectrum;
+mod space;
+mod wavelength_sampler;
 
 pub use self::cie_1931::xyz_from_wavelength;
+pub use self::grading::{CameraExposure, Chromaticity, ColorGrading};
+pub use self::illuminants::{illuminant, ILLUMINANTS};
+pub use self::binned_spectrum::{BinData, Bin36, BinnedSpectrum, ColorSpectrum};
+pub use self::rgb_base_colors::rgb_to_spectrum;
+pub use self::kahan::{KahanSum, KahanXyz};
+pub use self::sensor::{Sensor, SensorResponse};
+pub use self::sigmoid_spectrum::{SigmoidSpectrum, UpsampledSpectrum, Upsampling, set_upsampling, upsampling};
+pub use self::space::ColorSpace;
+pub use self::wavelength_sampler::{WavelengthSampler, WavelengthSampling};
 
 pub trait HasReflectance: Debug + Send + Sync {
     fn reflect(&self, wl: f32) -> f32;
@@ -22,6 +38,11 @@ pub trait HasReflectance: Debug + Send + Sync {
 impl HasReflectance for Rgb<E, f32> where
 {
     fn reflect(&self, wl: f32) -> f32 {
+        if upsampling() == Upsampling::Sigmoid {
+            if let Some(refl) = sigmoid_spectrum::sigmoid_reflect(*self, wl) {
+                return refl;
+            }
+        }
         let spectrum = rgb_base_colors::rgb_to_spectrum(*self);
         spectrum.reflect(wl)
     }
@@ -36,7 +57,6 @@ impl HasReflectance for Rgb<E, f32> where
 mod tests {
     use palette::white_point::WhitePoint;
     use super::*;
-    use test::*;
     use quickcheck::{Arbitrary, Gen};
 
     #[derive(Clone, Debug)]
@@ -135,6 +155,12 @@ mod tests {
             }
         }
     }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use super::*;
+    use test::*;
 
     #[bench]
     fn bench_match_color_rgb(bench: &mut Bencher) {
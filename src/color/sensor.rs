@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::path::Path;
+use palette::*;
+use palette::white_point::E;
+
+use color::cie_1931::xyz_from_wavelength;
+use color::binned_spectrum::ColorSpectrum;
+use color::HasReflectance;
+
+/// How the film turns light of a wavelength into the color it records.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Sensor {
+    /// The CIE 1931 standard observer, recording the colors a person would see.
+    Cie,
+    /// A camera or scientific sensor with its own spectral sensitivities.
+    Response(SensorResponse),
+}
+
+impl Default for Sensor {
+    fn default() -> Sensor {
+        Sensor::Cie
+    }
+}
+
+impl Sensor {
+    /// The color recorded for light of wavelength `wl` in nm.
+    pub fn xyz(&self, wl: f32) -> Xyz<E, f32> {
+        match *self {
+            Sensor::Cie => xyz_from_wavelength(wl),
+            Sensor::Response(ref response) => response.rgb(wl).into_xyz(),
+        }
+    }
+}
+
+/// The red, green and blue spectral sensitivity curves of a sensor.
+///
+/// What a sensor records goes straight to the red, green and blue channels of the output, without a matrix
+/// from the camera's colors to the output's primaries, like the raw colors of a camera.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct SensorResponse {
+    pub red: ColorSpectrum,
+    pub green: ColorSpectrum,
+    pub blue: ColorSpectrum,
+}
+
+impl SensorResponse {
+    pub fn new(red: ColorSpectrum, green: ColorSpectrum, blue: ColorSpectrum) -> SensorResponse {
+        SensorResponse { red, green, blue }
+    }
+
+    /// Read measured curves from a CSV file with a wavelength in nm and the red, green and blue sensitivity on every line.
+    /// Empty lines, lines starting with `#` and a header line are skipped.
+    pub fn from_csv(path: &Path) -> Result<SensorResponse, Error> {
+        let mut samples = [Vec::new(), Vec::new(), Vec::new()];
+        let file = File::open(path).map_err(|error| Error::new(error.kind(), format!("{}: {}", path.display(), error)))?;
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Result<Vec<f32>, _> = line.split(|c| c == ',' || c == ';' || c == '\t').map(|field| field.trim().parse()).collect();
+            match fields.as_ref().map(|fields| &fields[..]) {
+                Ok(&[wl, red, green, blue]) => {
+                    samples[0].push((wl, red));
+                    samples[1].push((wl, green));
+                    samples[2].push((wl, blue));
+                },
+                _ if i == 0 => continue,
+                _ => return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}:{}: expected a wavelength and red, green and blue values", path.display(), i+1),
+                )),
+            }
+        }
+        if samples[0].is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{}: no spectral data", path.display())));
+        }
+        Ok(SensorResponse::new(
+            ColorSpectrum::from_samples(&samples[0]),
+            ColorSpectrum::from_samples(&samples[1]),
+            ColorSpectrum::from_samples(&samples[2]),
+        ))
+    }
+
+    /// Scale the curves so equal energy light records as grey, as bright as the standard observer sees it.
+    /// Renders then keep the brightness they have with `Sensor::Cie`.
+    pub fn white_balanced(self) -> SensorResponse {
+        let integrate = |f: &dyn Fn(f32) -> f32| (360..720).map(|wl| f(wl as f32 + 0.5)).sum::<f32>();
+        let luminance = integrate(&|wl| xyz_from_wavelength(wl).y);
+        let balance = |curve: ColorSpectrum| {
+            let sum = integrate(&|wl| curve.reflect(wl));
+            if sum > 0.0 { curve.map(|x| x*luminance/sum) } else { curve }
+        };
+        SensorResponse::new(balance(self.red), balance(self.green), balance(self.blue))
+    }
+
+    /// The sensitivities of the channels at wavelength `wl` in nm.
+    pub fn rgb(&self, wl: f32) -> Rgb<E, f32> {
+        Rgb::with_wp(self.red.reflect(wl), self.green.reflect(wl), self.blue.reflect(wl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_from_csv() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "wavelength,red,green,blue").unwrap();
+        writeln!(file, "400, 0.0, 0.2, 1.0").unwrap();
+        writeln!(file, "# a gap in the measurements").unwrap();
+        writeln!(file, "700\t1.0\t0.2\t0.0").unwrap();
+        let response = SensorResponse::from_csv(file.path()).unwrap();
+        let col = response.rgb(550.0);
+        assert!((col.red - 0.5).abs() < 0.02 && (col.green - 0.2).abs() < 1e-6 && (col.blue - 0.5).abs() < 0.02);
+
+        writeln!(file, "720, 1.0, 0.2").unwrap();
+        let error = SensorResponse::from_csv(file.path()).unwrap_err();
+        assert!(error.to_string().ends_with(":5: expected a wavelength and red, green and blue values"), "{}", error);
+    }
+
+    #[test]
+    fn test_white_balanced() {
+        let response = SensorResponse::new(
+            ColorSpectrum::from_fn(|wl| if wl > 580.0 { 2.0 } else { 0.0 }),
+            ColorSpectrum::from_fn(|wl| if wl > 480.0 && wl < 600.0 { 0.5 } else { 0.0 }),
+            ColorSpectrum::from_fn(|wl| if wl < 500.0 { 1.0 } else { 0.0 }),
+        ).white_balanced();
+        let sum = (360..720).fold(Rgb::with_wp(0.0, 0.0, 0.0), |sum, wl| sum + Sensor::Response(response).xyz(wl as f32).into_rgb());
+        let luminance = (360..720).map(|wl| Sensor::Cie.xyz(wl as f32).y).sum::<f32>();
+        for channel in &[sum.red, sum.green, sum.blue] {
+            assert!((channel/luminance - 1.0).abs() < 1e-3, "{:?}", sum);
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use palette::*;
+use palette::white_point::E;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use color::cie_1931::xyz_from_wavelength;
+use color::HasReflectance;
+use color::binned_spectrum::ColorSpectrum;
+use color::rgb_base_colors::rgb_to_spectrum;
+
+const WL_MIN: f64 = 360.0;
+const WL_MAX: f64 = 830.0;
+const WL_STEP: f64 = 10.0;
+const MAX_ITERATIONS: usize = 30;
+const MAX_ERROR: f64 = 1e-3;
+const TABLE_RES: usize = 16;
+
+/// How RGB reflectances are turned into spectra.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upsampling {
+    /// Mix of precomputed binned base spectra (Smits 1999).
+    Binned,
+    /// Smooth sigmoid polynomial spectra (Jakob and Hanika 2019).
+    Sigmoid,
+}
+
+static SIGMOID_UPSAMPLING: AtomicBool = AtomicBool::new(false);
+
+/// Select the upsampling used for plain `Rgb` reflectances.
+pub fn set_upsampling(upsampling: Upsampling) {
+    SIGMOID_UPSAMPLING.store(upsampling == Upsampling::Sigmoid, Ordering::Relaxed);
+}
+
+pub fn upsampling() -> Upsampling {
+    if SIGMOID_UPSAMPLING.load(Ordering::Relaxed) {
+        Upsampling::Sigmoid
+    } else {
+        Upsampling::Binned
+    }
+}
+
+/// The reflectance `S(c0*λ² + c1*λ + c2)`, with `S` a sigmoid onto (0,1) and λ
+/// normalized to [0,1] over the visible range.
+/// Unlike the binned spectra these are smooth, so saturated colors don't show banding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SigmoidSpectrum {
+    coefficients: [f32; 3],
+}
+
+impl SigmoidSpectrum {
+    pub const fn new(coefficients: [f32; 3]) -> SigmoidSpectrum {
+        SigmoidSpectrum { coefficients }
+    }
+
+    /// Fit a spectrum to a reflectance inside [0,1]³.
+    /// Returns `None` for other colors or if the fit doesn't get close enough.
+    pub fn from_rgb(rgb: Rgb<E, f32>) -> Option<SigmoidSpectrum> {
+        if !in_unit_cube(rgb) {
+            return None;
+        }
+        let (coefficients, error) = fit(rgb, [0.0; 3]);
+        if error < MAX_ERROR {
+            Some(SigmoidSpectrum::new(coefficients))
+        } else {
+            None
+        }
+    }
+}
+
+impl HasReflectance for SigmoidSpectrum {
+    fn reflect(&self, wl: f32) -> f32 {
+        eval(self.coefficients, wl)
+    }
+
+    fn reflect_xyz(&self) -> Xyz<E, f32> {
+        let [x, y, z] = integrate(to_f64(self.coefficients));
+        Xyz::with_wp(x as f32, y as f32, z as f32)
+    }
+}
+
+/// A reflectance upsampled with a chosen method, using the binned spectra as fallback
+/// for colors the sigmoid model can't represent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpsampledSpectrum {
+    Binned(ColorSpectrum),
+    Sigmoid(SigmoidSpectrum),
+}
+
+impl UpsampledSpectrum {
+    pub fn new(rgb: Rgb<E, f32>, upsampling: Upsampling) -> UpsampledSpectrum {
+        match upsampling {
+            Upsampling::Sigmoid => match SigmoidSpectrum::from_rgb(rgb) {
+                Some(spectrum) => UpsampledSpectrum::Sigmoid(spectrum),
+                None => UpsampledSpectrum::Binned(rgb_to_spectrum(rgb)),
+            },
+            Upsampling::Binned => UpsampledSpectrum::Binned(rgb_to_spectrum(rgb)),
+        }
+    }
+}
+
+impl HasReflectance for UpsampledSpectrum {
+    fn reflect(&self, wl: f32) -> f32 {
+        match *self {
+            UpsampledSpectrum::Binned(ref spectrum) => spectrum.reflect(wl),
+            UpsampledSpectrum::Sigmoid(ref spectrum) => spectrum.reflect(wl),
+        }
+    }
+
+    fn reflect_xyz(&self) -> Xyz<E, f32> {
+        match *self {
+            UpsampledSpectrum::Binned(ref spectrum) => spectrum.reflect_xyz(),
+            UpsampledSpectrum::Sigmoid(ref spectrum) => spectrum.reflect_xyz(),
+        }
+    }
+}
+
+/// Evaluate the reflectance of an arbitrary color through the precomputed coefficient table.
+/// Colors above one are scaled down and back up, so emitters keep their intensity.
+/// Returns `None` for negative colors.
+pub fn sigmoid_reflect(rgb: Rgb<E, f32>, wl: f32) -> Option<f32> {
+    let c = [rgb.red, rgb.green, rgb.blue];
+    if !(c[0] >= 0.0 && c[1] >= 0.0 && c[2] >= 0.0) {
+        return None;
+    }
+    let i = if c[0] >= c[1] && c[0] >= c[2] { 0 } else if c[1] >= c[2] { 1 } else { 2 };
+    let max = c[i];
+    if max == 0.0 {
+        return Some(0.0);
+    }
+    let scale = max.max(1.0);
+    let z = max / scale;
+    let x = c[(i+1)%3] / max;
+    let y = c[(i+2)%3] / max;
+    Some(scale * eval(SIGMOID_TABLE.lookup(i, x, y, z), wl))
+}
+
+lazy_static! {
+    static ref SIGMOID_TABLE: CoefficientTable = CoefficientTable::new();
+}
+
+/// Coefficients for a grid of colors, indexed like Jakob and Hanika by the largest
+/// channel, its value `z` and the ratios `x` and `y` of the other two channels to it.
+struct CoefficientTable {
+    z_values: Vec<f32>,
+    coefficients: Vec<[f32; 3]>,
+}
+
+impl CoefficientTable {
+    fn new() -> CoefficientTable {
+        // Smoothstepped z values put more entries close to black and white.
+        let z_values: Vec<f32> = (0..TABLE_RES)
+            .map(|i| smoothstep(smoothstep(i as f32 / (TABLE_RES-1) as f32)))
+            .collect();
+        let mut coefficients = vec![[0.0; 3]; 3*TABLE_RES*TABLE_RES*TABLE_RES];
+        let start = TABLE_RES/5;
+        for i in 0..3 {
+            for yi in 0..TABLE_RES {
+                for xi in 0..TABLE_RES {
+                    let x = xi as f32 / (TABLE_RES-1) as f32;
+                    let y = yi as f32 / (TABLE_RES-1) as f32;
+                    // Start from a middle grey and warm start the fits towards both ends.
+                    let order = (start..TABLE_RES).chain((0..start).rev());
+                    let mut guess = [0.0; 3];
+                    for zi in order {
+                        if zi + 1 == start {
+                            guess = coefficients[CoefficientTable::index(i, xi, yi, start)];
+                        }
+                        let z = z_values[zi];
+                        let mut c = [0.0; 3];
+                        c[i] = z;
+                        c[(i+1)%3] = x*z;
+                        c[(i+2)%3] = y*z;
+                        let (fitted, _) = fit(Rgb::with_wp(c[0], c[1], c[2]), guess);
+                        coefficients[CoefficientTable::index(i, xi, yi, zi)] = fitted;
+                        guess = fitted;
+                    }
+                }
+            }
+        }
+        CoefficientTable { z_values, coefficients }
+    }
+
+    fn index(i: usize, xi: usize, yi: usize, zi: usize) -> usize {
+        ((i*TABLE_RES + zi)*TABLE_RES + yi)*TABLE_RES + xi
+    }
+
+    fn lookup(&self, i: usize, x: f32, y: f32, z: f32) -> [f32; 3] {
+        let scaled = (TABLE_RES-1) as f32;
+        let xi = ((x*scaled) as usize).min(TABLE_RES-2);
+        let yi = ((y*scaled) as usize).min(TABLE_RES-2);
+        let zi = match self.z_values.iter().position(|&v| v > z) {
+            Some(0) => 0,
+            Some(n) => (n-1).min(TABLE_RES-2),
+            None => TABLE_RES-2,
+        };
+        let tx = x*scaled - xi as f32;
+        let ty = y*scaled - yi as f32;
+        let tz = (z - self.z_values[zi]) / (self.z_values[zi+1] - self.z_values[zi]);
+        let mut res = [0.0; 3];
+        for (dz, wz) in [(0, 1.0-tz), (1, tz)].iter() {
+            for (dy, wy) in [(0, 1.0-ty), (1, ty)].iter() {
+                for (dx, wx) in [(0, 1.0-tx), (1, tx)].iter() {
+                    let c = self.coefficients[CoefficientTable::index(i, xi+dx, yi+dy, zi+dz)];
+                    for k in 0..3 {
+                        res[k] += wx*wy*wz*c[k];
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+fn smoothstep(x: f32) -> f32 {
+    x*x*(3.0 - 2.0*x)
+}
+
+fn in_unit_cube(rgb: Rgb<E, f32>) -> bool {
+    [rgb.red, rgb.green, rgb.blue].iter().all(|&c| c >= 0.0 && c <= 1.0)
+}
+
+fn to_f64(c: [f32; 3]) -> [f64; 3] {
+    [c[0] as f64, c[1] as f64, c[2] as f64]
+}
+
+fn normalized_wavelength(wl: f64) -> f64 {
+    (wl - WL_MIN) / (WL_MAX - WL_MIN)
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0*(1.0 + x*x).sqrt())
+}
+
+fn eval(c: [f32; 3], wl: f32) -> f32 {
+    let l = normalized_wavelength(wl as f64);
+    let c = to_f64(c);
+    sigmoid((c[0]*l + c[1])*l + c[2]) as f32
+}
+
+/// The wavelengths used for fitting and their weights, normalized so that a constant
+/// reflectance of one gives the white point of E.
+fn weighted_wavelengths() -> Vec<(f64, [f64; 3])> {
+    let n = ((WL_MAX - WL_MIN) / WL_STEP) as usize + 1;
+    let samples: Vec<(f64, Xyz<E, f32>)> = (0..n)
+        .map(|i| WL_MIN + WL_STEP*(i as f64))
+        .map(|wl| (wl, xyz_from_wavelength(wl as f32)))
+        .collect();
+    let mut sum = [0.0; 3];
+    for &(_, xyz) in samples.iter() {
+        sum[0] += xyz.x as f64;
+        sum[1] += xyz.y as f64;
+        sum[2] += xyz.z as f64;
+    }
+    samples.into_iter()
+        .map(|(wl, xyz)| (wl, [xyz.x as f64/sum[0], xyz.y as f64/sum[1], xyz.z as f64/sum[2]]))
+        .collect()
+}
+
+lazy_static! {
+    static ref WEIGHTS: Vec<(f64, [f64; 3])> = weighted_wavelengths();
+}
+
+fn integrate(c: [f64; 3]) -> [f64; 3] {
+    let mut res = [0.0; 3];
+    for &(wl, w) in WEIGHTS.iter() {
+        let l = normalized_wavelength(wl);
+        let s = sigmoid((c[0]*l + c[1])*l + c[2]);
+        for k in 0..3 {
+            res[k] += w[k]*s;
+        }
+    }
+    res
+}
+
+/// Gauss-Newton fit of the coefficients to the XYZ of the given color.
+/// Returns the coefficients and the remaining error.
+fn fit(rgb: Rgb<E, f32>, guess: [f32; 3]) -> ([f32; 3], f64) {
+    let target: Xyz<E, f32> = rgb.into();
+    let target = [target.x as f64, target.y as f64, target.z as f64];
+    let mut c = to_f64(guess);
+    let mut error = f64::INFINITY;
+    for _ in 0..MAX_ITERATIONS {
+        let mut r = [0.0; 3];
+        let mut jacobian = [[0.0; 3]; 3];
+        for &(wl, w) in WEIGHTS.iter() {
+            let l = normalized_wavelength(wl);
+            let x = (c[0]*l + c[1])*l + c[2];
+            let s = sigmoid(x);
+            let ds = 0.5 / (1.0 + x*x).powf(1.5);
+            for k in 0..3 {
+                r[k] += w[k]*s;
+                jacobian[k][0] += w[k]*ds*l*l;
+                jacobian[k][1] += w[k]*ds*l;
+                jacobian[k][2] += w[k]*ds;
+            }
+        }
+        for k in 0..3 {
+            r[k] -= target[k];
+        }
+        error = (r[0]*r[0] + r[1]*r[1] + r[2]*r[2]).sqrt();
+        if error < 1e-6 {
+            break;
+        }
+        let delta = match solve(jacobian, r) {
+            Some(delta) => delta,
+            None => break,
+        };
+        for k in 0..3 {
+            c[k] -= delta[k];
+        }
+        if !c.iter().all(|v| v.is_finite()) {
+            return (guess, f64::INFINITY);
+        }
+    }
+    ([c[0] as f32, c[1] as f32, c[2] as f32], error)
+}
+
+/// Solve the 3x3 system `a*x = b` with Cramer's rule.
+fn solve(a: [[f64; 3]; 3], b: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: [[f64; 3]; 3]| {
+        m[0][0]*(m[1][1]*m[2][2] - m[1][2]*m[2][1])
+            - m[0][1]*(m[1][0]*m[2][2] - m[1][2]*m[2][0])
+            + m[0][2]*(m[1][0]*m[2][1] - m[1][1]*m[2][0])
+    };
+    let d = det(a);
+    if d.abs() < 1e-30 {
+        return None;
+    }
+    let mut res = [0.0; 3];
+    for col in 0..3 {
+        let mut m = a;
+        for row in 0..3 {
+            m[row][col] = b[row];
+        }
+        res[col] = det(m) / d;
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_grey() {
+        let spectrum = SigmoidSpectrum::from_rgb(Rgb::with_wp(0.5, 0.5, 0.5)).unwrap();
+        for wl in 380..780 {
+            assert!((spectrum.reflect(wl as f32) - 0.5).abs() < 0.001, "{:?} {}", spectrum, spectrum.reflect(wl as f32));
+        }
+    }
+
+    #[test]
+    fn test_fit_matches_color() {
+        let rgb = Rgb::with_wp(0.6, 0.3, 0.1);
+        let spectrum = SigmoidSpectrum::from_rgb(rgb).unwrap();
+        let xyz: Xyz<E, f32> = rgb.into();
+        let fitted = spectrum.reflect_xyz();
+        assert!((xyz.x - fitted.x).abs() < 0.002, "{:?} != {:?}", xyz, fitted);
+        assert!((xyz.y - fitted.y).abs() < 0.002, "{:?} != {:?}", xyz, fitted);
+        assert!((xyz.z - fitted.z).abs() < 0.002, "{:?} != {:?}", xyz, fitted);
+    }
+
+    #[test]
+    fn test_fallback_to_binned() {
+        let rgb = Rgb::with_wp(2.0, 0.5, 0.5);
+        assert_eq!(UpsampledSpectrum::new(rgb, Upsampling::Sigmoid), UpsampledSpectrum::Binned(rgb_to_spectrum(rgb)));
+    }
+
+    #[test]
+    fn test_table_close_to_fit() {
+        let rgb = Rgb::with_wp(0.2, 0.7, 0.4);
+        let spectrum = SigmoidSpectrum::from_rgb(rgb).unwrap();
+        for wl in 380..780 {
+            let wl = wl as f32;
+            let table = sigmoid_reflect(rgb, wl).unwrap();
+            assert!((spectrum.reflect(wl) - table).abs() < 0.02, "wl={}nm: {} != {}", wl, spectrum.reflect(wl), table);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use palette::*;
+use palette::white_point::E;
+
+use color::Chromaticity;
+use color::grading::adapt;
+
+/// The primaries and white point of the channels written out. The film keeps colors with the primaries of sRGB, and
+/// equal energy light as its white, which comes out as the white of the space it is written in. Wider spaces get
+/// the colors converted, adapting the white with the Bradford transform, so they read right in tools like Nuke.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # use rayer::color::ColorSpace;
+/// # use palette::Rgb;
+/// let white = ColorSpace::AcesCg.convert(Rgb::with_wp(1.0, 1.0, 1.0));
+/// assert!((white.red - 1.0).abs() < 1e-4 && (white.blue - 1.0).abs() < 1e-4);
+/// let red = ColorSpace::AcesCg.convert(Rgb::with_wp(1.0, 0.0, 0.0));
+/// assert!(red.red < 1.0 && red.green > 0.0);
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ColorSpace {
+    /// Linear sRGB, with the primaries and D65 white Rec.709 has as well.
+    Srgb,
+    /// The primaries of DCI-P3 with a D65 white, as wide gamut displays show.
+    DisplayP3,
+    /// The AP1 primaries with the white of ACES, close to D60, the working space of ACES pipelines.
+    AcesCg,
+}
+
+impl Default for ColorSpace {
+    fn default() -> ColorSpace {
+        ColorSpace::Srgb
+    }
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" | "rec709" => Some(ColorSpace::Srgb),
+            "display-p3" => Some(ColorSpace::DisplayP3),
+            "acescg" => Some(ColorSpace::AcesCg),
+            _ => None,
+        }
+    }
+
+    /// The chromaticities of the red, green and blue primaries and of the white.
+    pub fn chromaticities(self) -> [Chromaticity; 4] {
+        let xy = |x, y| Chromaticity { x, y };
+        match self {
+            ColorSpace::Srgb => [xy(0.64, 0.33), xy(0.30, 0.60), xy(0.15, 0.06), Chromaticity::D65],
+            ColorSpace::DisplayP3 => [xy(0.680, 0.320), xy(0.265, 0.690), xy(0.150, 0.060), Chromaticity::D65],
+            ColorSpace::AcesCg => [xy(0.713, 0.293), xy(0.165, 0.830), xy(0.128, 0.044), xy(0.321_68, 0.337_67)],
+        }
+    }
+
+    /// The matrix from the channels to XYZ, scaled so white has a luminance of 1.
+    fn to_xyz(self) -> [[f32; 3]; 3] {
+        let [red, green, blue, white] = self.chromaticities();
+        let primaries = transpose([red.xyz(), green.xyz(), blue.xyz()]);
+        let scale = multiply(&inverse(&primaries), white.xyz());
+        let mut matrix = primaries;
+        for row in matrix.iter_mut() {
+            for (x, s) in row.iter_mut().zip(scale.iter()) {
+                *x *= s;
+            }
+        }
+        matrix
+    }
+
+    /// Convert a color of the film, in linear sRGB, to this space.
+    pub fn convert(self, col: Rgb<E, f32>) -> Rgb<E, f32> {
+        if self == ColorSpace::Srgb {
+            return col;
+        }
+        let source = ColorSpace::Srgb.chromaticities()[3];
+        let target = self.chromaticities()[3];
+        let xyz = adapt(multiply(&ColorSpace::Srgb.to_xyz(), [col.red, col.green, col.blue]), source, target);
+        let [r, g, b] = multiply(&inverse(&self.to_xyz()), xyz);
+        Rgb::with_wp(r, g, b)
+    }
+
+    /// Convert every pixel of an image.
+    pub fn apply(self, pixels: &mut [Rgb<E, f32>]) {
+        for pixel in pixels.iter_mut() {
+            *pixel = self.convert(*pixel);
+        }
+    }
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    let row = |r: &[f32; 3]| r[0]*v[0] + r[1]*v[1] + r[2]*v[2];
+    [row(&matrix[0]), row(&matrix[1]), row(&matrix[2])]
+}
+
+fn transpose(m: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    [[m[0][0], m[1][0], m[2][0]], [m[0][1], m[1][1], m[2][1]], [m[0][2], m[1][2], m[2][2]]]
+}
+
+/// The inverse by the adjugate, for the well conditioned matrices of primaries.
+fn inverse(m: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let cofactor = |r: usize, c: usize| {
+        let (r0, r1, c0, c1) = ((r + 1)%3, (r + 2)%3, (c + 1)%3, (c + 2)%3);
+        m[r0][c0]*m[r1][c1] - m[r0][c1]*m[r1][c0]
+    };
+    let determinant = m[0][0]*cofactor(0, 0) + m[0][1]*cofactor(0, 1) + m[0][2]*cofactor(0, 2);
+    let mut res = [[0.0; 3]; 3];
+    for (r, row) in res.iter_mut().enumerate() {
+        for (c, x) in row.iter_mut().enumerate() {
+            *x = cofactor(c, r)/determinant;
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matrices() {
+        // The published matrices from linear sRGB, to 4 decimals
+        let published = [
+            (ColorSpace::DisplayP3, [[0.8225, 0.1774, 0.0000], [0.0332, 0.9669, 0.0000], [0.0171, 0.0724, 0.9108]]),
+            (ColorSpace::AcesCg, [[0.6131, 0.3395, 0.0474], [0.0702, 0.9164, 0.0134], [0.0206, 0.1096, 0.8698]]),
+        ];
+        for &(space, matrix) in published.iter() {
+            for c in 0..3 {
+                let mut unit = [0.0; 3];
+                unit[c] = 1.0;
+                let col = space.convert(Rgb::with_wp(unit[0], unit[1], unit[2]));
+                for (r, value) in [col.red, col.green, col.blue].iter().enumerate() {
+                    assert!((value - matrix[r][c]).abs() < 2e-3, "{:?} {} {}: {}", space, r, c, value);
+                }
+            }
+        }
+        let col = Rgb::with_wp(0.8, 0.4, 0.1);
+        assert_eq!(ColorSpace::Srgb.convert(col), col);
+    }
+
+    #[test]
+    fn test_srgb_to_xyz() {
+        let matrix = ColorSpace::Srgb.to_xyz();
+        assert!((matrix[0][0] - 0.4124).abs() < 1e-3 && (matrix[1][1] - 0.7152).abs() < 1e-3 && (matrix[2][2] - 0.9505).abs() < 1e-3);
+        assert_eq!(ColorSpace::from_name("rec709"), Some(ColorSpace::Srgb));
+    }
+}
@@ -0,0 +1,179 @@
+use color::cie_1931::xyz_from_wavelength;
+
+/// How the wavelengths of paths are drawn from a range.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum WavelengthSampling {
+    /// Every wavelength equally often.
+    Uniform,
+    /// Proportional to the luminous efficiency, the CIE Y matching function. The wavelengths at the ends of the
+    /// visible range add little to any color, so spending fewer paths on them leaves less color noise.
+    Luminance,
+}
+
+impl Default for WavelengthSampling {
+    fn default() -> WavelengthSampling {
+        WavelengthSampling::Uniform
+    }
+}
+
+impl WavelengthSampling {
+    pub fn from_name(name: &str) -> Option<WavelengthSampling> {
+        match name {
+            "uniform" => Some(WavelengthSampling::Uniform),
+            "luminance" => Some(WavelengthSampling::Luminance),
+            _ => None,
+        }
+    }
+
+    /// Draws wavelengths in `[low, high)` this way.
+    pub fn sampler(self, low: f32, high: f32) -> WavelengthSampler {
+        match self {
+            WavelengthSampling::Uniform => WavelengthSampler::uniform(low, high),
+            WavelengthSampling::Luminance => WavelengthSampler::luminance(low, high),
+        }
+    }
+}
+
+/// A piecewise constant distribution over wavelengths to draw the wavelength of a path from.
+/// Estimates have to be divided by `pdf` of the drawn wavelength.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WavelengthSampler {
+    /// Bin boundaries in nm, increasing.
+    edges: Vec<f32>,
+    /// Cumulative probability at every edge, from 0 to 1.
+    cdf: Vec<f32>,
+}
+
+impl WavelengthSampler {
+    /// Every wavelength in `[low, high)` is equally likely.
+    pub fn uniform(low: f32, high: f32) -> WavelengthSampler {
+        WavelengthSampler::tabulated(&[low, high], &[1.0])
+    }
+
+    /// Wavelengths are drawn proportional to the CIE Y matching function,
+    /// so the wavelengths the eye is most sensitive to get the most samples.
+    ///
+    /// # Panics
+    ///
+    /// If the range lies outside of the 360 to 830 nm the matching function covers.
+    pub fn luminance(low: f32, high: f32) -> WavelengthSampler {
+        // Follow the 5nm spacing of the matching function table
+        let mut edges = vec![low];
+        let mut edge = (low/5.0).floor()*5.0 + 5.0;
+        while edge < high {
+            edges.push(edge);
+            edge += 5.0;
+        }
+        edges.push(high);
+        let weights: Vec<f32> = edges.windows(2)
+            .map(|bin| xyz_from_wavelength(0.5*(bin[0] + bin[1])).y)
+            .collect();
+        WavelengthSampler::tabulated(&edges, &weights)
+    }
+
+    /// A custom distribution with `weights[i]` the relative density between `edges[i]` and `edges[i+1]`.
+    ///
+    /// # Panics
+    ///
+    /// If the edges aren't increasing, the lengths don't match, or no weight is positive.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # use rayer::color::WavelengthSampler;
+    /// let sampler = WavelengthSampler::tabulated(&[400.0, 500.0, 700.0], &[3.0, 1.0]);
+    /// let (wl, pdf) = sampler.sample(0.3);
+    /// assert!((wl - 450.0).abs() < 1e-3);
+    /// assert!((pdf - 0.006).abs() < 1e-6);
+    /// assert!((sampler.pdf(600.0) - 0.002).abs() < 1e-6);
+    /// ```
+    pub fn tabulated(edges: &[f32], weights: &[f32]) -> WavelengthSampler {
+        assert_eq!(edges.len(), weights.len() + 1, "need one more edge than weights");
+        assert!(edges.windows(2).all(|bin| bin[0] < bin[1]), "edges must be increasing");
+        assert!(weights.iter().all(|&w| w >= 0.0), "weights must not be negative");
+        let mut cdf = Vec::with_capacity(edges.len());
+        let mut total = 0.0;
+        cdf.push(0.0);
+        for (bin, &weight) in edges.windows(2).zip(weights.iter()) {
+            total += weight*(bin[1] - bin[0]);
+            cdf.push(total);
+        }
+        assert!(total > 0.0, "at least one weight must be positive");
+        for c in cdf.iter_mut() {
+            *c /= total;
+        }
+        WavelengthSampler { edges: edges.to_vec(), cdf }
+    }
+
+    /// The range wavelengths are drawn from.
+    pub fn range(&self) -> (f32, f32) {
+        (self.edges[0], self.edges[self.edges.len() - 1])
+    }
+
+    /// Map `u` in [0,1) to a wavelength by inverting the CDF, returning it with its density.
+    pub fn sample(&self, u: f32) -> (f32, f32) {
+        // The last bin starting at or below u, which always has a positive probability
+        let bin = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.edges.len() - 2);
+        let (c0, c1) = (self.cdf[bin], self.cdf[bin + 1]);
+        let (low, high) = (self.edges[bin], self.edges[bin + 1]);
+        let t = ((u - c0)/(c1 - c0)).max(0.0).min(1.0);
+        (low + t*(high - low), (c1 - c0)/(high - low))
+    }
+
+    /// The probability density of drawing `wl`.
+    pub fn pdf(&self, wl: f32) -> f32 {
+        let (low, high) = self.range();
+        if wl < low || wl >= high {
+            return 0.0;
+        }
+        let bin = self.edges.partition_point(|&e| e <= wl) - 1;
+        (self.cdf[bin + 1] - self.cdf[bin])/(self.edges[bin + 1] - self.edges[bin])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform() {
+        let sampler = WavelengthSampler::uniform(400.0, 700.0);
+        for i in 0..100 {
+            let (wl, pdf) = sampler.sample(i as f32/100.0);
+            assert!((wl - (400.0 + 3.0*i as f32)).abs() < 1e-3);
+            assert!((pdf - 1.0/300.0).abs() < 1e-7);
+        }
+        assert_eq!(sampler.pdf(399.0), 0.0);
+        assert_eq!(sampler.pdf(700.0), 0.0);
+    }
+
+    #[test]
+    fn test_luminance_follows_y() {
+        let sampler = WavelengthSampler::luminance(390.0, 700.0);
+        assert_eq!(sampler.range(), (390.0, 700.0));
+        assert!(sampler.pdf(557.0) > 10.0*sampler.pdf(450.0));
+        let (median, _) = sampler.sample(0.5);
+        assert!(median > 540.0 && median < 575.0, "{}", median);
+
+        // The pdf integrates to one
+        let integral: f32 = (390..700).map(|wl| sampler.pdf(wl as f32 + 0.5)).sum();
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+    }
+
+    #[test]
+    fn test_sampling() {
+        assert_eq!(WavelengthSampling::from_name("luminance").unwrap().sampler(400.0, 650.0), WavelengthSampler::luminance(400.0, 650.0));
+        assert_eq!(WavelengthSampling::default().sampler(380.0, 720.0).range(), (380.0, 720.0));
+        assert_eq!(WavelengthSampling::from_name("y"), None);
+    }
+
+    #[test]
+    fn test_sample_matches_pdf() {
+        let sampler = WavelengthSampler::tabulated(&[400.0, 450.0, 500.0, 600.0, 700.0], &[1.0, 0.0, 2.0, 0.5]);
+        for i in 0..1000 {
+            let (wl, pdf) = sampler.sample(i as f32/1000.0);
+            assert!(wl < 450.0 || wl >= 500.0, "sampled an empty bin at {}", wl);
+            assert_eq!(pdf, sampler.pdf(wl));
+        }
+    }
+}
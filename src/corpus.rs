@@ -0,0 +1,175 @@
+//! Property tests that keep their failures.
+//!
+//! `check` first replays every case stored for a property in `tests/corpus/<name>.txt`
+//! and then runs quickcheck on fresh inputs. A failing input is shrunk as usual and appended to that file,
+//! so it is tested again on every later run instead of vanishing with the next random seed.
+//! Every failure prints its seed, set `RAYER_QUICKCHECK_SEED` to rerun quickcheck with the same inputs.
+//!
+//! Cases are stored one per line as the hexadecimal bits of their floats, so they replay exactly.
+//! Lines starting with `#` are comments, the recorded cases are preceded by their debug output.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::env;
+use std::fmt::Debug;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+use quickcheck::{Arbitrary, Gen, QuickCheck};
+use rand;
+
+/// An input to a property that can be written to the corpus as a fixed number of floats.
+pub trait Case: Arbitrary + Debug {
+    const FLOATS: usize;
+
+    fn encode(&self, out: &mut Vec<f32>);
+
+    /// Reads a case from exactly `FLOATS` floats.
+    fn decode(floats: &[f32]) -> Self;
+}
+
+impl Case for f32 {
+    const FLOATS: usize = 1;
+
+    fn encode(&self, out: &mut Vec<f32>) {
+        out.push(*self);
+    }
+
+    fn decode(floats: &[f32]) -> f32 {
+        floats[0]
+    }
+}
+
+impl<A: Case, B: Case> Case for (A, B) {
+    const FLOATS: usize = A::FLOATS + B::FLOATS;
+
+    fn encode(&self, out: &mut Vec<f32>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(floats: &[f32]) -> (A, B) {
+        (A::decode(&floats[..A::FLOATS]), B::decode(&floats[A::FLOATS..]))
+    }
+}
+
+impl<A: Case, B: Case, C: Case> Case for (A, B, C) {
+    const FLOATS: usize = A::FLOATS + B::FLOATS + C::FLOATS;
+
+    fn encode(&self, out: &mut Vec<f32>) {
+        self.0.encode(out);
+        self.1.encode(out);
+        self.2.encode(out);
+    }
+
+    fn decode(floats: &[f32]) -> (A, B, C) {
+        let (a, rest) = floats.split_at(A::FLOATS);
+        let (b, c) = rest.split_at(B::FLOATS);
+        (A::decode(a), B::decode(b), C::decode(c))
+    }
+}
+
+/// A float uniformly distributed in `[low, high)`.
+/// `f32::arbitrary` covers the whole range of floats including infinities and NaN,
+/// which most geometry doesn't have to handle.
+pub fn uniform(g: &mut Gen, low: f32, high: f32) -> f32 {
+    low + (high - low)*(u32::arbitrary(g) >> 8) as f32/(1 << 24) as f32
+}
+
+/// A float in `[0, 1)`, for positions within a box or along an edge.
+#[derive(Debug, Clone, Copy)]
+pub struct Fraction(pub f32);
+
+impl Arbitrary for Fraction {
+    fn arbitrary(g: &mut Gen) -> Fraction {
+        Fraction(uniform(g, 0.0, 1.0))
+    }
+}
+
+impl Case for Fraction {
+    const FLOATS: usize = 1;
+
+    fn encode(&self, out: &mut Vec<f32>) {
+        out.push(self.0);
+    }
+
+    fn decode(floats: &[f32]) -> Fraction {
+        Fraction(floats[0])
+    }
+}
+
+thread_local! {
+    // The property quickcheck is currently running, as a `fn(T) -> bool`.
+    static PROPERTY: RefCell<Option<Box<dyn Any>>> = RefCell::new(None);
+    static LAST_FAILURE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+fn corpus_path(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "corpus", &format!("{}.txt", name)].iter().collect()
+}
+
+fn encode<T: Case>(case: &T) -> String {
+    let mut floats = Vec::with_capacity(T::FLOATS);
+    case.encode(&mut floats);
+    let words: Vec<String> = floats.iter().map(|f| format!("{:08x}", f.to_bits())).collect();
+    format!("# {:?}\n{}\n", case, words.join(" "))
+}
+
+fn decode<T: Case>(line: &str) -> Result<T, String> {
+    let floats = line.split_whitespace()
+        .map(|word| u32::from_str_radix(word, 16).map(f32::from_bits).map_err(|e| e.to_string()))
+        .collect::<Result<Vec<f32>, String>>()?;
+    if floats.len() != T::FLOATS {
+        return Err(format!("expected {} floats, found {}", T::FLOATS, floats.len()));
+    }
+    Ok(T::decode(&floats))
+}
+
+// Quickcheck only takes properties it can name the type of, so the property itself is passed on the side.
+fn recording<T: Case>(case: T) -> bool {
+    let property = PROPERTY.with(|p| {
+        *p.borrow().as_ref().and_then(|p| p.downcast_ref::<fn(T) -> bool>()).expect("no property is being checked")
+    });
+    let encoded = encode(&case);
+    let holds = panic::catch_unwind(AssertUnwindSafe(|| property(case))).unwrap_or(false);
+    if !holds {
+        // Shrinking only continues from failing inputs, so the last one is the smallest.
+        LAST_FAILURE.with(|f| *f.borrow_mut() = Some(encoded));
+    }
+    holds
+}
+
+/// Checks that `property` holds for the stored cases and for random ones,
+/// storing any new failure in the corpus named `name`.
+pub fn check<T: Case>(name: &str, property: fn(T) -> bool) {
+    let path = corpus_path(name);
+    if let Ok(text) = fs::read_to_string(&path) {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let case: T = decode(line).unwrap_or_else(|e| panic!("{}:{}: {}", path.display(), i + 1, e));
+            let debug = format!("{:?}", case);
+            assert!(property(case), "{}:{}: {} fails for {}", path.display(), i + 1, name, debug);
+        }
+    }
+
+    let seed = match env::var("RAYER_QUICKCHECK_SEED") {
+        Ok(seed) => seed.parse().expect("RAYER_QUICKCHECK_SEED is not a number"),
+        Err(_) => rand::random(),
+    };
+    PROPERTY.with(|p| *p.borrow_mut() = Some(Box::new(property)));
+    LAST_FAILURE.with(|f| *f.borrow_mut() = None);
+    let result = QuickCheck::new().rng(Gen::from_size_and_seed(100, seed)).quicktest(recording::<T> as fn(T) -> bool);
+    PROPERTY.with(|p| *p.borrow_mut() = None);
+    if let Err(result) = result {
+        let failure = LAST_FAILURE.with(|f| f.borrow_mut().take()).expect("a failure was recorded");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut file = OpenOptions::new().create(true).append(true).open(&path).unwrap();
+        write!(file, "{}", failure).unwrap();
+        panic!("{} failed with RAYER_QUICKCHECK_SEED={}, the case was added to {}: {:?}", name, seed, path.display(), result);
+    }
+}
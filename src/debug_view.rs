@@ -0,0 +1,181 @@
+//! False colors showing what the camera rays hit instead of the light, and triangle edges drawn over the image,
+//! to find problems with meshes, texture coordinates and the BVH.
+
+use euclid::*;
+use palette::Rgb;
+use palette::white_point::E;
+
+use camera::Camera;
+use hitable::{HitRecord, Hitable, TMin};
+use hitable::bvh::BVH;
+use ray::Ray;
+
+/// Boxes entered at which the `Boxes` view turns fully red.
+const MAX_BOXES: f32 = 64.0;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DebugView {
+    /// The normal pointing out of the surface, with every axis from -1 to 1 mapped to a channel from 0 to 1.
+    Normals,
+    /// `u` as red and `v` as green, repeating every unit, and blue where they leave [0,1]. Magenta where they aren't numbers.
+    Uvs,
+    /// The distance to the hit as grey, white close to the camera and black at the far end of the scene.
+    Depth,
+    /// How many boxes of the scene's BVH the ray went through to the hit, from blue for few to red for many.
+    Boxes,
+}
+
+impl DebugView {
+    pub fn from_name(name: &str) -> Option<DebugView> {
+        match name {
+            "normals" => Some(DebugView::Normals),
+            "uvs" => Some(DebugView::Uvs),
+            "depth" => Some(DebugView::Depth),
+            "bbox" => Some(DebugView::Boxes),
+            _ => None,
+        }
+    }
+
+    /// The color of what `r` hits, or `None` if it hits nothing.
+    /// Only the `Boxes` view shows rays that miss, which still pass through boxes on the way.
+    pub fn color<H: Hitable>(self, r: Ray, world: &BVH<H>, t_min: TMin) -> Option<Rgb<E, f32>> {
+        let t_min = t_min.t_min(r);
+        let rec = world.hit(r, t_min, f32::MAX);
+        if self == DebugView::Boxes {
+            let boxes = world.boxes_entered(r, t_min, rec.map_or(f32::MAX, |rec| rec.t));
+            return if rec.is_some() || boxes > 0 { Some(heat(boxes as f32/MAX_BOXES)) } else { None };
+        }
+        let rec = rec?;
+        Some(match self {
+            DebugView::Normals => {
+                let n = rec.normal.normalize()*0.5 + vec3(0.5, 0.5, 0.5);
+                Rgb::with_wp(n.x, n.y, n.z)
+            },
+            DebugView::Uvs if !(rec.uv.x.is_finite() && rec.uv.y.is_finite()) => Rgb::with_wp(1.0, 0.0, 1.0),
+            DebugView::Uvs => {
+                let outside = rec.uv.x < 0.0 || rec.uv.x > 1.0 || rec.uv.y < 0.0 || rec.uv.y > 1.0;
+                Rgb::with_wp(rec.uv.x - rec.uv.x.floor(), rec.uv.y - rec.uv.y.floor(), if outside { 1.0 } else { 0.0 })
+            },
+            DebugView::Depth => {
+                let distance = rec.t*r.direction.length();
+                let [low, high] = world.bbox().bounds;
+                let far = (0..8)
+                    .map(|corner| point3(
+                        if corner & 1 == 0 { low.x } else { high.x },
+                        if corner & 2 == 0 { low.y } else { high.y },
+                        if corner & 4 == 0 { low.z } else { high.z },
+                    ))
+                    .map(|corner| (corner - r.origin).length())
+                    .fold(0.0, f32::max);
+                let grey = (1.0 - distance/far).max(0.0);
+                Rgb::with_wp(grey, grey, grey)
+            },
+            DebugView::Boxes => unreachable!(),
+        })
+    }
+}
+
+/// The edges of the triangles the camera sees, drawn a given number of pixels wide wherever the image is looked at.
+pub struct Wireframe<'a> {
+    pub camera: &'a Camera,
+    /// Height of the image in pixels.
+    pub height: u32,
+    /// Width of the lines in pixels.
+    pub width: f32,
+}
+
+impl<'a> Wireframe<'a> {
+    /// The color of the lines.
+    pub fn color() -> Rgb<E, f32> {
+        Rgb::with_wp(1.0, 0.5, 0.0)
+    }
+
+    /// Whether a hit seen by the camera lies on a line, which only the hits on triangles can.
+    pub fn covers(&self, rec: &HitRecord) -> bool {
+        rec.edge_distance.map_or(false, |distance| distance < 0.5*self.width*self.camera.pixel_footprint(rec.p, self.height))
+    }
+}
+
+/// Blue at 0 through cyan, green and yellow to red at 1 and above.
+fn heat(x: f32) -> Rgb<E, f32> {
+    let x = x.max(0.0).min(1.0)*4.0;
+    match x as u32 {
+        0 => Rgb::with_wp(0.0, x, 1.0),
+        1 => Rgb::with_wp(0.0, 1.0, 2.0 - x),
+        2 => Rgb::with_wp(x - 2.0, 1.0, 0.0),
+        _ => Rgb::with_wp(1.0, (4.0 - x).max(0.0), 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use hitable::sphere::Sphere;
+    use material::Lambertian;
+    use texture::Texture;
+
+    fn world() -> BVH<Arc<dyn Hitable>> {
+        let grey: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        BVH::initialize(vec![
+            Arc::new(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, grey.clone())) as Arc<dyn Hitable>,
+            Arc::new(Sphere::new(point3(0.0, 0.0, 10.0), 1.0, grey)),
+        ])
+    }
+
+    #[test]
+    fn test_views() {
+        let world = world();
+        let t_min = TMin::default();
+        let front = Ray::new(point3(0.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+        let miss = Ray::new(point3(0.0, 5.0, -5.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+
+        assert_eq!(DebugView::Normals.color(front, &world, t_min), Some(Rgb::with_wp(0.5, 0.5, 0.0)));
+        assert_eq!(DebugView::Normals.color(miss, &world, t_min), None);
+        let uv = DebugView::Uvs.color(front, &world, t_min).unwrap();
+        assert!(uv.red >= 0.0 && uv.red < 1.0 && uv.green >= 0.0 && uv.green < 1.0 && uv.blue == 0.0);
+
+        let near = DebugView::Depth.color(front, &world, t_min).unwrap().red;
+        let far = DebugView::Depth.color(Ray::new(point3(0.0, 0.0, 20.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0), &world, t_min).unwrap().red;
+        assert!(near > far && far >= 0.0 && near < 1.0, "{} {}", near, far);
+
+        assert_eq!(DebugView::Boxes.color(miss, &world, t_min), None);
+        assert!(DebugView::Boxes.color(front, &world, t_min).unwrap().blue > 0.0);
+    }
+
+    #[test]
+    fn test_wireframe() {
+        use camera::{CameraKeyframe, Movements};
+        use hitable::triangle::Triangle;
+
+        let grey: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let normal = vec3(0.0, 0.0, -1.0);
+        let triangle = Triangle::new(
+            (point3(-1.0, -1.0, 0.0), point3(1.0, -1.0, 0.0), point3(-1.0, 1.0, 0.0)),
+            (normal, normal, normal),
+            (vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0)),
+            grey,
+        );
+        // 2 units across 100 pixels, so a pixel covers 0.02
+        let fov = 2.0*(0.1f32).atan().to_degrees();
+        let camera = Camera::new(&CameraKeyframe { look_from: point3(0.0, 0.0, -10.0), look_at: point3(0.0, 0.0, 0.0), vfov: fov, aperture: 0.0, focus_dist: 10.0, movements: Movements::default() }, vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0);
+        let wireframe = Wireframe { camera: &camera, height: 100, width: 2.0 };
+        let at = |x: f32, y: f32| {
+            let r = Ray::new(point3(x, y, -10.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+            triangle.hit(r, 1e-3, f32::MAX).unwrap()
+        };
+        assert!(wireframe.covers(&at(-0.5, -0.99)));
+        assert!(wireframe.covers(&at(-0.5, 0.49)));
+        assert!(!wireframe.covers(&at(-0.5, -0.95)));
+        assert!(!wireframe.covers(&at(-0.5, 0.0)));
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5))));
+        assert!(!wireframe.covers(&sphere.hit(Ray::new(point3(0.0, 0.0, -10.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0), 1e-3, f32::MAX).unwrap()));
+    }
+
+    #[test]
+    fn test_heat() {
+        assert_eq!(heat(0.0), Rgb::with_wp(0.0, 0.0, 1.0));
+        assert_eq!(heat(0.5), Rgb::with_wp(0.0, 1.0, 0.0));
+        assert_eq!(heat(2.0), Rgb::with_wp(1.0, 0.0, 0.0));
+    }
+}
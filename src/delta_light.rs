@@ -0,0 +1,167 @@
+//! Lights without a surface, like lamps far smaller than the scene or the sun seen from the ground.
+//!
+//! Their light leaves from a single point or arrives from a single direction, so rays never hit them by chance.
+//! Instead the path tracer aims at them from each diffuse surface it reaches, at every one or at one picked by a
+//! `light_bvh::LightBVH` when there are many, and they stay invisible to the camera and to the other surfaces.
+//! Photon mapping and light tracing don't send light from them.
+use std::f32::consts::PI;
+use std::sync::Arc;
+use euclid::*;
+
+use color::HasReflectance;
+use sampler::sample_cone;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum LightShape {
+    /// Light from far away travelling along `direction`, coming from a disc `angular_radius` radians wide, which
+    /// softens the shadows like the sun does. `intensity` is the light falling on a surface facing it.
+    Directional { direction: Vector3D<f32, UnknownUnit>, angular_radius: f32 },
+    /// Light leaving `position` evenly in all directions. `intensity` is the light per solid angle, which falls off
+    /// with the square of the distance.
+    Point { position: Point3D<f32, UnknownUnit> },
+    /// A point light shining along `direction`, at full intensity within `inner` radians of it and fading out
+    /// smoothly towards `outer` radians.
+    Spot { position: Point3D<f32, UnknownUnit>, direction: Vector3D<f32, UnknownUnit>, inner: f32, outer: f32 },
+}
+
+/// The light reaching a point from a `DeltaLight`, or from a point on an emitting surface with `sppm::Light::sample`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct LightSample {
+    /// Towards the light, normalized.
+    pub direction: Vector3D<f32, UnknownUnit>,
+    /// How far the light is along `direction`, infinite for directional lights.
+    pub distance: f32,
+    /// The light falling on a surface facing it, which a surface turned away by an angle receives times its cosine.
+    pub irradiance: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeltaLight {
+    pub shape: LightShape,
+    /// The color of the light, scaled by `intensity`.
+    pub color: Arc<dyn HasReflectance>,
+    pub intensity: f32,
+}
+
+impl DeltaLight {
+    pub fn new<C: HasReflectance + 'static>(shape: LightShape, color: C, intensity: f32) -> DeltaLight {
+        DeltaLight { shape, color: Arc::new(color), intensity }
+    }
+
+    /// A directional light shining along `direction` from a disc as wide as the sun.
+    pub fn sun<C: HasReflectance + 'static>(direction: Vector3D<f32, UnknownUnit>, color: C, intensity: f32) -> DeltaLight {
+        DeltaLight::new(LightShape::Directional { direction, angular_radius: 0.00465 }, color, intensity)
+    }
+
+    /// The light from this light reaching `p` at the wavelength `wl`, aiming at a point of the light picked by the
+    /// random numbers `u`, for the lights that aren't points. `None` where it doesn't shine.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # extern crate euclid;
+    /// # extern crate palette;
+    /// # use euclid::*;
+    /// # use palette::Rgb;
+    /// # use rayer::delta_light::*;
+    /// let lamp = DeltaLight::new(LightShape::Point { position: point3(0.0, 2.0, 0.0) }, Rgb::with_wp(1.0, 1.0, 1.0), 8.0);
+    /// let sample = lamp.sample(point3(0.0, 0.0, 0.0), vec2(0.5, 0.5), 550.0).unwrap();
+    /// assert_eq!((sample.direction, sample.distance), (vec3(0.0, 1.0, 0.0), 2.0));
+    /// assert!((sample.irradiance - 2.0).abs() < 0.05);
+    /// ```
+    pub fn sample(&self, p: Point3D<f32, UnknownUnit>, u: Vector2D<f32, UnknownUnit>, wl: f32) -> Option<LightSample> {
+        let intensity = self.intensity*self.color.reflect(wl);
+        match self.shape {
+            LightShape::Directional { direction, angular_radius } => {
+                let direction = sample_cone(u, -direction.normalize(), angular_radius.cos());
+                Some(LightSample { direction, distance: f32::INFINITY, irradiance: intensity })
+            },
+            LightShape::Point { position } => {
+                let (direction, distance) = towards(p, position)?;
+                Some(LightSample { direction, distance, irradiance: intensity/(distance*distance) })
+            },
+            LightShape::Spot { position, direction: axis, inner, outer } => {
+                let (direction, distance) = towards(p, position)?;
+                let falloff = smoothstep(outer.cos(), inner.cos(), -direction.dot(axis.normalize()));
+                if falloff <= 0.0 {
+                    return None;
+                }
+                Some(LightSample { direction, distance, irradiance: intensity*falloff/(distance*distance) })
+            },
+        }
+    }
+
+    /// `intensity` times the color averaged over the visible wavelengths.
+    pub fn mean_intensity(&self) -> f32 {
+        self.intensity*(0..=12).map(|i| self.color.reflect(400.0 + 25.0*i as f32)).sum::<f32>()/13.0
+    }
+
+    /// Light emitted over all directions, averaged over the visible wavelengths, to compare lights by.
+    /// Directional lights have none, as they shine on all of space.
+    pub fn power(&self) -> f32 {
+        let intensity = self.mean_intensity();
+        match self.shape {
+            LightShape::Directional { .. } => 0.0,
+            LightShape::Point { .. } => 4.0*PI*intensity,
+            // The fading edge counted as half
+            LightShape::Spot { inner, outer, .. } => 2.0*PI*(1.0 - (0.5*(inner + outer)).cos())*intensity,
+        }
+    }
+}
+
+/// The direction from `p` to `position` and the distance between them, if they aren't the same point.
+fn towards(p: Point3D<f32, UnknownUnit>, position: Point3D<f32, UnknownUnit>) -> Option<(Vector3D<f32, UnknownUnit>, f32)> {
+    let offset = position - p;
+    let distance = offset.length();
+    if distance > 0.0 { Some((offset/distance, distance)) } else { None }
+}
+
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    if high <= low {
+        return if x >= high { 1.0 } else { 0.0 };
+    }
+    let t = ((x - low)/(high - low)).max(0.0).min(1.0);
+    t*t*(3.0 - 2.0*t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use palette::white_point::E;
+
+    fn white() -> Rgb<E, f32> {
+        Rgb::with_wp(1.0, 1.0, 1.0)
+    }
+
+    #[test]
+    fn test_directional() {
+        let sun = DeltaLight::sun(vec3(0.0, -1.0, 0.0), white(), 3.0);
+        for &u in &[vec2(0.0, 0.0), vec2(0.5, 0.25), vec2(0.99, 0.9)] {
+            let sample = sun.sample(point3(5.0, 0.0, -2.0), u, 550.0).unwrap();
+            assert!(sample.direction.y >= 0.0047f32.cos() && (sample.direction.length() - 1.0).abs() < 1e-5);
+            assert_eq!(sample.distance, f32::INFINITY);
+            assert!((sample.irradiance - 3.0).abs() < 0.05);
+        }
+        assert_eq!(sun.power(), 0.0);
+    }
+
+    #[test]
+    fn test_spot() {
+        let spot = DeltaLight::new(
+            LightShape::Spot { position: point3(0.0, 1.0, 0.0), direction: vec3(0.0, -1.0, 0.0), inner: 0.3, outer: 0.5 },
+            Rgb::with_wp(1.0, 0.0, 0.0),
+            1.0,
+        );
+        let at = |x: f32| spot.sample(point3(x, 0.0, 0.0), vec2(0.5, 0.5), 650.0).map(|sample| sample.irradiance);
+        let center = at(0.0).unwrap();
+        // Full intensity inside the inner cone, falling off with the distance
+        assert!((at(0.2).unwrap()*1.04 - center).abs() < 1e-4*center);
+        let edge = at(0.4).unwrap();
+        assert!(edge > 0.0 && edge < at(0.3).unwrap());
+        assert_eq!(at(0.6), None);
+        // Red light hardly shines in blue
+        assert!(spot.sample(point3(0.0, 0.0, 0.0), vec2(0.5, 0.5), 450.0).unwrap().irradiance < 0.2*center);
+        assert!(spot.power() > 0.0 && spot.power() < DeltaLight::new(LightShape::Point { position: point3(0.0, 0.0, 0.0) }, Rgb::<E, f32>::with_wp(1.0, 0.0, 0.0), 1.0).power());
+        assert_eq!(DeltaLight::new(LightShape::Point { position: point3(0.0, 0.0, 0.0) }, white(), 1.0).sample(point3(0.0, 0.0, 0.0), vec2(0.5, 0.5), 550.0), None);
+    }
+}
@@ -0,0 +1,1020 @@
+//! Scenes as plain data, to read from scene files and write back out in any serde format with the `serde` feature.
+//!
+//! Objects and materials are trait objects, which can't be deserialized on their own. A `Description` holds the tag
+//! of a type under `type` and its parameters, and a `Registry` builds it with the constructor registered for the tag.
+//! Materials and textures share one set of tags, since objects take either. Images and meshes are given by path
+//! and loaded when the scene is built.
+//!
+//! ```
+//! # extern crate rayer;
+//! # use rayer::description::*;
+//! # use rayer::scene::Loader;
+//! let sphere = Description::new("sphere")
+//!     .with("center", vec![0.0, 1.0, 0.0])
+//!     .with("radius", 1.0)
+//!     .with("material", Description::new("lambertian").with("albedo", vec![0.8, 0.3, 0.3]));
+//! let object = Registry::default().hitable(&sphere, &Loader::silent()).unwrap();
+//! assert_eq!(object.bbox().bounds[1].y, 2.0);
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use euclid::*;
+use image::{imageops, GrayImage};
+use palette::Rgb;
+use palette::white_point::E;
+
+use camera::{CameraKeyframe, CameraPath};
+use color::{illuminant, HasReflectance, ILLUMINANTS};
+use delta_light::{DeltaLight, LightShape};
+use flare::LensFlare;
+use hitable::Hitable;
+use hitable::csg::{Csg, Cuboid, Cylinder, Operation, Solid};
+use hitable::curve::{CurveKind, Curves};
+use hitable::heightfield::Heightfield;
+use hitable::instance::{moving, rotate_y, scale, translate, with_visibility, Visibility};
+use hitable::sphere::Sphere;
+use hitable::quad::Quad;
+use hitable::subdivision::MAX_LEVELS;
+use hitable::triangle::Triangle;
+use material::{glass, Dielectric, Lambertian, Metal};
+use material::coated::Coated;
+use material::graph::{MaterialNode, Node};
+use material::mix::MixMaterial;
+use material::pbr::PbrMaterial;
+use material::light::DiffuseLight;
+use scene::{Loader, Scene};
+use settings::SettingsOverrides;
+use sky::Sky;
+use texture::{Encoding, ImageTexture, Texture, TextureChannel};
+
+/// A parameter of a description, as any self-describing format can hold it.
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(untagged))]
+pub enum Value {
+    Bool(bool),
+    Number(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(x: f32) -> Value {
+        Value::Number(x as f64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Value {
+        Value::Number(x)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(list: Vec<T>) -> Value {
+        Value::List(list.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A nested description is a map with its tag under `type`.
+impl From<Description> for Value {
+    fn from(description: Description) -> Value {
+        let mut map = description.params;
+        map.insert("type".to_string(), Value::String(description.kind));
+        Value::Map(map)
+    }
+}
+
+/// An object, material or texture as the tag of its type and its parameters.
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Description {
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub kind: String,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub params: BTreeMap<String, Value>,
+}
+
+impl Description {
+    pub fn new(kind: &str) -> Description {
+        Description { kind: kind.to_string(), params: BTreeMap::new() }
+    }
+
+    /// Set a parameter.
+    pub fn with<V: Into<Value>>(mut self, name: &str, value: V) -> Description {
+        self.params.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// The description a map with a `type` holds.
+    pub fn from_value(value: &Value) -> Option<Description> {
+        match *value {
+            Value::Map(ref map) => match map.get("type") {
+                Some(&Value::String(ref kind)) => {
+                    let params = map.iter().filter(|&(name, _)| name != "type").map(|(name, value)| (name.clone(), value.clone())).collect();
+                    Some(Description { kind: kind.clone(), params })
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn error(&self, name: &str, expected: &str) -> Error {
+        Error::new(ErrorKind::InvalidData, format!("{}: expected {} for {}", self.kind, expected, name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.params.get(name)
+    }
+
+    fn param(&self, name: &str) -> Result<&Value, Error> {
+        self.get(name).ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{}: missing {}", self.kind, name)))
+    }
+
+    pub fn number(&self, name: &str) -> Result<f32, Error> {
+        match *self.param(name)? {
+            Value::Number(x) => Ok(x as f32),
+            _ => Err(self.error(name, "a number")),
+        }
+    }
+
+    /// A number that may be left out.
+    pub fn number_or(&self, name: &str, default: f32) -> Result<f32, Error> {
+        match self.get(name) {
+            Some(_) => self.number(name),
+            None => Ok(default),
+        }
+    }
+
+    pub fn string(&self, name: &str) -> Result<&str, Error> {
+        match *self.param(name)? {
+            Value::String(ref s) => Ok(s),
+            _ => Err(self.error(name, "a string")),
+        }
+    }
+
+    fn numbers<const N: usize>(&self, name: &str, expected: &str) -> Result<[f32; N], Error> {
+        let mut numbers = [0.0; N];
+        match *self.param(name)? {
+            Value::List(ref list) if list.len() == N => {
+                for (number, value) in numbers.iter_mut().zip(list) {
+                    match *value {
+                        Value::Number(x) => *number = x as f32,
+                        _ => return Err(self.error(name, expected)),
+                    }
+                }
+                Ok(numbers)
+            },
+            _ => Err(self.error(name, expected)),
+        }
+    }
+
+    /// A point given as `[x, y, z]`.
+    pub fn point(&self, name: &str) -> Result<Point3D<f32, UnknownUnit>, Error> {
+        let [x, y, z] = self.numbers(name, "a point [x, y, z]")?;
+        Ok(point3(x, y, z))
+    }
+
+    /// A color given as `[r, g, b]`, or a single number for grey.
+    pub fn color(&self, name: &str) -> Result<Rgb<E, f32>, Error> {
+        if let Value::Number(x) = *self.param(name)? {
+            return Ok(Rgb::with_wp(x as f32, x as f32, x as f32));
+        }
+        let [r, g, b] = self.numbers(name, "a color [r, g, b] or a number")?;
+        Ok(Rgb::with_wp(r, g, b))
+    }
+
+    pub fn description(&self, name: &str) -> Result<Description, Error> {
+        Description::from_value(self.param(name)?).ok_or_else(|| self.error(name, "a map with a type"))
+    }
+
+    /// The rays seeing an object, from a list of `camera`, `shadow` and `bounce` under `visible_to`. All of them if
+    /// it isn't there.
+    pub fn visibility(&self) -> Result<Visibility, Error> {
+        let kinds = match self.get("visible_to") {
+            None => return Ok(Visibility::default()),
+            Some(&Value::List(ref kinds)) => kinds,
+            Some(_) => return Err(self.error("visible_to", "a list of camera, shadow and bounce")),
+        };
+        let mut visibility = Visibility { camera: false, shadow: false, bounce: false };
+        for kind in kinds {
+            match *kind {
+                Value::String(ref kind) if kind == "camera" => visibility.camera = true,
+                Value::String(ref kind) if kind == "shadow" => visibility.shadow = true,
+                Value::String(ref kind) if kind == "bounce" => visibility.bounce = true,
+                _ => return Err(self.error("visible_to", "a list of camera, shadow and bounce")),
+            }
+        }
+        Ok(visibility)
+    }
+}
+
+/// Builds a type from its description, asking the registry for the descriptions nested in it
+/// and the loader for the files it needs.
+pub type Constructor<T> = fn(&Description, &Registry, &Loader) -> Result<T, Error>;
+
+/// Constructors for the types a description can name, by tag.
+/// The default registry knows the built-in types, and more can be registered next to them.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # use std::io::Error;
+/// # use std::sync::Arc;
+/// # use palette::Rgb;
+/// # use rayer::description::*;
+/// # use rayer::material::Lambertian;
+/// # use rayer::scene::Loader;
+/// # use rayer::texture::Texture;
+/// fn chalk(_: &Description, _: &Registry, _: &Loader) -> Result<Arc<dyn Texture>, Error> {
+///     Ok(Arc::new(Lambertian::new(Rgb::with_wp(0.9, 0.9, 0.85))))
+/// }
+///
+/// let mut registry = Registry::default();
+/// registry.register_texture("chalk", chalk);
+/// assert!(registry.texture(&Description::new("chalk"), &Loader::silent()).is_ok());
+/// ```
+#[derive(Clone)]
+pub struct Registry {
+    hitables: HashMap<String, Constructor<Arc<dyn Hitable>>>,
+    textures: HashMap<String, Constructor<Arc<dyn Texture>>>,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry::new();
+        registry.register_hitable("sphere", sphere);
+        registry.register_hitable("triangle", triangle);
+        registry.register_hitable("quad", quad);
+        registry.register_hitable("mesh", mesh);
+        registry.register_hitable("cuboid", cuboid);
+        registry.register_hitable("cylinder", cylinder);
+        registry.register_hitable("csg", csg);
+        registry.register_hitable("curves", curves);
+        registry.register_hitable("point_cloud", point_cloud);
+        registry.register_hitable("heightfield", heightfield);
+        registry.register_hitable("translate", translated);
+        registry.register_hitable("rotate_y", rotated);
+        registry.register_hitable("scale", scaled);
+        registry.register_hitable("moving", moved);
+        registry.register_texture("lambertian", lambertian);
+        registry.register_texture("metal", metal);
+        registry.register_texture("dielectric", dielectric);
+        registry.register_texture("coated", coated);
+        registry.register_texture("mix", mix);
+        registry.register_texture("light", light);
+        registry.register_texture("image", image);
+        registry.register_texture("pbr", pbr);
+        registry.register_texture("graph", graph);
+        registry
+    }
+}
+
+impl Registry {
+    /// A registry without any types.
+    pub fn new() -> Registry {
+        Registry { hitables: HashMap::new(), textures: HashMap::new() }
+    }
+
+    /// Add an object type, replacing any registered under the same tag.
+    pub fn register_hitable(&mut self, kind: &str, constructor: Constructor<Arc<dyn Hitable>>) {
+        self.hitables.insert(kind.to_string(), constructor);
+    }
+
+    /// Add a material or texture type, replacing any registered under the same tag.
+    pub fn register_texture(&mut self, kind: &str, constructor: Constructor<Arc<dyn Texture>>) {
+        self.textures.insert(kind.to_string(), constructor);
+    }
+
+    /// Build a described object. Any object can be hidden from some rays with `visible_to`, see `Description::visibility`.
+    pub fn hitable(&self, description: &Description, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+        let object = match self.hitables.get(&description.kind) {
+            Some(constructor) => constructor(description, self, loader)?,
+            None => return Err(Error::new(ErrorKind::InvalidData, format!("unknown object type {}", description.kind))),
+        };
+        let visibility = description.visibility()?;
+        if visibility == Visibility::default() {
+            return Ok(object);
+        }
+        Ok(Arc::new(with_visibility(object, visibility)))
+    }
+
+    pub fn texture(&self, description: &Description, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+        match self.textures.get(&description.kind) {
+            Some(constructor) => constructor(description, self, loader),
+            None => Err(Error::new(ErrorKind::InvalidData, format!("unknown material type {}", description.kind))),
+        }
+    }
+
+    /// Build a described scene with the objects it lists.
+    pub fn scene(&self, description: &SceneDescription, loader: &Loader) -> Result<Scene, Error> {
+        let objects = description.objects.iter().map(|object| self.hitable(object, loader)).collect::<Result<_, _>>()?;
+        let lights = description.lights.iter().map(delta_light).collect::<Result<_, _>>()?;
+        let camera = description.camera;
+        if let Some(ref animation) = description.animation {
+            animation.check().map_err(|message| Error::new(ErrorKind::InvalidData, message))?;
+        }
+        Ok(Scene {
+            objects,
+            look_from: camera.look_from,
+            look_at: camera.look_at,
+            focus_dist: camera.focus_dist,
+            aperture: camera.aperture,
+            vfov: camera.vfov,
+            movements: camera.movements,
+            render_sky: description.render_sky,
+            sky: description.sky,
+            lights,
+            animation: description.animation.clone(),
+            flare: if description.flare { Some(LensFlare::default()) } else { None },
+            settings: description.settings,
+        })
+    }
+}
+
+/// A scene as plain data, for `Registry::scene` to build.
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SceneDescription {
+    pub camera: CameraKeyframe,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub animation: Option<CameraPath>,
+    #[cfg_attr(feature = "serde", serde(default = "render_sky_default"))]
+    pub render_sky: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sky: Sky,
+    /// Whether the lens adds the default flare.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub flare: bool,
+    pub objects: Vec<Description>,
+    /// Lights without a surface, see `delta_light`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub lights: Vec<Description>,
+    /// How the scene is best rendered, where it differs from the defaults.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub settings: SettingsOverrides,
+}
+
+#[cfg(feature = "serde")]
+fn render_sky_default() -> bool {
+    true
+}
+
+/// A `directional` light along `direction`, spread over `angular_radius` degrees or as wide as the sun, a `point`
+/// light at `position`, or a `spot` light at `position` along `direction`, fading out from `inner` to `outer` degrees.
+/// All of them have an `intensity` and a `color`, white if there is none.
+pub fn delta_light(description: &Description) -> Result<DeltaLight, Error> {
+    let shape = match &description.kind[..] {
+        "directional" => LightShape::Directional {
+            direction: description.point("direction")?.to_vector(),
+            angular_radius: description.number_or("angular_radius", 0.2667)?.to_radians(),
+        },
+        "point" => LightShape::Point { position: description.point("position")? },
+        "spot" => LightShape::Spot {
+            position: description.point("position")?,
+            direction: description.point("direction")?.to_vector(),
+            inner: description.number("inner")?.to_radians(),
+            outer: description.number("outer")?.to_radians(),
+        },
+        kind => return Err(Error::new(ErrorKind::InvalidData, format!("unknown light type {}", kind))),
+    };
+    let color = match description.get("color") {
+        Some(_) => description.color("color")?,
+        None => Rgb::with_wp(1.0, 1.0, 1.0),
+    };
+    Ok(DeltaLight::new(shape, color, description.number("intensity")?))
+}
+
+fn sphere(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let material = registry.texture(&description.description("material")?, loader)?;
+    Ok(Arc::new(Sphere::new(description.point("center")?, description.number("radius")?, material)))
+}
+
+fn triangle(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let material = registry.texture(&description.description("material")?, loader)?;
+    let (a, b, c) = (description.point("a")?, description.point("b")?, description.point("c")?);
+    let normal = (b - a).cross(c - a).normalize();
+    let uv = (vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0));
+    Ok(Arc::new(Triangle::new((a, b, c), (normal, normal, normal), uv, material)))
+}
+
+/// Corners `a`, `b`, `c` and `d` in order around the quad, which can be bent out of a plane.
+fn quad(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let material = registry.texture(&description.description("material")?, loader)?;
+    let corners = [description.point("a")?, description.point("b")?, description.point("c")?, description.point("d")?];
+    // Across the diagonals, the average normal of a bent quad
+    let normal = (corners[2] - corners[0]).cross(corners[3] - corners[1]).normalize();
+    Ok(Arc::new(Quad::uniform(corners, normal, material)))
+}
+
+/// An obj file at `path`, subdivided `subdivide` times, or as often as the loader subdivides meshes.
+fn mesh(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let material = registry.texture(&description.description("material")?, loader)?;
+    let path = description.string("path")?;
+    let levels = description.number_or("subdivide", loader.subdivision() as f32)?;
+    if levels < 0.0 || levels.fract() != 0.0 {
+        return Err(description.error("subdivide", "a whole number"));
+    }
+    if levels > MAX_LEVELS as f32 {
+        return Err(description.error("subdivide", &format!("a whole number up to {}", MAX_LEVELS)));
+    }
+    let mut meshes = loader.subdivided_meshes(vec![(path, material)], levels as u32)?;
+    Ok(Arc::new(meshes.remove(0)))
+}
+
+/// A solid box from the corner `low` to `high`.
+fn cuboid(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    Ok(Arc::new(solid(description, registry, loader)?))
+}
+
+/// A solid cylinder of `radius` from the center of its `base` to the center of its `top`.
+fn cylinder(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    Ok(Arc::new(solid(description, registry, loader)?))
+}
+
+/// The `union`, `intersection` or `difference`, by `operation`, of the solids `a` and `b`. Solids are spheres,
+/// cuboids, cylinders and other csg objects.
+fn csg(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    Ok(Arc::new(solid(description, registry, loader)?))
+}
+
+fn solid(description: &Description, registry: &Registry, loader: &Loader) -> Result<Box<dyn Solid>, Error> {
+    let material = || registry.texture(&description.description("material")?, loader);
+    match &description.kind[..] {
+        "sphere" => Ok(Box::new(Sphere::new(description.point("center")?, description.number("radius")?, material()?))),
+        "cuboid" => Ok(Box::new(Cuboid::new(description.point("low")?, description.point("high")?, material()?))),
+        "cylinder" => {
+            let (base, top) = (description.point("base")?, description.point("top")?);
+            Ok(Box::new(Cylinder::new(base, top, description.number("radius")?, material()?)))
+        },
+        "csg" => {
+            let operation = match description.string("operation")? {
+                "union" => Operation::Union,
+                "intersection" => Operation::Intersection,
+                "difference" => Operation::Difference,
+                _ => return Err(description.error("operation", "union, intersection or difference")),
+            };
+            let a = solid(&description.description("a")?, registry, loader)?;
+            let b = solid(&description.description("b")?, registry, loader)?;
+            Ok(Box::new(Csg::new(a, b, operation)))
+        },
+        kind => Err(Error::new(ErrorKind::InvalidData, format!("csg: {} isn't a solid", kind))),
+    }
+}
+
+/// Strands through the control points of `strands`, each a list of `[x, y, z, radius]`, as `round` tubes or as
+/// `flat` ribbons by `kind`, round if there is none.
+fn curves(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let material = registry.texture(&description.description("material")?, loader)?;
+    let kind = match description.get("kind") {
+        None => CurveKind::Round,
+        Some(_) => match description.string("kind")? {
+            "round" => CurveKind::Round,
+            "flat" => CurveKind::Flat,
+            _ => return Err(description.error("kind", "round or flat")),
+        },
+    };
+    let expected = || description.error("strands", "a list of strands of points [x, y, z, radius]");
+    let point = |value: &Value| match *value {
+        Value::List(ref numbers) if numbers.len() == 4 => {
+            let mut point = [0.0; 4];
+            for (x, value) in point.iter_mut().zip(numbers) {
+                match *value {
+                    Value::Number(number) => *x = number as f32,
+                    _ => return Err(expected()),
+                }
+            }
+            Ok((point3(point[0], point[1], point[2]), point[3]))
+        },
+        _ => Err(expected()),
+    };
+    let strands = match *description.param("strands")? {
+        Value::List(ref strands) => strands.iter().map(|strand| match *strand {
+            Value::List(ref points) if points.len() >= 2 => points.iter().map(&point).collect::<Result<Vec<_>, _>>(),
+            _ => Err(expected()),
+        }).collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(expected()),
+    };
+    Ok(Arc::new(Curves::catmull_rom(&strands, kind, material)))
+}
+
+/// The points of the xyz or ply file at `path`, with the `radius` for points without one, 0.01 if there is none.
+fn point_cloud(description: &Description, _: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let path = description.string("path")?;
+    let mut clouds = loader.point_clouds(vec![(path, description.number_or("radius", 0.01)?)])?;
+    Ok(Arc::new(clouds.remove(0)))
+}
+
+/// Heights from the brightness of the image at `path`, covering `size.x` along x and `size.z` along z from `origin`
+/// and rising up to `size.y` above it.
+fn heightfield(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let material = registry.texture(&description.description("material")?, loader)?;
+    let image = grayscale(description, "path", loader)?;
+    let (origin, size) = (description.point("origin")?, description.point("size")?.to_vector());
+    Ok(Arc::new(Heightfield::from_image(&image, origin, size, material)))
+}
+
+/// The described `object` moved by `offset`.
+fn translated(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let object = registry.hitable(&description.description("object")?, loader)?;
+    Ok(Arc::new(translate(object, description.point("offset")?.to_vector())))
+}
+
+/// The described `object` turned `angle` degrees around the y axis.
+fn rotated(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let object = registry.hitable(&description.description("object")?, loader)?;
+    Ok(Arc::new(rotate_y(object, description.number("angle")?)))
+}
+
+/// The described `object` scaled by `scale`, a number or `[x, y, z]`.
+fn scaled(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let object = registry.hitable(&description.description("object")?, loader)?;
+    let factor = match *description.param("scale")? {
+        Value::Number(x) => vec3(x as f32, x as f32, x as f32),
+        _ => description.numbers("scale", "a number or [x, y, z]").map(|[x, y, z]| vec3(x, y, z))?,
+    };
+    Ok(Arc::new(scale(object, factor)))
+}
+
+/// The described `object` moving by `velocity` over the shutter and spinning `spin` degrees, 0 if there is none.
+fn moved(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Hitable>, Error> {
+    let object = registry.hitable(&description.description("object")?, loader)?;
+    Ok(Arc::new(moving(object, description.point("velocity")?.to_vector(), description.number_or("spin", 0.0)?)))
+}
+
+fn lambertian(description: &Description, _: &Registry, _: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    Ok(Arc::new(Lambertian::new(description.color("albedo")?)))
+}
+
+/// A mirror in `albedo`, blurred by `fuzz`, a number or a map like the channels of `pbr`, 0 if there is none.
+fn metal(description: &Description, _: &Registry, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    let fuzz = match description.get("fuzz") {
+        Some(_) => channel(description, "fuzz", loader)?,
+        None => TextureChannel::Constant(0.0),
+    };
+    Ok(Arc::new(Metal::new(description.color("albedo")?, fuzz)))
+}
+
+/// A glass from the catalog by `glass`, or one without dispersion by its index of refraction `ior`, which can also be
+/// a map like the channels of `pbr`.
+fn dielectric(description: &Description, _: &Registry, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    if let Some(&Value::Map(_)) = description.get("ior") {
+        return Ok(Arc::new(Dielectric::BK7.with_ior(channel(description, "ior", loader)?)));
+    }
+    Ok(Arc::new(glass(description)?))
+}
+
+fn glass(description: &Description) -> Result<Dielectric, Error> {
+    if description.get("glass").is_some() {
+        let name = description.string("glass")?;
+        return glass::by_name(name)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{}: unknown glass {}", description.kind, name)));
+    }
+    let ior = description.number("ior")?;
+    Ok(Dielectric::new(ior*ior - 1.0, 0.0, 0.0, 0.0, 0.0, 0.0))
+}
+
+/// A node graph from its `root`, a `diffuse` node with an `albedo`, a `metal` node with an `albedo` and a `fuzz`, 0 if
+/// there is none, a `dielectric` node like the `dielectric` material, an `emission` node with a `color` or a `mix` of
+/// the nodes `a` and `b` by `factor`. Values are numbers, colors, `image`s, `noise` with a `scale` and `octaves`,
+/// `fresnel` with an `ior`, a `mix` of `a` and `b` by `factor` or a `multiply` of `a` and `b`. Occlusion and curvature
+/// trace against the object they are on, so they can only be built in code.
+fn graph(description: &Description, _: &Registry, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    Ok(Arc::new(material_node(&description.description("root")?, loader)?))
+}
+
+fn material_node(description: &Description, loader: &Loader) -> Result<MaterialNode, Error> {
+    let node = |name: &str| value_node(description, name, loader);
+    match &description.kind[..] {
+        "diffuse" => Ok(MaterialNode::Diffuse { albedo: node("albedo")? }),
+        "metal" => Ok(MaterialNode::Metal { albedo: node("albedo")?, fuzz: description.number_or("fuzz", 0.0)? }),
+        "dielectric" => Ok(MaterialNode::Dielectric(glass(description)?)),
+        "emission" => Ok(MaterialNode::Emission { color: node("color")? }),
+        "mix" => {
+            let a = material_node(&description.description("a")?, loader)?;
+            let b = material_node(&description.description("b")?, loader)?;
+            Ok(MaterialNode::mix(a, b, node("factor")?))
+        },
+        kind => Err(Error::new(ErrorKind::InvalidData, format!("graph: unknown material node {}", kind))),
+    }
+}
+
+fn value_node(description: &Description, name: &str, loader: &Loader) -> Result<Node, Error> {
+    match *description.param(name)? {
+        Value::Number(x) => return Ok(Node::Constant(x as f32)),
+        Value::List(_) => return Ok(Node::Color(description.color(name)?)),
+        _ => (),
+    }
+    let node = description.description(name)?;
+    let value = |name: &str| value_node(&node, name, loader);
+    match &node.kind[..] {
+        "image" => Ok(Node::Image(image_texture(&node, loader)?)),
+        "noise" => {
+            let octaves = node.number_or("octaves", 1.0)?;
+            if octaves < 1.0 || octaves.fract() != 0.0 {
+                return Err(node.error("octaves", "a whole number of at least 1"));
+            }
+            Ok(Node::Noise { scale: node.number_or("scale", 1.0)?, octaves: octaves as u32 })
+        },
+        "fresnel" => Ok(Node::Fresnel { ior: node.number("ior")? }),
+        "mix" => Ok(Node::mix(value("a")?, value("b")?, value("factor")?)),
+        "multiply" => Ok(Node::multiply(value("a")?, value("b")?)),
+        kind => Err(Error::new(ErrorKind::InvalidData, format!("graph: unknown value node {}", kind))),
+    }
+}
+
+/// A clear coat with the index of refraction `ior`, 1.5 if there is none, and the `roughness`, 0 if there is none,
+/// over the material or texture `base`.
+fn coated(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    let base = registry.texture(&description.description("base")?, loader)?;
+    let coat = Coated::new(base, description.number_or("ior", 1.5)?).with_roughness(description.number_or("roughness", 0.0)?);
+    Ok(Arc::new(coat))
+}
+
+/// The materials or textures `a` and `b`, with `factor` of `b`, a number or a map like the channels of `pbr`.
+fn mix(description: &Description, registry: &Registry, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    let a = registry.texture(&description.description("a")?, loader)?;
+    let b = registry.texture(&description.description("b")?, loader)?;
+    Ok(Arc::new(MixMaterial::new(a, b, channel(description, "factor", loader)?)))
+}
+
+/// A light emitting `emit` from both sides, or only from the front with `"sides": "front"`. With an `illuminant`
+/// like `F11` it emits that spectrum, as bright as `emit` times a white of 1. A `strength` map like the channels of
+/// `pbr` scales it over the surface.
+fn light(description: &Description, _: &Registry, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    if description.get("illuminant").is_none() {
+        return sided(description, DiffuseLight::new(description.color("emit")?), loader);
+    }
+    let spectrum = illuminant(description.string("illuminant")?)
+        .ok_or_else(|| description.error("illuminant", &format!("one of {}", ILLUMINANTS.join(", "))))?;
+    sided(description, DiffuseLight::new(description.number_or("emit", 1.0)?*spectrum), loader)
+}
+
+fn sided<C: HasReflectance + Clone + 'static>(description: &Description, light: DiffuseLight<C>, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    let light = match description.get("strength") {
+        Some(_) => light.with_strength(channel(description, "strength", loader)?),
+        None => light,
+    };
+    if description.get("sides").is_none() {
+        return Ok(Arc::new(light));
+    }
+    match description.string("sides")? {
+        "both" => Ok(Arc::new(light)),
+        "front" => Ok(Arc::new(light.one_sided())),
+        _ => Err(description.error("sides", "front or both")),
+    }
+}
+
+/// The image at `path`, saved as sRGB unless `encoding` is `linear`, or with a power curve if there is a `gamma`.
+fn image(description: &Description, _: &Registry, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    Ok(Arc::new(image_texture(description, loader)?))
+}
+
+/// A texture set by `base_color`, `metalness`, `roughness`, `ior` and `emission`, each a number, a color, an `image`
+/// or a `map`. All but the base color can be left out.
+fn pbr(description: &Description, _: &Registry, loader: &Loader) -> Result<Arc<dyn Texture>, Error> {
+    let material = PbrMaterial::new(channel(description, "base_color", loader)?);
+    let optional = |name: &str, material: PbrMaterial, with: fn(PbrMaterial, TextureChannel) -> PbrMaterial| {
+        match description.get(name) {
+            Some(_) => Ok(with(material, channel(description, name, loader)?)),
+            None => Ok::<_, Error>(material),
+        }
+    };
+    let material = optional("metalness", material, PbrMaterial::with_metalness)?;
+    let material = optional("roughness", material, PbrMaterial::with_roughness)?;
+    let material = optional("ior", material, PbrMaterial::with_ior)?;
+    let material = optional("emission", material, PbrMaterial::with_emission)?;
+    Ok(Arc::new(material))
+}
+
+/// A channel of a material given as a number, a color, a color `image` or a grayscale `map` at `path` going from `low`,
+/// 0 if there is none, where it is black to `high`, 1 if there is none, where it is white.
+fn channel(description: &Description, name: &str, loader: &Loader) -> Result<TextureChannel, Error> {
+    match *description.param(name)? {
+        Value::Number(x) => return Ok(TextureChannel::Constant(x as f32)),
+        Value::List(_) => return Ok(TextureChannel::Color(description.color(name)?)),
+        _ => (),
+    }
+    let map = description.description(name)?;
+    match &map.kind[..] {
+        "image" => Ok(TextureChannel::ColorMap(image_texture(&map, loader)?)),
+        "map" => {
+            let image = grayscale(&map, "path", loader)?;
+            Ok(TextureChannel::Map { image: Arc::new(image), low: map.number_or("low", 0.0)?, high: map.number_or("high", 1.0)? })
+        },
+        _ => Err(description.error(name, "a number, a color, an image or a map")),
+    }
+}
+
+/// The brightness of the image at the path under `name`.
+fn grayscale(description: &Description, name: &str, loader: &Loader) -> Result<GrayImage, Error> {
+    let path = description.string(name)?;
+    let images = loader.images(&[path])
+        .map_err(|error| Error::new(ErrorKind::InvalidData, format!("{}: {}", Path::new(path).display(), error)))?;
+    Ok(imageops::grayscale(images[0].as_ref()))
+}
+
+fn image_texture(description: &Description, loader: &Loader) -> Result<ImageTexture, Error> {
+    let encoding = match description.get("gamma") {
+        Some(_) => Encoding::Gamma(description.number("gamma")?),
+        None if description.get("encoding").is_none() => Encoding::Srgb,
+        None => match description.string("encoding")? {
+            "srgb" => Encoding::Srgb,
+            "linear" => Encoding::Linear,
+            name => return Err(Error::new(ErrorKind::InvalidData, format!("image: unknown encoding {}", name))),
+        },
+    };
+    let path = description.string("path")?;
+    let mut images = loader.images(&[path])
+        .map_err(|error| Error::new(ErrorKind::InvalidData, format!("{}: {}", Path::new(path).display(), error)))?;
+    Ok(ImageTexture::with_encoding(&images.remove(0), encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::Movements;
+    use ray::{Ray, RayKind};
+    use settings::RenderSettings;
+    use std::io::Write;
+    use image::RgbImage;
+    use tempfile::{Builder, NamedTempFile};
+
+    fn scene() -> SceneDescription {
+        let white = Description::new("lambertian").with("albedo", 0.8);
+        SceneDescription {
+            camera: CameraKeyframe {
+                look_from: point3(0.0, 1.0, -5.0),
+                look_at: point3(0.0, 1.0, 0.0),
+                vfov: 40.0,
+                aperture: 0.0,
+                focus_dist: 5.0,
+                movements: Movements::default(),
+            },
+            animation: None,
+            render_sky: false,
+            sky: Sky::Gradient,
+            flare: true,
+            objects: vec![
+                Description::new("sphere").with("center", vec![0.0, 1.0, 0.0]).with("radius", 1.0)
+                    .with("material", Description::new("dielectric").with("glass", "bk7")),
+                Description::new("triangle")
+                    .with("a", vec![-5.0, 0.0, -5.0]).with("b", vec![5.0, 0.0, -5.0]).with("c", vec![0.0, 0.0, 5.0])
+                    .with("material", white.clone()),
+                Description::new("mesh").with("path", "data/bunny.obj").with("material", white),
+            ],
+            lights: vec![
+                Description::new("spot").with("position", vec![0.0, 4.0, 0.0]).with("direction", vec![0.0, -1.0, 0.0])
+                    .with("inner", 20.0).with("outer", 30.0).with("intensity", 10.0).with("color", vec![1.0, 0.9, 0.8]),
+            ],
+            settings: SettingsOverrides { samples: Some(64), max_depth: Some(8), ..Default::default() },
+        }
+    }
+
+    #[test]
+    fn test_scene() {
+        let still = SceneDescription { animation: Some(CameraPath::Keyframes(Vec::new())), ..scene() };
+        assert_eq!(Registry::default().scene(&still, &Loader::silent()).err().unwrap().to_string(), "the camera path has no keyframes");
+        let scene = Registry::default().scene(&scene(), &Loader::silent()).unwrap();
+        assert_eq!(scene.objects.len(), 3);
+        assert_eq!(scene.lights.len(), 1);
+        assert!(scene.lights[0].sample(point3(0.0, 0.0, 0.0), vec2(0.5, 0.5), 550.0).is_some());
+        assert!(scene.lights[0].sample(point3(4.0, 0.0, 0.0), vec2(0.5, 0.5), 550.0).is_none());
+        assert_eq!(scene.look_from, point3(0.0, 1.0, -5.0));
+        assert!(!scene.render_sky && scene.flare.is_some());
+        assert_eq!(scene.settings.apply(RenderSettings::default()).shading_rate().max_depth, 8);
+        let bounds = scene.bounds().unwrap();
+        assert_eq!((bounds.bounds[0].x, bounds.bounds[1].z), (-5.0, 5.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        let loader = Loader::silent();
+        let registry = Registry::default();
+        let error = |description: Description| registry.hitable(&description, &loader).err().unwrap().to_string();
+        let sphere = Description::new("sphere").with("center", vec![0.0, 1.0]).with("radius", 1.0);
+        assert_eq!(error(sphere.clone()), "sphere: missing material");
+        let sphere = sphere.with("material", Description::new("lambertian").with("albedo", 0.5));
+        assert_eq!(error(sphere.clone()), "sphere: expected a point [x, y, z] for center");
+        assert_eq!(error(sphere.with("center", vec![0.0; 3]).with("radius", "big")), "sphere: expected a number for radius");
+        assert_eq!(error(Description::new("cube")), "unknown object type cube");
+        let glass = Description::new("dielectric").with("glass", "window");
+        assert_eq!(registry.texture(&glass, &loader).err().unwrap().to_string(), "dielectric: unknown glass window");
+        let normals = Description::new("image").with("path", "normals.png").with("encoding", "raw");
+        assert_eq!(registry.texture(&normals, &loader).err().unwrap().to_string(), "image: unknown encoding raw");
+        assert_eq!(registry.texture(&Description::new("coated"), &loader).err().unwrap().to_string(), "coated: missing base");
+        let lacquer = Description::new("coated").with("base", Description::new("lambertian").with("albedo", 0.5)).with("roughness", 0.1);
+        assert!(registry.texture(&lacquer, &loader).is_ok());
+        let mix = Description::new("mix").with("a", lacquer.clone()).with("b", Description::new("metal").with("albedo", 0.9));
+        assert_eq!(registry.texture(&mix, &loader).err().unwrap().to_string(), "mix: missing factor");
+        assert!(registry.texture(&mix.with("factor", 0.3), &loader).is_ok());
+        let lamp = Description::new("light").with("emit", 4.0).with("sides", "back");
+        assert_eq!(registry.texture(&lamp, &loader).err().unwrap().to_string(), "light: expected front or both for sides");
+        let tube = Description::new("light").with("emit", 4.0).with("sides", "front");
+        assert!(registry.texture(&tube.clone().with("illuminant", "f11"), &loader).is_ok());
+        let error_message = registry.texture(&tube.with("illuminant", "F13"), &loader).err().unwrap().to_string();
+        assert_eq!(error_message, "light: expected one of E, D50, D55, D65, D75, A, F2, F7, F11, LED-cool, LED-warm for illuminant");
+        assert_eq!(delta_light(&Description::new("area")).err().unwrap().to_string(), "unknown light type area");
+        assert_eq!(delta_light(&Description::new("point").with("intensity", 1.0)).err().unwrap().to_string(), "point: missing position");
+        let hidden = Description::new("sphere").with("center", vec![0.0; 3]).with("radius", 1.0).with("material", Description::new("lambertian").with("albedo", 0.5));
+        let mesh = Description::new("mesh").with("path", "data/bunny.obj").with("material", Description::new("lambertian").with("albedo", 0.5));
+        assert_eq!(error(mesh.clone().with("subdivide", 1.5)), "mesh: expected a whole number for subdivide");
+        assert_eq!(error(mesh.with("subdivide", 7.0)), "mesh: expected a whole number up to 6 for subdivide");
+        assert_eq!(error(hidden.with("visible_to", vec!["camera", "mirror"])), "sphere: expected a list of camera, shadow and bounce for visible_to");
+    }
+
+    #[test]
+    fn test_quad() {
+        let floor = Description::new("quad")
+            .with("a", vec![-1.0, 0.0, -1.0]).with("b", vec![1.0, 0.0, -1.0]).with("c", vec![1.0, 0.0, 1.0]).with("d", vec![-1.0, 0.0, 1.0])
+            .with("material", Description::new("lambertian").with("albedo", 0.5));
+        let floor = Registry::default().hitable(&floor, &Loader::silent()).unwrap();
+        let hit = floor.hit(Ray::new(point3(0.5, 1.0, 0.5), vec3(0.0, -1.0, 0.0), 550.0, 0.0), 0.0, 10.0).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-6 && hit.normal.y.abs() > 0.999);
+        assert_eq!(floor.surface_area(), 4.0);
+    }
+
+    #[test]
+    fn test_solids_and_instances() {
+        let (registry, loader) = (Registry::default(), Loader::silent());
+        let white = Description::new("lambertian").with("albedo", 0.5);
+        let down = |x: f32, z: f32| Ray::new(point3(x, 5.0, z), vec3(0.0, -1.0, 0.0), 550.0, 0.0);
+        let late = Ray::new(point3(2.5, 5.0, 0.0), vec3(0.0, -1.0, 0.0), 550.0, 1.0);
+        let cuboid = Description::new("cuboid").with("low", vec![-1.0; 3]).with("high", vec![1.0; 3]).with("material", white.clone());
+        let hole = Description::new("cylinder").with("base", vec![0.0, -2.0, 0.0]).with("top", vec![0.0, 2.0, 0.0])
+            .with("radius", 0.5).with("material", white.clone());
+        let pipe = Description::new("csg").with("operation", "difference").with("a", cuboid.clone()).with("b", hole);
+        let object = registry.hitable(&pipe, &loader).unwrap();
+        assert!(object.hit(down(0.0, 0.0), 0.0, 10.0).is_none());
+        assert!((object.hit(down(0.75, 0.0), 0.0, 10.0).unwrap().t - 4.0).abs() < 1e-5);
+        assert_eq!(registry.hitable(&pipe.with("operation", "xor"), &loader).err().unwrap().to_string(),
+                   "csg: expected union, intersection or difference for operation");
+        let quad = Description::new("csg").with("operation", "union").with("a", cuboid.clone()).with("b", Description::new("quad"));
+        assert_eq!(registry.hitable(&quad, &loader).err().unwrap().to_string(), "csg: quad isn't a solid");
+
+        let moved = Description::new("translate").with("offset", vec![0.0, 1.0, 0.0]).with("object",
+            Description::new("scale").with("scale", 2.0).with("object",
+                Description::new("rotate_y").with("angle", 45.0).with("object", cuboid.clone())));
+        let object = registry.hitable(&moved, &loader).unwrap();
+        assert!((object.hit(down(0.0, 0.0), 0.0, 10.0).unwrap().t - 2.0).abs() < 1e-5);
+        assert!(object.hit(down(2.5, 0.0), 0.0, 10.0).is_some() && object.hit(down(2.5, 2.5), 0.0, 10.0).is_none());
+        let stretched = Description::new("scale").with("scale", vec![1.0, 3.0, 1.0]).with("object", cuboid.clone());
+        assert_eq!(registry.hitable(&stretched, &loader).unwrap().bbox().bounds[1].y, 3.0);
+        let moving = Description::new("moving").with("velocity", vec![2.0, 0.0, 0.0]).with("object", cuboid);
+        let object = registry.hitable(&moving, &loader).unwrap();
+        assert!(object.hit(down(2.5, 0.0), 0.0, 10.0).is_none());
+        assert!(object.hit(late, 0.0, 10.0).is_some());
+    }
+
+    #[test]
+    fn test_curves_heightfields_and_point_clouds() {
+        let (registry, loader) = (Registry::default(), Loader::silent());
+        let white = Description::new("lambertian").with("albedo", 0.5);
+        let down = |x: f32, z: f32| Ray::new(point3(x, 5.0, z), vec3(0.0, -1.0, 0.0), 550.0, 0.0);
+        let strand = Value::List(vec![vec![0.0, 0.0, -1.0, 0.1], vec![0.0, 0.0, 0.0, 0.1], vec![0.0, 0.0, 1.0, 0.1]]
+            .into_iter().map(Value::from).collect());
+        let hair = Description::new("curves").with("strands", Value::List(vec![strand])).with("material", white.clone());
+        let object = registry.hitable(&hair, &loader).unwrap();
+        assert!((object.hit(down(0.05, 0.0), 0.0, 10.0).unwrap().t - 5.0).abs() <= 0.1);
+        assert!(object.hit(down(0.5, 0.0), 0.0, 10.0).is_none());
+        let bent = hair.clone().with("strands", Value::List(vec![Value::from(vec![0.0, 0.0, 0.0])]));
+        assert_eq!(registry.hitable(&bent, &loader).err().unwrap().to_string(),
+                   "curves: expected a list of strands of points [x, y, z, radius] for strands");
+        assert!(registry.hitable(&hair.with("kind", "flat"), &loader).is_ok());
+
+        let hills = Builder::new().suffix(".png").tempfile().unwrap();
+        RgbImage::from_fn(8, 8, |x, y| image::Rgb([(x*y*4) as u8; 3])).save(hills.path()).unwrap();
+        let terrain = Description::new("heightfield").with("path", hills.path().to_str().unwrap())
+            .with("origin", vec![-1.0, 0.0, -1.0]).with("size", vec![2.0, 0.5, 2.0]).with("material", white);
+        let object = registry.hitable(&terrain, &loader).unwrap();
+        let height = 5.0 - object.hit(down(0.1, 0.2), 0.0, 10.0).unwrap().t;
+        assert!((0.0..=0.5).contains(&height));
+
+        let mut xyz = NamedTempFile::new().unwrap();
+        writeln!(xyz, "0 0 0 0 1 0").unwrap();
+        let points = Description::new("point_cloud").with("path", xyz.path().to_str().unwrap()).with("radius", 0.5);
+        let object = registry.hitable(&points, &loader).unwrap();
+        assert!((object.hit(down(0.3, 0.0), 0.0, 10.0).unwrap().t - 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_pbr() {
+        let (registry, loader) = (Registry::default(), Loader::silent());
+        let plastic = Description::new("pbr").with("base_color", vec![0.8, 0.1, 0.1]).with("roughness", 0.3);
+        assert!(registry.texture(&plastic, &loader).is_ok());
+        let scratched = Description::new("map").with("path", "data/earth.jpg").with("low", 0.2).with("high", 0.6);
+        let steel = Description::new("pbr").with("base_color", Description::new("image").with("path", "data/earth.jpg"))
+            .with("metalness", 1.0).with("roughness", scratched);
+        assert!(registry.texture(&steel, &loader).is_ok());
+        let lamp = Description::new("pbr").with("base_color", 0.5).with("emission", Description::new("light"));
+        assert_eq!(registry.texture(&lamp, &loader).err().unwrap().to_string(),
+                   "pbr: expected a number, a color, an image or a map for emission");
+        assert_eq!(registry.texture(&Description::new("pbr"), &loader).err().unwrap().to_string(), "pbr: missing base_color");
+    }
+
+    #[test]
+    fn test_parameter_maps() {
+        let (registry, loader) = (Registry::default(), Loader::silent());
+        let map = Description::new("map").with("path", "data/earth.jpg");
+        let brushed = Description::new("metal").with("albedo", 0.9).with("fuzz", map.clone());
+        assert!(registry.texture(&brushed, &loader).is_ok());
+        let varnish = Description::new("dielectric").with("ior", map.clone().with("low", 1.3).with("high", 1.6));
+        assert!(registry.texture(&varnish, &loader).is_ok());
+        let screen = Description::new("light").with("emit", 4.0).with("strength", map.clone()).with("sides", "front");
+        assert!(registry.texture(&screen, &loader).is_ok());
+        let rusty = Description::new("mix").with("a", Description::new("lambertian").with("albedo", 0.5))
+            .with("b", Description::new("lambertian").with("albedo", vec![0.4, 0.2, 0.1])).with("factor", map);
+        assert!(registry.texture(&rusty, &loader).is_ok());
+        let broken = Description::new("light").with("emit", 4.0).with("strength", Description::new("light"));
+        assert_eq!(registry.texture(&broken, &loader).err().unwrap().to_string(),
+                   "light: expected a number, a color, an image or a map for strength");
+    }
+
+    #[test]
+    fn test_graph() {
+        let (registry, loader) = (Registry::default(), Loader::silent());
+        let rust = Description::new("multiply").with("a", vec![0.6, 0.3, 0.1])
+            .with("b", Description::new("noise").with("scale", 4.0).with("octaves", 3.0));
+        let paint = Description::new("diffuse").with("albedo", Description::new("mix").with("a", 0.8).with("b", rust).with("factor", 0.5));
+        let chrome = Description::new("metal").with("albedo", Description::new("fresnel").with("ior", 2.5)).with("fuzz", 0.1);
+        let root = Description::new("mix").with("a", paint).with("b", chrome).with("factor", 0.25);
+        let built = material_node(&root, &loader).unwrap();
+        let rust = Node::multiply(Node::Color(Rgb::with_wp(0.6, 0.3, 0.1)), Node::Noise { scale: 4.0, octaves: 3 });
+        let expected = MaterialNode::mix(
+            MaterialNode::Diffuse { albedo: Node::mix(Node::Constant(0.8), rust, Node::Constant(0.5)) },
+            MaterialNode::Metal { albedo: Node::Fresnel { ior: 2.5 }, fuzz: 0.1 },
+            Node::Constant(0.25),
+        );
+        assert_eq!(format!("{:?}", built), format!("{:?}", expected));
+        let graph = Description::new("graph").with("root", root);
+        assert!(registry.texture(&graph, &loader).is_ok());
+
+        let glass = Description::new("graph").with("root", Description::new("dielectric").with("glass", "bk7"));
+        assert!(registry.texture(&glass, &loader).is_ok());
+        let lamp = Description::new("graph").with("root", Description::new("emission").with("color", Description::new("occlusion")));
+        assert_eq!(registry.texture(&lamp, &loader).err().unwrap().to_string(), "graph: unknown value node occlusion");
+        let glow = Description::new("graph").with("root", Description::new("glow"));
+        assert_eq!(registry.texture(&glow, &loader).err().unwrap().to_string(), "graph: unknown material node glow");
+    }
+
+    #[test]
+    fn test_visibility() {
+        let blocker = Description::new("sphere")
+            .with("center", vec![0.0; 3])
+            .with("radius", 1.0)
+            .with("material", Description::new("lambertian").with("albedo", 0.5))
+            .with("visible_to", vec!["shadow"]);
+        assert_eq!(blocker.visibility().unwrap(), Visibility { camera: false, shadow: true, bounce: false });
+        let object = Registry::default().hitable(&blocker, &Loader::silent()).unwrap();
+        let ray = Ray::new(point3(0.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+        assert!(object.hit(ray, 0.0, 10.0).is_none());
+        assert!(object.hit(ray.with_kind(RayKind::Shadow), 0.0, 10.0).is_some());
+    }
+
+    #[test]
+    fn test_nested_values() {
+        let material = Description::new("metal").with("albedo", vec![0.9, 0.8, 0.7]).with("fuzz", 0.1);
+        let nested = Description::new("sphere").with("material", material.clone());
+        assert_eq!(nested.description("material").unwrap(), material);
+        assert_eq!(material.color("albedo").unwrap(), Rgb::with_wp(0.9, 0.8, 0.7));
+        assert_eq!(Description::from_value(&Value::from(vec![1.0])), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize() {
+        use serde::Deserialize;
+        use serde::de::value::{Error as ValueError, MapDeserializer, SeqDeserializer};
+        use color::ColorSpectrum;
+
+        let fields = vec![("type", "dielectric"), ("glass", "F2")];
+        let glass = Description::deserialize(MapDeserializer::<_, ValueError>::new(fields.into_iter())).unwrap();
+        assert_eq!(glass, Description::new("dielectric").with("glass", "F2"));
+
+        let spectrum = ColorSpectrum::deserialize(SeqDeserializer::<_, ValueError>::new(vec![0.5f32; 36].into_iter())).unwrap();
+        assert_eq!(spectrum, ColorSpectrum::new([0.5; 36]));
+        assert!(ColorSpectrum::deserialize(SeqDeserializer::<_, ValueError>::new(vec![0.5f32; 3].into_iter())).is_err());
+    }
+}
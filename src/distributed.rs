@@ -0,0 +1,199 @@
+//! The messages between a coordinator handing out the passes of a render and the workers rendering them, over TCP.
+//!
+//! A coordinator connects to a worker and sends the token they share, then the command line to render with once, then
+//! a range of passes at a time. The worker answers every range with the film of those passes, which the coordinator
+//! merges into its own, or with an error if it can't render them. Every message is a tag byte and the length of what follows, so a worker that went away
+//! halfway is told apart from one that finished.
+
+use std::io::{Error, ErrorKind, Read, Write};
+use std::ops::Range;
+use std::time::Duration;
+
+use film::{Film, Layout};
+
+const ARGS: u8 = 1;
+const PASSES: u8 = 2;
+const FILM: u8 = 3;
+const TOKEN: u8 = 4;
+const ERROR: u8 = 5;
+
+/// The longest message other than a film, so a peer can't have the other side buffer whatever it likes.
+const MAX_LENGTH: u64 = 1 << 20;
+
+/// How long a worker waits for the next message of a coordinator before it gives up on it and takes the next one.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum Message {
+    /// The secret a worker was started with, which a coordinator has to know to have it render.
+    Token(String),
+    /// The command line arguments of the render, without the program name.
+    Args(Vec<String>),
+    /// Passes to render next, numbered like on a single machine.
+    Passes(Range<u64>),
+    /// The samples of the passes rendered last.
+    Film(Film),
+    /// Why the worker can't render with the options it was sent, in place of a film.
+    Error(String),
+}
+
+impl Message {
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        let (tag, payload) = match *self {
+            Message::Token(ref token) => (TOKEN, token.clone().into_bytes()),
+            Message::Error(ref message) => (ERROR, message.clone().into_bytes()),
+            // Arguments never contain a NUL byte
+            Message::Args(ref args) => (ARGS, args.join("\0").into_bytes()),
+            Message::Passes(ref passes) => {
+                let mut payload = passes.start.to_le_bytes().to_vec();
+                payload.extend(passes.end.to_le_bytes());
+                (PASSES, payload)
+            },
+            Message::Film(ref film) => {
+                let mut payload = Vec::new();
+                film.write_to(&mut payload)?;
+                (FILM, payload)
+            },
+        };
+        out.write_all(&[tag])?;
+        out.write_all(&(payload.len() as u64).to_le_bytes())?;
+        out.write_all(&payload)?;
+        out.flush()
+    }
+
+    /// The next message, or `None` if the stream ended before it. Films are only read with the `layout` they have
+    /// to have, which is checked before they are allocated.
+    pub fn read_from<R: Read>(input: &mut R, layout: Option<Layout>) -> Result<Option<Message>, Error> {
+        let mut tag = [0];
+        if input.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let mut length = [0; 8];
+        input.read_exact(&mut length)?;
+        let length = u64::from_le_bytes(length);
+        match (tag[0], layout) {
+            (FILM, Some(layout)) => return Film::read_from(&mut input.take(length), layout).map(|film| Some(Message::Film(film))),
+            (FILM, None) => return Err(Error::new(ErrorKind::InvalidData, "unexpected film")),
+            _ if length > MAX_LENGTH => return Err(Error::new(ErrorKind::InvalidData, format!("message {} of {} bytes is too long", tag[0], length))),
+            _ => (),
+        }
+        let mut payload = Vec::new();
+        input.take(length).read_to_end(&mut payload)?;
+        if payload.len() as u64 != length {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "the connection closed in the middle of a message"));
+        }
+        let message = match tag[0] {
+            TOKEN => Message::Token(String::from_utf8(payload).map_err(|error| Error::new(ErrorKind::InvalidData, error))?),
+            ERROR => Message::Error(String::from_utf8(payload).map_err(|error| Error::new(ErrorKind::InvalidData, error))?),
+            ARGS => {
+                let args = String::from_utf8(payload).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+                Message::Args(if args.is_empty() { Vec::new() } else { args.split('\0').map(String::from).collect() })
+            },
+            PASSES if payload.len() == 16 => {
+                let mut start = [0; 8];
+                let mut end = [0; 8];
+                start.copy_from_slice(&payload[..8]);
+                end.copy_from_slice(&payload[8..]);
+                Message::Passes(u64::from_le_bytes(start)..u64::from_le_bytes(end))
+            },
+            tag => return Err(Error::new(ErrorKind::InvalidData, format!("unknown message {} of {} bytes", tag, payload.len()))),
+        };
+        Ok(Some(message))
+    }
+}
+
+/// Whether `token` is the one `expected`, taking as long wherever they differ so the time doesn't give it away.
+pub fn same_token(token: &str, expected: &str) -> bool {
+    token.len() == expected.len() && token.bytes().zip(expected.bytes()).fold(0, |differ, (a, b)| differ | (a ^ b)) == 0
+}
+
+/// Split `passes` into ranges to hand out to `workers` workers as they finish the ones before,
+/// a few per worker so the fast ones take more.
+pub fn pass_chunks(passes: Range<u64>, workers: usize) -> Vec<Range<u64>> {
+    let count = passes.end.saturating_sub(passes.start);
+    let shares = 4*workers.max(1) as u64;
+    let size = ((count + shares - 1)/shares).max(1);
+    (0..(count + size - 1)/size)
+        .map(|i| passes.start + i*size..(passes.start + (i + 1)*size).min(passes.end))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use film::Accumulation;
+    use palette::Xyz;
+
+    fn layout() -> Layout {
+        Layout { pixels: 3, accumulation: Accumulation::Mean, track_variance: false }
+    }
+
+    fn round_trip(message: &Message) -> Message {
+        let mut bytes = Vec::new();
+        message.write_to(&mut bytes).unwrap();
+        Message::read_from(&mut &bytes[..], Some(layout())).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_messages() {
+        let args = vec!["--scene".to_string(), "cornell".to_string(), "--look-from".to_string(), "-1,2,3".to_string()];
+        match round_trip(&Message::Args(args.clone())) {
+            Message::Args(read) => assert_eq!(read, args),
+            _ => panic!("not the arguments"),
+        }
+        match round_trip(&Message::Passes(12..20)) {
+            Message::Passes(passes) => assert_eq!(passes, 12..20),
+            _ => panic!("not the passes"),
+        }
+        match round_trip(&Message::Token("secret".to_string())) {
+            Message::Token(token) => assert_eq!(token, "secret"),
+            _ => panic!("not the token"),
+        }
+        match round_trip(&Message::Error("data/bunny.obj: not found".to_string())) {
+            Message::Error(message) => assert_eq!(message, "data/bunny.obj: not found"),
+            _ => panic!("not the error"),
+        }
+        let mut film = Film::new(3, Accumulation::Mean, false);
+        film.add(0, 1, Xyz::with_wp(1.0, 2.0, 3.0), 1.0);
+        match round_trip(&Message::Film(film)) {
+            Message::Film(read) => assert_eq!((read.samples(1), read.alpha(1)), (1, 1.0)),
+            _ => panic!("not the film"),
+        }
+
+        assert!(Message::read_from(&mut &[][..], None).unwrap().is_none());
+        let mut bytes = Vec::new();
+        Message::Passes(0..4).write_to(&mut bytes).unwrap();
+        assert_eq!(Message::read_from(&mut &bytes[..10], None).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_refuses_films_unlike_the_layout() {
+        let mut bytes = Vec::new();
+        Message::Film(Film::new(3, Accumulation::Mean, false)).write_to(&mut bytes).unwrap();
+        assert!(Message::read_from(&mut &bytes[..], None).is_err());
+        let larger = Layout { pixels: 4, ..layout() };
+        assert_eq!(Message::read_from(&mut &bytes[..], Some(larger)).unwrap_err().kind(), ErrorKind::InvalidData);
+        // A length no command line has is refused before reading on
+        let mut bytes = vec![ARGS];
+        bytes.extend(u64::MAX.to_le_bytes());
+        assert_eq!(Message::read_from(&mut &bytes[..], None).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_same_token() {
+        assert!(same_token("secret", "secret"));
+        assert!(!same_token("secreT", "secret"));
+        assert!(!same_token("secret!", "secret"));
+        assert!(!same_token("", "secret"));
+    }
+
+    #[test]
+    fn test_pass_chunks() {
+        assert_eq!(pass_chunks(0..10, 1), vec![0..3, 3..6, 6..9, 9..10]);
+        assert_eq!(pass_chunks(5..7, 3), vec![5..6, 6..7]);
+        assert!(pass_chunks(0..0, 2).is_empty());
+        let chunks = pass_chunks(0..1000, 3);
+        assert_eq!(chunks.len(), 12);
+        assert_eq!(chunks.last().unwrap().end, 1000);
+    }
+}
@@ -0,0 +1,580 @@
+//! Accumulating the samples of every pass into pixel colors.
+
+use std::f32::consts::PI;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use euclid::*;
+use palette::*;
+use palette::white_point::E;
+
+use color::{KahanSum, KahanXyz};
+
+/// How the samples of a pixel are combined into its color.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Accumulation {
+    /// The mean of all samples.
+    Mean,
+    /// Passes go round robin into `buffers` separately averaged buffers, and every channel takes the median of their means.
+    /// A firefly only raises the mean of the buffer it landed in, which the median passes over.
+    /// This is biased towards darker pixels where bright paths are rare, but far less than clamping samples,
+    /// as long as every buffer gets enough samples for its mean to be close to the others.
+    MedianOfMeans { buffers: u32 },
+}
+
+impl Accumulation {
+    fn buffers(self) -> usize {
+        match self {
+            Accumulation::Mean => 1,
+            Accumulation::MedianOfMeans { buffers } => buffers.max(1) as usize,
+        }
+    }
+}
+
+/// How much a sample counts towards the pixels around it, by its distance from their centers in pixels.
+/// Every filter is the product of a function of the horizontal and one of the vertical distance,
+/// which is 0 from `radius` on.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Filter {
+    /// Samples count fully within `radius`. With the default radius of half a pixel, only towards their own pixel,
+    /// which makes every pixel the plain mean of its samples.
+    Box { radius: f32 },
+    /// Falling off linearly to 0 at `radius`.
+    Tent { radius: f32 },
+    /// A Gaussian of falloff `alpha`, shifted down to reach 0 at `radius`.
+    Gaussian { radius: f32, alpha: f32 },
+    /// The cubic of Mitchell and Netravali, whose negative lobes keep edges sharp.
+    /// B = C = 1/3 is what they recommend.
+    Mitchell { radius: f32, b: f32, c: f32 },
+    /// The Blackman-Harris window, about as sharp as a Gaussian but without the cut off at its edge.
+    BlackmanHarris { radius: f32 },
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    /// The filter called `name`, with its usual shape and default radius.
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "box" => Some(Filter::Box { radius: 0.5 }),
+            "tent" => Some(Filter::Tent { radius: 1.0 }),
+            "gaussian" => Some(Filter::Gaussian { radius: 1.5, alpha: 2.0 }),
+            "mitchell" => Some(Filter::Mitchell { radius: 2.0, b: 1.0/3.0, c: 1.0/3.0 }),
+            "blackman-harris" => Some(Filter::BlackmanHarris { radius: 2.0 }),
+            _ => None,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius } | Filter::Tent { radius } | Filter::Gaussian { radius, .. }
+                | Filter::Mitchell { radius, .. } | Filter::BlackmanHarris { radius } => radius,
+        }
+    }
+
+    /// The same filter reaching out to `radius`.
+    pub fn with_radius(self, radius: f32) -> Filter {
+        match self {
+            Filter::Box { .. } => Filter::Box { radius },
+            Filter::Tent { .. } => Filter::Tent { radius },
+            Filter::Gaussian { alpha, .. } => Filter::Gaussian { radius, alpha },
+            Filter::Mitchell { b, c, .. } => Filter::Mitchell { radius, b, c },
+            Filter::BlackmanHarris { .. } => Filter::BlackmanHarris { radius },
+        }
+    }
+
+    /// The weight of a sample `offset` from the center of a pixel.
+    pub fn weight(&self, offset: Vector2D<f32, UnknownUnit>) -> f32 {
+        self.weight_1d(offset.x)*self.weight_1d(offset.y)
+    }
+
+    fn weight_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match *self {
+            // A sample on the edge between two pixels counts half towards each
+            Filter::Box { radius } => if x < radius { 1.0 } else if x == radius { 0.5 } else { 0.0 },
+            Filter::Tent { radius } => (radius - x).max(0.0),
+            Filter::Gaussian { radius, alpha } => ((-alpha*x*x).exp() - (-alpha*radius*radius).exp()).max(0.0),
+            Filter::Mitchell { radius, b, c } => {
+                let x = 2.0*x/radius;
+                if x >= 2.0 {
+                    0.0
+                } else if x >= 1.0 {
+                    ((-b - 6.0*c)*x*x*x + (6.0*b + 30.0*c)*x*x + (-12.0*b - 48.0*c)*x + (8.0*b + 24.0*c))/6.0
+                } else {
+                    ((12.0 - 9.0*b - 6.0*c)*x*x*x + (-18.0 + 12.0*b + 6.0*c)*x*x + (6.0 - 2.0*b))/6.0
+                }
+            },
+            Filter::BlackmanHarris { radius } => {
+                if x >= radius {
+                    return 0.0;
+                }
+                let t = 2.0*PI*(0.5 + 0.5*x/radius);
+                0.35875 - 0.48829*t.cos() + 0.14128*(2.0*t).cos() - 0.01168*(3.0*t).cos()
+            },
+        }
+    }
+}
+
+/// Light added straight to the pixels, from any number of threads at once,
+/// like the paths of a light tracer that land on the film wherever they are seen.
+#[derive(Debug)]
+pub struct Splats {
+    /// The bits of the X, Y and Z sums of every pixel.
+    sums: Vec<[AtomicU32; 3]>,
+}
+
+impl Splats {
+    pub fn new(pixels: usize) -> Splats {
+        Splats { sums: (0..pixels).map(|_| [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)]).collect() }
+    }
+
+    pub fn splat(&self, pixel: usize, xyz: Xyz<E, f32>) {
+        for (sum, value) in self.sums[pixel].iter().zip(&[xyz.x, xyz.y, xyz.z]) {
+            if *value == 0.0 {
+                continue;
+            }
+            let mut current = sum.load(Ordering::Relaxed);
+            while let Err(actual) = sum.compare_exchange_weak(current, (f32::from_bits(current) + value).to_bits(), Ordering::Relaxed, Ordering::Relaxed) {
+                current = actual;
+            }
+        }
+    }
+
+    /// The light splatted onto a pixel so far.
+    pub fn get(&self, pixel: usize) -> Xyz<E, f32> {
+        let [x, y, z] = &self.sums[pixel];
+        let value = |sum: &AtomicU32| f32::from_bits(sum.load(Ordering::Relaxed));
+        Xyz::with_wp(value(x), value(y), value(z))
+    }
+}
+
+/// The size of a film and what it keeps per pixel, which films have to share to be merged.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Layout {
+    pub pixels: usize,
+    pub accumulation: Accumulation,
+    pub track_variance: bool,
+}
+
+/// The sums of the samples taken so far, per pixel in row major order.
+#[derive(Debug, Clone)]
+pub struct Film {
+    accumulation: Accumulation,
+    filter: Filter,
+    /// Pixels per row, to find the neighbours a filter spreads a sample over.
+    width: usize,
+    /// The buffers of a pixel are next to each other.
+    sums: Vec<KahanXyz>,
+    /// The filter weights the sums were taken with, for every buffer.
+    weights: Vec<KahanSum>,
+    counts: Vec<u32>,
+    /// Summed coverage of every pixel, weighted like the samples.
+    coverage: Vec<KahanSum>,
+    /// Squared samples of every pixel weighted like the samples, for the variance.
+    squares: Option<Vec<[KahanSum; 3]>>,
+    /// Light splatted onto every pixel, and the passes it was splatted over.
+    splats: Vec<KahanXyz>,
+    splat_passes: u32,
+}
+
+impl Film {
+    pub fn new(pixels: usize, accumulation: Accumulation, track_variance: bool) -> Film {
+        let buffers = accumulation.buffers();
+        Film {
+            accumulation,
+            filter: Filter::default(),
+            width: pixels,
+            sums: vec![KahanXyz::new(); pixels*buffers],
+            weights: vec![KahanSum::new(); pixels*buffers],
+            counts: vec![0; pixels*buffers],
+            coverage: vec![KahanSum::new(); pixels],
+            squares: if track_variance { Some(vec![[KahanSum::new(); 3]; pixels]) } else { None },
+            splats: vec![KahanXyz::new(); pixels],
+            splat_passes: 0,
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout { pixels: self.splats.len(), accumulation: self.accumulation, track_variance: self.squares.is_some() }
+    }
+
+    /// Spread the samples over the pixels around them with `filter`, in an image `width` pixels wide.
+    pub fn with_filter(self, filter: Filter, width: u32) -> Film {
+        Film { filter, width: width as usize, ..self }
+    }
+
+    /// Add a sample of the pass with index `pass` through the center of a pixel.
+    /// `coverage` is 1 if the camera ray hit something and 0 if it saw the background.
+    pub fn add(&mut self, pass: u64, pixel: usize, xyz: Xyz<E, f32>, coverage: f32) {
+        self.add_at(pass, pixel, vec2(0.0, 0.0), xyz, coverage);
+    }
+
+    /// Add a sample of the pass with index `pass` taken `offset` from the center of a pixel, in pixels right and down.
+    /// It counts towards the pixels around it by the weights of the filter, but only as a sample of its own pixel.
+    pub fn add_at(&mut self, pass: u64, pixel: usize, offset: Vector2D<f32, UnknownUnit>, xyz: Xyz<E, f32>, coverage: f32) {
+        let buffers = self.accumulation.buffers();
+        let buffer = (pass % buffers as u64) as usize;
+        self.counts[pixel*buffers + buffer] += 1;
+        let height = (self.sums.len()/buffers/self.width) as i64;
+        let (x, y) = ((pixel % self.width) as i64, (pixel/self.width) as i64);
+        let reach = (self.filter.radius() + 0.5).ceil() as i64;
+        let col = xyz.into_rgb();
+        for ny in (y - reach).max(0)..=(y + reach).min(height - 1) {
+            for nx in (x - reach).max(0)..=(x + reach).min(self.width as i64 - 1) {
+                let weight = self.filter.weight(offset - vec2((nx - x) as f32, (ny - y) as f32));
+                if weight == 0.0 {
+                    continue;
+                }
+                let neighbour = (ny*self.width as i64 + nx) as usize;
+                let i = neighbour*buffers + buffer;
+                self.sums[i].add(xyz*weight);
+                self.weights[i].add(weight);
+                self.coverage[neighbour].add(coverage*weight);
+                if let Some(ref mut squares) = self.squares {
+                    squares[neighbour][0].add(col.red*col.red*weight);
+                    squares[neighbour][1].add(col.green*col.green*weight);
+                    squares[neighbour][2].add(col.blue*col.blue*weight);
+                }
+            }
+        }
+    }
+
+    /// Add the light splatted over one pass, which every pixel shows the mean of over the passes on top of its samples.
+    pub fn add_splats(&mut self, splats: &Splats) {
+        for (sum, pixel) in self.splats.iter_mut().zip(0..) {
+            sum.add(splats.get(pixel));
+        }
+        self.splat_passes += 1;
+    }
+
+    /// Add the samples and splats of another film of the same size, like one a worker rendered other passes into.
+    /// With median of means, the passes have to have gone to the same buffers in both.
+    pub fn merge(&mut self, other: &Film) -> Result<(), Error> {
+        if self.layout() != other.layout() {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "can't merge a film of {} pixels in {} buffers into one of {} pixels in {}",
+                other.splats.len(), other.accumulation.buffers(), self.splats.len(), self.accumulation.buffers(),
+            )));
+        }
+        for (sum, other) in self.sums.iter_mut().zip(&other.sums) {
+            sum.add(other.sum());
+        }
+        for (weight, other) in self.weights.iter_mut().zip(&other.weights) {
+            weight.add(other.sum());
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        for (coverage, other) in self.coverage.iter_mut().zip(&other.coverage) {
+            coverage.add(other.sum());
+        }
+        if let (Some(squares), Some(other)) = (self.squares.as_mut(), other.squares.as_ref()) {
+            for (squares, other) in squares.iter_mut().zip(other) {
+                for (square, other) in squares.iter_mut().zip(other) {
+                    square.add(other.sum());
+                }
+            }
+        }
+        for (splat, other) in self.splats.iter_mut().zip(&other.splats) {
+            splat.add(other.sum());
+        }
+        self.splat_passes += other.splat_passes;
+        Ok(())
+    }
+
+    /// Write the sums in a little endian binary format that `read_from` reads back, to send them to another process.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        let mut bytes = Vec::new();
+        bytes.extend((self.splats.len() as u64).to_le_bytes());
+        bytes.extend((self.accumulation.buffers() as u32).to_le_bytes());
+        bytes.push(self.squares.is_some() as u8);
+        for sum in self.sums.iter().chain(&self.splats) {
+            let xyz = sum.sum();
+            for value in &[xyz.x, xyz.y, xyz.z] {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        for sum in self.weights.iter().chain(&self.coverage).chain(self.squares.iter().flatten().flatten()) {
+            bytes.extend(sum.sum().to_le_bytes());
+        }
+        for count in self.counts.iter().chain(Some(&self.splat_passes)) {
+            bytes.extend(count.to_le_bytes());
+        }
+        out.write_all(&bytes)
+    }
+
+    /// Read a film written by `write_to`, which is all that is left of `input`. Its header has to match `layout`,
+    /// which is checked before anything is allocated, so a film sent over the network can't ask for any size it likes.
+    /// It adds samples as the default `Film::new` does.
+    pub fn read_from<R: Read>(input: &mut R, layout: Layout) -> Result<Film, Error> {
+        let mut header = [0; 13];
+        input.read_exact(&mut header)?;
+        let mut field = [0; 8];
+        field.copy_from_slice(&header[0..8]);
+        let pixels = u64::from_le_bytes(field);
+        let mut field = [0; 4];
+        field.copy_from_slice(&header[8..12]);
+        let buffers = u32::from_le_bytes(field);
+        let expected = (layout.pixels as u64, layout.accumulation.buffers() as u32, layout.track_variance as u8);
+        if (pixels, buffers, header[12]) != expected {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "expected a film of {} pixels in {} buffers, got one of {} in {}", expected.0, expected.1, pixels, buffers,
+            )));
+        }
+        let mut film = Film::new(layout.pixels, layout.accumulation, layout.track_variance);
+        let floats = film.sums.len()*3 + film.splats.len()*3 + film.weights.len() + film.coverage.len() + film.squares.as_ref().map_or(0, |squares| squares.len()*3);
+        let mut bytes = vec![0; 4*(floats + film.counts.len() + 1)];
+        input.read_exact(&mut bytes)?;
+        if input.read(&mut [0])? != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "the film goes on past its samples"));
+        }
+        let words = |bytes: &[u8]| bytes.chunks(4).map(|word| [word[0], word[1], word[2], word[3]]).collect::<Vec<_>>().into_iter();
+        let (float_bytes, count_bytes) = bytes.split_at(4*floats);
+        let mut floats = words(float_bytes).map(f32::from_le_bytes);
+        let mut next = || floats.next().unwrap();
+        for sum in film.sums.iter_mut().chain(film.splats.iter_mut()) {
+            sum.add(Xyz::with_wp(next(), next(), next()));
+        }
+        for sum in film.weights.iter_mut().chain(film.coverage.iter_mut()).chain(film.squares.iter_mut().flatten().flatten()) {
+            sum.add(next());
+        }
+        for (count, read) in film.counts.iter_mut().chain(Some(&mut film.splat_passes)).zip(words(count_bytes).map(u32::from_le_bytes)) {
+            *count = read;
+        }
+        Ok(film)
+    }
+
+    /// Samples taken for a pixel.
+    pub fn samples(&self, pixel: usize) -> u32 {
+        let buffers = self.accumulation.buffers();
+        self.counts[pixel*buffers..(pixel + 1)*buffers].iter().sum()
+    }
+
+    /// The sum of the filter weights of the samples counting towards a pixel.
+    fn weight(&self, pixel: usize) -> f32 {
+        let buffers = self.accumulation.buffers();
+        self.weights[pixel*buffers..(pixel + 1)*buffers].iter().map(|weight| weight.sum()).sum()
+    }
+
+    fn mean(&self, pixel: usize) -> Rgb<E, f32> {
+        let buffers = self.accumulation.buffers();
+        let sum = self.sums[pixel*buffers..(pixel + 1)*buffers].iter()
+            .fold(Xyz::with_wp(0.0, 0.0, 0.0), |sum, buffer| sum + buffer.sum());
+        let weight = self.weight(pixel);
+        if weight > 0.0 { sum.into_rgb()/weight } else { Rgb::with_wp(0.0, 0.0, 0.0) }
+    }
+
+    /// The color of a pixel from the samples and splats so far.
+    pub fn color(&self, pixel: usize) -> Rgb<E, f32> {
+        let samples = self.sampled_color(pixel);
+        if self.splat_passes == 0 {
+            return samples;
+        }
+        samples + self.splats[pixel].sum().into_rgb()/self.splat_passes as f32
+    }
+
+    fn sampled_color(&self, pixel: usize) -> Rgb<E, f32> {
+        let buffers = self.accumulation.buffers();
+        if buffers == 1 {
+            return self.mean(pixel);
+        }
+        // Buffers that no pass reached yet have nothing to say
+        let means: Vec<Rgb<E, f32>> = (pixel*buffers..(pixel + 1)*buffers)
+            .filter(|&i| self.weights[i].sum() > 0.0)
+            .map(|i| self.sums[i].sum().into_rgb()/self.weights[i].sum())
+            .collect();
+        if means.is_empty() {
+            return Rgb::with_wp(0.0, 0.0, 0.0);
+        }
+        let median = |channel: fn(&Rgb<E, f32>) -> f32| {
+            let mut values: Vec<f32> = means.iter().map(channel).collect();
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+            let half = values.len()/2;
+            if values.len() % 2 == 0 { 0.5*(values[half - 1] + values[half]) } else { values[half] }
+        };
+        Rgb::with_wp(median(|col| col.red), median(|col| col.green), median(|col| col.blue))
+    }
+
+    /// The fraction of the samples of a pixel that hit something, its alpha when compositing.
+    /// The color is premultiplied with it, as samples of a transparent background have no color.
+    pub fn alpha(&self, pixel: usize) -> f32 {
+        let weight = self.weight(pixel);
+        if weight > 0.0 { self.coverage[pixel].sum()/weight } else { 0.0 }
+    }
+
+    /// Variance of the mean of all samples of a pixel, not of the individual samples, for a channel of red, green and blue.
+    /// Zero when the variance isn't tracked.
+    pub fn variance(&self, pixel: usize, channel: usize) -> f32 {
+        let squares = match self.squares {
+            Some(ref squares) => squares,
+            None => return 0.0,
+        };
+        let count = self.samples(pixel);
+        let mean = self.mean(pixel);
+        let mean = [mean.red, mean.green, mean.blue][channel];
+        let n = count as f32;
+        let weight = self.weight(pixel);
+        if !(weight > 0.0) {
+            return 0.0;
+        }
+        let sample_variance = (squares[pixel][channel].sum()/weight - mean*mean).max(0.0);
+        if count > 1 { sample_variance/(n - 1.0) } else { 0.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(value: f32) -> Xyz<E, f32> {
+        Rgb::with_wp(value, value, value).into_xyz()
+    }
+
+    #[test]
+    fn test_mean() {
+        let mut film = Film::new(2, Accumulation::Mean, true);
+        for pass in 0..4 {
+            film.add(pass, 0, grey(pass as f32), (pass % 2) as f32);
+        }
+        assert_eq!(film.samples(0), 4);
+        assert_eq!(film.samples(1), 0);
+        assert!((film.color(0).red - 1.5).abs() < 1e-4);
+        assert_eq!(film.color(1).green, 0.0);
+        assert_eq!(film.alpha(0), 0.5);
+        assert_eq!(film.alpha(1), 0.0);
+        // The sample variance of 0, 1, 2 and 3 over the four samples
+        assert!((film.variance(0, 1) - 5.0/12.0).abs() < 1e-3, "{}", film.variance(0, 1));
+    }
+
+    #[test]
+    fn test_median_of_means_ignores_a_firefly() {
+        let mut mean = Film::new(1, Accumulation::Mean, false);
+        let mut median = Film::new(1, Accumulation::MedianOfMeans { buffers: 5 }, false);
+        for pass in 0..100 {
+            let value = if pass == 42 { 1000.0 } else { 0.5 };
+            mean.add(pass, 0, grey(value), 1.0);
+            median.add(pass, 0, grey(value), 1.0);
+        }
+        assert_eq!(median.samples(0), 100);
+        assert!(mean.color(0).red > 10.0);
+        assert!((median.color(0).red - 0.5).abs() < 1e-3, "{:?}", median.color(0));
+    }
+
+    #[test]
+    fn test_median_of_means_before_every_buffer_has_samples() {
+        let mut film = Film::new(1, Accumulation::MedianOfMeans { buffers: 4 }, false);
+        assert_eq!(film.color(0).blue, 0.0);
+        film.add(0, 0, grey(1.0), 1.0);
+        film.add(1, 0, grey(2.0), 1.0);
+        assert!((film.color(0).blue - 1.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_filters() {
+        let filters = [
+            Filter::Box { radius: 0.5 },
+            Filter::Tent { radius: 1.0 },
+            Filter::Gaussian { radius: 1.5, alpha: 2.0 },
+            Filter::Mitchell { radius: 2.0, b: 1.0/3.0, c: 1.0/3.0 },
+            Filter::BlackmanHarris { radius: 2.0 },
+        ];
+        for filter in filters.iter() {
+            assert_eq!(filter.weight(vec2(filter.radius() + 0.01, 0.0)), 0.0, "{:?}", filter);
+            assert!(filter.weight(vec2(0.0, 0.0)) >= filter.weight(vec2(0.3, 0.2)) && filter.weight(vec2(0.3, 0.2)) > 0.0, "{:?}", filter);
+
+            // The weights are normalized, so an image of one color stays that color wherever the samples fall
+            let mut film = Film::new(9, Accumulation::Mean, true).with_filter(*filter, 3);
+            for pass in 0..50 {
+                for pixel in 0..9 {
+                    let offset = vec2((pass as f32*0.618 + pixel as f32*0.1) % 1.0 - 0.5, (pass as f32*0.382) % 1.0 - 0.5);
+                    film.add_at(pass, pixel, offset, grey(0.25), 1.0);
+                }
+            }
+            for pixel in 0..9 {
+                assert_eq!(film.samples(pixel), 50);
+                assert!((film.color(pixel).green - 0.25).abs() < 1e-4, "{:?} {:?}", filter, film.color(pixel));
+                assert!((film.alpha(pixel) - 1.0).abs() < 1e-4);
+                assert!(film.variance(pixel, 1) < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tent_spreads_a_sample() {
+        let mut film = Film::new(9, Accumulation::Mean, false).with_filter(Filter::Tent { radius: 1.0 }, 3);
+        film.add_at(0, 4, vec2(0.0, 0.0), grey(1.0), 1.0);
+        film.add_at(0, 3, vec2(0.25, 0.0), grey(2.0), 1.0);
+        // The center gets the first sample fully and the second at a quarter of its weight
+        assert!((film.color(4).red - (1.0 + 2.0*0.25)/1.25).abs() < 1e-4, "{:?}", film.color(4));
+        assert!((film.color(3).red - 2.0).abs() < 1e-4);
+        assert_eq!(film.color(0).red, 0.0);
+        assert_eq!(film.samples(4), 1);
+    }
+
+    #[test]
+    fn test_splats() {
+        let splats = Splats::new(2);
+        ::rayon::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|_| {
+                    for _ in 0..1000 {
+                        splats.splat(1, grey(0.001));
+                    }
+                });
+            }
+        });
+        assert!((splats.get(1).into_rgb().red - 4.0).abs() < 1e-3, "{:?}", splats.get(1));
+
+        let mut film = Film::new(2, Accumulation::Mean, false);
+        film.add(0, 1, grey(1.0), 1.0);
+        film.add_splats(&splats);
+        film.add_splats(&Splats::new(2));
+        // Splats are averaged over their passes and add to the samples
+        assert!((film.color(1).red - 3.0).abs() < 1e-3, "{:?}", film.color(1));
+        assert!((film.color(0).red).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_merge_and_round_trip() {
+        let new = || Film::new(4, Accumulation::MedianOfMeans { buffers: 3 }, true).with_filter(Filter::Tent { radius: 1.0 }, 2);
+        let mut whole = new();
+        let mut halves = [new(), new()];
+        for pass in 0..6 {
+            for pixel in 0..4 {
+                let (xyz, offset) = (grey((pass as usize + pixel) as f32), vec2(0.1, -0.2));
+                whole.add_at(pass, pixel, offset, xyz, (pixel % 2) as f32);
+                halves[pass as usize/3].add_at(pass, pixel, offset, xyz, (pixel % 2) as f32);
+            }
+        }
+        let splats = Splats::new(4);
+        splats.splat(2, grey(3.0));
+        whole.add_splats(&splats);
+        halves[1].add_splats(&splats);
+
+        let mut bytes = Vec::new();
+        halves[1].write_to(&mut bytes).unwrap();
+        let mut merged = halves[0].clone();
+        merged.merge(&Film::read_from(&mut &bytes[..], whole.layout()).unwrap()).unwrap();
+        for pixel in 0..4 {
+            assert_eq!(merged.samples(pixel), whole.samples(pixel));
+            assert!((merged.color(pixel).red - whole.color(pixel).red).abs() < 1e-4, "{:?} {:?}", merged.color(pixel), whole.color(pixel));
+            assert!((merged.alpha(pixel) - whole.alpha(pixel)).abs() < 1e-5);
+            assert!((merged.variance(pixel, 0) - whole.variance(pixel, 0)).abs() < 1e-4);
+        }
+
+        assert!(merged.merge(&Film::new(4, Accumulation::Mean, true)).is_err());
+        assert!(Film::read_from(&mut &bytes[..bytes.len() - 1], whole.layout()).is_err());
+        assert!(Film::read_from(&mut &[&bytes[..], &[0]].concat()[..], whole.layout()).is_err());
+        // The header is checked before the pixels it claims are allocated
+        let mut huge = bytes.clone();
+        huge[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(Film::read_from(&mut &huge[..], whole.layout()).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(Film::read_from(&mut &bytes[..], Layout { track_variance: false, ..whole.layout() }).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! Scenes and measurements shared by the tests of several modules.
+
+use euclid::*;
+use std::sync::Arc;
+
+use hitable::{HitRecord, Hitable};
+use hitable::sphere::Sphere;
+use material::Material;
+use ray::Ray;
+
+/// The ray from (0, 0, -2) straight at the origin.
+pub fn head_on(wavelength: f32) -> Ray {
+    Ray::new(point3(0.0, 0.0, -2.0), vec3(0.0, 0.0, 1.0), wavelength, 0.0)
+}
+
+/// `test` of where `ray` hits a unit sphere at the origin made of `material`.
+pub fn hit_unit_sphere<M, T, F>(material: &M, ray: Ray, test: F) -> T
+where M: Material + Clone + 'static, F: FnOnce(HitRecord) -> T
+{
+    let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, Arc::new(material.clone()));
+    test(sphere.hit(ray, 0.001, 10.0).expect("the ray misses the unit sphere"))
+}
+
+/// The mean attenuation of `samples` rays scattered by `material` at `rec`, with absorbed rays counting as 0.
+pub fn mean_attenuation<M: Material + ?Sized>(material: &M, ray: Ray, rec: HitRecord, samples: u32) -> f32 {
+    (0..samples).map(|_| material.scatter(ray, rec).reflection.map_or(0.0, |(attenuation, _)| attenuation)).sum::<f32>()/samples as f32
+}
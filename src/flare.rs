@@ -0,0 +1,211 @@
+//! Screen space lens flare, added to the finished image around its brightest spots.
+use palette::Rgb;
+use palette::white_point::E;
+
+/// A reflection between lens elements, showing up as a disc on the line from the source through the image center.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ghost {
+    /// Where along that line the ghost appears, `1.0` at the source, `0.0` at the center, negative on the other side.
+    pub position: f32,
+    /// Radius as a fraction of half the image height.
+    pub size: f32,
+    pub tint: Rgb<E, f32>,
+    /// Fraction of the source energy spread over the ghost.
+    pub intensity: f32,
+}
+
+/// The flare a camera adds, built from ghosts and a starburst of streaks through the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LensFlare {
+    /// Luminance above which pixels cast flares. Only the excess over it contributes.
+    pub threshold: f32,
+    pub ghosts: Vec<Ghost>,
+    /// Number of rays around the source.
+    pub streaks: u32,
+    /// Falloff length of the streaks as a fraction of half the image height.
+    pub streak_length: f32,
+    /// Fraction of the source energy spread over all streaks.
+    pub streak_intensity: f32,
+}
+
+/// Bright pixels are gathered in square cells of this size, each casting at most one flare.
+const CELL_SIZE: u32 = 16;
+/// Only the brightest cells cast flares, to bound the cost of very bright images.
+const MAX_SOURCES: usize = 32;
+
+impl Default for LensFlare {
+    fn default() -> LensFlare {
+        let ghost = |position, size, (r, g, b), intensity| Ghost { position, size, tint: Rgb::with_wp(r, g, b), intensity };
+        LensFlare {
+            threshold: 2.0,
+            ghosts: vec![
+                ghost(0.3, 0.04, (0.6, 1.0, 0.6), 0.03),
+                ghost(-0.4, 0.08, (0.4, 0.6, 1.0), 0.02),
+                ghost(-0.8, 0.15, (1.0, 0.7, 0.4), 0.01),
+                ghost(-1.3, 0.25, (0.7, 0.5, 1.0), 0.005),
+            ],
+            streaks: 6,
+            streak_length: 0.15,
+            streak_intensity: 0.01,
+        }
+    }
+}
+
+struct Source {
+    /// Energy weighted centroid in pixels.
+    x: f32,
+    y: f32,
+    energy: Rgb<E, f32>,
+    luminance: f32,
+}
+
+fn luminance(col: &Rgb<E, f32>) -> f32 {
+    0.2126*col.red + 0.7152*col.green + 0.0722*col.blue
+}
+
+fn tinted(energy: &Rgb<E, f32>, tint: &Rgb<E, f32>, intensity: f32) -> Rgb<E, f32> {
+    Rgb::with_wp(energy.red*tint.red, energy.green*tint.green, energy.blue*tint.blue)*intensity
+}
+
+impl LensFlare {
+    /// Add the flare to an image in row major order.
+    pub fn apply(&self, pixels: &mut [Rgb<E, f32>], width: u32, height: u32) {
+        assert_eq!(pixels.len(), (width*height) as usize);
+        let sources = self.find_sources(pixels, width, height);
+        let half_height = height as f32*0.5;
+        let (cx, cy) = (width as f32*0.5, height as f32*0.5);
+        for source in sources.iter() {
+            for ghost in self.ghosts.iter() {
+                let x = cx + (source.x - cx)*ghost.position;
+                let y = cy + (source.y - cy)*ghost.position;
+                let radius = ghost.size*half_height;
+                let color = tinted(&source.energy, &ghost.tint, ghost.intensity);
+                splat(pixels, width, height, (x, y), radius, |px, py| {
+                    // A disc with a soft rim
+                    let d = f32::hypot(px - x, py - y)/radius;
+                    if d >= 1.0 { 0.0 } else { f32::min(1.0, (1.0 - d)*5.0) }
+                }, color);
+            }
+            if self.streaks > 0 && self.streak_intensity > 0.0 {
+                let length = self.streak_length*half_height;
+                let streaks = self.streaks as f32;
+                let color = source.energy*self.streak_intensity;
+                // Beyond a few falloff lengths the streaks are invisible
+                splat(pixels, width, height, (source.x, source.y), 5.0*length, |px, py| {
+                    let (dx, dy) = (px - source.x, py - source.y);
+                    let distance = f32::hypot(dx, dy);
+                    // Distance to the nearest streak, with the streaks spread evenly around the source
+                    let sector = 2.0*std::f32::consts::PI/streaks;
+                    let angle = f32::atan2(dy, dx).rem_euclid(sector);
+                    let angle = f32::min(angle, sector - angle);
+                    let across = distance*angle.sin();
+                    if across > 0.75 { 0.0 } else { f32::exp(-distance/length) }
+                }, color);
+            }
+        }
+    }
+
+    /// Gather the excess energy over the threshold in cells and keep the brightest ones.
+    fn find_sources(&self, pixels: &[Rgb<E, f32>], width: u32, height: u32) -> Vec<Source> {
+        let cells_x = (width + CELL_SIZE - 1)/CELL_SIZE;
+        let cells_y = (height + CELL_SIZE - 1)/CELL_SIZE;
+        let mut sources: Vec<Source> = Vec::new();
+        for cell_y in 0..cells_y {
+            for cell_x in 0..cells_x {
+                let mut source = Source { x: 0.0, y: 0.0, energy: Rgb::with_wp(0.0, 0.0, 0.0), luminance: 0.0 };
+                for y in cell_y*CELL_SIZE..u32::min(height, (cell_y + 1)*CELL_SIZE) {
+                    for x in cell_x*CELL_SIZE..u32::min(width, (cell_x + 1)*CELL_SIZE) {
+                        let col = &pixels[(y*width + x) as usize];
+                        let lum = luminance(col);
+                        if lum > self.threshold {
+                            let excess = 1.0 - self.threshold/lum;
+                            source.energy = source.energy + *col*excess;
+                            source.luminance += lum*excess;
+                            source.x += (x as f32 + 0.5)*lum*excess;
+                            source.y += (y as f32 + 0.5)*lum*excess;
+                        }
+                    }
+                }
+                if source.luminance > 0.0 {
+                    source.x /= source.luminance;
+                    source.y /= source.luminance;
+                    sources.push(source);
+                }
+            }
+        }
+        sources.sort_by(|a, b| b.luminance.partial_cmp(&a.luminance).unwrap());
+        sources.truncate(MAX_SOURCES);
+        sources
+    }
+}
+
+/// Add `color` to the image within `reach` of `center`, distributed by the weights from `shape`.
+fn splat<F: Fn(f32, f32) -> f32>(pixels: &mut [Rgb<E, f32>], width: u32, height: u32, center: (f32, f32), reach: f32, shape: F, color: Rgb<E, f32>) {
+    let clip = |v: f32, max: u32| (v.max(0.0) as u32).min(max);
+    let (x0, x1) = (clip(center.0 - reach, width), clip(center.0 + reach + 1.0, width));
+    let (y0, y1) = (clip(center.1 - reach, height), clip(center.1 + reach + 1.0, height));
+    let mut weights = Vec::with_capacity(((x1 - x0)*(y1 - y0)) as usize);
+    let mut total = 0.0;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let w = shape(x as f32 + 0.5, y as f32 + 0.5);
+            total += w;
+            weights.push(w);
+        }
+    }
+    if total <= 0.0 {
+        return;
+    }
+    let mut weights = weights.into_iter();
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let w = weights.next().unwrap();
+            if w > 0.0 {
+                let pixel = &mut pixels[(y*width + x) as usize];
+                *pixel = *pixel + color*(w/total);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32) -> Vec<Rgb<E, f32>> {
+        vec![Rgb::with_wp(0.1, 0.1, 0.1); (width*height) as usize]
+    }
+
+    fn total(pixels: &[Rgb<E, f32>]) -> f32 {
+        pixels.iter().map(|col| col.red + col.green + col.blue).sum()
+    }
+
+    #[test]
+    fn test_dark_image_unchanged() {
+        let mut pixels = image(64, 48);
+        LensFlare::default().apply(&mut pixels, 64, 48);
+        assert_eq!(pixels, image(64, 48));
+    }
+
+    #[test]
+    fn test_ghost_mirrors_source() {
+        let (width, height) = (64, 48);
+        let mut pixels = image(width, height);
+        pixels[(10*width + 10) as usize] = Rgb::with_wp(1000.0, 1000.0, 1000.0);
+        let flare = LensFlare {
+            threshold: 1.0,
+            ghosts: vec![Ghost { position: -1.0, size: 0.1, tint: Rgb::with_wp(1.0, 1.0, 1.0), intensity: 0.1 }],
+            streaks: 0,
+            streak_length: 0.0,
+            streak_intensity: 0.0,
+        };
+        let before = total(&pixels);
+        flare.apply(&mut pixels, width, height);
+        // Mirrored through the center
+        assert!(pixels[(37*width + 53) as usize].red > 1.0);
+        assert_eq!(pixels[(37*width + 10) as usize].red, 0.1);
+        // The ghost carries the requested part of the energy above the threshold
+        let added = total(&pixels) - before;
+        assert!((added - 0.1*3.0*999.0).abs() < 1.0, "{}", added);
+    }
+}
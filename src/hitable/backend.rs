@@ -0,0 +1,57 @@
+//! Acceleration structures that find hits among many primitives, so they can be swapped for one another.
+
+use hitable::*;
+use hitable::bvh::BVH;
+
+/// Builds an acceleration structure over primitives of type `H`.
+/// Closest hits come from `Hitable::hit` and any hits from `Hitable::is_occluded`,
+/// while the primitives still produce the hit records, so shading is the same whichever backend found them.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # extern crate euclid;
+/// # use euclid::*;
+/// # use palette::Rgb;
+/// # use std::sync::Arc;
+/// # use rayer::hitable::*;
+/// # use rayer::hitable::backend::IntersectionBackend;
+/// # use rayer::hitable::bvh::BVH;
+/// # use rayer::hitable::sphere::Sphere;
+/// # use rayer::material::Lambertian;
+/// # use rayer::ray::Ray;
+/// # use rayer::texture::Texture;
+/// fn first_hit<B: IntersectionBackend<Sphere>>(spheres: Vec<Sphere>) -> Option<f32> {
+///     let backend = B::build(spheres);
+///     let ray = Ray::new(point3(0.0, 0.0, 5.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0);
+///     backend.hit(ray, 0.0, f32::MAX).map(|rec| rec.t)
+/// }
+///
+/// let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+/// let spheres = vec![
+///     Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture.clone()),
+///     Sphere::new(point3(0.0, 0.0, -3.0), 1.0, texture),
+/// ];
+/// assert_eq!(first_hit::<BVH<Sphere>>(spheres), Some(4.0));
+/// ```
+pub trait IntersectionBackend<H: Hitable>: Hitable + Sized {
+    /// Short name to report which backend is in use.
+    const NAME: &'static str;
+
+    fn build(items: Vec<H>) -> Self;
+
+    /// The primitives, in an order that stays fixed after building.
+    fn items(&self) -> &[H];
+}
+
+impl<H: Hitable> IntersectionBackend<H> for BVH<H> {
+    const NAME: &'static str = "bvh";
+
+    fn build(items: Vec<H>) -> BVH<H> {
+        BVH::initialize(items)
+    }
+
+    fn items(&self) -> &[H] {
+        BVH::items(self)
+    }
+}
@@ -3,32 +3,94 @@ use pdqselect::select_by;
 use decorum::Ordered;
 use std::ptr;
 use arrayvec::*;
+use rayon;
+use rayon::prelude::*;
+use trace;
 
+/// How the nodes of a `BVH` are split during construction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildStrategy {
+    /// Split at the median centroid along the longest axis. Fast to build.
+    Median,
+    /// Split where the binned surface area heuristic is lowest.
+    /// Slower to build, but faster to traverse when primitives are unevenly sized or distributed.
+    Sah,
+    /// Sort the centroids along a Morton curve and split where the codes first differ, in parallel.
+    /// The fastest to build, for rebuilding animated or edited scenes every frame, but slower to traverse than the
+    /// others as the splits follow a fixed grid rather than the primitives.
+    Lbvh,
+    /// Pick one of the above from statistics of the primitives.
+    Auto,
+}
+
+/// A binary tree of boxes around primitives.
+/// Any type can be held, built with `BVH::build_by` and intersected with `BVH::hit_by`,
+/// while `Hitable` primitives can use the `Hitable` implementation.
 #[derive(Debug)]
-pub struct BVH<H: Hitable> {
+pub struct BVH<H> {
     nodes: Vec<Node>,
     items: Vec<H>,
+    strategy: BuildStrategy,
 }
 
 #[derive(Debug)]
-struct Node {
-    bbox: AABB,
-    next: Next,
+pub(super) struct Node {
+    pub(super) bbox: AABB,
+    pub(super) next: Next,
 }
 
+/// The left child of a `Bin` node follows it directly, the right child after the whole left subtree.
 #[derive(Debug)]
-enum Next {
+pub(super) enum Next {
     Bin { left_length: usize },
     Tip { hitable: usize },
 }
 
+type Item = (Point3D<f32, UnknownUnit>, usize, AABB);
+
+#[derive(Clone, Copy)]
+enum Axis {
+    X, Y, Z
+}
+
+impl Axis {
+    fn coordinate(self, p: Point3D<f32, UnknownUnit>) -> f32 {
+        match self {
+            Axis::X => p.x,
+            Axis::Y => p.y,
+            Axis::Z => p.z,
+        }
+    }
+}
+
+/// Below this many primitives the build time doesn't matter and neither does the tree quality.
+const SMALL_SCENE: usize = 64;
+/// Above this many primitives the SAH build takes noticeably longer than rendering a preview.
+const LARGE_SCENE: usize = 2_000_000;
+const SAH_BINS: usize = 16;
+/// Bits of a Morton code, 10 for each axis.
+const MORTON_BITS: u32 = 30;
+/// Above this many primitives the two halves of an LBVH are built on different threads.
+const PARALLEL_BUILD: usize = 4096;
+
 impl<H: Hitable> BVH<H> {
+    /// Build a BVH, choosing the build strategy automatically.
     pub fn initialize(items: Vec<H>) -> BVH<H> {
-        #[derive(Clone, Copy)]
-        enum Axis {
-            X, Y, Z
-        }
-        fn go(items: &mut [(Point3D<f32, UnknownUnit>, usize, AABB)], res: &mut Vec<Node>) -> (AABB, usize) {
+        BVH::build(items, BuildStrategy::Auto)
+    }
+
+    pub fn build(items: Vec<H>, strategy: BuildStrategy) -> BVH<H> {
+        BVH::build_by(items, strategy, |item| (item.centroid(), item.bbox()))
+    }
+}
+
+impl<H> BVH<H> {
+    /// Build a BVH over primitives with the centroids and bounds `bounds` gives,
+    /// for primitives that can only be intersected together with data they share.
+    pub fn build_by<F>(items: Vec<H>, strategy: BuildStrategy, bounds: F) -> BVH<H>
+    where F: Fn(&H) -> (Point3D<f32, UnknownUnit>, AABB)
+    {
+        fn go(items: &mut [Item], strategy: BuildStrategy, res: &mut Vec<Node>) -> (AABB, usize) {
             match items {
                 &mut [] => { return (AABB::empty(), 0); },
                 &mut [ref item] => {
@@ -41,52 +103,16 @@ impl<H: Hitable> BVH<H> {
                 },
                 _ => {}
             }
-            // Find the "longest" axis
-            let mut min_x = items[0].0.x;
-            let mut min_y = items[0].0.y;
-            let mut min_z = items[0].0.z;
-            let mut max_x = items[0].0.x;
-            let mut max_y = items[0].0.y;
-            let mut max_z = items[0].0.z;
-            for &(centroid, _, _) in items[1..].iter() {
-                if min_x>centroid.x { min_x=centroid.x };
-                if min_y>centroid.y { min_y=centroid.y };
-                if min_z>centroid.z { min_z=centroid.z };
-                if max_x<centroid.x { max_x=centroid.x };
-                if max_y<centroid.y { max_y=centroid.y };
-                if max_z<centroid.z { max_z=centroid.z };
-            }
-            let width_x = max_x-min_x;
-            let width_y = max_y-min_y;
-            let width_z = max_z-min_z;
-            let mut direction = Axis::X;
-            if width_y>width_x {
-                direction = Axis::Y;
-            }
-            if width_z>f32::max(width_x, width_y) {
-                direction = Axis::Z;
-            }
-            let split_location = items.len()/2;
-            match direction {
-                Axis::X => select_by(
-                    items, split_location,
-                    | a, b | Ordered::from_inner(a.0.x).cmp(&Ordered::from_inner(b.0.x))
-                ),
-                Axis::Y => select_by(
-                    items, split_location,
-                    | a, b | Ordered::from_inner(a.0.y).cmp(&Ordered::from_inner(b.0.y))
-                ),
-                Axis::Z => select_by(
-                    items, split_location,
-                    | a, b | Ordered::from_inner(a.0.z).cmp(&Ordered::from_inner(b.0.z))
-                ),
-            };
+            let split_location = match strategy {
+                BuildStrategy::Sah => sah_split(items),
+                _ => None,
+            }.unwrap_or_else(|| median_split(items));
             let (mut left_items, mut right_items) = items.split_at_mut(split_location);
             let current_pos = res.len();
             // This spot will be filled later
             unsafe { res.set_len(current_pos+1) };
-            let (left_bbox, left_length) = go(&mut left_items, res);
-            let (right_bbox, right_length) = go(&mut right_items, res);
+            let (left_bbox, left_length) = go(&mut left_items, strategy, res);
+            let (right_bbox, right_length) = go(&mut right_items, strategy, res);
             let bbox = left_bbox.merge(right_bbox);
             unsafe {
                 ptr::write(
@@ -96,15 +122,306 @@ impl<H: Hitable> BVH<H> {
             };
             (bbox, 1+left_length+right_length)
         }
-        let mut item_stats: Vec<(Point3D<f32, UnknownUnit>, usize, AABB)> = items.iter().enumerate().map(|(i, x)| (x.centroid(), i, x.bbox())).collect();
-        let mut nodes: Vec<Node> = Vec::with_capacity(items.len()*2-1);
-        go(item_stats.as_mut_slice(), &mut nodes);
-        BVH { nodes, items }
+        let _span = trace::span("build", "BVH").with_arg("items", items.len() as u64);
+        let mut item_stats: Vec<Item> = items.iter().enumerate().map(|(i, x)| {
+            let (centroid, bbox) = bounds(x);
+            (centroid, i, bbox)
+        }).collect();
+        let strategy = match strategy {
+            BuildStrategy::Auto => choose_strategy(&item_stats),
+            strategy => strategy,
+        };
+        let nodes = if strategy == BuildStrategy::Lbvh {
+            lbvh(&item_stats)
+        } else {
+            let mut nodes: Vec<Node> = Vec::with_capacity((items.len()*2).saturating_sub(1));
+            go(item_stats.as_mut_slice(), strategy, &mut nodes);
+            nodes
+        };
+        BVH { nodes, items, strategy }
+    }
+
+    /// The strategy the BVH was built with, after resolving `BuildStrategy::Auto`.
+    pub fn strategy(&self) -> BuildStrategy {
+        self.strategy
+    }
+
+    /// Bytes taken by the nodes, not counting the primitives.
+    pub fn node_memory(&self) -> usize {
+        self.nodes.len()*std::mem::size_of::<Node>()
+    }
+
+    /// The primitives in the order they were given.
+    pub fn items(&self) -> &[H] {
+        &self.items
+    }
+
+    pub(super) fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub(super) fn into_parts(self) -> (Vec<Node>, Vec<H>) {
+        (self.nodes, self.items)
+    }
+}
+
+/// Pick a build strategy from the primitive count, the variation in primitive sizes
+/// and how much the primitives overlap.
+fn choose_strategy(items: &[Item]) -> BuildStrategy {
+    let n = items.len();
+    if n <= SMALL_SCENE || n >= LARGE_SCENE {
+        return BuildStrategy::Median;
+    }
+    let mut bounds = AABB::empty();
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    for &(_, _, bbox) in items {
+        let area = bbox.surface_area() as f64;
+        sum += area;
+        sum_sq += area*area;
+        bounds = bounds.merge(bbox);
+    }
+    let mean = sum / (n as f64);
+    let variation = f64::sqrt(f64::max(0.0, sum_sq / (n as f64) - mean*mean)) / mean;
+    // Roughly how many primitives a ray passes through the bounds of
+    let overlap = sum / (bounds.surface_area() as f64);
+    // Evenly sized, evenly spread primitives like tessellated meshes are split about as
+    // well by the median as by the SAH.
+    if variation > 1.0 || overlap > 4.0 {
+        BuildStrategy::Sah
+    } else {
+        BuildStrategy::Median
+    }
+}
+
+/// Split at the median centroid along the axis with the widest centroid spread.
+fn median_split(items: &mut [Item]) -> usize {
+    // Find the "longest" axis
+    let mut min_x = items[0].0.x;
+    let mut min_y = items[0].0.y;
+    let mut min_z = items[0].0.z;
+    let mut max_x = items[0].0.x;
+    let mut max_y = items[0].0.y;
+    let mut max_z = items[0].0.z;
+    for &(centroid, _, _) in items[1..].iter() {
+        if min_x>centroid.x { min_x=centroid.x };
+        if min_y>centroid.y { min_y=centroid.y };
+        if min_z>centroid.z { min_z=centroid.z };
+        if max_x<centroid.x { max_x=centroid.x };
+        if max_y<centroid.y { max_y=centroid.y };
+        if max_z<centroid.z { max_z=centroid.z };
+    }
+    let width_x = max_x-min_x;
+    let width_y = max_y-min_y;
+    let width_z = max_z-min_z;
+    let mut direction = Axis::X;
+    if width_y>width_x {
+        direction = Axis::Y;
+    }
+    if width_z>f32::max(width_x, width_y) {
+        direction = Axis::Z;
+    }
+    let split_location = items.len()/2;
+    select_by(
+        items, split_location,
+        | a, b | Ordered::from_inner(direction.coordinate(a.0)).cmp(&Ordered::from_inner(direction.coordinate(b.0)))
+    );
+    split_location
+}
+
+/// Split with the binned surface area heuristic.
+/// Returns `None` if the centroids can't be separated into two non-empty sets.
+fn sah_split(items: &mut [Item]) -> Option<usize> {
+    let centroid_bounds = items.iter()
+        .fold(AABB::empty(), |bounds, item| bounds.merge(AABB { bounds: [item.0, item.0] }));
+    let bin = |axis: Axis, p: Point3D<f32, UnknownUnit>| {
+        let low = axis.coordinate(centroid_bounds.bounds[0]);
+        let high = axis.coordinate(centroid_bounds.bounds[1]);
+        let scale = (SAH_BINS as f32) / (high - low);
+        (((axis.coordinate(p) - low)*scale) as usize).min(SAH_BINS-1)
+    };
+
+    let mut best: Option<(f32, Axis, usize)> = None;
+    for &axis in [Axis::X, Axis::Y, Axis::Z].iter() {
+        if !(axis.coordinate(centroid_bounds.bounds[1]) > axis.coordinate(centroid_bounds.bounds[0])) {
+            continue;
+        }
+        let mut bins = [(AABB::empty(), 0usize); SAH_BINS];
+        for &(centroid, _, bbox) in items.iter() {
+            let b = &mut bins[bin(axis, centroid)];
+            b.0 = b.0.merge(bbox);
+            b.1 += 1;
+        }
+        // Cost of everything right of each split
+        let mut right = [(0.0, 0usize); SAH_BINS];
+        let mut acc = (AABB::empty(), 0);
+        for i in (1..SAH_BINS).rev() {
+            acc = (acc.0.merge(bins[i].0), acc.1 + bins[i].1);
+            right[i] = (acc.0.surface_area(), acc.1);
+        }
+        let mut acc = (AABB::empty(), 0);
+        for i in 1..SAH_BINS {
+            acc = (acc.0.merge(bins[i-1].0), acc.1 + bins[i-1].1);
+            let (right_area, right_count) = right[i];
+            if acc.1 == 0 || right_count == 0 {
+                continue;
+            }
+            let cost = acc.0.surface_area()*(acc.1 as f32) + right_area*(right_count as f32);
+            if best.map_or(true, |(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    let (_, axis, split_bin) = best?;
+    let mut left = 0;
+    for i in 0..items.len() {
+        if bin(axis, items[i].0) < split_bin {
+            items.swap(left, i);
+            left += 1;
+        }
     }
+    Some(left)
+}
+
+/// Build the nodes of a linear BVH over `items`, after Karras, "Maximizing Parallelism in the Construction of BVHs,
+/// Octrees, and k-d Trees", 2012.
+fn lbvh(items: &[Item]) -> Vec<Node> {
+    if items.is_empty() {
+        return Vec::new();
+    }
+    let centroid_bounds = items.par_iter()
+        .map(|item| AABB { bounds: [item.0, item.0] })
+        .reduce(AABB::empty, AABB::merge);
+    let keys = items.par_iter().enumerate().map(|(i, item)| (morton_code(item.0, centroid_bounds), i)).collect();
+    let keys = radix_sort(keys);
+    let codes: Vec<u32> = keys.iter().map(|&(code, _)| code).collect();
+    let sorted: Vec<Item> = keys.iter().map(|&(_, i)| items[i]).collect();
+    // A tree over n primitives has 2n-1 nodes, so every subtree knows where its nodes go before it is built
+    let mut nodes: Vec<Node> = (0..2*items.len() - 1).map(|_| Node { bbox: AABB::empty(), next: Next::Tip { hitable: 0 } }).collect();
+    lbvh_nodes(&sorted, &codes, &mut nodes);
+    nodes
+}
+
+/// Fill `nodes` with the tree over `items`, sorted by their Morton `codes`, returning its bounds.
+fn lbvh_nodes(items: &[Item], codes: &[u32], nodes: &mut [Node]) -> AABB {
+    if let [item] = items {
+        nodes[0] = Node { bbox: item.2, next: Next::Tip { hitable: item.1 } };
+        return item.2;
+    }
+    let split = morton_split(codes);
+    let left_length = 2*split - 1;
+    let (node, children) = nodes.split_first_mut().unwrap();
+    let (left_nodes, right_nodes) = children.split_at_mut(left_length);
+    let (left_items, right_items) = items.split_at(split);
+    let (left_codes, right_codes) = codes.split_at(split);
+    let (left_bbox, right_bbox) = if items.len() > PARALLEL_BUILD {
+        rayon::join(|| lbvh_nodes(left_items, left_codes, left_nodes), || lbvh_nodes(right_items, right_codes, right_nodes))
+    } else {
+        (lbvh_nodes(left_items, left_codes, left_nodes), lbvh_nodes(right_items, right_codes, right_nodes))
+    };
+    let bbox = left_bbox.merge(right_bbox);
+    *node = Node { bbox, next: Next::Bin { left_length } };
+    bbox
+}
+
+/// Where the highest bit the sorted `codes` differ in turns on, or the middle if they are all the same.
+fn morton_split(codes: &[u32]) -> usize {
+    let (first, last) = (codes[0], codes[codes.len() - 1]);
+    if first == last {
+        return codes.len()/2;
+    }
+    // The codes agree on all the bits above it, so the ones without it come first
+    let bit = 1 << (31 - (first ^ last).leading_zeros());
+    codes.partition_point(|&code| code & bit == 0)
+}
+
+/// The position of `p` along a Morton curve through the grid of 1024 cells along every side of `bounds`.
+pub(super) fn morton_code(p: Point3D<f32, UnknownUnit>, bounds: AABB) -> u32 {
+    let [low, high] = bounds.bounds;
+    let cell = |axis: Axis| {
+        let width = axis.coordinate(high) - axis.coordinate(low);
+        if width > 0.0 { (((axis.coordinate(p) - axis.coordinate(low))/width*1024.0) as u32).min(1023) } else { 0 }
+    };
+    (expand_bits(cell(Axis::X)) << 2) | (expand_bits(cell(Axis::Y)) << 1) | expand_bits(cell(Axis::Z))
+}
+
+/// Spread the lowest 10 bits of `x` out to every third bit.
+fn expand_bits(x: u32) -> u32 {
+    let mut x = x & 0x3ff;
+    x = (x | x << 16) & 0x30000ff;
+    x = (x | x << 8) & 0x300f00f;
+    x = (x | x << 4) & 0x30c30c3;
+    x = (x | x << 2) & 0x9249249;
+    x
+}
+
+/// Sort `keys` by their codes 10 bits at a time, least significant first. Every pass counts the digits of chunks of
+/// the keys in parallel, which tells each chunk where its keys go, and then moves the chunks in parallel.
+/// Keys with the same code keep their order.
+fn radix_sort(mut keys: Vec<(u32, usize)>) -> Vec<(u32, usize)> {
+    const CHUNK: usize = 16384;
+    const DIGITS: usize = 1024;
+    /// Where the keys of a chunk go, each chunk writing to places no other chunk does.
+    struct Output(*mut (u32, usize));
+    unsafe impl Sync for Output {}
+
+    let mut sorted = keys.clone();
+    for shift in (0..MORTON_BITS).step_by(10) {
+        let digit = |key: (u32, usize)| (key.0 >> shift) as usize % DIGITS;
+        let counts: Vec<Vec<usize>> = keys.par_chunks(CHUNK).map(|chunk| {
+            let mut counts = vec![0; DIGITS];
+            for &key in chunk {
+                counts[digit(key)] += 1;
+            }
+            counts
+        }).collect();
+        // The keys with lower digits come first, then those of earlier chunks
+        let mut starts = vec![vec![0; DIGITS]; counts.len()];
+        let mut next = 0;
+        for d in 0..DIGITS {
+            for (chunk, chunk_counts) in counts.iter().enumerate() {
+                starts[chunk][d] = next;
+                next += chunk_counts[d];
+            }
+        }
+        let output = Output(sorted.as_mut_ptr());
+        keys.par_chunks(CHUNK).zip(starts.into_par_iter()).for_each(|(chunk, mut starts)| {
+            let output = &output;
+            for &key in chunk {
+                let place = &mut starts[digit(key)];
+                unsafe { *output.0.add(*place) = key };
+                *place += 1;
+            }
+        });
+        std::mem::swap(&mut keys, &mut sorted);
+    }
+    keys
 }
 
 impl<H: Hitable> Hitable for BVH<H> {
     fn bbox(&self) -> AABB {
+        self.bounds()
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.hit_by(r, t_min, t_max, |item, closest_so_far| item.hit(r, t_min, closest_so_far))
+    }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.is_occluded_by(r, t_min, t_max, |item| item.is_occluded(r, t_min, t_max))
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        for item in &self.items {
+            item.visit_textures(visit);
+        }
+    }
+}
+
+impl<H> BVH<H> {
+    /// The box around all primitives.
+    pub fn bounds(&self) -> AABB {
         let &BVH { ref nodes, .. } = self;
         match nodes.as_slice() {
             &[] => AABB::empty(),
@@ -112,19 +429,29 @@ impl<H: Hitable> Hitable for BVH<H> {
         }
     }
 
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let &BVH { ref nodes, ref items } = self;
-        // Avoid bounds checks later
-        if nodes.len()==0 {
+    /// The closest hit among the primitives whose boxes the ray passes through,
+    /// `hit` being called with a primitive and the distance of the closest hit found so far.
+    pub fn hit_by<'a, F>(&'a self, r: Ray, t_min: f32, t_max: f32, hit: F) -> Option<HitRecord<'a>>
+    where F: FnMut(&'a H, f32) -> Option<HitRecord<'a>>
+    {
+        if self.nodes.is_empty() {
             return None;
         }
+        self.hit_below(0, r, t_min, t_max, hit)
+    }
+
+    /// Like `hit_by`, among the primitives under node `node`, whose box the ray has entered already.
+    pub(super) fn hit_below<'a, F>(&'a self, node: usize, r: Ray, t_min: f32, t_max: f32, mut hit: F) -> Option<HitRecord<'a>>
+    where F: FnMut(&'a H, f32) -> Option<HitRecord<'a>>
+    {
+        let &BVH { ref nodes, ref items, .. } = self;
 
         let mut closest_match = None;
         let mut closest_so_far = t_max;
 
         // The nodes are arranged in a binary tree. This should be more than enough.
         let mut stack: ArrayVec<_, 64> = ArrayVec::new();
-        stack.push(0);
+        stack.push(node);
 
         let (origin_vec, inv_direction_vec, sign) = AABB::prepare_intersect(r);
 
@@ -153,7 +480,7 @@ impl<H: Hitable> Hitable for BVH<H> {
                     }
                 },
                 &Node {next: Next::Tip{hitable}, ..} => {
-                    let res = items[hitable].hit(r, t_min, closest_so_far);
+                    let res = hit(&items[hitable], closest_so_far);
                     match res {
                         None => (),
                         Some(hit) => {
@@ -167,22 +494,215 @@ impl<H: Hitable> Hitable for BVH<H> {
 
         closest_match
     }
+
+    /// Whether `is_occluded` holds for any of the primitives whose boxes the ray passes through.
+    pub fn is_occluded_by<F>(&self, r: Ray, t_min: f32, t_max: f32, is_occluded: F) -> bool
+    where F: FnMut(&H) -> bool
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        self.is_occluded_below(0, r, t_min, t_max, is_occluded)
+    }
+
+    /// Like `is_occluded_by`, among the primitives under node `node`, whose box the ray has entered already.
+    pub(super) fn is_occluded_below<F>(&self, node: usize, r: Ray, t_min: f32, t_max: f32, mut is_occluded: F) -> bool
+    where F: FnMut(&H) -> bool
+    {
+        let &BVH { ref nodes, ref items, .. } = self;
+        let mut stack: ArrayVec<_, 64> = ArrayVec::new();
+        stack.push(node);
+
+        let (origin_vec, inv_direction_vec, sign) = AABB::prepare_intersect(r);
+
+        // Any hit will do, so the children are visited in whatever order and the range never shrinks
+        while let Some(i) = stack.pop() {
+            match unsafe { nodes.get_unchecked(i) } {
+                &Node{ next: Next::Bin{left_length}, ..} => {
+                    let left_idx = i + 1;
+                    let right_idx = left_idx + left_length;
+                    let left = unsafe { nodes.get_unchecked(left_idx) };
+                    let right = unsafe { nodes.get_unchecked(right_idx) };
+                    let (left_hit, right_hit) = left.bbox.intersects_2(&right.bbox, sign, origin_vec, inv_direction_vec, t_min, t_max);
+                    if right_hit.is_some() {
+                        stack.push(right_idx);
+                    }
+                    if left_hit.is_some() {
+                        stack.push(left_idx);
+                    }
+                },
+                &Node {next: Next::Tip{hitable}, ..} => {
+                    if is_occluded(&items[hitable]) {
+                        return true;
+                    }
+                },
+            }
+        }
+
+        false
+    }
+
+    /// How many boxes of the tree the ray passes through between `t_min` and `t_max`, which is what a ray
+    /// to a hit at `t_max` costs to traverse, not counting the boxes of the primitives themselves.
+    pub fn boxes_entered(&self, r: Ray, t_min: f32, t_max: f32) -> usize {
+        let mut count = 0;
+        let mut stack: ArrayVec<_, 64> = ArrayVec::new();
+        if self.nodes.first().map_or(false, |root| root.bbox.intersects(r, t_min, t_max).is_some()) {
+            stack.push(0);
+        }
+        while let Some(i) = stack.pop() {
+            count += 1;
+            if let Next::Bin { left_length } = self.nodes[i].next {
+                for child in [i + 1, i + 1 + left_length] {
+                    if self.nodes[child].bbox.intersects(r, t_min, t_max).is_some() {
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+        count
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use random::*;
+    use pdqselect::select;
+    use palette::*;
+    use hitable::sphere::*;
+    use texture::*;
+    use material::*;
+    use std::sync::Arc;
+
+    fn spheres(n: usize) -> Vec<Sphere> {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let mut spheres: Vec<Sphere> = (0..n)
+            .map(|_| Sphere::new(rand_in_unit_sphere().to_point(), next_f32()/20.0, texture.clone()))
+            .collect();
+        spheres.push(Sphere::new(point3(0.0, -1000.0, 0.0), 998.0, texture));
+        spheres
+    }
+
+    #[test]
+    fn test_strategies_agree() {
+        let items = spheres(500);
+        let median = BVH::build(items.clone(), BuildStrategy::Median);
+        let sah = BVH::build(items.clone(), BuildStrategy::Sah);
+        let lbvh = BVH::build(items, BuildStrategy::Lbvh);
+        assert_eq!(lbvh.strategy(), BuildStrategy::Lbvh);
+        for _ in 0..1000 {
+            let origin = (rand_in_unit_sphere::<f32>()*3.0).to_point();
+            let ray = Ray::new(origin, rand_in_unit_sphere(), 500.0, 0.0);
+            let t_median = median.hit(ray, 0.001, f32::max_value()).map(|hit| hit.t);
+            let t_sah = sah.hit(ray, 0.001, f32::max_value()).map(|hit| hit.t);
+            let t_lbvh = lbvh.hit(ray, 0.001, f32::max_value()).map(|hit| hit.t);
+            assert_eq!(t_median, t_sah);
+            assert_eq!(t_median, t_lbvh);
+        }
+    }
+
+    #[test]
+    fn test_lbvh() {
+        assert_eq!(BVH::build(Vec::<Sphere>::new(), BuildStrategy::Lbvh).nodes.len(), 0);
+        assert_eq!(BVH::build(spheres(0), BuildStrategy::Lbvh).nodes.len(), 1);
+        // Large enough to be built in parallel, and with many spheres in the same cell of the grid
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let mut items = spheres(20000);
+        items.extend((0..100).map(|_| Sphere::new(point3(0.5, 0.5, 0.5), 0.01, texture.clone())));
+        let n = items.len();
+        let bvh = BVH::build(items, BuildStrategy::Lbvh);
+        assert_eq!(bvh.nodes.len(), 2*n - 1);
+        let mut tips: Vec<usize> = bvh.nodes.iter().filter_map(|node| match node.next { Next::Tip { hitable } => Some(hitable), _ => None }).collect();
+        tips.sort();
+        assert_eq!(tips, (0..n).collect::<Vec<_>>());
+        for (i, node) in bvh.nodes.iter().enumerate() {
+            if let Next::Bin { left_length } = node.next {
+                let (left, right) = (&bvh.nodes[i + 1], &bvh.nodes[i + 1 + left_length]);
+                assert_eq!(node.bbox, left.bbox.merge(right.bbox));
+            }
+        }
+    }
+
+    #[test]
+    fn test_morton() {
+        let bounds = AABB { bounds: [point3(0.0, 0.0, 0.0), point3(1.0, 1.0, 1.0)] };
+        assert_eq!(morton_code(point3(0.0, 0.0, 0.0), bounds), 0);
+        assert_eq!(morton_code(point3(1.0, 1.0, 1.0), bounds), (1 << MORTON_BITS) - 1);
+        // The top bits say which half of the bounds it lies in, along x, y and z
+        assert_eq!(morton_code(point3(0.5, 0.0, 0.0), bounds), 1 << 29);
+        assert_eq!(morton_code(point3(0.0, 0.0, 0.5), bounds), 1 << 27);
+        let keys: Vec<(u32, usize)> = (0..50000).map(|i| (rand::<u32>() >> 2, i)).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(radix_sort(keys), sorted);
+        assert_eq!(morton_split(&[0b000, 0b001, 0b010, 0b011, 0b110]), 4);
+        assert_eq!(morton_split(&[5, 5, 5]), 1);
+    }
+
+    #[test]
+    fn test_is_occluded_agrees_with_hit() {
+        let bvh = BVH::initialize(spheres(500));
+        for _ in 0..1000 {
+            let origin = (rand_in_unit_sphere::<f32>()*3.0).to_point();
+            let ray = Ray::new(origin, rand_in_unit_sphere(), 500.0, 0.0);
+            let t_max = next_f32()*4.0;
+            assert_eq!(bvh.is_occluded(ray, 0.001, t_max), bvh.hit(ray, 0.001, t_max).is_some());
+        }
+    }
+
+    #[test]
+    fn test_boxes_entered() {
+        let bvh = BVH::initialize(spheres(500));
+        let down = Ray::new(point3(0.0, 3.0, 0.0), vec3(0.0, -1.0, 0.0), 500.0, 0.0);
+        let up = Ray::new(point3(0.0, 3.0, 0.0), vec3(0.0, 1.0, 0.0), 500.0, 0.0);
+        assert_eq!(bvh.boxes_entered(up, 0.001, f32::max_value()), 0);
+        let to_ground = bvh.boxes_entered(down, 0.001, f32::max_value());
+        assert!(to_ground >= 2 && to_ground < bvh.nodes.len());
+        assert!(bvh.boxes_entered(down, 0.001, 1.0) < to_ground);
+    }
+
+    #[test]
+    fn test_auto_strategy() {
+        assert_eq!(BVH::initialize(spheres(10)).strategy(), BuildStrategy::Median);
+        // The huge ground sphere makes the sizes very uneven
+        assert_eq!(BVH::initialize(spheres(500)).strategy(), BuildStrategy::Sah);
+    }
+
+    #[test]
+    fn test_select() {
+        let n = 1000;
+        let split_location = n/2;
+        for _ in 0..100 {
+            let mut x: Vec<u64> = Vec::with_capacity(n);
+            for _ in 0..n {
+                x.push(rand());
+            }
+            select(&mut x, split_location);
+            let pivot = x[split_location];
+            for &i in &x[0..split_location] {
+                assert!(i<=pivot);
+            }
+            for &i in &x[split_location..] {
+                assert!(i>=pivot);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
     use super::*;
     use test::*;
     use palette::*;
     use random::*;
     use num_traits::Float;
     use hitable::sphere::*;
-    use pdqselect::select;
     use texture::*;
     use material::*;
     use std::sync::Arc;
 
-    fn bench_build(bench: &mut Bencher, n: u64) {
+    fn bench_build(bench: &mut Bencher, n: u64, strategy: BuildStrategy) {
         let mut hitables: Vec<Arc<dyn Hitable>> = black_box(Vec::new());
         let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
         for _ in 0..n {
@@ -193,17 +713,71 @@ mod tests {
             hitables.push(Arc::new(sphere));
         }
         bench.iter(|| {
-            black_box(BVH::initialize(hitables.clone()))
+            black_box(BVH::build(hitables.clone(), strategy))
         });
     }
 
     #[bench]
     fn bench_build_bvh_10000(bench: &mut Bencher) {
         let n = 10000;
-        bench_build(bench, n);
+        bench_build(bench, n, BuildStrategy::Median);
+    }
+
+    #[bench]
+    fn bench_build_sah_bvh_10000(bench: &mut Bencher) {
+        let n = 10000;
+        bench_build(bench, n, BuildStrategy::Sah);
+    }
+
+    #[bench]
+    fn bench_build_lbvh_10000(bench: &mut Bencher) {
+        let n = 10000;
+        bench_build(bench, n, BuildStrategy::Lbvh);
+    }
+
+    #[bench]
+    fn bench_build_bvh_1000000(bench: &mut Bencher) {
+        let n = 1000000;
+        bench_build(bench, n, BuildStrategy::Median);
+    }
+
+    #[bench]
+    fn bench_build_lbvh_1000000(bench: &mut Bencher) {
+        let n = 1000000;
+        bench_build(bench, n, BuildStrategy::Lbvh);
     }
 
     fn bench_intersect_bvh(bench: &mut Bencher, n: u64) {
+        let (bvh, ray) = random_bvh(n, BuildStrategy::Auto);
+        bench.iter(|| black_box(bvh.hit(ray, f32::epsilon(), f32::max_value())) );
+    }
+
+    /// Many rays through the same scene, as the strategies differ more on average than along any one ray.
+    fn bench_intersect_by_strategy(bench: &mut Bencher, strategy: BuildStrategy) {
+        let (bvh, _) = random_bvh(100000, strategy);
+        let rays: Vec<Ray> = (0..256).map(|_| {
+            let origin = (rand_in_unit_sphere::<f32>().normalize()*3.0).to_point();
+            Ray::new(origin, rand_in_unit_sphere::<f32>()*0.5 - origin.to_vector(), 500.0, 0.0)
+        }).collect();
+        bench.iter(|| for &ray in &rays { black_box(bvh.hit(ray, f32::epsilon(), f32::max_value())); });
+    }
+
+    #[bench]
+    fn bench_intersect_median_100000(bench: &mut Bencher) {
+        bench_intersect_by_strategy(bench, BuildStrategy::Median);
+    }
+
+    #[bench]
+    fn bench_intersect_sah_100000(bench: &mut Bencher) {
+        bench_intersect_by_strategy(bench, BuildStrategy::Sah);
+    }
+
+    #[bench]
+    fn bench_intersect_lbvh_100000(bench: &mut Bencher) {
+        bench_intersect_by_strategy(bench, BuildStrategy::Lbvh);
+    }
+
+    fn random_bvh(n: u64, strategy: BuildStrategy) -> (BVH<Sphere>, Ray) {
         let mut hitables: Vec<Sphere> = black_box(Vec::new());
         let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
         for _ in 0..n {
@@ -214,8 +788,7 @@ mod tests {
             hitables.push(sphere);
         }
         let ray = black_box(Ray::new(point3(-3.0, -2.0, -1.0), Vector3D::new(3.0, 2.0, 1.0), 500.0, 0.0));
-        let bvh = BVH::initialize(hitables);
-        bench.iter(|| black_box(bvh.hit(ray, f32::epsilon(), f32::max_value())) );
+        (BVH::build(hitables, strategy), ray)
     }
 
     #[bench]
@@ -236,23 +809,9 @@ mod tests {
         bench_intersect_bvh(bench, n)
     }
 
-    #[test]
-    fn test_select() {
-        let n = 1000;
-        let split_location = n/2;
-        for _ in 0..100 {
-            let mut x: Vec<u64> = Vec::with_capacity(n);
-            for _ in 0..n {
-                x.push(rand());
-            }
-            select(&mut x, split_location);
-            let pivot = x[split_location];
-            for &i in &x[0..split_location] {
-                assert!(i<=pivot);
-            }
-            for &i in &x[split_location..] {
-                assert!(i>=pivot);
-            }
-        }
+    #[bench]
+    fn bench_occluded_bvh_100000(bench: &mut Bencher) {
+        let (bvh, ray) = random_bvh(100000, BuildStrategy::Auto);
+        bench.iter(|| black_box(bvh.is_occluded(ray, f32::epsilon(), f32::max_value())) );
     }
 }
@@ -0,0 +1,471 @@
+//! Constructive solid geometry: solids combined by union, intersection and difference,
+//! like a lens cut from the intersection of two balls or a ball hollowed out by a smaller one.
+//!
+//! ```
+//! # extern crate rayer;
+//! # extern crate euclid;
+//! # extern crate palette;
+//! # use euclid::*;
+//! # use std::sync::Arc;
+//! # use palette::Rgb;
+//! # use rayer::hitable::Hitable;
+//! # use rayer::hitable::csg::*;
+//! # use rayer::hitable::sphere::Sphere;
+//! # use rayer::material::Dielectric;
+//! # use rayer::ray::Ray;
+//! # use rayer::texture::Texture;
+//! let glass: Arc<dyn Texture> = Arc::new(Dielectric::BK7);
+//! // A biconvex lens, 0.4 thick in the middle
+//! let lens = intersection(
+//!     Sphere::new(point3(0.0, 0.0, -1.8), 2.0, glass.clone()),
+//!     Sphere::new(point3(0.0, 0.0, 1.8), 2.0, glass),
+//! );
+//! let ray = Ray::new(point3(0.0, 0.0, 5.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0);
+//! assert!((lens.hit(ray, 0.0, 100.0).unwrap().t - 4.8).abs() < 1e-4);
+//! ```
+
+use bumpalo::Bump;
+use bumpalo::collections::Vec as ArenaVec;
+use euclid::*;
+use num_traits::{Float, FloatConst};
+use std::sync::Arc;
+
+use arena;
+use hitable::*;
+use texture::Texture;
+
+/// Where a ray enters a solid and where it leaves it again.
+pub type Interval<'a> = (HitRecord<'a>, HitRecord<'a>);
+
+/// An object with an inside, which can tell all the spans of a ray that lie within it.
+pub trait Solid: Hitable {
+    /// The spans of the whole line along `r` inside the solid, also those behind its origin,
+    /// sorted and not overlapping. The normals point out of the solid. They are only needed while a ray is traced,
+    /// so they go in `arena`, see the `arena` module.
+    fn intervals<'a, 'b>(&'a self, r: Ray, arena: &'b Bump) -> ArenaVec<'b, Interval<'a>> where 'a: 'b;
+}
+
+/// The first boundary of the intervals of `solid` between `t_min` and `t_max`.
+fn first_boundary<'a, S: Solid>(solid: &'a S, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'a>> {
+    arena::with(|arena| {
+        solid.intervals(r, arena).iter()
+            .flat_map(|&(enter, leave)| [enter, leave])
+            .find(|rec| rec.t > t_min && rec.t < t_max)
+    })
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Operation {
+    /// Inside either solid.
+    Union,
+    /// Inside both solids.
+    Intersection,
+    /// Inside the first solid but not the second.
+    Difference,
+}
+
+impl Operation {
+    fn contains(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            Operation::Union => in_a || in_b,
+            Operation::Intersection => in_a && in_b,
+            Operation::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// Two solids combined into one. Every part of the surface keeps the texture of the solid it came from,
+/// so the inside of a ball hollowed out by a difference has the texture of the ball cut away.
+#[derive(Debug, Clone)]
+pub struct Csg<A, B> {
+    a: A,
+    b: B,
+    operation: Operation,
+}
+
+impl<A: Solid, B: Solid> Csg<A, B> {
+    pub fn new(a: A, b: B, operation: Operation) -> Csg<A, B> {
+        Csg { a, b, operation }
+    }
+}
+
+pub fn union<A: Solid, B: Solid>(a: A, b: B) -> Csg<A, B> {
+    Csg::new(a, b, Operation::Union)
+}
+
+pub fn intersection<A: Solid, B: Solid>(a: A, b: B) -> Csg<A, B> {
+    Csg::new(a, b, Operation::Intersection)
+}
+
+/// `a` with `b` cut out of it.
+pub fn difference<A: Solid, B: Solid>(a: A, b: B) -> Csg<A, B> {
+    Csg::new(a, b, Operation::Difference)
+}
+
+impl<A: Solid, B: Solid> Solid for Csg<A, B> {
+    fn intervals<'a, 'b>(&'a self, r: Ray, arena: &'b Bump) -> ArenaVec<'b, Interval<'a>> where 'a: 'b {
+        // Walk the boundaries of both solids in order, keeping track of which ones the line is inside
+        let mut boundaries: ArenaVec<(HitRecord, usize, bool)> = ArenaVec::new_in(arena);
+        for (solid, intervals) in [self.a.intervals(r, arena), self.b.intervals(r, arena)].iter().enumerate() {
+            for &(enter, leave) in intervals.iter() {
+                boundaries.push((enter, solid, true));
+                boundaries.push((leave, solid, false));
+            }
+        }
+        boundaries.sort_by(|a, b| a.0.t.partial_cmp(&b.0.t).unwrap_or(::std::cmp::Ordering::Equal));
+        let mut inside = [false, false];
+        let mut start = None;
+        let mut intervals = ArenaVec::new_in(arena);
+        for (mut rec, solid, entering) in boundaries {
+            let was_inside = self.operation.contains(inside[0], inside[1]);
+            inside[solid] = entering;
+            if self.operation.contains(inside[0], inside[1]) == was_inside {
+                continue;
+            }
+            // The surface cut out by a difference faces into what is left
+            if self.operation == Operation::Difference && solid == 1 {
+                rec.normal = -rec.normal;
+                rec.geometric_normal = -rec.geometric_normal;
+                rec.front_face = !rec.front_face;
+            }
+            match start.take() {
+                None => start = Some(rec),
+                Some(enter) => intervals.push((enter, rec)),
+            }
+        }
+        intervals
+    }
+}
+
+impl<A: Solid, B: Solid> Hitable for Csg<A, B> {
+    /// The bounds of the solids combined, which can be larger than the result.
+    fn bbox(&self) -> AABB {
+        match self.operation {
+            Operation::Union => self.a.bbox().merge(self.b.bbox()),
+            Operation::Intersection => {
+                let (AABB { bounds: [low_a, high_a] }, AABB { bounds: [low_b, high_b] }) = (self.a.bbox(), self.b.bbox());
+                AABB { bounds: [low_a.max(low_b), high_a.min(high_b)] }
+            },
+            Operation::Difference => self.a.bbox(),
+        }
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if self.bbox().intersects(r, t_min, t_max).is_none() {
+            return None;
+        }
+        first_boundary(self, r, t_min, t_max)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.a.visit_textures(visit);
+        self.b.visit_textures(visit);
+    }
+}
+
+/// A solid chosen at run time, like the ones a scene description names.
+impl Solid for Box<dyn Solid> {
+    fn intervals<'a, 'b>(&'a self, r: Ray, arena: &'b Bump) -> ArenaVec<'b, Interval<'a>> where 'a: 'b {
+        self.as_ref().intervals(r, arena)
+    }
+}
+
+impl Hitable for Box<dyn Solid> {
+    fn centroid(&self) -> Point3D<f32, UnknownUnit> {
+        self.as_ref().centroid()
+    }
+    fn bbox(&self) -> AABB {
+        self.as_ref().bbox()
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(r, t_min, t_max)
+    }
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.as_ref().is_occluded(r, t_min, t_max)
+    }
+    fn surface_area(&self) -> f32 {
+        self.as_ref().surface_area()
+    }
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.as_ref().sample_surface(u)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.as_ref().visit_textures(visit)
+    }
+}
+
+/// A box with faces along the axes, solid unlike `triangle::axis_aligned_cuboid`.
+#[derive(Debug, Clone)]
+pub struct Cuboid {
+    bounds: AABB,
+    texture: Arc<dyn Texture>,
+}
+
+impl Cuboid {
+    pub fn new(low: Point3D<f32, UnknownUnit>, high: Point3D<f32, UnknownUnit>, texture: Arc<dyn Texture>) -> Cuboid {
+        Cuboid { bounds: AABB { bounds: [low.min(high), low.max(high)] }, texture }
+    }
+
+    /// The face crossed at `t` on the given axis, the texture coordinates spanning each face.
+    fn record(&self, r: Ray, t: f32, axis: usize, high: bool) -> HitRecord {
+        let [low_corner, high_corner] = self.bounds.bounds;
+        // Exactly on the face
+        let mut p = r.point_at_parameter(t).to_array();
+        p[axis] = if high { high_corner.to_array()[axis] } else { low_corner.to_array()[axis] };
+        let p = Point3D::from(p);
+        let mut normal = [0.0; 3];
+        normal[axis] = if high { 1.0 } else { -1.0 };
+        let normal = Vector3D::from(normal);
+        let relative = |i: usize| (p.to_array()[i] - low_corner.to_array()[i])/(high_corner.to_array()[i] - low_corner.to_array()[i]);
+        let uv = vec2(relative((axis + 1) % 3), relative((axis + 2) % 3));
+        let front_face = r.direction.dot(normal) < 0.0;
+        HitRecord { t, p, uv, normal, geometric_normal: normal, front_face, texture: self.texture.as_ref(), shading_rate: None, object_id: None, tangent: None, edge_distance: None, error: 0.0 }
+    }
+}
+
+impl Solid for Cuboid {
+    fn intervals<'a, 'b>(&'a self, r: Ray, arena: &'b Bump) -> ArenaVec<'b, Interval<'a>> where 'a: 'b {
+        let origin = r.origin.to_array();
+        let direction = r.direction.to_array();
+        let [low, high] = [self.bounds.bounds[0].to_array(), self.bounds.bounds[1].to_array()];
+        // The latest entry and earliest exit over the slabs, with the axis and side they happen on
+        let mut enter = (f32::neg_infinity(), 0, false);
+        let mut leave = (f32::infinity(), 0, false);
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if origin[axis] < low[axis] || origin[axis] > high[axis] {
+                    return ArenaVec::new_in(arena);
+                }
+                continue;
+            }
+            let t_low = (low[axis] - origin[axis])/direction[axis];
+            let t_high = (high[axis] - origin[axis])/direction[axis];
+            let (near, far) = if t_low < t_high { ((t_low, axis, false), (t_high, axis, true)) } else { ((t_high, axis, true), (t_low, axis, false)) };
+            if near.0 > enter.0 {
+                enter = near;
+            }
+            if far.0 < leave.0 {
+                leave = far;
+            }
+        }
+        if enter.0 > leave.0 || !enter.0.is_finite() || !leave.0.is_finite() {
+            return ArenaVec::new_in(arena);
+        }
+        bumpalo::vec![in arena; (self.record(r, enter.0, enter.1, enter.2), self.record(r, leave.0, leave.1, leave.2))]
+    }
+}
+
+impl Hitable for Cuboid {
+    fn bbox(&self) -> AABB {
+        self.bounds
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        first_boundary(self, r, t_min, t_max)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        visit(self.texture.as_ref());
+    }
+}
+
+/// A round cylinder closed by flat caps, along the axis from `base` to `top`.
+#[derive(Debug, Clone)]
+pub struct Cylinder {
+    base: Point3D<f32, UnknownUnit>,
+    /// Unit vector from the base to the top.
+    axis: Vector3D<f32, UnknownUnit>,
+    height: f32,
+    radius: f32,
+    texture: Arc<dyn Texture>,
+}
+
+impl Cylinder {
+    pub fn new(base: Point3D<f32, UnknownUnit>, top: Point3D<f32, UnknownUnit>, radius: f32, texture: Arc<dyn Texture>) -> Cylinder {
+        let height = (top - base).length();
+        Cylinder { base, axis: (top - base)/height, height, radius: radius.abs(), texture }
+    }
+
+    /// Around the side `u` goes once round and `v` from base to top, on the caps `uv` is the position across the cap.
+    fn record(&self, r: Ray, t: f32, cap: Option<bool>) -> HitRecord {
+        let p = r.point_at_parameter(t);
+        let along = (p - self.base).dot(self.axis);
+        let radial = (p - self.base) - self.axis*along;
+        // Back onto the side or cap, off by the rounding of the cylinder rather than of the way along the ray
+        let (along, radial) = match cap {
+            Some(top) => (if top { self.height } else { 0.0 }, radial),
+            None => (along, radial*(self.radius/radial.length())),
+        };
+        let p = self.base + self.axis*along + radial;
+        let error = rounding_error(magnitude(self.base) + self.height + self.radius);
+        // Any two directions across the axis, for the angle around it
+        let across = if self.axis.x.abs() < 0.5 { vec3(1.0, 0.0, 0.0) } else { vec3(0.0, 1.0, 0.0) };
+        let side = self.axis.cross(across).normalize();
+        let up = side.cross(self.axis);
+        let (normal, uv) = match cap {
+            Some(top) => (
+                if top { self.axis } else { -self.axis },
+                vec2(0.5 + 0.5*radial.dot(side)/self.radius, 0.5 + 0.5*radial.dot(up)/self.radius),
+            ),
+            None => (
+                radial/self.radius,
+                vec2(0.5 + f32::atan2(radial.dot(up), radial.dot(side))/(2.0*f32::PI()), along/self.height),
+            ),
+        };
+        let front_face = r.direction.dot(normal) < 0.0;
+        HitRecord { t, p, uv, normal, geometric_normal: normal, front_face, texture: self.texture.as_ref(), shading_rate: None, object_id: None, tangent: None, edge_distance: None, error }
+    }
+}
+
+impl Solid for Cylinder {
+    fn intervals<'a, 'b>(&'a self, r: Ray, arena: &'b Bump) -> ArenaVec<'b, Interval<'a>> where 'a: 'b {
+        let oc = r.origin - self.base;
+        // Between the caps
+        let d_along = r.direction.dot(self.axis);
+        let o_along = oc.dot(self.axis);
+        let (mut enter, mut leave) = if d_along == 0.0 {
+            if o_along < 0.0 || o_along > self.height {
+                return ArenaVec::new_in(arena);
+            }
+            ((f32::neg_infinity(), None), (f32::infinity(), None))
+        } else {
+            let t_base = -o_along/d_along;
+            let t_top = (self.height - o_along)/d_along;
+            if t_base < t_top { ((t_base, Some(false)), (t_top, Some(true))) } else { ((t_top, Some(true)), (t_base, Some(false))) }
+        };
+        // Within the radius, unbounded along a ray parallel to the axis
+        let d = r.direction - self.axis*d_along;
+        let o = oc - self.axis*o_along;
+        let a = d.dot(d);
+        let b = o.dot(d);
+        let c = o.dot(o) - self.radius*self.radius;
+        if a > 0.0 {
+            let discriminant = b*b - a*c;
+            if discriminant <= 0.0 {
+                return ArenaVec::new_in(arena);
+            }
+            let (near, far) = ((-b - discriminant.sqrt())/a, (-b + discriminant.sqrt())/a);
+            if near > enter.0 {
+                enter = (near, None);
+            }
+            if far < leave.0 {
+                leave = (far, None);
+            }
+        } else if c > 0.0 {
+            return ArenaVec::new_in(arena);
+        }
+        if enter.0 > leave.0 || !enter.0.is_finite() || !leave.0.is_finite() {
+            return ArenaVec::new_in(arena);
+        }
+        bumpalo::vec![in arena; (self.record(r, enter.0, enter.1), self.record(r, leave.0, leave.1))]
+    }
+}
+
+impl Hitable for Cylinder {
+    fn bbox(&self) -> AABB {
+        // The caps are discs, which reach less far along the axes the axis leans towards
+        let extent = |axis: f32| self.radius*f32::sqrt((1.0 - axis*axis).max(0.0));
+        let reach = vec3(extent(self.axis.x), extent(self.axis.y), extent(self.axis.z));
+        let top = self.base + self.axis*self.height;
+        AABB { bounds: [self.base.min(top) - reach, self.base.max(top) + reach] }
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        first_boundary(self, r, t_min, t_max)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        visit(self.texture.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use hitable::sphere::Sphere;
+    use material::Lambertian;
+
+    fn grey() -> Arc<dyn Texture> {
+        Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)))
+    }
+
+    fn along_x(x: f32) -> Ray {
+        Ray::new(point3(x, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 550.0, 0.0)
+    }
+
+    fn spans<S: Solid>(solid: &S, r: Ray) -> Vec<(f32, f32)> {
+        solid.intervals(r, &Bump::new()).iter().map(|&(enter, leave)| (enter.t, leave.t)).collect()
+    }
+
+    #[test]
+    fn test_operations() {
+        let a = || Sphere::new(point3(0.0, 0.0, 0.0), 1.0, grey());
+        let b = || Sphere::new(point3(1.5, 0.0, 0.0), 1.0, grey());
+        let ray = along_x(-5.0);
+        assert_eq!(spans(&union(a(), b()), ray), vec![(4.0, 7.5)]);
+        assert_eq!(spans(&intersection(a(), b()), ray), vec![(5.5, 6.0)]);
+        assert_eq!(spans(&difference(a(), b()), ray), vec![(4.0, 5.5)]);
+        let far = Sphere::new(point3(5.0, 0.0, 0.0), 1.0, grey());
+        assert_eq!(spans(&union(a(), far), ray), vec![(4.0, 6.0), (9.0, 11.0)]);
+    }
+
+    #[test]
+    fn test_hollow_sphere() {
+        let shell = difference(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, grey()), Sphere::new(point3(0.0, 0.0, 0.0), 0.5, grey()));
+        let ray = along_x(-5.0);
+        assert_eq!(spans(&shell, ray), vec![(4.0, 4.5), (5.5, 6.0)]);
+        // The inner surface faces into the hollow, so the ray leaving the shell sees its back
+        let inner = shell.hit(ray, 4.1, 100.0).unwrap();
+        assert_eq!(inner.t, 4.5);
+        assert_eq!(inner.normal, vec3(1.0, 0.0, 0.0));
+        assert!(!inner.front_face);
+        let across = shell.hit(ray, 4.6, 100.0).unwrap();
+        assert_eq!(across.normal, vec3(-1.0, 0.0, 0.0));
+        assert!(across.front_face);
+        // Starting inside the hollow
+        assert_eq!(shell.hit(along_x(0.0), 0.0, 100.0).unwrap().t, 0.5);
+    }
+
+    #[test]
+    fn test_cuboid() {
+        let cuboid = Cuboid::new(point3(-1.0, -1.0, -1.0), point3(1.0, 2.0, 1.0), grey());
+        let rec = cuboid.hit(along_x(-5.0), 0.0, 100.0).unwrap();
+        assert_eq!(rec.t, 4.0);
+        assert_eq!(rec.normal, vec3(-1.0, 0.0, 0.0));
+        assert!((rec.uv - vec2(1.0/3.0, 0.5)).length() < 1e-5, "{:?}", rec.uv);
+        let inside = cuboid.hit(along_x(0.0), 0.0, 100.0).unwrap();
+        assert_eq!((inside.t, inside.normal, inside.front_face), (1.0, vec3(1.0, 0.0, 0.0), false));
+        let down = Ray::new(point3(0.5, 5.0, 0.5), vec3(0.0, -1.0, 0.0), 550.0, 0.0);
+        assert_eq!(spans(&cuboid, down), vec![(3.0, 6.0)]);
+        assert!(cuboid.hit(Ray::new(point3(0.0, 3.0, 0.0), vec3(1.0, 0.0, 0.0), 550.0, 0.0), 0.0, 100.0).is_none());
+    }
+
+    #[test]
+    fn test_cylinder() {
+        let cylinder = Cylinder::new(point3(0.0, 0.0, 0.0), point3(0.0, 2.0, 0.0), 1.0, grey());
+        assert_eq!(cylinder.bbox(), AABB { bounds: [point3(-1.0, 0.0, -1.0), point3(1.0, 2.0, 1.0)] });
+        let side = cylinder.hit(Ray::new(point3(-5.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), 550.0, 0.0), 0.0, 100.0).unwrap();
+        assert!((side.t - 4.0).abs() < 1e-5);
+        assert!((side.normal - vec3(-1.0, 0.0, 0.0)).length() < 1e-5);
+        assert!((side.uv.y - 0.5).abs() < 1e-5);
+        let cap = cylinder.hit(Ray::new(point3(0.5, 5.0, 0.0), vec3(0.0, -1.0, 0.0), 550.0, 0.0), 0.0, 100.0).unwrap();
+        assert_eq!((cap.t, cap.normal), (3.0, vec3(0.0, 1.0, 0.0)));
+        // Slanted through a cap and out the side
+        let slanted = Ray::new(point3(0.0, 3.0, 0.0), vec3(0.5, -1.0, 0.0), 550.0, 0.0);
+        let arena = Bump::new();
+        let intervals = cylinder.intervals(slanted, &arena);
+        assert_eq!(spans(&cylinder, slanted), vec![(1.0, 2.0)]);
+        assert_eq!(intervals[0].0.normal, vec3(0.0, 1.0, 0.0));
+        assert!((intervals[0].1.normal - vec3(1.0, 0.0, 0.0)).length() < 1e-5);
+        assert!(cylinder.hit(Ray::new(point3(2.0, -1.0, 0.0), vec3(0.0, 1.0, 0.0), 550.0, 0.0), 0.0, 100.0).is_none());
+    }
+
+    #[test]
+    fn test_drill_through_a_box() {
+        let drilled = difference(
+            Cuboid::new(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0), grey()),
+            Cylinder::new(point3(-2.0, 0.0, 0.0), point3(2.0, 0.0, 0.0), 0.5, grey()),
+        );
+        // Straight down the bore nothing is hit, beside it the box is
+        assert!(drilled.hit(along_x(-5.0), 0.0, 100.0).is_none());
+        let beside = Ray::new(point3(-5.0, 0.75, 0.0), vec3(1.0, 0.0, 0.0), 550.0, 0.0);
+        assert_eq!(drilled.hit(beside, 0.0, 100.0).unwrap().t, 4.0);
+        let down = Ray::new(point3(0.0, 5.0, 0.0), vec3(0.0, -1.0, 0.0), 550.0, 0.0);
+        assert_eq!(spans(&drilled, down), vec![(4.0, 4.5), (5.5, 6.0)]);
+    }
+}
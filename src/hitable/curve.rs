@@ -0,0 +1,396 @@
+//! Thin curves swept with a varying radius, for hair, fur and wires.
+//!
+//! Strands are given by control points with a radius each, as cubic Bézier curves or as Catmull-Rom splines
+//! through the points, and held in a BVH over their segments.
+//!
+//! ```
+//! # extern crate rayer;
+//! # extern crate palette;
+//! # extern crate euclid;
+//! # use euclid::*;
+//! # use palette::Rgb;
+//! # use std::sync::Arc;
+//! # use rayer::hitable::Hitable;
+//! # use rayer::hitable::curve::*;
+//! # use rayer::material::hair::Hair;
+//! # use rayer::ray::Ray;
+//! # use rayer::texture::Texture;
+//! let hair: Arc<dyn Texture> = Arc::new(Hair::new(Rgb::with_wp(0.6, 0.4, 0.2), 0.1, 0.05));
+//! // A strand hanging down and curling towards +z
+//! let strand = vec![
+//!     (point3(0.0, 2.0, 0.0), 0.02),
+//!     (point3(0.0, 1.5, 0.1), 0.015),
+//!     (point3(0.0, 1.0, 0.4), 0.01),
+//!     (point3(0.0, 0.8, 0.8), 0.005),
+//! ];
+//! let curves = Curves::catmull_rom(&[strand], CurveKind::Round, hair);
+//! let ray = Ray::new(point3(-1.0, 1.5, 0.1), vec3(1.0, 0.0, 0.0), 550.0, 0.0);
+//! let rec = curves.hit(ray, 0.0, 10.0).unwrap();
+//! assert!((rec.p.x).abs() < 1e-3);
+//! assert!(rec.tangent.unwrap().y < 0.0);
+//! ```
+
+use euclid::*;
+use std::sync::Arc;
+
+use hitable::*;
+use hitable::bvh::BVH;
+use texture::Texture;
+
+/// How the width of a curve is intersected and shaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveKind {
+    /// A ribbon turned to face every ray, shaded flat. Cheap, and enough for hair seen from afar.
+    Flat,
+    /// The same ribbon with its normal bent across the width as on a tube, for wires and close ups.
+    Round,
+}
+
+/// A point a strand is built from, with the radius of the strand there.
+pub type ControlPoint = (Point3D<f32, UnknownUnit>, f32);
+
+/// Segments are split until they are at most this many times as long as they are wide, so their boxes stay tight.
+const SEGMENT_ASPECT: f32 = 8.0;
+const MAX_SPLITS: usize = 16;
+/// Subdividing stops once the curve is straight to within this fraction of its width.
+const FLATNESS: f32 = 0.05;
+const MAX_DEPTH: i32 = 10;
+
+/// A cubic Bézier piece of a strand.
+#[derive(Debug, Clone)]
+pub struct CurveSegment {
+    points: [Point3D<f32, UnknownUnit>; 4],
+    radii: [f32; 4],
+    /// The part of the strand covered, for the texture coordinate along it.
+    span: (f32, f32),
+    kind: CurveKind,
+    texture: Arc<dyn Texture>,
+}
+
+fn split<T: Copy, F: Fn(T, T, f32) -> T>(c: [T; 4], t: f32, lerp: F) -> ([T; 4], [T; 4]) {
+    let (c01, c12, c23) = (lerp(c[0], c[1], t), lerp(c[1], c[2], t), lerp(c[2], c[3], t));
+    let (c012, c123) = (lerp(c01, c12, t), lerp(c12, c23, t));
+    let c0123 = lerp(c012, c123, t);
+    ([c[0], c01, c012, c0123], [c0123, c123, c23, c[3]])
+}
+
+/// The part of a Bézier curve between the parameters `a` and `b`.
+fn sub_curve<T: Copy, F: Fn(T, T, f32) -> T + Copy>(c: [T; 4], a: f32, b: f32, lerp: F) -> [T; 4] {
+    let (_, right) = split(c, a, lerp);
+    if a >= 1.0 {
+        return right;
+    }
+    split(right, (b - a)/(1.0 - a), lerp).0
+}
+
+fn lerp_point(a: Point3D<f32, UnknownUnit>, b: Point3D<f32, UnknownUnit>, t: f32) -> Point3D<f32, UnknownUnit> {
+    a.lerp(b, t)
+}
+
+fn lerp_radius(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a)*t
+}
+
+fn evaluate(c: &[Point3D<f32, UnknownUnit>; 4], t: f32) -> Point3D<f32, UnknownUnit> {
+    split(*c, t, lerp_point).0[3]
+}
+
+fn derivative(c: &[Point3D<f32, UnknownUnit>; 4], t: f32) -> Vector3D<f32, UnknownUnit> {
+    let s = 1.0 - t;
+    ((c[1] - c[0])*(s*s) + (c[2] - c[1])*(2.0*s*t) + (c[3] - c[2])*(t*t))*3.0
+}
+
+impl CurveSegment {
+    /// Split a Bézier curve into segments covering `span` of its strand.
+    fn pieces(
+        points: [Point3D<f32, UnknownUnit>; 4],
+        radii: [f32; 4],
+        span: (f32, f32),
+        kind: CurveKind,
+        texture: &Arc<dyn Texture>,
+    ) -> Vec<CurveSegment> {
+        let length = (points[1] - points[0]).length() + (points[2] - points[1]).length() + (points[3] - points[2]).length();
+        let width = 2.0*radii.iter().cloned().fold(0.0, f32::max);
+        let count = if width > 0.0 {
+            ((length/(width*SEGMENT_ASPECT)).ceil() as usize).max(1).min(MAX_SPLITS)
+        } else {
+            1
+        };
+        (0..count).map(|i| {
+            let (a, b) = (i as f32/count as f32, (i + 1) as f32/count as f32);
+            CurveSegment {
+                points: sub_curve(points, a, b, lerp_point),
+                radii: sub_curve(radii, a, b, lerp_radius),
+                span: (span.0 + (span.1 - span.0)*a, span.0 + (span.1 - span.0)*b),
+                kind,
+                texture: texture.clone(),
+            }
+        }).collect()
+    }
+
+    fn radius(&self, t: f32) -> f32 {
+        split(self.radii, t, lerp_radius).0[3]
+    }
+
+    fn max_radius(&self) -> f32 {
+        self.radii.iter().cloned().fold(0.0, f32::max)
+    }
+
+    /// Hit against the piece of the segment between `u0` and `u1` with control points `c` in ray space,
+    /// where the ray starts at the origin and runs along z. Returns the distance along the ray and the parameters
+    /// along and across the segment.
+    fn intersect(&self, c: [Point3D<f32, UnknownUnit>; 4], u0: f32, u1: f32, depth: i32, z_min: f32, z_max: f32) -> Option<(f32, f32, f32)> {
+        let radius = self.max_radius();
+        let (mut low, mut high) = (c[0], c[0]);
+        for p in &c[1..] {
+            low = low.min(*p);
+            high = high.max(*p);
+        }
+        if low.x > radius || high.x < -radius || low.y > radius || high.y < -radius || low.z > z_max + radius || high.z < z_min - radius {
+            return None;
+        }
+        if depth > 0 {
+            let (left, right) = split(c, 0.5, lerp_point);
+            let middle = (u0 + u1)*0.5;
+            return match self.intersect(left, u0, middle, depth - 1, z_min, z_max) {
+                Some(hit) => Some(self.intersect(right, middle, u1, depth - 1, z_min, hit.0).unwrap_or(hit)),
+                None => self.intersect(right, middle, u1, depth - 1, z_min, z_max),
+            };
+        }
+        // Close enough to a line, cut off square at its ends
+        if (c[1].y - c[0].y)*-c[0].y + c[0].x*(c[0].x - c[1].x) < 0.0 || (c[2].y - c[3].y)*-c[3].y + c[3].x*(c[3].x - c[2].x) < 0.0 {
+            return None;
+        }
+        let direction: Vector2D<f32, UnknownUnit> = vec2(c[3].x - c[0].x, c[3].y - c[0].y);
+        let denominator = direction.square_length();
+        if denominator == 0.0 {
+            return None;
+        }
+        let w = ((-c[0].x*direction.x - c[0].y*direction.y)/denominator).max(0.0).min(1.0);
+        let u = u0 + (u1 - u0)*w;
+        let radius = self.radius(u);
+        let on_curve = evaluate(&c, w);
+        let distance_squared = on_curve.x*on_curve.x + on_curve.y*on_curve.y;
+        if distance_squared > radius*radius || on_curve.z < z_min || on_curve.z > z_max {
+            return None;
+        }
+        // A ray starting inside the tube, as one leaving the fiber it scattered off, passes through
+        if distance_squared + on_curve.z*on_curve.z < radius*radius*(1.0 + 1e-3) {
+            return None;
+        }
+        let tangent = derivative(&c, w);
+        let side = on_curve.x*tangent.y - on_curve.y*tangent.x;
+        let offset = distance_squared.sqrt()/radius*0.5;
+        Some((on_curve.z, u, if side > 0.0 { 0.5 + offset } else { 0.5 - offset }))
+    }
+}
+
+impl Hitable for CurveSegment {
+    fn bbox(&self) -> AABB {
+        let radius = self.max_radius();
+        let bbox = self.points.iter().fold(AABB::empty(), |bbox, p| bbox.merge(AABB { bounds: [*p, *p] }));
+        AABB { bounds: [bbox.bounds[0] - vec3(radius, radius, radius), bbox.bounds[1] + vec3(radius, radius, radius)] }
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let length = r.direction.length();
+        let radius = self.max_radius();
+        if !(radius > 0.0) || length == 0.0 {
+            return None;
+        }
+        let forward = r.direction/length;
+        let right = if forward.x.abs() < 0.5 {
+            vec3(0.0, -forward.z, forward.y).normalize()
+        } else {
+            vec3(-forward.z, 0.0, forward.x).normalize()
+        };
+        let up = forward.cross(right);
+        let to_ray_space = |p: Point3D<f32, UnknownUnit>| {
+            let d = p - r.origin;
+            point3(d.dot(right), d.dot(up), d.dot(forward))
+        };
+        let c = [to_ray_space(self.points[0]), to_ray_space(self.points[1]), to_ray_space(self.points[2]), to_ray_space(self.points[3])];
+        // Deep enough for the pieces to be straight to within a fraction of the width
+        let mut bend: f32 = 0.0;
+        for i in 0..2 {
+            let second_difference = (c[i].to_vector() - c[i + 1].to_vector()*2.0 + c[i + 2].to_vector()).abs();
+            bend = bend.max(second_difference.x).max(second_difference.y).max(second_difference.z);
+        }
+        let depth = ((2f32.sqrt()*6.0*bend/(8.0*2.0*radius*FLATNESS)).log2()*0.5).max(0.0).min(MAX_DEPTH as f32).round() as i32;
+
+        let (z, u, v) = self.intersect(c, 0.0, 1.0, depth, t_min*length, t_max*length)?;
+        let t = z/length;
+        let tangent = derivative(&self.points, u).normalize();
+        // The ribbon faces the ray
+        let facing = -forward + tangent*forward.dot(tangent);
+        let facing = if facing.square_length() > 0.0 { facing.normalize() } else { -forward };
+        let normal = match self.kind {
+            CurveKind::Flat => facing,
+            CurveKind::Round => {
+                let across = v*2.0 - 1.0;
+                facing*(1.0 - across*across).max(0.0).sqrt() + tangent.cross(facing)*across
+            }
+        };
+        let uv = vec2(self.span.0 + (self.span.1 - self.span.0)*u, v);
+        Some(HitRecord {
+            t,
+            p: r.point_at_parameter(t),
+            uv,
+            normal,
+            geometric_normal: normal,
+            front_face: true,
+            texture: self.texture.as_ref(),
+            shading_rate: None,
+            object_id: None,
+            tangent: Some(tangent),
+            edge_distance: None,
+            // Rays leaving from inside the tube pass through it, see `intersect`
+            error: rounding_error(magnitude(r.origin) + z),
+        })
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        visit(self.texture.as_ref());
+    }
+}
+
+/// Strands held in a BVH over their segments.
+///
+/// The texture coordinates run from 0 at the start of every strand to 1 at its end, and from 0 to 1 across it.
+/// Curves have no inside, every hit counts as on the front face.
+#[derive(Debug, Clone)]
+pub struct Curves {
+    segments: Arc<BVH<CurveSegment>>,
+}
+
+impl Curves {
+    /// Strands of cubic Bézier curves, with `3n+1` control points for `n` curves joined end to end.
+    pub fn bezier(strands: &[Vec<ControlPoint>], kind: CurveKind, texture: Arc<dyn Texture>) -> Curves {
+        let mut segments = Vec::new();
+        for strand in strands {
+            assert!(strand.len() >= 4 && (strand.len() - 1) % 3 == 0, "A Bézier strand needs 3n+1 control points, got {}", strand.len());
+            let count = (strand.len() - 1)/3;
+            for (i, c) in strand.windows(4).step_by(3).enumerate() {
+                let span = (i as f32/count as f32, (i + 1) as f32/count as f32);
+                segments.extend(CurveSegment::pieces(
+                    [c[0].0, c[1].0, c[2].0, c[3].0],
+                    [c[0].1, c[1].1, c[2].1, c[3].1],
+                    span,
+                    kind,
+                    &texture,
+                ));
+            }
+        }
+        Curves::from_segments(segments)
+    }
+
+    /// Strands of Catmull-Rom splines, passing through all their points.
+    pub fn catmull_rom(strands: &[Vec<ControlPoint>], kind: CurveKind, texture: Arc<dyn Texture>) -> Curves {
+        let bezier: Vec<Vec<ControlPoint>> = strands.iter().map(|strand| {
+            assert!(strand.len() >= 2, "A Catmull-Rom strand needs at least 2 points, got {}", strand.len());
+            // Continue straight beyond the ends
+            let n = strand.len();
+            let point = |i: isize| -> ControlPoint {
+                if i < 0 {
+                    (strand[0].0 + (strand[0].0 - strand[1].0), strand[0].1*2.0 - strand[1].1)
+                } else if i as usize >= n {
+                    (strand[n - 1].0 + (strand[n - 1].0 - strand[n - 2].0), strand[n - 1].1*2.0 - strand[n - 2].1)
+                } else {
+                    strand[i as usize]
+                }
+            };
+            let mut bezier = vec![strand[0]];
+            for i in 0..n as isize - 1 {
+                let (p0, p1, p2, p3) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+                bezier.push((p1.0 + (p2.0 - p0.0)/6.0, (p1.1 + (p2.1 - p0.1)/6.0).max(0.0)));
+                bezier.push((p2.0 - (p3.0 - p1.0)/6.0, (p2.1 - (p3.1 - p1.1)/6.0).max(0.0)));
+                bezier.push(p2);
+            }
+            bezier
+        }).collect();
+        Curves::bezier(&bezier, kind, texture)
+    }
+
+    pub fn from_segments(segments: Vec<CurveSegment>) -> Curves {
+        Curves { segments: Arc::new(BVH::initialize(segments)) }
+    }
+
+    pub fn segments(&self) -> &[CurveSegment] {
+        self.segments.items()
+    }
+}
+
+impl Hitable for Curves {
+    fn bbox(&self) -> AABB {
+        self.segments.bbox()
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.segments.hit(r, t_min, t_max)
+    }
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.segments.is_occluded(r, t_min, t_max)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.segments.visit_textures(visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use material::Lambertian;
+
+    fn wire(kind: CurveKind) -> Curves {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let strand = (0..4).map(|i| (point3(i as f32/3.0, 0.0, 0.0), 0.1)).collect();
+        Curves::bezier(&[strand], kind, texture)
+    }
+
+    fn down(x: f32, z: f32) -> Ray {
+        Ray::new(point3(x, 1.0, z), vec3(0.0, -2.0, 0.0), 550.0, 0.0)
+    }
+
+    #[test]
+    fn test_straight_wire() {
+        let flat = wire(CurveKind::Flat);
+        let bbox = flat.bbox();
+        assert!((bbox.bounds[0] - point3(-0.1, -0.1, -0.1)).length() < 1e-6 && (bbox.bounds[1] - point3(1.1, 0.1, 0.1)).length() < 1e-6);
+        let rec = flat.hit(down(0.5, 0.05), 0.0, 10.0).unwrap();
+        assert!((rec.t - 0.5).abs() < 1e-4, "{}", rec.t);
+        assert!((rec.uv - vec2(0.5, 0.25)).length() < 1e-3 || (rec.uv - vec2(0.5, 0.75)).length() < 1e-3, "{:?}", rec.uv);
+        assert!((rec.normal - vec3(0.0, 1.0, 0.0)).length() < 1e-5, "{:?}", rec.normal);
+        assert!((rec.tangent.unwrap() - vec3(1.0, 0.0, 0.0)).length() < 1e-5);
+        assert!(flat.hit(down(0.5, 0.15), 0.0, 10.0).is_none());
+        assert!(flat.hit(down(1.2, 0.0), 0.0, 10.0).is_none());
+        assert!(flat.hit(down(0.5, 0.0), 0.0, 0.4).is_none());
+
+        // Halfway to the edge the tube is tilted by 30 degrees, away from the axis
+        let round = wire(CurveKind::Round);
+        let rec = round.hit(down(0.5, 0.05), 0.0, 10.0).unwrap();
+        let expected = vec3(0.0, 0.75f32.sqrt(), 0.5);
+        assert!((rec.normal - expected).length() < 1e-3, "{:?}", rec.normal);
+    }
+
+    #[test]
+    fn test_rays_from_inside_pass() {
+        let curves = wire(CurveKind::Round);
+        assert!(curves.hit(Ray::new(point3(0.5, 0.0, 0.05), vec3(0.0, 1.0, 0.0), 550.0, 0.0), 0.0, 10.0).is_none());
+        assert!(curves.hit(Ray::new(point3(0.5, 0.0, 0.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0), 0.0, 10.0).is_none());
+    }
+
+    #[test]
+    fn test_catmull_rom_passes_through_its_points() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let points = [point3(0.0, 0.0, 0.0), point3(1.0, 0.5, 0.0), point3(2.0, -0.5, 0.0), point3(3.0, 0.0, 0.0)];
+        let strand: Vec<ControlPoint> = points.iter().map(|p| (*p, 0.01)).collect();
+        let curves = Curves::catmull_rom(&[strand], CurveKind::Flat, texture);
+        for p in &points[1..3] {
+            let rec = curves.hit(Ray::new(point3(p.x, p.y, 1.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0), 0.0, 10.0).unwrap();
+            assert!((rec.p - *p).length() < 0.01, "{:?} != {:?}", rec.p, p);
+        }
+        assert!(curves.segments().len() > 3);
+        let rec = curves.hit(Ray::new(point3(2.995, 0.0, 1.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0), 0.0, 10.0);
+        assert!(rec.map_or(false, |rec| rec.uv.x > 0.99));
+    }
+}
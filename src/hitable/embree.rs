@@ -0,0 +1,176 @@
+//! Triangle intersection with Embree, to compare against the built in BVH
+//! or to trade the pure Rust build for faster traversal.
+
+use std::fmt;
+use std::sync::Arc;
+
+use cgmath::{Vector3, Vector4};
+use embree_rs::{Device, Geometry, IntersectContext, RayHit, Scene, TriangleMesh};
+use embree_rs::Ray as EmbreeRay;
+
+use hitable::*;
+use hitable::backend::IntersectionBackend;
+use hitable::triangle::Triangle;
+
+/// An Embree scene over triangles.
+/// Embree only finds which triangle is hit, the triangle itself then computes the hit record.
+/// Alpha masks and vertex motion aren't supported, a ray hitting a cut out part of a triangle first finds nothing at all,
+/// and moving triangles are only found where they start.
+pub struct EmbreeTriangles {
+    scene: Scene,
+    items: Vec<Triangle>,
+    bbox: AABB,
+}
+
+// A committed scene is only read while tracing, which Embree allows from any number of threads.
+unsafe impl Send for EmbreeTriangles {}
+unsafe impl Sync for EmbreeTriangles {}
+
+impl fmt::Debug for EmbreeTriangles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmbreeTriangles").field("items", &self.items.len()).finish()
+    }
+}
+
+fn to_embree(r: Ray, t_min: f32, t_max: f32) -> EmbreeRay {
+    EmbreeRay::segment(
+        Vector3::new(r.origin.x, r.origin.y, r.origin.z),
+        Vector3::new(r.direction.x, r.direction.y, r.direction.z),
+        t_min,
+        t_max,
+    )
+}
+
+impl IntersectionBackend<Triangle> for EmbreeTriangles {
+    const NAME: &'static str = "embree";
+
+    fn build(items: Vec<Triangle>) -> EmbreeTriangles {
+        let device = Device::new();
+        let mut mesh = TriangleMesh::unanimated(device.clone(), items.len(), 3*items.len());
+        {
+            let mut vertices = mesh.vertex_buffer.map();
+            let mut indices = mesh.index_buffer.map();
+            for (i, triangle) in items.iter().enumerate() {
+                let (a, b, c) = triangle.vertices();
+                for (j, p) in [a, b, c].iter().enumerate() {
+                    vertices[3*i + j] = Vector4::new(p.x, p.y, p.z, 0.0);
+                }
+                let first = 3*i as u32;
+                indices[i] = Vector3::new(first, first + 1, first + 2);
+            }
+        }
+        let mut geometry = Geometry::Triangle(mesh);
+        geometry.commit();
+        let mut scene = Scene::new(device);
+        scene.attach_geometry(geometry);
+        scene.commit();
+        let bbox = items.iter().fold(AABB::empty(), |bbox, triangle| bbox.merge(triangle.bbox()));
+        EmbreeTriangles { scene, items, bbox }
+    }
+
+    fn items(&self) -> &[Triangle] {
+        &self.items
+    }
+}
+
+impl Hitable for EmbreeTriangles {
+    fn bbox(&self) -> AABB {
+        self.bbox
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut ctx = IntersectContext::incoherent();
+        let mut ray_hit = RayHit::new(to_embree(r, t_min, t_max));
+        self.scene.intersect(&mut ctx, &mut ray_hit);
+        if !ray_hit.hit.hit() {
+            return None;
+        }
+        // Embree and the triangle may round differently at the edges,
+        // in which case the hit is lost like a ray slipping between two triangles.
+        self.items[ray_hit.hit.primID as usize].hit(r, t_min, t_max)
+    }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        let mut ctx = IntersectContext::incoherent();
+        let mut ray = to_embree(r, t_min, t_max);
+        self.scene.occluded(&mut ctx, &mut ray);
+        // Embree marks an occluded ray by setting its far end to minus infinity
+        ray.tfar == f32::NEG_INFINITY
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        for item in &self.items {
+            item.visit_textures(visit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use palette::Rgb;
+    use hitable::triangle::Mesh;
+    use material::Lambertian;
+    use random::*;
+    use texture::Texture;
+
+    #[test]
+    fn test_agrees_with_bvh() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let bvh = Mesh::from_obj(Path::new("data/bunny.obj"), texture.clone()).unwrap();
+        let embree: Mesh<EmbreeTriangles> = Mesh::from_obj_with_backend(Path::new("data/bunny.obj"), texture).unwrap();
+        assert_eq!(bvh.bbox(), embree.bbox());
+        let AABB { bounds: [low, high] } = bvh.bbox();
+        let center = low.lerp(high, 0.5);
+        let radius = (high - low).length();
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let origin = center + rand_in_unit_sphere()*radius;
+            let target = center + rand_in_unit_sphere()*radius*0.2;
+            let ray = Ray::new(origin, target - origin, 500.0, 0.0);
+            let expected = bvh.hit(ray, 0.0, f32::MAX);
+            let actual = embree.hit(ray, 0.0, f32::MAX);
+            match (expected, actual) {
+                (Some(a), Some(b)) => {
+                    assert!((a.t - b.t).abs() < 1e-4, "{} != {}", a.t, b.t);
+                    hits += 1;
+                }
+                (a, b) => assert_eq!(a.is_some(), b.is_some()),
+            }
+            assert_eq!(bvh.is_occluded(ray, 0.0, 0.5), embree.is_occluded(ray, 0.0, 0.5));
+        }
+        assert!(hits > 100);
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use super::*;
+    use test::*;
+    use std::path::Path;
+    use palette::Rgb;
+    use hitable::bvh::BVH;
+    use hitable::triangle::Mesh;
+    use material::Lambertian;
+    use texture::Texture;
+
+    fn bench_bunny<B: IntersectionBackend<Triangle>>(bench: &mut Bencher) {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let bunny: Mesh<B> = Mesh::from_obj_with_backend(Path::new("data/bunny.obj"), texture).unwrap();
+        let AABB { bounds: [low, high] } = bunny.bbox();
+        let center = low.lerp(high, 0.5);
+        let ray = black_box(Ray::new(center + (high - low), low - high, 500.0, 0.0));
+        bench.iter(|| black_box(bunny.hit(ray, 0.0, f32::MAX)).map(|rec| rec.t));
+    }
+
+    #[bench]
+    fn bench_bunny_bvh(bench: &mut Bencher) {
+        bench_bunny::<BVH<Triangle>>(bench);
+    }
+
+    #[bench]
+    fn bench_bunny_embree(bench: &mut Bencher) {
+        bench_bunny::<EmbreeTriangles>(bench);
+    }
+}
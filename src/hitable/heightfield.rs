@@ -0,0 +1,154 @@
+//! Terrain from a grid of heights, read from a grayscale image or computed by a function.
+
+use euclid::*;
+use image::GrayImage;
+use std::sync::Arc;
+
+use hitable::*;
+use hitable::triangle::{Mesh, Triangle};
+use texture::Texture;
+
+/// A surface over a rectangle of the ground, raised at every point of a regular grid.
+/// Every cell of the grid is split into two triangles with normals smoothed across the cells, and held in a BVH.
+///
+/// The texture coordinates run from 0 to 1 over the whole rectangle, the way an image texture is laid out when seen
+/// from above with the far edge at the top, so a ground texture of the same extent lines up with the heights.
+#[derive(Debug, Clone)]
+pub struct Heightfield {
+    mesh: Mesh,
+}
+
+impl Heightfield {
+    /// Build from `columns*rows` heights in row major order, in [0,1], with the first row at the far edge.
+    /// The field covers `size.x` along x and `size.z` along z from `origin`, and rises up to `size.y` above it.
+    pub fn new(
+        columns: usize,
+        rows: usize,
+        heights: &[f32],
+        origin: Point3D<f32, UnknownUnit>,
+        size: Vector3D<f32, UnknownUnit>,
+        texture: Arc<dyn Texture>,
+    ) -> Heightfield {
+        assert!(columns >= 2 && rows >= 2, "A heightfield needs at least 2x2 heights, got {}x{}", columns, rows);
+        assert_eq!(heights.len(), columns*rows);
+        let (step_x, step_z) = (size.x/(columns - 1) as f32, size.z/(rows - 1) as f32);
+        let height = |i: usize, j: usize| heights[j*columns + i]*size.y;
+        let position = |i: usize, j: usize| origin + vec3(i as f32*step_x, height(i, j), j as f32*step_z);
+        let uv = |i: usize, j: usize| vec2(i as f32/(columns - 1) as f32, 1.0 - j as f32/(rows - 1) as f32);
+        // Central differences inside the grid, one sided at its edges
+        let normal = |i: usize, j: usize| {
+            let (left, right) = (i.saturating_sub(1), (i + 1).min(columns - 1));
+            let (far, near) = (j.saturating_sub(1), (j + 1).min(rows - 1));
+            let dx = (height(right, j) - height(left, j))/((right - left) as f32*step_x);
+            let dz = (height(i, near) - height(i, far))/((near - far) as f32*step_z);
+            vec3(-dx, 1.0, -dz).normalize()
+        };
+        let mut triangles = Vec::with_capacity(2*(columns - 1)*(rows - 1));
+        for j in 0..rows - 1 {
+            for i in 0..columns - 1 {
+                let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+                for &(a, b, c) in [(0, 1, 2), (0, 2, 3)].iter() {
+                    let (a, b, c) = (corners[a], corners[b], corners[c]);
+                    triangles.push(Triangle::new(
+                        (position(a.0, a.1), position(b.0, b.1), position(c.0, c.1)),
+                        (normal(a.0, a.1), normal(b.0, b.1), normal(c.0, c.1)),
+                        (uv(a.0, a.1), uv(b.0, b.1), uv(c.0, c.1)),
+                        texture.clone(),
+                    ));
+                }
+            }
+        }
+        Heightfield { mesh: Mesh::from_triangles(triangles) }
+    }
+
+    /// Build from a function of the position on the grid, both coordinates in [0,1] with `(0, 0)` at the far left corner,
+    /// sampled at `columns*rows` points. The function returns heights in [0,1].
+    pub fn from_fn<F: Fn(f32, f32) -> f32>(
+        columns: usize,
+        rows: usize,
+        height: F,
+        origin: Point3D<f32, UnknownUnit>,
+        size: Vector3D<f32, UnknownUnit>,
+        texture: Arc<dyn Texture>,
+    ) -> Heightfield {
+        let mut heights = Vec::with_capacity(columns*rows);
+        for j in 0..rows {
+            for i in 0..columns {
+                heights.push(height(i as f32/(columns.max(2) - 1) as f32, j as f32/(rows.max(2) - 1) as f32));
+            }
+        }
+        Heightfield::new(columns, rows, &heights, origin, size, texture)
+    }
+
+    /// Build from a grayscale image with a height per pixel, white the highest.
+    pub fn from_image(
+        image: &GrayImage,
+        origin: Point3D<f32, UnknownUnit>,
+        size: Vector3D<f32, UnknownUnit>,
+        texture: Arc<dyn Texture>,
+    ) -> Heightfield {
+        let heights: Vec<f32> = image.pixels().map(|pixel| pixel[0] as f32/255.0).collect();
+        Heightfield::new(image.width() as usize, image.height() as usize, &heights, origin, size, texture)
+    }
+}
+
+impl Hitable for Heightfield {
+    fn bbox(&self) -> AABB {
+        self.mesh.bbox()
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.mesh.hit(r, t_min, t_max)
+    }
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.mesh.is_occluded(r, t_min, t_max)
+    }
+    fn surface_area(&self) -> f32 {
+        self.mesh.surface_area()
+    }
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.mesh.sample_surface(u)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.mesh.visit_textures(visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use palette::Rgb;
+    use material::Lambertian;
+
+    fn down(x: f32, z: f32) -> Ray {
+        Ray::new(point3(x, 10.0, z), vec3(0.0, -1.0, 0.0), 550.0, 0.0)
+    }
+
+    #[test]
+    fn test_slope() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        // Rising towards +x, one unit over the four units of the field
+        let field = Heightfield::from_fn(5, 3, |x, _| x, point3(-2.0, 0.0, 0.0), vec3(4.0, 1.0, 2.0), texture);
+        assert_eq!(field.bbox(), AABB { bounds: [point3(-2.0, 0.0, 0.0), point3(2.0, 1.0, 2.0)] });
+        let rec = field.hit(down(1.0, 0.5), 0.0, 100.0).unwrap();
+        assert!((rec.p.y - 0.75).abs() < 1e-5, "{:?}", rec.p);
+        assert!((rec.normal - vec3(-1.0, 4.0, 0.0).normalize()).length() < 1e-5, "{:?}", rec.normal);
+        assert!((rec.uv - vec2(0.75, 0.75)).length() < 1e-5, "{:?}", rec.uv);
+        assert!(field.hit(down(2.5, 0.5), 0.0, 100.0).is_none());
+        assert!((field.surface_area() - 2.0*17f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_from_image() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        // A single raised pixel in the middle of a flat image
+        let image = GrayImage::from_fn(3, 3, |x, y| Luma([if (x, y) == (1, 1) { 255 } else { 0 }]));
+        let field = Heightfield::from_image(&image, point3(0.0, 0.0, 0.0), vec3(2.0, 0.5, 2.0), texture);
+        let peak = field.hit(down(1.0, 1.0), 0.0, 100.0).unwrap();
+        assert!((peak.p.y - 0.5).abs() < 1e-5);
+        assert!((peak.normal - vec3(0.0, 1.0, 0.0)).length() < 1e-5);
+        let corner = field.hit(down(0.01, 1.99), 0.0, 100.0).unwrap();
+        assert!(corner.p.y < 0.01);
+        assert!(corner.uv.x < 0.01 && corner.uv.y < 0.01, "{:?}", corner.uv);
+    }
+}
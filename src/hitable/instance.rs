@@ -50,21 +50,44 @@ impl<H: Hitable> Hitable for Translate<H> {
     }
 
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let r = Ray {
-            origin: r.origin-self.offset,
-            ..r
-        };
-        let res = self.object.hit(r, t_min, t_max);
+        let res = self.object.hit(self.object_ray(r), t_min, t_max);
         match res {
             None => None,
             Some(rec) => {
+                // The rounding of the hit in the object's own coordinates stays with it
                 Some(HitRecord{
                     p: rec.p+self.offset,
+                    error: rec.error + rounding_error(magnitude(rec.p)),
                     ..rec
                 })
             }
         }
     }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.object.is_occluded(self.object_ray(r), t_min, t_max)
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.object.surface_area()
+    }
+
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.object.sample_surface(u).map(|sample| SurfaceSample { p: sample.p + self.offset, ..sample })
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.object.visit_textures(visit);
+    }
+}
+
+impl<H: Hitable> Translate<H> {
+    fn object_ray(&self, r: Ray) -> Ray {
+        Ray {
+            origin: r.origin-self.offset,
+            ..r
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -75,41 +98,74 @@ struct RotateY<H: Hitable> {
     bbox: AABB,
 }
 
-impl<H: Hitable> Hitable for RotateY<H> {
-    fn bbox(&self) -> AABB {
-        self.bbox
-    }
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+impl<H: Hitable> RotateY<H> {
+    fn object_ray(&self, r: Ray) -> Ray {
         let mut origin = r.origin;
         origin.x = self.cos_theta*r.origin.x - self.sin_theta*r.origin.z;
         origin.z = self.sin_theta*r.origin.x + self.cos_theta*r.origin.z;
         let mut direction = r.direction;
         direction.x = self.cos_theta*r.direction.x - self.sin_theta*r.direction.z;
         direction.z = self.sin_theta*r.direction.x + self.cos_theta*r.direction.z;
-        let rotated_r =
-            Ray::new(
-                origin,
-                direction,
-                r.wl,
-                r.ti
-            );
-        match self.object.hit(rotated_r, t_min, t_max) {
+        Ray::new(
+            origin,
+            direction,
+            r.wl,
+            r.ti
+        ).with_kind(r.kind)
+    }
+}
+
+impl<H: Hitable> Hitable for RotateY<H> {
+    fn bbox(&self) -> AABB {
+        self.bbox
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        match self.object.hit(self.object_ray(r), t_min, t_max) {
             None => None,
             Some(rec) => {
                 let mut p = rec.p;
                 p.x = self.cos_theta*rec.p.x + self.sin_theta*rec.p.z;
                 p.z = -self.sin_theta*rec.p.x + self.cos_theta*rec.p.z;
-                let mut normal = rec.normal;
-                normal.x = self.cos_theta*rec.normal.x + self.sin_theta*rec.normal.z;
-                normal.z = -self.sin_theta*rec.normal.x + self.cos_theta*rec.normal.z;
+                let rotate = |v: Vector3D<f32, UnknownUnit>| vec3(
+                    self.cos_theta*v.x + self.sin_theta*v.z,
+                    v.y,
+                    -self.sin_theta*v.x + self.cos_theta*v.z,
+                );
                 Some(HitRecord{
                     p,
-                    normal,
+                    normal: rotate(rec.normal),
+                    geometric_normal: rotate(rec.geometric_normal),
+                    tangent: rec.tangent.map(rotate),
+                    error: rec.error + rounding_error(magnitude(rec.p)),
                     ..rec
                 })
             }
         }
     }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.object.is_occluded(self.object_ray(r), t_min, t_max)
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.object.surface_area()
+    }
+
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.object.sample_surface(u).map(|sample| {
+            let mut p = sample.p;
+            p.x = self.cos_theta*sample.p.x + self.sin_theta*sample.p.z;
+            p.z = -self.sin_theta*sample.p.x + self.cos_theta*sample.p.z;
+            let mut normal = sample.normal;
+            normal.x = self.cos_theta*sample.normal.x + self.sin_theta*sample.normal.z;
+            normal.z = -self.sin_theta*sample.normal.x + self.cos_theta*sample.normal.z;
+            SurfaceSample { p, normal, ..sample }
+        })
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.object.visit_textures(visit);
+    }
 }
 
 pub fn rotate_y<H: Hitable>(object: H, angle: f32) -> impl Hitable {
@@ -168,12 +224,8 @@ pub fn scale<H: Hitable>(object: H, scale: Vector3D<f32, UnknownUnit>) -> impl H
     }
 }
 
-impl<H: Hitable> Hitable for Scale<H> {
-    fn bbox(&self) -> AABB {
-        self.bbox
-    }
-
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+impl<H: Hitable> Scale<H> {
+    fn object_ray(&self, r: Ray) -> Ray {
         let scaled_origin = point3(
             r.origin.x*self.inv_scale.x,
             r.origin.y*self.inv_scale.y,
@@ -184,9 +236,17 @@ impl<H: Hitable> Hitable for Scale<H> {
             r.direction.y*self.inv_scale.y,
             r.direction.z*self.inv_scale.z,
         );
-        let scaled_r = Ray::new(scaled_origin, scaled_direction, r.wl, r.ti);
+        Ray::new(scaled_origin, scaled_direction, r.wl, r.ti).with_kind(r.kind)
+    }
+}
+
+impl<H: Hitable> Hitable for Scale<H> {
+    fn bbox(&self) -> AABB {
+        self.bbox
+    }
 
-        match self.object.hit(scaled_r, t_min, t_max) {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        match self.object.hit(self.object_ray(r), t_min, t_max) {
             None => None,
             Some(rec) => {
                 let p = point3(
@@ -194,18 +254,564 @@ impl<H: Hitable> Hitable for Scale<H> {
                     rec.p.y*self.scale.y,
                     rec.p.z*self.scale.z,
                 );
-                let normal = vec3(
-                    rec.normal.x*self.scale.x,
-                    rec.normal.y*self.scale.y,
-                    rec.normal.z*self.scale.z,
+                // Normals stretch the other way from the surface, to stay perpendicular to it
+                let transform_normal = |n: Vector3D<f32, UnknownUnit>| vec3(
+                    n.x*self.inv_scale.x,
+                    n.y*self.inv_scale.y,
+                    n.z*self.inv_scale.z,
                 ).normalize();
+                let normal = transform_normal(rec.normal);
+                let geometric_normal = transform_normal(rec.geometric_normal);
+                let tangent = rec.tangent.map(|tangent| vec3(
+                    tangent.x*self.scale.x,
+                    tangent.y*self.scale.y,
+                    tangent.z*self.scale.z,
+                ).normalize());
+
+                // Lengths along the surface stretch by some mix of the factors
+                let edge_distance = rec.edge_distance.map(|distance| distance*(self.scale.x*self.scale.y*self.scale.z).abs().cbrt());
+                let stretch = self.scale.abs();
+                let error = (rec.error + rounding_error(magnitude(rec.p)))*stretch.x.max(stretch.y).max(stretch.z);
 
                 Some(HitRecord {
                     p,
                     normal,
+                    geometric_normal,
+                    tangent,
+                    edge_distance,
+                    error,
                     ..rec
                 })
             }
         }
     }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.object.is_occluded(self.object_ray(r), t_min, t_max)
+    }
+
+    /// Exact for the same scale along every axis, and otherwise as if the surface faced every way alike.
+    fn surface_area(&self) -> f32 {
+        self.object.surface_area()*(self.scale.x*self.scale.y*self.scale.z).abs().powf(2.0/3.0)
+    }
+
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.object.sample_surface(u).map(|sample| {
+            let normal = vec3(
+                sample.normal.x*self.inv_scale.x,
+                sample.normal.y*self.inv_scale.y,
+                sample.normal.z*self.inv_scale.z,
+            );
+            // The surface around the point grows by the change in volume over the stretch across it
+            let stretch = (self.scale.x*self.scale.y*self.scale.z).abs()*normal.length();
+            SurfaceSample {
+                p: point3(sample.p.x*self.scale.x, sample.p.y*self.scale.y, sample.p.z*self.scale.z),
+                normal: normal.normalize(),
+                pdf: sample.pdf/stretch,
+            }
+        })
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.object.visit_textures(visit);
+    }
+}
+
+
+/// Move an object over the shutter, by `velocity` and spinning `spin` degrees around the y axis through its origin,
+/// so instances of a mesh get motion blur without a copy of it for every time.
+/// A ray at time `ti` sees it spun by `spin*ti` and then moved by `velocity*ti`, and its box covers all of that,
+/// so the BVH above it finds it at any time.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # extern crate euclid;
+/// # use euclid::*;
+/// # use palette::*;
+/// # use std::sync::Arc;
+/// # use rayer::ray::Ray;
+/// # use rayer::texture::*;
+/// # use rayer::material::*;
+/// # use rayer::hitable::*;
+/// # use rayer::hitable::instance::moving;
+/// # use rayer::hitable::triangle::axis_aligned_cuboid;
+/// #
+/// # let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+/// # let object = axis_aligned_cuboid(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0), texture);
+/// let object = moving(object, vec3(4.0, 0.0, 0.0), 0.0);
+/// assert_eq!(object.bbox().bounds, [point3(-1.0, -1.0, -1.0), point3(5.0, 1.0, 1.0)]);
+/// // A ray along the path hits the box where it is at its time
+/// let ray = |ti| Ray::new(point3(10.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0), 500.0, ti);
+/// assert_eq!(object.hit(ray(0.0), 0.0, 100.0).unwrap().p.x, 1.0);
+/// assert_eq!(object.hit(ray(0.5), 0.0, 100.0).unwrap().p.x, 3.0);
+/// ```
+pub fn moving<H: Hitable>(object: H, velocity: Vector3D<f32, UnknownUnit>, spin: f32) -> impl Hitable {
+    let spin = spin.to_radians();
+    let AABB { bounds: [low, high] } = object.bbox();
+    let swept = if object.bbox().is_empty() || spin == 0.0 {
+        AABB { bounds: [low, high] }
+    } else {
+        // Corners spinning around the y axis stay within the circle through the one furthest from it
+        let radius = (0..4)
+            .map(|corner| Vector2D::<f32, UnknownUnit>::new(if corner & 1 == 0 { low.x } else { high.x }, if corner & 2 == 0 { low.z } else { high.z }).length())
+            .fold(0.0, f32::max);
+        AABB { bounds: [point3(-radius, low.y, -radius), point3(radius, high.y, radius)] }
+    };
+    let bbox = if swept.is_empty() {
+        swept
+    } else {
+        swept.merge(AABB { bounds: [swept.bounds[0] + velocity, swept.bounds[1] + velocity] })
+    };
+    Moving { object, velocity, spin, bbox }
+}
+
+#[derive(Debug, Clone)]
+struct Moving<H: Hitable> {
+    object: H,
+    velocity: Vector3D<f32, UnknownUnit>,
+    /// Radians around the y axis over the shutter.
+    spin: f32,
+    bbox: AABB,
+}
+
+impl<H: Hitable> Moving<H> {
+    /// Turn `v` by the angle with sine `sin` and cosine `cos` around the y axis, the way `rotate_y` does.
+    fn turn(v: Vector3D<f32, UnknownUnit>, sin: f32, cos: f32) -> Vector3D<f32, UnknownUnit> {
+        vec3(cos*v.x + sin*v.z, v.y, -sin*v.x + cos*v.z)
+    }
+
+    /// The sine and cosine of the spin at time `ti`, and the offset.
+    fn at(&self, ti: f32) -> (f32, f32, Vector3D<f32, UnknownUnit>) {
+        let (sin, cos) = (self.spin*ti).sin_cos();
+        (sin, cos, self.velocity*ti)
+    }
+
+    /// The ray in the object's own coordinates, with the inverse direction the boxes of its BVH are tested with
+    /// worked out again for the turned direction.
+    fn object_ray(&self, r: Ray) -> Ray {
+        let (sin, cos, offset) = self.at(r.ti);
+        let origin = Moving::<H>::turn(r.origin.to_vector() - offset, -sin, cos).to_point();
+        let direction = Moving::<H>::turn(r.direction, -sin, cos);
+        Ray::new(origin, direction, r.wl, r.ti).with_absorption(r.absorption).with_kind(r.kind)
+    }
+}
+
+impl<H: Hitable> Hitable for Moving<H> {
+    fn bbox(&self) -> AABB {
+        self.bbox
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let rec = self.object.hit(self.object_ray(r), t_min, t_max)?;
+        let (sin, cos, offset) = self.at(r.ti);
+        let turn = |v| Moving::<H>::turn(v, sin, cos);
+        let p = turn(rec.p.to_vector()).to_point() + offset;
+        Some(HitRecord {
+            p,
+            normal: turn(rec.normal),
+            geometric_normal: turn(rec.geometric_normal),
+            tangent: rec.tangent.map(turn),
+            error: rec.error + rounding_error(magnitude(rec.p).max(magnitude(p))),
+            ..rec
+        })
+    }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.object.is_occluded(self.object_ray(r), t_min, t_max)
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.object.surface_area()
+    }
+
+    /// Uniform over the surface where it is at the start of the shutter, like a moving sphere.
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.object.sample_surface(u)
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.object.visit_textures(visit);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WithShadingRate<H: Hitable> {
+    object: H,
+    shading_rate: ShadingRate,
+}
+
+/// Override the shading rate for all hits on an object.
+/// Overrides set on the inner objects take precedence.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # extern crate euclid;
+/// # use euclid::*;
+/// # use palette::*;
+/// # use std::sync::Arc;
+/// # use rayer::ray::Ray;
+/// # use rayer::texture::*;
+/// # use rayer::material::*;
+/// # use rayer::hitable::*;
+/// # use rayer::hitable::instance::with_shading_rate;
+/// # use rayer::hitable::triangle::axis_aligned_cuboid;
+/// #
+/// # let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+/// # let object = axis_aligned_cuboid(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0), texture);
+/// let rate = ShadingRate { max_depth: 4, roulette_threshold: 0.1 };
+/// let object = with_shading_rate(object, rate);
+/// let ray = Ray::new(point3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0);
+/// assert_eq!(object.hit(ray, 0.0, 100.0).unwrap().shading_rate, Some(rate));
+/// ```
+pub fn with_shading_rate<H: Hitable>(object: H, shading_rate: ShadingRate) -> impl Hitable {
+    WithShadingRate { object, shading_rate }
+}
+
+impl<H: Hitable> Hitable for WithShadingRate<H> {
+    fn centroid(&self) -> Point3D<f32, UnknownUnit> {
+        self.object.centroid()
+    }
+
+    fn bbox(&self) -> AABB {
+        self.object.bbox()
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.object.hit(r, t_min, t_max).map(|rec| HitRecord {
+            shading_rate: rec.shading_rate.or(Some(self.shading_rate)),
+            ..rec
+        })
+    }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.object.is_occluded(r, t_min, t_max)
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.object.surface_area()
+    }
+
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.object.sample_surface(u)
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.object.visit_textures(visit);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WithId<H: Hitable> {
+    object: H,
+    id: u32,
+}
+
+/// Tag all hits on an object with an id, for picking and tooling.
+/// Ids set on the inner objects take precedence.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # extern crate euclid;
+/// # use euclid::*;
+/// # use palette::*;
+/// # use std::sync::Arc;
+/// # use rayer::ray::Ray;
+/// # use rayer::texture::*;
+/// # use rayer::material::*;
+/// # use rayer::hitable::*;
+/// # use rayer::hitable::instance::with_id;
+/// # use rayer::hitable::triangle::axis_aligned_cuboid;
+/// #
+/// # let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+/// # let object = axis_aligned_cuboid(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0), texture);
+/// let object = with_id(object, 7);
+/// let ray = Ray::new(point3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0);
+/// assert_eq!(object.hit(ray, 0.0, 100.0).unwrap().object_id, Some(7));
+/// ```
+pub fn with_id<H: Hitable>(object: H, id: u32) -> impl Hitable {
+    WithId { object, id }
+}
+
+impl<H: Hitable> Hitable for WithId<H> {
+    fn centroid(&self) -> Point3D<f32, UnknownUnit> {
+        self.object.centroid()
+    }
+
+    fn bbox(&self) -> AABB {
+        self.object.bbox()
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.object.hit(r, t_min, t_max).map(|rec| HitRecord {
+            object_id: rec.object_id.or(Some(self.id)),
+            ..rec
+        })
+    }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.object.is_occluded(r, t_min, t_max)
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.object.surface_area()
+    }
+
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.object.sample_surface(u)
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.object.visit_textures(visit);
+    }
+}
+
+struct Filtered<H: Hitable, F> {
+    object: H,
+    filter: F,
+}
+
+/// Only count the hits on an object that `filter` accepts, given the ray and the hit, like those on one side of a
+/// clipping plane. Rays go on past the hits it rejects to the ones behind them, so it gets to see every surface of
+/// the object along the ray in turn, as long as they don't meet the ray at the same distance.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # extern crate euclid;
+/// # use euclid::*;
+/// # use palette::*;
+/// # use std::sync::Arc;
+/// # use rayer::ray::Ray;
+/// # use rayer::texture::*;
+/// # use rayer::material::*;
+/// # use rayer::hitable::*;
+/// # use rayer::hitable::instance::with_filter;
+/// # use rayer::hitable::sphere::Sphere;
+/// #
+/// # let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+/// // A ball cut open at x = 0, looked into from the open side
+/// let ball = with_filter(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture), |_, rec: &HitRecord| rec.p.x >= 0.0);
+/// let ray = Ray::new(point3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0);
+/// let rec = ball.hit(ray, 0.0, 100.0).unwrap();
+/// assert_eq!(rec.p, point3(1.0, 0.0, 0.0));
+/// assert!(!rec.front_face);
+/// assert!(!ball.is_occluded(ray, 0.0, 3.5));
+/// ```
+pub fn with_filter<H: Hitable, F: Fn(Ray, &HitRecord) -> bool + Send + Sync>(object: H, filter: F) -> impl Hitable {
+    Filtered { object, filter }
+}
+
+impl<H: Hitable, F: Fn(Ray, &HitRecord) -> bool + Send + Sync> Hitable for Filtered<H, F> {
+    fn centroid(&self) -> Point3D<f32, UnknownUnit> {
+        self.object.centroid()
+    }
+
+    fn bbox(&self) -> AABB {
+        self.object.bbox()
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut t_min = t_min;
+        loop {
+            let rec = self.object.hit(r, t_min, t_max)?;
+            if (self.filter)(r, &rec) {
+                return Some(rec);
+            }
+            // Objects only find hits past `t_min`, but one that doesn't mustn't keep the ray here
+            if !(rec.t > t_min) {
+                return None;
+            }
+            t_min = rec.t;
+        }
+    }
+
+    /// Every hit found has to be shown to the filter, so this is no faster than `hit`.
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.object.surface_area()
+    }
+
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.object.sample_surface(u)
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.object.visit_textures(visit);
+    }
+}
+
+/// The kinds of rays that see an object, see `RayKind`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Visibility {
+    pub camera: bool,
+    /// Whether it casts shadows on surfaces lit by lights they aim at.
+    pub shadow: bool,
+    /// Whether it shows up in reflections and refractions and lights other surfaces.
+    pub bounce: bool,
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility { camera: true, shadow: true, bounce: true }
+    }
+}
+
+impl Visibility {
+    pub fn sees(self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Bounce => self.bounce,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WithVisibility<H: Hitable> {
+    object: H,
+    visibility: Visibility,
+}
+
+/// Hide an object from some kinds of rays, like a light blocker that casts a shadow without being seen.
+/// A light hidden from the camera still lights the scene.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # extern crate euclid;
+/// # use euclid::*;
+/// # use palette::*;
+/// # use std::sync::Arc;
+/// # use rayer::ray::{Ray, RayKind};
+/// # use rayer::texture::*;
+/// # use rayer::material::*;
+/// # use rayer::hitable::*;
+/// # use rayer::hitable::instance::{with_visibility, Visibility};
+/// # use rayer::hitable::sphere::Sphere;
+/// #
+/// # let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+/// let blocker = with_visibility(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture), Visibility { camera: false, ..Default::default() });
+/// let ray = Ray::new(point3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0);
+/// assert!(blocker.hit(ray, 0.0, 100.0).is_none());
+/// assert!(blocker.is_occluded(ray.with_kind(RayKind::Shadow), 0.0, 100.0));
+/// ```
+pub fn with_visibility<H: Hitable>(object: H, visibility: Visibility) -> impl Hitable {
+    WithVisibility { object, visibility }
+}
+
+impl<H: Hitable> Hitable for WithVisibility<H> {
+    fn centroid(&self) -> Point3D<f32, UnknownUnit> {
+        self.object.centroid()
+    }
+
+    fn bbox(&self) -> AABB {
+        self.object.bbox()
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if self.visibility.sees(r.kind) { self.object.hit(r, t_min, t_max) } else { None }
+    }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.visibility.sees(r.kind) && self.object.is_occluded(r, t_min, t_max)
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.object.surface_area()
+    }
+
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.object.sample_surface(u)
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.object.visit_textures(visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use std::sync::Arc;
+    use material::Lambertian;
+    use palette::Rgb;
+    use texture::Texture;
+    use hitable::sphere::Sphere;
+    use hitable::triangle::axis_aligned_cuboid;
+
+    #[test]
+    fn test_scaled_normals() {
+        // A sphere squashed into a disc faces up almost everywhere on top
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let disc = scale(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture), vec3(10.0, 0.1, 10.0));
+        let rec = disc.hit(Ray::new(point3(7.0, 5.0, 0.0), vec3(0.0, -1.0, 0.0), 500.0, 0.0), 0.0, 100.0).unwrap();
+        assert!(rec.normal.y > 0.99 && rec.geometric_normal.y > 0.99, "{:?}", rec.normal);
+        // and at right angles to the surface, which runs along the sphere's tangent stretched like its points
+        let along: Vector3D<f32, UnknownUnit> = vec3(-rec.p.y/0.1*10.0, rec.p.x/10.0*0.1, 0.0).normalize();
+        assert!(rec.normal.dot(along).abs() < 1e-3, "{:?} {:?}", rec.normal, along);
+    }
+
+    #[test]
+    fn test_scaled_surface() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let sphere = scale(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture.clone()), vec3(2.0, 2.0, 2.0));
+        assert!((sphere.surface_area() - 16.0*PI).abs() < 1e-4);
+        let sample = sphere.sample_surface(vec2(0.3, 0.6)).unwrap();
+        assert!((sample.p.to_vector().length() - 2.0).abs() < 1e-5);
+        assert!((sample.pdf - 1.0/(16.0*PI)).abs() < 1e-6);
+        // Stretched along x, the ends of a cube keep their area and the sides double it
+        let cuboid = scale(axis_aligned_cuboid(point3(-0.5, -0.5, -0.5), point3(0.5, 0.5, 0.5), texture), vec3(-2.0, 1.0, 1.0));
+        for i in 0..16 {
+            let sample = cuboid.sample_surface(vec2(i as f32/16.0, 0.4)).unwrap();
+            let expected = if sample.normal.x.abs() > 0.99 { 1.0/6.0 } else { 1.0/12.0 };
+            assert!((sample.pdf - expected).abs() < 1e-5, "{:?}", sample);
+            assert!(sample.p.x.abs() <= 1.0 && sample.normal.dot(sample.p.to_vector()) > 0.0, "{:?}", sample);
+        }
+    }
+
+    #[test]
+    fn test_filtered_hits() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let ball = || Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture.clone());
+        let ray = |wl| Ray::new(point3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), wl, 0.0);
+        assert_eq!(ball().hit(ray(500.0), 0.0, 100.0).unwrap().t, 2.0);
+        // Rejecting the front of the ball lets the ray through to the back
+        let back = with_filter(ball(), |_, rec: &HitRecord| !rec.front_face);
+        assert_eq!(back.hit(ray(500.0), 0.0, 100.0).unwrap().t, 4.0);
+        assert!(back.is_occluded(ray(500.0), 0.0, 100.0) && !back.is_occluded(ray(500.0), 0.0, 3.0));
+        let nothing = with_filter(ball(), |_, _: &HitRecord| false);
+        assert!(nothing.hit(ray(500.0), 0.0, 100.0).is_none() && !nothing.is_occluded(ray(500.0), 0.0, 100.0));
+        // The filter sees the ray as well, here only stopping red light
+        let red = with_filter(ball(), |r: Ray, _: &HitRecord| r.wl > 600.0);
+        assert!(red.hit(ray(500.0), 0.0, 100.0).is_none());
+        assert_eq!(red.hit(ray(650.0), 0.0, 100.0).unwrap().t, 2.0);
+    }
+
+    #[test]
+    fn test_spinning_mesh() {
+        // A long box spun half a turn over the shutter, across the rays at half time
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let cuboid = || axis_aligned_cuboid(point3(-2.0, -0.5, -0.5), point3(2.0, 0.5, 0.5), texture.clone());
+        let spinning = moving(cuboid(), vec3(0.0, 0.0, 0.0), 180.0);
+        let turned = rotate_y(cuboid(), 90.0);
+        for &(origin, direction) in &[(point3(0.0, 0.0, 10.0), vec3(0.0, 0.0, -1.0)), (point3(10.0, 0.1, 0.0), vec3(-1.0, 0.0, 0.0)), (point3(3.0, 3.0, 3.0), vec3(-1.0, -1.0, -1.0))] {
+            let r = Ray::new(origin, direction, 500.0, 0.5);
+            let (hit, expected) = (spinning.hit(r, 0.0, 100.0).unwrap(), turned.hit(r, 0.0, 100.0).unwrap());
+            assert!((hit.p - expected.p).length() < 1e-4, "{:?} {:?}", hit.p, expected.p);
+            assert!((hit.normal - expected.normal).length() < 1e-4);
+            assert!(spinning.is_occluded(r, 0.0, 100.0));
+        }
+        // Where the box was at the start of the shutter it has turned away from by half time
+        let r = |ti| Ray::new(point3(1.5, 0.0, 10.0), vec3(0.0, 0.0, -1.0), 500.0, ti);
+        assert!(spinning.hit(r(0.0), 0.0, 100.0).is_some());
+        assert!(spinning.hit(r(0.5), 0.0, 100.0).is_none());
+        assert!(!spinning.is_occluded(r(0.5), 0.0, 100.0));
+    }
 }
@@ -0,0 +1,153 @@
+//! Fog, smoke and other media filling a solid, which light scatters in on its way through instead of on a surface.
+//! Lit through glass, the beams the glass focuses show in the medium as volume caustics.
+//!
+//! ```
+//! # extern crate rayer;
+//! # extern crate euclid;
+//! # extern crate palette;
+//! # use euclid::*;
+//! # use std::sync::Arc;
+//! # use palette::Rgb;
+//! # use rayer::hitable::Hitable;
+//! # use rayer::hitable::csg::Cuboid;
+//! # use rayer::hitable::medium::Medium;
+//! # use rayer::material::Isotropic;
+//! # use rayer::ray::Ray;
+//! let smoke = Arc::new(Isotropic::new(Rgb::with_wp(0.8, 0.8, 0.8)));
+//! let room = Medium::new(Cuboid::new(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0), smoke.clone()), 1000.0, smoke);
+//! // So dense that light scatters right where it enters
+//! let rec = room.hit(Ray::new(point3(0.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0), 0.0, 100.0).unwrap();
+//! assert!((rec.t - 4.0).abs() < 0.02);
+//! ```
+
+use euclid::*;
+use std::sync::Arc;
+
+use arena;
+use hitable::*;
+use hitable::csg::Solid;
+use random::next_f32;
+use texture::Texture;
+
+/// A medium of the same `density` all through a solid, scattering light as the texture it is given does, usually an
+/// `Isotropic` material. The density is the chance of light scattering per unit of distance, so light travels
+/// `1/density` on average before it scatters, and gets through by `exp(-density*distance)`.
+///
+/// Hits are drawn at random along the ray, and shadow rays get through the medium or not by chance, which comes out
+/// to the transmittance on average.
+#[derive(Debug, Clone)]
+pub struct Medium<S> {
+    boundary: S,
+    density: f32,
+    texture: Arc<dyn Texture>,
+}
+
+impl<S: Solid> Medium<S> {
+    pub fn new(boundary: S, density: f32, texture: Arc<dyn Texture>) -> Medium<S> {
+        Medium { boundary, density, texture }
+    }
+
+    fn record(&self, r: Ray, t: f32) -> HitRecord<'_> {
+        let p = r.point_at_parameter(t);
+        // There is no surface, so the normal faces back along the ray, and rays leave the hit in every direction
+        let normal = -r.direction.normalize();
+        HitRecord {
+            t,
+            p,
+            uv: vec2(0.0, 0.0),
+            normal,
+            geometric_normal: normal,
+            front_face: true,
+            texture: self.texture.as_ref(),
+            shading_rate: None,
+            object_id: None,
+            tangent: None,
+            edge_distance: None,
+            error: rounding_error(magnitude(p)),
+        }
+    }
+}
+
+impl<S: Solid> Hitable for Medium<S> {
+    fn centroid(&self) -> Point3D<f32, UnknownUnit> {
+        self.boundary.centroid()
+    }
+    fn bbox(&self) -> AABB {
+        self.boundary.bbox()
+    }
+    /// Where light going along `r` scatters, after a distance inside the solid drawn from the exponential
+    /// distribution of the free paths. Nothing if the light gets through all of it before `t_max`.
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        if self.density <= 0.0 {
+            return None;
+        }
+        let length = r.direction.length();
+        let mut free_path = -(1.0 - next_f32()).ln()/self.density;
+        arena::with(|arena| {
+            for &(enter, leave) in self.boundary.intervals(r, arena).iter() {
+                let (start, end) = (enter.t.max(t_min), leave.t.min(t_max));
+                if end <= start {
+                    continue;
+                }
+                let inside = (end - start)*length;
+                if free_path < inside {
+                    return Some(self.record(r, start + free_path/length));
+                }
+                free_path -= inside;
+            }
+            None
+        })
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        visit(self.texture.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use hitable::csg::{difference, Cuboid};
+    use hitable::sphere::Sphere;
+    use material::Isotropic;
+
+    fn fog() -> Arc<dyn Texture> {
+        Arc::new(Isotropic::new(Rgb::with_wp(0.9, 0.9, 0.9)))
+    }
+
+    #[test]
+    fn test_transmittance() {
+        let slab = Medium::new(Cuboid::new(point3(-10.0, -10.0, 0.0), point3(10.0, 10.0, 2.0), fog()), 0.5, fog());
+        let ray = Ray::new(point3(0.0, 0.0, -1.0), vec3(0.0, 0.0, 2.0), 550.0, 0.0);
+        let n = 20000;
+        let mut through = 0;
+        for _ in 0..n {
+            match slab.hit(ray, 0.0, 100.0) {
+                Some(rec) => assert!(rec.p.z >= 0.0 && rec.p.z <= 2.0, "{:?}", rec.p),
+                None => through += 1,
+            }
+        }
+        let expected = (-0.5f32*2.0).exp();
+        assert!((through as f32/n as f32 - expected).abs() < 0.02, "{} {}", through, expected);
+        // Starting inside, only the rest of the slab is in the way
+        let inside = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+        let through = (0..n).filter(|_| slab.hit(inside, 0.0, 100.0).is_none()).count();
+        assert!((through as f32/n as f32 - (-0.5f32).exp()).abs() < 0.02, "{}", through);
+        // And nothing of it beyond t_max
+        assert!(slab.hit(ray, 0.0, 0.5).is_none());
+    }
+
+    #[test]
+    fn test_hollow_boundary() {
+        // Dense fog around a clear ball, so rays through the middle of the ball only scatter where they leave it
+        let boundary = difference(
+            Cuboid::new(point3(-2.0, -2.0, -2.0), point3(2.0, 2.0, 2.0), fog()),
+            Sphere::new(point3(0.0, 0.0, 0.0), 1.0, fog()),
+        );
+        let hollow = Medium::new(boundary, 1e4, fog());
+        let ray = Ray::new(point3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+        let rec = hollow.hit(ray, 0.0, 100.0).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-2, "{}", rec.t);
+        assert!(rec.front_face && rec.normal.dot(ray.direction) < 0.0);
+    }
+}
@@ -1,22 +1,168 @@
 pub mod sphere;
 pub mod triangle;
+pub mod quad;
 pub mod bvh;
+pub mod qbvh;
 pub mod instance;
+pub mod heightfield;
+pub mod csg;
+pub mod medium;
+pub mod curve;
+pub mod point_cloud;
+pub mod wavefront;
+pub mod subdivision;
+pub mod backend;
+pub mod stream;
+#[cfg(feature = "embree")]
+pub mod embree;
 
 use num_traits::Float;
 use euclid::*;
-use core_simd::*;
 
 use ray::*;
 use texture::*;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub struct HitRecord<'a> {
     pub t: f32,
     pub p: Point3D<f32, UnknownUnit>,
     pub uv: Vector2D<f32, UnknownUnit>,
+    /// The shading normal, which materials reflect and refract around. Points out of the object, whichever side it
+    /// was hit from. Interpolated across triangles, so it can lean away from `geometric_normal`.
     pub normal: Vector3D<f32, UnknownUnit>,
+    /// The normal of the surface itself, pointing out of the object like `normal`. It tells the sides of the surface
+    /// apart, and rays leaving the surface start off it along this one.
+    pub geometric_normal: Vector3D<f32, UnknownUnit>,
+    /// Whether the ray hit the outside of the surface, so against the geometric normal.
+    pub front_face: bool,
     pub texture: &'a dyn Texture,
+    pub shading_rate: Option<ShadingRate>,
+    /// Set by `instance::with_id`, to tell which object was hit.
+    pub object_id: Option<u32>,
+    /// The direction of the fibers at the hit, along a curve, for materials reflecting differently along and across them.
+    pub tangent: Option<Vector3D<f32, UnknownUnit>>,
+    /// How far the hit lies from the closest edge of the triangle or quad it is on, for drawing wireframes. `None` off
+    /// those.
+    pub edge_distance: Option<f32>,
+    /// How far `p` may lie off the surface from rounding, beyond the last bits of its own coordinates. Large
+    /// objects near the origin round their hits by more than those. See `rounding_error`.
+    pub error: f32,
+}
+
+impl<'a> HitRecord<'a> {
+    /// The shading normal turned to the side the ray came from.
+    pub fn facing_normal(&self) -> Vector3D<f32, UnknownUnit> {
+        if self.front_face { self.normal } else { -self.normal }
+    }
+
+    /// The geometric normal turned to the side the ray came from.
+    pub fn facing_geometric_normal(&self) -> Vector3D<f32, UnknownUnit> {
+        if self.front_face { self.geometric_normal } else { -self.geometric_normal }
+    }
+
+    /// Whether `direction` leaves the surface on the side the ray came from, as a reflection does, rather than
+    /// through it. Directions from around the shading normal can end up on the wrong side.
+    pub fn reflects(&self, direction: Vector3D<f32, UnknownUnit>) -> bool {
+        direction.dot(self.facing_geometric_normal()) > 0.0
+    }
+
+    /// A ray continuing the path of `r_in` from the hit along `direction`, starting just off the surface on the side
+    /// `direction` leaves to, so it doesn't find the surface it starts on again. See `ray::offset_origin`.
+    pub fn spawn_ray(&self, r_in: Ray, direction: Vector3D<f32, UnknownUnit>) -> Ray {
+        let normal = self.geometric_normal.normalize();
+        let normal = if direction.dot(normal) < 0.0 { -normal } else { normal };
+        r_in.scattered(offset_origin(self.p + normal*self.error, normal), direction)
+    }
+}
+
+/// A point drawn on the surface of an object.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub p: Point3D<f32, UnknownUnit>,
+    /// The geometric normal, pointing out of the object.
+    pub normal: Vector3D<f32, UnknownUnit>,
+    /// Probability density of drawing `p`, with respect to surface area.
+    pub pdf: f32,
+}
+
+/// Limits the work the integrator spends on paths hitting an object.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct ShadingRate {
+    /// Maximum number of bounces for a path hitting the object.
+    pub max_depth: u32,
+    /// Paths with a throughput below this value are subject to russian roulette.
+    /// `0.0` disables roulette.
+    pub roulette_threshold: f32,
+}
+
+impl Default for ShadingRate {
+    fn default() -> ShadingRate {
+        ShadingRate { max_depth: 50, roulette_threshold: 0.0 }
+    }
+}
+
+/// The distance along a ray before which hits are ignored.
+/// Rays leaving a surface start just off it, see `HitRecord::spawn_ray`, which is what keeps them from hitting it
+/// again. This only covers the rounding error of the intersection tests on top, which grows with the size of the
+/// coordinates of the ray origin, so it is 0 for rays starting at the origin whatever the size of the scene.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct TMin {
+    scale: f32,
+}
+
+impl Default for TMin {
+    fn default() -> TMin {
+        TMin { scale: 1.0 }
+    }
+}
+
+/// The largest coordinate of `p`, which the rounding error of computations with it grows with.
+pub fn magnitude(p: Point3D<f32, UnknownUnit>) -> f32 {
+    let p = p.to_vector().abs();
+    f32::max(f32::max(p.x, p.y), p.z)
+}
+
+/// A bound on the rounding error of points computed from coordinates up to `magnitude`, a few ulps of it.
+/// Objects set the `error` of their hits with it, from the sizes they compute them with.
+pub fn rounding_error(magnitude: f32) -> f32 {
+    8.0*f32::EPSILON*magnitude
+}
+
+impl TMin {
+    /// Relative to the largest coordinate of the ray origin, about 32 ulps.
+    const ORIGIN_EPSILON: f32 = 4e-6;
+
+    /// The distances scaled by `scale`, for scenes the default doesn't suit.
+    pub fn scaled(self, scale: f32) -> TMin {
+        TMin { scale: self.scale*scale }
+    }
+
+    pub fn t_min(&self, r: Ray) -> f32 {
+        self.scale*magnitude(r.origin)*TMin::ORIGIN_EPSILON / r.direction.length()
+    }
+}
+
+/// Four lanes laid out so the compiler can vectorize the slab tests.
+pub type Lanes = [f32; 4];
+
+#[inline(always)]
+fn slab_distances(bounds: Lanes, origin: Lanes, inv_direction: Lanes) -> Lanes {
+    [
+        (bounds[0] - origin[0]) * inv_direction[0],
+        (bounds[1] - origin[1]) * inv_direction[1],
+        (bounds[2] - origin[2]) * inv_direction[2],
+        (bounds[3] - origin[3]) * inv_direction[3],
+    ]
+}
+
+#[inline(always)]
+fn reduce_max(lanes: Lanes) -> f32 {
+    f32::max(f32::max(lanes[0], lanes[1]), f32::max(lanes[2], lanes[3]))
+}
+
+#[inline(always)]
+fn reduce_min(lanes: Lanes) -> f32 {
+    f32::min(f32::min(lanes[0], lanes[1]), f32::min(lanes[2], lanes[3]))
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -25,56 +171,50 @@ pub struct AABB {
 }
 
 impl AABB {
+    /// The distance along the ray to where it enters the box, if it does so before `t1` and leaves after `t0`.
+    /// Like `intersects_2`, a ray missing the box by less than `WIGGLE_FACTOR` still counts as a hit,
+    /// so that a ray through a flat box, as around an axis aligned rectangle, isn't lost to rounding.
     pub fn intersects(&self, r: Ray, t0: f32, t1: f32) -> Option<f32> {
-        match self {
-            &AABB { bounds } => {
-                let mut tmin = (bounds[r.sign.x as usize].x - r.origin.x) * r.inv_direction.x;
-                let mut tmax = (bounds[1-r.sign.x as usize].x - r.origin.x) * r.inv_direction.x;
-                let tymin = (bounds[r.sign.y as usize].y - r.origin.y) * r.inv_direction.y;
-                let tymax = (bounds[1-r.sign.y as usize].y - r.origin.y) * r.inv_direction.y;
-                if (tmin > tymax) || (tmax < tymin) {
-                    return None;
-                }
-                if tymin>tmin {
-                    tmin = tymin;
-                }
-                if tymax<tmax {
-                    tmax = tymax;
-                }
-                let tzmin = (bounds[r.sign.z as usize].z - r.origin.z) * r.inv_direction.z;
-                let tzmax = (bounds[1-r.sign.z as usize].z - r.origin.z) * r.inv_direction.z;
-                if (tmin > tzmax) || (tmax < tzmin) {
-                    return None;
-                }
-                if tzmin>tmin {
-                    tmin = tzmin;
-                }
-                if tzmax<tmax {
-                    tmax = tzmax;
-                }
-                if (tmin < t1) && (tmax > t0) {
-                    Some(tmin)
-                } else {
-                    None
-                }
-            }
+        let (origin_vec, inv_direction_vec, sign) = AABB::prepare_intersect(r);
+        let tmin = {
+            let bounds_vec = [
+                self.bounds[sign.x as usize].x,
+                self.bounds[sign.y as usize].y,
+                self.bounds[sign.z as usize].z,
+                self.bounds[sign.z as usize].z,
+            ];
+            reduce_max(slab_distances(bounds_vec, origin_vec, inv_direction_vec))
+        };
+        let tmax = {
+            let bounds_vec = [
+                self.bounds[1-sign.x as usize].x,
+                self.bounds[1-sign.y as usize].y,
+                self.bounds[1-sign.z as usize].z,
+                self.bounds[1-sign.z as usize].z,
+            ];
+            reduce_min(slab_distances(bounds_vec, origin_vec, inv_direction_vec))
+        };
+        if (tmin>tmax+AABB::WIGGLE_FACTOR) || (tmin > t1) || (tmax < t0) {
+            None
+        } else {
+            Some(tmin)
         }
     }
 
-    pub fn prepare_intersect(r: Ray) -> (f32x4, f32x4, Vector3D<bool, Inverted>) {
-        let origin_vec = f32x4::from([
+    pub fn prepare_intersect(r: Ray) -> (Lanes, Lanes, Vector3D<bool, Inverted>) {
+        let origin_vec = [
             r.origin.x,
             r.origin.y,
             r.origin.z,
             r.origin.z,
-        ]);
+        ];
 
-        let inv_direction_vec = f32x4::from([
+        let inv_direction_vec = [
             r.inv_direction.x,
             r.inv_direction.y,
             r.inv_direction.z,
             r.inv_direction.z,
-        ]);
+        ];
 
         return (origin_vec, inv_direction_vec, r.sign)
     }
@@ -82,49 +222,45 @@ impl AABB {
     const WIGGLE_FACTOR: f32 = 0.0001;
 
     #[inline(always)]
-    pub fn intersects_2(&self, second: &Self, sign: Vector3D<bool, Inverted>, origin_vec: f32x4, inv_direction_vec: f32x4, t0: f32, t1: f32) -> (Option<f32>, Option<f32>) {
+    pub fn intersects_2(&self, second: &Self, sign: Vector3D<bool, Inverted>, origin_vec: Lanes, inv_direction_vec: Lanes, t0: f32, t1: f32) -> (Option<f32>, Option<f32>) {
         let tmin_0 = {
-            let bounds_vec = f32x4::from([
+            let bounds_vec = [
                 self.bounds[sign.x as usize].x,
                 self.bounds[sign.y as usize].y,
                 self.bounds[sign.z as usize].z,
                 self.bounds[sign.z as usize].z,
-            ]);
-            let res_vec = (bounds_vec - origin_vec) * inv_direction_vec;
-            res_vec.reduce_max()
+            ];
+            reduce_max(slab_distances(bounds_vec, origin_vec, inv_direction_vec))
         };
 
         let tmax_0 = {
-            let bounds_vec = f32x4::from([
+            let bounds_vec = [
                 self.bounds[1-sign.x as usize].x,
                 self.bounds[1-sign.y as usize].y,
                 self.bounds[1-sign.z as usize].z,
                 self.bounds[1-sign.z as usize].z,
-            ]);
-            let res_vec = (bounds_vec - origin_vec) * inv_direction_vec;
-            res_vec.reduce_min()
+            ];
+            reduce_min(slab_distances(bounds_vec, origin_vec, inv_direction_vec))
         };
 
         let tmin_1 = {
-            let bounds_vec = f32x4::from([
+            let bounds_vec = [
                 second.bounds[sign.x as usize].x,
                 second.bounds[sign.y as usize].y,
                 second.bounds[sign.z as usize].z,
                 second.bounds[sign.z as usize].z,
-            ]);
-            let res_vec = (bounds_vec - origin_vec) * inv_direction_vec;
-            res_vec.reduce_max()
+            ];
+            reduce_max(slab_distances(bounds_vec, origin_vec, inv_direction_vec))
         };
 
         let tmax_1 = {
-            let bounds_vec = f32x4::from([
+            let bounds_vec = [
                 second.bounds[1-sign.x as usize].x,
                 second.bounds[1-sign.y as usize].y,
                 second.bounds[1-sign.z as usize].z,
                 second.bounds[1-sign.z as usize].z,
-            ]);
-            let res_vec = (bounds_vec - origin_vec) * inv_direction_vec;
-            res_vec.reduce_min()
+            ];
+            reduce_min(slab_distances(bounds_vec, origin_vec, inv_direction_vec))
         };
 
         let res_0 = {
@@ -163,6 +299,14 @@ impl AABB {
         }
     }
 
+    pub fn surface_area(self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.bounds[1] - self.bounds[0];
+        2.0*(d.x*d.y + d.y*d.z + d.z*d.x)
+    }
+
     pub fn merge(self, other: AABB) -> AABB {
         match (self, other) {
             (AABB { bounds: [low_0, high_0] }, AABB { bounds: [low_1, high_1] }) => {
@@ -197,6 +341,24 @@ pub trait Hitable: Send + Sync {
     }
     fn bbox(&self) -> AABB;
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    /// Whether anything is hit between `t_min` and `t_max`, for shadow rays.
+    /// Unlike `hit` it can stop at the first intersection found.
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+    /// Area of the surface, for picking lights by their size.
+    /// Zero for objects that can't be sampled.
+    fn surface_area(&self) -> f32 {
+        0.0
+    }
+    /// Map `u` in [0,1)², for instance from `random::sample_2d`, to a point on the surface.
+    /// `None` for objects that can't be sampled.
+    fn sample_surface(&self, _u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        None
+    }
+    /// Call `visit` with every texture the object can be hit with, in a fixed order, for numbering the materials.
+    /// Objects that don't tell aren't numbered.
+    fn visit_textures(&self, _visit: &mut dyn FnMut(&dyn Texture)) {}
 }
 
 impl<T: AsRef<dyn Hitable> + Sync + Send> Hitable for T {
@@ -209,14 +371,26 @@ impl<T: AsRef<dyn Hitable> + Sync + Send> Hitable for T {
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         self.as_ref().hit(r, t_min, t_max)
     }
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.as_ref().is_occluded(r, t_min, t_max)
+    }
+    fn surface_area(&self) -> f32 {
+        self.as_ref().surface_area()
+    }
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.as_ref().sample_surface(u)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.as_ref().visit_textures(visit)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use test::*;
     use num_traits::Float;
     use quickcheck::{Arbitrary, Gen};
+    use corpus::{self, Case, Fraction, uniform};
 
     impl Arbitrary for AABB {
         fn arbitrary(g: &mut Gen) -> Self {
@@ -227,6 +401,67 @@ mod tests {
         }
     }
 
+    /// A box of at most unit size near the origin, for the properties kept in the corpus.
+    #[derive(Debug, Clone)]
+    struct UnitBox(AABB);
+
+    impl Arbitrary for UnitBox {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let l = point3(uniform(g, -1.0, 1.0), uniform(g, -1.0, 1.0), uniform(g, -1.0, 1.0));
+            let h = point3(l.x+uniform(g, 0.0, 1.0), l.y+uniform(g, 0.0, 1.0), l.z+uniform(g, 0.0, 1.0));
+            UnitBox(AABB { bounds: [l,h] })
+        }
+    }
+
+    impl Case for UnitBox {
+        const FLOATS: usize = 6;
+
+        fn encode(&self, out: &mut Vec<f32>) {
+            for p in self.0.bounds.iter() {
+                out.extend_from_slice(&[p.x, p.y, p.z]);
+            }
+        }
+
+        fn decode(floats: &[f32]) -> UnitBox {
+            UnitBox(AABB { bounds: [point3(floats[0], floats[1], floats[2]), point3(floats[3], floats[4], floats[5])] })
+        }
+    }
+
+    impl Arbitrary for Ray {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let origin = point3(uniform(g, -2.0, 2.0), uniform(g, -2.0, 2.0), uniform(g, -2.0, 2.0));
+            let direction = vec3(uniform(g, -1.0, 1.0), uniform(g, -1.0, 1.0), uniform(g, -1.0, 1.0));
+            Ray::new(origin, direction, 500.0, 0.0)
+        }
+    }
+
+    impl Case for Ray {
+        const FLOATS: usize = 6;
+
+        fn encode(&self, out: &mut Vec<f32>) {
+            out.extend_from_slice(&[self.origin.x, self.origin.y, self.origin.z]);
+            out.extend_from_slice(&[self.direction.x, self.direction.y, self.direction.z]);
+        }
+
+        fn decode(floats: &[f32]) -> Ray {
+            Ray::new(point3(floats[0], floats[1], floats[2]), vec3(floats[3], floats[4], floats[5]), 500.0, 0.0)
+        }
+    }
+
+    #[test]
+    fn test_t_min_scales() {
+        let t_min = TMin::default();
+        let near = Ray::new(point3(0.5, 0.5, 0.5), vec3(0.0, 0.0, 2.0), 500.0, 0.0);
+        let far = Ray::new(point3(555.0, 278.0, 0.0), vec3(0.0, 0.0, 1.0), 500.0, 0.0);
+        assert!(t_min.t_min(near) < 1e-5);
+        // Well above the spacing of floats around 555
+        assert!(t_min.t_min(far) > 10.0*6.1e-5);
+        assert_eq!(t_min.t_min(Ray::new(point3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 500.0, 0.0)), 0.0);
+        // In units of the direction length
+        assert!((t_min.t_min(near)*2.0 - t_min.t_min(Ray { direction: vec3(0.0, 0.0, 1.0), ..near })).abs() < 1e-9);
+        assert!((t_min.scaled(3.0).t_min(far) - 3.0*t_min.t_min(far)).abs() < 1e-9);
+    }
+
     quickcheck ! {
         fn intersect_2_equivalence(aabb_1: AABB, aabb_2: AABB) -> () {
             let ray = Ray::new(point3(-1.0, -1.0, -1.0), vec3(1.0, 1.0, 1.0), 500.0, 0.0);
@@ -239,6 +474,95 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_spawned_rays_leave_the_surface() {
+        use std::sync::Arc;
+        use hitable::sphere::Sphere;
+        use hitable::triangle::Triangle;
+        use material::Lambertian;
+        use palette::Rgb;
+        use random::{next_f32, rand_in_unit_sphere};
+
+        let grey: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let (a, b, c) = (point3(500.0, 0.0, 0.0), point3(600.0, 10.0, 0.0), point3(500.0, 30.0, 100.0));
+        let n = (b - a).cross(c - a).normalize();
+        let (d, e, f) = (point3(-1000.0, 1.0, -1000.0), point3(1000.0, -1.0, -1000.0), point3(0.0, 0.0, 1000.0));
+        let m = (e - d).cross(f - d).normalize();
+        let uv = (vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0));
+        let near_origin = AABB { bounds: [point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0)] };
+        // Tiny, unit sized and large spheres near and far from the origin, and triangles, all without a `TMin`. The
+        // ground and the large triangle are aimed at where they pass the origin, far from their centers and vertices.
+        let objects: Vec<(Box<dyn Hitable>, f32, Option<AABB>)> = vec![
+            (Box::new(Sphere::new(point3(0.0, 0.0, 0.0), 0.01, grey.clone())), 0.01, None),
+            (Box::new(Sphere::new(point3(0.3, -0.2, 0.1), 1.0, grey.clone())), 1.0, None),
+            (Box::new(Sphere::new(point3(555.0, 278.0, -300.0), 100.0, grey.clone())), 100.0, None),
+            (Box::new(Sphere::new(point3(-20000.0, 5000.0, 10000.0), 3000.0, grey.clone())), 3000.0, None),
+            (Box::new(Sphere::new(point3(0.0, -1000.0, 0.0), 1000.0, grey.clone())), 1000.0, Some(near_origin)),
+            (Box::new(instance::translate(Sphere::new(point3(0.0, 0.0, 0.0), 1000.0, grey.clone()), vec3(0.0, -1000.0, 0.0))), 1000.0, Some(near_origin)),
+            (Box::new(Triangle::new((a, b, c), (n, n, n), uv, grey.clone())), 100.0, None),
+            (Box::new(Triangle::new((d, e, f), (m, m, m), uv, grey.clone())), 1000.0, Some(near_origin)),
+        ];
+        for &(ref object, size, region) in objects.iter() {
+            let [low, high] = region.unwrap_or(object.bbox()).bounds;
+            for _ in 0..20000 {
+                let target = point3(low.x + (high.x - low.x)*next_f32(), low.y + (high.y - low.y)*next_f32(), low.z + (high.z - low.z)*next_f32());
+                let origin = target + rand_in_unit_sphere::<f32>().normalize()*(4.0*size);
+                let r = Ray::new(origin, target - origin, 500.0, 0.0);
+                let rec = match object.hit(r, 0.0, f32::MAX) {
+                    Some(rec) => rec,
+                    None => continue,
+                };
+                // Out of the surface or into it, where only the far side of a sphere is in the way
+                let direction: Vector3D<f32, UnknownUnit> = rand_in_unit_sphere();
+                let again = object.hit(rec.spawn_ray(r, direction), 0.0, f32::MAX);
+                assert!(again.map_or(true, |again| again.t*direction.length() > 1e-3*size), "{:?} found {:?} again", rec.p, again.unwrap().p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_negative_zero_direction() {
+        // The inverse of -0 is -inf, so the slabs along x have to be taken in the order of a negative direction
+        let unit = AABB { bounds: [point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0)] };
+        let ray = Ray::new(point3(0.5, 0.0, -3.0), vec3(-0.0, 0.0, 1.0), 500.0, 0.0);
+        assert!(ray.sign.x);
+        assert_eq!(unit.intersects(ray, 0.0, 10.0), Some(2.0));
+    }
+
+    #[test]
+    fn test_flat_box() {
+        // The bounds of a line along x, where the slabs of y and z have no thickness
+        let line = AABB { bounds: [point3(0.33077013, -1.0, -0.19976974), point3(0.9435809, -1.0, -0.19976974)] };
+        let target = point3(0.33077013 + (0.9435809 - 0.33077013)*0.581623, -1.0, -0.19976974);
+        let origin = point3(-0.9305446, -1.6668441, -0.48408985);
+        let ray = Ray::new(origin, target - origin, 500.0, 0.0);
+        let t = line.intersects(ray, 0.0, f32::max_value()).expect("Expected a hit");
+        assert!(t <= 1.0);
+    }
+
+    // A ray aimed at a point inside the box enters it before reaching that point.
+    fn ray_into_aabb_hits((ray, UnitBox(aabb), (Fraction(x), Fraction(y), Fraction(z))): (Ray, UnitBox, (Fraction, Fraction, Fraction))) -> bool {
+        let AABB { bounds: [low, high] } = aabb;
+        let target = point3(low.x + (high.x - low.x)*x, low.y + (high.y - low.y)*y, low.z + (high.z - low.z)*z);
+        let ray = Ray::new(ray.origin, target - ray.origin, ray.wl, ray.ti);
+        match aabb.intersects(ray, 0.0, f32::max_value()) {
+            Some(t) => t <= 1.0,
+            None => false,
+        }
+    }
+
+    #[test]
+    fn test_ray_into_aabb_hits() {
+        corpus::check("ray_into_aabb_hits", ray_into_aabb_hits);
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use super::*;
+    use test::*;
+    use num_traits::Float;
+
     #[bench]
     fn bench_intersect_aabb_hit(bench: &mut Bencher) {
         let ray = black_box(Ray::new(point3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0));
@@ -0,0 +1,443 @@
+//! Point clouds from scanners, drawn as small discs facing along the normals of the points.
+//!
+//! Clouds are read from ASCII or binary PLY files, or from XYZ text files with one point per line:
+//!
+//! ```text
+//! # x y z nx ny nz [radius] [red green blue]
+//! 0.0 1.0 0.0 0.0 1.0 0.0 0.05 255 128 0
+//! ```
+//!
+//! Colors are sRGB, from 0 to 255. In PLY files the points are the `vertex` element, with the properties
+//! `x`, `y`, `z`, `nx`, `ny`, `nz`, and optionally `radius` and `red`, `green`, `blue`, as fractions of the largest value
+//! of their type. Points without a radius get the default one, those without a color are gray.
+
+use euclid::*;
+use palette::Rgb;
+use palette::white_point::E;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use hitable::*;
+use hitable::bvh::BVH;
+use material::Lambertian;
+
+/// A point of a cloud, a disc around `center` facing along `normal`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surfel {
+    pub center: Point3D<f32, UnknownUnit>,
+    pub normal: Vector3D<f32, UnknownUnit>,
+    pub radius: f32,
+    pub color: Rgb<E, f32>,
+}
+
+#[derive(Debug, Clone)]
+struct Disc {
+    center: Point3D<f32, UnknownUnit>,
+    normal: Vector3D<f32, UnknownUnit>,
+    radius: f32,
+    material: Lambertian<Rgb<E, f32>>,
+}
+
+impl Hitable for Disc {
+    fn bbox(&self) -> AABB {
+        let n = self.normal;
+        let extent = vec3(
+            self.radius*(1.0 - n.x*n.x).max(0.0).sqrt(),
+            self.radius*(1.0 - n.y*n.y).max(0.0).sqrt(),
+            self.radius*(1.0 - n.z*n.z).max(0.0).sqrt(),
+        );
+        AABB { bounds: [self.center - extent, self.center + extent] }
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let facing = r.direction.dot(self.normal);
+        if facing == 0.0 {
+            return None;
+        }
+        let t = (self.center - r.origin).dot(self.normal)/facing;
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        // Onto the plane of the disc, off by the rounding of its center rather than of the way along the ray
+        let p = r.point_at_parameter(t);
+        let p = p - self.normal*(p - self.center).dot(self.normal);
+        let offset = p - self.center;
+        if offset.square_length() > self.radius*self.radius {
+            return None;
+        }
+        // Across the disc in a frame of its plane
+        let n = self.normal;
+        let u = if n.x.abs() < 0.5 { vec3(0.0, -n.z, n.y).normalize() } else { vec3(-n.z, 0.0, n.x).normalize() };
+        let w = n.cross(u);
+        let uv = vec2(0.5 + 0.5*offset.dot(u)/self.radius, 0.5 + 0.5*offset.dot(w)/self.radius);
+        Some(HitRecord {
+            t,
+            p,
+            uv,
+            normal: n,
+            geometric_normal: n,
+            front_face: facing < 0.0,
+            texture: &self.material,
+            shading_rate: None,
+            object_id: None,
+            tangent: None,
+            edge_distance: None,
+            error: rounding_error(magnitude(self.center) + self.radius),
+        })
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        visit(&self.material);
+    }
+}
+
+/// A cloud of surfels in a BVH, each shaded diffuse in its own color.
+#[derive(Debug, Clone)]
+pub struct PointCloud {
+    discs: Arc<BVH<Disc>>,
+}
+
+impl PointCloud {
+    pub fn new(surfels: Vec<Surfel>) -> PointCloud {
+        let discs = surfels.into_iter().map(|surfel| Disc {
+            center: surfel.center,
+            normal: surfel.normal.normalize(),
+            radius: surfel.radius,
+            material: Lambertian::new(surfel.color),
+        }).collect();
+        PointCloud { discs: Arc::new(BVH::initialize(discs)) }
+    }
+
+    /// Load a PLY file, or an XYZ file for any other extension, giving points without a radius `radius`.
+    pub fn from_file(path: &Path, radius: f32) -> Result<PointCloud, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        let surfels = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("ply") => read_ply(reader, radius)?,
+            _ => read_xyz(reader, radius)?,
+        };
+        Ok(PointCloud::new(surfels))
+    }
+
+    pub fn len(&self) -> usize {
+        self.discs.items().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Hitable for PointCloud {
+    fn bbox(&self) -> AABB {
+        self.discs.bbox()
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.discs.hit(r, t_min, t_max)
+    }
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.discs.is_occluded(r, t_min, t_max)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.discs.visit_textures(visit);
+    }
+}
+
+fn invalid<S: Into<String>>(message: S) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+const GRAY: (f32, f32, f32) = (0.5, 0.5, 0.5);
+
+fn surfel(position: [f32; 3], normal: [f32; 3], radius: f32, color: (f32, f32, f32)) -> Surfel {
+    Surfel {
+        center: point3(position[0], position[1], position[2]),
+        normal: vec3(normal[0], normal[1], normal[2]),
+        radius,
+        color: palette::pixel::Srgb::with_wp(color.0, color.1, color.2).into(),
+    }
+}
+
+/// Read points from lines of `x y z nx ny nz`, optionally followed by a radius and a color from 0 to 255.
+/// Empty lines and those starting with `#` are skipped.
+pub fn read_xyz<R: BufRead>(reader: R, radius: f32) -> Result<Vec<Surfel>, Error> {
+    let mut surfels = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line.split_whitespace()
+            .map(|value| value.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|e| invalid(format!("line {}: {}", i + 1, e)))?;
+        let (radius, color) = match values.len() {
+            6 => (radius, GRAY),
+            7 => (values[6], GRAY),
+            9 => (radius, (values[6]/255.0, values[7]/255.0, values[8]/255.0)),
+            10 => (values[6], (values[7]/255.0, values[8]/255.0, values[9]/255.0)),
+            n => return Err(invalid(format!("line {}: expected 6, 7, 9 or 10 values, got {}", i + 1, n))),
+        };
+        surfels.push(surfel([values[0], values[1], values[2]], [values[3], values[4], values[5]], radius, color));
+    }
+    Ok(surfels)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8, U8, I16, U16, I32, U32, F32, F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar, Error> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(invalid(format!("unknown PLY type {}", name))),
+        })
+    }
+
+    /// The value colors of this type are a fraction of.
+    fn full_scale(self) -> f64 {
+        match self {
+            Scalar::I8 => i8::MAX as f64,
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::I16 => i16::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            Scalar::I32 => i32::MAX as f64,
+            Scalar::U32 => u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+
+    fn read<R: Read>(self, reader: &mut R, big_endian: bool) -> Result<f64, Error> {
+        macro_rules! read {
+            ($t:ty) => {{
+                let mut bytes = [0u8; std::mem::size_of::<$t>()];
+                reader.read_exact(&mut bytes)?;
+                (if big_endian { <$t>::from_be_bytes(bytes) } else { <$t>::from_le_bytes(bytes) }) as f64
+            }}
+        }
+        Ok(match self {
+            Scalar::I8 => read!(i8),
+            Scalar::U8 => read!(u8),
+            Scalar::I16 => read!(i16),
+            Scalar::U16 => read!(u16),
+            Scalar::I32 => read!(i32),
+            Scalar::U32 => read!(u32),
+            Scalar::F32 => read!(f32),
+            Scalar::F64 => read!(f64),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    /// Lists start with their length.
+    List(Scalar, Scalar),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    Binary { big_endian: bool },
+}
+
+/// Read the points of a PLY file, in ASCII or binary, giving points without a radius `radius`.
+pub fn read_ply<R: BufRead>(mut reader: R, radius: f32) -> Result<Vec<Surfel>, Error> {
+    let mut line = String::new();
+    let mut header = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("PLY header doesn't end"));
+        }
+        let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+        if words.first().map(String::as_str) == Some("end_header") {
+            break;
+        }
+        header.push(words);
+    }
+    if header.first().map(|words| words.as_slice()) != Some(&["ply".to_string()][..]) {
+        return Err(invalid("not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for words in &header[1..] {
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::Binary { big_endian: false }),
+            ["format", "binary_big_endian", _] => format = Some(Format::Binary { big_endian: true }),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid(format!("bad count for {}: {}", name, count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, _] => elements.last_mut()
+                .ok_or_else(|| invalid("property before any element"))?
+                .properties.push(Property::List(Scalar::parse(count)?, Scalar::parse(item)?)),
+            ["property", scalar, name] => elements.last_mut()
+                .ok_or_else(|| invalid("property before any element"))?
+                .properties.push(Property::Scalar(name.to_string(), Scalar::parse(scalar)?)),
+            ["comment", ..] | ["obj_info", ..] | [] => {},
+            _ => return Err(invalid(format!("unexpected PLY header line: {}", words.join(" ")))),
+        }
+    }
+    let format = format.ok_or_else(|| invalid("PLY format missing"))?;
+
+    let mut ascii = String::new();
+    if format == Format::Ascii {
+        reader.read_to_string(&mut ascii)?;
+    }
+    let mut tokens = ascii.split_whitespace();
+    let mut next = |reader: &mut R, scalar: Scalar| -> Result<f64, Error> {
+        match format {
+            Format::Ascii => tokens.next()
+                .ok_or_else(|| invalid("PLY data ends early"))?
+                .parse::<f64>()
+                .map_err(|e| invalid(e.to_string())),
+            Format::Binary { big_endian } => scalar.read(reader, big_endian),
+        }
+    };
+
+    for element in &elements {
+        let names: Vec<Option<&str>> = element.properties.iter().map(|property| match *property {
+            Property::Scalar(ref name, _) => Some(name.as_str()),
+            Property::List(..) => None,
+        }).collect();
+        let column = |name: &str| names.iter().position(|&n| n == Some(name));
+        let is_vertex = element.name == "vertex";
+        let columns = if is_vertex {
+            let required = |name: &str| column(name).ok_or_else(|| invalid(format!("PLY vertices need the property {}", name)));
+            Some((
+                [required("x")?, required("y")?, required("z")?],
+                [required("nx")?, required("ny")?, required("nz")?],
+                column("radius"),
+                match (column("red"), column("green"), column("blue")) {
+                    (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+                    _ => None,
+                },
+            ))
+        } else {
+            None
+        };
+        let mut surfels = Vec::with_capacity(if is_vertex { element.count } else { 0 });
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(&element.properties) {
+                match *property {
+                    Property::Scalar(_, scalar) => *value = next(&mut reader, scalar)?,
+                    Property::List(count, item) => {
+                        for _ in 0..next(&mut reader, count)? as usize {
+                            next(&mut reader, item)?;
+                        }
+                    }
+                }
+            }
+            if let Some((position, normal, radius_column, color)) = columns {
+                let get = |i: usize| values[i] as f32;
+                let scale = |i: usize| match element.properties[i] {
+                    Property::Scalar(_, scalar) => (values[i]/scalar.full_scale()) as f32,
+                    Property::List(..) => 0.0,
+                };
+                surfels.push(surfel(
+                    [get(position[0]), get(position[1]), get(position[2])],
+                    [get(normal[0]), get(normal[1]), get(normal[2])],
+                    radius_column.map_or(radius, get),
+                    color.map_or(GRAY, |c| (scale(c[0]), scale(c[1]), scale(c[2]))),
+                ));
+            }
+        }
+        if is_vertex {
+            return Ok(surfels);
+        }
+    }
+    Err(invalid("PLY file has no vertex element"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use tempfile::{Builder, NamedTempFile};
+
+    fn down(x: f32, z: f32) -> Ray {
+        Ray::new(point3(x, 10.0, z), vec3(0.0, -1.0, 0.0), 550.0, 0.0)
+    }
+
+    #[test]
+    fn test_xyz() {
+        let xyz = "# two points\n0 0 0 0 1 0\n\n2 1 0 0 1 0 0.5 255 0 0\n";
+        let surfels = read_xyz(Cursor::new(xyz), 0.1).unwrap();
+        assert_eq!(surfels.len(), 2);
+        assert_eq!(surfels[0].radius, 0.1);
+        assert_eq!(surfels[1].radius, 0.5);
+        assert_eq!(surfels[1].color, Rgb::with_wp(1.0, 0.0, 0.0));
+        assert!(read_xyz(Cursor::new("0 0 0 0 1\n"), 0.1).is_err());
+
+        let cloud = PointCloud::new(surfels);
+        assert_eq!(cloud.len(), 2);
+        let rec = cloud.hit(down(2.3, 0.3), 0.0, 100.0).unwrap();
+        assert_eq!(rec.p, point3(2.3, 1.0, 0.3));
+        assert!(rec.front_face);
+        assert!(cloud.hit(down(2.3, 0.5), 0.0, 100.0).is_none());
+        assert!(cloud.hit(down(0.05, 0.0), 0.0, 100.0).is_some());
+        assert_eq!(cloud.bbox(), AABB { bounds: [point3(-0.1, 0.0, -0.5), point3(2.5, 1.0, 0.5)] });
+    }
+
+    #[test]
+    fn test_ply_ascii_and_binary() {
+        let header = |format: &str| format!(
+            "ply\nformat {} 1.0\ncomment scanned\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\n\
+             property float nx\nproperty float ny\nproperty float nz\nproperty uchar red\nproperty uchar green\n\
+             property uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n",
+            format,
+        );
+        let ascii = header("ascii") + "0 0 0 0 0 1 255 255 255\n1 0 0 0 0 1 0 0 255\n3 0 1 2\n";
+        let mut binary = header("binary_little_endian").into_bytes();
+        for &(x, blue) in [(0.0f32, 255u8), (1.0, 255)].iter() {
+            for value in [x, 0.0, 0.0, 0.0, 0.0, 1.0].iter() {
+                binary.extend_from_slice(&value.to_le_bytes());
+            }
+            binary.extend_from_slice(&[if x == 0.0 { 255 } else { 0 }, if x == 0.0 { 255 } else { 0 }, blue]);
+        }
+        let from_ascii = read_ply(Cursor::new(ascii), 0.2).unwrap();
+        let from_binary = read_ply(Cursor::new(binary), 0.2).unwrap();
+        assert_eq!(from_ascii, from_binary);
+        assert_eq!(from_ascii.len(), 2);
+        assert_eq!(from_ascii[1].center, point3(1.0, 0.0, 0.0));
+        assert_eq!(from_ascii[1].radius, 0.2);
+        assert_eq!(from_ascii[1].color, Rgb::with_wp(0.0, 0.0, 1.0));
+
+        let without_normals = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n";
+        assert!(read_ply(Cursor::new(without_normals), 0.2).is_err());
+    }
+
+    #[test]
+    fn test_from_file_by_extension() {
+        let mut xyz = NamedTempFile::new().unwrap();
+        writeln!(xyz, "0 0 0 0 1 0").unwrap();
+        assert_eq!(PointCloud::from_file(xyz.path(), 0.1).unwrap().len(), 1);
+        let mut ply = Builder::new().suffix(".PLY").tempfile().unwrap();
+        write!(ply, "ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\nproperty float y\nproperty float z\n\
+                     property float nx\nproperty float ny\nproperty float nz\nend_header\n").unwrap();
+        assert!(PointCloud::from_file(ply.path(), 0.1).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+//! A four wide BVH with child bounds quantized to bytes, for scenes where the binary `BVH` gets too large.
+
+use hitable::*;
+use hitable::backend::IntersectionBackend;
+use hitable::bvh::{BVH, BuildStrategy, Node, Next};
+use arrayvec::*;
+use std::ops::DerefMut;
+
+/// A BVH with four children per node, built by collapsing a binary `BVH`.
+/// Every node stores its own bounds in full precision, but the bounds of its children
+/// only as bytes on a grid spanning the node, rounded outwards so they stay conservative.
+/// A node fills one cache line and the tree has about a quarter of the nodes of a binary one,
+/// so it takes less than half the memory.
+#[derive(Debug)]
+pub struct QBVH<H: Hitable> {
+    nodes: Vec<QNode>,
+    items: Vec<H>,
+    bbox: AABB,
+    /// The most nodes a traversal can have left to visit, three for every level of the tree and the root.
+    stack_size: usize,
+}
+
+const WIDTH: usize = 4;
+/// Traversals of trees needing a stack up to this size keep it on the stack of the thread, and deeper ones on the heap.
+const STACK: usize = 128;
+/// Child slots pointing to a primitive rather than a node have this bit set.
+const LEAF: u32 = 1 << 31;
+const EMPTY: u32 = u32::MAX;
+
+#[derive(Debug, Clone)]
+struct QNode {
+    /// Low corner of the quantization grid, the low corner of the node bounds.
+    origin: [f32; 3],
+    /// Size of a grid step along each axis.
+    scale: [f32; 3],
+    /// Quantized child bounds, by axis and then child.
+    low: [[u8; WIDTH]; 3],
+    high: [[u8; WIDTH]; 3],
+    /// Node index, `LEAF` with an item index, or `EMPTY`.
+    children: [u32; WIDTH],
+}
+
+impl QNode {
+    fn new(bbox: AABB) -> QNode {
+        let AABB { bounds: [low, high] } = bbox;
+        let origin = [low.x, low.y, low.z];
+        let high = [high.x, high.y, high.z];
+        let mut scale = [0.0; 3];
+        for axis in 0..3 {
+            scale[axis] = (high[axis] - origin[axis])/255.0;
+            // Rounding must not leave the far end of the node outside the grid
+            while origin[axis] + 255.0*scale[axis] < high[axis] {
+                scale[axis] = scale[axis]*(1.0 + f32::EPSILON) + f32::MIN_POSITIVE;
+            }
+        }
+        QNode { origin, scale, low: [[0; WIDTH]; 3], high: [[0; WIDTH]; 3], children: [EMPTY; WIDTH] }
+    }
+
+    #[inline(always)]
+    fn decode(&self, axis: usize, q: u8) -> f32 {
+        self.origin[axis] + q as f32*self.scale[axis]
+    }
+
+    /// Store the bounds of child `slot`, rounding them outwards to the grid.
+    fn set_child(&mut self, slot: usize, child: u32, bbox: AABB) {
+        let AABB { bounds: [low, high] } = bbox;
+        let (low, high) = ([low.x, low.y, low.z], [high.x, high.y, high.z]);
+        for axis in 0..3 {
+            let steps = |v: f32| if self.scale[axis] > 0.0 { (v - self.origin[axis])/self.scale[axis] } else { 0.0 };
+            let mut q_low = steps(low[axis]).floor().max(0.0).min(255.0) as u8;
+            while q_low > 0 && self.decode(axis, q_low) > low[axis] {
+                q_low -= 1;
+            }
+            let mut q_high = steps(high[axis]).ceil().max(0.0).min(255.0) as u8;
+            while q_high < 255 && self.decode(axis, q_high) < high[axis] {
+                q_high += 1;
+            }
+            self.low[axis][slot] = q_low;
+            self.high[axis][slot] = q_high;
+        }
+        self.children[slot] = child;
+    }
+
+    /// Entry distance of the ray into every child, infinite for missed and empty children.
+    #[inline(always)]
+    fn intersect(&self, origin: [f32; 3], inv_direction: [f32; 3], t_min: f32, t_max: f32) -> Lanes {
+        let mut near = [t_min; WIDTH];
+        let mut far = [t_max; WIDTH];
+        for axis in 0..3 {
+            // Distance to the grid origin and per grid step, so every bound costs one multiply and add
+            let start = (self.origin[axis] - origin[axis])*inv_direction[axis];
+            let step = self.scale[axis]*inv_direction[axis];
+            let (entry, exit) = if inv_direction[axis] < 0.0 {
+                (&self.high[axis], &self.low[axis])
+            } else {
+                (&self.low[axis], &self.high[axis])
+            };
+            for c in 0..WIDTH {
+                let t0 = start + entry[c] as f32*step;
+                let t1 = start + exit[c] as f32*step;
+                // Plain comparisons rather than `f32::max`, which compiles to more than one instruction to handle NaN
+                near[c] = if t0 > near[c] { t0 } else { near[c] };
+                far[c] = if t1 < far[c] { t1 } else { far[c] };
+            }
+        }
+        let mut res = [f32::INFINITY; WIDTH];
+        for c in 0..WIDTH {
+            if self.children[c] != EMPTY && near[c] <= far[c] {
+                res[c] = near[c];
+            }
+        }
+        res
+    }
+}
+
+impl<H: Hitable> QBVH<H> {
+    /// Build a QBVH, choosing the build strategy of the underlying binary BVH automatically.
+    pub fn initialize(items: Vec<H>) -> QBVH<H> {
+        QBVH::build(items, BuildStrategy::Auto)
+    }
+
+    pub fn build(items: Vec<H>, strategy: BuildStrategy) -> QBVH<H> {
+        QBVH::from_bvh(BVH::build(items, strategy))
+    }
+
+    /// Collapse a binary BVH, pulling up grandchildren until every node has four children.
+    pub fn from_bvh(bvh: BVH<H>) -> QBVH<H> {
+        let bbox = bvh.bbox();
+        let (binary, items) = bvh.into_parts();
+        QBVH::from_parts(&binary, items, bbox)
+    }
+
+    fn from_parts(binary: &[Node], items: Vec<H>, bbox: AABB) -> QBVH<H> {
+        let mut nodes = Vec::with_capacity(binary.len()/3 + 1);
+        let depth = if binary.is_empty() { 0 } else { collapse(binary, 0, &mut nodes).1 };
+        QBVH { nodes, items, bbox, stack_size: (WIDTH - 1)*depth + 1 }
+    }
+
+    /// Bytes taken by the nodes, not counting the primitives.
+    pub fn node_memory(&self) -> usize {
+        self.nodes.len()*std::mem::size_of::<QNode>()
+    }
+
+    /// The primitives in the order they were given.
+    pub fn items(&self) -> &[H] {
+        &self.items
+    }
+}
+
+fn children(binary: &[Node], i: usize) -> Option<(usize, usize)> {
+    match binary[i].next {
+        Next::Bin { left_length } => Some((i + 1, i + 1 + left_length)),
+        Next::Tip { .. } => None,
+    }
+}
+
+/// Append the node for binary node `i` and its subtree, returning its index and the depth of the subtree.
+fn collapse(binary: &[Node], i: usize, nodes: &mut Vec<QNode>) -> (u32, usize) {
+    let mut slots: ArrayVec<usize, WIDTH> = ArrayVec::new();
+    match children(binary, i) {
+        Some((left, right)) => {
+            slots.push(left);
+            slots.push(right);
+        },
+        // A tree of a single primitive
+        None => slots.push(i),
+    }
+    // Open the largest inner child until the node is full
+    while slots.len() < WIDTH {
+        let largest = slots.iter().enumerate()
+            .filter(|&(_, &child)| children(binary, child).is_some())
+            .max_by(|a, b| binary[*a.1].bbox.surface_area().partial_cmp(&binary[*b.1].bbox.surface_area()).unwrap());
+        match largest {
+            Some((slot, &child)) => {
+                let (left, right) = children(binary, child).unwrap();
+                slots[slot] = left;
+                slots.push(right);
+            },
+            None => break,
+        }
+    }
+    let index = nodes.len();
+    nodes.push(QNode::new(binary[i].bbox));
+    let mut depth = 1;
+    for (slot, &child) in slots.iter().enumerate() {
+        let pointer = match binary[child].next {
+            Next::Tip { hitable } => LEAF | hitable as u32,
+            Next::Bin { .. } => {
+                let (pointer, below) = collapse(binary, child, nodes);
+                depth = depth.max(below + 1);
+                pointer
+            },
+        };
+        nodes[index].set_child(slot, pointer, binary[child].bbox);
+    }
+    (index as u32, depth)
+}
+
+/// The nodes a traversal has left to visit, in an `ArrayVec` for trees of the usual depth or a `Vec` for deeper ones.
+trait Stack<T>: DerefMut<Target = [T]> {
+    fn push(&mut self, item: T);
+    fn pop(&mut self) -> Option<T>;
+}
+
+impl<T> Stack<T> for ArrayVec<T, STACK> {
+    fn push(&mut self, item: T) {
+        ArrayVec::push(self, item)
+    }
+    fn pop(&mut self) -> Option<T> {
+        ArrayVec::pop(self)
+    }
+}
+
+impl<T> Stack<T> for Vec<T> {
+    fn push(&mut self, item: T) {
+        Vec::push(self, item)
+    }
+    fn pop(&mut self) -> Option<T> {
+        Vec::pop(self)
+    }
+}
+
+fn prepare(r: Ray) -> ([f32; 3], [f32; 3]) {
+    ([r.origin.x, r.origin.y, r.origin.z], [r.inv_direction.x, r.inv_direction.y, r.inv_direction.z])
+}
+
+impl<H: Hitable> QBVH<H> {
+    /// The closest hit, keeping the nodes to visit with their distances on `stack`.
+    fn closest_hit<S: Stack<(u32, f32)>>(&self, stack: &mut S, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest_match = None;
+        let mut closest_so_far = t_max;
+        let (origin, inv_direction) = prepare(r);
+        stack.push((0, t_min));
+
+        while let Some((i, near)) = stack.pop() {
+            // Skip nodes behind a closer hit found since they were pushed
+            if near > closest_so_far {
+                continue;
+            }
+            if i & LEAF != 0 {
+                if let Some(hit) = self.items[(i & !LEAF) as usize].hit(r, t_min, closest_so_far) {
+                    closest_so_far = hit.t;
+                    closest_match = Some(hit);
+                }
+                continue;
+            }
+            let node = unsafe { self.nodes.get_unchecked(i as usize) };
+            let distances = node.intersect(origin, inv_direction, t_min, closest_so_far);
+            // Push the farthest children first, so the nearest is visited next
+            let base = stack.len();
+            for c in 0..WIDTH {
+                if distances[c] < f32::INFINITY {
+                    let mut j = stack.len();
+                    stack.push((node.children[c], distances[c]));
+                    while j > base && stack[j - 1].1 < distances[c] {
+                        stack.swap(j - 1, j);
+                        j -= 1;
+                    }
+                }
+            }
+        }
+
+        closest_match
+    }
+
+    /// Whether anything is hit, keeping the nodes to visit on `stack`.
+    fn any_hit<S: Stack<u32>>(&self, stack: &mut S, r: Ray, t_min: f32, t_max: f32) -> bool {
+        let (origin, inv_direction) = prepare(r);
+        stack.push(0);
+
+        while let Some(i) = stack.pop() {
+            if i & LEAF != 0 {
+                if self.items[(i & !LEAF) as usize].is_occluded(r, t_min, t_max) {
+                    return true;
+                }
+                continue;
+            }
+            let node = unsafe { self.nodes.get_unchecked(i as usize) };
+            let distances = node.intersect(origin, inv_direction, t_min, t_max);
+            for c in 0..WIDTH {
+                if distances[c] < f32::INFINITY {
+                    stack.push(node.children[c]);
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl<H: Hitable> Hitable for QBVH<H> {
+    fn bbox(&self) -> AABB {
+        self.bbox
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        if self.stack_size <= STACK {
+            self.closest_hit(&mut ArrayVec::<_, STACK>::new(), r, t_min, t_max)
+        } else {
+            self.closest_hit(&mut Vec::with_capacity(self.stack_size), r, t_min, t_max)
+        }
+    }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        if self.stack_size <= STACK {
+            self.any_hit(&mut ArrayVec::<_, STACK>::new(), r, t_min, t_max)
+        } else {
+            self.any_hit(&mut Vec::with_capacity(self.stack_size), r, t_min, t_max)
+        }
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        for item in &self.items {
+            item.visit_textures(visit);
+        }
+    }
+}
+
+impl<H: Hitable> IntersectionBackend<H> for QBVH<H> {
+    const NAME: &'static str = "qbvh";
+
+    fn build(items: Vec<H>) -> QBVH<H> {
+        QBVH::initialize(items)
+    }
+
+    fn items(&self) -> &[H] {
+        QBVH::items(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random::*;
+    use palette::*;
+    use hitable::sphere::*;
+    use texture::*;
+    use material::*;
+    use std::sync::Arc;
+
+    fn spheres(n: usize) -> Vec<Sphere> {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        (0..n)
+            .map(|_| Sphere::new((rand_in_unit_sphere::<f32>()*100.0).to_point(), next_f32(), texture.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_agrees_with_bvh() {
+        let items = spheres(2000);
+        let bvh = BVH::build(items.clone(), BuildStrategy::Sah);
+        let qbvh = QBVH::build(items, BuildStrategy::Sah);
+        assert_eq!(bvh.bbox(), qbvh.bbox());
+        assert!(qbvh.node_memory() < bvh.node_memory());
+        for _ in 0..1000 {
+            let origin = (rand_in_unit_sphere::<f32>()*300.0).to_point();
+            let ray = Ray::new(origin, rand_in_unit_sphere(), 500.0, 0.0);
+            let t_max = next_f32()*400.0;
+            let t_bvh = bvh.hit(ray, 0.001, t_max).map(|hit| hit.t);
+            let t_qbvh = qbvh.hit(ray, 0.001, t_max).map(|hit| hit.t);
+            assert_eq!(t_bvh, t_qbvh);
+            assert_eq!(qbvh.is_occluded(ray, 0.001, t_max), t_bvh.is_some());
+        }
+    }
+
+    #[test]
+    fn test_deep_trees() {
+        // A binary tree that is a chain of spheres along x, each node holding one sphere and the rest of the chain
+        let n = 400;
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let items: Vec<_> = (0..n).map(|k| Sphere::new(point3(k as f32, 0.0, 0.0), 0.4, texture.clone())).collect();
+        let bounds = |k: usize| AABB { bounds: [point3(k as f32 - 0.4, -0.4, -0.4), point3(n as f32 - 0.6, 0.4, 0.4)] };
+        let mut binary = Vec::new();
+        for (k, sphere) in items.iter().enumerate() {
+            if k + 1 < n {
+                binary.push(Node { bbox: bounds(k), next: Next::Bin { left_length: 1 } });
+            }
+            binary.push(Node { bbox: sphere.bbox(), next: Next::Tip { hitable: k } });
+        }
+        let qbvh = QBVH::from_parts(&binary, items, bounds(0));
+        assert!(qbvh.stack_size > STACK, "{}", qbvh.stack_size);
+        // Looking down the chain from its far end, every level of the tree is opened before any sphere is hit
+        let ray = Ray::new(point3(n as f32 + 10.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0), 500.0, 0.0);
+        assert!((qbvh.hit(ray, 0.0, 1000.0).unwrap().t - 10.6).abs() < 1e-3);
+        assert!(qbvh.is_occluded(ray, 0.0, 1000.0));
+        let along = Ray::new(point3(-10.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0);
+        assert!((qbvh.hit(along, 0.0, 1000.0).unwrap().t - 9.6).abs() < 1e-3);
+        assert!(qbvh.is_occluded(along, 0.0, 1000.0));
+        assert!(!qbvh.is_occluded(along, 0.0, 9.0));
+
+        let small = QBVH::initialize(spheres(2000));
+        assert!(small.stack_size <= STACK, "{}", small.stack_size);
+    }
+
+    #[test]
+    fn test_small_trees() {
+        for n in 1..6 {
+            let items = spheres(n);
+            let qbvh = QBVH::initialize(items.clone());
+            for sphere in items.iter() {
+                let ray = Ray::new(sphere.centroid() + vec3(0.0, 10.0, 0.0), vec3(0.0, -1.0, 0.0), 500.0, 0.0);
+                assert!(qbvh.hit(ray, 0.0, f32::MAX).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantized_bounds_are_conservative() {
+        // Far from the origin, so the grid steps are close to the float spacing
+        let bbox = AABB { bounds: [point3(10000.0, -3.0, 0.0), point3(10000.01, 5.0, 0.0)] };
+        let mut node = QNode::new(bbox);
+        for _ in 0..100 {
+            let (a, b) = (next_f32(), next_f32());
+            let lerp = |t: f32| bbox.bounds[0].lerp(bbox.bounds[1], t);
+            let child = AABB { bounds: [lerp(a.min(b)), lerp(a.max(b))] };
+            node.set_child(0, LEAF, child);
+            let AABB { bounds: [low, high] } = child;
+            for (axis, (l, h)) in [(low.x, high.x), (low.y, high.y), (low.z, high.z)].iter().enumerate() {
+                assert!(node.decode(axis, node.low[axis][0]) <= *l);
+                assert!(node.decode(axis, node.high[axis][0]) >= *h);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use super::*;
+    use test::*;
+    use palette::*;
+    use random::*;
+    use num_traits::Float;
+    use hitable::sphere::*;
+    use texture::*;
+    use material::*;
+    use std::sync::Arc;
+
+    fn random_spheres(n: u64) -> (Vec<Sphere>, Ray) {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let spheres = (0..n).map(|_| {
+            let radius = rand::<f32>()/10.0/f32::cbrt(n as f32);
+            Sphere::new(rand_in_unit_sphere().to_point(), radius, texture.clone())
+        }).collect();
+        let ray = black_box(Ray::new(point3(-3.0, -2.0, -1.0), Vector3D::new(3.0, 2.0, 1.0), 500.0, 0.0));
+        (spheres, ray)
+    }
+
+    #[bench]
+    fn bench_intersect_qbvh_100000(bench: &mut Bencher) {
+        let (spheres, ray) = random_spheres(100000);
+        let qbvh = QBVH::initialize(spheres);
+        bench.iter(|| black_box(qbvh.hit(ray, f32::epsilon(), f32::max_value())) );
+    }
+
+    #[bench]
+    fn bench_intersect_qbvh_1000000(bench: &mut Bencher) {
+        let (spheres, ray) = random_spheres(1000000);
+        let qbvh = QBVH::initialize(spheres);
+        bench.iter(|| black_box(qbvh.hit(ray, f32::epsilon(), f32::max_value())) );
+    }
+
+    #[bench]
+    fn bench_occluded_qbvh_100000(bench: &mut Bencher) {
+        let (spheres, ray) = random_spheres(100000);
+        let qbvh = QBVH::initialize(spheres);
+        bench.iter(|| black_box(qbvh.is_occluded(ray, f32::epsilon(), f32::max_value())) );
+    }
+}
@@ -0,0 +1,255 @@
+//! Quads intersected as bilinear patches, for the walls and floors that would otherwise be split into two triangles.
+
+use euclid::*;
+use std::sync::Arc;
+
+use hitable::*;
+use texture::Texture;
+
+/// Four corners in order around the quad, spanning the bilinear patch between them. Rays hit the patch itself, so
+/// quads with corners off a plane are curved smoothly instead of folding along a diagonal, and flat ones have no
+/// diagonal whose seam could show in interpolated normals or textures.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate euclid;
+/// # extern crate palette;
+/// # use std::sync::Arc;
+/// # use euclid::*;
+/// # use palette::Rgb;
+/// # use rayer::hitable::Hitable;
+/// # use rayer::hitable::quad::Quad;
+/// # use rayer::material::Lambertian;
+/// # use rayer::ray::Ray;
+/// let corners = [point3(-1.0, 0.0, -1.0), point3(1.0, 0.0, -1.0), point3(1.0, 0.0, 1.0), point3(-1.0, 0.0, 1.0)];
+/// let floor = Quad::uniform(corners, vec3(0.0, 1.0, 0.0), Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5))));
+/// let hit = floor.hit(Ray::new(point3(0.5, 1.0, 0.0), vec3(0.0, -1.0, 0.0), 550.0, 0.0), 0.0, 10.0).unwrap();
+/// assert!((hit.t - 1.0).abs() < 1e-6 && (hit.uv - vec2(0.75, 0.5)).length() < 1e-6);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Quad {
+    corners: [Point3D<f32, UnknownUnit>; 4],
+    normals: [Vector3D<f32, UnknownUnit>; 4],
+    uvs: [Vector2D<f32, UnknownUnit>; 4],
+    texture: Arc<dyn Texture>,
+}
+
+impl Quad {
+    pub fn new(
+        corners: [Point3D<f32, UnknownUnit>; 4],
+        normals: [Vector3D<f32, UnknownUnit>; 4],
+        uvs: [Vector2D<f32, UnknownUnit>; 4],
+        texture: Arc<dyn Texture>,
+    ) -> Quad {
+        Quad { corners, normals, uvs, texture }
+    }
+
+    /// A quad with the same normal at every corner, and texture coordinates running from (0,0) at the first corner
+    /// to (1,0) at the second and (1,1) at the third.
+    pub fn uniform(corners: [Point3D<f32, UnknownUnit>; 4], normal: Vector3D<f32, UnknownUnit>, texture: Arc<dyn Texture>) -> Quad {
+        Quad::new(corners, [normal; 4], [vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)], texture)
+    }
+
+    /// The point of the patch at `u` along the first edge and `v` along the last.
+    fn point(&self, u: f32, v: f32) -> Point3D<f32, UnknownUnit> {
+        bilinear(self.corners.map(|corner| corner.to_vector()), u, v).to_point()
+    }
+
+    /// The derivatives of the patch along `u` and `v`.
+    fn tangents(&self, u: f32, v: f32) -> (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>) {
+        let [p00, p10, p11, p01] = self.corners;
+        ((p10 - p00).lerp(p11 - p01, v), (p01 - p00).lerp(p11 - p10, u))
+    }
+
+    /// The two triangles of a fan over the corners, with their areas.
+    fn triangles(&self) -> [((Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>), f32); 2] {
+        let [a, b, c, d] = self.corners;
+        let area = |a: Point3D<f32, UnknownUnit>, b: Point3D<f32, UnknownUnit>, c: Point3D<f32, UnknownUnit>| {
+            0.5*(b - a).cross(c - a).length()
+        };
+        [((a, b, c), area(a, b, c)), ((a, c, d), area(a, c, d))]
+    }
+}
+
+/// Bilinear interpolation between values at the corners of a quad, in the order they go around it.
+fn bilinear<T>(corners: [T; 4], u: f32, v: f32) -> T
+where T: Copy + ::std::ops::Mul<f32, Output = T> + ::std::ops::Add<Output = T> {
+    let [p00, p10, p11, p01] = corners;
+    p00*((1.0 - u)*(1.0 - v)) + p10*(u*(1.0 - v)) + p11*(u*v) + p01*((1.0 - u)*v)
+}
+
+/// Where the ray hits the bilinear patch `corners` between `t_min` and `t_max`, with the coordinates of the hit along
+/// the first and last edge. Solves a quadratic for `u`, the way Reshetov's "Cool Patches" does, which turns linear
+/// for flat parallelograms.
+fn intersect(corners: [Point3D<f32, UnknownUnit>; 4], r: Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+    let [p00, p10, p11, p01] = corners;
+    let a = (p10 - p00).cross(p01 - p11).dot(r.direction) as f64;
+    let c = (p00 - r.origin).cross(r.direction).dot(p01 - p00) as f64;
+    let b = (p10 - r.origin).cross(r.direction).dot(p11 - p10) as f64 - (a + c);
+    let roots = if a.abs() < 1e-9*(b.abs() + c.abs()) {
+        if b == 0.0 {
+            return None;
+        }
+        [-c/b, f64::NAN]
+    } else {
+        let discriminant = b*b - 4.0*a*c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        // Without the cancellation of the textbook formula
+        let q = -0.5*(b + discriminant.sqrt().copysign(b));
+        [q/a, if q == 0.0 { f64::NAN } else { c/q }]
+    };
+    let mut closest = None;
+    for &u in roots.iter() {
+        let u = u as f32;
+        if !(0.0..=1.0).contains(&u) {
+            continue;
+        }
+        // The closest points of the ray and the line across the patch at u
+        let start = p00.lerp(p10, u);
+        let across = p01.lerp(p11, u) - start;
+        let offset = start - r.origin;
+        let perpendicular = r.direction.cross(across);
+        let square = perpendicular.square_length();
+        if square == 0.0 {
+            continue;
+        }
+        let v = offset.dot(r.direction.cross(perpendicular))/square;
+        let t = offset.dot(across.cross(perpendicular))/square;
+        if (0.0..=1.0).contains(&v) && t > t_min && t < closest.map_or(t_max, |(t, _, _)| t) {
+            closest = Some((t, u, v));
+        }
+    }
+    closest
+}
+
+impl Hitable for Quad {
+    fn bbox(&self) -> AABB {
+        let [a, b, c, d] = self.corners;
+        AABB { bounds: [a.min(b).min(c).min(d), a.max(b).max(c).max(d)] }
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t, u, v) = intersect(self.corners, r, t_min, t_max)?;
+        let uv = bilinear(self.uvs, u, v);
+        if !self.texture.is_opaque(uv) {
+            return None;
+        }
+        let normal = bilinear(self.normals, u, v).normalize();
+        let (along_u, along_v) = self.tangents(u, v);
+        let face = along_u.cross(along_v);
+        let geometric_normal = if face.dot(normal) < 0.0 { -face.normalize() } else { face.normalize() };
+        let front_face = r.direction.dot(geometric_normal) < 0.0;
+        // The distance to the edges across u and v is the coordinate times the height of the patch over them
+        let area = face.length();
+        let edge_distance = Some(f32::min(u.min(1.0 - u)*area/along_v.length(), v.min(1.0 - v)*area/along_u.length()));
+        let error = rounding_error(self.corners.iter().map(|&corner| magnitude(corner)).fold(0.0, f32::max));
+        Some(HitRecord {
+            p: self.point(u, v), t, normal, geometric_normal, front_face, texture: self.texture.as_ref(), uv,
+            shading_rate: None, object_id: None, tangent: None, edge_distance, error,
+        })
+    }
+
+    /// The area of the two triangles between the corners, that of the quad itself if it is flat.
+    fn surface_area(&self) -> f32 {
+        self.triangles().iter().map(|&(_, area)| area).sum()
+    }
+
+    /// Uniform over the two triangles between the corners, so uniform over flat quads, and close to it over quads
+    /// bent only a little, like the lights of scenes are.
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        let [(first, first_area), (second, second_area)] = self.triangles();
+        let total = first_area + second_area;
+        if !(total > 0.0) {
+            return None;
+        }
+        // Reuse the sample to pick a triangle, stretching what is left of it back over [0,1)
+        let share = first_area/total;
+        let (vert, x) = if u.x < share { (first, u.x/share) } else { (second, (u.x - share)/(1.0 - share)) };
+        let s = x.min(1.0).sqrt();
+        let (b1, b2) = (s*(1.0 - u.y), s*u.y);
+        let p = (vert.0.to_vector()*(1.0 - s) + vert.1.to_vector()*b1 + vert.2.to_vector()*b2).to_point();
+        let normal = (vert.1 - vert.0).cross(vert.2 - vert.0).normalize();
+        let shading_normal = bilinear(self.normals, 0.5, 0.5);
+        let normal = if normal.dot(shading_normal) < 0.0 { -normal } else { normal };
+        Some(SurfaceSample { p, normal, pdf: 1.0/total })
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        visit(self.texture.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use material::Lambertian;
+    use hitable::triangle::uniform_polygon;
+
+    fn grey() -> Arc<dyn Texture> {
+        Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn test_flat_quad() {
+        let corners = [point3(0.0, 0.0, 0.0), point3(4.0, 0.0, 0.0), point3(3.0, 0.0, 2.0), point3(1.0, 0.0, 2.0)];
+        let quad = Quad::uniform(corners, vec3(0.0, 1.0, 0.0), grey());
+        let triangles = uniform_polygon(&corners, vec3(0.0, 1.0, 0.0), grey());
+        assert_eq!(quad.surface_area(), 6.0);
+        // The same hits as the two triangles of the fan, on either side of the diagonal
+        for &(x, z) in &[(0.5, 0.1), (2.0, 1.0), (3.4, 1.0), (0.5, 1.5), (3.9, 0.1), (2.0, 2.1), (-0.1, 0.5)] {
+            let ray = Ray::new(point3(x, 2.0, z), vec3(0.0, -1.0, 0.0), 550.0, 0.0);
+            let expected = triangles.iter().filter_map(|triangle| triangle.hit(ray, 0.0, 10.0)).next();
+            let hit = quad.hit(ray, 0.0, 10.0);
+            assert_eq!(hit.is_some(), expected.is_some(), "{} {}", x, z);
+            if let (Some(hit), Some(expected)) = (hit, expected) {
+                assert!((hit.t - expected.t).abs() < 1e-5 && (hit.p - expected.p).length() < 1e-5);
+                assert_eq!(hit.geometric_normal, vec3(0.0, 1.0, 0.0));
+                assert!(hit.front_face && hit.edge_distance.unwrap() >= 0.0);
+            }
+        }
+        // From below, and beyond t_max
+        let ray = Ray::new(point3(2.0, -1.0, 1.0), vec3(0.0, 1.0, 0.0), 550.0, 0.0);
+        assert!(!quad.hit(ray, 0.0, 10.0).unwrap().front_face);
+        assert!(quad.hit(ray, 0.0, 0.5).is_none());
+    }
+
+    #[test]
+    fn test_bent_quad() {
+        // A saddle, the hyperbolic paraboloid y = x*z over the unit square
+        let corners = [point3(0.0, 0.0, 0.0), point3(1.0, 0.0, 0.0), point3(1.0, 1.0, 1.0), point3(0.0, 0.0, 1.0)];
+        let quad = Quad::uniform(corners, vec3(0.0, 1.0, 0.0), grey());
+        for &(x, z) in &[(0.25, 0.25), (0.5, 0.5), (0.9, 0.3), (0.2, 0.8), (0.95, 0.95)] {
+            let ray = Ray::new(point3(x, 3.0, z), vec3(0.0, -1.0, 0.0), 550.0, 0.0);
+            let hit = quad.hit(ray, 0.0, 10.0).unwrap();
+            assert!((hit.p.y - x*z).abs() < 1e-5 && (hit.uv - vec2(x, z)).length() < 1e-5, "{:?}", hit.p);
+            let normal = vec3(-z, 1.0, -x).normalize();
+            assert!(hit.geometric_normal.dot(normal) > 0.9999, "{:?}", hit.geometric_normal);
+        }
+        // Rising along the diagonal, where the saddle curves up as x², a ray goes in and out again
+        let ray = Ray::new(point3(-0.1, -0.19, -0.1), vec3(1.0, 0.9, 1.0), 550.0, 0.0);
+        let first = quad.hit(ray, 0.0, 10.0).unwrap();
+        let second = quad.hit(ray, first.t + 1e-3, 10.0).unwrap();
+        assert!((first.t - 0.2298).abs() < 1e-3 && (second.t - 0.8702).abs() < 1e-3, "{} {}", first.t, second.t);
+        assert!(first.front_face != second.front_face);
+    }
+
+    #[test]
+    fn test_sample_surface() {
+        let corners = [point3(0.0, 1.0, 0.0), point3(2.0, 1.0, 0.0), point3(2.0, 1.0, 1.0), point3(0.0, 1.0, 1.0)];
+        let light = Quad::uniform(corners, vec3(0.0, -1.0, 0.0), grey());
+        let n = 64;
+        let mut left = 0;
+        for i in 0..n {
+            for j in 0..n {
+                let sample = light.sample_surface(vec2((i as f32 + 0.5)/n as f32, (j as f32 + 0.5)/n as f32)).unwrap();
+                assert_eq!((sample.normal, sample.pdf), (vec3(0.0, -1.0, 0.0), 0.5));
+                assert!(sample.p.x >= 0.0 && sample.p.x <= 2.0 && sample.p.z >= 0.0 && sample.p.z <= 1.0);
+                left += (sample.p.x < 1.0) as usize;
+            }
+        }
+        assert!((left as f32/(n*n) as f32 - 0.5).abs() < 0.02);
+    }
+}
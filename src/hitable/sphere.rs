@@ -1,9 +1,12 @@
+use bumpalo::Bump;
+use bumpalo::collections::Vec as ArenaVec;
 use euclid::*;
 use ray::Ray;
 use hitable::*;
 use std::sync::Arc;
 use num_traits::FloatConst;
 use texture::Texture;
+use hitable::csg::{Solid, Interval};
 
 #[derive(Debug, Clone)]
 pub struct Sphere {
@@ -49,6 +52,51 @@ impl Sphere {
             texture,
         }
     }
+
+    fn center(&self, ti: f32) -> Point3D<f32, UnknownUnit> {
+        self.center0 + (self.center1 - self.center0) * ((ti-self.t0) / (self.t1-self.t0))
+    }
+
+    /// The distances along `r` to where it enters and leaves the sphere centered at `center`, if it crosses it.
+    fn roots(&self, r: Ray, center: Point3D<f32, UnknownUnit>) -> Option<(f32, f32)> {
+        let oc = r.origin - center;
+        let a = r.direction.dot(r.direction);
+        let b = oc.dot(r.direction);
+        let c = oc.dot(oc) - self.radius*self.radius;
+        let discriminant = b*b - a*c;
+        if discriminant > 0.0 {
+            Some(((-b - f32::sqrt(discriminant))/a, (-b + f32::sqrt(discriminant))/a))
+        } else {
+            None
+        }
+    }
+
+    fn record(&self, r: Ray, t: f32, center: Point3D<f32, UnknownUnit>, radius: f32) -> HitRecord {
+        // Back onto the sphere, off by the rounding of the center and radius rather than of the way along the ray
+        let offset = r.point_at_parameter(t) - center;
+        let offset = offset*(radius.abs()/offset.length());
+        let p = center + offset;
+        let normal = offset / radius;
+        let error = rounding_error(magnitude(center) + radius.abs());
+        let phi = f32::atan2(normal.z, normal.x);
+        let theta = f32::asin(normal.y);
+        let u = 1.0 - (phi+f32::PI()) / (f32::PI()+f32::PI());
+        let v = (theta + f32::PI()*0.5) / f32::PI();
+        let uv = vec2(u, v);
+        let front_face = r.direction.dot(normal) < 0.0;
+        HitRecord{normal, geometric_normal: normal, front_face, p, t, uv, texture: self.texture.as_ref(), shading_rate: None, object_id: None, tangent: None, edge_distance: None, error}
+    }
+}
+
+/// The ball inside the sphere, also for a negative radius, and without the cut outs of alpha masks.
+impl Solid for Sphere {
+    fn intervals<'a, 'b>(&'a self, r: Ray, arena: &'b Bump) -> ArenaVec<'b, Interval<'a>> where 'a: 'b {
+        let center = self.center(r.ti);
+        match self.roots(r, center) {
+            Some((t0, t1)) => bumpalo::vec![in arena; (self.record(r, t0, center, self.radius.abs()), self.record(r, t1, center, self.radius.abs()))],
+            None => ArenaVec::new_in(arena),
+        }
+    }
 }
 
 impl Hitable for Sphere {
@@ -67,30 +115,39 @@ impl Hitable for Sphere {
         bounds0.merge(bounds1)
     }
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let center = self.center0 + (self.center1 - self.center0) * ((r.ti-self.t0) / (self.t1-self.t0));
-        let oc = r.origin - center;
-        let a = r.direction.dot(r.direction);
-        let b = oc.dot(r.direction);
-        let c = oc.dot(oc) - self.radius*self.radius;
-        let discriminant = b*b - a*c;
-        if discriminant > 0.0 {
-            let mut t = (-b - f32::sqrt(discriminant))/a;
+        let center = self.center(r.ti);
+        let (near, far) = self.roots(r, center)?;
+        // Where the near side is cut out the ray goes on to the far side
+        for &t in [near, far].iter() {
             if !(t < t_max && t > t_min) {
-                t = (-b + f32::sqrt(discriminant))/a;
+                continue;
             }
-            if t < t_max && t > t_min {
-                let p = r.point_at_parameter(t);
-                let normal = (p-center) / self.radius;
-                let phi = f32::atan2(normal.z, normal.x);
-                let theta = f32::asin(normal.y);
-                let u = 1.0 - (phi+f32::PI()) / (f32::PI()+f32::PI());
-                let v = (theta + f32::PI()*0.5) / f32::PI();
-                let uv = vec2(u, v);
-                return Some(HitRecord{normal, p, t, uv, texture: self.texture.as_ref()});
+            let rec = self.record(r, t, center, self.radius);
+            if self.texture.is_opaque(rec.uv) {
+                return Some(rec);
             }
         }
         None
     }
+    fn surface_area(&self) -> f32 {
+        4.0*f32::PI()*self.radius*self.radius
+    }
+    /// Uniform over the sphere at its position at `t0`.
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        let z = 1.0 - 2.0*u.x;
+        let r = f32::sqrt(f32::max(0.0, 1.0 - z*z));
+        let phi = 2.0*f32::PI()*u.y;
+        let direction = vec3(r*phi.cos(), r*phi.sin(), z);
+        // A negative radius turns the normal inwards, like in `hit`
+        Some(SurfaceSample {
+            p: self.center0 + direction*self.radius,
+            normal: direction,
+            pdf: 1.0/self.surface_area(),
+        })
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        visit(self.texture.as_ref());
+    }
 }
 
 #[cfg(test)]
@@ -112,7 +169,7 @@ mod tests {
                 let p = point3(-1.0, 0.0, 0.0);
                 let normal = vec3(-1.0, 0.0, 0.0);
                 let uv = vec2(0.0, 0.5);
-                let expected = HitRecord{t, p, normal, uv, texture: texture.as_ref()};
+                let expected = HitRecord{t, p, normal, geometric_normal: normal, front_face: true, uv, texture: texture.as_ref(), shading_rate: None, object_id: None, tangent: None, edge_distance: None, error: rounding_error(1.0)};
                 assert_eq!(expected, hit);
             }
         }
@@ -125,7 +182,7 @@ mod tests {
                 let p = point3(1.0, 0.0, 0.0);
                 let normal = vec3(1.0, 0.0, 0.0);
                 let uv = vec2(0.5, 0.5);
-                let expected = HitRecord{t, p, normal, uv, texture: texture.as_ref()};
+                let expected = HitRecord{t, p, normal, geometric_normal: normal, front_face: true, uv, texture: texture.as_ref(), shading_rate: None, object_id: None, tangent: None, edge_distance: None, error: rounding_error(1.0)};
                 assert_eq!(expected, hit);
             }
         }
@@ -138,9 +195,49 @@ mod tests {
                 let p = point3(0.0, 1.0, 0.0);
                 let normal = vec3(0.0, 1.0, 0.0);
                 let uv = vec2(0.5, 1.0);
-                let expected = HitRecord{t, p, normal, uv, texture: texture.as_ref()};
+                let expected = HitRecord{t, p, normal, geometric_normal: normal, front_face: true, uv, texture: texture.as_ref(), shading_rate: None, object_id: None, tangent: None, edge_distance: None, error: rounding_error(1.0)};
                 assert_eq!(expected, hit);
             }
         }
+        // From the inside the normal still points out
+        let ray = Ray::new(point3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 2.0), 500.0, 0.0);
+        let hit = sphere.hit(ray, 0.0, 1000.0).expect("Expected a hit");
+        assert_eq!(hit.normal, vec3(0.0, 0.0, 1.0));
+        assert!(!hit.front_face);
+        assert_eq!(hit.facing_normal(), vec3(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn test_sample_surface() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let center = point3(1.0, 2.0, 3.0);
+        let sphere = Sphere::new(center, 2.0, texture);
+        assert!((sphere.surface_area() - 16.0*f32::PI()).abs() < 1e-4);
+        for i in 0..10 {
+            for j in 0..10 {
+                let u = vec2(i as f32/10.0, j as f32/10.0);
+                let sample = sphere.sample_surface(u).unwrap();
+                assert!(((sample.p - center).length() - 2.0).abs() < 1e-5);
+                assert!((sample.p - (center + sample.normal*2.0)).length() < 1e-5);
+                assert_eq!(sample.pdf, 1.0/sphere.surface_area());
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_mask() {
+        use image::{Luma, GrayImage};
+        use texture::{AlphaMask, Masked};
+        // Cut out where u < 0.5, which is the side facing -x
+        let image = Arc::new(GrayImage::from_fn(4, 1, |x, _| Luma([if x < 2 { 0 } else { 255 }])));
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, Arc::new(Masked::new(texture, AlphaMask::new(&image, 0.5))));
+        let ray = Ray::new(point3(-2.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0);
+        let hit = sphere.hit(ray, 0.0, 1000.0).expect("Expected the far side");
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert!(!hit.front_face);
+        assert!(sphere.hit(ray, 0.0, 2.5).is_none());
+        let ray = Ray::new(point3(2.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0), 500.0, 0.0);
+        assert!((sphere.hit(ray, 0.0, 1000.0).unwrap().t - 1.0).abs() < 1e-5);
     }
 }
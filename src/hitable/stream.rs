@@ -0,0 +1,384 @@
+//! Intersecting whole streams of rays with a `BVH`, for using rayer as an intersection kernel without the rest of
+//! the renderer, the way Embree's stream functions are used.
+//!
+//! The rays are sorted so that neighbours in the sorted stream go the same way from about the same place: by the
+//! octant of their direction, and then along Morton curves through their origins and their directions. They are then
+//! traced in packets of four from the same octant, which share the near and far sides of every box, so a node is
+//! fetched once for the packet rather than once per ray. Occlusion queries, for shadow rays and ambient occlusion, go
+//! the same way but stop as soon as every ray of a packet is blocked.
+//!
+//! The box tests of a packet are plain loops over the four lanes of `Lanes`, written so the compiler can turn them
+//! into SIMD instructions, but there are no intrinsics making sure it does. The benchmarks at the end of this file
+//! compare the streams with tracing the same rays one by one: on a tile of camera rays through 10000 spheres they come
+//! out about even, so the streams are for the convenience of tracing slices of rays more than for speed. A `Scene`
+//! gives its objects in a `BVH` to trace streams against with `Scene::bvh`.
+//!
+//! ```
+//! # extern crate rayer;
+//! # use rayer::prelude::*;
+//! # use std::sync::Arc;
+//! let grey: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+//! let bvh = BVH::initialize(vec![Sphere::new(point3(0.0, 0.0, 0.0), 1.0, grey)]);
+//! let rays: Vec<Ray> = (0..10)
+//!     .map(|i| Ray::new(point3(i as f32*0.25 - 1.125, 0.0, 5.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0))
+//!     .collect();
+//! let mut hits = vec![None; rays.len()];
+//! bvh.intersect_stream(&rays, 0.0, f32::MAX, &mut hits);
+//! assert_eq!(hits.iter().filter(|hit| hit.is_some()).count(), 8);
+//! assert_eq!(hits[4].unwrap().t, bvh.hit(rays[4], 0.0, f32::MAX).unwrap().t);
+//! let blocked = bvh.occluded_stream(&rays, 0.0, f32::MAX);
+//! assert!(blocked.iter().zip(&hits).all(|(blocked, hit)| blocked == hit.is_some()));
+//! ```
+
+use hitable::*;
+use hitable::bvh::{BVH, Next, morton_code};
+use arrayvec::*;
+pub use bit_vec::BitVec;
+
+/// Rays in a packet, one to a lane.
+const WIDTH: usize = 4;
+
+/// Up to four rays with directions in the same octant, with their origins and inverse directions by axis and then
+/// lane. Unused lanes repeat the first ray, and are left out of `active`.
+struct Packet {
+    origin: [Lanes; 3],
+    inv_direction: [Lanes; 3],
+    sign: [usize; 3],
+    active: u8,
+}
+
+impl Packet {
+    fn new(rays: &[Ray]) -> Packet {
+        let ray = |lane: usize| rays.get(lane).unwrap_or(&rays[0]);
+        let mut packet = Packet {
+            origin: [[0.0; WIDTH]; 3],
+            inv_direction: [[0.0; WIDTH]; 3],
+            sign: [rays[0].sign.x as usize, rays[0].sign.y as usize, rays[0].sign.z as usize],
+            active: (1 << rays.len()) - 1,
+        };
+        for lane in 0..WIDTH {
+            let r = ray(lane);
+            packet.origin[0][lane] = r.origin.x;
+            packet.origin[1][lane] = r.origin.y;
+            packet.origin[2][lane] = r.origin.z;
+            packet.inv_direction[0][lane] = r.inv_direction.x;
+            packet.inv_direction[1][lane] = r.inv_direction.y;
+            packet.inv_direction[2][lane] = r.inv_direction.z;
+        }
+        packet
+    }
+
+    /// The lanes of `lanes` whose rays enter `bbox` before their closest hit so far and leave it after `t_min`,
+    /// with the distance to where the first of them enters. The same test as `AABB::intersects`, four at a time.
+    #[inline(always)]
+    fn enter(&self, bbox: &AABB, lanes: u8, t_min: f32, closest: &Lanes) -> (u8, f32) {
+        let near = [bbox.bounds[self.sign[0]].x, bbox.bounds[self.sign[1]].y, bbox.bounds[self.sign[2]].z];
+        let far = [bbox.bounds[1 - self.sign[0]].x, bbox.bounds[1 - self.sign[1]].y, bbox.bounds[1 - self.sign[2]].z];
+        let mut entry = [f32::NEG_INFINITY; WIDTH];
+        let mut exit = [f32::INFINITY; WIDTH];
+        for axis in 0..3 {
+            for lane in 0..WIDTH {
+                // Compared rather than with `f32::max`, whose care for NaN keeps it from vectorizing as well
+                let enter = (near[axis] - self.origin[axis][lane])*self.inv_direction[axis][lane];
+                let leave = (far[axis] - self.origin[axis][lane])*self.inv_direction[axis][lane];
+                entry[lane] = if enter > entry[lane] { enter } else { entry[lane] };
+                exit[lane] = if leave < exit[lane] { leave } else { exit[lane] };
+            }
+        }
+        let mut entered = 0;
+        let mut first = f32::INFINITY;
+        for lane in 0..WIDTH {
+            let miss = entry[lane] > exit[lane] + AABB::WIGGLE_FACTOR || entry[lane] > closest[lane] || exit[lane] < t_min;
+            if !miss && lanes & (1 << lane) != 0 {
+                entered |= 1 << lane;
+                first = first.min(entry[lane]);
+            }
+        }
+        (entered, first)
+    }
+}
+
+/// Which of the eight octants the direction of `r` points into.
+fn octant(r: &Ray) -> u8 {
+    r.sign.x as u8 | (r.sign.y as u8) << 1 | (r.sign.z as u8) << 2
+}
+
+/// The indices of `rays` in an order where neighbours take similar paths through the tree.
+fn coherent_order(rays: &[Ray]) -> Vec<usize> {
+    let origins = rays.iter().fold(AABB::empty(), |bounds, r| bounds.merge(AABB { bounds: [r.origin, r.origin] }));
+    let directions = AABB { bounds: [point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0)] };
+    let mut keys: Vec<(u64, usize)> = rays.iter().enumerate().map(|(i, r)| {
+        let key = (octant(r) as u64) << 60
+            | (morton_code(r.origin, origins) as u64) << 30
+            | morton_code(r.direction.normalize().to_point(), directions) as u64;
+        (key, i)
+    }).collect();
+    keys.sort_unstable();
+    keys.into_iter().map(|(_, i)| i).collect()
+}
+
+/// The indices of `rays` in packets of up to four rays from the same octant, in coherent order.
+fn packets(rays: &[Ray]) -> Vec<ArrayVec<usize, WIDTH>> {
+    let mut packets: Vec<ArrayVec<usize, WIDTH>> = Vec::with_capacity(rays.len()/WIDTH + 8);
+    for i in coherent_order(rays) {
+        match packets.last_mut() {
+            Some(packet) if !packet.is_full() && octant(&rays[packet[0]]) == octant(&rays[i]) => packet.push(i),
+            _ => packets.push(::std::iter::once(i).collect()),
+        }
+    }
+    packets
+}
+
+impl<H: Hitable> BVH<H> {
+    /// Set `hits[i]` to the closest hit of `rays[i]` between `t_min` and `t_max`, the same one `hit` finds, but
+    /// tracing the rays in coherent packets. The more the rays have in common, like those of a tile of the camera,
+    /// the fewer nodes the packets visit. It runs on the calling thread, so streams can be traced in parallel.
+    pub fn intersect_stream<'a>(&'a self, rays: &[Ray], t_min: f32, t_max: f32, hits: &mut [Option<HitRecord<'a>>]) {
+        assert_eq!(rays.len(), hits.len(), "a hit for every ray");
+        for indices in packets(rays) {
+            let packet: ArrayVec<Ray, WIDTH> = indices.iter().map(|&i| rays[i]).collect();
+            let found = self.hit_packet(&packet, t_min, t_max);
+            for (&i, &hit) in indices.iter().zip(found.iter()) {
+                hits[i] = hit;
+            }
+        }
+    }
+
+    /// Whether anything blocks each of `rays` between `t_min` and `t_max`, as `is_occluded` would say, with bit `i`
+    /// for `rays[i]`. Rays towards points, like lights or the samples of an occlusion baker, reach them at 1 when
+    /// their directions span the distance, so a `t_max` just under 1 tests all of them at once.
+    pub fn occluded_stream(&self, rays: &[Ray], t_min: f32, t_max: f32) -> BitVec {
+        let mut occluded = BitVec::from_elem(rays.len(), false);
+        for indices in packets(rays) {
+            let packet: ArrayVec<Ray, WIDTH> = indices.iter().map(|&i| rays[i]).collect();
+            let blocked = self.occluded_packet(&packet, t_min, t_max);
+            for (lane, &i) in indices.iter().enumerate() {
+                occluded.set(i, blocked & (1 << lane) != 0);
+            }
+        }
+        occluded
+    }
+
+    /// The closest hits of up to four rays from the same octant.
+    fn hit_packet<'a>(&'a self, rays: &[Ray], t_min: f32, t_max: f32) -> [Option<HitRecord<'a>>; WIDTH] {
+        let mut hits = [None; WIDTH];
+        let nodes = self.nodes();
+        let items = self.items();
+        let packet = Packet::new(rays);
+        let mut closest = [t_max; WIDTH];
+        let root = match nodes.first() {
+            Some(root) => packet.enter(&root.bbox, packet.active, t_min, &closest).0,
+            None => return hits,
+        };
+        // Every node goes with the lanes that entered it
+        let mut stack: ArrayVec<(usize, u8), 64> = ArrayVec::new();
+        if root != 0 {
+            stack.push((0, root));
+        }
+        while let Some((i, lanes)) = stack.pop() {
+            // A ray left on its own is cheaper to trace by itself than in a packet of empty lanes
+            if lanes.count_ones() == 1 {
+                let lane = lanes.trailing_zeros() as usize;
+                let r = rays[lane];
+                if let Some(hit) = self.hit_below(i, r, t_min, closest[lane], |item, closest| item.hit(r, t_min, closest)) {
+                    closest[lane] = hit.t;
+                    hits[lane] = Some(hit);
+                }
+                continue;
+            }
+            match nodes[i].next {
+                Next::Bin { left_length } => {
+                    let (left, right) = (i + 1, i + 1 + left_length);
+                    let (left_lanes, left_entry) = packet.enter(&nodes[left].bbox, lanes, t_min, &closest);
+                    let (right_lanes, right_entry) = packet.enter(&nodes[right].bbox, lanes, t_min, &closest);
+                    // The child the packet enters first goes on top
+                    let mut children = [(left, left_lanes, left_entry), (right, right_lanes, right_entry)];
+                    if left_entry < right_entry {
+                        children.swap(0, 1);
+                    }
+                    for &(child, lanes, _) in &children {
+                        if lanes != 0 {
+                            stack.push((child, lanes));
+                        }
+                    }
+                },
+                Next::Tip { hitable } => {
+                    for (lane, r) in rays.iter().enumerate() {
+                        if lanes & (1 << lane) == 0 {
+                            continue;
+                        }
+                        if let Some(hit) = items[hitable].hit(*r, t_min, closest[lane]) {
+                            closest[lane] = hit.t;
+                            hits[lane] = Some(hit);
+                        }
+                    }
+                },
+            }
+        }
+        hits
+    }
+
+    /// The lanes of up to four rays from the same octant that something blocks.
+    fn occluded_packet(&self, rays: &[Ray], t_min: f32, t_max: f32) -> u8 {
+        let nodes = self.nodes();
+        let items = self.items();
+        let packet = Packet::new(rays);
+        let range = [t_max; WIDTH];
+        let mut blocked = 0;
+        let mut stack: ArrayVec<(usize, u8), 64> = ArrayVec::new();
+        match nodes.first() {
+            Some(root) => stack.push((0, packet.enter(&root.bbox, packet.active, t_min, &range).0)),
+            None => return blocked,
+        }
+        // Any hit will do, so the children are visited in whatever order, and rays leave the packet once blocked
+        while let Some((i, lanes)) = stack.pop() {
+            let lanes = lanes & !blocked;
+            if lanes == 0 {
+                continue;
+            }
+            if lanes.count_ones() == 1 {
+                let lane = lanes.trailing_zeros() as usize;
+                let r = rays[lane];
+                if self.is_occluded_below(i, r, t_min, t_max, |item| item.is_occluded(r, t_min, t_max)) {
+                    blocked |= lanes;
+                }
+                continue;
+            }
+            match nodes[i].next {
+                Next::Bin { left_length } => {
+                    for child in [i + 1 + left_length, i + 1] {
+                        let (entered, _) = packet.enter(&nodes[child].bbox, lanes, t_min, &range);
+                        if entered != 0 {
+                            stack.push((child, entered));
+                        }
+                    }
+                },
+                Next::Tip { hitable } => {
+                    for (lane, r) in rays.iter().enumerate() {
+                        if lanes & (1 << lane) != 0 && items[hitable].is_occluded(*r, t_min, t_max) {
+                            blocked |= 1 << lane;
+                        }
+                    }
+                    if blocked == packet.active {
+                        break;
+                    }
+                },
+            }
+        }
+        blocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use random::*;
+    use hitable::sphere::Sphere;
+    use material::Lambertian;
+    use std::sync::Arc;
+
+    fn spheres(n: usize) -> Vec<Sphere> {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let mut spheres: Vec<Sphere> = (0..n)
+            .map(|_| Sphere::new(rand_in_unit_sphere().to_point(), next_f32()/20.0, texture.clone()))
+            .collect();
+        spheres.push(Sphere::new(point3(0.0, -1000.0, 0.0), 998.0, texture));
+        spheres
+    }
+
+    #[test]
+    fn test_stream_agrees_with_hit() {
+        let bvh = BVH::initialize(spheres(500));
+        // A fan of rays from one point, as from the camera, and rays going every which way
+        let camera = point3(0.0, 0.5, 4.0);
+        let mut rays: Vec<Ray> = (0..31*33)
+            .map(|i| Ray::new(camera, vec3((i%31) as f32/15.0 - 1.0, (i/31) as f32/16.0 - 1.0, -2.0), 500.0, 0.0))
+            .collect();
+        rays.extend((0..1001).map(|_| {
+            let origin = (rand_in_unit_sphere::<f32>()*3.0).to_point();
+            Ray::new(origin, rand_in_unit_sphere(), 500.0, 0.0)
+        }));
+        let mut hits = vec![None; rays.len()];
+        bvh.intersect_stream(&rays, 0.001, 10.0, &mut hits);
+        for (r, hit) in rays.iter().zip(hits.iter()) {
+            assert_eq!(hit.map(|hit| hit.t), bvh.hit(*r, 0.001, 10.0).map(|hit| hit.t), "{:?}", r);
+        }
+        assert!(hits.iter().any(|hit| hit.is_none()) && hits.iter().any(|hit| hit.is_some()));
+    }
+
+    #[test]
+    fn test_occluded_stream_agrees_with_is_occluded() {
+        let bvh = BVH::initialize(spheres(500));
+        // From points on the ground to random points above it, the way an occlusion baker would ask
+        let rays: Vec<Ray> = (0..2001).map(|_| {
+            let origin = point3(next_f32()*4.0 - 2.0, -2.0, next_f32()*4.0 - 2.0);
+            let target = (rand_in_unit_sphere::<f32>()*2.0).to_point();
+            Ray::new(origin, target - origin, 500.0, 0.0)
+        }).collect();
+        let occluded = bvh.occluded_stream(&rays, 0.001, 0.999);
+        assert_eq!(occluded.len(), rays.len());
+        for (r, blocked) in rays.iter().zip(occluded.iter()) {
+            assert_eq!(blocked, bvh.is_occluded(*r, 0.001, 0.999), "{:?}", r);
+        }
+        assert!(occluded.any() && !occluded.all());
+    }
+
+    #[test]
+    fn test_empty_streams() {
+        let bvh = BVH::initialize(Vec::<Sphere>::new());
+        let rays = [Ray::new(point3(0.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0), 500.0, 0.0)];
+        let mut hits = [None];
+        bvh.intersect_stream(&rays, 0.0, f32::MAX, &mut hits);
+        assert!(hits[0].is_none());
+        assert!(bvh.occluded_stream(&rays, 0.0, f32::MAX).none());
+        BVH::initialize(spheres(10)).intersect_stream(&[], 0.0, f32::MAX, &mut []);
+        assert!(BVH::initialize(spheres(10)).occluded_stream(&[], 0.0, f32::MAX).is_empty());
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use super::*;
+    use test::*;
+    use palette::Rgb;
+    use random::*;
+    use hitable::sphere::Sphere;
+    use material::Lambertian;
+    use std::sync::Arc;
+
+    /// A tile of 64 by 64 camera rays a pixel apart in an image 1024 wide, through 10000 spheres.
+    fn tile() -> (BVH<Sphere>, Vec<Ray>) {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let spheres = (0..10000).map(|_| Sphere::new(rand_in_unit_sphere().to_point(), next_f32()/20.0, texture.clone()));
+        let rays = (0..64*64)
+            .map(|i| Ray::new(point3(0.0, 0.0, 3.0), vec3((i%64) as f32/1024.0 - 0.03, (i/64) as f32/1024.0 - 0.03, -1.0), 500.0, 0.0))
+            .collect();
+        (BVH::initialize(spheres.collect()), rays)
+    }
+
+    #[bench]
+    fn bench_intersect_stream(bench: &mut Bencher) {
+        let (bvh, rays) = tile();
+        let mut hits = vec![None; rays.len()];
+        bench.iter(|| bvh.intersect_stream(&rays, 0.0, f32::MAX, black_box(&mut hits)));
+    }
+
+    #[bench]
+    fn bench_occluded_stream(bench: &mut Bencher) {
+        let (bvh, rays) = tile();
+        bench.iter(|| black_box(bvh.occluded_stream(&rays, 0.0, 10.0)));
+    }
+
+    #[bench]
+    fn bench_occluded_one_by_one(bench: &mut Bencher) {
+        let (bvh, rays) = tile();
+        bench.iter(|| for &r in &rays { black_box(bvh.is_occluded(r, 0.0, 10.0)); });
+    }
+
+    #[bench]
+    fn bench_intersect_one_by_one(bench: &mut Bencher) {
+        let (bvh, rays) = tile();
+        bench.iter(|| for &r in &rays { black_box(bvh.hit(r, 0.0, f32::MAX)); });
+    }
+}
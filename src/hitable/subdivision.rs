@@ -0,0 +1,327 @@
+//! Loop subdivision of obj meshes, so low-poly models render as the smooth surfaces they stand for.
+//!
+//! ```
+//! # extern crate rayer;
+//! # use rayer::hitable::wavefront::*;
+//! let tetrahedron = ObjMesh::parse(b"v 1 1 1\nv 1 -1 -1\nv -1 1 -1\nv -1 -1 1\nf 1 2 3\nf 1 3 4\nf 1 4 2\nf 2 4 3\n", |_, _| {}).unwrap();
+//! let smooth = tetrahedron.subdivided(2);
+//! assert_eq!(smooth.triangles.len(), 64);
+//! assert_eq!(smooth.normals.len(), smooth.positions.len());
+//! ```
+
+use euclid::*;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use hitable::wavefront::{ObjMesh, ObjVertex};
+
+type Vector = Vector3D<f32, UnknownUnit>;
+
+/// The most levels the command line and scene descriptions subdivide meshes by, as every level quadruples the triangles.
+pub const MAX_LEVELS: u32 = 6;
+
+impl ObjMesh {
+    /// The mesh with every triangle split into four `levels` times by Loop's scheme, and its vertices moved onto the
+    /// limit surface the scheme converges to, with the normals of that surface. The normals of the file are replaced,
+    /// smoothing out any creases they made, and texture coordinates are interpolated linearly. Open borders are
+    /// smoothed as curves of their own, except for corners on a single triangle, and vertices where more than two
+    /// triangles share an edge stay where they are.
+    pub fn subdivided(&self, levels: u32) -> ObjMesh {
+        if levels == 0 {
+            return self.clone();
+        }
+        let mut mesh = ObjMesh {
+            normals: Vec::new(),
+            triangles: self.triangles.iter().map(|corners| corners.map(|corner| ObjVertex { normal: None, ..corner })).collect(),
+            ..self.clone()
+        };
+        for _ in 0..levels {
+            mesh = mesh.refined();
+        }
+        mesh.limit()
+    }
+
+    /// One level of subdivision.
+    fn refined(&self) -> ObjMesh {
+        let topology = Topology::new(self);
+        let p = |i: u32| self.positions[i as usize].to_vector();
+        let mut positions: Vec<_> = (0..self.positions.len() as u32).map(|v| match topology.kind(v) {
+            Kind::Interior => {
+                let neighbours = &topology.neighbours[v as usize];
+                let n = neighbours.len() as f32;
+                let beta = if neighbours.len() == 3 { 3.0/16.0 } else { 3.0/(8.0*n) };
+                (p(v)*(1.0 - n*beta) + sum(neighbours, p)*beta).to_point()
+            },
+            Kind::Border(a, b) => (p(v)*0.75 + (p(a) + p(b))*0.125).to_point(),
+            Kind::Fixed => p(v).to_point(),
+        }).collect();
+        let mut uvs = self.uvs.clone();
+
+        // The new vertices on the edges, made once for the triangles on both sides
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut uv_midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: ObjVertex, b: ObjVertex| {
+            let position = *midpoints.entry(key(a.position, b.position)).or_insert_with(|| {
+                let edge = topology.edges[&key(a.position, b.position)];
+                let (a, b) = (p(a.position), p(b.position));
+                let point = if edge.faces == 2 {
+                    (a + b)*0.375 + (p(edge.opposite[0]) + p(edge.opposite[1]))*0.125
+                } else {
+                    (a + b)*0.5
+                };
+                positions.push(point.to_point());
+                positions.len() as u32 - 1
+            });
+            let uv = match (a.uv, b.uv) {
+                (Some(a), Some(b)) => Some(*uv_midpoints.entry(key(a, b)).or_insert_with(|| {
+                    uvs.push(uvs[a as usize].lerp(uvs[b as usize], 0.5));
+                    uvs.len() as u32 - 1
+                })),
+                _ => None,
+            };
+            ObjVertex { position, uv, normal: None }
+        };
+        let mut triangles = Vec::with_capacity(4*self.triangles.len());
+        for &[a, b, c] in &self.triangles {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            triangles.extend_from_slice(&[[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]);
+        }
+        ObjMesh { positions, uvs, normals: Vec::new(), triangles }
+    }
+
+    /// The mesh with its vertices moved onto the limit surface, each with the normal of the surface there.
+    fn limit(self) -> ObjMesh {
+        let topology = Topology::new(&self);
+        let p = |i: u32| self.positions[i as usize].to_vector();
+        // The corners after and before each position on its triangles, in the order of their winding
+        let mut fans = vec![Vec::new(); self.positions.len()];
+        let mut face_normals = vec![vec3(0.0, 0.0, 0.0); self.positions.len()];
+        for corners in &self.triangles {
+            let [a, b, c] = corners.map(|corner| corner.position);
+            let normal = (p(b) - p(a)).cross(p(c) - p(a));
+            for &(v, next, previous) in &[(a, b, c), (b, c, a), (c, a, b)] {
+                fans[v as usize].push((next, previous));
+                face_normals[v as usize] += normal;
+            }
+        }
+        let mut positions = Vec::with_capacity(self.positions.len());
+        let mut normals = Vec::with_capacity(self.positions.len());
+        for v in 0..self.positions.len() as u32 {
+            let neighbours = &topology.neighbours[v as usize];
+            let ring = ring(&fans[v as usize]);
+            let (position, tangents) = match (topology.kind(v), ring) {
+                (Kind::Interior, Some(Ring::Closed(ring))) => {
+                    let n = neighbours.len() as f32;
+                    let beta = if neighbours.len() == 3 { 3.0/16.0 } else { 3.0/(8.0*n) };
+                    let gamma = 1.0/(n + 3.0/(8.0*beta));
+                    let position = p(v)*(1.0 - n*gamma) + sum(neighbours, p)*gamma;
+                    let angle = |i: usize| 2.0*PI*i as f32/ring.len() as f32;
+                    let s = ring.iter().enumerate().fold(vec3(0.0, 0.0, 0.0), |s, (i, &r)| s + p(r)*angle(i).cos());
+                    let t = ring.iter().enumerate().fold(vec3(0.0, 0.0, 0.0), |t, (i, &r)| t + p(r)*angle(i).sin());
+                    (position, Some((s, t)))
+                },
+                (Kind::Border(a, b), ring) => {
+                    let position = p(v)*0.6 + (p(a) + p(b))*0.2;
+                    let tangents = match ring {
+                        Some(Ring::Open(ring)) => Some(border_tangents(p(v), &ring.iter().map(|&r| p(r)).collect::<Vec<_>>())),
+                        _ => None,
+                    };
+                    (position, tangents)
+                },
+                (Kind::Interior, _) | (Kind::Fixed, _) => (p(v), None),
+            };
+            // The tangents give the direction of the normal, the triangles which side it is on
+            let face_normal = face_normals[v as usize];
+            let normal = match tangents.map(|(s, t)| s.cross(t)) {
+                Some(normal) if normal.square_length() > 1e-6*face_normal.square_length() && normal.square_length() > 0.0 => {
+                    if normal.dot(face_normal) < 0.0 { -normal } else { normal }
+                },
+                _ => face_normal,
+            };
+            positions.push(position.to_point());
+            normals.push(if normal.square_length() > 0.0 { normal.normalize() } else { normal });
+        }
+        let triangles = self.triangles.iter()
+            .map(|corners| corners.map(|corner| ObjVertex { normal: Some(corner.position), ..corner }))
+            .collect();
+        ObjMesh { positions, uvs: self.uvs, normals, triangles }
+    }
+}
+
+/// The tangents of the limit surface at a vertex `v` on a border, from its neighbours in order from one end of the
+/// border to the other.
+fn border_tangents(v: Vector, ring: &[Vector]) -> (Vector, Vector) {
+    let valence = ring.len();
+    let along = ring[valence - 1] - ring[0];
+    let across = match valence {
+        2 => ring[0] + ring[1] - v*2.0,
+        3 => ring[1] - v,
+        4 => -ring[0] + ring[1]*2.0 + ring[2]*2.0 - ring[3] - v*2.0,
+        _ => {
+            let theta = PI/(valence - 1) as f32;
+            let inner = (1..valence - 1).fold(vec3(0.0, 0.0, 0.0), |t, k| t + ring[k]*((2.0*theta.cos() - 2.0)*(k as f32*theta).sin()));
+            -((ring[0] + ring[valence - 1])*theta.sin() + inner)
+        },
+    };
+    (along, across)
+}
+
+fn sum<F: Fn(u32) -> Vector>(vertices: &[u32], p: F) -> Vector {
+    vertices.iter().fold(vec3(0.0, 0.0, 0.0), |sum, &v| sum + p(v))
+}
+
+fn key(a: u32, b: u32) -> (u32, u32) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+#[derive(Clone, Copy)]
+struct Edge {
+    faces: u32,
+    /// The corners facing the edge on its first two triangles.
+    opposite: [u32; 2],
+}
+
+enum Kind {
+    Interior,
+    /// On an open border, between the two neighbours along it.
+    Border(u32, u32),
+    /// Where the surface isn't a plain sheet, like on edges of more than two triangles or where borders meet, or on the
+    /// corner of a border with a single triangle.
+    Fixed,
+}
+
+/// How the triangles of a mesh connect through the positions of their corners.
+struct Topology {
+    edges: HashMap<(u32, u32), Edge>,
+    /// The positions sharing an edge with each position.
+    neighbours: Vec<Vec<u32>>,
+}
+
+impl Topology {
+    fn new(mesh: &ObjMesh) -> Topology {
+        let mut edges: HashMap<(u32, u32), Edge> = HashMap::new();
+        let mut neighbours = vec![Vec::new(); mesh.positions.len()];
+        for corners in &mesh.triangles {
+            let [a, b, c] = corners.map(|corner| corner.position);
+            for &(a, b, opposite) in &[(a, b, c), (b, c, a), (c, a, b)] {
+                let edge = edges.entry(key(a, b)).or_insert(Edge { faces: 0, opposite: [opposite; 2] });
+                if edge.faces == 0 && a != b {
+                    neighbours[a as usize].push(b);
+                    neighbours[b as usize].push(a);
+                }
+                if edge.faces < 2 {
+                    edge.opposite[edge.faces as usize] = opposite;
+                }
+                edge.faces += 1;
+            }
+        }
+        Topology { edges, neighbours }
+    }
+
+    fn kind(&self, v: u32) -> Kind {
+        let mut border = Vec::new();
+        for &w in &self.neighbours[v as usize] {
+            match self.edges[&key(v, w)].faces {
+                1 => border.push(w),
+                2 => {},
+                _ => return Kind::Fixed,
+            }
+        }
+        match border[..] {
+            [] if !self.neighbours[v as usize].is_empty() => Kind::Interior,
+            [a, b] if self.neighbours[v as usize].len() > 2 => Kind::Border(a, b),
+            _ => Kind::Fixed,
+        }
+    }
+}
+
+enum Ring {
+    Closed(Vec<u32>),
+    Open(Vec<u32>),
+}
+
+/// The neighbours of a vertex in the order its triangles wind around it, from the corners after and before it on each
+/// triangle. None where the triangles don't form a single fan, wound the same way.
+fn ring(fan: &[(u32, u32)]) -> Option<Ring> {
+    // An open fan starts at the corner no triangle leads to
+    let start = fan.iter().map(|&(next, _)| next).find(|&next| fan.iter().all(|&(_, previous)| previous != next));
+    let mut ring = vec![start.unwrap_or(fan.first()?.0)];
+    for _ in 0..fan.len() {
+        let last = *ring.last().unwrap();
+        let &(_, previous) = fan.iter().find(|&&(next, _)| next == last)?;
+        if ring[1..].contains(&previous) {
+            return None;
+        }
+        ring.push(previous);
+    }
+    match start {
+        None if ring[0] == ring[fan.len()] => {
+            ring.pop();
+            Some(Ring::Closed(ring))
+        },
+        Some(_) if !ring[..fan.len()].contains(&ring[fan.len()]) => Some(Ring::Open(ring)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> ObjMesh {
+        ObjMesh::parse(data, |_, _| {}).unwrap()
+    }
+
+    #[test]
+    fn test_counts() {
+        let tetrahedron = parse(b"v 1 1 1\nv 1 -1 -1\nv -1 1 -1\nv -1 -1 1\nf 1 2 3\nf 1 3 4\nf 1 4 2\nf 2 4 3\n");
+        assert_eq!(tetrahedron.subdivided(0), tetrahedron);
+        // Every level adds a vertex on each edge and splits each triangle into four
+        let smooth = tetrahedron.subdivided(2);
+        assert_eq!((smooth.positions.len(), smooth.triangles.len()), (34, 64));
+        // The limit surface lies within the hull, and its normals point out
+        for (position, normal) in smooth.positions.iter().zip(&smooth.normals) {
+            assert!(position.to_vector().length() < 3.0f32.sqrt());
+            assert!(normal.dot(position.to_vector()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_octahedron() {
+        let octahedron = parse(b"v 1 0 0\nv -1 0 0\nv 0 1 0\nv 0 -1 0\nv 0 0 1\nv 0 0 -1\n\
+            f 1 3 5\nf 3 2 5\nf 2 4 5\nf 4 1 5\nf 3 1 6\nf 2 3 6\nf 4 2 6\nf 1 4 6\n");
+        let smooth = octahedron.subdivided(3);
+        assert_eq!(smooth.triangles.len(), 8*64);
+        // Roughly round, with normals close to those of the triangles around each vertex
+        let radii: Vec<f32> = smooth.positions.iter().map(|position| position.to_vector().length()).collect();
+        let (min, max) = radii.iter().fold((f32::MAX, 0.0f32), |(min, max), &r| (min.min(r), max.max(r)));
+        assert!(max/min < 1.25, "{} {}", min, max);
+        let mut around = vec![vec3(0.0, 0.0, 0.0); smooth.positions.len()];
+        for corners in &smooth.triangles {
+            let [a, b, c] = corners.map(|corner| smooth.positions[corner.position as usize]);
+            for corner in corners {
+                around[corner.position as usize] += (b - a).cross(c - a);
+            }
+        }
+        for (normal, around) in smooth.normals.iter().zip(around) {
+            assert!(normal.dot(around.normalize()) > 0.99, "{:?} {:?}", normal, around.normalize());
+        }
+        assert!(smooth.triangles.iter().all(|corners| corners.iter().all(|corner| corner.normal == Some(corner.position))));
+    }
+
+    #[test]
+    fn test_open_border() {
+        let quad = parse(b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n");
+        let smooth = quad.subdivided(2);
+        assert_eq!(smooth.triangles.len(), 32);
+        // The square stays flat, and the corners on a single triangle stay where they are
+        assert!(smooth.positions.iter().all(|position| position.z == 0.0));
+        assert!(smooth.normals.iter().all(|&normal| normal == vec3(0.0, 0.0, 1.0)));
+        assert_eq!((smooth.positions[1], smooth.positions[3]), (quad.positions[1], quad.positions[3]));
+        assert!(smooth.positions[0] != quad.positions[0]);
+        for corner in smooth.triangles.iter().flat_map(|corners| corners.iter()) {
+            let (position, uv) = (smooth.positions[corner.position as usize], smooth.uvs[corner.uv.unwrap() as usize]);
+            assert!((position.x - uv.x).abs() < 0.2 && (position.y - uv.y).abs() < 0.2, "{:?} {:?}", position, uv);
+        }
+    }
+}
@@ -1,11 +1,13 @@
 use euclid::*;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::path::Path;
-use std::io::Error;
-use obj::{SimplePolygon, Obj};
+use std::io::{Error, ErrorKind};
 
 use hitable::*;
-use hitable::bvh::BVH;
+use hitable::bvh::{BVH, BuildStrategy};
+use hitable::backend::IntersectionBackend;
+use hitable::wavefront::{ObjMesh, ObjVertex};
 use texture::Texture;
 
 #[derive(Debug, Clone)]
@@ -14,6 +16,47 @@ pub struct Triangle {
     normal: (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>),
     uv: (Vector2D<f32, UnknownUnit>, Vector2D<f32, UnknownUnit>, Vector2D<f32, UnknownUnit>),
     texture: Arc<dyn Texture>,
+    /// Boxed, as most triangles stand still.
+    motion: Option<Box<VertexMotion>>,
+}
+
+/// Where the vertices of a deforming triangle end up, moving in a straight line from `t0` to `t1`.
+#[derive(Debug, Clone)]
+struct VertexMotion {
+    vert: (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+    normal: (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>),
+    t0: f32,
+    t1: f32,
+}
+
+fn bounds(vert: &[Point3D<f32, UnknownUnit>]) -> AABB {
+    let mut low = vert[0];
+    let mut high = vert[0];
+    for obj in vert[1..].iter() {
+        low = point3(
+            f32::min(low.x, obj.x),
+            f32::min(low.y, obj.y),
+            f32::min(low.z, obj.z),
+        );
+        high = point3(
+            f32::max(high.x, obj.x),
+            f32::max(high.y, obj.y),
+            f32::max(high.z, obj.z),
+        );
+    }
+    AABB { bounds: [low, high] }
+}
+
+/// How far along its motion from `t0` to `t1` a vertex is at the ray time `ti`, in [0,1].
+/// Motion that takes no time jumps from the start to the end at `t0`.
+fn motion_fraction(ti: f32, t0: f32, t1: f32) -> f32 {
+    if t1 > t0 {
+        ((ti - t0) / (t1 - t0)).clamp(0.0, 1.0)
+    } else if ti < t0 {
+        0.0
+    } else {
+        1.0
+    }
 }
 
 impl Triangle {
@@ -28,6 +71,41 @@ impl Triangle {
             normal,
             uv,
             texture,
+            motion: None,
+        }
+    }
+
+    /// Move every vertex in a straight line to `vert` with the normal `normal`, from the ray time `t0` to `t1`,
+    /// like the two samples of a deforming mesh cached at the start and end of the shutter.
+    pub fn with_motion(
+        self,
+        vert: (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+        normal: (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>),
+        t0: f32,
+        t1: f32,
+    ) -> Triangle {
+        Triangle { motion: Some(Box::new(VertexMotion { vert, normal, t0, t1 })), ..self }
+    }
+
+    /// The vertices at the start of the motion, if the triangle moves.
+    pub fn vertices(&self) -> (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>) {
+        self.vert
+    }
+
+    /// The vertices and normals at the ray time `ti`.
+    fn at_time(&self, ti: f32) -> (
+        (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+        (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>),
+    ) {
+        match self.motion {
+            None => (self.vert, self.normal),
+            Some(ref motion) => {
+                let s = motion_fraction(ti, motion.t0, motion.t1);
+                (
+                    (self.vert.0.lerp(motion.vert.0, s), self.vert.1.lerp(motion.vert.1, s), self.vert.2.lerp(motion.vert.2, s)),
+                    (self.normal.0.lerp(motion.normal.0, s), self.normal.1.lerp(motion.normal.1, s), self.normal.2.lerp(motion.normal.2, s)),
+                )
+            },
         }
     }
 }
@@ -54,63 +132,140 @@ pub fn polygon(
 }
 
 impl Hitable for Triangle {
+    /// Moving triangles stay within the bounds of their start and end, as every vertex moves in a straight line.
     fn bbox(&self) -> AABB {
-        let mut low = self.vert.0;
-        let mut high = self.vert.0;
-        for obj in [self.vert.1, self.vert.2].iter() {
-            low = point3(
-                f32::min(low.x, obj.x),
-                f32::min(low.y, obj.y),
-                f32::min(low.z, obj.z),
-            );
-            high = point3(
-                f32::max(high.x, obj.x),
-                f32::max(high.y, obj.y),
-                f32::max(high.z, obj.z),
-            );
+        let bbox = bounds(&[self.vert.0, self.vert.1, self.vert.2]);
+        match self.motion {
+            None => bbox,
+            Some(ref motion) => bbox.merge(bounds(&[motion.vert.0, motion.vert.1, motion.vert.2])),
         }
-        AABB { bounds: [low, high] }
     }
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        // find vectors for two edges sharing vert0
-        let edge1 = self.vert.1 - self.vert.0;
-        let edge2 = self.vert.2 - self.vert.0;
-        // begin calculating determinant also used to calculate U parameter
-        let pvec = r.direction.cross(edge2);
-        // if determinant is near zero ray lies in plane of triangle
-        let det = edge1.dot(pvec);
-        if !det.is_normal() {
-            return None;
-        }
-        let inv_det = det.recip();
-        // calculate distance from vert0 to ray origin
-        let tvec = r.origin - self.vert.0;
-        // calculate U parameter and test bounds
-        let u = tvec.dot(pvec) * inv_det;
-        if u<0.0 || u>1.0 {
-            return None;
-        }
-        // prepare to test V parameter
-        let qvec = tvec.cross(edge1);
-        // calculate V parameter and test bounds
-        let v = r.direction.dot(qvec) * inv_det;
-        if v<0.0 || v>1.0 {
-            return None;
-        }
-        // calculate t, ray intersects triangle
-        let t = edge2.dot(qvec) * inv_det;
-        if t<=t_min || t>=t_max {
-            return None;
-        }
+        let (vert, normals) = self.at_time(r.ti);
+        let (t, u, v) = intersect(vert, r, t_min, t_max)?;
         let w = 1.0 - u - v;
-        if w<0.0 || w>1.0 {
+        // u and v weigh the second and third vertex
+        let normal = (normals.0*w + normals.1*u + normals.2*v).normalize();
+        let (p, error) = interpolate(vert, w, u, v);
+        let uv = self.uv.0*w + self.uv.1*u + self.uv.2*v;
+        if !self.texture.is_opaque(uv) {
             return None;
         }
-        let normal = (self.normal.0*v + self.normal.1*u + self.normal.2*w).normalize();
-        let p = r.point_at_parameter(t);
-        let uv = self.uv.0*v + self.uv.1*u + self.uv.2*w;
-        Some(HitRecord{p, t, normal, texture: self.texture.as_ref(), uv})
+        let geometric_normal = geometric_normal(vert, normal);
+        let front_face = r.direction.dot(geometric_normal) < 0.0;
+        let edge_distance = Some(edge_distance(vert, w, u, v));
+        Some(HitRecord{p, t, normal, geometric_normal, front_face, texture: self.texture.as_ref(), uv, shading_rate: None, object_id: None, tangent: None, edge_distance, error})
+    }
+    /// At the start of the motion, as for sampling.
+    fn surface_area(&self) -> f32 {
+        0.5*(self.vert.1 - self.vert.0).cross(self.vert.2 - self.vert.0).length()
+    }
+    /// Uniform over the triangle, with the normal on the side of the vertex normals.
+    /// Moving triangles are sampled where they start.
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        sample_triangle(self.vert, self.normal, u)
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        visit(self.texture.as_ref());
+    }
+}
+
+/// The distance from the point with barycentric coordinates `w`, `u` and `v` to the closest edge of the triangle `vert`.
+/// Every coordinate is the distance to the edge across from its vertex relative to the height of the vertex above it.
+fn edge_distance(
+    vert: (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+    w: f32, u: f32, v: f32,
+) -> f32 {
+    let twice_area = (vert.1 - vert.0).cross(vert.2 - vert.0).length();
+    let height = |edge: Vector3D<f32, UnknownUnit>| twice_area/edge.length();
+    f32::min(w*height(vert.2 - vert.1), f32::min(u*height(vert.0 - vert.2), v*height(vert.1 - vert.0)))
+}
+
+/// The normal of the plane of the triangle `vert`, on the side of the shading normal `normal`, so both point out of
+/// the object.
+fn geometric_normal(
+    vert: (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+    normal: Vector3D<f32, UnknownUnit>,
+) -> Vector3D<f32, UnknownUnit> {
+    let face = (vert.1 - vert.0).cross(vert.2 - vert.0).normalize();
+    if face.dot(normal) < 0.0 { -face } else { face }
+}
+
+/// The point with the barycentric coordinates `w`, `u` and `v`, and the bound on its rounding error. Unlike the
+/// point along the ray it doesn't get less precise with the distance the ray travelled.
+fn interpolate(
+    vert: (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+    w: f32, u: f32, v: f32,
+) -> (Point3D<f32, UnknownUnit>, f32) {
+    let p = (vert.0.to_vector()*w + vert.1.to_vector()*u + vert.2.to_vector()*v).to_point();
+    (p, rounding_error(magnitude(vert.0).max(magnitude(vert.1)).max(magnitude(vert.2))))
+}
+
+/// Where the ray hits the triangle `vert` between `t_min` and `t_max`,
+/// with the barycentric coordinates of the hit weighing the second and third vertex.
+fn intersect(
+    vert: (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+    r: Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32)> {
+    // find vectors for two edges sharing vert0
+    let edge1 = vert.1 - vert.0;
+    let edge2 = vert.2 - vert.0;
+    // begin calculating determinant also used to calculate U parameter
+    let pvec = r.direction.cross(edge2);
+    // if determinant is near zero ray lies in plane of triangle
+    let det = edge1.dot(pvec);
+    if !det.is_normal() {
+        return None;
     }
+    let inv_det = det.recip();
+    // calculate distance from vert0 to ray origin
+    let tvec = r.origin - vert.0;
+    // calculate U parameter and test bounds
+    let u = tvec.dot(pvec) * inv_det;
+    if u<0.0 || u>1.0 {
+        return None;
+    }
+    // prepare to test V parameter
+    let qvec = tvec.cross(edge1);
+    // calculate V parameter and test bounds
+    let v = r.direction.dot(qvec) * inv_det;
+    if v<0.0 || v>1.0 {
+        return None;
+    }
+    // calculate t, ray intersects triangle
+    let t = edge2.dot(qvec) * inv_det;
+    if t<=t_min || t>=t_max {
+        return None;
+    }
+    let w = 1.0 - u - v;
+    if w<0.0 || w>1.0 {
+        return None;
+    }
+    Some((t, u, v))
+}
+
+/// A point uniformly distributed over the triangle `vert`, with the normal on the side of the vertex normals.
+fn sample_triangle(
+    vert: (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+    normals: (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>),
+    u: Vector2D<f32, UnknownUnit>,
+) -> Option<SurfaceSample> {
+    let cross = (vert.1 - vert.0).cross(vert.2 - vert.0);
+    let area = 0.5*cross.length();
+    if !(area > 0.0) {
+        return None;
+    }
+    let s = u.x.sqrt();
+    let (b0, b1) = (1.0 - s, u.y*s);
+    let b2 = 1.0 - b0 - b1;
+    let p = point3(0.0, 0.0, 0.0)
+        + vert.0.to_vector()*b0 + vert.1.to_vector()*b1 + vert.2.to_vector()*b2;
+    let normal = cross.normalize();
+    let shading_normal = normals.0*b0 + normals.1*b1 + normals.2*b2;
+    let normal = if normal.dot(shading_normal) < 0.0 { -normal } else { normal };
+    Some(SurfaceSample { p, normal, pdf: 1.0/area })
 }
 
 /// Construct a polygon from a number of points.
@@ -128,9 +283,18 @@ pub fn uniform_polygon(
     polygon(args.as_slice(), material.into())
 }
 
-#[derive(Debug, Clone)]
-pub struct Mesh {
-    data: Arc<BVH<Triangle>>
+/// A triangle mesh, its triangles held by an intersection backend, the built in BVH unless chosen otherwise.
+#[derive(Debug)]
+pub struct Mesh<B = BVH<Triangle>> {
+    data: Arc<B>,
+    /// Running total of the triangle areas, in the order of `IntersectionBackend::items`.
+    area_cdf: Arc<Vec<f32>>,
+}
+
+impl<B> Clone for Mesh<B> {
+    fn clone(&self) -> Mesh<B> {
+        Mesh { data: self.data.clone(), area_cdf: self.area_cdf.clone() }
+    }
 }
 
 impl Mesh {
@@ -162,53 +326,358 @@ impl Mesh {
         path: &Path,
         texture: Arc<dyn Texture>
     ) -> Result<Mesh, Error> {
-        let obj: Obj<'_, SimplePolygon> = Obj::load(path)?;
-        let mut triangles: Vec<Triangle> = Vec::new();
-        let get_normal = |i| Vector3D::from(obj.normal[i]);
-
-        for o in obj.objects.iter() {
-            for g in o.groups.iter() {
-                for p in g.polys.iter() {
-                    let p0 = p[0];
-                    let vert0 = obj.position[p0.0].into();
-                    for (p1, p2) in p[1..p.len()-1].iter().zip(p[2..].iter()) {
-                        let vert1 = obj.position[p1.0].into();
-                        let vert2 = obj.position[p2.0].into();
-
-                        let v: Vector3D<f32, UnknownUnit> = vert1-vert0;
-                        let w: Vector3D<f32, UnknownUnit> = vert2-vert0;
-                        let default_normal = v.cross(w);
-
-                        let normal0 = p0.2.map_or(default_normal, &get_normal);
-                        let normal1 = p1.2.map_or(default_normal, &get_normal);
-                        let normal2 = p2.2.map_or(default_normal, &get_normal);
-
-                        let uv0 = p0.1.map_or(vec2(0.0, 0.0), |i| obj.texture[i].into());
-                        let uv1 = p1.1.map_or(vec2(0.0, 0.0), |i| obj.texture[i].into());
-                        let uv2 = p2.1.map_or(vec2(0.0, 0.0), |i| obj.texture[i].into());
-
-                        triangles.push(Triangle::new(
-                            (vert0, vert1, vert2),
-                            (normal0, normal1, normal2),
-                            (uv0, uv1, uv2),
-                            texture.clone(),
-                        ));
-                    }
-                }
-            }
+        Mesh::from_obj_with_backend(path, texture)
+    }
+
+    /// Load a deforming mesh from two obj files with the same faces, the first holding the vertices at the ray time `t0`,
+    /// the second at `t1`. In between every vertex moves in a straight line, blurring the deformation.
+    /// The texture coordinates are taken from the first file.
+    pub fn from_moving_obj(
+        start: &Path,
+        end: &Path,
+        t0: f32,
+        t1: f32,
+        texture: Arc<dyn Texture>
+    ) -> Result<Mesh, Error> {
+        let start = load_obj(start, texture.clone())?;
+        let end = load_obj(end, texture)?;
+        if start.len() != end.len() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} triangles can't move to {}", start.len(), end.len())));
         }
+        let triangles = start.into_iter()
+            .zip(end)
+            .map(|(start, end)| start.with_motion(end.vert, end.normal, t0, t1))
+            .collect();
+        Ok(Mesh::from_triangles(triangles))
+    }
+}
+
+/// The triangles of an obj file, with the texture coordinates and normals it has.
+fn load_obj(path: &Path, texture: Arc<dyn Texture>) -> Result<Vec<Triangle>, Error> {
+    Ok(ObjMesh::load(path)?.to_triangles(texture))
+}
+
+impl<B: IntersectionBackend<Triangle>> Mesh<B> {
+    /// Load an obj file like `Mesh::from_obj`, building the given backend over its triangles.
+    pub fn from_obj_with_backend(
+        path: &Path,
+        texture: Arc<dyn Texture>
+    ) -> Result<Mesh<B>, Error> {
+        Ok(Mesh::from_triangles(load_obj(path, texture)?))
+    }
 
-        Ok(Mesh{ data: Arc::new(BVH::initialize(triangles)) })
+    pub fn from_triangles(triangles: Vec<Triangle>) -> Mesh<B> {
+        let data = B::build(triangles);
+        let mut total = 0.0;
+        let area_cdf = data.items().iter().map(|triangle| {
+            total += triangle.surface_area();
+            total
+        }).collect();
+        Mesh { data: Arc::new(data), area_cdf: Arc::new(area_cdf) }
     }
 }
 
-impl Hitable for Mesh {
+impl<B: IntersectionBackend<Triangle>> Hitable for Mesh<B> {
     fn bbox(&self) -> AABB {
         self.data.bbox()
     }
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         self.data.hit(r, t_min, t_max)
     }
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.data.is_occluded(r, t_min, t_max)
+    }
+    fn surface_area(&self) -> f32 {
+        self.area_cdf.last().cloned().unwrap_or(0.0)
+    }
+    /// Uniform over the whole mesh, picking triangles by their area.
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        let total = self.surface_area();
+        if !(total > 0.0) {
+            return None;
+        }
+        let target = u.x*total;
+        // Triangles without area never contain the target
+        let i = self.area_cdf.partition_point(|&c| c <= target).min(self.area_cdf.len() - 1);
+        let low = if i == 0 { 0.0 } else { self.area_cdf[i - 1] };
+        let u_x = ((target - low)/(self.area_cdf[i] - low)).max(0.0).min(1.0);
+        let sample = self.data.items()[i].sample_surface(vec2(u_x, u.y))?;
+        Some(SurfaceSample { pdf: 1.0/total, ..sample })
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        for triangle in self.data.items() {
+            triangle.visit_textures(visit);
+        }
+    }
+}
+
+/// Where the vertices of a deforming `TriangleMesh` end up, moving in a straight line from the ray time `t0` to `t1`.
+#[derive(Debug, Clone)]
+pub struct MeshMotion {
+    pub positions: Vec<Point3D<f32, UnknownUnit>>,
+    /// Empty if the normals stay the same.
+    pub normals: Vec<Vector3D<f32, UnknownUnit>>,
+    pub t0: f32,
+    pub t1: f32,
+}
+
+/// The vertices of a `TriangleMesh`, with an entry for every vertex in each of the buffers that isn't empty.
+#[derive(Debug, Clone, Default)]
+pub struct Vertices {
+    pub positions: Vec<Point3D<f32, UnknownUnit>>,
+    /// Vertices without a normal, or with a zero one, take the normal of each face they belong to.
+    pub normals: Vec<Vector3D<f32, UnknownUnit>>,
+    /// Vertices without texture coordinates are mapped to (0,0).
+    pub uvs: Vec<Vector2D<f32, UnknownUnit>>,
+    pub motion: Option<MeshMotion>,
+}
+
+/// A triangle of a `TriangleMesh`, with the indices of its vertices and of its material.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct IndexedTriangle {
+    pub vertices: [u32; 3],
+    pub material: u32,
+}
+
+/// A triangle mesh holding every vertex once, its triangles indexing into the vertices and a list of materials.
+/// Unlike `Mesh`, which copies the vertices and a reference to the material into every triangle,
+/// it takes a few bytes per triangle on top of the BVH.
+#[derive(Debug, Clone)]
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+}
+
+#[derive(Debug)]
+struct MeshData {
+    vertices: Vertices,
+    materials: Vec<Arc<dyn Texture>>,
+    triangles: BVH<IndexedTriangle>,
+    /// Running total of the triangle areas, in the order of `BVH::items`.
+    area_cdf: Vec<f32>,
+}
+
+impl TriangleMesh {
+    /// Panics if a buffer of the vertices has the wrong length, or a triangle refers to a vertex or material
+    /// that isn't there.
+    pub fn new(vertices: Vertices, triangles: Vec<IndexedTriangle>, materials: Vec<Arc<dyn Texture>>) -> TriangleMesh {
+        let n = vertices.positions.len();
+        let fits = |len: usize| len == 0 || len == n;
+        assert!(fits(vertices.normals.len()) && fits(vertices.uvs.len()), "buffers of different lengths for {} vertices", n);
+        if let Some(ref motion) = vertices.motion {
+            assert!(motion.positions.len() == n && fits(motion.normals.len()), "motion of different length for {} vertices", n);
+        }
+        for triangle in triangles.iter() {
+            assert!(
+                triangle.vertices.iter().all(|&i| (i as usize) < n) && (triangle.material as usize) < materials.len(),
+                "{:?} refers to a vertex or material that isn't there", triangle,
+            );
+        }
+
+        let triangles = BVH::build_by(triangles, BuildStrategy::Auto, |triangle| {
+            let [a, b, c] = triangle.vertices;
+            let corners = |positions: &[Point3D<f32, UnknownUnit>]| {
+                bounds(&[positions[a as usize], positions[b as usize], positions[c as usize]])
+            };
+            let bbox = match vertices.motion {
+                None => corners(&vertices.positions),
+                Some(ref motion) => corners(&vertices.positions).merge(corners(&motion.positions)),
+            };
+            (bbox.bounds[0].lerp(bbox.bounds[1], 0.5), bbox)
+        });
+        let mut total = 0.0;
+        let area_cdf = triangles.items().iter().map(|triangle| {
+            let [a, b, c] = triangle.vertices;
+            let p = &vertices.positions;
+            total += 0.5*(p[b as usize] - p[a as usize]).cross(p[c as usize] - p[a as usize]).length();
+            total
+        }).collect();
+        TriangleMesh { data: Arc::new(MeshData { vertices, materials, triangles, area_cdf }) }
+    }
+
+    /// Load an obj file like `Mesh::from_obj`.
+    pub fn from_obj(path: &Path, texture: Arc<dyn Texture>) -> Result<TriangleMesh, Error> {
+        Ok(TriangleMesh::from_obj_mesh(&ObjMesh::load(path)?, texture))
+    }
+
+    /// The triangles of a parsed obj file, every distinct combination of position, normal and texture coordinates
+    /// becoming a vertex.
+    pub fn from_obj_mesh(obj: &ObjMesh, texture: Arc<dyn Texture>) -> TriangleMesh {
+        let (vertices, triangles, _) = unified(obj);
+        TriangleMesh::new(vertices, triangles, vec![texture])
+    }
+
+    /// Load a deforming mesh from two obj files with the same faces, like `Mesh::from_moving_obj`.
+    pub fn from_moving_obj(
+        start: &Path,
+        end: &Path,
+        t0: f32,
+        t1: f32,
+        texture: Arc<dyn Texture>
+    ) -> Result<TriangleMesh, Error> {
+        let start = ObjMesh::load(start)?;
+        let end = ObjMesh::load(end)?;
+        if start.triangles.len() != end.triangles.len() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} triangles can't move to {}", start.triangles.len(), end.triangles.len())));
+        }
+        let (mut vertices, triangles, corners) = unified(&start);
+        // Every vertex moves to where the corner it was first seen at goes
+        let end_corner = |&(triangle, corner): &(usize, usize)| end.triangles[triangle][corner];
+        let positions = corners.iter().map(|c| end.positions[end_corner(c).position as usize]).collect();
+        let normals = if end.normals.is_empty() {
+            Vec::new()
+        } else {
+            corners.iter().map(|c| end_corner(c).normal.map_or(vec3(0.0, 0.0, 0.0), |i| end.normals[i as usize])).collect()
+        };
+        vertices.motion = Some(MeshMotion { positions, normals, t0, t1 });
+        Ok(TriangleMesh::new(vertices, triangles, vec![texture]))
+    }
+
+    /// Bytes taken by the vertices, the triangles and the nodes of the BVH, not counting the materials.
+    pub fn memory(&self) -> usize {
+        let vertices = &self.data.vertices;
+        let motion = vertices.motion.as_ref().map_or(0, |motion| {
+            motion.positions.len()*std::mem::size_of::<Point3D<f32, UnknownUnit>>()
+                + motion.normals.len()*std::mem::size_of::<Vector3D<f32, UnknownUnit>>()
+        });
+        vertices.positions.len()*std::mem::size_of::<Point3D<f32, UnknownUnit>>()
+            + vertices.normals.len()*std::mem::size_of::<Vector3D<f32, UnknownUnit>>()
+            + vertices.uvs.len()*std::mem::size_of::<Vector2D<f32, UnknownUnit>>()
+            + motion
+            + self.data.triangles.items().len()*std::mem::size_of::<IndexedTriangle>()
+            + self.data.triangles.node_memory()
+    }
+}
+
+/// The distinct corners of the triangles of an obj file as vertices, the triangles indexing into them,
+/// and for every vertex the triangle and corner it was first seen at.
+fn unified(obj: &ObjMesh) -> (Vertices, Vec<IndexedTriangle>, Vec<(usize, usize)>) {
+    let mut indices: HashMap<ObjVertex, u32> = HashMap::new();
+    let mut corners = Vec::new();
+    let triangles = obj.triangles.iter().enumerate().map(|(triangle, obj_corners)| {
+        let mut vertices = [0; 3];
+        for (corner, &obj_corner) in obj_corners.iter().enumerate() {
+            vertices[corner] = *indices.entry(obj_corner).or_insert_with(|| {
+                corners.push((triangle, corner));
+                corners.len() as u32 - 1
+            });
+        }
+        IndexedTriangle { vertices, material: 0 }
+    }).collect();
+    let corner = |&(triangle, corner): &(usize, usize)| obj.triangles[triangle][corner];
+    let positions = corners.iter().map(|c| obj.positions[corner(c).position as usize]).collect();
+    let normals = if obj.normals.is_empty() {
+        Vec::new()
+    } else {
+        corners.iter().map(|c| corner(c).normal.map_or(vec3(0.0, 0.0, 0.0), |i| obj.normals[i as usize])).collect()
+    };
+    let uvs = if obj.uvs.is_empty() {
+        Vec::new()
+    } else {
+        corners.iter().map(|c| corner(c).uv.map_or(vec2(0.0, 0.0), |i| obj.uvs[i as usize])).collect()
+    };
+    (Vertices { positions, normals, uvs, motion: None }, triangles, corners)
+}
+
+impl MeshData {
+    /// The positions and normals of the corners of a triangle at the ray time `ti`.
+    /// Missing normals are left zero.
+    fn corners(&self, triangle: &IndexedTriangle, ti: f32) -> (
+        (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+        (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>),
+    ) {
+        let vertices = &self.vertices;
+        let corner = |i: u32| {
+            let i = i as usize;
+            let position = vertices.positions[i];
+            let normal = vertices.normals.get(i).cloned().unwrap_or(vec3(0.0, 0.0, 0.0));
+            match vertices.motion {
+                None => (position, normal),
+                Some(ref motion) => {
+                    let s = motion_fraction(ti, motion.t0, motion.t1);
+                    let normal = match motion.normals.get(i) {
+                        Some(&end) => normal.lerp(end, s),
+                        None => normal,
+                    };
+                    (position.lerp(motion.positions[i], s), normal)
+                },
+            }
+        };
+        let [a, b, c] = triangle.vertices;
+        let ((p0, n0), (p1, n1), (p2, n2)) = (corner(a), corner(b), corner(c));
+        ((p0, p1, p2), (n0, n1, n2))
+    }
+
+    /// Corners without a normal take the normal of the face, unnormalized as in `Mesh`.
+    fn face_normals(
+        vert: (Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>, Point3D<f32, UnknownUnit>),
+        normals: (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>),
+    ) -> (Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>) {
+        let zero = vec3(0.0, 0.0, 0.0);
+        if normals.0 != zero && normals.1 != zero && normals.2 != zero {
+            return normals;
+        }
+        let face = (vert.1 - vert.0).cross(vert.2 - vert.0);
+        let or_face = |normal| if normal == zero { face } else { normal };
+        (or_face(normals.0), or_face(normals.1), or_face(normals.2))
+    }
+
+    fn hit(&self, triangle: &IndexedTriangle, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (vert, normals) = self.corners(triangle, r.ti);
+        let (t, u, v) = intersect(vert, r, t_min, t_max)?;
+        let w = 1.0 - u - v;
+        let normals = MeshData::face_normals(vert, normals);
+        let normal = (normals.0*w + normals.1*u + normals.2*v).normalize();
+        let (p, error) = interpolate(vert, w, u, v);
+        let [a, b, c] = triangle.vertices;
+        let uv_at = |i: u32| self.vertices.uvs.get(i as usize).cloned().unwrap_or(vec2(0.0, 0.0));
+        let uv = uv_at(a)*w + uv_at(b)*u + uv_at(c)*v;
+        let texture = self.materials[triangle.material as usize].as_ref();
+        if !texture.is_opaque(uv) {
+            return None;
+        }
+        let geometric_normal = geometric_normal(vert, normal);
+        let front_face = r.direction.dot(geometric_normal) < 0.0;
+        let edge_distance = Some(edge_distance(vert, w, u, v));
+        Some(HitRecord{p, t, normal, geometric_normal, front_face, texture, uv, shading_rate: None, object_id: None, tangent: None, edge_distance, error})
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn bbox(&self) -> AABB {
+        self.data.triangles.bounds()
+    }
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let data = self.data.as_ref();
+        data.triangles.hit_by(r, t_min, t_max, |triangle, closest_so_far| data.hit(triangle, r, t_min, closest_so_far))
+    }
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        let data = self.data.as_ref();
+        data.triangles.is_occluded_by(r, t_min, t_max, |triangle| data.hit(triangle, r, t_min, t_max).is_some())
+    }
+    fn surface_area(&self) -> f32 {
+        self.data.area_cdf.last().cloned().unwrap_or(0.0)
+    }
+    /// Uniform over the whole mesh where it starts, picking triangles by their area.
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        let data = self.data.as_ref();
+        let total = self.surface_area();
+        if !(total > 0.0) {
+            return None;
+        }
+        let target = u.x*total;
+        let i = data.area_cdf.partition_point(|&c| c <= target).min(data.area_cdf.len() - 1);
+        let low = if i == 0 { 0.0 } else { data.area_cdf[i - 1] };
+        let u_x = ((target - low)/(data.area_cdf[i] - low)).max(0.0).min(1.0);
+        let triangle = &data.triangles.items()[i];
+        let (vert, normals) = data.corners(triangle, data.vertices.motion.as_ref().map_or(0.0, |motion| motion.t0));
+        let sample = sample_triangle(vert, MeshData::face_normals(vert, normals), vec2(u_x, u.y))?;
+        Some(SurfaceSample { pdf: 1.0/total, ..sample })
+    }
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        for texture in &self.data.materials {
+            visit(texture.as_ref());
+        }
+    }
 }
 
 /// Build an axis aligned cuboid.
@@ -250,5 +719,271 @@ pub fn axis_aligned_cuboid(
         texture.clone()
     ).as_slice());
 
-    Mesh { data: Arc::new(BVH::initialize(triangles)) }
+    Mesh::from_triangles(triangles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use material::Lambertian;
+    use random::*;
+    use corpus::{self, Case, Fraction, uniform};
+    use quickcheck::{Arbitrary, Gen};
+
+    lazy_static! {
+        static ref GREY: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+    }
+
+    fn flat(a: Point3D<f32, UnknownUnit>, b: Point3D<f32, UnknownUnit>, c: Point3D<f32, UnknownUnit>) -> Triangle {
+        let normal = (b - a).cross(c - a).normalize();
+        let uv = vec2(0.0, 0.0);
+        Triangle::new((a, b, c), (normal, normal, normal), (uv, uv, uv), GREY.clone())
+    }
+
+    impl Arbitrary for Triangle {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let mut vertex = || point3(uniform(g, -1.0, 1.0), uniform(g, -1.0, 1.0), uniform(g, -1.0, 1.0));
+            flat(vertex(), vertex(), vertex())
+        }
+    }
+
+    impl Case for Triangle {
+        const FLOATS: usize = 9;
+
+        fn encode(&self, out: &mut Vec<f32>) {
+            let (a, b, c) = self.vert;
+            for p in [a, b, c].iter() {
+                out.extend_from_slice(&[p.x, p.y, p.z]);
+            }
+        }
+
+        fn decode(floats: &[f32]) -> Triangle {
+            let vertex = |i: usize| point3(floats[3*i], floats[3*i + 1], floats[3*i + 2]);
+            flat(vertex(0), vertex(1), vertex(2))
+        }
+    }
+
+    // A ray aimed at a point inside the triangle hits it there,
+    // unless the triangle is too thin or the ray too close to its plane to tell.
+    fn ray_into_triangle_hits((triangle, ray, (Fraction(s), Fraction(t))): (Triangle, Ray, (Fraction, Fraction))) -> bool {
+        let (a, b, c) = triangle.vertices();
+        let (u, v) = (s*(1.0 - t), s*t);
+        let target = a + (b - a)*u + (c - a)*v;
+        let direction = target - ray.origin;
+        let normal = (b - a).cross(c - a);
+        let thin = triangle.surface_area() < 1e-3*f32::max((b - a).square_length(), (c - a).square_length());
+        let grazing = normal.normalize().dot(direction.normalize()).abs() < 1e-2;
+        let on_edge = u.min(v).min(1.0 - u - v) < 1e-4;
+        if thin || grazing || on_edge || direction.length() < 1e-3 {
+            return true;
+        }
+        match triangle.hit(Ray::new(ray.origin, direction, ray.wl, ray.ti), 0.0, f32::max_value()) {
+            Some(rec) => (rec.t - 1.0).abs() < 1e-3,
+            None => false,
+        }
+    }
+
+    #[test]
+    fn test_ray_into_triangle_hits() {
+        corpus::check("ray_into_triangle_hits", ray_into_triangle_hits);
+    }
+
+    #[test]
+    fn test_interpolation() {
+        // Texture coordinates that are the position, and normals leaning towards their own vertex
+        let triangle = Triangle::new(
+            (point3(0.0, 0.0, 0.0), point3(1.0, 0.0, 0.0), point3(0.0, 1.0, 0.0)),
+            (vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 1.0), vec3(0.0, 1.0, 1.0)),
+            (vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0)),
+            GREY.clone(),
+        );
+        let hit = |x: f32, y: f32| triangle.hit(Ray::new(point3(x, y, 1.0), vec3(0.0, 0.0, -1.0), 500.0, 0.0), 0.0, 10.0).unwrap();
+        for &(x, y) in &[(0.9, 0.05), (0.05, 0.9), (0.05, 0.05), (0.3, 0.3)] {
+            let rec = hit(x, y);
+            assert!((rec.uv - vec2(x, y)).length() < 1e-5, "{:?} at {} {}", rec.uv, x, y);
+            let expected: Vector3D<f32, UnknownUnit> = vec3(x, y, 1.0).normalize();
+            assert!((rec.normal - expected).length() < 1e-5, "{:?} at {} {}", rec.normal, x, y);
+        }
+    }
+
+    #[test]
+    fn test_mask() {
+        use image::{Luma, GrayImage};
+        use texture::{AlphaMask, Masked};
+        // Cut out where u < 0.5
+        let image = Arc::new(GrayImage::from_fn(2, 1, |x, _| Luma([if x < 1 { 0 } else { 255 }])));
+        let masked: Arc<dyn Texture> = Arc::new(Masked::new(GREY.clone(), AlphaMask::new(&image, 0.5)));
+        let triangle = Triangle::new(
+            (point3(0.0, 0.0, 0.0), point3(1.0, 0.0, 0.0), point3(0.0, 1.0, 0.0)),
+            (vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 1.0)),
+            (vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0)),
+            masked,
+        );
+        let hit = |x: f32, y: f32| triangle.hit(Ray::new(point3(x, y, 1.0), vec3(0.0, 0.0, -1.0), 500.0, 0.0), 0.0, 10.0);
+        assert!(hit(0.9, 0.05).is_some());
+        assert!(hit(0.1, 0.8).is_none());
+    }
+
+    #[test]
+    fn test_geometric_normal() {
+        use material::{Dielectric, Material};
+        // The shading normals lean far over, so a grazing ray from above hits their back
+        let leaning = vec3(1.0, 0.0, 1.0).normalize();
+        let uv = vec2(0.0, 0.0);
+        let vert = (point3(-1.0, -1.0, 0.0), point3(1.0, -1.0, 0.0), point3(0.0, 1.0, 0.0));
+        let glass: Arc<dyn Texture> = Arc::new(Dielectric::QUARTZ);
+        let triangle = Triangle::new(vert, (leaning, leaning, leaning), (uv, uv, uv), glass);
+        let r = Ray::new(point3(-1.0, 0.0, 0.2), vec3(1.0, 0.0, -0.2), 550.0, 0.0);
+        let rec = triangle.hit(r, 0.0, 10.0).expect("Expected a hit");
+        assert_eq!(rec.geometric_normal, vec3(0.0, 0.0, 1.0));
+        assert!((rec.normal - leaning).length() < 1e-5);
+        assert!(rec.front_face && rec.facing_geometric_normal() == rec.geometric_normal);
+        // Reflecting around the shading normal would send the light down through the glass
+        assert!(!rec.reflects(r.direction - rec.normal*2.0*r.direction.dot(rec.normal)));
+        for _ in 0..100 {
+            let (_, scattered) = rec.texture.value(rec.uv).scatter(r, rec).reflection.unwrap();
+            assert!(scattered.direction.z > 0.0 && scattered.origin.z > 0.0, "{:?}", scattered);
+        }
+    }
+
+    #[test]
+    fn test_vertex_motion() {
+        let normal = vec3(0.0, 0.0, 1.0);
+        let uv = vec2(0.0, 0.0);
+        let triangle = Triangle::new(
+            (point3(0.0, 0.0, 0.0), point3(1.0, 0.0, 0.0), point3(0.0, 1.0, 0.0)),
+            (normal, normal, normal),
+            (uv, uv, uv),
+            GREY.clone(),
+        ).with_motion(
+            // The first vertex stays, the others swing forward and turn the normal
+            (point3(0.0, 0.0, 0.0), point3(1.0, 0.0, 1.0), point3(0.0, 1.0, 1.0)),
+            (vec3(-1.0, -1.0, 1.0), vec3(-1.0, -1.0, 1.0), vec3(-1.0, -1.0, 1.0)),
+            0.0,
+            1.0,
+        );
+        assert_eq!(triangle.bbox(), AABB { bounds: [point3(0.0, 0.0, 0.0), point3(1.0, 1.0, 1.0)] });
+        let mesh: Mesh = Mesh::from_triangles(vec![triangle, flat(point3(5.0, 5.0, 5.0), point3(6.0, 5.0, 5.0), point3(5.0, 6.0, 5.0))]);
+        let at = |ti: f32| mesh.hit(Ray::new(point3(0.4, 0.4, 2.0), vec3(0.0, 0.0, -1.0), 500.0, ti), 0.0, 10.0).expect("Expected a hit");
+        assert!((at(0.0).t - 2.0).abs() < 1e-5);
+        assert!((at(1.0).t - 1.2).abs() < 1e-5);
+        assert!((at(0.5).t - 1.6).abs() < 1e-5);
+        assert!(at(0.0).normal.x.abs() < 1e-5);
+        assert!(at(1.0).normal.x < -0.5);
+    }
+
+    #[test]
+    fn test_moving_obj() {
+        let path = Path::new("data/bunny.obj");
+        let still: Mesh = Mesh::from_obj(path, GREY.clone()).unwrap();
+        // The same file at both ends, and a motion that takes no time
+        let moving: Mesh = Mesh::from_moving_obj(path, path, 0.5, 0.5, GREY.clone()).unwrap();
+        let indexed = TriangleMesh::from_moving_obj(path, path, 0.5, 0.5, GREY.clone()).unwrap();
+        assert_eq!(still.bbox(), moving.bbox());
+        let center = still.bbox().bounds[0].lerp(still.bbox().bounds[1], 0.5);
+        for &ti in &[0.0, 0.5, 1.0] {
+            let ray = Ray::new(center + vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0), 500.0, ti);
+            let expected = still.hit(ray, 0.0, 10.0).expect("Expected a hit").t;
+            for hit in &[moving.hit(ray, 0.0, 10.0), indexed.hit(ray, 0.0, 10.0)] {
+                assert!((hit.as_ref().expect("Expected a hit").t - expected).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_motion_fraction() {
+        assert_eq!(motion_fraction(0.5, 0.0, 1.0), 0.5);
+        assert_eq!(motion_fraction(2.0, 0.0, 1.0), 1.0);
+        assert_eq!(motion_fraction(0.2, 0.5, 0.5), 0.0);
+        assert_eq!(motion_fraction(0.5, 0.5, 0.5), 1.0);
+    }
+
+    #[test]
+    fn test_sample_cuboid() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let low = point3(0.0, 0.0, 0.0);
+        let high = point3(1.0, 2.0, 3.0);
+        let cuboid = axis_aligned_cuboid(low, high, texture);
+        assert!((cuboid.surface_area() - 22.0).abs() < 1e-5);
+        let mut on_large_faces = 0;
+        for _ in 0..1000 {
+            let sample = cuboid.sample_surface(vec2(next_f32(), next_f32())).unwrap();
+            assert_eq!(sample.pdf, 1.0/cuboid.surface_area());
+            // On the face the normal points out of
+            let (p, n) = (sample.p, sample.normal);
+            let on_face = |coordinate: f32, normal: f32, low: f32, high: f32| {
+                (normal < -0.5 && coordinate.abs() < 1e-5) || (normal > 0.5 && (coordinate - high).abs() < 1e-5)
+                    || (normal.abs() < 1e-5 && coordinate >= low - 1e-5 && coordinate <= high + 1e-5)
+            };
+            assert!(on_face(p.x, n.x, 0.0, 1.0) && on_face(p.y, n.y, 0.0, 2.0) && on_face(p.z, n.z, 0.0, 3.0), "{:?}", sample);
+            if n.x.abs() > 0.5 {
+                on_large_faces += 1;
+            }
+        }
+        // The two faces facing x make up 12 of the 22 square units
+        assert!(on_large_faces > 480 && on_large_faces < 620, "{}", on_large_faces);
+    }
+
+    #[test]
+    fn test_triangle_mesh_matches_mesh() {
+        let obj = ObjMesh::load(Path::new("data/bunny.obj")).unwrap();
+        let mesh: Mesh = Mesh::from_triangles(obj.to_triangles(GREY.clone()));
+        let indexed = TriangleMesh::from_obj_mesh(&obj, GREY.clone());
+        assert_eq!(indexed.bbox(), mesh.bbox());
+        assert!((indexed.surface_area() - mesh.surface_area()).abs() < 1e-3*mesh.surface_area());
+        for _ in 0..2000 {
+            let origin = (rand_in_unit_sphere::<f32>()*4.0).to_point();
+            let target = point3(next_f32()*3.0 - 1.5, next_f32()*2.0, next_f32()*2.0 - 1.0);
+            let ray = Ray::new(origin, target - origin, 500.0, 0.0);
+            let expected = mesh.hit(ray, 0.001, f32::MAX);
+            let hit = indexed.hit(ray, 0.001, f32::MAX);
+            assert_eq!(hit.map(|rec| (rec.t, rec.normal, rec.uv)), expected.map(|rec| (rec.t, rec.normal, rec.uv)));
+            assert_eq!(indexed.is_occluded(ray, 0.001, 1.0), mesh.is_occluded(ray, 0.001, 1.0));
+        }
+
+        // The triangles share their vertices instead of copying them
+        let copied = mesh.data.items().len()*std::mem::size_of::<Triangle>();
+        let shared = indexed.memory() - indexed.data.triangles.node_memory();
+        assert!(shared*3 < copied, "{} bytes for the vertices and triangles, {} copied", shared, copied);
+    }
+
+    #[test]
+    fn test_triangle_mesh() {
+        let normal = vec3(0.0, 0.0, 1.0);
+        // A quad of two triangles sharing two vertices, the second one flat shaded
+        let vertices = Vertices {
+            positions: vec![point3(0.0, 0.0, 0.0), point3(1.0, 0.0, 0.0), point3(1.0, 1.0, 0.0), point3(0.0, 1.0, 0.0)],
+            normals: vec![normal, normal, normal, vec3(0.0, 0.0, 0.0)],
+            uvs: vec![vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)],
+            motion: Some(MeshMotion {
+                positions: vec![point3(0.0, 0.0, 1.0), point3(1.0, 0.0, 1.0), point3(1.0, 1.0, 1.0), point3(0.0, 1.0, 1.0)],
+                normals: Vec::new(),
+                t0: 0.0,
+                t1: 1.0,
+            }),
+        };
+        let red: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.8, 0.1, 0.1)));
+        let mesh = TriangleMesh::new(
+            vertices,
+            vec![IndexedTriangle { vertices: [0, 1, 2], material: 0 }, IndexedTriangle { vertices: [0, 2, 3], material: 1 }],
+            vec![GREY.clone(), red.clone()],
+        );
+        assert_eq!(mesh.bbox(), AABB { bounds: [point3(0.0, 0.0, 0.0), point3(1.0, 1.0, 1.0)] });
+        assert!((mesh.surface_area() - 1.0).abs() < 1e-6);
+
+        let at = |x: f32, y: f32, ti: f32| mesh.hit(Ray::new(point3(x, y, 3.0), vec3(0.0, 0.0, -1.0), 500.0, ti), 0.0, 10.0).expect("Expected a hit");
+        let lower = at(0.7, 0.2, 0.0);
+        assert!((lower.t - 3.0).abs() < 1e-5);
+        assert!((lower.uv - vec2(0.7, 0.2)).length() < 1e-5);
+        assert!(*lower.texture == *GREY.as_ref());
+        let upper = at(0.2, 0.7, 0.5);
+        assert!((upper.t - 2.5).abs() < 1e-5);
+        assert!((upper.normal - normal).length() < 1e-5);
+        assert!(*upper.texture == *red.as_ref());
+        for _ in 0..100 {
+            let sample = mesh.sample_surface(vec2(next_f32(), next_f32())).unwrap();
+            assert!(sample.p.z.abs() < 1e-6 && sample.normal == normal, "{:?}", sample);
+        }
+    }
 }
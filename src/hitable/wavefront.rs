@@ -0,0 +1,248 @@
+//! A loader for Wavefront obj files, fast enough for scans with millions of triangles.
+//!
+//! Files are parsed in place, memory mapped with the `mmap` feature, into buffers of positions, texture coordinates
+//! and normals that the triangles index into. Errors name the file and the line they were found on.
+//!
+//! ```
+//! # extern crate rayer;
+//! # use rayer::hitable::wavefront::*;
+//! let mesh = ObjMesh::parse(b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n", |_, _| {}).unwrap();
+//! assert_eq!(mesh.positions.len(), 4);
+//! assert_eq!(mesh.triangles.len(), 2);
+//! ```
+
+use euclid::*;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::str;
+use std::sync::Arc;
+
+use hitable::triangle::Triangle;
+use texture::Texture;
+
+/// Bytes parsed between two reports of the progress.
+const PROGRESS_STEP: usize = 16 << 20;
+
+/// A corner of a triangle, indexing into the buffers of an `ObjMesh`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct ObjVertex {
+    pub position: u32,
+    pub uv: Option<u32>,
+    pub normal: Option<u32>,
+}
+
+/// The geometry of an obj file, its faces split into triangles.
+/// Objects, groups, smoothing and materials are ignored.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ObjMesh {
+    pub positions: Vec<Point3D<f32, UnknownUnit>>,
+    pub uvs: Vec<Vector2D<f32, UnknownUnit>>,
+    pub normals: Vec<Vector3D<f32, UnknownUnit>>,
+    pub triangles: Vec<[ObjVertex; 3]>,
+}
+
+impl ObjMesh {
+    pub fn load(path: &Path) -> Result<ObjMesh, Error> {
+        ObjMesh::load_with_progress(path, |_, _| {})
+    }
+
+    /// Load an obj file, calling `progress` with the bytes parsed so far and the size of the file
+    /// every 16 MiB along the way.
+    pub fn load_with_progress<P: FnMut(usize, usize)>(path: &Path, progress: P) -> Result<ObjMesh, Error> {
+        let context = |error: Error| Error::new(error.kind(), format!("{}: {}", path.display(), error));
+        let file = File::open(path).map_err(context)?;
+        let data = read(file).map_err(context)?;
+        ObjMesh::parse(&data, progress).map_err(context)
+    }
+
+    /// Parse the contents of an obj file, reporting progress like `load_with_progress`.
+    pub fn parse<P: FnMut(usize, usize)>(data: &[u8], mut progress: P) -> Result<ObjMesh, Error> {
+        let mut mesh = ObjMesh::default();
+        let mut parsed = 0;
+        let mut reported = 0;
+        for (number, line) in data.split(|&byte| byte == b'\n').enumerate() {
+            mesh.parse_line(line)
+                .map_err(|message| Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, message)))?;
+            parsed = (parsed + line.len() + 1).min(data.len());
+            if parsed - reported >= PROGRESS_STEP {
+                reported = parsed;
+                progress(parsed, data.len());
+            }
+        }
+        Ok(mesh)
+    }
+
+    fn parse_line(&mut self, line: &[u8]) -> Result<(), String> {
+        let line = match line.iter().position(|&byte| byte == b'#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut tokens = line.split(|byte| byte.is_ascii_whitespace()).filter(|token| !token.is_empty());
+        match tokens.next() {
+            Some(b"v") => {
+                let (x, y, z) = (number(&mut tokens)?, number(&mut tokens)?, number(&mut tokens)?);
+                self.positions.push(point3(x, y, z));
+            },
+            Some(b"vt") => {
+                let u = number(&mut tokens)?;
+                let v = if let Some(token) = tokens.next() { parse(token)? } else { 0.0 };
+                self.uvs.push(vec2(u, v));
+            },
+            Some(b"vn") => {
+                let (x, y, z) = (number(&mut tokens)?, number(&mut tokens)?, number(&mut tokens)?);
+                self.normals.push(vec3(x, y, z));
+            },
+            Some(b"f") => {
+                // Split into a fan around the first corner
+                let mut corners = 0;
+                let mut first = ObjVertex { position: 0, uv: None, normal: None };
+                let mut previous = first;
+                for token in tokens {
+                    let vertex = self.vertex(token)?;
+                    if corners == 0 {
+                        first = vertex;
+                    } else if corners >= 2 {
+                        self.triangles.push([first, previous, vertex]);
+                    }
+                    previous = vertex;
+                    corners += 1;
+                }
+                if corners < 3 {
+                    return Err(format!("a face needs at least 3 corners, not {}", corners));
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// A corner of a face, `position`, `position/uv`, `position//normal` or `position/uv/normal`.
+    fn vertex(&self, token: &[u8]) -> Result<ObjVertex, String> {
+        let mut parts = token.split(|&byte| byte == b'/');
+        let position = match parts.next() {
+            Some(index) => resolve(index, self.positions.len(), "position")?,
+            None => return Err("a corner needs a position".to_string()),
+        };
+        let uv = match parts.next() {
+            Some(index) if !index.is_empty() => Some(resolve(index, self.uvs.len(), "texture coordinate")?),
+            _ => None,
+        };
+        let normal = match parts.next() {
+            Some(index) if !index.is_empty() => Some(resolve(index, self.normals.len(), "normal")?),
+            _ => None,
+        };
+        Ok(ObjVertex { position, uv, normal })
+    }
+
+    /// The triangles with their corners copied out of the buffers.
+    /// Corners without texture coordinates are mapped to (0,0), and ones without a normal get the normal of the face.
+    pub fn to_triangles(&self, texture: Arc<dyn Texture>) -> Vec<Triangle> {
+        self.triangles.iter().map(|&[a, b, c]| {
+            let vert = (self.positions[a.position as usize], self.positions[b.position as usize], self.positions[c.position as usize]);
+            let face_normal = (vert.1 - vert.0).cross(vert.2 - vert.0);
+            let normal = |corner: ObjVertex| corner.normal.map_or(face_normal, |i| self.normals[i as usize]);
+            let uv = |corner: ObjVertex| corner.uv.map_or(vec2(0.0, 0.0), |i| self.uvs[i as usize]);
+            Triangle::new(vert, (normal(a), normal(b), normal(c)), (uv(a), uv(b), uv(c)), texture.clone())
+        }).collect()
+    }
+}
+
+#[cfg(feature = "mmap")]
+fn read(file: File) -> Result<::memmap2::Mmap, Error> {
+    // Like any reader the parser expects the file not to change while it is loaded
+    unsafe { ::memmap2::Mmap::map(&file) }
+}
+
+#[cfg(not(feature = "mmap"))]
+fn read(mut file: File) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn parse(token: &[u8]) -> Result<f32, String> {
+    str::from_utf8(token).ok()
+        .and_then(|token| token.parse().ok())
+        .ok_or_else(|| format!("expected a number, found {:?}", String::from_utf8_lossy(token)))
+}
+
+fn number<'a, I: Iterator<Item = &'a [u8]>>(tokens: &mut I) -> Result<f32, String> {
+    tokens.next().map_or(Err("missing a coordinate".to_string()), parse)
+}
+
+/// The index into a buffer holding `len` items of an index counting from 1, or back from the end if negative.
+fn resolve(token: &[u8], len: usize, what: &str) -> Result<u32, String> {
+    let index: i64 = str::from_utf8(token).ok()
+        .and_then(|token| token.parse().ok())
+        .ok_or_else(|| format!("expected an index, found {:?}", String::from_utf8_lossy(token)))?;
+    let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(format!("{} {} is not defined, there are {} before it", what, index, len));
+    }
+    Ok(resolved as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let data = b"# A quad and a triangle\r\n\
+            o quad\n\
+            v 0 0 0\nv 1 0 0\nv 1 1 0 1.0\nv 0 1 0\n\
+            vt 0 0\nvt 1 0\nvt 1 1\nvt 0.5\n\
+            vn 0 0 1\n\
+            usemtl white\n\
+            s off\n\
+            f 1/1/1 2/2/1 3/3/1 4/4/1 # a comment\n\
+            f -3//-1 -2//1 -1\n";
+        let mesh = ObjMesh::parse(data, |_, _| {}).unwrap();
+        assert_eq!(mesh.positions[2], point3(1.0, 1.0, 0.0));
+        assert_eq!(mesh.uvs[3], vec2(0.5, 0.0));
+        let corner = |position, uv, normal| ObjVertex { position, uv, normal };
+        assert_eq!(mesh.triangles, vec![
+            [corner(0, Some(0), Some(0)), corner(1, Some(1), Some(0)), corner(2, Some(2), Some(0))],
+            [corner(0, Some(0), Some(0)), corner(2, Some(2), Some(0)), corner(3, Some(3), Some(0))],
+            [corner(1, None, Some(0)), corner(2, None, Some(0)), corner(3, None, None)],
+        ]);
+
+        let triangles = mesh.to_triangles(Arc::new(::material::Lambertian::new(::palette::Rgb::with_wp(0.5, 0.5, 0.5))));
+        assert_eq!(triangles.len(), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |data: &[u8]| ObjMesh::parse(data, |_, _| {}).unwrap_err().to_string();
+        assert_eq!(error(b"v 0 0 0\nv 1 x 0\n"), "line 2: expected a number, found \"x\"");
+        assert_eq!(error(b"v 0 0\n"), "line 1: missing a coordinate");
+        assert_eq!(error(b"v 0 0 0\nv 1 0 0\n\nf 1 2\n"), "line 4: a face needs at least 3 corners, not 2");
+        assert_eq!(error(b"v 0 0 0\nv 1 0 0\nf 1 2 3\n"), "line 3: position 3 is not defined, there are 2 before it");
+        assert_eq!(error(b"v 0 0 0\nf 1 1/1 1\n"), "line 2: texture coordinate 1 is not defined, there are 0 before it");
+
+        let missing = ObjMesh::load(Path::new("data/missing.obj")).unwrap_err();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+        assert!(missing.to_string().starts_with("data/missing.obj: "), "{}", missing);
+    }
+
+    #[test]
+    fn test_progress() {
+        let line = b"v 0.25 0.5 0.75\n";
+        let data: Vec<u8> = line.iter().cycle().take(line.len()*(5*PROGRESS_STEP/2/line.len())).cloned().collect();
+        let mut reports = Vec::new();
+        let mesh = ObjMesh::parse(&data, |parsed, size| reports.push((parsed, size))).unwrap();
+        assert_eq!(mesh.positions.len(), data.len()/line.len());
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|&(parsed, size)| parsed <= size && size == data.len()));
+    }
+
+    #[test]
+    fn test_bunny() {
+        let bunny = ObjMesh::load(Path::new("data/bunny.obj")).unwrap();
+        assert_eq!(bunny.triangles.len(), 69630);
+        assert_eq!(bunny.positions.len(), bunny.normals.len());
+        assert!(bunny.uvs.is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+//! Which object and material the camera sees in every pixel, to mask parts of an image in post.
+//!
+//! Objects go by their `object_id`, and materials by the texture they were hit with, numbered in the order they are
+//! found in the scene. Every pixel keeps how much of it each id covers over its samples, so masks get the same soft edges
+//! as the image, and the ids covering the most are written in the layers Cryptomatte uses: ids hashed from names
+//! like `object 3` with MurmurHash3, and the fraction of the pixel they cover, two ranks to a layer.
+
+use std::collections::{BTreeMap, HashMap};
+use palette::Rgb;
+use palette::white_point::E;
+
+use hitable::{Hitable, HitRecord};
+use output::OutputLayer;
+use texture::Texture;
+
+/// Ids covering a pixel that make it into the Cryptomatte layers, the most covering first.
+pub const RANKS: usize = 6;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum IdKind {
+    Object,
+    Material,
+}
+
+impl IdKind {
+    /// The name of the id `id`, which is hashed for the Cryptomatte layers.
+    pub fn name(self, id: u32) -> String {
+        match self {
+            IdKind::Object => format!("object {}", id),
+            IdKind::Material => format!("material {}", id),
+        }
+    }
+
+    /// The name of the Cryptomatte layers, which they are numbered after.
+    pub fn layer(self) -> &'static str {
+        match self {
+            IdKind::Object => "CryptoObject",
+            IdKind::Material => "CryptoMaterial",
+        }
+    }
+}
+
+/// The ids of what a camera ray hit, both `None` if it hit nothing.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct HitIds {
+    pub object: Option<u32>,
+    pub material: Option<u32>,
+}
+
+impl HitIds {
+    pub fn get(&self, kind: IdKind) -> Option<u32> {
+        match kind {
+            IdKind::Object => self.object,
+            IdKind::Material => self.material,
+        }
+    }
+}
+
+/// Numbers the textures of a scene in the order `Hitable::visit_textures` gives them,
+/// so the same scene gets the same ids whichever parts of it are rendered first.
+#[derive(Debug, Default)]
+pub struct MaterialIds {
+    ids: HashMap<usize, u32>,
+}
+
+fn texture_key(texture: &dyn Texture) -> usize {
+    texture as *const dyn Texture as *const () as usize
+}
+
+impl MaterialIds {
+    pub fn new<H: Hitable + ?Sized>(scene: &H) -> MaterialIds {
+        let mut ids = HashMap::new();
+        scene.visit_textures(&mut |texture| {
+            let next = ids.len() as u32;
+            ids.entry(texture_key(texture)).or_insert(next);
+        });
+        MaterialIds { ids }
+    }
+
+    /// The id of `texture`, `None` if it isn't part of the scene.
+    pub fn id(&self, texture: &dyn Texture) -> Option<u32> {
+        self.ids.get(&texture_key(texture)).cloned()
+    }
+
+    /// The ids of the object and material of `rec`, if there is a hit.
+    pub fn hit_ids(&self, rec: Option<&HitRecord>) -> HitIds {
+        match rec {
+            Some(rec) => HitIds { object: rec.object_id, material: self.id(rec.texture) },
+            None => HitIds::default(),
+        }
+    }
+}
+
+/// How much of every pixel each id covers, as the share of the samples that hit it.
+#[derive(Debug, Clone)]
+pub struct IdCoverage {
+    kind: IdKind,
+    ids: Vec<Vec<(u32, f32)>>,
+    samples: Vec<f32>,
+}
+
+impl IdCoverage {
+    pub fn new(kind: IdKind, pixels: usize) -> IdCoverage {
+        IdCoverage { kind, ids: vec![Vec::new(); pixels], samples: vec![0.0; pixels] }
+    }
+
+    /// Add a sample of `pixel` hitting what has the ids `ids`.
+    pub fn add(&mut self, pixel: usize, ids: HitIds) {
+        self.samples[pixel] += 1.0;
+        let id = match ids.get(self.kind) {
+            Some(id) => id,
+            None => return,
+        };
+        match self.ids[pixel].iter_mut().find(|&&mut (other, _)| other == id) {
+            Some(&mut (_, ref mut count)) => *count += 1.0,
+            None => self.ids[pixel].push((id, 1.0)),
+        }
+    }
+
+    /// The ids seen in `pixel` with the share of the samples they cover, the most covering first.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # use rayer::ids::*;
+    /// let mut coverage = IdCoverage::new(IdKind::Object, 1);
+    /// coverage.add(0, HitIds { object: Some(3), material: Some(0) });
+    /// coverage.add(0, HitIds::default());
+    /// coverage.add(0, HitIds { object: Some(5), material: Some(0) });
+    /// coverage.add(0, HitIds { object: Some(5), material: Some(1) });
+    /// assert_eq!(coverage.ranked(0), vec![(5, 0.5), (3, 0.25)]);
+    /// ```
+    pub fn ranked(&self, pixel: usize) -> Vec<(u32, f32)> {
+        let samples = self.samples[pixel];
+        let mut ranked: Vec<(u32, f32)> = self.ids[pixel].iter().map(|&(id, count)| (id, count/samples)).collect();
+        // Ties go to the lower id, so the ranks don't depend on the order the samples came in
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+        ranked
+    }
+
+    /// The Cryptomatte layers of the ids, with the manifest of their names in the header of the first.
+    pub fn layers(&self) -> Vec<OutputLayer> {
+        let mut names = BTreeMap::new();
+        for &(id, _) in self.ids.iter().flatten() {
+            names.entry(id).or_insert_with(|| self.kind.name(id));
+        }
+        let manifest = names.values()
+            .map(|name| format!("\"{}\":\"{:08x}\"", name, id_hash(name).to_bits()))
+            .collect::<Vec<_>>()
+            .join(",");
+        let ranked: Vec<Vec<(f32, f32)>> = (0..self.ids.len())
+            .map(|pixel| self.ranked(pixel).into_iter().map(|(id, share)| (id_hash(&self.kind.name(id)), share)).collect())
+            .collect();
+        let rank = |rank: usize, value: fn((f32, f32)) -> f32| {
+            ranked.iter().map(|ids| ids.get(rank).map_or(0.0, |&pair| value(pair))).collect()
+        };
+        let key = format!("{:08x}", murmur3(self.kind.layer().as_bytes(), 0));
+        (0..RANKS/2).map(|layer| {
+            let output = OutputLayer::new(&format!("{}{:02}", self.kind.layer(), layer))
+                .with_channel("R", rank(2*layer, |(id, _)| id))
+                .with_channel("G", rank(2*layer, |(_, share)| share))
+                .with_channel("B", rank(2*layer + 1, |(id, _)| id))
+                .with_channel("A", rank(2*layer + 1, |(_, share)| share));
+            if layer > 0 {
+                return output;
+            }
+            let attribute = |name: &str| format!("cryptomatte/{}/{}", &key[..7], name);
+            output
+                .with_attribute(&attribute("name"), self.kind.layer())
+                .with_attribute(&attribute("hash"), "MurmurHash3_32")
+                .with_attribute(&attribute("conversion"), "uint32_to_float32")
+                .with_attribute(&attribute("manifest"), &format!("{{{}}}", manifest))
+        }).collect()
+    }
+}
+
+/// The 32 bit MurmurHash3 of `bytes`.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::ids::murmur3;
+/// assert_eq!(murmur3(b"", 0), 0);
+/// assert_eq!(murmur3(b"hello", 0), 0x248bfa47);
+/// ```
+pub fn murmur3(bytes: &[u8], seed: u32) -> u32 {
+    let mix = |k: u32| k.wrapping_mul(0xcc9e2d51).rotate_left(15).wrapping_mul(0x1b873593);
+    let mut h = seed;
+    let chunks = bytes.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        h ^= mix(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    if !tail.is_empty() {
+        h ^= mix(tail.iter().rev().fold(0, |k, &byte| k << 8 | byte as u32));
+    }
+    h ^= bytes.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ h >> 16
+}
+
+/// The hash of `name` as Cryptomatte stores it, a float made of its bits that is neither infinite, NaN nor denormal.
+pub fn id_hash(name: &str) -> f32 {
+    let hash = murmur3(name.as_bytes(), 0);
+    let exponent = hash >> 23 & 255;
+    f32::from_bits(if exponent == 0 || exponent == 255 { hash ^ 1 << 23 } else { hash })
+}
+
+/// A color for the id `id`, to tell ids apart at a glance in images of them.
+pub fn id_color(kind: IdKind, id: u32) -> Rgb<E, f32> {
+    let hash = murmur3(kind.name(id).as_bytes(), 0);
+    let channel = |shift: u32| (hash >> shift & 255) as f32/255.0;
+    Rgb::with_wp(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_murmur3() {
+        assert_eq!(murmur3(b"The quick brown fox jumps over the lazy dog", 0), 0x2e4ff723);
+        // Every length of tail
+        assert_eq!(murmur3(b"a", 0), 0x3c2569b2);
+        assert_eq!(murmur3(b"ab", 0), 0x9bbfd75f);
+        assert_eq!(murmur3(b"abc", 0), 0xb3dd93fa);
+        assert_eq!(murmur3(b"abcd", 0), 0x43ed676a);
+    }
+
+    #[test]
+    fn test_id_hash() {
+        for id in 0..1000 {
+            let hash = id_hash(&IdKind::Object.name(id));
+            assert!(hash.is_normal() || hash == 0.0, "{}", hash);
+        }
+        assert_ne!(id_hash("object 1"), id_hash("material 1"));
+    }
+
+    #[test]
+    fn test_material_ids() {
+        use std::sync::Arc;
+        use euclid::*;
+        use hitable::bvh::BVH;
+        use hitable::instance::translate;
+        use hitable::sphere::Sphere;
+        use material::Lambertian;
+        use ray::Ray;
+
+        let grey = |v: f32| -> Arc<dyn Texture> { Arc::new(Lambertian::new(Rgb::with_wp(v, v, v))) };
+        let (first, second, unused) = (grey(0.2), grey(0.5), grey(0.8));
+        let objects: Vec<Arc<dyn Hitable>> = vec![
+            Arc::new(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, first.clone())),
+            Arc::new(translate(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, second.clone()), vec3(5.0, 0.0, 0.0))),
+            Arc::new(Sphere::new(point3(10.0, 0.0, 0.0), 1.0, first.clone())),
+        ];
+        let scene = BVH::initialize(objects);
+        let materials = MaterialIds::new(&scene);
+        assert_eq!(materials.id(first.as_ref()), Some(0));
+        assert_eq!(materials.id(second.as_ref()), Some(1));
+        assert_eq!(materials.id(unused.as_ref()), None);
+        // Hitting the second material first doesn't renumber it
+        let r = Ray::new(point3(5.0, 0.0, 5.0), vec3(0.0, 0.0, -1.0), 500.0, 0.0);
+        assert_eq!(materials.hit_ids(scene.hit(r, 0.0, 100.0).as_ref()).material, Some(1));
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut coverage = IdCoverage::new(IdKind::Material, 2);
+        for &material in &[Some(2), Some(2), Some(7), None] {
+            coverage.add(0, HitIds { object: None, material });
+        }
+        let layers = coverage.layers();
+        assert_eq!(layers.len(), RANKS/2);
+        // The empty pixel covers nothing
+        assert_eq!(coverage.ranked(1), vec![]);
+        assert_eq!(coverage.ranked(0), vec![(2, 0.5), (7, 0.25)]);
+    }
+}
@@ -0,0 +1,376 @@
+//! Camera rays traced through every surface of a real lens, for the aberrations, vignetting and bokeh that a thin
+//! lens leaves out, as in [Kolb, Mitchell and Hanrahan, A Realistic Camera Model for Computer Graphics](https://doi.org/10.1145/218380.218463).
+//!
+//! Lenses are listed from the front to the film, the way lens patents and pbrt's lens files give them, in millimeters.
+//! Rays are aimed from the film at the exit pupil, the part of the rear surface light from the scene gets through,
+//! which is found ahead of rendering for points at every distance from the center of the film.
+use std::f32::consts::{PI, SQRT_2};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use euclid::*;
+
+use material::{Dielectric, refract};
+use material::glass;
+
+/// Wavelength lenses are focused at, the yellow d line.
+const D_LINE: f32 = 587.6;
+/// Distances from the center of the film the exit pupil is found at, out to the corners.
+const PUPIL_BINS: usize = 64;
+/// Points across the rear surface tried from every one of them.
+const PUPIL_GRID: usize = 64;
+
+/// One surface of a lens, with the glass or air behind it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct LensSurface {
+    /// Radius of curvature, positive when the surface bulges towards the front. 0 for the flat aperture stop.
+    pub radius: f32,
+    /// Distance along the axis to the next surface. The last surface's is replaced when the lens is focused.
+    pub thickness: f32,
+    /// What fills the space behind the surface, up to the next one.
+    pub medium: Dielectric,
+    /// Radius of the opening, outside which rays are blocked.
+    pub aperture_radius: f32,
+}
+
+/// The surfaces of a lens from the front to the film.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Lens {
+    pub surfaces: Vec<LensSurface>,
+}
+
+/// A double Gauss lens of 50 mm at f/2, from US patent 2,673,491 by Tronnier, as scaled in Modern Lens Design and pbrt.
+const DOUBLE_GAUSS: &str = "
+# radius  thickness  index  aperture
+29.475    3.76       1.67   25.2
+84.83     0.12       1      25.2
+19.275    4.025      1.67   23
+40.77     3.275      1.699  23
+12.75     5.705      1      18
+0         4.5        0      17.1
+-14.495   1.18       1.603  17
+40.77     6.065      1.658  20
+-20.385   0.19       1      20
+437.065   3.22       1.717  20
+-39.73    0          1      20
+";
+
+impl Lens {
+    /// A 50 mm lens at f/2 with six elements, the design of most standard lenses since the 1950s.
+    /// Its glasses are given by their refractive index only, so colors don't separate.
+    pub fn double_gauss() -> Lens {
+        Lens::parse(DOUBLE_GAUSS).unwrap()
+    }
+
+    /// Read a lens with a line of curvature radius, thickness, refractive index and aperture diameter for every surface.
+    /// The index is that of the glass behind the surface, with 0 or 1 for air, or the name of a glass in
+    /// `material::glass::CATALOG` for its dispersion. Empty lines and lines starting with `#` are skipped.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # use rayer::lens_system::Lens;
+    /// let singlet = Lens::parse("50 5 bk7 20\n-50 45 1 20").unwrap();
+    /// assert_eq!(singlet.surfaces.len(), 2);
+    /// assert!(singlet.surfaces[0].medium.refractive_index(450.0) > singlet.surfaces[0].medium.refractive_index(650.0));
+    /// ```
+    pub fn parse(text: &str) -> Result<Lens, Error> {
+        let mut surfaces = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| Error::new(ErrorKind::InvalidData, format!("line {}: {}", i+1, message));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (radius, thickness, medium, diameter) = match fields[..] {
+                [radius, thickness, medium, diameter] => (radius, thickness, medium, diameter),
+                _ => return Err(invalid("expected a radius, thickness, refractive index and aperture".to_string())),
+            };
+            let number = |field: &str| field.parse::<f32>().map_err(|_| invalid(format!("expected a number, got {}", field)));
+            let medium = match medium.parse::<f32>() {
+                Ok(index) if index == 0.0 => Dielectric::new(0.0, 0.0, 0.0, 0.0, 0.0, 0.0),
+                Ok(index) if index >= 1.0 => Dielectric::new(index*index - 1.0, 0.0, 0.0, 0.0, 0.0, 0.0),
+                Ok(index) => return Err(invalid(format!("refractive index {} below 1", index))),
+                Err(_) => glass::by_name(medium).ok_or_else(|| invalid(format!("unknown glass {}", medium)))?,
+            };
+            surfaces.push(LensSurface { radius: number(radius)?, thickness: number(thickness)?, medium, aperture_radius: 0.5*number(diameter)? });
+        }
+        if surfaces.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "no lens surfaces"));
+        }
+        Ok(Lens { surfaces })
+    }
+
+    /// Read a lens from a file in the format of `parse`.
+    pub fn from_file(path: &Path) -> Result<Lens, Error> {
+        let prefix = |error: Error| Error::new(error.kind(), format!("{}: {}", path.display(), error));
+        Lens::parse(&fs::read_to_string(path).map_err(prefix)?).map_err(prefix)
+    }
+
+    /// Where the surfaces lie along the axis in front of the film, with the last one `rear` from it.
+    fn vertices(&self, rear: f32) -> Vec<f32> {
+        let mut vertices = vec![rear; self.surfaces.len()];
+        for i in (0..self.surfaces.len() - 1).rev() {
+            vertices[i] = vertices[i+1] + self.surfaces[i].thickness;
+        }
+        vertices
+    }
+
+    /// A ray leaving the back of the lens from a point on the axis `distance` in front of the first surface, or from
+    /// infinitely far, entering close to the axis. With the last surface at the film. `None` if the lens blocks it.
+    fn paraxial(&self, distance: f32) -> Option<(Point3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>)> {
+        let vertices = self.vertices(0.0);
+        let height = 0.01*self.surfaces[0].aperture_radius;
+        let (origin, direction) = if distance.is_finite() {
+            let object = point3(0.0, 0.0, vertices[0] + distance);
+            (object, (point3(height, 0.0, vertices[0]) - object).normalize())
+        } else {
+            (point3(height, 0.0, vertices[0] + 1.0), vec3(0.0, 0.0, -1.0))
+        };
+        trace(&self.surfaces, &vertices, origin, direction, D_LINE, false)
+    }
+
+    /// The focal length of the lens, if it brings parallel light to a focus.
+    pub fn focal_length(&self) -> Option<f32> {
+        let height = 0.01*self.surfaces[0].aperture_radius;
+        let (p, d) = self.paraxial(f32::INFINITY)?;
+        if !(d.x < 0.0 && d.z < 0.0) {
+            return None;
+        }
+        // From where the ray would have bent all at once to where it crosses the axis
+        let principal_plane = p.z + (height - p.x)/d.x*d.z;
+        let focus = p.z - p.x/d.x*d.z;
+        Some(principal_plane - focus)
+    }
+
+    /// The distance from the last surface to the film that brings points `distance` in front of the first surface into focus.
+    /// `None` if they come into focus in front of the last surface, or not at all.
+    pub fn rear_distance(&self, distance: f32) -> Option<f32> {
+        let (p, d) = self.paraxial(distance)?;
+        if !(d.x < 0.0 && d.z < 0.0) {
+            return None;
+        }
+        let rear = p.x/d.x*d.z - p.z;
+        if rear > 0.0 { Some(rear) } else { None }
+    }
+
+    /// Focus the lens `distance` millimeters in front of it, with a film that makes for a view of `vfov` degrees
+    /// high of distant subjects, `aspect` times as wide as it is high.
+    pub fn focus(&self, distance: f32, vfov: f32, aspect: f32) -> Result<FocusedLens, Error> {
+        let focal_length = self.focal_length()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "the lens doesn't bring light to a focus"))?;
+        let rear = self.rear_distance(distance)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("the lens can't focus {} mm away", distance)))?;
+        let half_height = focal_length*(0.5*vfov.to_radians()).tan();
+        let mut lens = FocusedLens {
+            surfaces: self.surfaces.clone(),
+            vertices: self.vertices(rear),
+            film: vec2(aspect*half_height, half_height),
+            pupils: Vec::new(),
+        };
+        let corner = lens.film.length();
+        lens.pupils = (0..PUPIL_BINS).map(|i| lens.exit_pupil(corner*i as f32/PUPIL_BINS as f32)).collect();
+        if lens.pupils[0].area == 0.0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "no light gets through the lens to the center of the film"));
+        }
+        Ok(lens)
+    }
+}
+
+/// The part of the rear surface that light reaches a point of the film through, for a point on the x axis.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Pupil {
+    min: Vector2D<f32, UnknownUnit>,
+    max: Vector2D<f32, UnknownUnit>,
+    /// The area of the pupil itself, in mm².
+    area: f32,
+}
+
+/// A lens focused at a distance, with its film.
+#[derive(Debug, Clone)]
+pub struct FocusedLens {
+    surfaces: Vec<LensSurface>,
+    vertices: Vec<f32>,
+    /// Half the width and height of the film.
+    film: Vector2D<f32, UnknownUnit>,
+    pupils: Vec<Pupil>,
+}
+
+impl FocusedLens {
+    /// Bound the pupil of the point `x` from the center of the film by tracing rays through a grid over the rear surface.
+    fn exit_pupil(&self, x: f32) -> Pupil {
+        let rear = self.surfaces.len() - 1;
+        let radius = self.surfaces[rear].aperture_radius;
+        let cell = 2.0*radius/PUPIL_GRID as f32;
+        let (mut min, mut max, mut count) = (vec2(f32::MAX, f32::MAX), vec2(f32::MIN, f32::MIN), 0);
+        for i in 0..PUPIL_GRID {
+            for j in 0..PUPIL_GRID {
+                let q = vec2(-radius + cell*(i as f32 + 0.5), -radius + cell*(j as f32 + 0.5));
+                let direction = (point3(q.x, q.y, self.vertices[rear]) - point3(x, 0.0, 0.0)).normalize();
+                if q.square_length() <= radius*radius && trace(&self.surfaces, &self.vertices, point3(x, 0.0, 0.0), direction, D_LINE, true).is_some() {
+                    min = min.min(q);
+                    max = max.max(q);
+                    count += 1;
+                }
+            }
+        }
+        if count == 0 {
+            return Pupil { min: vec2(0.0, 0.0), max: vec2(0.0, 0.0), area: 0.0 };
+        }
+        // Points between the ones tried may still get through
+        let margin = vec2(cell, cell);
+        Pupil { min: min - margin, max: max + margin, area: count as f32*cell*cell }
+    }
+
+    /// A ray from the point `film` in [0,1]² of the film through the point `lens` of the unit disk mapped onto the
+    /// exit pupil, and the light along it relative to the center of the film, which the pupil narrowing towards the
+    /// corners and the cos⁴ falloff lessen. The ray leaves the front of the lens, with x and y as on the film and z
+    /// towards the scene, from the vertex of the first surface. `film` counts from the bottom left of the image, which
+    /// lies upside down on the film. `None` if the lens blocks the ray.
+    pub fn ray(&self, film: Vector2D<f32, UnknownUnit>, wl: f32, lens: Vector2D<f32, UnknownUnit>) -> Option<(Point3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>, f32)> {
+        let p: Vector2D<f32, UnknownUnit> = vec2((0.5 - film.x)*2.0*self.film.x, (0.5 - film.y)*2.0*self.film.y);
+        let r = p.length();
+        let bin = ((r/self.film.length()*PUPIL_BINS as f32) as usize).min(PUPIL_BINS - 1);
+        let pupil = self.pupils[bin];
+        if pupil.area == 0.0 {
+            return None;
+        }
+        // Uniformly over the ellipse around the bounds, turned from the x axis to the point
+        let (center, half) = ((pupil.min + pupil.max)*0.5, (pupil.max - pupil.min)*0.5);
+        let q = center + vec2(lens.x*half.x, lens.y*half.y)*SQRT_2;
+        let (sin, cos) = if r > 0.0 { (p.y/r, p.x/r) } else { (0.0, 1.0) };
+        let rear = self.vertices[self.vertices.len() - 1];
+        let direction = (point3(q.x*cos - q.y*sin, q.x*sin + q.y*cos, rear) - point3(p.x, p.y, 0.0)).normalize();
+        let weight = 2.0*PI*half.x*half.y/self.pupils[0].area*direction.z.powi(4);
+        let (origin, direction) = trace(&self.surfaces, &self.vertices, point3(p.x, p.y, 0.0), direction, wl, true)?;
+        Some((point3(origin.x, origin.y, origin.z - self.vertices[0]), direction, weight))
+    }
+}
+
+/// Where a ray crosses a surface with its vertex at `z`, and the normal there facing the ray. `None` if it passes
+/// outside the opening.
+fn intersect(surface: &LensSurface, z: f32, origin: Point3D<f32, UnknownUnit>, direction: Vector3D<f32, UnknownUnit>) -> Option<(Point3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>)> {
+    let (t, normal) = if surface.radius == 0.0 {
+        ((z - origin.z)/direction.z, vec3(0.0, 0.0, 1.0))
+    } else {
+        let center = point3(0.0, 0.0, z - surface.radius);
+        let oc = origin - center;
+        let b = oc.dot(direction);
+        let discriminant = b*b - oc.square_length() + surface.radius*surface.radius;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        // Of the two crossings of the sphere the surface is the one on the side of the vertex
+        let t = [-b - root, -b + root].iter().cloned()
+            .find(|&t| t > 0.0 && (origin.z + t*direction.z - center.z)*surface.radius > 0.0)?;
+        (t, (origin + direction*t - center)/surface.radius)
+    };
+    let p = origin + direction*t;
+    if !(t > 0.0) || p.x*p.x + p.y*p.y > surface.aperture_radius*surface.aperture_radius {
+        return None;
+    }
+    Some((p, if normal.dot(direction) > 0.0 { -normal } else { normal }))
+}
+
+/// Trace a ray through the surfaces at `vertices`, from the film to the front if `from_film`, or the other way,
+/// returning it as it leaves the lens. `direction` has to be normalized.
+fn trace(surfaces: &[LensSurface], vertices: &[f32], mut origin: Point3D<f32, UnknownUnit>, mut direction: Vector3D<f32, UnknownUnit>, wl: f32, from_film: bool) -> Option<(Point3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>)> {
+    let air = Dielectric::new(0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+    for k in 0..surfaces.len() {
+        let i = if from_film { surfaces.len() - 1 - k } else { k };
+        let (p, normal) = intersect(&surfaces[i], vertices[i], origin, direction)?;
+        origin = p;
+        if surfaces[i].radius != 0.0 {
+            let behind = surfaces[i].medium.refractive_index(wl);
+            let in_front = if i == 0 { &air } else { &surfaces[i-1].medium }.refractive_index(wl);
+            let ratio = if from_film { behind/in_front } else { in_front/behind };
+            direction = refract(direction, normal, ratio)?.normalize();
+        }
+    }
+    Some((origin, direction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let lens = Lens::double_gauss();
+        assert_eq!(lens.surfaces.len(), 11);
+        assert_eq!(lens.surfaces[5].radius, 0.0);
+        assert_eq!(lens.surfaces[5].aperture_radius, 8.55);
+        assert!((lens.surfaces[0].medium.refractive_index(450.0) - 1.67).abs() < 1e-6);
+        assert_eq!(lens.surfaces[1].medium.refractive_index(550.0), 1.0);
+
+        assert!(Lens::parse("50 5 1.5").is_err());
+        assert!(Lens::parse("50 5 unobtainium 20").is_err());
+        assert!(Lens::parse("50 five 1.5 20").is_err());
+        assert!(Lens::parse("50 5 0.5 20").is_err());
+        assert!(Lens::parse("# nothing").is_err());
+    }
+
+    #[test]
+    fn test_focus() {
+        let lens = Lens::double_gauss();
+        let focal_length = lens.focal_length().unwrap();
+        assert!((focal_length - 50.0).abs() < 2.0, "{}", focal_length);
+        // Focusing closer moves the lens away from the film
+        let infinity = lens.rear_distance(f32::INFINITY).unwrap();
+        let near = lens.rear_distance(1000.0).unwrap();
+        assert!(infinity > 0.0 && near > infinity, "{} {}", infinity, near);
+        assert_eq!(lens.rear_distance(10.0), None);
+
+        // Rays from the center of the film through all of the pupil meet close to where the lens is focused
+        let focused = lens.focus(1000.0, 30.0, 1.5).unwrap();
+        let object = point3(0.0, 0.0, 1000.0);
+        let mut weight = 0.0;
+        let samples = 16;
+        for i in 0..samples {
+            for j in 0..samples {
+                let u = vec2(-1.0 + 2.0*(i as f32 + 0.5)/samples as f32, -1.0 + 2.0*(j as f32 + 0.5)/samples as f32);
+                if let Some((origin, direction, w)) = focused.ray(vec2(0.5, 0.5), D_LINE, u) {
+                    let miss = (object - origin).cross(direction).length();
+                    assert!(miss < 2.0, "{} mm", miss);
+                    weight += w;
+                }
+            }
+        }
+        // The points of the square that fall in the unit disk average to about 1
+        let average = weight/(samples*samples) as f32*4.0/PI;
+        assert!((average - 1.0).abs() < 0.1, "{}", average);
+    }
+
+    #[test]
+    fn test_pupil() {
+        let focused = Lens::double_gauss().focus(f32::INFINITY, 40.0, 1.5).unwrap();
+        // The pupil shrinks towards the corners of the film
+        let (center, corner) = (focused.pupils[0], focused.pupils[PUPIL_BINS - 1]);
+        assert!(center.area > 0.0 && corner.area < center.area);
+        assert!((center.min + center.max).length() < 0.1);
+
+        // The film is upside down behind the lens, so the top right of the image sees up and to the right
+        let (_, direction, _) = focused.ray(vec2(0.9, 0.9), D_LINE, vec2(0.0, 0.0)).unwrap();
+        assert!(direction.x > 0.0 && direction.y > 0.0 && direction.z > 0.0);
+        // Rays into the corners are dimmer
+        let light = |film: Vector2D<f32, UnknownUnit>| (0..32)
+            .map(|i| { let angle = i as f32*0.7; vec2(angle.cos(), angle.sin())*(i as f32/32.0).sqrt() })
+            .filter_map(|u| focused.ray(film, D_LINE, u).map(|(_, _, weight)| weight))
+            .sum::<f32>();
+        assert!(light(vec2(0.0, 0.0)) < 0.8*light(vec2(0.5, 0.5)));
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // A simple lens of crown glass focuses blue closer to it than red
+        let singlet = Lens::parse("100 6 N-BK7 30\n-100 90 0 30").unwrap();
+        let rear = singlet.rear_distance(f32::INFINITY).unwrap();
+        let focused = singlet.focus(f32::INFINITY, 10.0, 1.0).unwrap();
+        let crossing = |wl| {
+            let (p, d) = trace(&focused.surfaces, &singlet.vertices(rear), point3(5.0, 0.0, 200.0), vec3(0.0, 0.0, -1.0), wl, false).unwrap();
+            p.z - p.x/d.x*d.z
+        };
+        assert!(crossing(450.0) > crossing(650.0));
+    }
+}
@@ -1,34 +1,75 @@
-#![feature(stdsimd)]
-#![feature(test)]
-#![feature(portable_simd)]
+#![cfg_attr(feature = "bench", feature(test))]
 extern crate arrayvec;
+extern crate bit_vec;
+extern crate bumpalo;
+#[cfg(feature = "embree")]
+extern crate cgmath;
 extern crate core;
 extern crate clap;
 extern crate cpuprofiler;
 extern crate crossbeam_channel;
 extern crate decorum;
-extern crate euclid;
+#[cfg(feature = "embree")]
+extern crate embree_rs;
+pub extern crate euclid;
+extern crate exr;
 extern crate image;
+#[macro_use]
+extern crate lazy_static;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+extern crate notify;
 extern crate num_traits;
-extern crate obj;
-extern crate palette;
+pub extern crate palette;
 extern crate pbr;
 extern crate pdqselect;
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
-extern crate core_simd;
 extern crate rand;
 extern crate rand_xorshift;
 extern crate rand_xoshiro;
 extern crate rayon;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 extern crate tempfile;
+#[cfg(all(test, feature = "bench"))]
 extern crate test;
 
 pub mod texture;
+pub mod arena;
+pub mod bake;
 pub mod camera;
+pub mod cli;
 pub mod color;
+pub mod debug_view;
+pub mod delta_light;
+pub mod description;
+pub mod distributed;
+pub mod film;
+pub mod flare;
 pub mod hitable;
+pub mod ids;
+pub mod lens_system;
+pub mod light_bvh;
+pub mod light_paths;
+pub mod light_tracing;
 pub mod material;
+pub mod output;
+pub mod prelude;
 pub mod random;
 pub mod ray;
+pub mod render;
+pub mod sampler;
+pub mod scene;
+pub mod settings;
+pub mod sky;
+pub mod sppm;
+pub mod tiles;
+pub mod trace;
+pub mod watch;
+#[cfg(test)]
+mod corpus;
+#[cfg(test)]
+mod fixtures;
@@ -0,0 +1,435 @@
+//! A tree over the lights of a scene, to pick the light a shading point aims at when there are too many to aim at
+//! all of them, like the street lamps and windows of a city at night.
+//!
+//! Every node bounds the positions of the lights under it with a box, the directions they shine in with a cone, and
+//! adds up how bright they are. From these it estimates how much light the node can send to a point on a surface,
+//! which falls off with the distance to the box and vanishes where the cone turns away from the point or the surface
+//! from the box. Picking a light walks down from the root, choosing each child in proportion to its estimate, so the
+//! lights likely to matter are picked most, and the light found is divided by the probability of picking it. This
+//! is the light tree of Conty Estevez and Kulla, "Importance Sampling of Many Lights with Adaptive Tree Splitting",
+//! 2018, without the splitting.
+//!
+//! Only lights with a position go in the tree: delta lights, and objects that emit light, which are bounded by their
+//! boxes and shine in every direction. Directional lights shine on every point alike, so they are aimed at from every
+//! shading point anyway.
+//!
+//! ```
+//! # extern crate rayer;
+//! # use rayer::prelude::*;
+//! # use rayer::delta_light::{DeltaLight, LightShape};
+//! # use rayer::light_bvh::LightBVH;
+//! let lamp = |x: f32| DeltaLight::new(LightShape::Point { position: point3(x, 1.0, 0.0) }, Rgb::with_wp(1.0, 1.0, 1.0), 1.0);
+//! let lights = LightBVH::new((0..100).map(|i| lamp(i as f32)).collect());
+//! // Under the first lamp, the lamps far away are hardly ever picked
+//! let (light, probability) = lights.sample(point3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 0.1).unwrap();
+//! assert!(probability > 0.2);
+//! assert_eq!(lights.probability(point3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), light), probability);
+//! ```
+
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::cmp::Ordering;
+use std::sync::Arc;
+use euclid::*;
+
+use delta_light::{DeltaLight, LightSample, LightShape};
+use hitable::bvh::BVH;
+use hitable::{Hitable, AABB};
+use ray::Ray;
+use sppm::Light;
+
+/// Up to this many lights with a position are all aimed at from every shading point, which is free of the noise of
+/// picking one, and still cheap.
+pub const AIM_AT_ALL: usize = 8;
+
+/// A light in the tree.
+#[derive(Clone)]
+pub enum TreeLight {
+    Delta(DeltaLight),
+    /// An object that emits light, which paths find by chance as well.
+    Surface(Light),
+}
+
+impl TreeLight {
+    /// The light reaching `p`, from a point on the light drawn with `u` for lights with a surface, see
+    /// `DeltaLight::sample` and `sppm::Light::sample`.
+    pub fn sample(&self, p: Point3D<f32, UnknownUnit>, u: Vector2D<f32, UnknownUnit>, wl: f32) -> Option<LightSample> {
+        match *self {
+            TreeLight::Delta(ref light) => light.sample(p, u, wl),
+            TreeLight::Surface(ref light) => light.sample(p, u, wl),
+        }
+    }
+}
+
+/// The directions within `angle` radians of `axis`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cone {
+    axis: Vector3D<f32, UnknownUnit>,
+    angle: f32,
+}
+
+impl Cone {
+    fn all() -> Cone {
+        Cone { axis: vec3(0.0, 0.0, 1.0), angle: PI }
+    }
+
+    /// The narrowest cone around both, turning the axis of the wider one towards the other.
+    fn merge(self, other: Cone) -> Cone {
+        let (wide, narrow) = if self.angle >= other.angle { (self, other) } else { (other, self) };
+        let between = angle_between(wide.axis, narrow.axis);
+        if between + narrow.angle <= wide.angle {
+            return wide;
+        }
+        let angle = 0.5*(wide.angle + between + narrow.angle);
+        let turn = wide.axis.cross(narrow.axis);
+        if angle >= PI || turn.square_length() == 0.0 {
+            return Cone::all();
+        }
+        // A rotation about `turn`, which is at right angles to the axis
+        let turn = turn.normalize();
+        let rotation = angle - wide.angle;
+        let axis = wide.axis*rotation.cos() + turn.cross(wide.axis)*rotation.sin();
+        Cone { axis: axis.normalize(), angle }
+    }
+}
+
+fn angle_between(a: Vector3D<f32, UnknownUnit>, b: Vector3D<f32, UnknownUnit>) -> f32 {
+    a.dot(b).clamp(-1.0, 1.0).acos()
+}
+
+/// The lights `lights[first..first + count]`, bounded together. The left child of a node with more than one light
+/// follows it directly, and the right one after the `2*left.count - 1` nodes of the left subtree.
+#[derive(Debug, Clone)]
+struct Node {
+    bbox: AABB,
+    /// The axes of the lights.
+    axes: Cone,
+    /// How far from its axis any of the lights shines.
+    spread: f32,
+    /// The light per solid angle of all the lights together, averaged over the visible wavelengths.
+    intensity: f32,
+    first: usize,
+    count: usize,
+}
+
+impl Node {
+    fn leaf(light: &TreeLight) -> Option<Node> {
+        let light = match *light {
+            TreeLight::Delta(ref light) => light,
+            // As bright as the surface seen face on, from every direction
+            TreeLight::Surface(ref light) => {
+                let bbox = light.object().bbox();
+                return Some(Node { bbox, axes: Cone::all(), spread: FRAC_PI_2, intensity: light.power(), first: 0, count: 1 });
+            },
+        };
+        let (position, axes, spread) = match light.shape {
+            LightShape::Directional { .. } => return None,
+            LightShape::Point { position } => (position, Cone::all(), FRAC_PI_2),
+            LightShape::Spot { position, direction, outer, .. } => (position, Cone { axis: direction.normalize(), angle: 0.0 }, outer),
+        };
+        Some(Node { bbox: AABB { bounds: [position, position] }, axes, spread, intensity: light.mean_intensity(), first: 0, count: 1 })
+    }
+
+    fn merge(&self, other: &Node) -> Node {
+        Node {
+            bbox: self.bbox.merge(other.bbox),
+            axes: self.axes.merge(other.axes),
+            spread: self.spread.max(other.spread),
+            intensity: self.intensity + other.intensity,
+            first: self.first.min(other.first),
+            count: self.count + other.count,
+        }
+    }
+
+    /// A bound on the light the node sends to `p`, on a surface facing `normal`, up to a factor the same for all
+    /// nodes. Zero only where none of its lights shine on the front of the surface.
+    fn importance(&self, p: Point3D<f32, UnknownUnit>, normal: Vector3D<f32, UnknownUnit>) -> f32 {
+        let AABB { bounds: [low, high] } = self.bbox;
+        let center = low.lerp(high, 0.5);
+        let radius = 0.5*(high - low).length();
+        let offset = p - center;
+        let square = offset.square_length();
+        // Within the sphere around the box every direction may lead to a light
+        let (outwards, bounding) = if square > radius*radius {
+            (offset/square.sqrt(), (radius/square.sqrt()).asin())
+        } else {
+            (self.axes.axis, PI)
+        };
+        // The smallest angle between a light's direction to p and its axis, which the lights shine within `spread` of
+        let off_axis = (angle_between(self.axes.axis, outwards) - self.axes.angle - bounding).max(0.0);
+        if off_axis >= self.spread {
+            return 0.0;
+        }
+        // Likewise the smallest angle between the normal and a direction to a light
+        let incident = (angle_between(normal, -outwards) - bounding).max(0.0);
+        if incident >= FRAC_PI_2 {
+            return 0.0;
+        }
+        // Closer than the size of the box the distance says little, and it mustn't make the estimate infinite
+        let distance = square.max(radius*radius).max(1e-8);
+        self.intensity*off_axis.cos()*incident.cos()/distance
+    }
+}
+
+/// The lights of a scene, with a tree over those with a position to pick them by how much they are likely to light a
+/// point.
+#[derive(Clone, Default)]
+pub struct LightBVH {
+    /// The lights with a position, in the order of the leaves of the tree.
+    lights: Vec<TreeLight>,
+    directional: Vec<DeltaLight>,
+    nodes: Vec<Node>,
+    /// The objects of the lights with a surface, to tell their hits.
+    surfaces: Option<Arc<BVH<Arc<dyn Hitable>>>>,
+}
+
+impl LightBVH {
+    pub fn new(lights: Vec<DeltaLight>) -> LightBVH {
+        LightBVH::with_surfaces(lights, Vec::new())
+    }
+
+    /// A tree over `lights` and the objects emitting light `surfaces`, like those `sppm::find_lights` finds.
+    pub fn with_surfaces(lights: Vec<DeltaLight>, surfaces: Vec<Light>) -> LightBVH {
+        let (directional, lights): (Vec<_>, Vec<_>) = lights.into_iter()
+            .partition(|light| matches!(light.shape, LightShape::Directional { .. }));
+        let objects: Vec<Arc<dyn Hitable>> = surfaces.iter().map(|light| light.object().clone()).collect();
+        let mut leaves: Vec<(Node, TreeLight)> = lights.into_iter().map(TreeLight::Delta)
+            .chain(surfaces.into_iter().map(TreeLight::Surface))
+            .filter_map(|light| Node::leaf(&light).map(|node| (node, light)))
+            .collect();
+        let mut nodes = Vec::with_capacity((2*leaves.len()).saturating_sub(1));
+        if !leaves.is_empty() {
+            build(&mut leaves, 0, &mut nodes);
+        }
+        let surfaces = if objects.is_empty() { None } else { Some(Arc::new(BVH::initialize(objects))) };
+        LightBVH { lights: leaves.into_iter().map(|(_, light)| light).collect(), directional, nodes, surfaces }
+    }
+
+    /// The lights with a position, those picked with `sample`.
+    pub fn lights(&self) -> &[TreeLight] {
+        &self.lights
+    }
+
+    /// Whether `r` hits one of the lights with a surface at `t`, as it does when it hits an object at `t` that is
+    /// one of them.
+    pub fn emits_at(&self, r: Ray, t: f32) -> bool {
+        self.surfaces.as_ref().is_some_and(|surfaces| surfaces.hit(r, t*(1.0 - 1e-4), t*(1.0 + 1e-4)).is_some())
+    }
+
+    /// The lights shining along a direction, which aren't in the tree.
+    pub fn directional(&self) -> &[DeltaLight] {
+        &self.directional
+    }
+
+    /// Pick one of `lights` to aim at from `p`, on a surface facing `normal`, with the random number `u`, as its
+    /// index and the probability it was picked with. `None` when the way down ends where no light could shine on
+    /// the front of the surface, so the probabilities of the lights can add up to less than one.
+    pub fn sample(&self, p: Point3D<f32, UnknownUnit>, normal: Vector3D<f32, UnknownUnit>, u: f32) -> Option<(usize, f32)> {
+        let mut u = u;
+        let mut probability = 1.0;
+        let mut i = 0;
+        if self.nodes.first()?.importance(p, normal) <= 0.0 {
+            return None;
+        }
+        while self.nodes[i].count > 1 {
+            let (left, right) = self.children(i);
+            let left_share = self.share(left, right, p, normal)?;
+            // Reuse what is left of `u` further down
+            if u < left_share {
+                u /= left_share;
+                probability *= left_share;
+                i = left;
+            } else {
+                u = (u - left_share)/(1.0 - left_share);
+                probability *= 1.0 - left_share;
+                i = right;
+            }
+            u = u.min(1.0 - f32::EPSILON);
+        }
+        Some((self.nodes[i].first, probability))
+    }
+
+    /// The probability that `sample` picks `lights()[index]` at `p` on a surface facing `normal`.
+    pub fn probability(&self, p: Point3D<f32, UnknownUnit>, normal: Vector3D<f32, UnknownUnit>, index: usize) -> f32 {
+        if index >= self.lights.len() || self.nodes[0].importance(p, normal) <= 0.0 {
+            return 0.0;
+        }
+        let mut probability = 1.0;
+        let mut i = 0;
+        while self.nodes[i].count > 1 {
+            let (left, right) = self.children(i);
+            let left_share = match self.share(left, right, p, normal) {
+                Some(share) => share,
+                None => return 0.0,
+            };
+            if index < self.nodes[right].first {
+                probability *= left_share;
+                i = left;
+            } else {
+                probability *= 1.0 - left_share;
+                i = right;
+            }
+        }
+        probability
+    }
+
+    fn children(&self, i: usize) -> (usize, usize) {
+        let left = i + 1;
+        (left, left + 2*self.nodes[left].count - 1)
+    }
+
+    /// The probability of going left rather than right, if either can light the point.
+    fn share(&self, left: usize, right: usize, p: Point3D<f32, UnknownUnit>, normal: Vector3D<f32, UnknownUnit>) -> Option<f32> {
+        let left = self.nodes[left].importance(p, normal);
+        let right = self.nodes[right].importance(p, normal);
+        if left + right > 0.0 { Some(left/(left + right)) } else { None }
+    }
+}
+
+/// Push the nodes of a tree over `leaves`, the first of which is light `first`, onto `nodes`, depth first. Splits at
+/// the median along the axis the lights are spread out the most over, which leaves `leaves` sorted into the order of
+/// the leaves of the tree.
+fn build(leaves: &mut [(Node, TreeLight)], first: usize, nodes: &mut Vec<Node>) -> Node {
+    if leaves.len() == 1 {
+        let node = Node { first, ..leaves[0].0.clone() };
+        nodes.push(node.clone());
+        return node;
+    }
+    let bounds = leaves.iter().fold(AABB::empty(), |bounds, (node, _)| bounds.merge(node.bbox));
+    let [low, high] = bounds.bounds;
+    let extent = high - low;
+    let coordinate = |node: &Node| {
+        let p = node.bbox.bounds[0];
+        if extent.x >= extent.y && extent.x >= extent.z { p.x } else if extent.y >= extent.z { p.y } else { p.z }
+    };
+    leaves.sort_by(|(a, _), (b, _)| coordinate(a).partial_cmp(&coordinate(b)).unwrap_or(Ordering::Equal));
+    // Filled in once the children are built
+    let at = nodes.len();
+    nodes.push(leaves[0].0.clone());
+    let split = leaves.len()/2;
+    let (left, right) = leaves.split_at_mut(split);
+    let left = build(left, first, nodes);
+    let right = build(right, first + split, nodes);
+    let node = left.merge(&right);
+    nodes[at] = node.clone();
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use hitable::sphere::Sphere;
+    use material::light::DiffuseLight;
+    use random::*;
+    use sppm::find_lights;
+
+    /// Lamps and spots over the ground, with a moon that stays out of the tree.
+    fn city(n: usize) -> Vec<DeltaLight> {
+        let mut lights: Vec<DeltaLight> = (0..n).map(|i| {
+            let position = point3(next_f32()*20.0 - 10.0, 0.5 + next_f32()*3.0, next_f32()*20.0 - 10.0);
+            let color = Rgb::with_wp(next_f32(), next_f32(), next_f32());
+            let shape = if i % 3 == 0 {
+                LightShape::Spot { position, direction: rand_in_unit_sphere(), inner: 0.2, outer: 0.2 + next_f32() }
+            } else {
+                LightShape::Point { position }
+            };
+            DeltaLight::new(shape, color, next_f32()*5.0)
+        }).collect();
+        lights.push(DeltaLight::sun(vec3(0.0, -1.0, 0.0), Rgb::with_wp(0.5, 0.5, 0.5), 0.1));
+        lights
+    }
+
+    /// Small glowing spheres among the lamps, like windows.
+    fn windows(n: usize) -> Vec<Light> {
+        let objects: Vec<Arc<dyn Hitable>> = (0..n).map(|_| {
+            let center = point3(next_f32()*20.0 - 10.0, 1.0 + next_f32()*5.0, next_f32()*20.0 - 10.0);
+            let glow = Arc::new(DiffuseLight::new(Rgb::with_wp(next_f32(), next_f32(), next_f32())));
+            Arc::new(Sphere::new(center, 0.1 + next_f32()*0.3, glow)) as Arc<dyn Hitable>
+        }).collect();
+        find_lights(&objects)
+    }
+
+    fn shading_point() -> (Point3D<f32, UnknownUnit>, Vector3D<f32, UnknownUnit>) {
+        let p = point3(next_f32()*24.0 - 12.0, next_f32()*4.0, next_f32()*24.0 - 12.0);
+        (p, rand_in_unit_sphere::<f32>().normalize())
+    }
+
+    #[test]
+    fn test_cone_merge() {
+        let up = Cone { axis: vec3(0.0, 1.0, 0.0), angle: 0.1 };
+        let side = Cone { axis: vec3(1.0, 0.0, 0.0), angle: 0.2 };
+        let both = up.merge(side);
+        assert!((both.angle - 0.5*(0.2 + FRAC_PI_2 + 0.1)).abs() < 1e-5);
+        for cone in [up, side] {
+            assert!(angle_between(both.axis, cone.axis) + cone.angle <= both.angle + 1e-5);
+        }
+        assert_eq!(both.merge(up), both);
+        assert_eq!(up.merge(Cone { axis: vec3(0.0, -1.0, 0.0), angle: 0.0 }).angle, PI);
+    }
+
+    #[test]
+    fn test_probabilities() {
+        let lights = LightBVH::new(city(300));
+        assert_eq!((lights.lights().len(), lights.directional().len()), (300, 1));
+        assert_eq!(lights.nodes.len(), 2*300 - 1);
+        for _ in 0..200 {
+            let (p, normal) = shading_point();
+            // Less than one where the way down can end at lights that turn out to be out of reach
+            let total: f32 = (0..300).map(|i| lights.probability(p, normal, i)).sum();
+            assert!(total < 1.0 + 1e-3, "{}", total);
+            if let Some((i, probability)) = lights.sample(p, normal, next_f32()) {
+                assert!(probability > 0.0 && (lights.probability(p, normal, i) - probability).abs() < 1e-6);
+            }
+        }
+        assert!(LightBVH::new(Vec::new()).sample(point3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 0.5).is_none());
+    }
+
+    #[test]
+    fn test_unbiased() {
+        // The light falling on a point, from aiming at all of the lights and from picking one at a time,
+        // with lamps only and with windows among them
+        let irradiance = |light: &TreeLight, p, normal: Vector3D<f32, UnknownUnit>| {
+            light.sample(p, vec2(0.5, 0.5), 550.0).map_or(0.0, |sample| sample.irradiance*sample.direction.dot(normal).max(0.0))
+        };
+        for lights in [LightBVH::new(city(200)), LightBVH::with_surfaces(city(150), windows(50))].iter().cycle().take(10) {
+            let (p, normal) = shading_point();
+            let exact: f32 = lights.lights().iter().map(|light| irradiance(light, p, normal)).sum();
+            let n = 20000;
+            // Stratified, so the estimate doesn't depend on luck with the lights picked rarely
+            let estimate = (0..n).filter_map(|i| lights.sample(p, normal, (i as f32 + 0.5)/n as f32))
+                .map(|(i, probability)| irradiance(&lights.lights()[i], p, normal)/probability)
+                .sum::<f32>()/n as f32;
+            assert!((estimate - exact).abs() <= 0.05*exact + 1e-6, "{} {}", estimate, exact);
+        }
+    }
+
+    #[test]
+    fn test_emits_at() {
+        let lights = LightBVH::with_surfaces(city(20), windows(20));
+        assert_eq!(lights.lights().len(), 40);
+        let window = lights.lights().iter().find_map(|light| match *light {
+            TreeLight::Surface(ref light) => Some(light),
+            TreeLight::Delta(_) => None,
+        }).unwrap();
+        let AABB { bounds: [low, high] } = window.object().bbox();
+        let r = Ray::new(low.lerp(high, 0.5) + vec3(0.0, 0.0, 20.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0);
+        let t = window.object().hit(r, 0.0, f32::MAX).unwrap().t;
+        assert!(lights.emits_at(r, t));
+        assert!(!lights.emits_at(r, 0.5*t));
+        assert!(!LightBVH::new(city(20)).emits_at(r, t));
+    }
+
+    #[test]
+    fn test_prefers_lights_nearby() {
+        // Two streets of lamps far apart
+        let lamp = |x: f32, z: f32| DeltaLight::new(LightShape::Point { position: point3(x, 1.0, z) }, Rgb::with_wp(1.0, 1.0, 1.0), 1.0);
+        let lights = LightBVH::new((0..50).flat_map(|i| vec![lamp(i as f32, 0.0), lamp(i as f32, 100.0)]).collect());
+        let p = point3(25.0, 0.0, 0.0);
+        let near = (0..1000).filter_map(|i| lights.sample(p, vec3(0.0, 1.0, 0.0), (i as f32 + 0.5)/1000.0))
+            .filter(|&(i, _)| matches!(lights.lights()[i], TreeLight::Delta(ref light) if light.shape == LightShape::Point { position: point3(25.0, 1.0, 0.0) }))
+            .count();
+        assert!(near > 300, "{}", near);
+        // Facing down, none of them light it
+        assert!(lights.sample(p, vec3(0.0, -1.0, 0.0), 0.5).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+//! The light of an image split by the way it reached the camera, to adjust or check the parts of it apart.
+//!
+//! A path from the camera is sorted by the first surface it scatters off: light seen straight on the lights and the
+//! sky is emission, light off a diffuse surface is direct when it came from a light on the next step and indirect
+//! otherwise, and light off any other surface is specular when it was reflected and transmission when it went through.
+//! Every path lands in exactly one pass, so the passes add up to the image.
+
+use std::ops::{Add, AddAssign};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum PathPass {
+    /// Lights and the sky seen by the camera.
+    Emission,
+    /// Diffuse surfaces lit by the lights.
+    DirectDiffuse,
+    /// Diffuse surfaces lit by other surfaces.
+    IndirectDiffuse,
+    /// Reflections in surfaces that aren't diffuse, like mirrors, metals and the front of glass.
+    Specular,
+    /// What is seen through glass and other clear surfaces.
+    Transmission,
+}
+
+impl PathPass {
+    pub const ALL: [PathPass; 5] = [PathPass::Emission, PathPass::DirectDiffuse, PathPass::IndirectDiffuse, PathPass::Specular, PathPass::Transmission];
+
+    /// The name of the pass, which goes before the extension of the output for the file of the pass, see `output::sibling_path`.
+    pub fn name(self) -> &'static str {
+        match self {
+            PathPass::Emission => "emission",
+            PathPass::DirectDiffuse => "diffuse_direct",
+            PathPass::IndirectDiffuse => "diffuse_indirect",
+            PathPass::Specular => "specular",
+            PathPass::Transmission => "transmission",
+        }
+    }
+}
+
+/// A value for each `PathPass`.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct PathPasses<T> {
+    values: [T; 5],
+}
+
+impl<T: Copy> PathPasses<T> {
+    pub fn get(&self, pass: PathPass) -> T {
+        self.values[pass as usize]
+    }
+
+    pub fn map<U, F: Fn(T) -> U>(&self, f: F) -> PathPasses<U> {
+        let v = &self.values;
+        PathPasses { values: [f(v[0]), f(v[1]), f(v[2]), f(v[3]), f(v[4])] }
+    }
+
+    /// All passes added up, which is the whole image.
+    pub fn total(&self) -> T where T: Add<Output = T> {
+        self.values[1..].iter().fold(self.values[0], |total, &value| total + value)
+    }
+}
+
+impl<T: AddAssign> PathPasses<T> {
+    pub fn add(&mut self, pass: PathPass, value: T) {
+        self.values[pass as usize] += value;
+    }
+}
+
+/// Follows a path from the camera to tell which pass the light it finds on the way belongs to.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::light_paths::*;
+/// let mut tracker = PassTracker::default();
+/// assert_eq!(tracker.pass(), PathPass::Emission);
+/// // Off a mirror, then a wall
+/// tracker.scatter(false, true);
+/// tracker.scatter(true, true);
+/// assert_eq!(tracker.pass(), PathPass::Specular);
+/// ```
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct PassTracker {
+    first: Option<PathPass>,
+    bounces: u32,
+}
+
+impl PassTracker {
+    /// The pass of the light found at the current end of the path, emitted there or arriving there from a light.
+    pub fn pass(&self) -> PathPass {
+        match self.first {
+            None => PathPass::Emission,
+            Some(PathPass::DirectDiffuse) if self.bounces > 1 => PathPass::IndirectDiffuse,
+            Some(pass) => pass,
+        }
+    }
+
+    /// Move on past a surface that is diffuse or not, in a direction back off its side the path came from or through it.
+    pub fn scatter(&mut self, diffuse: bool, reflects: bool) {
+        if self.first.is_none() {
+            self.first = Some(match (diffuse, reflects) {
+                (true, _) => PathPass::DirectDiffuse,
+                (false, true) => PathPass::Specular,
+                (false, false) => PathPass::Transmission,
+            });
+        }
+        self.bounces += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker() {
+        let pass_after = |events: &[(bool, bool)]| {
+            let mut tracker = PassTracker::default();
+            for &(diffuse, reflects) in events {
+                tracker.scatter(diffuse, reflects);
+            }
+            tracker.pass()
+        };
+        assert_eq!(pass_after(&[]), PathPass::Emission);
+        assert_eq!(pass_after(&[(true, true)]), PathPass::DirectDiffuse);
+        assert_eq!(pass_after(&[(true, true), (true, true)]), PathPass::IndirectDiffuse);
+        // Light reaching a wall through glass is indirect, but a wall seen through glass stays transmission
+        assert_eq!(pass_after(&[(true, true), (false, false)]), PathPass::IndirectDiffuse);
+        assert_eq!(pass_after(&[(false, false), (true, true), (true, true)]), PathPass::Transmission);
+        assert_eq!(pass_after(&[(false, true)]), PathPass::Specular);
+    }
+
+    #[test]
+    fn test_passes_add_up() {
+        let mut passes = PathPasses::default();
+        passes.add(PathPass::Emission, 1.0);
+        passes.add(PathPass::Specular, 0.5);
+        passes.add(PathPass::Specular, 0.25);
+        assert_eq!(passes.get(PathPass::Specular), 0.75);
+        assert_eq!(passes.total(), 1.75);
+        assert_eq!(passes.map(|value| 2.0*value).total(), 3.5);
+        assert!(PathPass::ALL.iter().enumerate().all(|(i, &pass)| pass as usize == i));
+    }
+}
@@ -0,0 +1,213 @@
+//! Light tracing, which follows paths from the lights and adds the light they carry to the pixels they are seen in.
+//!
+//! Paths from the camera through glass rarely find a small light, so the caustics it casts on diffuse surfaces come out
+//! as noise. Traced from the lights like photons, such paths land on the diffuse surface through the glass,
+//! which is then connected straight to a point on the lens.
+//! Diffuse surfaces, those whose material `is_diffuse`, are treated as Lambertian as in photon mapping.
+//!
+//! Light tracing on its own misses whatever the camera sees through glass and mirrors, and is noisier than paths
+//! from the camera on surfaces lit directly. With `caustics_only` it leaves those to a path tracer, only adding paths
+//! that reached the diffuse surface seen by the camera through surfaces that aren't diffuse.
+//! The path tracer skips the same paths, which a `CausticTracker` finds, so none are counted twice.
+
+use std::f32::consts::PI;
+use euclid::*;
+use rayon::prelude::*;
+
+use camera::Camera;
+use color::{Sensor, WavelengthSampler};
+use film::Splats;
+use hitable::*;
+use material::Material;
+use ray::{Ray, RayKind};
+use random::*;
+use sampler::sample_disk;
+use settings::RenderSettings;
+use sppm::{emit_photon, Light};
+
+/// The image a light tracer adds to the pixels.
+pub struct LightTracer<'a> {
+    pub camera: &'a Camera,
+    /// The size of the image, and the limits of the paths.
+    pub settings: &'a RenderSettings,
+    pub lights: &'a [Light],
+    pub sensor: Sensor,
+    /// Only add paths the light took through surfaces that aren't diffuse before reaching the one seen by the camera.
+    pub caustics_only: bool,
+}
+
+/// Tracks whether a path from the camera is one that a light tracer with `caustics_only` covers.
+/// Those paths hit a diffuse surface first, and went through one that isn't diffuse after it.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct CausticTracker {
+    first_diffuse: bool,
+    caustic: bool,
+}
+
+impl CausticTracker {
+    /// Whether light emitted at the current hit of the path reaches the camera along a path the light tracer covers.
+    pub fn is_caustic(&self) -> bool {
+        self.caustic
+    }
+
+    /// Move on past hit number `depth`, counting from 0, on a surface that is diffuse or not.
+    pub fn scatter(&mut self, depth: u32, diffuse: bool) {
+        if depth == 0 {
+            self.first_diffuse = diffuse;
+        } else if self.first_diffuse && !diffuse {
+            self.caustic = true;
+        }
+    }
+}
+
+impl<'a> LightTracer<'a> {
+    /// Splat the light of `paths` paths traced from the lights onto `splats`, with a pixel per pixel of the image.
+    /// It is scaled so that tracing as many paths as there are pixels gives an estimate of the light at every pixel,
+    /// like a pass of paths from the camera.
+    pub fn trace<H: Hitable>(&self, world: &H, paths: usize, splats: &Splats) {
+        let total_power: f32 = self.lights.iter().map(|light| light.power()).sum();
+        if !(total_power > 0.0) || paths == 0 {
+            return;
+        }
+        let t_min = self.settings.t_min();
+        let scale = (self.settings.width*self.settings.height) as f32/paths as f32;
+        let wavelengths = &self.settings.wavelength_sampler();
+        (0..paths)
+            .into_par_iter()
+            .for_each_init(|| set_path_sampler(None), |_, _| self.trace_path(world, total_power, wavelengths, t_min, scale, splats));
+    }
+
+    fn trace_path<H: Hitable>(&self, world: &H, total_power: f32, wavelengths: &WavelengthSampler, t_min: TMin, scale: f32, splats: &Splats) {
+        let (mut r, mut flux) = match emit_photon(self.lights, total_power, wavelengths) {
+            Some(photon) => photon,
+            None => return,
+        };
+        let mut caustic = false;
+        let default_rate = self.settings.shading_rate();
+        for depth in 0.. {
+            let rec = match world.hit(r, t_min.t_min(r), f32::MAX) {
+                Some(rec) => rec,
+                None => return,
+            };
+            flux *= r.transmittance(rec.t);
+            let material = rec.texture.value(rec.uv);
+            let diffuse = material.is_diffuse();
+            let result = material.scatter(r, rec);
+            if diffuse && (caustic || !self.caustics_only) {
+                let albedo = result.reflection.map_or(0.0, |(attenuation, _)| attenuation);
+                self.splat(world, r, rec, t_min, flux*albedo/PI*scale, splats);
+            }
+            caustic |= !diffuse;
+            if depth + 1 >= rec.shading_rate.unwrap_or(default_rate).max_depth {
+                return;
+            }
+            match result.reflection {
+                None => return,
+                // Surviving by the attenuation keeps the flux of a path constant
+                Some((attenuation, ray)) => {
+                    let survival = attenuation.min(1.0);
+                    if !(next_f32() < survival) {
+                        return;
+                    }
+                    flux *= attenuation/survival;
+                    r = ray;
+                }
+            }
+        }
+    }
+
+    /// Add the `radiance` leaving the diffuse hit `rec` of `r_in` towards a random point on the lens to the pixel it is seen in.
+    fn splat<H: Hitable>(&self, world: &H, r_in: Ray, rec: HitRecord, t_min: TMin, radiance: f32, splats: &Splats) {
+        let lens = sample_disk(vec2(next_f32(), next_f32()));
+        let (film, from_lens, importance) = match self.camera.connect(rec.p, r_in.wl, r_in.ti, lens) {
+            Some(connection) => connection,
+            None => return,
+        };
+        // Pixels are placed on the film as paths from the camera are
+        let (width, height) = (self.settings.width, self.settings.height);
+        let (i, j) = ((film.x*width as f32).floor(), (film.y*height as f32).floor());
+        if !(i >= 0.0 && i < width as f32 && j >= 1.0 && j <= height as f32) {
+            return;
+        }
+        let pixel = (height - j as u32)*width + i as u32;
+        // Light only reflects back to the side it came from
+        // The camera sees the surface along it, so objects hidden from the camera don't block it
+        let to_lens = rec.spawn_ray(r_in, -from_lens.direction).with_kind(RayKind::Camera);
+        let cosine = to_lens.direction.dot(rec.facing_normal())/to_lens.direction.length();
+        if !(cosine > 0.0 && importance > 0.0) || world.is_occluded(to_lens, t_min.t_min(to_lens), 1.0) {
+            return;
+        }
+        // Scaled like paths from the camera
+        let weight = 3.0*radiance*cosine/to_lens.direction.square_length()*importance*to_lens.transmittance(1.0);
+        splats.splat(pixel as usize, self.sensor.xyz(r_in.wl)*weight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use camera::{CameraKeyframe, Movements};
+    use hitable::sphere::Sphere;
+    use hitable::bvh::BVH;
+    use color::{ColorSpectrum, SensorResponse};
+    use material::Lambertian;
+    use material::light::DiffuseLight;
+    use sppm::find_lights;
+
+    #[test]
+    fn test_caustic_tracker() {
+        let path = |hits: &[bool]| {
+            let mut tracker = CausticTracker::default();
+            hits.iter().enumerate().map(|(depth, &diffuse)| {
+                let caustic = tracker.is_caustic();
+                tracker.scatter(depth as u32, diffuse);
+                caustic
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(path(&[true, true, false, true, true]), vec![false, false, false, true, true]);
+        assert_eq!(path(&[false, true, false, true]), vec![false; 4]);
+    }
+
+    #[test]
+    fn test_lit_sphere() {
+        // A white sphere lit by a small glowing one right above its top, seen from the side
+        let objects: Vec<Arc<dyn Hitable>> = vec![
+            Arc::new(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, Arc::new(Lambertian::new(ColorSpectrum::new([1.0; 36]))))),
+            Arc::new(Sphere::new(point3(0.0, 0.0, 1.5), 0.1, Arc::new(DiffuseLight::new(ColorSpectrum::new([100.0; 36]))))),
+        ];
+        let lights = find_lights(&objects);
+        assert_eq!(lights.len(), 1);
+        let world = BVH::initialize(objects);
+        let camera = Camera::new(&CameraKeyframe { look_from: point3(0.0, 3.0, 3.0), look_at: point3(0.0, 0.0, 1.0), vfov: 4.0, aperture: 0.0, focus_dist: 3.6, movements: Movements::default() }, vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0);
+        // Recording every wavelength alike keeps the test from depending on the spectrum
+        let grey = ColorSpectrum::new([1.0; 36]);
+        let sensor = Sensor::Response(SensorResponse::new(grey, grey, grey));
+        let settings = RenderSettings::default().with_size(16, 16);
+        let tracer = LightTracer { camera: &camera, settings: &settings, lights: &lights, sensor, caustics_only: false };
+        let image = Splats::new(16*16);
+        tracer.trace(&world, 1 << 20, &image);
+
+        // A glowing sphere gives an irradiance of its radiance times π(radius/distance)² and the cosine towards it,
+        // which a white surface reflects as that over π. Paths from the camera triple the light they see.
+        let expected = |x: u32, y: u32| {
+            let r = camera.get_ray(x as f32/16.0 + 0.5/16.0, 1.0 - y as f32/16.0 + 0.5/16.0, 550.0);
+            let p = world.hit(r, 1e-3, f32::MAX).unwrap().p;
+            let to_light = point3(0.0, 0.0, 1.5) - p;
+            3.0*100.0*(0.1/to_light.length()).powi(2)*to_light.normalize().dot(p.to_vector())
+        };
+        let (mut sum, mut sum_expected) = (0.0, 0.0);
+        for y in 4..12 {
+            for x in 4..12 {
+                sum += image.get((y*16 + x) as usize).y;
+                sum_expected += expected(x, y);
+            }
+        }
+        assert!((sum/sum_expected - 1.0).abs() < 0.05, "{} instead of {}", sum/64.0, sum_expected/64.0);
+
+        // Nothing on the way is glass or a mirror
+        let caustics = Splats::new(16*16);
+        LightTracer { caustics_only: true, ..tracer }.trace(&world, 1 << 12, &caustics);
+        assert!((0..16*16).all(|pixel| caustics.get(pixel).y == 0.0));
+    }
+}
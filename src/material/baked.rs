@@ -0,0 +1,173 @@
+//! Tables of how much light a material reflects and transmits depending on the angle of incidence and the wavelength,
+//! baked once so preview renders don't have to evaluate expensive materials at every hit.
+
+use euclid::*;
+use palette::Rgb;
+
+use color::{HasReflectance, BinData, Bin36, ColorSpectrum};
+use hitable::*;
+use material::*;
+use texture::Texture;
+
+/// Cosines of the angle of incidence the table is baked for, spread evenly from grazing to head on.
+const COSINE_BINS: usize = 16;
+
+/// Reflected light within this cosine of the mirror direction counts as specular.
+const SPECULAR_COSINE: f32 = 0.999;
+
+/// The directional-hemispherical response of a material, which stands in for it as a cheap material of its own.
+///
+/// Reflected light leaves either along the mirror direction or diffusely, in the proportion the baked material had,
+/// and transmitted light passes straight through, so the table keeps the brightness and color of the material
+/// but not the shape of its lobes, nor the bending of refracted rays.
+/// Only the front of the material is baked and emission is left out.
+#[derive(Debug, Clone)]
+pub struct ResponseTable {
+    reflectance: Vec<ColorSpectrum>,
+    transmittance: Vec<ColorSpectrum>,
+    /// The part of the reflectance along the mirror direction.
+    specular: Vec<ColorSpectrum>,
+}
+
+impl ResponseTable {
+    /// Bake the material `texture` has in the middle of its texture coordinates, scattering `samples` rays per entry.
+    pub fn bake(texture: &dyn Texture, samples: u32) -> ResponseTable {
+        let uv = vec2(0.5, 0.5);
+        let material = texture.value(uv);
+        let normal = vec3(0.0, 1.0, 0.0);
+        let rec = HitRecord {
+            t: 1.0,
+            p: point3(0.0, 0.0, 0.0),
+            uv,
+            normal,
+            geometric_normal: normal,
+            front_face: true,
+            texture,
+            shading_rate: None,
+            object_id: None,
+            tangent: None,
+            edge_distance: None,
+            error: 0.0,
+        };
+        let samples = samples.max(1);
+        let mut table = ResponseTable { reflectance: Vec::new(), transmittance: Vec::new(), specular: Vec::new() };
+        for bin in 0..COSINE_BINS {
+            let cosine = (bin as f32 + 0.5)/COSINE_BINS as f32;
+            let direction = vec3(f32::sqrt(1.0 - cosine*cosine), -cosine, 0.0);
+            let mirror = reflect(direction, normal);
+            // Reflected, transmitted and specular light for a wavelength
+            let response = |wl: f32| {
+                let r_in = Ray::new(rec.p - direction, direction, wl, 0.0);
+                let mut sums = [0.0; 3];
+                for _ in 0..samples {
+                    if let Some((attenuation, scattered)) = material.scatter(r_in, rec).reflection {
+                        let out = scattered.direction.normalize();
+                        if out.dot(normal) > 0.0 {
+                            sums[0] += attenuation;
+                            if out.dot(mirror) > SPECULAR_COSINE {
+                                sums[2] += attenuation;
+                            }
+                        } else {
+                            sums[1] += attenuation;
+                        }
+                    }
+                }
+                [sums[0]/samples as f32, sums[1]/samples as f32, sums[2]/samples as f32]
+            };
+            let mut parts = [[0.0; 36]; 3];
+            for i in 0..36 {
+                let [reflected, transmitted, specular] = response(Bin36::WL_0 + Bin36::BIN_WIDTH*(i as f32 + 0.5));
+                parts[0][i] = reflected;
+                parts[1][i] = transmitted;
+                parts[2][i] = specular;
+            }
+            table.reflectance.push(ColorSpectrum::new(parts[0]));
+            table.transmittance.push(ColorSpectrum::new(parts[1]));
+            table.specular.push(ColorSpectrum::new(parts[2]));
+        }
+        table
+    }
+
+    fn bin(cosine: f32) -> usize {
+        ((cosine*COSINE_BINS as f32) as usize).min(COSINE_BINS - 1)
+    }
+
+    /// Fraction of the light arriving at the given cosine to the normal that is reflected, for a wavelength in nm.
+    pub fn reflectance(&self, wl: f32, cosine: f32) -> f32 {
+        self.reflectance[ResponseTable::bin(cosine.abs())].reflect(wl)
+    }
+
+    /// Fraction of the light arriving at the given cosine to the normal that passes through the surface.
+    pub fn transmittance(&self, wl: f32, cosine: f32) -> f32 {
+        self.transmittance[ResponseTable::bin(cosine.abs())].reflect(wl)
+    }
+
+    /// Fraction of the light arriving at the given cosine to the normal that is reflected along the mirror direction.
+    pub fn specular(&self, wl: f32, cosine: f32) -> f32 {
+        self.specular[ResponseTable::bin(cosine.abs())].reflect(wl)
+    }
+}
+
+impl Material for ResponseTable {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        let cosine = r_in.direction.dot(rec.normal) / r_in.direction.length();
+        let reflected = self.reflectance(r_in.wl, cosine);
+        let total = reflected + self.transmittance(r_in.wl, cosine);
+        if total <= 0.0 {
+            return ScatterResult { emittance: 0.0, reflection: None };
+        }
+        // Choosing by the share of each part and attenuating by their sum keeps the brightness unbiased.
+        let ray = if sample_1d()*total >= reflected {
+            rec.spawn_ray(r_in, r_in.direction)
+        } else if sample_1d()*reflected < self.specular(r_in.wl, cosine) {
+            rec.spawn_ray(r_in, reflect(r_in.direction, rec.normal))
+        } else {
+            let white = Lambertian::new(Rgb::with_wp(1.0, 1.0, 1.0));
+            match white.scatter(r_in, rec).reflection {
+                Some((_, ray)) => ray,
+                None => return ScatterResult { emittance: 0.0, reflection: None },
+            }
+        };
+        ScatterResult { emittance: 0.0, reflection: Some((total, ray)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use material::thin_film::ThinFilm;
+
+    #[test]
+    fn test_lambertian() {
+        let table = ResponseTable::bake(&Lambertian::new(ColorSpectrum::from_fn(|wl| if wl < 550.0 { 0.2 } else { 0.8 })), 64);
+        for &cosine in [0.1, 0.5, 1.0].iter() {
+            assert!((table.reflectance(450.0, cosine) - 0.2).abs() < 1e-4);
+            assert!((table.reflectance(650.0, cosine) - 0.8).abs() < 1e-4);
+            assert_eq!(table.transmittance(650.0, cosine), 0.0);
+            assert!(table.specular(650.0, cosine) < 0.05, "{}", table.specular(650.0, cosine));
+        }
+    }
+
+    #[test]
+    fn test_mirror() {
+        let table = ResponseTable::bake(&Metal::new(Rgb::with_wp(0.9, 0.9, 0.9), 0.0), 16);
+        let reflectance = table.reflectance(550.0, 0.7);
+        assert!(reflectance > 0.5);
+        assert!((table.specular(550.0, 0.7) - reflectance).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_thin_film() {
+        let film = ThinFilm::new(1.33, 380.0);
+        let table = ResponseTable::bake(&film, 4096);
+        for &wl in [455.0, 555.0, 655.0].iter() {
+            for &cosine in [0.25, 0.75].iter() {
+                // The table is baked at the centers of its bins
+                let cosine = (cosine*COSINE_BINS as f32).floor()/COSINE_BINS as f32 + 0.5/COSINE_BINS as f32;
+                let expected = film.reflectance(wl, cosine);
+                assert!((table.reflectance(wl, cosine) - expected).abs() < 0.03, "wl={}, cosine={}", wl, cosine);
+                assert!((table.reflectance(wl, cosine) + table.transmittance(wl, cosine) - 1.0).abs() < 1e-4);
+            }
+        }
+    }
+}
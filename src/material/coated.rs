@@ -0,0 +1,84 @@
+//! A clear coat over another material, like the lacquer on car paint or varnished wood.
+
+use std::sync::Arc;
+
+use hitable::HitRecord;
+use material::*;
+use texture::Texture;
+
+/// A thin clear layer over `base`, which reflects the fraction of the light the Fresnel term gives for its index of
+/// refraction and lets the rest through to the base. The base can be any material or texture, so a coat over an
+/// image of wood makes it varnished, and one over a metal paint makes it look like a car's.
+///
+/// The roughness blurs the reflection of the coat, from a mirror at 0 to fully fuzzy at 1. Only the outside of a
+/// surface is coated, so rays inside a transparent base reach its back faces as they would without the coat.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # use std::sync::Arc;
+/// # use palette::Rgb;
+/// # use rayer::material::Lambertian;
+/// # use rayer::material::coated::Coated;
+/// let red_paint = Coated::new(Arc::new(Lambertian::new(Rgb::with_wp(0.6, 0.05, 0.05))), 1.5);
+/// let satin = red_paint.with_roughness(0.2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Coated {
+    base: Arc<dyn Texture>,
+    ior: f32,
+    roughness: f32,
+}
+
+impl Coated {
+    /// A polished coat with the index of refraction `ior`, about 1.5 for most lacquers.
+    pub fn new(base: Arc<dyn Texture>, ior: f32) -> Coated {
+        Coated { base, ior, roughness: 0.0 }
+    }
+
+    pub fn with_roughness(self, roughness: f32) -> Coated {
+        Coated { roughness: roughness.max(0.0).min(1.0), ..self }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        // Choosing the layer with the reflectance of the coat as probability keeps the estimate unbiased, and light
+        // the base emits comes through as much as the coat lets through.
+        if rec.front_face {
+            let cosine = -r_in.direction.dot(rec.normal)/r_in.direction.length();
+            if sample_1d() < schlick(cosine, self.ior) {
+                return Metal::new(Flat(1.0), self.roughness).scatter(r_in, rec);
+            }
+        }
+        self.base.value(rec.uv).scatter(r_in, rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use fixtures::{hit_unit_sphere, mean_attenuation};
+
+    /// The mean attenuation of rays hitting a coat over black from `origin` along `direction`, towards the center of a
+    /// unit sphere, which is how often the coat reflects them.
+    fn reflected(origin: Point3D<f32, UnknownUnit>, direction: Vector3D<f32, UnknownUnit>) -> f32 {
+        let coat = Coated::new(Arc::new(Lambertian::new(Rgb::with_wp(0.0, 0.0, 0.0))), 1.5);
+        let ray = Ray::new(origin, direction, 500.0, 0.0);
+        hit_unit_sphere(&coat, ray, |rec| mean_attenuation(&coat, ray, rec, 100_000))
+    }
+
+    #[test]
+    fn test_fresnel_reflectance() {
+        let head_on = reflected(point3(0.0, 0.0, -2.0), vec3(0.0, 0.0, 1.0));
+        assert!((head_on - 0.04).abs() < 0.005, "{}", head_on);
+        let grazing = reflected(point3(0.0, 0.99, -2.0), vec3(0.0, 0.0, 1.0));
+        assert!(grazing > 0.3, "{}", grazing);
+    }
+
+    #[test]
+    fn test_inside_is_not_coated() {
+        assert_eq!(reflected(point3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0)), 0.0);
+    }
+}
@@ -0,0 +1,73 @@
+//! Optical glasses by their catalog names.
+//! Coefficients are from the Schott and Ohara datasheets, with `c` in µm² as printed there.
+
+use material::Dielectric;
+
+pub const CATALOG: &[(&str, Dielectric)] = &[
+    // Schott
+    ("N-BK7", Dielectric::sellmeier([1.03961212, 0.231792344, 1.01046945], [0.00600069867, 0.0200179144, 103.560653])),
+    ("N-K5", Dielectric::sellmeier([1.08511833, 0.199562005, 0.930511663], [0.00661099503, 0.024110866, 111.982777])),
+    ("N-BAK1", Dielectric::sellmeier([1.12365662, 0.309276848, 0.881511957], [0.00644742752, 0.0222284402, 107.297751])),
+    ("N-BAK4", Dielectric::sellmeier([1.28834642, 0.132817724, 0.945395373], [0.00779980626, 0.0315631177, 105.965875])),
+    ("N-BAF10", Dielectric::sellmeier([1.5851495, 0.143559385, 1.08521269], [0.00926681282, 0.0424489805, 105.613573])),
+    ("N-SK16", Dielectric::sellmeier([1.34317774, 0.241144399, 0.994317969], [0.00704687339, 0.0229005, 92.7508526])),
+    ("N-FK51A", Dielectric::sellmeier([0.971247817, 0.216901417, 0.904651666], [0.00472301995, 0.0153575612, 168.68133])),
+    ("F2", Dielectric::sellmeier([1.34533359, 0.209073176, 0.937357162], [0.00997743871, 0.0470450767, 111.886764])),
+    ("N-F2", Dielectric::sellmeier([1.39757037, 0.159201403, 1.2686543], [0.00995906143, 0.0546931752, 119.248346])),
+    ("N-SF5", Dielectric::sellmeier([1.52481889, 0.187085527, 1.42729015], [0.011254756, 0.0588995392, 129.141675])),
+    ("N-SF6", Dielectric::sellmeier([1.77931763, 0.338149866, 2.08734474], [0.0133714182, 0.0617533621, 174.01759])),
+    ("N-SF10", Dielectric::sellmeier([1.62153902, 0.256287842, 1.64447552], [0.0122241457, 0.0595736775, 147.468793])),
+    ("N-SF11", Dielectric::sellmeier([1.73759695, 0.313747346, 1.89878101], [0.013188707, 0.0623068142, 155.23629])),
+    ("N-SF66", Dielectric::sellmeier([2.0245976, 0.470187196, 2.59970433], [0.0147053225, 0.0692998276, 161.817601])),
+    ("N-LASF9", Dielectric::sellmeier([2.00029547, 0.298926886, 1.80691843], [0.0121426017, 0.0538736236, 156.530829])),
+    // Ohara
+    ("S-BSL7", Dielectric::sellmeier([1.15150190, 0.118583612, 1.26301359], [0.0105984130, -0.0118225190, 129.617662])),
+    ("S-FPL51", Dielectric::sellmeier([1.17010505, 0.0475710783, 0.763832445], [0.00616203924, 0.0263076937, 138.116542])),
+    ("S-TIH6", Dielectric::sellmeier([1.77227611, 0.34569125, 2.40788501], [0.0131182633, 0.0614479619, 200.753254])),
+    // Malitson (1965)
+    ("FUSED-SILICA", Dielectric::sellmeier([0.6961663, 0.4079426, 0.8974794], [0.004679148, 0.01351206, 97.934003])),
+];
+
+/// Look up a glass by name, ignoring case.
+/// The vendor prefix can be left out, so "bk7" finds N-BK7.
+pub fn by_name(name: &str) -> Option<Dielectric> {
+    let without_prefix = |catalog_name: &str| catalog_name.splitn(2, '-').nth(1).map_or(false, |short| short.eq_ignore_ascii_case(name));
+    CATALOG.iter()
+        .find(|&&(catalog_name, _)| catalog_name.eq_ignore_ascii_case(name))
+        .or_else(|| CATALOG.iter().find(|&&(catalog_name, _)| without_prefix(catalog_name)))
+        .map(|&(_, ref glass)| glass.clone())
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    CATALOG.iter().map(|&(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_matches_datasheet_nd() {
+        // Refractive index at the helium d line, 587.56nm
+        let nd = [
+            ("N-BK7", 1.5168), ("N-K5", 1.52249), ("N-BAK1", 1.5725), ("N-BAK4", 1.56883),
+            ("N-BAF10", 1.67003), ("N-SK16", 1.62041), ("N-FK51A", 1.48656), ("F2", 1.62004),
+            ("N-F2", 1.62005), ("N-SF5", 1.67271), ("N-SF6", 1.80518), ("N-SF10", 1.72828),
+            ("N-SF11", 1.78472), ("N-SF66", 1.92286), ("N-LASF9", 1.85025), ("S-BSL7", 1.51633),
+            ("S-FPL51", 1.497), ("S-TIH6", 1.80518), ("FUSED-SILICA", 1.4585),
+        ];
+        assert_eq!(nd.len(), CATALOG.len());
+        for &(name, expected) in nd.iter() {
+            let n = by_name(name).unwrap().refractive_index(587.56);
+            assert!((n - expected).abs() < 1e-3, "{}: n_d={} but expected {}", name, n, expected);
+        }
+    }
+
+    #[test]
+    fn test_by_name() {
+        assert_eq!(by_name("bk7"), by_name("N-BK7"));
+        assert_eq!(by_name("N-BK7"), Some(Dielectric::BK7));
+        assert_eq!(by_name("F2"), Some(CATALOG[7].1.clone()));
+        assert_eq!(by_name("unobtainium"), None);
+    }
+}
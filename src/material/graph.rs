@@ -0,0 +1,324 @@
+//! A small node graph to combine materials and textures without writing a new type for every look.
+//! Value nodes are evaluated for the wavelength of the incoming ray at every hit.
+
+use euclid::*;
+use palette::Rgb;
+use palette::white_point::E;
+use std::fmt;
+use std::sync::Arc;
+
+use color::{HasReflectance, ColorSpectrum};
+use hitable::*;
+use material::*;
+use ray::{Ray, RayKind};
+use sampler::sample_disk;
+use texture::ImageTexture;
+
+/// A scalar or spectral value computed per shading point.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Constant(f32),
+    Color(Rgb<E, f32>),
+    Spectrum(ColorSpectrum),
+    /// Image lookup at the texture coordinates of the hit.
+    Image(ImageTexture),
+    /// Fractal value noise in [0,1] over the hit position.
+    Noise { scale: f32, octaves: u32 },
+    /// Schlick's approximation of the Fresnel reflectance for the given index of refraction.
+    Fresnel { ior: f32 },
+    /// Linear blend from `a` to `b`.
+    Mix { a: Box<Node>, b: Box<Node>, factor: Box<Node> },
+    Multiply(Box<Node>, Box<Node>),
+    /// Fraction of the hemisphere above the hit that is open within `distance`,
+    /// estimated with `samples` probe rays. Darkens crevices when multiplied in.
+    Occlusion { geometry: ProbeGeometry, distance: f32, samples: u32 },
+    /// How convex the surface is within `radius` of the hit,
+    /// from 0 for flat or concave areas to 1 for sharp edges.
+    Curvature { geometry: ProbeGeometry, radius: f32 },
+}
+
+/// The geometry wear nodes trace their probe rays against, usually the object carrying the material.
+/// It has to be in the same space as the hits, so pass the object with its transforms applied.
+#[derive(Clone)]
+pub struct ProbeGeometry {
+    object: Arc<dyn Hitable>,
+    t_min: TMin,
+}
+
+impl ProbeGeometry {
+    pub fn new(object: Arc<dyn Hitable>) -> ProbeGeometry {
+        let t_min = TMin::default();
+        ProbeGeometry { object, t_min }
+    }
+
+    fn hit(&self, r: Ray, t_max: f32) -> Option<HitRecord> {
+        self.object.hit(r, self.t_min.t_min(r), t_max)
+    }
+
+    fn is_occluded(&self, r: Ray, t_max: f32) -> bool {
+        self.object.is_occluded(r, self.t_min.t_min(r), t_max)
+    }
+}
+
+impl fmt::Debug for ProbeGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProbeGeometry").field("bbox", &self.object.bbox()).finish()
+    }
+}
+
+impl Node {
+    pub fn mix(a: Node, b: Node, factor: Node) -> Node {
+        Node::Mix { a: Box::new(a), b: Box::new(b), factor: Box::new(factor) }
+    }
+
+    pub fn multiply(a: Node, b: Node) -> Node {
+        Node::Multiply(Box::new(a), Box::new(b))
+    }
+
+    /// Darken the crevices of `albedo` and lighten its edges, as if the surface had worn off where it sticks out.
+    /// `radius` is the size of the features that should show wear.
+    pub fn wear(albedo: Node, geometry: ProbeGeometry, radius: f32) -> Node {
+        let occluded = Node::multiply(albedo, Node::Occlusion { geometry: geometry.clone(), distance: radius, samples: 8 });
+        let edges = Node::multiply(Node::Curvature { geometry, radius }, Node::Constant(0.5));
+        Node::mix(occluded, Node::Constant(0.9), edges)
+    }
+
+    pub fn eval(&self, r_in: &Ray, rec: &HitRecord) -> f32 {
+        match *self {
+            Node::Constant(value) => value,
+            Node::Color(ref color) => color.reflect(r_in.wl),
+            Node::Spectrum(ref spectrum) => spectrum.reflect(r_in.wl),
+            Node::Image(ref image) => image.color(rec.uv).reflect(r_in.wl),
+            Node::Noise { scale, octaves } => fractal_noise(rec.p.to_vector()*scale, octaves),
+            Node::Fresnel { ior } => {
+                let cosine = r_in.direction.dot(rec.normal).abs() / r_in.direction.length();
+                schlick(cosine, ior)
+            },
+            Node::Mix { ref a, ref b, ref factor } => {
+                let factor = factor.eval(r_in, rec);
+                a.eval(r_in, rec)*(1.0 - factor) + b.eval(r_in, rec)*factor
+            },
+            Node::Multiply(ref a, ref b) => a.eval(r_in, rec)*b.eval(r_in, rec),
+            Node::Occlusion { ref geometry, distance, samples } => occlusion(geometry, r_in, rec, distance, samples),
+            Node::Curvature { ref geometry, radius } => curvature(geometry, r_in, rec, radius),
+        }
+    }
+}
+
+/// The normal on the side of the surface the ray arrived from, with two tangents.
+fn shading_frame(rec: &HitRecord) -> [Vector3D<f32, UnknownUnit>; 3] {
+    let normal = rec.facing_normal();
+    let u = if normal.x.abs()<0.5 {
+        vec3(0.0, -normal.z, normal.y).normalize()
+    } else {
+        vec3(-normal.z, 0.0, normal.x).normalize()
+    };
+    [normal, u, normal.cross(u)]
+}
+
+fn occlusion(geometry: &ProbeGeometry, r_in: &Ray, rec: &HitRecord, distance: f32, samples: u32) -> f32 {
+    let [normal, u, w] = shading_frame(rec);
+    let samples = samples.max(1);
+    let open = (0..samples)
+        .filter(|_| {
+            // Cosine weighted, like the light the crevice would receive
+            let p = sample_disk(vec2(next_f32(), next_f32()));
+            let direction = u*p.x + w*p.y + normal*f32::sqrt(1.0 - p.square_length());
+            !geometry.is_occluded(rec.spawn_ray(*r_in, direction).with_kind(RayKind::Shadow), distance)
+        })
+        .count();
+    open as f32 / samples as f32
+}
+
+fn curvature(geometry: &ProbeGeometry, r_in: &Ray, rec: &HitRecord, radius: f32) -> f32 {
+    let [normal, u, w] = shading_frame(rec);
+    // Find the surface next to the hit by probing down along the normal, and see how its normal turns
+    let mut sum = 0.0;
+    for &offset in [u, -u, w, -w].iter() {
+        let probe = Ray::new(rec.p + (normal + offset)*radius, -normal, r_in.wl, r_in.ti);
+        sum += match geometry.hit(probe, 2.0*radius) {
+            Some(neighbour) => {
+                let neighbour_normal = if neighbour.normal.dot(normal) < 0.0 { -neighbour.normal } else { neighbour.normal };
+                neighbour_normal.dot(offset).max(0.0).min(1.0)
+            },
+            // The surface drops away, so we're on an edge
+            None => 1.0,
+        };
+    }
+    sum / 4.0
+}
+
+/// The root of a graph, describing how light scatters.
+#[derive(Debug, Clone)]
+pub enum MaterialNode {
+    Diffuse { albedo: Node },
+    Metal { albedo: Node, fuzz: f32 },
+    Dielectric(Dielectric),
+    Emission { color: Node },
+    /// Scatter like `b` with probability `factor`, otherwise like `a`.
+    Mix { a: Box<MaterialNode>, b: Box<MaterialNode>, factor: Node },
+}
+
+impl MaterialNode {
+    pub fn mix(a: MaterialNode, b: MaterialNode, factor: Node) -> MaterialNode {
+        MaterialNode::Mix { a: Box::new(a), b: Box::new(b), factor }
+    }
+}
+
+impl Material for MaterialNode {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        match *self {
+            MaterialNode::Diffuse { ref albedo } =>
+                Lambertian::new(Flat(albedo.eval(&r_in, &rec))).scatter(r_in, rec),
+            MaterialNode::Metal { ref albedo, fuzz } =>
+                Metal::new(Flat(albedo.eval(&r_in, &rec)), fuzz).scatter(r_in, rec),
+            MaterialNode::Dielectric(ref dielectric) => dielectric.scatter(r_in, rec),
+            MaterialNode::Emission { ref color } =>
+                ScatterResult { emittance: color.eval(&r_in, &rec), reflection: None },
+            MaterialNode::Mix { ref a, ref b, ref factor } => {
+                // Choosing with the blend weight as probability keeps the estimate unbiased.
+                if sample_1d() < factor.eval(&r_in, &rec) {
+                    b.scatter(r_in, rec)
+                } else {
+                    a.scatter(r_in, rec)
+                }
+            },
+        }
+    }
+
+    /// A mix of diffuse materials is diffuse, its attenuation picks one albedo or the other by the blend weight.
+    fn is_diffuse(&self) -> bool {
+        match *self {
+            MaterialNode::Diffuse { .. } => true,
+            MaterialNode::Mix { ref a, ref b, .. } => a.is_diffuse() && b.is_diffuse(),
+            _ => false,
+        }
+    }
+}
+
+fn lattice(x: i32, y: i32, z: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343)
+        ^ (y as u32).wrapping_mul(0xd8163841)
+        ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    (h >> 8) as f32 / ((1u32 << 24) as f32)
+}
+
+/// Trilinearly interpolated value noise in [0,1].
+fn value_noise(p: Vector3D<f32, UnknownUnit>) -> f32 {
+    let base = vec3(p.x.floor(), p.y.floor(), p.z.floor());
+    let f = p - base;
+    let smooth = |t: f32| t*t*(3.0 - 2.0*t);
+    let (sx, sy, sz) = (smooth(f.x), smooth(f.y), smooth(f.z));
+    let (x, y, z) = (base.x as i32, base.y as i32, base.z as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a)*t;
+    let plane = |dz: i32| lerp(
+        lerp(lattice(x, y, z+dz), lattice(x+1, y, z+dz), sx),
+        lerp(lattice(x, y+1, z+dz), lattice(x+1, y+1, z+dz), sx),
+        sy,
+    );
+    lerp(plane(0), plane(1), sz)
+}
+
+fn fractal_noise(p: Vector3D<f32, UnknownUnit>, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut weight = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves.max(1) {
+        sum += value_noise(p*frequency)*amplitude;
+        weight += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use hitable::bvh::BVH;
+    use hitable::sphere::Sphere;
+    use hitable::triangle::axis_aligned_cuboid;
+    use texture::Texture;
+
+    fn eval_at(node: &Node, origin: Point3D<f32, UnknownUnit>) -> f32 {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture);
+        let ray = Ray::new(origin, vec3(1.0, 0.0, 0.0), 500.0, 0.0);
+        let rec = sphere.hit(ray, 0.001, 10.0).unwrap();
+        node.eval(&ray, &rec)
+    }
+
+    #[test]
+    fn test_mix_and_multiply() {
+        let node = Node::mix(Node::Constant(0.2), Node::multiply(Node::Constant(0.5), Node::Constant(2.0)), Node::Constant(0.25));
+        assert!((eval_at(&node, point3(-2.0, 0.0, 0.0)) - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fresnel_grows_at_grazing_angles() {
+        let fresnel = Node::Fresnel { ior: 1.5 };
+        let head_on = eval_at(&fresnel, point3(-2.0, 0.0, 0.0));
+        assert!((head_on - 0.04).abs() < 1e-3);
+        assert!(eval_at(&fresnel, point3(-2.0, 0.99, 0.0)) > head_on);
+    }
+
+    #[test]
+    fn test_wear_on_cube() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let cube: Arc<dyn Hitable> = Arc::new(axis_aligned_cuboid(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0), texture));
+        let geometry = ProbeGeometry::new(cube.clone());
+        let curvature = Node::Curvature { geometry: geometry.clone(), radius: 0.1 };
+        let occlusion = Node::Occlusion { geometry, distance: 0.5, samples: 16 };
+        let eval = |node: &Node, origin: Point3D<f32, UnknownUnit>| {
+            let ray = Ray::new(origin, vec3(0.0, 0.0, 1.0), 500.0, 0.0);
+            let rec = cube.hit(ray, 0.001, 10.0).unwrap();
+            node.eval(&ray, &rec)
+        };
+        // The middle of a face is flat and open, close to an edge it is convex
+        assert_eq!(eval(&curvature, point3(0.0, 0.0, -2.0)), 0.0);
+        assert!(eval(&curvature, point3(0.95, 0.0, -2.0)) >= 0.25);
+        assert_eq!(eval(&occlusion, point3(0.0, 0.0, -2.0)), 1.0);
+    }
+
+    #[test]
+    fn test_frame_faces_the_front() {
+        use hitable::triangle::Triangle;
+        // The shading normals lean so far that the ray arrives from behind them, on the front of the triangle
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let leaning = vec3(1.0, 0.0, 1.0).normalize();
+        let uv = vec2(0.0, 0.0);
+        let vert = (point3(-1.0, -1.0, 0.0), point3(1.0, -1.0, 0.0), point3(0.0, 1.0, 0.0));
+        let triangle = Triangle::new(vert, (leaning, leaning, leaning), (uv, uv, uv), texture);
+        let ray = Ray::new(point3(-1.0, 0.0, 0.2), vec3(1.0, 0.0, -0.2), 500.0, 0.0);
+        let rec = triangle.hit(ray, 0.0, 10.0).unwrap();
+        assert!(ray.direction.dot(rec.normal) > 0.0);
+        assert_eq!(shading_frame(&rec)[0], rec.normal);
+    }
+
+    #[test]
+    fn test_occlusion_in_a_corner() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let floor = axis_aligned_cuboid(point3(-2.0, -1.0, -2.0), point3(2.0, 0.0, 2.0), texture.clone());
+        let wall = axis_aligned_cuboid(point3(-2.0, 0.0, 0.0), point3(2.0, 2.0, 1.0), texture);
+        let scene: Arc<dyn Hitable> = Arc::new(BVH::initialize(vec![floor, wall]));
+        let occlusion = Node::Occlusion { geometry: ProbeGeometry::new(scene.clone()), distance: 1.0, samples: 256 };
+        let ray = Ray::new(point3(0.0, 1.0, -0.05), vec3(0.0, -1.0, 0.0), 500.0, 0.0);
+        let rec = scene.hit(ray, 0.001, 10.0).unwrap();
+        let open = occlusion.eval(&ray, &rec);
+        assert!(open > 0.3 && open < 0.8, "{}", open);
+    }
+
+    #[test]
+    fn test_noise_range() {
+        for i in 0..1000 {
+            let p = vec3(i as f32*0.37, i as f32*0.11, -(i as f32)*0.23);
+            let n = fractal_noise(p, 4);
+            assert!(n >= 0.0 && n <= 1.0, "{}", n);
+        }
+    }
+}
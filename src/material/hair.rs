@@ -0,0 +1,138 @@
+//! Fibers like hair and fur, after the model of Marschner et al., simplified.
+
+use euclid::*;
+use std::f32::consts::PI;
+
+use color::HasReflectance;
+use hitable::HitRecord;
+use material::*;
+
+/// Refractive index of keratin.
+const HAIR_IOR: f32 = 1.55;
+
+/// A fiber reflecting light off its surface (R), transmitting it straight through (TT), and reflecting it once inside (TRT).
+/// Each lobe leaves on the cone around the fiber mirroring the incoming ray, shifted along the fiber by the tilt of
+/// the cuticle scales and blurred by the roughness. The TRT lobe also carries the light of all longer paths.
+///
+/// Around the cone the lobes go where the ray leaves a smooth fiber it entered `h` off the middle, `h` in [-1,1]
+/// taken from the `v` texture coordinate across a curve. The fibers run along the tangent of the hit record,
+/// which curves give, and across the texture coordinates elsewhere.
+#[derive(Debug, Clone)]
+pub struct Hair<C: HasReflectance> {
+    color: C,
+    roughness: f32,
+    tilt: f32,
+}
+
+impl<C: HasReflectance> Hair<C> {
+    /// `color` is the fraction of light remaining after crossing the fiber once through its middle.
+    /// `roughness` is the width of the R lobe in radians, `tilt` the angle of the scales, around 0.05 for human hair.
+    pub fn new(color: C, roughness: f32, tilt: f32) -> Hair<C> {
+        Hair { color, roughness, tilt }
+    }
+}
+
+/// A pair of draws from the standard normal distribution.
+fn gaussian(u: Vector2D<f32, UnknownUnit>) -> (f32, f32) {
+    let r = f32::sqrt(-2.0*(1.0 - u.x).ln());
+    let angle = 2.0*PI*u.y;
+    (r*angle.cos(), r*angle.sin())
+}
+
+impl<C: HasReflectance> Material for Hair<C> {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        let incoming = -r_in.direction.normalize();
+        let normal = rec.facing_normal();
+        let tangent = rec.tangent.unwrap_or_else(|| if normal.x.abs() < 0.5 {
+            vec3(0.0, -normal.z, normal.y).normalize()
+        } else {
+            vec3(-normal.z, 0.0, normal.x).normalize()
+        });
+        let sin_theta_i = incoming.dot(tangent).max(-1.0).min(1.0);
+        let cos_theta_i = f32::sqrt(1.0 - sin_theta_i*sin_theta_i).max(1e-4);
+        // Around the fiber, 0 towards where the ray came from
+        let across = incoming - tangent*sin_theta_i;
+        let toward = if across.square_length() > 0.0 { across.normalize() } else { normal };
+        let side = tangent.cross(toward);
+
+        let h = (rec.uv.y*2.0 - 1.0).max(-1.0).min(1.0);
+        let gamma_i = h.asin();
+        // Refracting in the plane across the fiber, with the index of Bravais
+        let eta = f32::sqrt(HAIR_IOR*HAIR_IOR - sin_theta_i*sin_theta_i)/cos_theta_i;
+        let gamma_t = (h/eta).asin();
+        let sin_theta_t = sin_theta_i/HAIR_IOR;
+        let cos_theta_t = f32::sqrt(1.0 - sin_theta_t*sin_theta_t);
+        let fresnel = schlick(cos_theta_i*gamma_i.cos(), HAIR_IOR);
+        let transmittance = self.color.reflect(r_in.wl).max(0.0).min(1.0).powf(gamma_t.cos()/cos_theta_t);
+        let inside = (1.0 - fresnel)*(1.0 - fresnel)*transmittance;
+        let lobes = [fresnel, inside, inside*fresnel*transmittance/(1.0 - fresnel*transmittance)];
+        let total = lobes[0] + lobes[1] + lobes[2];
+        if !(total > 0.0) {
+            return ScatterResult { emittance: 0.0, reflection: None };
+        }
+        let pick = sample_1d()*total;
+        let p = if pick < lobes[0] { 0 } else if pick < lobes[0] + lobes[1] { 1 } else { 2 };
+        let (shift, width) = [
+            (-2.0*self.tilt, self.roughness),
+            (self.tilt, self.roughness*0.5),
+            (3.0*self.tilt, self.roughness*2.0),
+        ][p];
+
+        let (longitudinal, azimuthal) = gaussian(sample_2d());
+        let theta_o = (-sin_theta_i.asin() + shift + width*longitudinal).max(-PI*0.5).min(PI*0.5);
+        let p = p as f32;
+        let phi = 2.0*gamma_i - 2.0*p*gamma_t + p*PI + width*azimuthal;
+        let direction = tangent*theta_o.sin() + (toward*phi.cos() + side*phi.sin())*theta_o.cos();
+        // Each lobe is drawn as often as it carries light, so the path keeps all of it
+        ScatterResult { emittance: 0.0, reflection: Some((total, rec.spawn_ray(r_in, direction))) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use texture::Texture;
+
+    fn hit(texture: &dyn Texture, v: f32) -> HitRecord {
+        HitRecord {
+            t: 1.0,
+            p: point3(0.0, 0.0, 0.0),
+            uv: vec2(0.5, v),
+            normal: vec3(0.0, 0.0, 1.0),
+            geometric_normal: vec3(0.0, 0.0, 1.0),
+            front_face: true,
+            texture,
+            shading_rate: None,
+            object_id: None,
+            tangent: Some(vec3(0.0, 1.0, 0.0)),
+            edge_distance: None,
+            error: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_smooth_fibers_scatter_on_the_cone() {
+        let hair = Hair::new(Rgb::with_wp(0.8, 0.5, 0.3), 0.0, 0.0);
+        let r_in = Ray::new(point3(0.0, 1.0, 2.0), vec3(0.0, -1.0, -2.0), 600.0, 0.0);
+        let along = r_in.direction.normalize().y;
+        for &v in [0.1, 0.5, 0.8].iter() {
+            for _ in 0..100 {
+                let (weight, ray) = hair.scatter(r_in, hit(&hair, v)).reflection.unwrap();
+                assert!(weight > 0.0 && weight <= 1.0, "{}", weight);
+                assert!((ray.direction.length() - 1.0).abs() < 1e-4);
+                assert!((ray.direction.y - along).abs() < 1e-4, "{:?}", ray.direction);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hit_in_the_middle_goes_back_or_through() {
+        let hair = Hair::new(Rgb::with_wp(0.8, 0.5, 0.3), 0.0, 0.0);
+        let r_in = Ray::new(point3(0.0, 0.0, 2.0), vec3(0.0, 0.0, -1.0), 600.0, 0.0);
+        for _ in 0..100 {
+            let (_, ray) = hair.scatter(r_in, hit(&hair, 0.5)).reflection.unwrap();
+            assert!(ray.direction.cross(r_in.direction).length() < 1e-4, "{:?}", ray.direction);
+        }
+    }
+}
@@ -3,21 +3,41 @@ use material::*;
 use color::HasReflectance;
 use ray::Ray;
 use hitable::*;
+use texture::TextureChannel;
 
+/// A surface emitting the same light in every direction, from both of its sides unless it is made `one_sided`.
 #[derive(Debug, Clone)]
 pub struct DiffuseLight<C: HasReflectance> {
     light: C,
+    strength: TextureChannel,
+    two_sided: bool,
 }
 
 impl<C: HasReflectance> DiffuseLight<C> {
     pub fn new(light: C) -> Self {
-        DiffuseLight { light }
+        DiffuseLight { light, strength: TextureChannel::Constant(1.0), two_sided: true }
+    }
+
+    /// Scale the light by `strength` looked up at the texture coordinates of the hit, like an emission map.
+    pub fn with_strength(self, strength: TextureChannel) -> Self {
+        DiffuseLight { strength, ..self }
+    }
+
+    /// Only emit from the front, the side the geometric normal points to, like a lamp in a ceiling.
+    /// Photon mapping and light tracing send light from the front of every light, so one-sided lights look the same
+    /// with every integrator, and lights hit from behind are as dark as they are unlit.
+    pub fn one_sided(self) -> Self {
+        DiffuseLight { two_sided: false, ..self }
     }
 }
 
 impl<C: HasReflectance> Material for DiffuseLight<C> {
-    fn scatter(&self, r_in: Ray, _hit_record: HitRecord) -> ScatterResult {
-        let emittance = self.light.reflect(r_in.wl);
+    fn scatter(&self, r_in: Ray, hit_record: HitRecord) -> ScatterResult {
+        let emittance = if self.two_sided || r_in.direction.dot(hit_record.geometric_normal) < 0.0 {
+            self.light.reflect(r_in.wl)*self.strength.value(hit_record.uv, r_in.wl)
+        } else {
+            0.0
+        };
         ScatterResult {
             emittance,
             reflection: None,
@@ -25,3 +45,46 @@ impl<C: HasReflectance> Material for DiffuseLight<C> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use palette::Rgb;
+    use hitable::sphere::Sphere;
+    use hitable::triangle::uniform_polygon;
+    use image::{GrayImage, Luma};
+    use texture::Texture;
+
+    #[test]
+    fn test_one_sided() {
+        let emittance = |light: DiffuseLight<Rgb<_, f32>>, from: f32| {
+            let panel = uniform_polygon(
+                &[point3(-1.0, 0.0, -1.0), point3(1.0, 0.0, -1.0), point3(1.0, 0.0, 1.0), point3(-1.0, 0.0, 1.0)],
+                vec3(0.0, -1.0, 0.0),
+                Arc::new(light.clone()),
+            );
+            let ray = Ray::new(point3(0.0, from, 0.0), vec3(0.0, -from, 0.0), 550.0, 0.0);
+            let rec = panel.iter().filter_map(|triangle| triangle.hit(ray, 0.0, 10.0)).next().unwrap();
+            light.scatter(ray, rec).emittance
+        };
+        let light = DiffuseLight::new(Rgb::with_wp(2.0, 2.0, 2.0));
+        assert!(emittance(light.clone(), -1.0) > 1.9);
+        assert!(emittance(light.clone(), 1.0) > 1.9);
+        // Facing down, like a lamp in the ceiling
+        assert!(emittance(light.clone().one_sided(), -1.0) > 1.9);
+        assert_eq!(emittance(light.one_sided(), 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_strength_map() {
+        // Dark on the left, twice as bright on the right
+        let map = GrayImage::from_fn(2, 1, |x, _| Luma([if x == 0 { 0 } else { 255 }]));
+        let light = DiffuseLight::new(Rgb::with_wp(2.0, 2.0, 2.0)).with_strength(TextureChannel::Map { image: Arc::new(map), low: 0.0, high: 2.0 });
+        let texture: Arc<dyn Texture> = Arc::new(light.clone());
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture);
+        let ray = Ray::new(point3(0.0, 0.0, -2.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+        let rec = sphere.hit(ray, 0.0, 10.0).unwrap();
+        assert_eq!(light.scatter(ray, HitRecord { uv: vec2(0.1, 0.5), ..rec }).emittance, 0.0);
+        assert!((light.scatter(ray, HitRecord { uv: vec2(0.9, 0.5), ..rec }).emittance - 4.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,99 @@
+//! Blending two materials, evenly or by a map of where each shows.
+
+use std::sync::Arc;
+
+use hitable::HitRecord;
+use material::*;
+use texture::{Texture, TextureChannel};
+
+/// Scatters like `b` with the probability `factor` gives at the hit, and like `a` otherwise, so on average the
+/// surface reflects a blend of the two. The materials can be any materials or textures, and the factor a constant or
+/// a map, like one of the rust on a painted metal.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # use std::sync::Arc;
+/// # use palette::Rgb;
+/// # use rayer::material::{Lambertian, Metal};
+/// # use rayer::material::mix::MixMaterial;
+/// # use rayer::texture::TextureChannel;
+/// let paint = Arc::new(Lambertian::new(Rgb::with_wp(0.1, 0.3, 0.6)));
+/// let steel = Arc::new(Metal::new(Rgb::with_wp(0.6, 0.6, 0.6), 0.2));
+/// let scratched = MixMaterial::new(paint, steel, TextureChannel::Constant(0.1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MixMaterial {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+    factor: TextureChannel,
+}
+
+impl MixMaterial {
+    /// `factor` is how much of `b` there is, from 0 for all `a` to 1 for all `b`.
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, factor: TextureChannel) -> MixMaterial {
+        MixMaterial { a, b, factor }
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        // Choosing with the blend weight as probability keeps the estimate unbiased.
+        let chosen = if sample_1d() < self.factor.value(rec.uv, r_in.wl) { &self.b } else { &self.a };
+        chosen.value(rec.uv).scatter(r_in, rec)
+    }
+
+    /// A mix of diffuse materials is diffuse. Textures are judged by the material they have at the origin of the
+    /// texture coordinates, since this can't look at a hit.
+    fn is_diffuse(&self) -> bool {
+        let origin = vec2(0.0, 0.0);
+        self.a.value(origin).is_diffuse() && self.b.value(origin).is_diffuse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+    use palette::Rgb;
+    use fixtures::{head_on, hit_unit_sphere, mean_attenuation};
+
+    /// The mean attenuation of rays hitting `material` head on at the texture coordinates `u`, 0.5.
+    fn attenuation(material: MixMaterial, u: f32) -> f32 {
+        let ray = head_on(500.0);
+        hit_unit_sphere(&material, ray, |rec| mean_attenuation(&material, ray, HitRecord { uv: vec2(u, 0.5), ..rec }, 10_000))
+    }
+
+    fn black_and_white(factor: TextureChannel) -> MixMaterial {
+        let black = Arc::new(Lambertian::new(Rgb::with_wp(0.0, 0.0, 0.0)));
+        let white = Arc::new(Lambertian::new(Rgb::with_wp(1.0, 1.0, 1.0)));
+        MixMaterial::new(black, white, factor)
+    }
+
+    #[test]
+    fn test_constant_factor() {
+        let mean = attenuation(black_and_white(TextureChannel::Constant(0.25)), 0.5);
+        assert!((mean - 0.25).abs() < 0.02, "{}", mean);
+        assert!(black_and_white(TextureChannel::Constant(0.25)).is_diffuse());
+    }
+
+    #[test]
+    fn test_diffuse_only_if_both_are() {
+        let paint: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.1, 0.3, 0.6)));
+        let steel: Arc<dyn Texture> = Arc::new(Metal::new(Rgb::with_wp(0.6, 0.6, 0.6), 0.2));
+        for &factor in &[0.0, 0.5, 1.0] {
+            assert!(!MixMaterial::new(paint.clone(), steel.clone(), TextureChannel::Constant(factor)).is_diffuse());
+            assert!(!MixMaterial::new(steel.clone(), paint.clone(), TextureChannel::Constant(factor)).is_diffuse());
+        }
+        assert!(MixMaterial::new(paint.clone(), paint, TextureChannel::Constant(0.5)).is_diffuse());
+    }
+
+    #[test]
+    fn test_factor_map() {
+        // White on the right half only
+        let image = Arc::new(GrayImage::from_fn(2, 1, |x, _| Luma([if x == 0 { 0 } else { 255 }])));
+        let mix = black_and_white(TextureChannel::Map { image, low: 0.0, high: 1.0 });
+        assert_eq!(attenuation(mix.clone(), 0.25), 0.0);
+        assert!(attenuation(mix, 0.75) > 0.99);
+    }
+}
@@ -1,12 +1,27 @@
 use std::fmt::Debug;
 use euclid::*;
+use palette::Rgb;
+use palette::white_point::E;
 
+pub mod glass;
+pub mod graph;
 pub mod light;
+pub mod thin_film;
+pub mod presets;
+pub mod baked;
+pub mod murky;
+pub mod hair;
+pub mod pbr;
+pub mod coated;
+pub mod mix;
 
-use color::HasReflectance;
+use color::{HasReflectance, ColorSpectrum};
 use ray::Ray;
 use hitable::*;
 use random::*;
+use sampler::{sample_ball, sample_disk};
+use std::sync::Arc;
+use texture::TextureChannel;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct ScatterResult {
@@ -16,6 +31,12 @@ pub struct ScatterResult {
 
 pub trait Material: Debug + Send + Sync {
     fn scatter(&self, r_in: Ray, hit_record: HitRecord) -> ScatterResult;
+
+    /// Whether light leaves the surface evenly in all directions, as from `Lambertian` with the attenuation `scatter` reports as albedo.
+    /// Photon mapping gathers light on diffuse surfaces and follows paths through all others.
+    fn is_diffuse(&self) -> bool {
+        false
+    }
 }
 
 impl<'a, 'b> PartialEq<dyn Material+'b> for dyn Material+'a {
@@ -37,56 +58,105 @@ impl<C: HasReflectance> Lambertian<C> {
 
 impl<C: HasReflectance> Material for Lambertian<C> {
     fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
-        let u = if rec.normal.x.abs()<0.5 {
-            vec3(0.0,-rec.normal.z, rec.normal.y).normalize()
+        let normal = rec.facing_normal();
+        let u = if normal.x.abs()<0.5 {
+            vec3(0.0,-normal.z, normal.y).normalize()
         } else {
-            vec3(-rec.normal.z, 0.0, rec.normal.x).normalize()
+            vec3(-normal.z, 0.0, normal.x).normalize()
         };
-        let w = rec.normal.cross(u);
-        let p: Vector2D<f32, UnknownUnit> = rand_in_unit_disk();
+        let w = normal.cross(u);
+        let p = sample_disk(sample_2d());
         let z = f32::sqrt(1.0-p.square_length());
-        let direction = u*p.x + w*p.y + rec.normal*z;
+        let direction = u*p.x + w*p.y + normal*z;
+        // Around a shading normal leaning away from the surface some directions point into it, which are mirrored
+        // back out of it
+        let geometric = rec.facing_geometric_normal();
+        let direction = if rec.reflects(direction) { direction } else { direction - geometric*(2.0*direction.dot(geometric)/geometric.square_length()) };
 
-        let ray = Ray::new(rec.p, direction, r_in.wl, r_in.ti);
+        let ray = rec.spawn_ray(r_in, direction);
         let attenuation = self.albedo.reflect(r_in.wl);
         ScatterResult{ emittance: 0.0, reflection: Some((attenuation, ray))}
     }
+
+    fn is_diffuse(&self) -> bool {
+        true
+    }
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+/// Scatters light evenly in all directions, for the particles of a `hitable::medium::Medium`.
+/// It isn't diffuse, since light leaves it into the whole sphere and not only off a surface.
+#[derive(Debug, Clone)]
+pub struct Isotropic<C: HasReflectance> {
+    albedo: C
+}
+
+impl<C: HasReflectance> Isotropic<C> {
+    pub fn new(albedo: C) -> Self {
+        Isotropic { albedo }
+    }
+}
+
+impl<C: HasReflectance> Material for Isotropic<C> {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        let u = sample_2d();
+        let z = 1.0 - 2.0*u.x;
+        let r = f32::sqrt(f32::max(0.0, 1.0 - z*z));
+        let phi = 2.0*::std::f32::consts::PI*u.y;
+        let direction = vec3(r*phi.cos(), r*phi.sin(), z);
+        // Not on a surface, so there is nothing to leave
+        let ray = r_in.scattered(rec.p, direction);
+        ScatterResult{ emittance: 0.0, reflection: Some((self.albedo.reflect(r_in.wl), ray))}
+    }
+}
+
+/// A mirror blurred by its fuzz, from 0 for a sharp reflection to 1. The fuzz can be a number or a map, looked up at
+/// the texture coordinates of the hit.
+#[derive(Debug, Clone)]
 pub struct Metal<R: HasReflectance> {
     albedo: R,
-    fuzz: f32,
+    fuzz: TextureChannel,
 }
 
 impl<R: HasReflectance> Metal<R> {
-    pub fn new(albedo: R, fuzz: f32) -> Self {
-        let fuzz = if fuzz<0.0 {
-            0.0
-        } else if fuzz>1.0 {
-            1.0
-        } else {
-            fuzz
-        };
-        Metal { albedo, fuzz }
+    pub fn new<F: Into<TextureChannel>>(albedo: R, fuzz: F) -> Self {
+        Metal { albedo, fuzz: fuzz.into() }
     }
 }
 
 impl<R: HasReflectance> Material for Metal<R> {
     fn scatter(&self, r_in: Ray, hit_record: HitRecord) -> ScatterResult {
         let reflected = reflect(r_in.direction, hit_record.normal);
-        let scattered =  reflected + rand_in_unit_sphere()*self.fuzz;
-        let ray = Ray::new(hit_record.p, scattered, r_in.wl, r_in.ti);
+        let fuzz = self.fuzz.value(hit_record.uv, r_in.wl).clamp(0.0, 1.0);
+        let scattered =  reflected + sample_ball(sample_2d(), sample_1d())*fuzz;
+        // The fuzz or a leaning shading normal can send the reflection into the surface, which absorbs it
+        if !hit_record.reflects(scattered) {
+            return ScatterResult { emittance: 0.0, reflection: None };
+        }
+        let ray = hit_record.spawn_ray(r_in, scattered);
         let attenuation = self.albedo.reflect(r_in.wl);
         ScatterResult{ emittance: 0.0, reflection: Some((attenuation, ray))}
     }
 }
 
+/// The same reflectance for every wavelength, for materials computing theirs per hit.
+#[derive(Debug, Clone, Copy)]
+struct Flat(f32);
+
+impl HasReflectance for Flat {
+    fn reflect(&self, _wl: f32) -> f32 {
+        self.0
+    }
+
+    fn reflect_rgb(&self) -> Rgb<E, f32> {
+        Rgb::with_wp(self.0, self.0, self.0)
+    }
+}
+
 fn reflect(v: Vector3D<f32, UnknownUnit>, n: Vector3D<f32, UnknownUnit>) -> Vector3D<f32, UnknownUnit> {
     v - n*v.dot(n)*2.0
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Dielectric {
     b1: f32,
     b2: f32,
@@ -94,9 +164,64 @@ pub struct Dielectric {
     c1: f32,
     c2: f32,
     c3: f32,
+    absorption: Option<ColorSpectrum>,
+    ior: Option<Arc<TextureChannel>>,
+}
+
+/// Glasses are the same if their dispersion and absorption are, and they have no index of refraction map or the same one.
+impl PartialEq for Dielectric {
+    fn eq(&self, other: &Dielectric) -> bool {
+        let ior = match (&self.ior, &other.ior) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+        ior && (self.b1, self.b2, self.b3, self.c1, self.c2, self.c3, self.absorption)
+            == (other.b1, other.b2, other.b3, other.c1, other.c2, other.c3, other.absorption)
+    }
 }
 
 impl Dielectric {
+    /// Construct a glass from its Sellmeier coefficients.
+    /// The `c` coefficients are given in nm².
+    pub const fn new(b1: f32, b2: f32, b3: f32, c1: f32, c2: f32, c3: f32) -> Dielectric {
+        Dielectric { b1, b2, b3, c1, c2, c3, absorption: None, ior: None }
+    }
+
+    /// Refract by the index of refraction `ior` looked up at the texture coordinates of the hit, instead of by the
+    /// Sellmeier coefficients, like a varnish of uneven thickness.
+    pub fn with_ior(self, ior: TextureChannel) -> Dielectric {
+        Dielectric { ior: Some(Arc::new(ior)), ..self }
+    }
+
+    /// The absorption coefficient per unit of distance for a wavelength in nm.
+    pub fn absorption(&self, wl: f32) -> f32 {
+        self.absorption.map_or(0.0, |absorption| absorption.reflect(wl))
+    }
+
+    /// Construct a glass from Sellmeier coefficients as listed in glass catalogs, with `c` in µm².
+    pub const fn sellmeier(b: [f32; 3], c: [f32; 3]) -> Dielectric {
+        Dielectric::new(b[0], b[1], b[2], c[0]*1e6, c[1]*1e6, c[2]*1e6)
+    }
+
+    /// The refractive index for a wavelength in nm.
+    pub fn refractive_index(&self, wl: f32) -> f32 {
+        let wl_2 = wl*wl;
+        let ref_idx_squared =
+            1.0 +
+            self.b1*wl_2/(wl_2-self.c1) +
+            self.b2*wl_2/(wl_2-self.c2) +
+            self.b3*wl_2/(wl_2-self.c3);
+        ref_idx_squared.sqrt()
+    }
+
+    /// Tint the glass using Beer–Lambert absorption.
+    /// `transmittance` is the color, or measured spectrum, that remains after light travelled `distance` through the material.
+    pub fn with_absorption<C: HasReflectance>(self, transmittance: C, distance: f32) -> Dielectric {
+        let absorption = ColorSpectrum::from_fn(|wl| -transmittance.reflect(wl).max(1e-4).min(1.0).ln() / distance);
+        Dielectric { absorption: Some(absorption), ..self }
+    }
+
     #[allow(dead_code)]
     pub const BAF10: Dielectric =
         Dielectric{
@@ -106,6 +231,8 @@ impl Dielectric {
             c1: 0.00926681282*1e6,
             c2: 0.0424489805*1e6,
             c3: 105.613573*1e6,
+            absorption: None,
+            ior: None,
         };
 
     #[allow(dead_code)]
@@ -117,6 +244,8 @@ impl Dielectric {
             c1: 0.013188707*1e6,
             c2: 0.0623068142*1e6,
             c3: 155.23629*1e6,
+            absorption: None,
+            ior: None,
         };
 
     #[allow(dead_code)]
@@ -128,10 +257,39 @@ impl Dielectric {
             c1: 0.0147053225*1e6,
             c2: 0.0692998276*1e6,
             c3: 161.817601*1e6,
+            absorption: None,
+            ior: None,
+        };
+
+    #[allow(dead_code)]
+    pub const BK7: Dielectric =
+        Dielectric{
+            b1: 1.03961212,
+            b2: 0.231792344,
+            b3: 1.01046945,
+            c1: 0.00600069867*1e6,
+            c2: 0.0200179144*1e6,
+            c3: 103.560653*1e6,
+            absorption: None,
+            ior: None,
+        };
+
+    /// Crystalline quartz (ordinary ray), the base of amethyst and citrine.
+    #[allow(dead_code)]
+    pub const QUARTZ: Dielectric =
+        Dielectric{
+            b1: 0.663044,
+            b2: 0.517852,
+            b3: 0.175912,
+            c1: 0.0036*1e6,
+            c2: 0.011236*1e6,
+            c3: 0.014161*1e6,
+            absorption: None,
+            ior: None,
         };
 }
 
-fn refract(v: Vector3D<f32, UnknownUnit>, n: Vector3D<f32, UnknownUnit>, ni_over_nt: f32) -> Option<Vector3D<f32, UnknownUnit>> {
+pub(crate) fn refract(v: Vector3D<f32, UnknownUnit>, n: Vector3D<f32, UnknownUnit>, ni_over_nt: f32) -> Option<Vector3D<f32, UnknownUnit>> {
     let uv = v.normalize();
     let dt = uv.dot(n);
     let discriminant = 1.0 - ni_over_nt*ni_over_nt*(1.0-dt*dt);
@@ -151,37 +309,35 @@ fn schlick(cosine: f32, ref_idx: f32) -> f32 {
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
-        let wl_2 = r_in.wl*r_in.wl;
-        let ref_idx_squared =
-            1.0 +
-            self.b1*wl_2/(wl_2-self.c1) +
-            self.b2*wl_2/(wl_2-self.c2) +
-            self.b3*wl_2/(wl_2-self.c3);
-        let ref_idx = ref_idx_squared.sqrt();
-        let (outward_normal, ni_over_nt, cosine) =
-            if r_in.direction.dot(rec.normal) > 0.0 {
-                (-rec.normal,
-                 ref_idx,
-                 ref_idx * r_in.direction.dot(rec.normal) / r_in.direction.length()
-                )
+        let ref_idx = match self.ior {
+            Some(ref ior) => ior.value(rec.uv, r_in.wl),
+            None => self.refractive_index(r_in.wl),
+        };
+        let facing_normal = rec.facing_normal();
+        let cosine = -r_in.direction.dot(facing_normal) / r_in.direction.length();
+        let (ni_over_nt, cosine) =
+            if rec.front_face {
+                (ref_idx.recip(), cosine)
             } else {
-                (rec.normal,
-                 ref_idx.recip(),
-                 -r_in.direction.dot(rec.normal) / r_in.direction.length()
-                )
+                (ref_idx, ref_idx * cosine)
             };
-        let refracted = refract(r_in.direction, outward_normal, ni_over_nt);
+        // Around a shading normal leaning away from the surface, reflections can point into it and refractions out
+        // of it, letting light through to the wrong side. Those go around the geometric normal instead.
+        let reflected = reflect(r_in.direction, rec.normal);
+        let reflected = if rec.reflects(reflected) { reflected } else { reflect(r_in.direction, rec.geometric_normal) };
+        let refracted = match refract(r_in.direction, facing_normal, ni_over_nt) {
+            Some(refracted) if rec.reflects(refracted) => refract(r_in.direction, rec.facing_geometric_normal(), ni_over_nt),
+            refracted => refracted,
+        };
         let scattered = match refracted {
-            None => {
-                let reflected = reflect(r_in.direction, rec.normal);
-                Ray::new(rec.p, reflected, r_in.wl, r_in.ti)
-            },
+            None => rec.spawn_ray(r_in, reflected),
             Some(refracted) => {
-                if next_f32() < schlick(cosine, ref_idx) {
-                    let reflected = reflect(r_in.direction, rec.normal);
-                    Ray::new(rec.p, reflected, r_in.wl, r_in.ti)
+                if sample_1d() < schlick(cosine, ref_idx) {
+                    rec.spawn_ray(r_in, reflected)
                 } else {
-                    Ray::new(rec.p, refracted, r_in.wl, r_in.ti)
+                    // The integrator applies the absorption over the distance to the next hit.
+                    let absorption = if rec.front_face { self.absorption(r_in.wl) } else { 0.0 };
+                    rec.spawn_ray(r_in, refracted).with_absorption(absorption)
                 }
             }
         };
@@ -189,3 +345,75 @@ impl Material for Dielectric {
 
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use hitable::sphere::Sphere;
+
+    #[test]
+    fn test_scattering_stays_above_the_surface() {
+        // A bump leaning the shading normal far over, seen at a grazing angle
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5))));
+        let r = Ray::new(point3(-2.0, 1.0, 0.0), vec3(1.0, -0.2, 0.0), 500.0, 0.0);
+        let rec = sphere.hit(r, 0.001, 10.0).unwrap();
+        let rec = HitRecord { normal: (rec.normal + vec3(0.5, 0.0, 0.0)).normalize(), ..rec };
+        let lambertian = Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5));
+        let metal = Metal::new(Rgb::with_wp(0.5, 0.5, 0.5), 0.3);
+        let mut reflected = 0;
+        for _ in 0..1000 {
+            let (_, ray) = lambertian.scatter(r, rec).reflection.unwrap();
+            assert!(ray.direction.dot(rec.geometric_normal) > 0.0, "{:?}", ray.direction);
+            if let Some((_, ray)) = metal.scatter(r, rec).reflection {
+                assert!(ray.direction.dot(rec.geometric_normal) > 0.0, "{:?}", ray.direction);
+                reflected += 1;
+            }
+        }
+        // The mirror direction itself points just into the surface
+        assert!(reflected > 0 && reflected < 1000, "{}", reflected);
+    }
+
+    #[test]
+    fn test_parameter_maps() {
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5))));
+        let r = Ray::new(point3(0.0, 0.0, -2.0), vec3(0.0, 0.0, 1.0), 500.0, 0.0);
+        let rec = sphere.hit(r, 0.001, 10.0).unwrap();
+        let (left, right) = (HitRecord { uv: vec2(0.1, 0.5), ..rec }, HitRecord { uv: vec2(0.9, 0.5), ..rec });
+        // A mirror on the left, fully fuzzy on the right
+        let map = image::GrayImage::from_fn(2, 1, |x, _| image::Luma([if x == 0 { 0 } else { 255 }]));
+        let metal = Metal::new(Rgb::with_wp(0.9, 0.9, 0.9), TextureChannel::Map { image: Arc::new(map), low: 0.0, high: 1.0 });
+        let direction = |rec: HitRecord| metal.scatter(r, rec).reflection.map(|(_, ray)| ray.direction.normalize());
+        for _ in 0..100 {
+            assert!((direction(left).unwrap() - vec3(0.0, 0.0, -1.0)).length() < 1e-4);
+        }
+        assert!((0..100).filter_map(|_| direction(right)).any(|direction| direction.z > -0.99));
+        // Glass with the index of refraction of air lets light straight through
+        let air = Dielectric::BK7.with_ior(TextureChannel::Constant(1.0));
+        for _ in 0..100 {
+            let (_, ray) = air.scatter(r, rec).reflection.unwrap();
+            assert!((ray.direction.normalize() - vec3(0.0, 0.0, 1.0)).length() < 1e-4, "{:?}", ray.direction);
+        }
+        assert_ne!(air, Dielectric::BK7);
+        assert_eq!(air.clone(), air);
+    }
+
+    #[test]
+    fn test_isotropic_scatters_everywhere() {
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5))));
+        let r = Ray::new(point3(-2.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0);
+        let rec = sphere.hit(r, 0.001, 10.0).unwrap();
+        let isotropic = Isotropic::new(Rgb::with_wp(0.5, 0.5, 0.5));
+        let mut sum = vec3(0.0, 0.0, 0.0);
+        let mut backwards = 0;
+        for _ in 0..4000 {
+            let (attenuation, ray) = isotropic.scatter(r, rec).reflection.unwrap();
+            assert!((attenuation - 0.5).abs() < 1e-3 && (ray.direction.length() - 1.0).abs() < 1e-4);
+            sum += ray.direction;
+            backwards += (ray.direction.x < 0.0) as u32;
+        }
+        assert!(sum.length()/4000.0 < 0.05, "{:?}", sum);
+        assert!(backwards > 1800 && backwards < 2200, "{}", backwards);
+        assert!(!isotropic.is_diffuse());
+    }
+}
@@ -0,0 +1,107 @@
+//! Glass whose absorption varies through its volume, like smoky quartz or murky water.
+
+use euclid::*;
+use std::sync::Arc;
+
+use hitable::HitRecord;
+use material::*;
+use texture::Density;
+
+/// A dielectric absorbing light in proportion to a density field inside it.
+///
+/// The absorption the glass was given with `Dielectric::with_absorption` applies where the density is 1.
+/// Instead of a single Beer–Lambert factor for the distance travelled, the density is ray marched along every segment
+/// a ray travels inside, in steps of at most `step`, and the transmittance is applied where the segment leaves
+/// through the surface of the object. Segments inside ending at another object, like one sunk into the glass, pass unattenuated.
+#[derive(Debug, Clone)]
+pub struct MurkyDielectric {
+    glass: Dielectric,
+    density: Arc<dyn Density>,
+    step: f32,
+}
+
+impl MurkyDielectric {
+    pub fn new(glass: Dielectric, density: Arc<dyn Density>, step: f32) -> MurkyDielectric {
+        MurkyDielectric { glass, density, step }
+    }
+
+    /// The fraction of light at wavelength `wl` that remains after going from `from` to `to` inside the object.
+    /// Jittered along the segment, so the steps don't show as bands.
+    pub fn transmittance(&self, from: Point3D<f32, UnknownUnit>, to: Point3D<f32, UnknownUnit>, wl: f32) -> f32 {
+        let absorption = self.glass.absorption(wl);
+        if absorption <= 0.0 {
+            return 1.0;
+        }
+        let length = (to - from).length();
+        let steps = (length/self.step).ceil().max(1.0);
+        let jitter = sample_1d();
+        let mut optical_depth = 0.0;
+        for i in 0..steps as u32 {
+            let p = from.lerp(to, (i as f32 + jitter)/steps);
+            optical_depth += self.density.density(p).max(0.0);
+        }
+        f32::exp(-absorption*optical_depth*length/steps)
+    }
+}
+
+impl Material for MurkyDielectric {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        let result = self.glass.scatter(r_in, rec);
+        // Hit from inside, the ray just crossed the interior from where it last scattered
+        let transmittance = if rec.front_face { 1.0 } else { self.transmittance(r_in.origin, rec.p, r_in.wl) };
+        // The absorption is marched here, not by the integrator
+        let reflection = result.reflection.map(|(attenuation, ray)| (attenuation*transmittance, ray.with_absorption(0.0)));
+        ScatterResult { reflection, ..result }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use hitable::Hitable;
+    use hitable::sphere::Sphere;
+    use texture::Texture;
+
+    fn smoky() -> Dielectric {
+        Dielectric::QUARTZ.with_absorption(Rgb::with_wp(0.5, 0.5, 0.5), 1.0)
+    }
+
+    #[test]
+    fn test_uniform_density_is_beer_lambert() {
+        let murky = MurkyDielectric::new(smoky(), Arc::new(1.0), 0.1);
+        let from = point3(0.0, 0.0, 0.0);
+        let to = point3(0.0, 2.0, 0.0);
+        let expected = f32::exp(-smoky().absorption(550.0)*2.0);
+        assert!((murky.transmittance(from, to, 550.0) - expected).abs() < 1e-4);
+        let clear = MurkyDielectric::new(Dielectric::QUARTZ, Arc::new(1.0), 0.1);
+        assert_eq!(clear.transmittance(from, to, 550.0), 1.0);
+    }
+
+    #[test]
+    fn test_density_varies() {
+        // Smoke only above y=1
+        fn upper(p: Point3D<f32, UnknownUnit>) -> f32 {
+            if p.y > 1.0 { 1.0 } else { 0.0 }
+        }
+        let murky = MurkyDielectric::new(smoky(), Arc::new(upper as fn(Point3D<f32, UnknownUnit>) -> f32), 0.01);
+        assert_eq!(murky.transmittance(point3(0.0, 0.0, 0.0), point3(1.0, 0.9, 0.0), 550.0), 1.0);
+        let half = murky.transmittance(point3(0.0, 0.0, 0.0), point3(0.0, 2.0, 0.0), 550.0);
+        let expected = f32::exp(-smoky().absorption(550.0));
+        assert!((half - expected).abs() < 0.01, "{} != {}", half, expected);
+    }
+
+    #[test]
+    fn test_leaving_the_object_applies_the_transmittance() {
+        let texture: Arc<dyn Texture> = Arc::new(MurkyDielectric::new(smoky(), Arc::new(1.0), 0.1));
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture);
+        // Head on through the center, most light leaves the back and the ray carries no absorption of its own
+        let inside = Ray::new(point3(0.0, 0.0, -1.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+        let rec = sphere.hit(inside, 1e-3, 10.0).unwrap();
+        assert!(!rec.front_face);
+        let (attenuation, ray) = rec.texture.value(rec.uv).scatter(inside, rec).reflection.unwrap();
+        let expected = f32::exp(-smoky().absorption(550.0)*2.0);
+        assert!((attenuation - expected).abs() < 1e-3, "{} != {}", attenuation, expected);
+        assert_eq!(ray.absorption, 0.0);
+    }
+}
@@ -0,0 +1,118 @@
+//! A material driven by texture maps for each of its parameters, as PBR texture sets come.
+
+use hitable::HitRecord;
+use material::*;
+use texture::TextureChannel;
+
+/// A surface blending from a dielectric to a metal by its metalness.
+///
+/// Where it is metal, light reflects tinted by the base color. Elsewhere a glossy coat reflects the fraction the
+/// Fresnel term gives for the index of refraction and the rest is scattered diffusely in the base color.
+/// The roughness blurs both reflections, from a mirror at 0 to fully fuzzy at 1. The surface also emits its emission.
+///
+/// Every parameter is a `TextureChannel`, looked up at the texture coordinates of the hit.
+#[derive(Debug, Clone)]
+pub struct PbrMaterial {
+    pub base_color: TextureChannel,
+    pub metalness: TextureChannel,
+    pub roughness: TextureChannel,
+    pub ior: TextureChannel,
+    pub emission: TextureChannel,
+}
+
+impl PbrMaterial {
+    /// A rough, non emitting dielectric in `base_color`, with the index of refraction of most plastics.
+    pub fn new(base_color: TextureChannel) -> PbrMaterial {
+        PbrMaterial {
+            base_color,
+            metalness: TextureChannel::Constant(0.0),
+            roughness: TextureChannel::Constant(0.5),
+            ior: TextureChannel::Constant(1.5),
+            emission: TextureChannel::Constant(0.0),
+        }
+    }
+
+    pub fn with_metalness(self, metalness: TextureChannel) -> PbrMaterial {
+        PbrMaterial { metalness, ..self }
+    }
+
+    pub fn with_roughness(self, roughness: TextureChannel) -> PbrMaterial {
+        PbrMaterial { roughness, ..self }
+    }
+
+    pub fn with_ior(self, ior: TextureChannel) -> PbrMaterial {
+        PbrMaterial { ior, ..self }
+    }
+
+    pub fn with_emission(self, emission: TextureChannel) -> PbrMaterial {
+        PbrMaterial { emission, ..self }
+    }
+}
+
+impl Material for PbrMaterial {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        let (uv, wl) = (rec.uv, r_in.wl);
+        let clamp = |value: f32| value.max(0.0).min(1.0);
+        let base_color = clamp(self.base_color.value(uv, wl));
+        let roughness = clamp(self.roughness.value(uv, wl));
+        let emittance = self.emission.value(uv, wl).max(0.0);
+        let result = if sample_1d() < clamp(self.metalness.value(uv, wl)) {
+            Metal::new(Flat(base_color), roughness).scatter(r_in, rec)
+        } else {
+            let cosine = -r_in.direction.dot(rec.facing_normal())/r_in.direction.length();
+            if sample_1d() < schlick(cosine, self.ior.value(uv, wl)) {
+                Metal::new(Flat(1.0), roughness).scatter(r_in, rec)
+            } else {
+                Lambertian::new(Flat(base_color)).scatter(r_in, rec)
+            }
+        };
+        ScatterResult { emittance, ..result }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+    use texture::Texture;
+    use std::sync::Arc;
+
+    fn hit(texture: &dyn Texture, uv: Vector2D<f32, UnknownUnit>) -> HitRecord {
+        HitRecord {
+            t: 1.0,
+            p: point3(0.0, 0.0, 0.0),
+            uv,
+            normal: vec3(0.0, 1.0, 0.0),
+            geometric_normal: vec3(0.0, 1.0, 0.0),
+            front_face: true,
+            texture,
+            shading_rate: None,
+            object_id: None,
+            tangent: None,
+            edge_distance: None,
+            error: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_maps_drive_the_parameters() {
+        // Polished metal on the left half, glowing on the right
+        let left = Arc::new(GrayImage::from_fn(2, 1, |x, _| Luma([if x == 0 { 255 } else { 0 }])));
+        let right = Arc::new(GrayImage::from_fn(2, 1, |x, _| Luma([if x == 0 { 0 } else { 255 }])));
+        let material = PbrMaterial::new(TextureChannel::Constant(0.8))
+            .with_metalness(TextureChannel::Map { image: left.clone(), low: 0.0, high: 1.0 })
+            .with_roughness(TextureChannel::Map { image: left, low: 1.0, high: 0.0 })
+            .with_emission(TextureChannel::Map { image: right, low: 0.0, high: 1.0 }.scaled(4.0));
+        let r_in = Ray::new(point3(-1.0, 1.0, 0.0), vec3(1.0, -1.0, 0.0), 550.0, 0.0);
+
+        let metal = material.scatter(r_in, hit(&material, vec2(0.25, 0.5)));
+        assert_eq!(metal.emittance, 0.0);
+        let (attenuation, ray) = metal.reflection.unwrap();
+        assert!((attenuation - 0.8).abs() < 1e-6);
+        assert!((ray.direction.normalize() - vec3(1.0, 1.0, 0.0).normalize()).length() < 1e-5, "{:?}", ray.direction);
+
+        let glowing = material.scatter(r_in, hit(&material, vec2(0.75, 0.5)));
+        assert_eq!(glowing.emittance, 4.0);
+        assert!(glowing.reflection.unwrap().1.direction.y > 0.0);
+    }
+}
@@ -0,0 +1,67 @@
+//! Ready made materials showing off the spectral features of the renderer.
+//! Absorption distances are given in scene units, assuming objects of roughly unit size.
+
+use palette::Rgb;
+
+use material::Dielectric;
+use material::thin_film::ThinFilm;
+
+/// Deep red wine colored glass.
+pub fn wine_glass() -> Dielectric {
+    Dielectric::BK7.with_absorption(Rgb::with_wp(0.6, 0.05, 0.1), 1.0)
+}
+
+/// Purple quartz.
+pub fn amethyst() -> Dielectric {
+    Dielectric::QUARTZ.with_absorption(Rgb::with_wp(0.6, 0.3, 0.75), 1.0)
+}
+
+/// Slightly green bottle glass.
+pub fn bottle_glass() -> Dielectric {
+    Dielectric::BK7.with_absorption(Rgb::with_wp(0.5, 0.8, 0.55), 1.0)
+}
+
+/// A soap film a few hundred nanometers thick.
+pub fn soap_bubble() -> ThinFilm {
+    ThinFilm::new(1.33, 380.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use euclid::*;
+    use std::sync::Arc;
+    use hitable::*;
+    use hitable::sphere::Sphere;
+    use material::Material;
+    use ray::Ray;
+    use texture::Texture;
+
+    fn transmittance(mat: Dielectric, wl: f32) -> f32 {
+        // A ray crossing a unit sphere along its diameter
+        let texture: Arc<dyn Texture> = Arc::new(mat.clone());
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture);
+        let ray = Ray::new(point3(-2.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), wl, 0.0);
+        // Some rays get reflected at the surface, keep trying until one enters.
+        let inside = (0..1000)
+            .filter_map(|_| mat.scatter(ray, sphere.hit(ray, 0.001, 10.0).unwrap()).reflection)
+            .map(|(_, scattered)| scattered)
+            .find(|scattered| scattered.direction.x > 0.0)
+            .unwrap();
+        let exit = sphere.hit(inside, 0.001, 10.0).unwrap();
+        inside.transmittance(exit.t)
+    }
+
+    #[test]
+    fn test_wine_absorbs_blue() {
+        let red = transmittance(wine_glass(), 650.0);
+        let blue = transmittance(wine_glass(), 450.0);
+        assert!(red > blue, "red={:}, blue={:}", red, blue);
+        assert!(red < 1.0);
+    }
+
+    #[test]
+    fn test_clear_glass_does_not_absorb() {
+        assert_eq!(transmittance(Dielectric::BK7, 500.0), 1.0);
+    }
+}
@@ -0,0 +1,80 @@
+use num_traits::FloatConst;
+
+use material::*;
+use material::reflect;
+use ray::Ray;
+use hitable::*;
+use random::*;
+
+/// A thin transparent film surrounded by air, like a soap bubble.
+/// The reflectance is computed from the interference between
+/// the front and back surface of the film, so it varies strongly with the wavelength.
+/// Transmitted rays pass through without changing direction.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct ThinFilm {
+    ior: f32,
+    thickness: f32,
+}
+
+impl ThinFilm {
+    /// `thickness` is given in nm.
+    pub fn new(ior: f32, thickness: f32) -> Self {
+        ThinFilm { ior, thickness }
+    }
+
+    /// Fraction of light reflected for a given wavelength and cosine of the incident angle.
+    pub fn reflectance(&self, wl: f32, cos_i: f32) -> f32 {
+        let cos_i = cos_i.abs().min(1.0);
+        let sin_t = f32::sqrt(1.0 - cos_i*cos_i) / self.ior;
+        let cos_t = f32::sqrt(1.0 - sin_t*sin_t);
+        let n = self.ior;
+        let r_s = (cos_i - n*cos_t) / (cos_i + n*cos_t);
+        let r_p = (n*cos_i - cos_t) / (n*cos_i + cos_t);
+        let delta = 4.0*f32::PI()*n*self.thickness*cos_t / wl;
+        // Both surfaces reflect with the same magnitude but opposite sign.
+        let airy = |r: f32| {
+            let r2 = r*r;
+            2.0*r2*(1.0 - delta.cos()) / (1.0 + r2*r2 - 2.0*r2*delta.cos())
+        };
+        0.5*(airy(r_s) + airy(r_p))
+    }
+}
+
+impl Material for ThinFilm {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        let cos_i = r_in.direction.dot(rec.normal) / r_in.direction.length();
+        let direction = if sample_1d() < self.reflectance(r_in.wl, cos_i) {
+            reflect(r_in.direction, rec.normal)
+        } else {
+            r_in.direction
+        };
+        let ray = rec.spawn_ray(r_in, direction);
+        ScatterResult { emittance: 0.0, reflection: Some((1.0, ray)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reflectance_bounds() {
+        let film = ThinFilm::new(1.33, 400.0);
+        for wl in 380..780 {
+            for &cos_i in [0.0, 0.1, 0.5, 0.9, 1.0].iter() {
+                let r = film.reflectance(wl as f32, cos_i);
+                assert!(r >= 0.0 && r <= 1.0, "wl={:}nm, cos_i={:}, r={:}", wl, cos_i, r);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reflectance_varies_with_wavelength() {
+        let film = ThinFilm::new(1.33, 400.0);
+        // Destructive interference at 2*n*d/m, constructive in between.
+        let dark = film.reflectance(2.0*1.33*400.0/2.0, 1.0);
+        let bright = film.reflectance(4.0*1.33*400.0/3.0, 1.0);
+        assert!(dark < 0.001, "dark={:}", dark);
+        assert!(bright > 0.05, "bright={:}", bright);
+    }
+}
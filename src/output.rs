@@ -0,0 +1,100 @@
+//! Writing render results with more than the final color in them.
+use exr::meta::attribute::Chromaticities;
+use exr::prelude::*;
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+
+use color::ColorSpace;
+
+/// A named group of channels with one value per pixel, in row major order.
+#[derive(Debug, Clone)]
+pub struct OutputLayer {
+    name: String,
+    channels: Vec<(String, Vec<f32>)>,
+    attributes: Vec<(String, String)>,
+}
+
+impl OutputLayer {
+    pub fn new(name: &str) -> OutputLayer {
+        OutputLayer { name: String::from(name), channels: Vec::new(), attributes: Vec::new() }
+    }
+
+    pub fn with_channel(mut self, name: &str, data: Vec<f32>) -> OutputLayer {
+        self.channels.push((String::from(name), data));
+        self
+    }
+
+    /// Add a text attribute to the header of the layer, like the metadata other tools read the channels by.
+    pub fn with_attribute(mut self, name: &str, value: &str) -> OutputLayer {
+        self.attributes.push((String::from(name), String::from(value)));
+        self
+    }
+}
+
+/// Where an image to go with the one written to `output` goes, named `name` before the extension.
+///
+/// ```
+/// # extern crate rayer;
+/// # use std::path::Path;
+/// # use rayer::output::*;
+/// assert_eq!(sibling_path(Path::new("renders/room.exr"), "specular"), Path::new("renders/room.specular.exr"));
+/// assert_eq!(sibling_path(Path::new("room"), "specular"), Path::new("room.specular"));
+/// ```
+pub fn sibling_path(output: &Path, name: &str) -> PathBuf {
+    let stem = output.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    let file_name = match output.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, name, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, name),
+    };
+    output.with_file_name(file_name)
+}
+
+/// Write the layers into a single EXR file, each layer as its own part, with the chromaticities of `color_space` in
+/// the header for tools to read the colors by.
+pub fn write_exr<W: Write + Seek>(out: W, width: u32, height: u32, color_space: ColorSpace, layers: Vec<OutputLayer>) -> Result<()> {
+    let size = (width as usize, height as usize);
+    let layers: Vec<_> = layers.into_iter()
+        .map(|layer| {
+            let channels = layer.channels.into_iter()
+                .map(|(name, data)| AnyChannel::new(name.as_str(), FlatSamples::F32(data)))
+                .collect();
+            let mut attributes = LayerAttributes::named(layer.name.as_str());
+            for (name, value) in layer.attributes {
+                attributes.other.insert(Text::from(name.as_str()), AttributeValue::Text(Text::from(value.as_str())));
+            }
+            Layer::new(size, attributes, Encoding::FAST_LOSSLESS, AnyChannels::sort(channels))
+        })
+        .collect();
+    let mut attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+    let [red, green, blue, white] = color_space.chromaticities().map(|xy| Vec2(xy.x, xy.y));
+    attributes.chromaticities = Some(Chromaticities { red, green, blue, white });
+    Image::from_layers(attributes, layers).write().to_buffered(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_exr_layers() {
+        let mut out = Cursor::new(Vec::new());
+        let layers = vec![
+            OutputLayer::new("beauty")
+                .with_channel("R", vec![1.0; 6])
+                .with_channel("G", vec![0.5; 6])
+                .with_channel("B", vec![0.0; 6]),
+            OutputLayer::new("stats")
+                .with_channel("samples", vec![4.0; 6])
+                .with_attribute("note", "four samples"),
+        ];
+        write_exr(&mut out, 3, 2, ColorSpace::AcesCg, layers).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+            .from_buffered(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(image.layer_data.len(), 2);
+        assert_eq!(image.attributes.chromaticities.unwrap().red, Vec2(0.713, 0.293));
+        assert_eq!(image.layer_data[1].channel_data.list[0].name, Text::from("samples"));
+        assert_eq!(image.layer_data[1].attributes.other.get(&Text::from("note")), Some(&AttributeValue::Text(Text::from("four samples"))));
+    }
+}
@@ -0,0 +1,41 @@
+//! The types most scenes are built from, to import in one go. The euclid and palette types in the public API come
+//! along, so a crate using rayer doesn't need to depend on the exact versions of those itself.
+//!
+//! ```
+//! # extern crate rayer;
+//! use rayer::prelude::*;
+//! use std::sync::Arc;
+//!
+//! let grey: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+//! let spheres = BVH::initialize(vec![
+//!     Sphere::new(point3(0.0, 0.0, 0.0), 1.0, grey.clone()),
+//!     Sphere::new(point3(0.0, -101.0, 0.0), 100.0, grey),
+//! ]);
+//! let camera = Camera::new(
+//!     &CameraKeyframe { look_from: point3(0.0, 0.0, 5.0), look_at: point3(0.0, 0.0, 0.0), vfov: 40.0, aperture: 0.0, focus_dist: 5.0, movements: Movements::default() },
+//!     vec3(0.0, 1.0, 0.0), 1.0, 0.0, 1.0,
+//! );
+//! let ray = camera.get_ray(0.5, 0.5, 550.0);
+//! assert!((spheres.hit(ray, 0.0, 100.0).unwrap().p - point3(0.0, 0.0, 1.0)).length() < 1e-4);
+//! ```
+
+pub use euclid::{point2, point3, vec2, vec3, Point2D, Point3D, Vector2D, Vector3D, UnknownUnit};
+pub use palette::Rgb;
+pub use palette::white_point::E;
+
+pub use camera::{Camera, CameraKeyframe, Movements};
+pub use color::HasReflectance;
+pub use hitable::{Hitable, HitRecord, AABB};
+pub use hitable::bvh::BVH;
+pub use hitable::quad::Quad;
+pub use hitable::sphere::Sphere;
+pub use hitable::triangle::{Mesh, Triangle, TriangleMesh};
+pub use hitable::wavefront::ObjMesh;
+pub use material::{Material, Lambertian, Metal, Dielectric};
+pub use material::light::DiffuseLight;
+pub use material::pbr::PbrMaterial;
+pub use ray::Ray;
+pub use scene::{Loader, Scene, SceneBuilder, SceneRegistry};
+pub use settings::RenderSettings;
+pub use sky::Sky;
+pub use texture::Texture;
@@ -1,5 +1,7 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use rand::{RngCore, Rng, SeedableRng};
 use rand_xoshiro::Xoshiro256Plus;
 use rand::distributions::{Distribution, Standard};
@@ -7,6 +9,8 @@ use rand::distributions::uniform::{SampleUniform};
 use euclid::*;
 use num_traits::Float;
 
+use sampler::Sampler;
+
 pub fn rand_in_unit_sphere<T>() -> Vector3D<T, UnknownUnit>
 where T: Float, Standard: Distribution<T>
 {
@@ -58,6 +62,96 @@ pub fn gen_range<T: PartialOrd + SampleUniform>(low: T, high: T) -> T {
     thread_rng().gen_range(low..high)
 }
 
+struct PathSamples {
+    sampler: Arc<dyn Sampler>,
+    pixel: u32,
+    index: u64,
+    dimension: u32,
+}
+
+thread_local!(
+    static PATH_SAMPLES: RefCell<Option<PathSamples>> = RefCell::new(None)
+);
+
+/// Draw the samples of the following paths traced on this thread from `sampler`.
+/// With `None`, `sample_1d` and `sample_2d` fall back to independent random numbers.
+pub fn set_path_sampler(sampler: Option<Arc<dyn Sampler>>) {
+    PATH_SAMPLES.with(|p| {
+        *p.borrow_mut() = sampler.map(|sampler| PathSamples { sampler, pixel: 0, index: 0, dimension: 0 });
+    })
+}
+
+/// Paths traced on the current thread drawing their samples from a sampler, until it is dropped.
+#[must_use = "the paths stop drawing from the sampler when it is dropped"]
+pub struct PathSampler(());
+
+/// Draw the samples of the paths traced on this thread from `sampler` until the returned guard is dropped, so the
+/// samples of the last path don't carry on into whatever the thread does next.
+pub fn path_sampler(sampler: Arc<dyn Sampler>) -> PathSampler {
+    set_path_sampler(Some(sampler));
+    PathSampler(())
+}
+
+impl Drop for PathSampler {
+    fn drop(&mut self) {
+        set_path_sampler(None);
+    }
+}
+
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+/// Draw the random numbers from `seed` from now on instead of from the system's entropy, for renders that come out
+/// the same every time. This thread's generator starts over from it, so a scene built next on this thread comes out
+/// the same too, and every path started with `start_path` gets a generator of its own from it and the pixel and sample.
+pub fn seed(seed: u64) {
+    SEED.store(seed, Ordering::SeqCst);
+    SEEDED.store(true, Ordering::SeqCst);
+    THREAD_RNG_KEY.with(|t| *t.borrow_mut() = Xoshiro256Plus::seed_from_u64(seed));
+}
+
+/// Start a new path for the given pixel and sample index.
+/// Every call to `sample_1d` or `sample_2d` uses the next dimension, starting at `first_dimension`.
+pub fn start_path(pixel: u32, index: u64, first_dimension: u32) {
+    if SEEDED.load(Ordering::Relaxed) {
+        let path = (pixel as u64).rotate_left(40) ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        THREAD_RNG_KEY.with(|t| *t.borrow_mut() = Xoshiro256Plus::seed_from_u64(SEED.load(Ordering::Relaxed) ^ path));
+    }
+    PATH_SAMPLES.with(|p| {
+        if let Some(ref mut p) = *p.borrow_mut() {
+            p.pixel = pixel;
+            p.index = index;
+            p.dimension = first_dimension;
+        }
+    })
+}
+
+/// A sample in [0,1) for the next decision along the current path.
+pub fn sample_1d() -> f32 {
+    PATH_SAMPLES.with(|p| {
+        match *p.borrow_mut() {
+            Some(ref mut p) => {
+                p.dimension += 1;
+                p.sampler.get_1d(p.pixel, p.index, p.dimension - 1)
+            },
+            None => next_f32(),
+        }
+    })
+}
+
+/// A sample in [0,1)² for the next decision along the current path.
+pub fn sample_2d() -> Vector2D<f32, UnknownUnit> {
+    PATH_SAMPLES.with(|p| {
+        match *p.borrow_mut() {
+            Some(ref mut p) => {
+                p.dimension += 1;
+                p.sampler.get_2d(p.pixel, p.index, p.dimension - 1)
+            },
+            None => vec2(next_f32(), next_f32()),
+        }
+    })
+}
+
 #[derive(Clone, Debug)]
 pub struct XorShiftThreadRng {
     rng: Rc<RefCell<Xoshiro256Plus>>,
@@ -97,6 +191,52 @@ impl RngCore for XorShiftThreadRng {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use sampler::SobolSampler;
+
+    #[test]
+    fn test_path_samples_are_reproducible() {
+        set_path_sampler(Some(Arc::new(SobolSampler)));
+        start_path(12, 3, 4);
+        let a = sample_1d();
+        let b = sample_2d();
+        start_path(12, 3, 4);
+        assert_eq!(a, sample_1d());
+        assert_eq!(b, sample_2d());
+        assert_ne!(a, sample_1d());
+        start_path(12, 4, 4);
+        assert_ne!(a, sample_1d());
+        set_path_sampler(None);
+    }
+
+    #[test]
+    fn test_path_sampler_is_cleared() {
+        {
+            let _paths = path_sampler(Arc::new(SobolSampler));
+            start_path(12, 3, 4);
+            sample_1d();
+            assert_eq!(PATH_SAMPLES.with(|p| p.borrow().as_ref().map(|p| p.dimension)), Some(5));
+        }
+        assert!(PATH_SAMPLES.with(|p| p.borrow().is_none()));
+    }
+
+    #[test]
+    fn test_seeded_paths() {
+        seed(7);
+        let scene = next_f32();
+        start_path(5, 2, 0);
+        let path = next_f32();
+        seed(7);
+        assert_eq!(scene, next_f32());
+        start_path(5, 3, 0);
+        assert_ne!(path, next_f32());
+        start_path(5, 2, 0);
+        assert_eq!(path, next_f32());
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
     use test::*;
     use rand;
     use rand_xorshift;
@@ -143,4 +283,9 @@ mod tests {
     fn bench_rand_in_unit_disk(bench: &mut Bencher) {
         bench.iter(|| black_box(super::rand_in_unit_disk() as Vector2D<f32, UnknownUnit>));
     }
+
+    #[bench]
+    fn bench_sample_disk_concentric(bench: &mut Bencher) {
+        bench.iter(|| black_box(::sampler::sample_disk_concentric(vec2(super::next_f32(), super::next_f32()))));
+    }
 }
@@ -2,6 +2,17 @@ use euclid::*;
 
 pub struct Inverted;
 
+/// What a ray is traced for, which objects can be hidden from, see `instance::with_visibility`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RayKind {
+    /// Straight from the camera, or from a surface to the camera.
+    Camera,
+    /// Towards a light, to see whether anything is in the way.
+    Shadow,
+    /// Continuing a path after a surface scattered it, or leaving a light.
+    Bounce,
+}
+
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Point3D<f32, UnknownUnit>,
@@ -10,6 +21,9 @@ pub struct Ray {
     pub inv_direction: Vector3D<f32, Inverted>,
     pub sign: Vector3D<bool, Inverted>,
     pub ti: f32,
+    /// Absorption coefficient of the medium the ray travels through, per unit of distance, at its wavelength.
+    pub absorption: f32,
+    pub kind: RayKind,
 }
 
 impl Ray
@@ -17,9 +31,32 @@ impl Ray
     pub fn new(origin: Point3D<f32, UnknownUnit>, direction: Vector3D<f32, UnknownUnit>, wl: f32, ti: f32) -> Ray {
         let inv_direction =
             vec3(direction.x.recip(), direction.y.recip(), direction.z.recip());
+        // Taken from the inverse so a negative zero, with an inverse of -inf, selects the matching slab bound
         let sign =
-            vec3(direction.x < 0.0, direction.y < 0.0, direction.z < 0.0);
-        Ray{origin, direction, wl, inv_direction, sign, ti}
+            vec3(inv_direction.x < 0.0, inv_direction.y < 0.0, inv_direction.z < 0.0);
+        Ray{origin, direction, wl, inv_direction, sign, ti, absorption: 0.0, kind: RayKind::Camera}
+    }
+
+    /// A ray continuing the path from a hit, with the same wavelength, time and medium, as a bounce.
+    pub fn scattered(self, origin: Point3D<f32, UnknownUnit>, direction: Vector3D<f32, UnknownUnit>) -> Ray {
+        Ray::new(origin, direction, self.wl, self.ti).with_absorption(self.absorption).with_kind(RayKind::Bounce)
+    }
+
+    pub fn with_kind(self, kind: RayKind) -> Ray {
+        Ray { kind, ..self }
+    }
+
+    pub fn with_absorption(self, absorption: f32) -> Ray {
+        Ray { absorption, ..self }
+    }
+
+    /// The fraction of light that remains after travelling through the medium up to `t`.
+    pub fn transmittance(self, t: f32) -> f32 {
+        if self.absorption > 0.0 {
+            f32::exp(-self.absorption * t * self.direction.length())
+        } else {
+            1.0
+        }
     }
 
     pub fn point_at_parameter(self, t: f32) -> Point3D<f32, UnknownUnit> {
@@ -27,8 +64,41 @@ impl Ray
     }
 }
 
-#[cfg(test)]
-mod tests {
+/// Move `p`, a point on a surface, off it along the unit `normal`, just far enough that the rounding error of
+/// intersecting a ray from there with the surface can't put the surface in front of it again.
+///
+/// The offset is a fixed number of ulps of every coordinate, so it follows the precision of `p` wherever it lies,
+/// and a fixed distance close to the origin, where the ulps get too small. This is the method of Wächter and Binder,
+/// "A Fast and Robust Method for Avoiding Self-Intersection", Ray Tracing Gems, 2019.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate euclid;
+/// # use euclid::*;
+/// # use rayer::ray::offset_origin;
+/// let p = offset_origin(point3(555.0, 0.0, -0.0), vec3(-1.0, 0.0, 1.0));
+/// assert!(p.x < 555.0 && p.x > 554.9);
+/// assert_eq!(p.y, 0.0);
+/// assert!(p.z > 0.0 && p.z < 1e-4);
+/// ```
+pub fn offset_origin(p: Point3D<f32, UnknownUnit>, normal: Vector3D<f32, UnknownUnit>) -> Point3D<f32, UnknownUnit> {
+    // Below this the offset is a distance, above it ulps
+    const ORIGIN: f32 = 1.0/32.0;
+    const FLOAT_SCALE: f32 = 1.0/65536.0;
+    const INT_SCALE: f32 = 256.0;
+    let offset = |p: f32, n: f32| {
+        if p.abs() < ORIGIN {
+            return p + FLOAT_SCALE*n;
+        }
+        // Moving the bits of a negative float up moves it away from 0, so they move the other way
+        let ulps = (INT_SCALE*n) as i32;
+        f32::from_bits((p.to_bits() as i32).wrapping_add(if p < 0.0 { -ulps } else { ulps }) as u32)
+    };
+    point3(offset(p.x, normal.x), offset(p.y, normal.y), offset(p.z, normal.z))
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
     use super::*;
     use test::*;
 
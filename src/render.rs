@@ -0,0 +1,525 @@
+//! Control over a render running on other threads, for front ends embedding the renderer.
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use euclid::*;
+use rayon::prelude::*;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use hitable::{HitRecord, Hitable, SurfaceSample, AABB};
+use ray::Ray;
+use tiles::Tile;
+use texture::Texture;
+
+/// What happened to a render, as sent to the receivers from `RenderHandle::subscribe`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RenderEvent {
+    /// `done` of `total` passes are in the film.
+    Progress { done: u64, total: u64 },
+    Paused,
+    Resumed,
+    /// The render stopped, after all its passes or because it was cancelled.
+    Finished { cancelled: bool },
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum State {
+    Running,
+    Paused,
+    Cancelled,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+    subscribers: Mutex<Vec<Sender<RenderEvent>>>,
+}
+
+/// Cancels, pauses and resumes a render from another thread, and tells how far it got.
+///
+/// The sampling loops call `checkpoint` between their pieces of work, so a pause or cancellation takes effect
+/// as soon as the pieces running finish. Clones control the same render.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::render::{RenderEvent, RenderHandle};
+/// let handle = RenderHandle::new();
+/// let events = handle.subscribe();
+/// // Held at its first checkpoint until cancelled
+/// handle.pause();
+/// let render = {
+///     let handle = handle.clone();
+///     std::thread::spawn(move || {
+///         let mut done = 0;
+///         while done < 1000 && handle.checkpoint() {
+///             done += 1;
+///             handle.report(RenderEvent::Progress { done, total: 1000 });
+///         }
+///         handle.report(RenderEvent::Finished { cancelled: handle.is_cancelled() });
+///     })
+/// };
+/// handle.cancel();
+/// render.join().unwrap();
+/// assert!(events.try_iter().any(|event| event == RenderEvent::Finished { cancelled: true }));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RenderHandle {
+    shared: Arc<Shared>,
+}
+
+impl Default for RenderHandle {
+    fn default() -> RenderHandle {
+        RenderHandle::new()
+    }
+}
+
+impl RenderHandle {
+    pub fn new() -> RenderHandle {
+        RenderHandle {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State::Running),
+                changed: Condvar::new(),
+                subscribers: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Stop the render for good. The passes already done are kept, and it stops paused too.
+    pub fn cancel(&self) {
+        self.set_state(State::Cancelled);
+    }
+
+    /// Hold the render until `resume` or `cancel`.
+    pub fn pause(&self) {
+        if self.set_state(State::Paused) {
+            self.report(RenderEvent::Paused);
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.set_state(State::Running) {
+            self.report(RenderEvent::Resumed);
+        }
+    }
+
+    /// Whether the state changed, which it doesn't after a cancellation.
+    fn set_state(&self, new: State) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        if *state == new || *state == State::Cancelled {
+            return false;
+        }
+        *state = new;
+        self.shared.changed.notify_all();
+        true
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.shared.state.lock().unwrap() == State::Cancelled
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.shared.state.lock().unwrap() == State::Paused
+    }
+
+    /// Wait while the render is paused, and tell whether it should go on. For the sampling loops.
+    pub fn checkpoint(&self) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        while *state == State::Paused {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        *state == State::Running
+    }
+
+    /// A channel getting every event reported from now on.
+    pub fn subscribe(&self) -> Receiver<RenderEvent> {
+        let (sender, receiver) = unbounded();
+        self.shared.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Tell the subscribers about an event, forgetting the ones that went away.
+    pub fn report(&self, event: RenderEvent) {
+        self.shared.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
+
+/// How long a render may run and how many rays it may trace, after which it stops with the samples it has,
+/// for comparing renderers at the same cost or fitting renders into fixed slots.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub time: Option<Duration>,
+    pub rays: Option<u64>,
+}
+
+/// What a render spent of its budget so far, counting from when it was made. The rays traced through `count` add up
+/// here from every thread, and the sampling loops check it next to `RenderHandle::checkpoint`, so the render stops
+/// once the pieces running finish, and the next render starts with a budget of its own.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::render::{Budget, Spending};
+/// let spending = Spending::new(Budget { time: None, rays: Some(100) });
+/// assert!(!spending.spend(60));
+/// assert!(spending.spend(60));
+/// assert!(spending.is_spent());
+/// assert_eq!(spending.rays(), 120);
+/// ```
+#[derive(Debug)]
+pub struct Spending {
+    budget: Budget,
+    start: Instant,
+    rays: AtomicU64,
+}
+
+impl Spending {
+    pub fn new(budget: Budget) -> Spending {
+        Spending { budget, start: Instant::now(), rays: AtomicU64::new(0) }
+    }
+
+    /// Add `rays` traced, and tell whether the budget is used up.
+    pub fn spend(&self, rays: u64) -> bool {
+        let total = self.rays.fetch_add(rays, Ordering::Relaxed) + rays;
+        self.budget.rays.map_or(false, |budget| total >= budget)
+            || self.budget.time.map_or(false, |budget| self.start.elapsed() >= budget)
+    }
+
+    pub fn is_spent(&self) -> bool {
+        self.spend(0)
+    }
+
+    /// The scene `world`, counting the rays traced against it as spent.
+    pub fn count<'a, H: Hitable>(&'a self, world: &'a H) -> CountRays<'a, H> {
+        CountRays { world, spending: self }
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.rays.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Render the passes `passes` of an image split into `tiles`, the passes and the tiles of every pass in parallel,
+/// calling `render_tile` for every tile of a pass and then `finish_pass` once all of them are done. This is the
+/// sampling loop of the renderer, for front ends bringing what they do with a tile and a pass.
+///
+/// Between the tiles it checks `handle` and `spending`: a pause holds the tiles not started yet, and a cancellation
+/// or the budget running out skips them, so only the passes that got all their tiles are finished. Progress is left
+/// to `finish_pass` to report.
+///
+/// ```
+/// # extern crate rayer;
+/// # use std::sync::atomic::{AtomicU64, Ordering};
+/// # use rayer::render::{render_passes, Budget, RenderHandle, Spending};
+/// # use rayer::tiles::TileOrder;
+/// let tiles = TileOrder::Scanline.tiles(64, 48, 16);
+/// let handle = RenderHandle::new();
+/// let (pixels, passes) = (AtomicU64::new(0), AtomicU64::new(0));
+/// // Cancelled as soon as the first pass is in
+/// render_passes(0..1000, &tiles, &handle, &Spending::new(Budget::default()), |_, tile| {
+///     pixels.fetch_add(tile.pixels(64).count() as u64, Ordering::Relaxed);
+/// }, |_| {
+///     passes.fetch_add(1, Ordering::Relaxed);
+///     handle.cancel();
+/// });
+/// let passes = passes.load(Ordering::Relaxed);
+/// assert!(passes >= 1 && passes < 1000);
+/// assert!(pixels.load(Ordering::Relaxed) >= passes*64*48);
+/// ```
+pub fn render_passes<T, P>(passes: Range<u64>, tiles: &[Tile], handle: &RenderHandle, spending: &Spending, render_tile: T, finish_pass: P)
+where
+    T: Fn(u64, Tile) + Sync,
+    P: Fn(u64) + Sync,
+{
+    passes.into_par_iter().for_each(|index| {
+        if !handle.checkpoint() || spending.is_spent() {
+            return;
+        }
+        // Tiles are handed out to the threads whole and in order, so they show up as spans in a trace
+        tiles.iter().par_bridge().for_each(|&tile| {
+            if !handle.checkpoint() || spending.is_spent() {
+                return;
+            }
+            render_tile(index, tile);
+        });
+        if handle.is_cancelled() || spending.is_spent() {
+            return;
+        }
+        finish_pass(index);
+    });
+}
+
+/// A scene counting the rays traced against it, closest hits and shadow rays alike, into the `Spending` of a render,
+/// see `Spending::count`.
+#[derive(Debug)]
+pub struct CountRays<'a, H: Hitable + 'a> {
+    world: &'a H,
+    spending: &'a Spending,
+}
+
+impl<'a, H: Hitable> Hitable for CountRays<'a, H> {
+    fn centroid(&self) -> Point3D<f32, UnknownUnit> {
+        self.world.centroid()
+    }
+
+    fn bbox(&self) -> AABB {
+        self.world.bbox()
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.spending.rays.fetch_add(1, Ordering::Relaxed);
+        self.world.hit(r, t_min, t_max)
+    }
+
+    fn is_occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.spending.rays.fetch_add(1, Ordering::Relaxed);
+        self.world.is_occluded(r, t_min, t_max)
+    }
+
+    fn surface_area(&self) -> f32 {
+        self.world.surface_area()
+    }
+
+    fn sample_surface(&self, u: Vector2D<f32, UnknownUnit>) -> Option<SurfaceSample> {
+        self.world.sample_surface(u)
+    }
+
+    fn visit_textures(&self, visit: &mut dyn FnMut(&dyn Texture)) {
+        self.world.visit_textures(visit);
+    }
+}
+
+/// A radiance that wasn't a finite number, with where a path found it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct NonFinite {
+    pub value: f32,
+    /// What the value was: emission, reflection, direct light, transmittance or sky.
+    pub source: &'static str,
+    pub wavelength: f32,
+    /// The material hit, as far as its debug output fits a line, and the object and point it was hit at.
+    pub material: Option<String>,
+    pub object_id: Option<u32>,
+    pub position: Option<Point3D<f32, UnknownUnit>>,
+}
+
+impl NonFinite {
+    /// A value found at the hit `rec` of the ray `r` on `material`.
+    pub fn at<M: fmt::Debug>(source: &'static str, value: f32, r: Ray, rec: &HitRecord, material: &M) -> NonFinite {
+        let mut material = format!("{:?}", material);
+        if let Some((end, _)) = material.char_indices().nth(MATERIAL_LENGTH) {
+            material.truncate(end);
+            material.push_str("...");
+        }
+        NonFinite {
+            value,
+            source,
+            wavelength: r.wl,
+            material: Some(material),
+            object_id: rec.object_id,
+            position: Some(rec.p),
+        }
+    }
+
+    /// A value found where the ray `r` left the scene.
+    pub fn missed(source: &'static str, value: f32, r: Ray) -> NonFinite {
+        NonFinite { value, source, wavelength: r.wl, material: None, object_id: None, position: None }
+    }
+}
+
+/// How much of the debug output of a material a `NonFinite` keeps.
+const MATERIAL_LENGTH: usize = 120;
+
+impl fmt::Display for NonFinite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} at {:.1} nm", self.value, self.source, self.wavelength)?;
+        if let Some(id) = self.object_id {
+            write!(f, " on object {}", id)?;
+        }
+        if let Some(p) = self.position {
+            write!(f, " at ({}, {}, {})", p.x, p.y, p.z)?;
+        }
+        if let Some(ref material) = self.material {
+            write!(f, " with the material {}", material)?;
+        }
+        Ok(())
+    }
+}
+
+/// Catches radiance that isn't a finite number, NaN or infinite, before it turns pixels black or white. Paths pass
+/// what they add up through `check`, which counts the bad values, replaces them with 0 and keeps the first with where
+/// it came from to report after the render. A strict watchdog panics at the first instead, with the same report.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate euclid;
+/// # use euclid::*;
+/// # use rayer::render::{NonFinite, Watchdog};
+/// # use rayer::ray::Ray;
+/// let watchdog = Watchdog::new(false);
+/// let r = Ray::new(point3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+/// assert_eq!(watchdog.check(0.5, || NonFinite::missed("sky", 0.5, r)), 0.5);
+/// assert_eq!(watchdog.check(std::f32::NAN, || NonFinite::missed("sky", std::f32::NAN, r)), 0.0);
+/// assert_eq!(watchdog.count(), 1);
+/// assert_eq!(watchdog.first().unwrap().source, "sky");
+/// ```
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    strict: bool,
+    count: AtomicU64,
+    first: Mutex<Option<NonFinite>>,
+}
+
+impl Watchdog {
+    pub fn new(strict: bool) -> Watchdog {
+        Watchdog { strict, ..Default::default() }
+    }
+
+    /// `value` if it is finite, and otherwise 0, recording where it came from with `found`.
+    pub fn check<F: FnOnce() -> NonFinite>(&self, value: f32, found: F) -> f32 {
+        if value.is_finite() {
+            return value;
+        }
+        let found = found();
+        if self.strict {
+            panic!("radiance isn't finite: {}", found);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.first.lock().unwrap().get_or_insert(found);
+        0.0
+    }
+
+    /// How many values weren't finite.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn first(&self) -> Option<NonFinite> {
+        self.first.lock().unwrap().clone()
+    }
+
+    /// A line about what was caught for the log, if anything was.
+    pub fn report(&self) -> Option<String> {
+        self.first().map(|first| format!("Dropped {} radiance values that weren't finite, the first {}", self.count(), first))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pause_and_resume() {
+        let handle = RenderHandle::new();
+        let events = handle.subscribe();
+        handle.pause();
+        handle.pause();
+        let passed = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let (handle, passed) = (handle.clone(), passed.clone());
+            thread::spawn(move || {
+                let proceed = handle.checkpoint();
+                passed.store(true, Ordering::SeqCst);
+                proceed
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!passed.load(Ordering::SeqCst));
+        handle.resume();
+        assert!(worker.join().unwrap());
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![RenderEvent::Paused, RenderEvent::Resumed]);
+    }
+
+    #[test]
+    fn test_cancel_while_paused() {
+        let handle = RenderHandle::new();
+        handle.pause();
+        let worker = {
+            let handle = handle.clone();
+            thread::spawn(move || handle.checkpoint())
+        };
+        handle.cancel();
+        assert!(!worker.join().unwrap());
+        handle.resume();
+        assert!(handle.is_cancelled() && !handle.checkpoint());
+    }
+
+    #[test]
+    fn test_dropped_subscribers() {
+        let handle = RenderHandle::new();
+        drop(handle.subscribe());
+        let events = handle.subscribe();
+        handle.report(RenderEvent::Progress { done: 1, total: 2 });
+        assert_eq!(handle.shared.subscribers.lock().unwrap().len(), 1);
+        assert_eq!(events.try_recv(), Ok(RenderEvent::Progress { done: 1, total: 2 }));
+    }
+
+    #[test]
+    fn test_time_budget() {
+        let spending = Spending::new(Budget { time: Some(Duration::from_millis(20)), rays: None });
+        assert!(!spending.spend(1_000_000));
+        thread::sleep(Duration::from_millis(30));
+        assert!(spending.is_spent());
+        // Without limits it goes on
+        assert!(!Spending::new(Budget::default()).spend(u64::MAX/2));
+    }
+
+    #[test]
+    fn test_count_rays() {
+        use hitable::sphere::Sphere;
+        use material::Lambertian;
+        use palette::Rgb;
+
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5))));
+        let spending = Spending::new(Budget { time: None, rays: Some(4) });
+        let world = spending.count(&sphere);
+        let r = Ray::new(point3(0.0, 0.0, 5.0), vec3(0.0, 0.0, -1.0), 500.0, 0.0);
+        assert!(world.hit(r, 0.0, 10.0).is_some());
+        assert!(world.is_occluded(r, 0.0, 10.0));
+        assert_eq!(spending.rays(), 2);
+        // Rays traced on other threads count for the same render
+        thread::scope(|scope| {
+            scope.spawn(|| world.hit(r, 0.0, 10.0));
+            scope.spawn(|| world.hit(r, 0.0, 10.0));
+        });
+        assert_eq!(spending.rays(), 4);
+        assert!(spending.is_spent());
+        assert_eq!(Spending::new(Budget::default()).rays(), 0);
+    }
+
+    /// A NaN emitted by a grey sphere, with where it was found.
+    fn found_nan() -> NonFinite {
+        use fixtures::{head_on, hit_unit_sphere};
+        use material::Lambertian;
+        use palette::Rgb;
+
+        let material = Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5));
+        let r = head_on(550.0);
+        hit_unit_sphere(&material, r, |rec| NonFinite::at("emission", std::f32::NAN, r, &rec, &material))
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let watchdog = Watchdog::new(false);
+        assert_eq!(watchdog.report(), None);
+        assert_eq!(watchdog.check(std::f32::INFINITY, found_nan), 0.0);
+        assert_eq!(watchdog.check(std::f32::NAN, found_nan), 0.0);
+        let report = watchdog.report().unwrap();
+        assert!(report.starts_with("Dropped 2 radiance values that weren't finite, the first NaN emission at 550.0 nm at (0, 0, -1)"), "{}", report);
+        assert!(report.contains("Lambertian"), "{}", report);
+    }
+
+    #[test]
+    #[should_panic(expected = "radiance isn't finite: NaN emission")]
+    fn test_strict_watchdog() {
+        Watchdog::new(true).check(std::f32::NAN, found_nan);
+    }
+}
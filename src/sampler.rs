@@ -0,0 +1,419 @@
+use std::fmt::Debug;
+use euclid::*;
+use num_traits::FloatConst;
+
+use random::*;
+
+/// The dimension used for the position inside a pixel.
+pub const PIXEL_DIMENSION: u32 = 0;
+/// The dimension used for the position on the lens.
+pub const LENS_DIMENSION: u32 = 1;
+/// The dimension used to select the wavelength.
+pub const WAVELENGTH_DIMENSION: u32 = 2;
+/// The first dimension used for the decisions along a path.
+pub const FIRST_PATH_DIMENSION: u32 = 3;
+
+/// Generates the sample positions used for a pixel.
+///
+/// Samplers are stateless, so the same pixel, index and dimension always produce
+/// the same value (apart from `RandomSampler`). Using different dimensions for
+/// different decisions keeps those decisions uncorrelated.
+pub trait Sampler: Debug + Send + Sync {
+    fn get_1d(&self, pixel: u32, index: u64, dimension: u32) -> f32;
+    fn get_2d(&self, pixel: u32, index: u64, dimension: u32) -> Vector2D<f32, UnknownUnit>;
+}
+
+/// Map a point in the unit square onto the unit disk.
+pub fn sample_disk(u: Vector2D<f32, UnknownUnit>) -> Vector2D<f32, UnknownUnit> {
+    let r = u.x.sqrt();
+    let theta = 2.0*f32::PI()*u.y;
+    vec2(r*theta.cos(), r*theta.sin())
+}
+
+/// Map a point in the unit square onto the unit disk, squeezing concentric squares into circles
+/// (Shirley and Chiu 1997). Unlike `sample_disk` it keeps strata compact, so stratified samples stay stratified.
+pub fn sample_disk_concentric(u: Vector2D<f32, UnknownUnit>) -> Vector2D<f32, UnknownUnit> {
+    let a = 2.0*u.x - 1.0;
+    let b = 2.0*u.y - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return vec2(0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, f32::FRAC_PI_4()*(b/a))
+    } else {
+        (b, f32::FRAC_PI_2() - f32::FRAC_PI_4()*(a/b))
+    };
+    vec2(r*theta.cos(), r*theta.sin())
+}
+
+/// The golden angle as a fraction of a full turn, `2 - φ`.
+const GOLDEN_ANGLE_TURNS: f32 = 0.381_966_02;
+
+/// How the position on the lens is chosen for every camera sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LensSampling {
+    /// The 2D sample of the pixel sampler, mapped with `sample_disk_concentric`.
+    Concentric,
+    /// Points on a golden angle spiral, spreading `samples_per_pixel` samples evenly over the lens.
+    /// Every pixel turns the spiral and jitters the radii, so neighbouring pixels don't share a pattern.
+    Spiral { samples_per_pixel: u32 },
+}
+
+impl LensSampling {
+    /// A point on the unit disk for the given sample of a pixel.
+    pub fn sample(&self, sampler: &dyn Sampler, pixel: u32, index: u64) -> Vector2D<f32, UnknownUnit> {
+        match *self {
+            LensSampling::Concentric => sample_disk_concentric(sampler.get_2d(pixel, index, LENS_DIMENSION)),
+            LensSampling::Spiral { samples_per_pixel } => {
+                let n = samples_per_pixel.max(1);
+                let (i, p) = split_index(index, n, pixel_seed(pixel, LENS_DIMENSION));
+                // Every point gets a ring of equal area, and consecutive points are a golden angle apart
+                let r = ((i as f32 + rand_float(i, p.wrapping_mul(0x2c1b3c6d)))/(n as f32)).sqrt();
+                let turns = rand_float(0, p.wrapping_mul(0x297a2d39)) + (i as f32)*GOLDEN_ANGLE_TURNS;
+                let theta = 2.0*f32::PI()*turns.fract();
+                vec2(r*theta.cos(), r*theta.sin())
+            },
+        }
+    }
+}
+
+/// Map a point in the unit square and a radius sample onto the unit ball.
+pub fn sample_ball(u: Vector2D<f32, UnknownUnit>, r: f32) -> Vector3D<f32, UnknownUnit> {
+    let z = 1.0 - 2.0*u.x;
+    let radius = f32::sqrt(f32::max(0.0, 1.0 - z*z));
+    let phi = 2.0*f32::PI()*u.y;
+    vec3(radius*phi.cos(), radius*phi.sin(), z)*r.cbrt()
+}
+
+/// Map a point in the unit square onto the directions within the cone around the normalized `axis` whose angle has
+/// the cosine `cos_max`, evenly over their solid angle.
+pub fn sample_cone(u: Vector2D<f32, UnknownUnit>, axis: Vector3D<f32, UnknownUnit>, cos_max: f32) -> Vector3D<f32, UnknownUnit> {
+    let cos_theta = 1.0 - u.x*(1.0 - cos_max);
+    let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta*cos_theta));
+    let phi = 2.0*f32::PI()*u.y;
+    let a = if axis.x.abs() < 0.5 { vec3(0.0, -axis.z, axis.y) } else { vec3(-axis.z, 0.0, axis.x) }.normalize();
+    let b = axis.cross(a);
+    a*(sin_theta*phi.cos()) + b*(sin_theta*phi.sin()) + axis*cos_theta
+}
+
+/// Independent uniform random samples.
+#[derive(Debug, Clone, Copy)]
+pub struct RandomSampler;
+
+impl Sampler for RandomSampler {
+    fn get_1d(&self, _pixel: u32, _index: u64, _dimension: u32) -> f32 {
+        next_f32()
+    }
+
+    fn get_2d(&self, _pixel: u32, _index: u64, _dimension: u32) -> Vector2D<f32, UnknownUnit> {
+        vec2(next_f32(), next_f32())
+    }
+}
+
+/// Jittered samples, one per stratum.
+/// The strata are visited in a different random order for every pixel and dimension.
+#[derive(Debug, Clone, Copy)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        StratifiedSampler { samples_per_pixel: samples_per_pixel.max(1) }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn get_1d(&self, pixel: u32, index: u64, dimension: u32) -> f32 {
+        let n = self.samples_per_pixel;
+        let (s, p) = split_index(index, n, pixel_seed(pixel, dimension));
+        let stratum = permute(s, n, p);
+        ((stratum as f32) + rand_float(s, p.wrapping_mul(0xa399d265))) / (n as f32)
+    }
+
+    fn get_2d(&self, pixel: u32, index: u64, dimension: u32) -> Vector2D<f32, UnknownUnit> {
+        let nx = (self.samples_per_pixel as f32).sqrt() as u32;
+        let ny = (self.samples_per_pixel + nx - 1) / nx;
+        let (s, p) = split_index(index, nx*ny, pixel_seed(pixel, dimension));
+        let stratum = permute(s, nx*ny, p);
+        let jx = rand_float(s, p.wrapping_mul(0xa399d265));
+        let jy = rand_float(s, p.wrapping_mul(0x711ad6a5));
+        vec2(
+            ((stratum % nx) as f32 + jx) / (nx as f32),
+            ((stratum / nx) as f32 + jy) / (ny as f32),
+        )
+    }
+}
+
+/// Correlated multi-jittered samples as described by Kensler (2013).
+/// Stratified in 2D and additionally well distributed in each of the two axes.
+#[derive(Debug, Clone, Copy)]
+pub struct MultiJitteredSampler {
+    samples_per_pixel: u32,
+}
+
+impl MultiJitteredSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        MultiJitteredSampler { samples_per_pixel: samples_per_pixel.max(1) }
+    }
+}
+
+impl Sampler for MultiJitteredSampler {
+    fn get_1d(&self, pixel: u32, index: u64, dimension: u32) -> f32 {
+        StratifiedSampler::new(self.samples_per_pixel).get_1d(pixel, index, dimension)
+    }
+
+    fn get_2d(&self, pixel: u32, index: u64, dimension: u32) -> Vector2D<f32, UnknownUnit> {
+        let n = self.samples_per_pixel;
+        let (s, p) = split_index(index, n, pixel_seed(pixel, dimension));
+        let m = (n as f32).sqrt() as u32;
+        let n_rows = (n + m - 1) / m;
+        let s = permute(s, n, p.wrapping_mul(0x51633e2d));
+        let sx = permute(s % m, m, p.wrapping_mul(0x68bc21eb));
+        let sy = permute(s / m, n_rows, p.wrapping_mul(0x02e5be93));
+        let jx = rand_float(s, p.wrapping_mul(0x967a889b));
+        let jy = rand_float(s, p.wrapping_mul(0x368cc8b7));
+        vec2(
+            ((s % m) as f32 + ((sy as f32) + jx) / (n_rows as f32)) / (m as f32),
+            ((s / m) as f32 + ((sx as f32) + jy) / (m as f32)) / (n_rows as f32),
+        )
+    }
+}
+
+/// Owen scrambled Sobol points with hash based scrambling (Burley 2020).
+/// The sequence is progressive, so the number of samples doesn't need to be known.
+#[derive(Debug, Clone, Copy)]
+pub struct SobolSampler;
+
+impl Sampler for SobolSampler {
+    fn get_1d(&self, pixel: u32, index: u64, dimension: u32) -> f32 {
+        let seed = pixel_seed(pixel, dimension);
+        let index = nested_uniform_scramble(index as u32, seed);
+        to_unit_float(nested_uniform_scramble(sobol_0(index), hash_combine(seed, 0)))
+    }
+
+    fn get_2d(&self, pixel: u32, index: u64, dimension: u32) -> Vector2D<f32, UnknownUnit> {
+        let seed = pixel_seed(pixel, dimension);
+        let index = nested_uniform_scramble(index as u32, seed);
+        vec2(
+            to_unit_float(nested_uniform_scramble(sobol_0(index), hash_combine(seed, 0))),
+            to_unit_float(nested_uniform_scramble(sobol_1(index), hash_combine(seed, 1))),
+        )
+    }
+}
+
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+fn hash_combine(seed: u32, v: u32) -> u32 {
+    seed ^ v.wrapping_add(0x9e3779b9).wrapping_add(seed << 6).wrapping_add(seed >> 2)
+}
+
+fn pixel_seed(pixel: u32, dimension: u32) -> u32 {
+    hash(hash_combine(hash(pixel), dimension))
+}
+
+/// Samples beyond the planned count start a new, differently permuted, pattern.
+fn split_index(index: u64, n: u32, seed: u32) -> (u32, u32) {
+    let pattern = (index / n as u64) as u32;
+    ((index % n as u64) as u32, hash_combine(seed, pattern))
+}
+
+fn to_unit_float(x: u32) -> f32 {
+    (x >> 8) as f32 / ((1u32 << 24) as f32)
+}
+
+/// Pseudo random permutation of `0..l` (Kensler 2013).
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    if l <= 1 {
+        return 0;
+    }
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+/// Pseudo random float in [0,1) for a given index and seed (Kensler 2013).
+fn rand_float(mut i: u32, p: u32) -> f32 {
+    i ^= p;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb36534e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc4795);
+    i ^= 0xdf6e307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | p >> 18);
+    to_unit_float(i)
+}
+
+/// First Sobol dimension, the van der Corput sequence.
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+/// Second Sobol dimension.
+fn sobol_1(mut index: u32) -> u32 {
+    let mut v: u32 = 1 << 31;
+    let mut res = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            res ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    res
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samplers(n: u32) -> Vec<Box<dyn Sampler>> {
+        vec![
+            Box::new(RandomSampler),
+            Box::new(StratifiedSampler::new(n)),
+            Box::new(MultiJitteredSampler::new(n)),
+            Box::new(SobolSampler),
+        ]
+    }
+
+    #[test]
+    fn test_in_unit_square() {
+        for sampler in samplers(10) {
+            for pixel in 0..10 {
+                for index in 0..50 {
+                    let x = sampler.get_1d(pixel, index, WAVELENGTH_DIMENSION);
+                    assert!(x >= 0.0 && x < 1.0, "{:?}: {:}", sampler, x);
+                    let p = sampler.get_2d(pixel, index, PIXEL_DIMENSION);
+                    assert!(p.x >= 0.0 && p.x < 1.0 && p.y >= 0.0 && p.y < 1.0, "{:?}: {:?}", sampler, p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_one_sample_per_stratum_1d() {
+        let n = 16;
+        for sampler in samplers(n)[1..].iter() {
+            let mut seen = vec![false; n as usize];
+            for index in 0..n {
+                let x = sampler.get_1d(7, index as u64, LENS_DIMENSION);
+                let stratum = (x*(n as f32)) as usize;
+                assert!(!seen[stratum], "{:?}: stratum {:} used twice", sampler, stratum);
+                seen[stratum] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_jittered_is_n_rooks() {
+        let n = 16;
+        let sampler = MultiJitteredSampler::new(n);
+        let mut seen_x = vec![false; n as usize];
+        let mut seen_y = vec![false; n as usize];
+        for index in 0..n {
+            let p = sampler.get_2d(3, index as u64, PIXEL_DIMENSION);
+            let sx = (p.x*(n as f32)) as usize;
+            let sy = (p.y*(n as f32)) as usize;
+            assert!(!seen_x[sx] && !seen_y[sy]);
+            seen_x[sx] = true;
+            seen_y[sy] = true;
+        }
+    }
+
+    #[test]
+    fn test_permute_is_permutation() {
+        for &l in [1, 2, 3, 7, 16, 100].iter() {
+            let mut seen = vec![false; l as usize];
+            for i in 0..l {
+                let j = permute(i, l, 0x12345678) as usize;
+                assert!(!seen[j]);
+                seen[j] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn test_concentric_disk() {
+        let n = 32;
+        let mut mean_square_radius = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let p = sample_disk_concentric(vec2((i as f32 + 0.5)/(n as f32), (j as f32 + 0.5)/(n as f32)));
+                assert!(p.square_length() <= 1.0);
+                mean_square_radius += p.square_length()/((n*n) as f32);
+            }
+        }
+        // Uniform over the disk
+        assert!((mean_square_radius - 0.5).abs() < 1e-2, "{}", mean_square_radius);
+        // The corners of the square end up on the rim
+        assert!((sample_disk_concentric(vec2(1.0, 1.0)).length() - 1.0).abs() < 1e-6);
+        assert_eq!(sample_disk_concentric(vec2(0.5, 0.5)), vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_spiral_covers_lens() {
+        let n = 64;
+        let lens = LensSampling::Spiral { samples_per_pixel: n };
+        let points: Vec<_> = (0..n as u64).map(|index| lens.sample(&RandomSampler, 5, index)).collect();
+        // One point in every ring of equal area
+        let mut rings: Vec<usize> = points.iter().map(|p| (p.square_length()*(n as f32)) as usize).collect();
+        rings.sort();
+        assert_eq!(rings, (0..n as usize).collect::<Vec<_>>());
+        // No two points close together
+        for (k, a) in points.iter().enumerate() {
+            for b in points[k + 1..].iter() {
+                assert!((*a - *b).length() > 0.05, "{:?} {:?}", a, b);
+            }
+        }
+    }
+}
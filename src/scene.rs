@@ -0,0 +1,767 @@
+//! Scenes and a registry to look them up by name.
+
+use euclid::*;
+use image::{ImageError, RgbImage};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use camera::{Camera, CameraKeyframe, CameraPath, Movements};
+use color::{ColorSpectrum, SensorResponse};
+use delta_light::DeltaLight;
+use flare::LensFlare;
+use hitable::{Hitable, AABB};
+use hitable::bvh::BVH;
+use hitable::point_cloud::PointCloud;
+use hitable::sphere::Sphere;
+use hitable::triangle::TriangleMesh;
+use hitable::wavefront::ObjMesh;
+use lens_system::Lens;
+use material::baked::ResponseTable;
+use ray::Ray;
+use settings::{RenderSettings, SettingsOverrides};
+use sky::Sky;
+use texture::Texture;
+use trace;
+
+/// The objects to render together with the camera setup.
+pub struct Scene {
+    pub objects: Vec<Arc<dyn Hitable>>,
+    pub look_from: Point3D<f32, UnknownUnit>,
+    pub look_at: Point3D<f32, UnknownUnit>,
+    pub focus_dist: f32,
+    pub aperture: f32,
+    pub vfov: f32,
+    /// Roll, shift and tilt of the camera.
+    pub movements: Movements,
+    pub render_sky: bool,
+    /// The light of the sky, unless `render_sky` is off.
+    pub sky: Sky,
+    /// Lights without a surface, which only light diffuse surfaces.
+    pub lights: Vec<DeltaLight>,
+    /// Camera movement for animations, if the scene defines one.
+    pub animation: Option<CameraPath>,
+    /// Flare the camera lens adds around bright spots.
+    pub flare: Option<LensFlare>,
+    /// How the scene is best rendered, where it differs from the defaults. The command line overrides these in turn.
+    pub settings: SettingsOverrides,
+}
+
+/// What a camera ray through a pixel hit.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Pick {
+    /// Index into `Scene::objects`.
+    pub object_index: usize,
+    /// The id given with `instance::with_id`, if any.
+    pub object_id: Option<u32>,
+    pub p: Point3D<f32, UnknownUnit>,
+    pub normal: Vector3D<f32, UnknownUnit>,
+    pub t: f32,
+}
+
+/// Widest field of view in degrees `Scene::auto_frame` chooses. Closer to the scene the camera backs off instead.
+const MAX_AUTO_VFOV: f32 = 60.0;
+
+impl Scene {
+    /// The box around all objects, as the root of a BVH over them has it, or `None` for an empty scene.
+    pub fn bounds(&self) -> Option<AABB> {
+        self.objects.iter().map(|object| object.bbox()).fold(None, |bounds, bbox| match bounds {
+            Some(bounds) => Some(bbox.merge(bounds)),
+            None => Some(bbox),
+        })
+    }
+
+    /// The objects in a BVH, for tracing rays against them without the rest of the renderer, one at a time with `hit`
+    /// or whole slices of them with `BVH::intersect_stream` and `BVH::occluded_stream`.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # use rayer::prelude::*;
+    /// # use std::sync::Arc;
+    /// let grey: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+    /// let scene = SceneBuilder::new().add_sphere(point3(0.0, 0.0, 0.0), 1.0, grey).scene();
+    /// let rays: Vec<Ray> = (0..4).map(|i| Ray::new(point3(i as f32 - 1.5, 0.0, 5.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0)).collect();
+    /// let bvh = scene.bvh();
+    /// let mut hits = vec![None; rays.len()];
+    /// bvh.intersect_stream(&rays, 0.0, f32::MAX, &mut hits);
+    /// assert_eq!(hits.iter().map(Option::is_some).collect::<Vec<_>>(), vec![false, true, true, false]);
+    /// ```
+    pub fn bvh(&self) -> BVH<Arc<dyn Hitable>> {
+        BVH::initialize(self.objects.clone())
+    }
+
+    /// Aim the camera at the middle of the scene, focused there, and choose the field of view so all of the scene
+    /// fits into an image with the aspect ratio `aspect`, for models of unknown size and position.
+    /// The camera keeps looking from the same direction. Where it would need a field of view wider than
+    /// `MAX_AUTO_VFOV`, as from within the scene, it backs off along that direction instead.
+    pub fn auto_frame(&mut self, aspect: f32) {
+        let AABB { bounds: [low, high] } = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let center = low.lerp(high, 0.5);
+        let radius = ((high - low).length()*0.5).max(1e-6);
+        let offset = self.look_from - center;
+        let direction = if offset.length() > 0.0 { offset.normalize() } else { vec3(0.0, 0.0, 1.0) };
+        // The sphere around the box has to fit into the narrower of the vertical and horizontal field of view
+        let narrow = aspect.min(1.0);
+        let widest = (MAX_AUTO_VFOV.to_radians()*0.5).tan()*narrow;
+        let distance = offset.length().max(radius*f32::sqrt(1.0 + widest*widest)/widest);
+        let tangent = radius/f32::sqrt(distance*distance - radius*radius);
+        self.look_from = center + direction*distance;
+        self.look_at = center;
+        self.focus_dist = distance;
+        self.vfov = 2.0*(tangent/narrow).atan().to_degrees();
+    }
+
+    /// Trace a ray through the center of pixel `(x, y)` of a `width` by `height` image,
+    /// counting rows from the top, and report the closest hit.
+    /// The lens is treated as a pinhole and time is fixed at the shutter opening, so the result doesn't depend on chance.
+    pub fn pick(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Pick> {
+        let keyframe = CameraKeyframe {
+            look_from: self.look_from,
+            look_at: self.look_at,
+            vfov: self.vfov,
+            aperture: 0.0,
+            focus_dist: self.focus_dist,
+            movements: self.movements,
+        };
+        let cam = keyframe.to_camera(vec3(0.0, 1.0, 0.0), width as f32/height as f32, 0.0, 1.0);
+        let s = (x as f32 + 0.5)/width as f32;
+        let t = 1.0 - (y as f32 + 0.5)/height as f32;
+        let ray = Ray { ti: 0.0, ..cam.get_ray(s, t, 550.0) };
+        let mut closest: Option<Pick> = None;
+        for (object_index, object) in self.objects.iter().enumerate() {
+            let t_max = closest.map_or(f32::MAX, |pick| pick.t);
+            if let Some(rec) = object.hit(ray, 0.0, t_max) {
+                closest = Some(Pick { object_index, object_id: rec.object_id, p: rec.p, normal: rec.normal, t: rec.t });
+            }
+        }
+        closest
+    }
+}
+
+/// Builds a `Scene` a piece at a time, leaving the camera, sky and settings at their defaults unless told otherwise:
+/// the camera looking from `(0, 0, 1)` towards the origin, sharp throughout, under the gradient sky.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::prelude::*;
+/// # use std::sync::Arc;
+/// let grey: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+/// let builder = SceneBuilder::new()
+///     .add_sphere(point3(0.0, 0.0, 0.0), 1.0, grey.clone())
+///     .add_sphere(point3(0.0, -101.0, 0.0), 100.0, grey)
+///     .camera(point3(0.0, 0.0, 5.0), point3(0.0, 0.0, 0.0), 40.0)
+///     .environment(Sky::Uniform { radiance: 1.0 });
+/// assert!(builder.warnings().is_empty());
+/// let (world, camera, settings) = builder.build().unwrap();
+/// assert_eq!((settings.width, settings.height), (800, 600));
+/// assert!((world.hit(camera.get_ray(0.5, 0.5, 550.0), 0.0, 100.0).unwrap().p - point3(0.0, 0.0, 1.0)).length() < 1e-4);
+/// ```
+pub struct SceneBuilder {
+    scene: Scene,
+}
+
+impl Default for SceneBuilder {
+    fn default() -> SceneBuilder {
+        SceneBuilder::new()
+    }
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene {
+                objects: Vec::new(),
+                look_from: point3(0.0, 0.0, 1.0),
+                look_at: point3(0.0, 0.0, 0.0),
+                focus_dist: 1.0,
+                aperture: 0.0,
+                vfov: 40.0,
+                movements: Movements::default(),
+                render_sky: true,
+                sky: Sky::default(),
+                lights: Vec::new(),
+                animation: None,
+                flare: None,
+                settings: SettingsOverrides::default(),
+            },
+        }
+    }
+
+    pub fn add<H: Hitable + 'static>(self, object: H) -> SceneBuilder {
+        self.add_shared(Arc::new(object))
+    }
+
+    /// Add an object that may be part of other scenes or objects as well.
+    pub fn add_shared(mut self, object: Arc<dyn Hitable>) -> SceneBuilder {
+        self.scene.objects.push(object);
+        self
+    }
+
+    /// Add all of `objects`, in order.
+    pub fn add_all(mut self, objects: Vec<Arc<dyn Hitable>>) -> SceneBuilder {
+        self.scene.objects.extend(objects);
+        self
+    }
+
+    pub fn add_sphere(self, center: Point3D<f32, UnknownUnit>, radius: f32, texture: Arc<dyn Texture>) -> SceneBuilder {
+        self.add(Sphere::new(center, radius, texture))
+    }
+
+    /// Add a mesh loaded with `ObjMesh::load`, all of it with one texture.
+    pub fn add_mesh(self, mesh: &ObjMesh, texture: Arc<dyn Texture>) -> SceneBuilder {
+        self.add(TriangleMesh::from_obj_mesh(mesh, texture))
+    }
+
+    /// Look from `look_from` at `look_at`, with a vertical field of view of `vfov` degrees and focused on `look_at`.
+    pub fn camera(mut self, look_from: Point3D<f32, UnknownUnit>, look_at: Point3D<f32, UnknownUnit>, vfov: f32) -> SceneBuilder {
+        self.scene.look_from = look_from;
+        self.scene.look_at = look_at;
+        self.scene.vfov = vfov;
+        self.scene.focus_dist = (look_at - look_from).length();
+        self
+    }
+
+    /// Blur what is out of focus, the more the larger `aperture` is.
+    pub fn aperture(mut self, aperture: f32) -> SceneBuilder {
+        self.scene.aperture = aperture;
+        self
+    }
+
+    /// Focus `focus_dist` away from the camera instead of on what it looks at.
+    pub fn focus_dist(mut self, focus_dist: f32) -> SceneBuilder {
+        self.scene.focus_dist = focus_dist;
+        self
+    }
+
+    pub fn movements(mut self, movements: Movements) -> SceneBuilder {
+        self.scene.movements = movements;
+        self
+    }
+
+    /// Light the scene by `sky`, which is also seen behind it.
+    pub fn environment(mut self, sky: Sky) -> SceneBuilder {
+        self.scene.sky = sky;
+        self.scene.render_sky = true;
+        self
+    }
+
+    /// Leave the scene in the dark, but for its own lights.
+    pub fn no_environment(mut self) -> SceneBuilder {
+        self.scene.render_sky = false;
+        self
+    }
+
+    pub fn light(mut self, light: DeltaLight) -> SceneBuilder {
+        self.scene.lights.push(light);
+        self
+    }
+
+    /// Add all of `lights`. Scenes can have hundreds, as beyond a handful every shading point picks one to aim at.
+    pub fn lights<I: IntoIterator<Item = DeltaLight>>(mut self, lights: I) -> SceneBuilder {
+        self.scene.lights.extend(lights);
+        self
+    }
+
+    pub fn animation(mut self, animation: CameraPath) -> SceneBuilder {
+        self.scene.animation = Some(animation);
+        self
+    }
+
+    pub fn flare(mut self, flare: LensFlare) -> SceneBuilder {
+        self.scene.flare = Some(flare);
+        self
+    }
+
+    /// How the scene is best rendered, see `Scene::settings`.
+    pub fn settings(mut self, settings: SettingsOverrides) -> SceneBuilder {
+        self.scene.settings = settings;
+        self
+    }
+
+    /// What is likely not meant, though it can be rendered: an empty scene, or objects at positions that aren't
+    /// numbers, which no ray hits.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.scene.objects.is_empty() {
+            warnings.push("the scene is empty".to_string());
+        }
+        for (i, object) in self.scene.objects.iter().enumerate() {
+            let AABB { bounds: [low, high] } = object.bbox();
+            if low.to_array().iter().chain(high.to_array().iter()).any(|x| x.is_nan()) {
+                warnings.push(format!("object {} has bounds {:?} to {:?}, which aren't all numbers", i, low, high));
+            }
+        }
+        warnings
+    }
+
+    /// The scene, to register in a `SceneRegistry` or render with the options of a front end.
+    pub fn scene(self) -> Scene {
+        self.scene
+    }
+
+    /// The objects in a BVH, the camera, and the settings the scene asks for with the rest left at the defaults.
+    /// Fails where the camera, its animation or the settings can't be rendered with.
+    pub fn build(&self) -> Result<(BVH<Arc<dyn Hitable>>, Camera, RenderSettings), String> {
+        let scene = &self.scene;
+        let finite = |p: Point3D<f32, UnknownUnit>| p.to_array().iter().all(|x| x.is_finite());
+        if !finite(scene.look_from) || !finite(scene.look_at) || scene.look_from == scene.look_at {
+            return Err(format!("the camera can't look from {:?} at {:?}", scene.look_from, scene.look_at));
+        }
+        if !(scene.vfov > 0.0 && scene.vfov < 180.0) {
+            return Err(format!("the field of view of {} degrees isn't between 0 and 180", scene.vfov));
+        }
+        let settings = scene.settings.apply(RenderSettings::default());
+        settings.check()?;
+        if let Some(ref animation) = scene.animation {
+            animation.check()?;
+        }
+        let keyframe = CameraKeyframe {
+            look_from: scene.look_from,
+            look_at: scene.look_at,
+            vfov: scene.vfov,
+            aperture: scene.aperture,
+            focus_dist: scene.focus_dist,
+            movements: scene.movements,
+        };
+        let camera = keyframe.to_camera(vec3(0.0, 1.0, 0.0), settings.aspect(), 0.0, 1.0);
+        Ok((scene.bvh(), camera, settings))
+    }
+}
+
+/// Reported to the `Loader` callback whenever a loading step finishes, and along the way for large files.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct LoadProgress<'a> {
+    pub done: usize,
+    /// Steps started so far. It grows as a scene asks for more files.
+    pub total: usize,
+    /// The step that just finished or progressed, usually a file name.
+    pub step: &'a str,
+    /// How far the step has got, 1 once it finished.
+    pub fraction: f32,
+}
+
+/// Loads the files a scene needs, decoding images and building mesh BVHs in parallel,
+/// and reports every finished file so front ends can show progress.
+///
+/// ```
+/// # extern crate rayer;
+/// # extern crate palette;
+/// # use palette::Rgb;
+/// # use std::sync::{Arc, Mutex};
+/// # use rayer::material::Lambertian;
+/// # use rayer::scene::*;
+/// # use rayer::texture::Texture;
+/// let finished = Mutex::new(Vec::new());
+/// let loader = Loader::new(|progress: LoadProgress| finished.lock().unwrap().push(progress.done));
+/// let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+/// let meshes = loader.meshes(vec![("data/bunny.obj", texture.clone()), ("data/bunny.obj", texture)]).unwrap();
+/// assert_eq!(meshes.len(), 2);
+/// assert_eq!(loader.files().len(), 2);
+/// drop(loader);
+/// let mut finished = finished.into_inner().unwrap();
+/// finished.sort();
+/// assert_eq!(finished, vec![1, 2]);
+/// ```
+pub struct Loader<'a> {
+    progress: Box<dyn Fn(LoadProgress<'_>) + Sync + 'a>,
+    done: AtomicUsize,
+    total: AtomicUsize,
+    preview: bool,
+    subdivision: u32,
+    files: Mutex<Vec<PathBuf>>,
+}
+
+/// Rays scattered per entry of a baked response table.
+const BAKE_SAMPLES: u32 = 256;
+
+impl<'a> Loader<'a> {
+    /// `progress` is called from the loading threads, possibly several at once.
+    pub fn new<F: Fn(LoadProgress<'_>) + Sync + 'a>(progress: F) -> Loader<'a> {
+        Loader {
+            progress: Box::new(progress),
+            done: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+            preview: false,
+            subdivision: 0,
+            files: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A loader for preview renders, which shades expensive materials with tables baked from them.
+    pub fn preview(self, preview: bool) -> Loader<'a> {
+        Loader { preview, ..self }
+    }
+
+    pub fn is_preview(&self) -> bool {
+        self.preview
+    }
+
+    /// A loader that subdivides the meshes it loads `levels` times, see `ObjMesh::subdivided`.
+    pub fn subdivide(self, levels: u32) -> Loader<'a> {
+        Loader { subdivision: levels, ..self }
+    }
+
+    /// How many times meshes are subdivided.
+    pub fn subdivision(&self) -> u32 {
+        self.subdivision
+    }
+
+    /// A loader that doesn't report anything.
+    pub fn silent() -> Loader<'static> {
+        Loader::new(|_| {})
+    }
+
+    /// Load obj files in parallel, each with its own texture, returning the meshes in the same order.
+    pub fn meshes(&self, files: Vec<(&str, Arc<dyn Texture>)>) -> Result<Vec<TriangleMesh>, Error> {
+        self.subdivided_meshes(files, self.subdivision)
+    }
+
+    /// Load obj files like `meshes`, subdividing them `levels` times whatever the loader is set to.
+    pub fn subdivided_meshes(&self, files: Vec<(&str, Arc<dyn Texture>)>, levels: u32) -> Result<Vec<TriangleMesh>, Error> {
+        self.run(files, |&(path, _)| path, |(path, texture)| {
+            self.read(path);
+            let obj = ObjMesh::load_with_progress(Path::new(path), |parsed, size| {
+                self.report(self.done.load(Ordering::SeqCst), path, parsed as f32/size as f32);
+            })?;
+            Ok(TriangleMesh::from_obj_mesh(&obj.subdivided(levels), texture))
+        })
+            .into_iter()
+            .collect()
+    }
+
+    /// Load point clouds in parallel, each with the radius for points that have none, returning them in the same order.
+    pub fn point_clouds(&self, files: Vec<(&str, f32)>) -> Result<Vec<PointCloud>, Error> {
+        self.run(files, |&(path, _)| path, |(path, radius)| {
+            self.read(path);
+            PointCloud::from_file(Path::new(path), radius)
+        })
+            .into_iter()
+            .collect()
+    }
+
+    /// Materials that are expensive to evaluate, each with a name to report.
+    /// For previews their responses are baked into `ResponseTable`s in parallel, otherwise they are returned as they are,
+    /// in the same order either way.
+    pub fn materials(&self, materials: Vec<(&str, Arc<dyn Texture>)>) -> Vec<Arc<dyn Texture>> {
+        if !self.preview {
+            return materials.into_iter().map(|(_, texture)| texture).collect();
+        }
+        self.run(materials, |&(name, _)| name, |(_, texture)| {
+            Arc::new(ResponseTable::bake(texture.as_ref(), BAKE_SAMPLES)) as Arc<dyn Texture>
+        })
+    }
+
+    /// Decode images in parallel, returning them in the same order.
+    pub fn images(&self, paths: &[&str]) -> Result<Vec<Arc<RgbImage>>, ImageError> {
+        self.run(paths.to_vec(), |&path| path, |path| {
+            self.read(path);
+            image::open(path).map(|image| Arc::new(image.to_rgb8()))
+        })
+            .into_iter()
+            .collect()
+    }
+
+    /// Read a lens from a file, see `Lens::from_file`.
+    pub fn lens(&self, path: &str) -> Result<Lens, Error> {
+        self.read(path);
+        Lens::from_file(Path::new(path))
+    }
+
+    /// Read the spectral sensitivities of a camera from a CSV file, see `SensorResponse::from_csv`.
+    pub fn sensor(&self, path: &str) -> Result<SensorResponse, Error> {
+        self.read(path);
+        SensorResponse::from_csv(Path::new(path))
+    }
+
+    /// Read a measured spectrum from a CSV file, see `BinnedSpectrum::from_csv`.
+    pub fn spectrum(&self, path: &str) -> Result<ColorSpectrum, Error> {
+        self.read(path);
+        ColorSpectrum::from_csv(Path::new(path))
+            .map_err(|error| Error::new(error.kind(), format!("{}: {}", path, error)))
+    }
+
+    /// The files read so far, to watch for changes with `watch::Watcher`.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files.lock().unwrap().clone()
+    }
+
+    fn read(&self, path: &str) {
+        self.files.lock().unwrap().push(PathBuf::from(path));
+    }
+
+    fn run<I, T, N, L>(&self, items: Vec<I>, name: N, load: L) -> Vec<T>
+    where I: Send, T: Send, N: Fn(&I) -> &str + Sync, L: Fn(I) -> T + Sync
+    {
+        self.total.fetch_add(items.len(), Ordering::SeqCst);
+        items.into_par_iter().map(|item| {
+            let step = name(&item).to_string();
+            let result = {
+                let _span = trace::span("load", step.clone());
+                load(item)
+            };
+            let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+            self.report(done, &step, 1.0);
+            result
+        }).collect()
+    }
+
+    fn report(&self, done: usize, step: &str, fraction: f32) {
+        (self.progress)(LoadProgress { done, total: self.total.load(Ordering::SeqCst), step, fraction });
+    }
+}
+
+/// A registered scene.
+#[derive(Clone, Copy)]
+pub struct SceneEntry {
+    pub description: &'static str,
+    /// Builds the scene, loading its files through the given loader.
+    pub build: fn(&Loader) -> Scene,
+}
+
+/// Scenes by name, so front ends can list them and pick one.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::scene::*;
+/// fn empty(_: &Loader) -> Scene {
+///     SceneBuilder::new().scene()
+/// }
+///
+/// let mut scenes = SceneRegistry::new();
+/// scenes.register("empty", "Nothing but sky", empty);
+/// assert_eq!(scenes.get("empty").unwrap().description, "Nothing but sky");
+/// assert!(scenes.get("missing").is_none());
+/// assert_eq!(scenes.suggest("emtpy"), Some("empty"));
+/// ```
+#[derive(Clone, Default)]
+pub struct SceneRegistry {
+    scenes: BTreeMap<&'static str, SceneEntry>,
+}
+
+impl SceneRegistry {
+    pub fn new() -> SceneRegistry {
+        SceneRegistry { scenes: BTreeMap::new() }
+    }
+
+    /// Add a scene, replacing any scene registered under the same name.
+    pub fn register(&mut self, name: &'static str, description: &'static str, build: fn(&Loader) -> Scene) {
+        self.scenes.insert(name, SceneEntry { description, build });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SceneEntry> {
+        self.scenes.get(name)
+    }
+
+    /// All scenes, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &SceneEntry)> {
+        self.scenes.iter().map(|(&name, entry)| (name, entry))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.scenes.keys().cloned()
+    }
+
+    /// The registered name closest to a mistyped one, if any is close enough to be what was meant.
+    /// Case is ignored, and up to a third of the letters, rounded up, may be wrong, missing or extra.
+    pub fn suggest(&self, name: &str) -> Option<&'static str> {
+        let name = name.to_lowercase();
+        self.names()
+            .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+            .filter(|&(distance, _)| distance <= (name.chars().count() + 2)/3)
+            .min_by_key(|&(distance, _)| distance)
+            .map(|(_, candidate)| candidate)
+    }
+}
+
+/// The number of letters to insert, delete or replace to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // Distances from the start of `a` processed so far to every prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let replaced = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use hitable::instance::with_id;
+    use hitable::sphere::Sphere;
+    use material::Lambertian;
+    use texture::Texture;
+    use color::HasReflectance;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_loader_files() {
+        let folder = tempdir().unwrap();
+        let path = |name: &str| folder.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("singlet.lens"), "50 5 bk7 20\n-50 45 1 20").unwrap();
+        fs::write(path("camera.csv"), "400, 0.0, 0.2, 1.0\n700, 1.0, 0.2, 0.0").unwrap();
+        fs::write(path("paint.csv"), "400, 0.2\n600, 0.6").unwrap();
+        let loader = Loader::silent();
+        assert_eq!(loader.lens(&path("singlet.lens")).unwrap().surfaces.len(), 2);
+        assert!(loader.sensor(&path("camera.csv")).is_ok());
+        assert!((loader.spectrum(&path("paint.csv")).unwrap().reflect(500.0) - 0.4).abs() < 0.02);
+        // The error names the file that couldn't be read
+        assert!(loader.spectrum(&path("missing.csv")).unwrap_err().to_string().starts_with(&path("missing.csv")));
+        let files: Vec<String> = loader.files().iter().map(|file| file.to_str().unwrap().to_string()).collect();
+        assert_eq!(files, vec![path("singlet.lens"), path("camera.csv"), path("paint.csv"), path("missing.csv")]);
+    }
+
+    #[test]
+    fn test_pick() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let scene = Scene {
+            objects: vec![
+                Arc::new(Sphere::new(point3(0.0, 0.0, -10.0), 1.0, texture.clone())),
+                Arc::new(with_id(Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture), 42)),
+            ],
+            look_from: point3(0.0, 0.0, 5.0),
+            look_at: point3(0.0, 0.0, 0.0),
+            focus_dist: 5.0,
+            aperture: 0.5,
+            vfov: 40.0,
+            movements: Movements::default(),
+            render_sky: true,
+            sky: Sky::Gradient,
+            lights: Vec::new(),
+            animation: None,
+            flare: None,
+            settings: SettingsOverrides::default(),
+        };
+        let pick = scene.pick(50, 50, 101, 101).unwrap();
+        assert_eq!(pick.object_index, 1);
+        assert_eq!(pick.object_id, Some(42));
+        assert!((pick.p - point3(0.0, 0.0, 1.0)).length() < 1e-4);
+        assert!((pick.normal - vec3(0.0, 0.0, 1.0)).length() < 1e-4);
+        assert_eq!(scene.pick(0, 0, 101, 101), None);
+    }
+
+    #[test]
+    fn test_auto_frame() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let mut scene = Scene {
+            objects: vec![
+                Arc::new(Sphere::new(point3(10.0, 0.0, -3.0), 1.0, texture.clone())),
+                Arc::new(Sphere::new(point3(12.0, 0.0, -3.0), 1.0, texture)),
+            ],
+            look_from: point3(0.0, 0.0, 0.0),
+            look_at: point3(0.0, 0.0, -1.0),
+            focus_dist: 1.0,
+            aperture: 0.0,
+            vfov: 40.0,
+            movements: Movements::default(),
+            render_sky: true,
+            sky: Sky::Gradient,
+            lights: Vec::new(),
+            animation: None,
+            flare: None,
+            settings: SettingsOverrides::default(),
+        };
+        let center = point3(11.0, 0.0, -3.0);
+        scene.auto_frame(2.0);
+        assert_eq!(scene.look_from, point3(0.0, 0.0, 0.0));
+        assert!((scene.look_at - center).length() < 1e-5);
+        assert!((scene.focus_dist - center.to_vector().length()).abs() < 1e-5);
+        // Both spheres are in view, with nothing at the edges
+        assert_eq!(scene.pick(100, 50, 201, 101).map(|pick| pick.object_index), Some(0));
+        assert!(scene.pick(0, 50, 201, 101).is_none());
+        assert!(scene.pick(100, 0, 201, 101).is_none());
+        assert!(scene.pick(100, 100, 201, 101).is_none());
+
+        // From within the scene the camera backs off
+        scene.look_from = center;
+        scene.auto_frame(2.0);
+        assert!(scene.look_from.z > -1.0 && (scene.look_from.x - 11.0).abs() < 1e-5, "{:?}", scene.look_from);
+        assert!((scene.vfov - MAX_AUTO_VFOV).abs() < 1e-3, "{}", scene.vfov);
+    }
+
+    #[test]
+    fn test_builder() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let builder = SceneBuilder::new()
+            .add_sphere(point3(0.0, 0.0, 0.0), 1.0, texture.clone())
+            .camera(point3(0.0, 0.0, 5.0), point3(0.0, 0.0, 0.0), 40.0)
+            .aperture(0.5)
+            .no_environment()
+            .settings(SettingsOverrides { width: Some(300), height: Some(100), ..Default::default() });
+        assert!(builder.warnings().is_empty());
+        let (world, _, settings) = builder.build().unwrap();
+        assert_eq!(world.items().len(), 1);
+        assert_eq!((settings.width, settings.height, settings.samples), (300, 100, 100));
+        let scene = builder.scene();
+        assert_eq!((scene.focus_dist, scene.aperture, scene.render_sky), (5.0, 0.5, false));
+
+        assert_eq!(SceneBuilder::new().warnings(), vec!["the scene is empty".to_string()]);
+        let lost = SceneBuilder::new().add_sphere(point3(f32::NAN, 0.0, 0.0), 1.0, texture.clone());
+        assert_eq!(lost.warnings().len(), 1);
+        assert!(lost.build().is_ok());
+        let blind = SceneBuilder::new().add_sphere(point3(0.0, 0.0, 0.0), 1.0, texture);
+        assert!(blind.camera(point3(0.0, 0.0, 1.0), point3(0.0, 0.0, 1.0), 40.0).build().is_err());
+        let tiny = SceneBuilder::new().settings(SettingsOverrides { width: Some(0), ..Default::default() });
+        assert!(tiny.build().is_err());
+        let still = SceneBuilder::new().animation(CameraPath::Keyframes(Vec::new()));
+        assert_eq!(still.build().err(), Some("the camera path has no keyframes".to_string()));
+    }
+
+    #[test]
+    fn test_suggest() {
+        fn empty(_: &Loader) -> Scene {
+            Scene {
+                objects: Vec::new(),
+                look_from: point3(0.0, 0.0, -1.0),
+                look_at: point3(0.0, 0.0, 0.0),
+                focus_dist: 1.0,
+                aperture: 0.0,
+                vfov: 40.0,
+                movements: Movements::default(),
+                render_sky: true,
+                sky: Sky::Gradient,
+                lights: Vec::new(),
+                animation: None,
+                flare: None,
+                settings: SettingsOverrides::default(),
+            }
+        }
+        let mut scenes = SceneRegistry::new();
+        for &name in ["cornell", "cornell_glass", "bunny", "worn_bunny"].iter() {
+            scenes.register(name, "", empty);
+        }
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "bunny"), 5);
+        assert_eq!(scenes.suggest("cornel"), Some("cornell"));
+        assert_eq!(scenes.suggest("Cornell_Glas"), Some("cornell_glass"));
+        assert_eq!(scenes.suggest("bunyn"), Some("bunny"));
+        assert_eq!(scenes.suggest("teapot"), None);
+    }
+
+    #[test]
+    fn test_materials_are_baked_for_previews() {
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)));
+        let materials = Loader::silent().materials(vec![("clay", texture.clone())]);
+        assert!(Arc::ptr_eq(&materials[0], &texture));
+        let steps = AtomicUsize::new(0);
+        let loader = Loader::new(|_| { steps.fetch_add(1, Ordering::SeqCst); }).preview(true);
+        let baked = loader.materials(vec![("clay", texture.clone()), ("more clay", texture)]);
+        drop(loader);
+        assert_eq!(steps.into_inner(), 2);
+        assert!(format!("{:?}", baked[1]).starts_with("ResponseTable"));
+    }
+}
@@ -0,0 +1,263 @@
+//! How a scene is rendered: the size of the image, the samples taken and the limits of the light paths.
+//!
+//! Renders start from `RenderSettings::default()`. A scene can change some of them with the `SettingsOverrides` it
+//! comes with, and the command line has the last word with its own, see `cli::settings_args`.
+
+use color::{WavelengthSampler, WavelengthSampling};
+use film::Filter;
+use hitable::{ShadingRate, TMin};
+use tiles::TileOrder;
+
+/// Everything about a render apart from the scene, the camera and the output.
+///
+/// ```
+/// # extern crate rayer;
+/// # use rayer::settings::*;
+/// let settings = RenderSettings::default().with_size(400, 300).with_samples(16).with_max_depth(8);
+/// assert_eq!((settings.width, settings.height, settings.samples), (400, 300, 16));
+/// assert_eq!(settings.shading_rate().max_depth, 8);
+/// ```
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct RenderSettings {
+    /// Size of the image in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// Passes over every pixel.
+    pub samples: u64,
+    /// Shortest and longest wavelength traced, in nanometers.
+    pub wavelength_range: (f32, f32),
+    /// How the wavelengths are drawn from that range.
+    pub wavelength_sampling: WavelengthSampling,
+    /// Bounces of paths hitting objects without a shading rate of their own, see `ShadingRate`.
+    pub max_depth: u32,
+    /// Throughput below which those paths are subject to russian roulette, 0 to never end them early.
+    pub roulette_threshold: f32,
+    /// Scales the distance rays ignore hits within on top of starting off the surface they leave, see `TMin`.
+    /// Raise it where surfaces shadow themselves in speckles, lower it where light leaks through thin gaps.
+    pub epsilon_scale: f32,
+    pub filter: Filter,
+    pub tile_order: TileOrder,
+    /// Width and height of the tiles in pixels.
+    pub tile_size: u32,
+    /// Stop at the first radiance that isn't a finite number, instead of dropping it, see `render::Watchdog`.
+    pub strict_nan: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        let rate = ShadingRate::default();
+        RenderSettings {
+            width: 800,
+            height: 600,
+            samples: 100,
+            wavelength_range: (390.0, 700.0),
+            wavelength_sampling: WavelengthSampling::default(),
+            max_depth: rate.max_depth,
+            roulette_threshold: rate.roulette_threshold,
+            epsilon_scale: 1.0,
+            filter: Filter::default(),
+            tile_order: TileOrder::default(),
+            tile_size: 32,
+            strict_nan: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn with_size(self, width: u32, height: u32) -> RenderSettings {
+        RenderSettings { width, height, ..self }
+    }
+
+    pub fn with_samples(self, samples: u64) -> RenderSettings {
+        RenderSettings { samples, ..self }
+    }
+
+    pub fn with_wavelength_range(self, low: f32, high: f32) -> RenderSettings {
+        RenderSettings { wavelength_range: (low, high), ..self }
+    }
+
+    pub fn with_wavelength_sampling(self, wavelength_sampling: WavelengthSampling) -> RenderSettings {
+        RenderSettings { wavelength_sampling, ..self }
+    }
+
+    pub fn with_max_depth(self, max_depth: u32) -> RenderSettings {
+        RenderSettings { max_depth, ..self }
+    }
+
+    pub fn with_roulette_threshold(self, roulette_threshold: f32) -> RenderSettings {
+        RenderSettings { roulette_threshold, ..self }
+    }
+
+    pub fn with_epsilon_scale(self, epsilon_scale: f32) -> RenderSettings {
+        RenderSettings { epsilon_scale, ..self }
+    }
+
+    pub fn with_filter(self, filter: Filter) -> RenderSettings {
+        RenderSettings { filter, ..self }
+    }
+
+    pub fn with_tiles(self, tile_order: TileOrder, tile_size: u32) -> RenderSettings {
+        RenderSettings { tile_order, tile_size, ..self }
+    }
+
+    pub fn with_strict_nan(self, strict_nan: bool) -> RenderSettings {
+        RenderSettings { strict_nan, ..self }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width as f32/self.height as f32
+    }
+
+    /// Draws the wavelength of every path.
+    pub fn wavelength_sampler(&self) -> WavelengthSampler {
+        let (low, high) = self.wavelength_range;
+        self.wavelength_sampling.sampler(low, high)
+    }
+
+    /// The limits of paths hitting objects that don't set their own.
+    pub fn shading_rate(&self) -> ShadingRate {
+        ShadingRate { max_depth: self.max_depth, roulette_threshold: self.roulette_threshold }
+    }
+
+    /// Where rays start finding hits.
+    pub fn t_min(&self) -> TMin {
+        TMin::default().scaled(self.epsilon_scale)
+    }
+
+    /// Why these settings can't be rendered with, if they can't.
+    pub fn check(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!("the image can't be {}x{} pixels", self.width, self.height));
+        }
+        let (low, high) = self.wavelength_range;
+        if !(low > 0.0 && low < high) {
+            return Err(format!("the wavelength range {}-{} nm is empty", low, high));
+        }
+        if self.wavelength_sampling == WavelengthSampling::Luminance && !(high > 360.0 && low < 830.0) {
+            return Err(format!("the eye doesn't see any light between {} and {} nm to sample by", low, high));
+        }
+        if !(self.epsilon_scale >= 0.0) {
+            return Err(format!("the epsilon scale {} is below 0", self.epsilon_scale));
+        }
+        if !(self.filter.radius() > 0.0) {
+            return Err("the filter radius has to be above 0".to_string());
+        }
+        if self.tile_size == 0 {
+            return Err("the tile size has to be above 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Changes to `RenderSettings`, from a scene or the command line. What isn't set stays as it was.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct SettingsOverrides {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u64>,
+    pub wavelength_range: Option<(f32, f32)>,
+    pub wavelength_sampling: Option<WavelengthSampling>,
+    pub max_depth: Option<u32>,
+    pub roulette_threshold: Option<f32>,
+    pub epsilon_scale: Option<f32>,
+    pub filter: Option<Filter>,
+    /// The radius of the filter, whichever it is.
+    pub filter_radius: Option<f32>,
+    pub tile_order: Option<TileOrder>,
+    pub tile_size: Option<u32>,
+    pub strict_nan: Option<bool>,
+}
+
+impl SettingsOverrides {
+    /// Change `settings`. A width or height without the other keeps the aspect ratio of `settings`.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # use rayer::settings::*;
+    /// let scene = SettingsOverrides { width: Some(500), height: Some(500), max_depth: Some(5), ..Default::default() };
+    /// let command_line = SettingsOverrides { width: Some(200), samples: Some(4), ..Default::default() };
+    /// let settings = command_line.apply(scene.apply(RenderSettings::default()));
+    /// assert_eq!((settings.width, settings.height, settings.samples, settings.max_depth), (200, 200, 4, 5));
+    /// ```
+    pub fn apply(&self, settings: RenderSettings) -> RenderSettings {
+        let (width, height) = match (self.width, self.height) {
+            (None, None) => (settings.width, settings.height),
+            (Some(width), None) => (width, (width as u64*settings.height as u64/settings.width.max(1) as u64) as u32),
+            (None, Some(height)) => ((height as u64*settings.width as u64/settings.height.max(1) as u64) as u32, height),
+            (Some(width), Some(height)) => (width, height),
+        };
+        let filter = self.filter.unwrap_or(settings.filter);
+        RenderSettings {
+            width,
+            height,
+            samples: self.samples.unwrap_or(settings.samples),
+            wavelength_range: self.wavelength_range.unwrap_or(settings.wavelength_range),
+            wavelength_sampling: self.wavelength_sampling.unwrap_or(settings.wavelength_sampling),
+            max_depth: self.max_depth.unwrap_or(settings.max_depth),
+            roulette_threshold: self.roulette_threshold.unwrap_or(settings.roulette_threshold),
+            epsilon_scale: self.epsilon_scale.unwrap_or(settings.epsilon_scale),
+            filter: self.filter_radius.map_or(filter, |radius| filter.with_radius(radius)),
+            tile_order: self.tile_order.unwrap_or(settings.tile_order),
+            tile_size: self.tile_size.unwrap_or(settings.tile_size),
+            strict_nan: self.strict_nan.unwrap_or(settings.strict_nan),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use euclid::*;
+    use ray::Ray;
+
+    #[test]
+    fn test_overrides() {
+        let settings = RenderSettings::default().with_size(300, 200);
+        let unchanged = SettingsOverrides::default().apply(settings);
+        assert_eq!(unchanged, settings);
+        assert_eq!(SettingsOverrides { height: Some(100), ..Default::default() }.apply(settings).width, 150);
+
+        let overrides = SettingsOverrides {
+            filter: Some(Filter::Tent { radius: 1.0 }),
+            filter_radius: Some(2.5),
+            roulette_threshold: Some(0.1),
+            ..Default::default()
+        };
+        let changed = overrides.apply(settings);
+        assert_eq!(changed.filter, Filter::Tent { radius: 2.5 });
+        assert_eq!(changed.shading_rate(), ShadingRate { max_depth: 50, roulette_threshold: 0.1 });
+        // The radius applies to the filter the settings already had too
+        assert_eq!(SettingsOverrides { filter_radius: Some(1.5), ..Default::default() }.apply(settings).filter, Filter::Box { radius: 1.5 });
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(RenderSettings::default().check(), Ok(()));
+        assert!(RenderSettings::default().with_size(0, 10).check().is_err());
+        assert!(RenderSettings::default().with_wavelength_range(700.0, 390.0).check().is_err());
+        let infrared = RenderSettings::default().with_wavelength_range(850.0, 1000.0);
+        assert_eq!(infrared.check(), Ok(()));
+        assert!(infrared.with_wavelength_sampling(WavelengthSampling::Luminance).check().is_err());
+        assert!(RenderSettings::default().with_tiles(TileOrder::Spiral, 0).check().is_err());
+        assert!(RenderSettings::default().with_filter(Filter::Gaussian { radius: 0.0, alpha: 2.0 }).check().is_err());
+    }
+
+    #[test]
+    fn test_wavelength_sampler() {
+        let settings = RenderSettings::default().with_wavelength_range(380.0, 780.0).with_wavelength_sampling(WavelengthSampling::Luminance);
+        let sampler = settings.wavelength_sampler();
+        assert_eq!(sampler.range(), (380.0, 780.0));
+        assert!(sampler.pdf(555.0) > 10.0*sampler.pdf(420.0));
+    }
+
+    #[test]
+    fn test_t_min() {
+        let r = Ray::new(point3(0.0, 0.0, -200.0), vec3(0.0, 0.0, 1.0), 550.0, 0.0);
+        let t_min = RenderSettings::default().t_min().t_min(r);
+        assert!(t_min > 0.0);
+        assert_eq!(RenderSettings::default().with_epsilon_scale(4.0).t_min().t_min(r), 4.0*t_min);
+        assert_eq!(RenderSettings::default().with_epsilon_scale(0.0).t_min().t_min(r), 0.0);
+    }
+}
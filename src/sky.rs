@@ -0,0 +1,334 @@
+//! The light arriving along rays that leave the scene.
+//!
+//! Besides the plain gradient, the sky can be clear daylight from the analytic model of
+//! [Preetham, Shirley and Smits, A Practical Analytic Model for Daylight](https://doi.org/10.1145/311535.311545),
+//! evaluated at the wavelength of every ray through the CIE daylight basis, with a sun disc that paths aim at
+//! from diffuse surfaces. Hosek and Wilkie's model fits the sky better near the horizon, but needs their tables
+//! of fitted coefficients, which this doesn't ship.
+use std::f32::consts::PI;
+use euclid::*;
+use palette::Rgb;
+use palette::white_point::E;
+
+use color::{HasReflectance, xyz_from_wavelength};
+use ray::Ray;
+use sampler::sample_cone;
+
+/// Luminance, in kcd/m², that comes out as 1. White paper in the midday sun is about this bright.
+const LUMINANCE_UNIT: f32 = 25.0;
+/// Luminance of the sun outside the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f32 = 1.88e6;
+/// Temperature of the sun as a black body, in K.
+const SUN_TEMPERATURE: f32 = 5778.0;
+/// Angular radius of the sun disc, in radians.
+const SUN_RADIUS: f32 = 0.00465;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum Sky {
+    /// White at the horizon to light blue overhead, as in Ray Tracing in One Weekend.
+    Gradient,
+    /// A clear sky with the sun in the direction `sun`, with y pointing up. `turbidity` is how hazy the air is,
+    /// from 2 for a very clear day to 10 for a hazy one. Below the horizon the sky continues as it is at the horizon.
+    Daylight { sun: Vector3D<f32, UnknownUnit>, turbidity: f32 },
+    /// The same light from every direction at every wavelength. A scene that neither emits nor absorbs light looks
+    /// as bright as this everywhere, which furnace tests check materials with.
+    Uniform { radiance: f32 },
+}
+
+impl Default for Sky {
+    fn default() -> Sky {
+        Sky::Gradient
+    }
+}
+
+impl Sky {
+    /// Daylight with the sun `elevation` degrees above the horizon, `azimuth` degrees from the negative z axis
+    /// towards positive x.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # use rayer::sky::Sky;
+    /// let sky = Sky::daylight(90.0, 0.0, 3.0);
+    /// let sun = sky.sample_sun(0.5, 0.5, 550.0).unwrap();
+    /// assert!(sun.0.y > 0.99);
+    /// ```
+    pub fn daylight(elevation: f32, azimuth: f32, turbidity: f32) -> Sky {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let sun = vec3(elevation.cos()*azimuth.sin(), elevation.sin(), -elevation.cos()*azimuth.cos());
+        Sky::Daylight { sun, turbidity }
+    }
+
+    /// The light arriving along `r` from the sky. Paths that already aimed at the sun with `sample_sun` leave it out
+    /// with `include_sun: false`, so it isn't counted twice.
+    pub fn radiance(&self, r: Ray, include_sun: bool) -> f32 {
+        let direction = r.direction.normalize();
+        match *self {
+            Sky::Gradient => {
+                let t: f32 = (direction.y + 1.0)*0.5;
+                let rgb = Rgb::<E, f32>::with_wp(1.0, 1.0, 1.0)*(1.0-t) + Rgb::with_wp(0.5, 0.7, 1.0)*t;
+                rgb.reflect(r.wl)
+            },
+            Sky::Daylight { sun, turbidity } => {
+                let sun = sun.normalize();
+                let mut res = sky_radiance(direction, sun, turbidity, r.wl);
+                if include_sun && direction.dot(sun) > SUN_RADIUS.cos() {
+                    res += sun_radiance(sun, turbidity, r.wl);
+                }
+                res
+            },
+            Sky::Uniform { radiance } => radiance,
+        }
+    }
+
+    /// Whether there is a sun above the horizon for `sample_sun` to aim at.
+    pub fn has_sun(&self) -> bool {
+        match *self {
+            Sky::Gradient | Sky::Uniform { .. } => false,
+            Sky::Daylight { sun, .. } => sun.y > 0.0,
+        }
+    }
+
+    /// A direction to a point of the sun disc for the random numbers `u` and `v` in [0,1), and the light of the sun
+    /// at the wavelength `wl` along it times the solid angle of the disc, which is what a surface receives from it
+    /// divided by the cosine. `None` without a sun, or with the sun below the horizon.
+    pub fn sample_sun(&self, u: f32, v: f32, wl: f32) -> Option<(Vector3D<f32, UnknownUnit>, f32)> {
+        let (sun, turbidity) = match *self {
+            Sky::Gradient | Sky::Uniform { .. } => return None,
+            Sky::Daylight { sun, turbidity } => (sun.normalize(), turbidity),
+        };
+        if sun.y <= 0.0 {
+            return None;
+        }
+        let cos_max = SUN_RADIUS.cos();
+        let solid_angle = 2.0*PI*(1.0 - cos_max);
+        let direction = sample_cone(vec2(u, v), sun, cos_max);
+        Some((direction, sun_radiance(sun, turbidity, wl)*solid_angle))
+    }
+}
+
+/// Perez et al.'s distribution of light over the sky, for a view `theta` from the zenith and `gamma` from the sun.
+fn perez([a, b, c, d, e]: [f32; 5], theta: f32, gamma: f32) -> f32 {
+    (1.0 + a*(b/theta.cos().max(1e-3)).exp())*(1.0 + c*(d*gamma).exp() + e*gamma.cos()*gamma.cos())
+}
+
+/// Luminance in kcd/m² and CIE xy chromaticity of the sky in `direction`.
+fn sky_xyy(direction: Vector3D<f32, UnknownUnit>, sun: Vector3D<f32, UnknownUnit>, turbidity: f32) -> (f32, f32, f32) {
+    let t = turbidity.max(1.7).min(10.0);
+    let theta_sun = sun.y.max(0.0).min(1.0).acos();
+    let theta = direction.y.max(0.0).min(1.0).acos();
+    let gamma = direction.dot(sun).max(-1.0).min(1.0).acos();
+
+    let chi = (4.0/9.0 - t/120.0)*(PI - 2.0*theta_sun);
+    let zenith_luminance = ((4.0453*t - 4.9710)*chi.tan() - 0.2155*t + 2.4192).max(0.0);
+    let zenith_chromaticity = |m: [[f32; 4]; 3]| {
+        let angles = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+        let row = |r: [f32; 4]| r.iter().zip(&angles).map(|(c, a)| c*a).sum::<f32>();
+        t*t*row(m[0]) + t*row(m[1]) + row(m[2])
+    };
+    let zenith_x = zenith_chromaticity([
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    ]);
+    let zenith_y = zenith_chromaticity([
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    ]);
+
+    let luminance = [0.1787*t - 1.4630, -0.3554*t + 0.4275, -0.0227*t + 5.3251, 0.1206*t - 2.5771, -0.0670*t + 0.3703];
+    let x = [-0.0193*t - 0.2592, -0.0665*t + 0.0008, -0.0004*t + 0.2125, -0.0641*t - 0.8989, -0.0033*t + 0.0452];
+    let y = [-0.0167*t - 0.2608, -0.0950*t + 0.0092, -0.0079*t + 0.2102, -0.0441*t - 1.6537, -0.0109*t + 0.0529];
+    let relative = |coefficients| perez(coefficients, theta, gamma)/perez(coefficients, 0.0, theta_sun);
+    (zenith_luminance*relative(luminance), zenith_x*relative(x), zenith_y*relative(y))
+}
+
+fn sky_radiance(direction: Vector3D<f32, UnknownUnit>, sun: Vector3D<f32, UnknownUnit>, turbidity: f32, wl: f32) -> f32 {
+    let (luminance, x, y) = sky_xyy(direction, sun, turbidity);
+    luminance/LUMINANCE_UNIT*daylight(x, y, wl)
+}
+
+/// The light of the sun after passing through the atmosphere towards the ground, from the scattering by air and
+/// haze Preetham et al. model. Absorption by ozone and water vapour is left out.
+fn sun_radiance(sun: Vector3D<f32, UnknownUnit>, turbidity: f32, wl: f32) -> f32 {
+    if sun.y <= 0.0 {
+        return 0.0;
+    }
+    let theta = sun.y.min(1.0).acos();
+    let air_mass = 1.0/(theta.cos() + 0.15*(93.885 - theta.to_degrees()).powf(-1.253));
+    let um = wl*1e-3;
+    let rayleigh = 0.008735*um.powf(-4.08);
+    let haze = (0.04608*turbidity - 0.04586)*um.powf(-1.3);
+    let transmittance = (-air_mass*(rayleigh + haze)).exp();
+    SUN_LUMINANCE/LUMINANCE_UNIT*planck(wl)/SPECTRA.luminance*transmittance
+}
+
+/// Spectral radiance of a black body as hot as the sun, up to a constant factor.
+fn planck(wl: f32) -> f32 {
+    // hc/k in nm K
+    const C2: f32 = 1.4388e7;
+    let wl = wl/500.0;
+    1.0/(wl.powi(5)*((C2/500.0/(wl*SUN_TEMPERATURE)).exp() - 1.0))
+}
+
+/// The CIE daylight spectrum of chromaticity `x`, `y` at the wavelength `wl`, scaled to a luminance of 1.
+pub(crate) fn daylight(x: f32, y: f32, wl: f32) -> f32 {
+    let denominator = 0.0241 + 0.2562*x - 0.7341*y;
+    let m1 = (-1.3515 - 1.7703*x + 5.9114*y)/denominator;
+    let m2 = (0.0300 - 31.4424*x + 30.0717*y)/denominator;
+    let [s0, s1, s2] = daylight_basis(wl);
+    let [l0, l1, l2] = SPECTRA.basis_luminance;
+    (s0 + m1*s1 + m2*s2)/(l0 + m1*l1 + m2*l2)
+}
+
+/// The components S0, S1 and S2 of the CIE daylight spectra at `wl`, interpolated between the 10 nm table.
+fn daylight_basis(wl: f32) -> [f32; 3] {
+    let x = ((wl - 360.0)/10.0).max(0.0).min((DAYLIGHT_BASIS.len() - 1) as f32);
+    let i = (x as usize).min(DAYLIGHT_BASIS.len() - 2);
+    let f = x - i as f32;
+    let (a, b) = (DAYLIGHT_BASIS[i], DAYLIGHT_BASIS[i+1]);
+    [a[0] + (b[0] - a[0])*f, a[1] + (b[1] - a[1])*f, a[2] + (b[2] - a[2])*f]
+}
+
+/// Luminances to scale the spectra by, the mean of each weighted by the luminous efficiency.
+struct Luminances {
+    basis_luminance: [f32; 3],
+    luminance: f32,
+}
+
+lazy_static! {
+    static ref SPECTRA: Luminances = {
+        let wavelengths = || (0..95).map(|i| 360.0 + 5.0*i as f32);
+        let weight: f32 = wavelengths().map(|wl| xyz_from_wavelength(wl).y).sum();
+        let mean = |f: &dyn Fn(f32) -> f32| wavelengths().map(|wl| f(wl)*xyz_from_wavelength(wl).y).sum::<f32>()/weight;
+        Luminances {
+            basis_luminance: [
+                mean(&|wl| daylight_basis(wl)[0]),
+                mean(&|wl| daylight_basis(wl)[1]),
+                mean(&|wl| daylight_basis(wl)[2]),
+            ],
+            luminance: mean(&planck),
+        }
+    };
+}
+
+/// S0, S1 and S2 from 360 nm to 780 nm in steps of 10 nm.
+static DAYLIGHT_BASIS: [[f32; 3]; 43] = [
+    [61.5, 38.0, 5.3],
+    [68.8, 42.4, 6.1],
+    [63.4, 38.5, 3.0],
+    [65.8, 35.0, 1.2],
+    [94.8, 43.4, -1.1],
+    [104.8, 46.3, -0.5],
+    [105.9, 43.9, -0.7],
+    [96.8, 37.1, -1.2],
+    [113.9, 36.7, -2.6],
+    [125.6, 35.9, -2.9],
+    [125.5, 32.6, -2.8],
+    [121.3, 27.9, -2.6],
+    [121.3, 24.3, -2.6],
+    [113.5, 20.1, -1.8],
+    [113.1, 16.2, -1.5],
+    [110.8, 13.2, -1.3],
+    [106.5, 8.6, -1.2],
+    [108.8, 6.1, -1.0],
+    [105.3, 4.2, -0.5],
+    [104.4, 1.9, -0.3],
+    [100.0, 0.0, 0.0],
+    [96.0, -1.6, 0.2],
+    [95.1, -3.5, 0.5],
+    [89.1, -3.5, 2.1],
+    [90.5, -5.8, 3.2],
+    [90.3, -7.2, 4.1],
+    [88.4, -8.6, 4.7],
+    [84.0, -9.5, 5.1],
+    [85.1, -10.9, 6.7],
+    [81.9, -10.7, 7.3],
+    [82.6, -12.0, 8.6],
+    [84.9, -14.0, 9.8],
+    [81.3, -13.6, 10.2],
+    [71.9, -12.0, 8.3],
+    [74.3, -13.3, 9.6],
+    [76.4, -12.9, 8.5],
+    [63.3, -10.6, 7.0],
+    [71.7, -11.6, 7.6],
+    [77.0, -12.2, 8.0],
+    [65.2, -10.2, 6.7],
+    [47.7, -7.8, 5.2],
+    [68.6, -11.2, 7.4],
+    [65.0, -10.4, 6.8],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn luminance(f: &dyn Fn(f32) -> f32) -> f32 {
+        let wavelengths = || (0..95).map(|i| 360.0 + 5.0*i as f32);
+        wavelengths().map(|wl| f(wl)*xyz_from_wavelength(wl).y).sum::<f32>()/wavelengths().map(|wl| xyz_from_wavelength(wl).y).sum::<f32>()
+    }
+
+    #[test]
+    fn test_daylight_spectrum() {
+        // D65 is bluer than D50, and both come out with the luminance they are scaled to
+        let d65 = |wl| daylight(0.3127, 0.3290, wl);
+        let d50 = |wl| daylight(0.3457, 0.3585, wl);
+        assert!((luminance(&d65) - 1.0).abs() < 1e-3);
+        assert!((luminance(&d50) - 1.0).abs() < 1e-3);
+        assert!(d65(450.0)/d65(650.0) > d50(450.0)/d50(650.0));
+        assert!((daylight_basis(560.0)[0] - 100.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_daylight_sky() {
+        let sky = Sky::daylight(30.0, 90.0, 3.0);
+        let ray = |direction: Vector3D<f32, UnknownUnit>, wl| Ray::new(point3(0.0, 0.0, 0.0), direction, wl, 0.0);
+        let sun = match sky { Sky::Daylight { sun, .. } => sun, _ => unreachable!() };
+        assert!(sun.x > 0.8 && (sun.y - 0.5).abs() < 1e-4);
+
+        // Blue overhead, and brighter towards the sun than away from it
+        let zenith = vec3(0.0, 1.0, 0.0);
+        assert!(sky.radiance(ray(zenith, 450.0), true) > sky.radiance(ray(zenith, 650.0), true));
+        let towards = vec3(1.0, 0.3, 0.0);
+        let away = vec3(-1.0, 0.3, 0.0);
+        assert!(sky.radiance(ray(towards, 550.0), true) > sky.radiance(ray(away, 550.0), true));
+
+        // The sun outshines the sky, and is only left out when asked to
+        let with_sun = sky.radiance(ray(sun, 550.0), true);
+        let without_sun = sky.radiance(ray(sun, 550.0), false);
+        assert!(with_sun > 1000.0*without_sun, "{} {}", with_sun, without_sun);
+        assert!(without_sun > 0.0);
+
+        let (direction, light) = sky.sample_sun(0.3, 0.7, 550.0).unwrap();
+        assert!(direction.dot(sun) > SUN_RADIUS.cos() - 1e-6 && (direction.length() - 1.0).abs() < 1e-5);
+        let solid_angle = 2.0*PI*(1.0 - SUN_RADIUS.cos());
+        assert!((light/solid_angle/sun_radiance(sun, 3.0, 550.0) - 1.0).abs() < 1e-3);
+        // Redder when lower and hazier
+        let low = Sky::daylight(5.0, 0.0, 6.0).sample_sun(0.5, 0.5, 450.0).unwrap().1/Sky::daylight(5.0, 0.0, 6.0).sample_sun(0.5, 0.5, 650.0).unwrap().1;
+        assert!(low < sky.sample_sun(0.5, 0.5, 450.0).unwrap().1/sky.sample_sun(0.5, 0.5, 650.0).unwrap().1);
+
+        assert_eq!(Sky::daylight(-10.0, 0.0, 3.0).sample_sun(0.5, 0.5, 550.0), None);
+        assert!(sky.has_sun() && !Sky::daylight(-10.0, 0.0, 3.0).has_sun() && !Sky::Gradient.has_sun());
+        assert_eq!(Sky::Gradient.sample_sun(0.5, 0.5, 550.0), None);
+    }
+
+    #[test]
+    fn test_white_in_sun() {
+        // A white surface in the midday sun and sky comes out around 1
+        let sky = Sky::daylight(60.0, 0.0, 3.0);
+        let (_, sun) = sky.sample_sun(0.5, 0.5, 550.0).unwrap();
+        let direct = sun*60f32.to_radians().sin()/PI;
+        assert!(direct > 0.5 && direct < 2.0, "{}", direct);
+    }
+
+    #[test]
+    fn test_uniform_sky() {
+        let sky = Sky::Uniform { radiance: 2.0 };
+        for &(direction, wl) in &[(vec3(0.0, 1.0, 0.0), 450.0), (vec3(1.0, -3.0, 0.5), 650.0)] {
+            assert_eq!(sky.radiance(Ray::new(point3(0.0, 0.0, 0.0), direction, wl, 0.0), true), 2.0);
+        }
+        assert!(!sky.has_sun());
+        assert_eq!(sky.sample_sun(0.5, 0.5, 550.0), None);
+    }
+}
@@ -0,0 +1,446 @@
+//! Stochastic progressive photon mapping, which finds the caustics paths from the camera rarely do,
+//! like light focused by glass onto a diffuse floor.
+//!
+//! Every iteration traces photons from the lights into a `PhotonMap`,
+//! then each pixel follows a camera path through specular surfaces to the first diffuse one
+//! and gathers the photons within its radius there. The radius shrinks over the iterations,
+//! so the estimate converges (Hachisuka and Jensen, "Stochastic Progressive Photon Mapping", 2009).
+//! Diffuse surfaces, those whose material `is_diffuse`, are treated as Lambertian.
+//! Light from the sky isn't traced as photons, only seen directly.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use euclid::*;
+use palette::Xyz;
+use palette::white_point::E;
+use rayon::prelude::*;
+
+use color::{Sensor, WavelengthSampler};
+use delta_light::LightSample;
+use hitable::*;
+use material::Material;
+use random::*;
+use ray::{offset_origin, Ray, RayKind};
+use sampler::sample_disk;
+use settings::RenderSettings;
+
+/// An object that emits light, to send photons from.
+#[derive(Clone)]
+pub struct Light {
+    object: Arc<dyn Hitable>,
+    /// Emitted power averaged over the visible wavelengths, to pick lights by.
+    power: f32,
+}
+
+impl Light {
+    /// Emitted power averaged over the visible wavelengths.
+    pub fn power(&self) -> f32 {
+        self.power
+    }
+
+    /// The emitting object.
+    pub fn object(&self) -> &Arc<dyn Hitable> {
+        &self.object
+    }
+
+    /// The light reaching `p` of wavelength `wl` from a point on the surface drawn with `u`, for instance from
+    /// `random::sample_2d`, divided by the probability density of drawing it. The distance stops just short of the
+    /// surface, so shadow rays don't find the light itself. `None` where another part of the object hides the point.
+    pub fn sample(&self, p: Point3D<f32, UnknownUnit>, u: Vector2D<f32, UnknownUnit>, wl: f32) -> Option<LightSample> {
+        let sample = self.object.sample_surface(u)?;
+        let offset = sample.p - p;
+        let distance = offset.length();
+        if !(distance > 0.0 && sample.pdf > 0.0) {
+            return None;
+        }
+        let direction = offset/distance;
+        // Hitting the point from `p` finds the light emitted on that side
+        let probe = Ray::new(p, direction, wl, 0.0).with_kind(RayKind::Shadow);
+        let rec = self.object.hit(probe, 1e-4*distance, distance*(1.0 + 1e-4))?;
+        if rec.t < distance*(1.0 - 1e-4) {
+            return None;
+        }
+        let emittance = rec.texture.value(rec.uv).scatter(probe, rec).emittance;
+        let cosine = direction.dot(sample.normal).abs();
+        Some(LightSample { direction, distance: distance*(1.0 - 1e-3), irradiance: emittance*cosine/(distance*distance*sample.pdf) })
+    }
+
+    /// The light emitted at a point of the surface, found by hitting it from the side of the normal.
+    fn emittance(&self, sample: &SurfaceSample, wl: f32) -> f32 {
+        let AABB { bounds: [low, high] } = self.object.bbox();
+        let offset = 1e-3*(high - low).length();
+        let probe = Ray::new(sample.p + sample.normal*offset, -sample.normal, wl, 0.0).with_kind(RayKind::Bounce);
+        match self.object.hit(probe, 0.0, 2.0*offset) {
+            Some(rec) => rec.texture.value(rec.uv).scatter(probe, rec).emittance,
+            None => 0.0,
+        }
+    }
+}
+
+/// The objects among `objects` that emit light, found by looking at their surfaces.
+/// Only objects with a surface to sample count, and only if they emit where it is first sampled.
+pub fn find_lights(objects: &[Arc<dyn Hitable>]) -> Vec<Light> {
+    objects.iter()
+        .filter_map(|object| {
+            let sample = object.sample_surface(vec2(0.5, 0.5))?;
+            let light = Light { object: object.clone(), power: 0.0 };
+            let emittance = (0..=12).map(|i| light.emittance(&sample, 400.0 + 25.0*i as f32)).sum::<f32>()/13.0;
+            let power = emittance*object.surface_area();
+            if power > 0.0 {
+                Some(Light { power, ..light })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Light arriving at a diffuse surface.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Photon {
+    pub p: Point3D<f32, UnknownUnit>,
+    /// The direction the photon travelled in, normalized.
+    pub direction: Vector3D<f32, UnknownUnit>,
+    pub wl: f32,
+    /// Flux at `wl`, weighted relative to uniform wavelength sampling like camera paths.
+    pub flux: f32,
+}
+
+/// Photons hashed into a grid of cubic cells, to find the photons near a point.
+#[derive(Debug, Clone)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    cell_size: f32,
+    /// Photons of bucket `i` are `photons[buckets[i]..buckets[i+1]]`.
+    buckets: Vec<u32>,
+    emitted: usize,
+}
+
+impl PhotonMap {
+    /// Trace `count` photons from `lights`, picked by power, into `world`, at wavelengths and bouncing as far as
+    /// `settings` say. Gathering is fastest with `cell_size` the largest radius used.
+    pub fn trace<H: Hitable>(world: &H, lights: &[Light], count: usize, cell_size: f32, settings: &RenderSettings) -> PhotonMap {
+        let total_power: f32 = lights.iter().map(|light| light.power).sum();
+        if !(total_power > 0.0) {
+            return PhotonMap::from_photons(Vec::new(), count, cell_size);
+        }
+        let t_min = settings.t_min();
+        let default_rate = settings.shading_rate();
+        let wavelengths = &settings.wavelength_sampler();
+        let photons = (0..count)
+            .into_par_iter()
+            .map_init(|| set_path_sampler(None), |_, _| {
+                let mut stored = Vec::new();
+                trace_photon(world, lights, total_power, wavelengths, t_min, default_rate, &mut stored);
+                stored
+            })
+            .flatten_iter()
+            .collect();
+        PhotonMap::from_photons(photons, count, cell_size)
+    }
+
+    /// A map of photons traced from `emitted` photons leaving the lights.
+    pub fn from_photons(mut photons: Vec<Photon>, emitted: usize, cell_size: f32) -> PhotonMap {
+        let bucket_count = photons.len().next_power_of_two();
+        photons.sort_by_cached_key(|photon| bucket(cell(photon.p, cell_size), bucket_count));
+        let mut buckets = vec![0u32; bucket_count + 1];
+        for photon in photons.iter() {
+            buckets[bucket(cell(photon.p, cell_size), bucket_count) + 1] += 1;
+        }
+        for i in 0..bucket_count {
+            buckets[i + 1] += buckets[i];
+        }
+        PhotonMap { photons, cell_size, buckets, emitted }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Number of photons that left the lights, including those that never arrived anywhere.
+    pub fn emitted(&self) -> usize {
+        self.emitted
+    }
+
+    /// Call `f` with every photon within `radius` of `p`, which may not be larger than the cell size.
+    pub fn gather<F: FnMut(&Photon)>(&self, p: Point3D<f32, UnknownUnit>, radius: f32, mut f: F) {
+        debug_assert!(radius <= self.cell_size);
+        if self.photons.is_empty() {
+            return;
+        }
+        let bucket_count = self.buckets.len() - 1;
+        let mut visited = [usize::MAX; 27];
+        let (x, y, z) = cell(p, self.cell_size);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    // Neighbouring cells may share a bucket, which must only be searched once
+                    let key = bucket((x + dx, y + dy, z + dz), bucket_count);
+                    if visited.contains(&key) {
+                        continue;
+                    }
+                    visited[(9*(dx + 1) + 3*(dy + 1) + dz + 1) as usize] = key;
+                    let photons = &self.photons[self.buckets[key] as usize..self.buckets[key + 1] as usize];
+                    for photon in photons {
+                        if (photon.p - p).square_length() < radius*radius {
+                            f(photon);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Light arriving within `radius` of a diffuse hit from the side `r_in` came from, reflected towards `r_in`,
+    /// summed over the photons as `sensor` records them together with their number.
+    /// Dividing the sum by the emitted photons and the area of the disk gives the radiance.
+    pub fn reflected(&self, r_in: Ray, rec: HitRecord, radius: f32, sensor: &Sensor) -> (Xyz<E, f32>, u32) {
+        let material = rec.texture.value(rec.uv);
+        let normal = rec.facing_normal();
+        let mut sum = Xyz::with_wp(0.0, 0.0, 0.0);
+        let mut count = 0;
+        self.gather(rec.p, radius, |photon| {
+            if photon.direction.dot(normal) >= 0.0 {
+                return;
+            }
+            let albedo = match material.scatter(Ray { wl: photon.wl, ..r_in }, rec).reflection {
+                Some((attenuation, _)) => attenuation,
+                None => 0.0,
+            };
+            sum = sum + sensor.xyz(photon.wl)*(albedo/PI*photon.flux);
+            count += 1;
+        });
+        (sum, count)
+    }
+}
+
+fn cell(p: Point3D<f32, UnknownUnit>, cell_size: f32) -> (i32, i32, i32) {
+    let p = p/cell_size;
+    (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32)
+}
+
+fn bucket((x, y, z): (i32, i32, i32), bucket_count: usize) -> usize {
+    let h = (x as u32).wrapping_mul(73856093) ^ (y as u32).wrapping_mul(19349663) ^ (z as u32).wrapping_mul(83492791);
+    h as usize & (bucket_count - 1)
+}
+
+/// A photon leaving one of `lights`, picked by power out of their `total_power`, with its flux.
+pub(crate) fn emit_photon(lights: &[Light], total_power: f32, wavelengths: &WavelengthSampler) -> Option<(Ray, f32)> {
+    let mut pick = next_f32()*total_power;
+    let light = lights.iter().find(|light| { pick -= light.power; pick < 0.0 }).unwrap_or(&lights[lights.len() - 1]);
+    let sample = light.object.sample_surface(vec2(next_f32(), next_f32()))?;
+    let (wl_low, wl_high) = wavelengths.range();
+    let (wl, wl_pdf) = wavelengths.sample(next_f32());
+    // Cosine weighted away from the side of the normal, which cancels the cosine of the emitted flux
+    let normal = sample.normal;
+    let u = if normal.x.abs()<0.5 { vec3(0.0, -normal.z, normal.y).normalize() } else { vec3(-normal.z, 0.0, normal.x).normalize() };
+    let w = normal.cross(u);
+    let d = sample_disk(vec2(next_f32(), next_f32()));
+    let direction = u*d.x + w*d.y + normal*f32::sqrt(1.0 - d.square_length());
+    let flux = light.emittance(&sample, wl)*PI/(sample.pdf*light.power/total_power)/(wl_pdf*(wl_high - wl_low));
+    Some((Ray::new(offset_origin(sample.p, normal.normalize()), direction, wl, next_f32()).with_kind(RayKind::Bounce), flux))
+}
+
+fn trace_photon<H: Hitable>(world: &H, lights: &[Light], total_power: f32, wavelengths: &WavelengthSampler, t_min: TMin, default_rate: ShadingRate, stored: &mut Vec<Photon>) {
+    let (mut r, mut flux) = match emit_photon(lights, total_power, wavelengths) {
+        Some(photon) => photon,
+        None => return,
+    };
+    let wl = r.wl;
+    for depth in 0.. {
+        let rec = match world.hit(r, t_min.t_min(r), f32::MAX) {
+            Some(rec) => rec,
+            None => return,
+        };
+        flux *= r.transmittance(rec.t);
+        let material = rec.texture.value(rec.uv);
+        if material.is_diffuse() {
+            stored.push(Photon { p: rec.p, direction: r.direction.normalize(), wl, flux });
+        }
+        if depth + 1 >= rec.shading_rate.unwrap_or(default_rate).max_depth {
+            return;
+        }
+        match material.scatter(r, rec).reflection {
+            None => return,
+            // Surviving by the attenuation keeps the flux of a photon constant
+            Some((attenuation, ray)) => {
+                let survival = attenuation.min(1.0);
+                if !(next_f32() < survival) {
+                    return;
+                }
+                flux *= attenuation/survival;
+                r = ray;
+            }
+        }
+    }
+}
+
+/// What a pixel has gathered over the iterations so far.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct PixelEstimate {
+    /// Photons within it count, it shrinks as they add up.
+    pub radius: f32,
+    /// Photons gathered, discounted as the radius shrank.
+    photons: f32,
+    /// Light reflected from the gathered photons, rescaled with the radius.
+    flux: Xyz<E, f32>,
+    /// Light seen directly along the camera paths.
+    direct: Xyz<E, f32>,
+    iterations: u32,
+    emitted: usize,
+}
+
+impl PixelEstimate {
+    /// The fraction of newly gathered photons kept each iteration, trading noise for blur.
+    pub const ALPHA: f32 = 2.0/3.0;
+
+    pub fn new(radius: f32) -> PixelEstimate {
+        let zero = Xyz::with_wp(0.0, 0.0, 0.0);
+        PixelEstimate { radius, photons: 0.0, flux: zero, direct: zero, iterations: 0, emitted: 0 }
+    }
+
+    /// Add an iteration, with the light seen directly along its camera path,
+    /// and the light `reflected` by `count` photons around the diffuse hit it ended on, out of `emitted`.
+    /// Returns the estimate of the iteration on its own. With the same `emitted` every iteration, `radiance` is the
+    /// mean of these, so they can be averaged as samples of one pass each.
+    pub fn add(&mut self, direct: Xyz<E, f32>, (reflected, count): (Xyz<E, f32>, u32), emitted: usize) -> Xyz<E, f32> {
+        self.direct = self.direct + direct;
+        self.iterations += 1;
+        self.emitted += emitted;
+        let sample = if count > 0 && emitted > 0 {
+            direct + reflected/(emitted as f32*PI*self.radius*self.radius)
+        } else {
+            direct
+        };
+        if count > 0 {
+            let photons = self.photons + PixelEstimate::ALPHA*count as f32;
+            let shrink = photons/(self.photons + count as f32);
+            self.flux = (self.flux + reflected)*shrink;
+            self.photons = photons;
+            self.radius *= shrink.sqrt();
+        }
+        sample
+    }
+
+    pub fn radiance(&self) -> Xyz<E, f32> {
+        if self.iterations == 0 {
+            return Xyz::with_wp(0.0, 0.0, 0.0);
+        }
+        let gathered = if self.emitted > 0 {
+            self.flux/(self.emitted as f32*PI*self.radius*self.radius)
+        } else {
+            Xyz::with_wp(0.0, 0.0, 0.0)
+        };
+        self.direct/self.iterations as f32 + gathered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::Rgb;
+    use hitable::bvh::BVH;
+    use hitable::sphere::Sphere;
+    use material::Lambertian;
+    use material::light::DiffuseLight;
+
+    #[test]
+    fn test_gather() {
+        let photons: Vec<Photon> = (0..1000)
+            .map(|_| Photon { p: point3(0.0, 0.0, 0.0) + rand_in_unit_sphere::<f32>()*2.0, direction: vec3(0.0, -1.0, 0.0), wl: 500.0, flux: 1.0 })
+            .collect();
+        let map = PhotonMap::from_photons(photons.clone(), 2000, 0.3);
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.emitted(), 2000);
+        for _ in 0..100 {
+            let p = point3(0.0, 0.0, 0.0) + rand_in_unit_sphere::<f32>()*2.0;
+            let radius = next_f32()*0.3;
+            let mut found = Vec::new();
+            map.gather(p, radius, |photon| found.push(photon.p.to_array()));
+            let mut expected: Vec<_> = photons.iter().filter(|photon| (photon.p - p).length() < radius).map(|photon| photon.p.to_array()).collect();
+            found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_estimate_keeps_density() {
+        // The same photon density at every radius, with the radiance the density gives
+        let xyz = Xyz::with_wp(0.2, 0.3, 0.4);
+        let mut estimate = PixelEstimate::new(1.0);
+        for _ in 0..20 {
+            let radius = estimate.radius;
+            let count = (100.0*radius*radius) as u32 + 1;
+            estimate.add(Xyz::with_wp(0.0, 0.0, 0.0), (xyz*(PI*radius*radius*1000.0), count), 1000);
+        }
+        assert!(estimate.radius < 0.9, "{}", estimate.radius);
+        let radiance = estimate.radiance();
+        assert!((radiance.x - 0.2).abs() < 1e-4 && (radiance.y - 0.3).abs() < 1e-4 && (radiance.z - 0.4).abs() < 1e-4, "{:?}", radiance);
+    }
+
+    #[test]
+    fn test_estimate_is_the_mean_of_its_iterations() {
+        let mut estimate = PixelEstimate::new(1.0);
+        let mut sum = Xyz::with_wp(0.0, 0.0, 0.0);
+        for i in 0..20 {
+            let direct = Xyz::with_wp(0.1, 0.2, 0.3)*(i % 3) as f32;
+            // Fewer photons some iterations, and none in others
+            let count = [0, 40, 7][i % 3];
+            let reflected = Xyz::with_wp(1.0, 2.0, 1.5)*count as f32;
+            sum = sum + estimate.add(direct, (reflected, count), 500);
+            let (mean, radiance) = (sum/(i + 1) as f32, estimate.radiance());
+            assert!((mean.x - radiance.x).abs() < 1e-5 && (mean.y - radiance.y).abs() < 1e-5 && (mean.z - radiance.z).abs() < 1e-5,
+                    "{:?} {:?}", mean, radiance);
+        }
+    }
+
+    #[test]
+    fn test_find_lights() {
+        let light: Arc<dyn Hitable> = Arc::new(Sphere::new(point3(0.0, 2.0, 0.0), 0.5, Arc::new(DiffuseLight::new(Rgb::with_wp(4.0, 4.0, 4.0)))));
+        let ground: Arc<dyn Hitable> = Arc::new(Sphere::new(point3(0.0, -100.0, 0.0), 100.0, Arc::new(Lambertian::new(Rgb::with_wp(0.5, 0.5, 0.5)))));
+        let lights = find_lights(&[ground, light]);
+        assert_eq!(lights.len(), 1);
+        assert!((lights[0].power/(4.0*PI*0.25) - 4.0).abs() < 0.2, "{}", lights[0].power);
+    }
+
+    #[test]
+    fn test_sample() {
+        let light: Arc<dyn Hitable> = Arc::new(Sphere::new(point3(0.0, 2.0, 0.0), 0.5, Arc::new(DiffuseLight::new(Rgb::with_wp(4.0, 4.0, 4.0)))));
+        let lights = find_lights(&[light]);
+        // The far side of the sphere is hidden, and the near side makes up for it
+        let n = 100;
+        let samples: Vec<_> = (0..n*n)
+            .filter_map(|i| lights[0].sample(point3(0.0, 0.0, 0.0), vec2(((i % n) as f32 + 0.5)/n as f32, ((i/n) as f32 + 0.5)/n as f32), 550.0))
+            .collect();
+        assert!(samples.len() < n*n*6/10, "{}", samples.len());
+        for sample in samples.iter() {
+            assert!(sample.direction.y > 0.0 && sample.distance < 2.0);
+        }
+        // A sphere of radiance L and radius R at distance D lights a point facing it with π L (R/D)²
+        let irradiance = samples.iter().map(|sample| sample.irradiance*sample.direction.y).sum::<f32>()/(n*n) as f32;
+        assert!((irradiance - PI*4.0*0.0625).abs() < 0.05*PI*4.0*0.0625, "{}", irradiance);
+    }
+
+    #[test]
+    fn test_photons_reach_the_ground() {
+        let white = Lambertian::new(Rgb::with_wp(1.0, 1.0, 1.0));
+        let light: Arc<dyn Hitable> = Arc::new(Sphere::new(point3(0.0, 2.0, 0.0), 0.5, Arc::new(DiffuseLight::new(Rgb::with_wp(4.0, 4.0, 4.0)))));
+        let ground: Arc<dyn Hitable> = Arc::new(Sphere::new(point3(0.0, -1000.0, 0.0), 1000.0, Arc::new(white)));
+        let lights = find_lights(&[light.clone(), ground.clone()]);
+        let world = BVH::initialize(vec![light, ground]);
+        let map = PhotonMap::trace(&world, &lights, 10000, 0.5, &RenderSettings::default().with_wavelength_range(400.0, 700.0));
+        // Roughly half the photons leave the light downwards, to be stored once where they land
+        assert!(map.len() > 4000 && map.len() < 6000, "{}", map.len());
+        let mut below = 0;
+        map.gather(point3(0.0, 0.0, 0.0), 0.5, |photon| {
+            assert!(photon.direction.y < 0.0);
+            below += 1;
+        });
+        assert!(below > 0);
+    }
+}
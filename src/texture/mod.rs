@@ -5,10 +5,48 @@ use image::*;
 use std::sync::Arc;
 use num_traits::ToPrimitive;
 use palette::white_point::E;
+use hitable::HitRecord;
+use color::HasReflectance;
 use material::*;
+use ray::Ray;
+use random::sample_1d;
 
 pub trait Texture: Debug + Send + Sync {
-    fn value(&self, uv: Vector2D<f32, UnknownUnit>) -> Box<dyn Material>;
+    fn value(&self, uv: Vector2D<f32, UnknownUnit>) -> SurfaceMaterial<'_>;
+
+    /// Whether the surface is there at the texture coordinates.
+    /// Objects ask while intersecting, and rays pass through where it isn't, as if nothing was hit.
+    fn is_opaque(&self, _uv: Vector2D<f32, UnknownUnit>) -> bool {
+        true
+    }
+}
+
+/// The material at a point of a surface.
+/// Constant materials are borrowed and textured ones built in place, so looking one up never allocates.
+#[derive(Debug, Clone)]
+pub enum SurfaceMaterial<'a> {
+    Shared(&'a dyn Material),
+    Diffuse(Lambertian<palette::Rgb<E, f32>>),
+    /// A shared material looking up its own textures at other texture coordinates than those of the hit.
+    Remapped(&'a dyn Material, Vector2D<f32, UnknownUnit>),
+}
+
+impl<'a> Material for SurfaceMaterial<'a> {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        match *self {
+            SurfaceMaterial::Shared(material) => material.scatter(r_in, rec),
+            SurfaceMaterial::Diffuse(ref material) => material.scatter(r_in, rec),
+            SurfaceMaterial::Remapped(material, uv) => material.scatter(r_in, HitRecord { uv, ..rec }),
+        }
+    }
+
+    fn is_diffuse(&self) -> bool {
+        match *self {
+            SurfaceMaterial::Shared(material) => material.is_diffuse(),
+            SurfaceMaterial::Diffuse(_) => true,
+            SurfaceMaterial::Remapped(material, _) => material.is_diffuse(),
+        }
+    }
 }
 
 impl<'a, 'b> PartialEq<dyn Texture+'b> for dyn Texture+'a {
@@ -17,37 +55,477 @@ impl<'a, 'b> PartialEq<dyn Texture+'b> for dyn Texture+'a {
     }
 }
 
-impl<M: Material+Clone+'static> Texture for M {
-    fn value(&self, _uv: Vector2D<f32, UnknownUnit>) -> Box<dyn Material> {
-        Box::new(self.clone())
+impl<M: Material+'static> Texture for M {
+    fn value(&self, _uv: Vector2D<f32, UnknownUnit>) -> SurfaceMaterial<'_> {
+        SurfaceMaterial::Shared(self)
+    }
+}
+
+/// A value at every point in space, like the density of a murky medium, given in the coordinates of the scene.
+pub trait Density: Debug + Send + Sync {
+    fn density(&self, p: Point3D<f32, UnknownUnit>) -> f32;
+}
+
+/// The same density everywhere.
+impl Density for f32 {
+    fn density(&self, _p: Point3D<f32, UnknownUnit>) -> f32 {
+        *self
     }
 }
 
+impl Density for fn(Point3D<f32, UnknownUnit>) -> f32 {
+    fn density(&self, p: Point3D<f32, UnknownUnit>) -> f32 {
+        self(p)
+    }
+}
+
+/// How the 8 bit values of an image stand for linear values.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Encoding {
+    /// The sRGB transfer curve, which color images are usually saved with.
+    Srgb,
+    /// Values that are linear already, like normal maps and other data.
+    Linear,
+    /// A plain power curve with this gamma, for images saved with another than sRGB.
+    Gamma(f32),
+}
+
+impl Encoding {
+    /// The linear value, in [0,1], of an 8 bit value.
+    pub fn decode(self, value: u8) -> f32 {
+        let value = value as f32/255.0;
+        match self {
+            Encoding::Srgb => {
+                let linear: palette::Rgb<E, f32> = palette::pixel::Srgb::with_wp(value, value, value).into();
+                linear.red
+            },
+            Encoding::Linear => value,
+            Encoding::Gamma(gamma) => value.powf(gamma),
+        }
+    }
+}
+
+/// A color image, decoded to linear colors once when it is made so lookups only have to pick the pixel.
 #[derive(Debug, Clone)]
 pub struct ImageTexture {
-    image: Arc<RgbImage>,
+    image: Arc<Rgb32FImage>,
 }
 
 impl ImageTexture {
+    /// An image saved as sRGB, like most color textures are.
     pub fn new(image: &Arc<RgbImage>) -> ImageTexture {
-        ImageTexture { image: image.clone() }
+        ImageTexture::with_encoding(image, Encoding::Srgb)
+    }
+
+    pub fn with_encoding(image: &Arc<RgbImage>, encoding: Encoding) -> ImageTexture {
+        let table: Vec<f32> = (0..=255).map(|value| encoding.decode(value)).collect();
+        let linear = Rgb32FImage::from_fn(image.width(), image.height(), |x, y| {
+            let Rgb([r, g, b]) = image[(x, y)];
+            Rgb([table[r as usize], table[g as usize], table[b as usize]])
+        });
+        ImageTexture::from_linear(Arc::new(linear))
+    }
+
+    /// An image of linear colors, like one loaded from an HDR file.
+    pub fn from_linear(image: Arc<Rgb32FImage>) -> ImageTexture {
+        ImageTexture { image }
+    }
+}
+
+/// The pixel of a `width` by `height` image at the texture coordinates, with `v` pointing up.
+fn texel(width: u32, height: u32, uv: Vector2D<f32, UnknownUnit>) -> (u32, u32) {
+    let i: isize = (uv.x*(width as f32)).to_isize().unwrap_or(0);
+    let j: isize = ((1.0 - uv.y)*(height as f32)-0.001).to_isize().unwrap_or(0);
+    let i: u32 = i.max(0).min(width as isize - 1).to_u32().unwrap();
+    let j: u32 = j.max(0).min(height as isize - 1).to_u32().unwrap();
+    (i, j)
+}
+
+impl ImageTexture {
+    /// The color of the image at the texture coordinates.
+    pub fn color(&self, uv: Vector2D<f32, UnknownUnit>) -> palette::Rgb<E, f32> {
+        let Rgb([r, g, b]) = self.image[texel(self.image.width(), self.image.height(), uv)];
+        palette::Rgb::with_wp(r, g, b)
     }
 }
 
 impl Texture for ImageTexture {
-    fn value(&self, uv: Vector2D<f32, UnknownUnit>) -> Box<dyn Material> {
-        let nx = self.image.width();
-        let ny = self.image.height();
-        let i: isize = (uv.x*(nx as f32)).to_isize().unwrap();
-        let j: isize = ((1.0 - uv.y)*(ny as f32)-0.001).to_isize().unwrap();
-        let i: u32 = i.max(0).min(nx as isize).to_u32().unwrap();
-        let j: u32 = j.max(0).min(ny as isize).to_u32().unwrap();
-        let Rgb([r,g,b]) = self.image[(i, j)];
-        let rgbf: palette::Rgb<E, f32> = palette::pixel::Srgb::with_wp(
-            r as f32/255.0,
-            g as f32/255.0,
-            b as f32/255.0,
-        ).into();
-        Box::new(Lambertian::new(rgbf))
+    fn value(&self, uv: Vector2D<f32, UnknownUnit>) -> SurfaceMaterial<'_> {
+        SurfaceMaterial::Diffuse(Lambertian::new(self.color(uv)))
+    }
+}
+
+/// Where a surface is cut out, read from a grayscale image like the alpha channel of a leaf or fence texture.
+#[derive(Debug, Clone)]
+pub struct AlphaMask {
+    image: Arc<GrayImage>,
+    threshold: f32,
+}
+
+impl AlphaMask {
+    /// The surface is there where the image is at least `threshold`, in [0,1].
+    pub fn new(image: &Arc<GrayImage>, threshold: f32) -> AlphaMask {
+        AlphaMask { image: image.clone(), threshold }
+    }
+
+    /// The alpha channel of an image, cut at one half.
+    pub fn from_alpha(image: &RgbaImage) -> AlphaMask {
+        let alpha = GrayImage::from_fn(image.width(), image.height(), |x, y| Luma([image[(x, y)][3]]));
+        AlphaMask::new(&Arc::new(alpha), 0.5)
+    }
+
+    pub fn covers(&self, uv: Vector2D<f32, UnknownUnit>) -> bool {
+        let Luma([alpha]) = self.image[texel(self.image.width(), self.image.height(), uv)];
+        alpha as f32/255.0 >= self.threshold
+    }
+}
+
+/// A texture with parts cut out by an `AlphaMask`, so flat geometry can have the outline of leaves or a fence.
+#[derive(Debug, Clone)]
+pub struct Masked {
+    texture: Arc<dyn Texture>,
+    mask: AlphaMask,
+}
+
+impl Masked {
+    pub fn new(texture: Arc<dyn Texture>, mask: AlphaMask) -> Masked {
+        Masked { texture, mask }
+    }
+}
+
+impl Texture for Masked {
+    fn value(&self, uv: Vector2D<f32, UnknownUnit>) -> SurfaceMaterial<'_> {
+        self.texture.value(uv)
+    }
+
+    fn is_opaque(&self, uv: Vector2D<f32, UnknownUnit>) -> bool {
+        self.mask.covers(uv) && self.texture.is_opaque(uv)
+    }
+}
+
+/// A texture looked up at moved texture coordinates: scaled, then rotated counterclockwise around the origin and
+/// then offset. The result wraps around into [0,1), so scaling up tiles the texture across the surface.
+#[derive(Debug, Clone)]
+pub struct UvTransform {
+    texture: Arc<dyn Texture>,
+    transform: Transform2D<f32, UnknownUnit, UnknownUnit>,
+}
+
+impl UvTransform {
+    pub fn new(texture: Arc<dyn Texture>) -> UvTransform {
+        UvTransform { texture, transform: Transform2D::identity() }
+    }
+
+    pub fn scaled(self, u: f32, v: f32) -> UvTransform {
+        UvTransform { transform: self.transform.then_scale(u, v), ..self }
+    }
+
+    /// Rotate by `angle` in radians.
+    pub fn rotated(self, angle: f32) -> UvTransform {
+        UvTransform { transform: self.transform.then_rotate(Angle::radians(angle)), ..self }
+    }
+
+    pub fn offset(self, u: f32, v: f32) -> UvTransform {
+        UvTransform { transform: self.transform.then_translate(vec2(u, v)), ..self }
+    }
+
+    pub fn apply(&self, uv: Vector2D<f32, UnknownUnit>) -> Vector2D<f32, UnknownUnit> {
+        let moved = self.transform.transform_point(uv.to_point());
+        vec2(moved.x - moved.x.floor(), moved.y - moved.y.floor())
+    }
+}
+
+impl Texture for UvTransform {
+    fn value(&self, uv: Vector2D<f32, UnknownUnit>) -> SurfaceMaterial<'_> {
+        let uv = self.apply(uv);
+        match self.texture.value(uv) {
+            // Materials reading the texture coordinates of the hit have to see the moved ones too
+            SurfaceMaterial::Shared(material) => SurfaceMaterial::Remapped(material, uv),
+            material => material,
+        }
+    }
+
+    fn is_opaque(&self, uv: Vector2D<f32, UnknownUnit>) -> bool {
+        self.texture.is_opaque(self.apply(uv))
+    }
+}
+
+/// A texture projected onto a surface along the axes of the scene, for objects without texture coordinates of
+/// their own, like polygons or loaded meshes without them.
+///
+/// The texture is laid onto the three planes through the axes, repeating every `size` units, and each hit uses
+/// one of the projections, chosen at random by how much the normal faces along its axis raised to `sharpness`.
+/// Higher sharpness narrows the blend where the projections meet. The texture is seen unmirrored from outside.
+#[derive(Debug, Clone)]
+pub struct Triplanar {
+    texture: Arc<dyn Texture>,
+    size: f32,
+    sharpness: f32,
+}
+
+impl Triplanar {
+    pub fn new(texture: Arc<dyn Texture>, size: f32, sharpness: f32) -> Triplanar {
+        Triplanar { texture, size, sharpness }
+    }
+
+    /// The texture coordinates of a point projected along the axis `axis`, with a normal pointing to `sign`.
+    fn project(&self, p: Point3D<f32, UnknownUnit>, axis: usize, sign: f32) -> Vector2D<f32, UnknownUnit> {
+        let p = p/self.size;
+        let uv: Vector2D<f32, UnknownUnit> = match axis {
+            0 => vec2(-sign*p.z, p.y),
+            1 => vec2(p.x, -sign*p.z),
+            _ => vec2(sign*p.x, p.y),
+        };
+        vec2(uv.x - uv.x.floor(), uv.y - uv.y.floor())
+    }
+}
+
+impl Material for Triplanar {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> ScatterResult {
+        let normal = rec.normal.normalize();
+        let axes = [normal.x, normal.y, normal.z];
+        let weights = [
+            axes[0].abs().powf(self.sharpness),
+            axes[1].abs().powf(self.sharpness),
+            axes[2].abs().powf(self.sharpness),
+        ];
+        let pick = sample_1d()*(weights[0] + weights[1] + weights[2]);
+        let axis = if pick < weights[0] { 0 } else if pick < weights[0] + weights[1] { 1 } else { 2 };
+        let uv = self.project(rec.p, axis, axes[axis].signum());
+        self.texture.value(uv).scatter(r_in, HitRecord { uv, ..rec })
+    }
+
+    fn is_diffuse(&self) -> bool {
+        self.texture.value(vec2(0.0, 0.0)).is_diffuse()
+    }
+}
+
+/// A single parameter of a material looked up at the texture coordinates of a hit,
+/// like the roughness, metalness or emission maps of a PBR texture set.
+#[derive(Debug, Clone)]
+pub enum TextureChannel {
+    Constant(f32),
+    /// A reflectance, evaluated at the wavelength of the ray.
+    Color(palette::Rgb<E, f32>),
+    /// A color image, evaluated at the wavelength of the ray like `ImageTexture`.
+    ColorMap(ImageTexture),
+    /// A grayscale image of linear values, from `low` where it is black to `high` where it is white.
+    Map { image: Arc<GrayImage>, low: f32, high: f32 },
+    /// Another channel times a factor, like the strength of an emission map.
+    Scaled(Box<TextureChannel>, f32),
+}
+
+impl TextureChannel {
+    /// A grayscale map from one channel of a color image, as packed texture sets store roughness and metalness
+    /// in the green and blue channels.
+    pub fn from_channel(image: &RgbImage, channel: usize, low: f32, high: f32) -> TextureChannel {
+        let gray = GrayImage::from_fn(image.width(), image.height(), |x, y| Luma([image[(x, y)][channel]]));
+        TextureChannel::Map { image: Arc::new(gray), low, high }
+    }
+
+    pub fn scaled(self, factor: f32) -> TextureChannel {
+        TextureChannel::Scaled(Box::new(self), factor)
+    }
+
+    pub fn value(&self, uv: Vector2D<f32, UnknownUnit>, wl: f32) -> f32 {
+        match *self {
+            TextureChannel::Constant(value) => value,
+            TextureChannel::Color(ref color) => color.reflect(wl),
+            TextureChannel::ColorMap(ref image) => image.color(uv).reflect(wl),
+            TextureChannel::Map { ref image, low, high } => {
+                let Luma([value]) = image[texel(image.width(), image.height(), uv)];
+                low + (high - low)*value as f32/255.0
+            },
+            TextureChannel::Scaled(ref channel, factor) => channel.value(uv, wl)*factor,
+        }
+    }
+}
+
+impl From<f32> for TextureChannel {
+    fn from(value: f32) -> TextureChannel {
+        TextureChannel::Constant(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() {
+        // The left half is cut out
+        let image = RgbaImage::from_fn(4, 2, |x, _| Rgba([255, 255, 255, if x < 2 { 0 } else { 255 }]));
+        let texture: Arc<dyn Texture> = Arc::new(Lambertian::new(palette::Rgb::with_wp(0.5, 0.5, 0.5)));
+        let masked = Masked::new(texture.clone(), AlphaMask::from_alpha(&image));
+        assert!(texture.is_opaque(vec2(0.1, 0.5)));
+        assert!(!masked.is_opaque(vec2(0.1, 0.5)));
+        assert!(masked.is_opaque(vec2(0.9, 0.5)));
+        // The edges of the texture coordinates stay within the image
+        assert!(masked.is_opaque(vec2(1.0, 0.0)));
+        assert!(!masked.is_opaque(vec2(0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_image_edges() {
+        // Left half black, right half white
+        let image = Arc::new(RgbImage::from_fn(2, 1, |x, _| Rgb([if x == 0 { 0 } else { 255 }; 3])));
+        let texture = ImageTexture::new(&image);
+        assert!(texture.color(vec2(0.0, 0.0)).red < 0.5);
+        assert!(texture.color(vec2(1.0, 1.0)).red > 0.5);
+        // Out of range and not a number still read a texel
+        texture.color(vec2(-0.5, 2.0));
+        texture.color(vec2(f32::NAN, f32::NAN));
+    }
+
+    #[test]
+    fn test_uv_transform() {
+        // Left half black, right half white
+        let image = Arc::new(RgbImage::from_fn(2, 1, |x, _| Rgb([if x == 0 { 0 } else { 255 }; 3])));
+        let texture: Arc<dyn Texture> = Arc::new(ImageTexture::new(&image));
+        let tiled = UvTransform::new(texture.clone()).scaled(2.0, 1.0);
+        assert!((tiled.apply(vec2(0.7, 0.2)) - vec2(0.4, 0.2)).length() < 1e-5);
+        let turned = UvTransform::new(texture).rotated(std::f32::consts::PI*0.5).offset(1.0, 0.0);
+        assert!((turned.apply(vec2(0.2, 0.3)) - vec2(0.7, 0.2)).length() < 1e-5);
+
+        // Materials reading the texture coordinates themselves see the moved ones
+        let channel = TextureChannel::ColorMap(ImageTexture::new(&image));
+        let pbr: Arc<dyn Texture> = Arc::new(pbr::PbrMaterial::new(channel).with_ior(1.0.into()));
+        let flipped = UvTransform::new(pbr).scaled(-1.0, 1.0);
+        let rec = HitRecord {
+            t: 1.0,
+            p: point3(0.0, 0.0, 0.0),
+            uv: vec2(0.25, 0.5),
+            normal: vec3(0.0, 1.0, 0.0),
+            geometric_normal: vec3(0.0, 1.0, 0.0),
+            front_face: true,
+            texture: &flipped,
+            shading_rate: None,
+            object_id: None,
+            tangent: None,
+            edge_distance: None,
+            error: 0.0,
+        };
+        let r_in = Ray::new(point3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0), 550.0, 0.0);
+        for _ in 0..20 {
+            let (attenuation, _) = flipped.value(rec.uv).scatter(r_in, rec).reflection.unwrap();
+            assert!(attenuation > 0.5, "{}", attenuation);
+        }
+    }
+
+    #[test]
+    fn test_triplanar() {
+        let image = Arc::new(RgbImage::from_fn(2, 2, |x, y| Rgb([(x*100 + y*50) as u8; 3])));
+        let triplanar = Triplanar::new(Arc::new(ImageTexture::new(&image)), 2.0, 4.0);
+        let p = point3(0.5, 3.2, -0.6);
+        assert!((triplanar.project(p, 0, 1.0) - vec2(0.3, 0.6)).length() < 1e-5);
+        assert!((triplanar.project(p, 0, -1.0) - vec2(0.7, 0.6)).length() < 1e-5);
+        assert!((triplanar.project(p, 1, 1.0) - vec2(0.25, 0.3)).length() < 1e-5);
+        assert!((triplanar.project(p, 2, 1.0) - vec2(0.25, 0.6)).length() < 1e-5);
+        assert!(triplanar.is_diffuse());
+
+        // Facing straight along an axis only ever uses its projection
+        let rec = HitRecord {
+            t: 1.0,
+            p,
+            uv: vec2(0.0, 0.0),
+            normal: vec3(0.0, 0.0, 2.0),
+            geometric_normal: vec3(0.0, 0.0, 2.0),
+            front_face: true,
+            texture: &triplanar,
+            shading_rate: None,
+            object_id: None,
+            tangent: None,
+            edge_distance: None,
+            error: 0.0,
+        };
+        let r_in = Ray::new(point3(0.5, 3.2, 1.0), vec3(0.0, 0.0, -1.0), 550.0, 0.0);
+        let expected = ImageTexture::new(&image).color(vec2(0.25, 0.6)).reflect(550.0);
+        for _ in 0..20 {
+            let (attenuation, _) = triplanar.scatter(r_in, rec).reflection.unwrap();
+            assert!((attenuation - expected).abs() < 1e-6, "{} {}", attenuation, expected);
+        }
+    }
+
+    #[test]
+    fn test_channels() {
+        // Roughness in green and metalness in blue, both rising to the right
+        let packed = RgbImage::from_fn(2, 1, |x, _| Rgb([0, if x == 0 { 0 } else { 255 }, if x == 0 { 51 } else { 255 }]));
+        let roughness = TextureChannel::from_channel(&packed, 1, 0.2, 0.6);
+        let metalness = TextureChannel::from_channel(&packed, 2, 0.0, 1.0);
+        assert_eq!(roughness.value(vec2(0.25, 0.5), 550.0), 0.2);
+        assert_eq!(roughness.value(vec2(0.75, 0.5), 550.0), 0.6);
+        assert!((metalness.value(vec2(0.25, 0.5), 550.0) - 0.2).abs() < 1e-6);
+        assert_eq!(TextureChannel::from(2.0).scaled(1.5).value(vec2(0.0, 0.0), 550.0), 3.0);
+        let white = palette::Rgb::with_wp(1.0, 1.0, 1.0);
+        let color = TextureChannel::Color(white).value(vec2(0.0, 0.0), 550.0);
+        let image = TextureChannel::ColorMap(ImageTexture::new(&Arc::new(RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])))));
+        assert_eq!(image.value(vec2(0.3, 0.3), 550.0), color);
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(Encoding::Linear.decode(51), 0.2);
+        assert!((Encoding::Gamma(2.0).decode(51) - 0.04).abs() < 1e-6);
+        // sRGB is linear near black and close to a gamma of 2.2 above
+        assert!((Encoding::Srgb.decode(1) - 1.0/255.0/12.92).abs() < 1e-6);
+        assert!((Encoding::Srgb.decode(128) - 0.2159).abs() < 1e-3);
+        assert_eq!((Encoding::Srgb.decode(0), Encoding::Srgb.decode(255)), (0.0, 1.0));
+
+        let image = Arc::new(RgbImage::from_pixel(2, 2, Rgb([128, 0, 255])));
+        let srgb = ImageTexture::new(&image).color(vec2(0.5, 0.5));
+        assert_eq!(srgb, palette::Rgb::with_wp(Encoding::Srgb.decode(128), 0.0, 1.0));
+        let linear = ImageTexture::with_encoding(&image, Encoding::Linear).color(vec2(0.5, 0.5));
+        assert_eq!(linear, palette::Rgb::with_wp(128.0/255.0, 0.0, 1.0));
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+    use super::*;
+    use test::*;
+    use hitable::Hitable;
+    use hitable::sphere::Sphere;
+
+    fn bench_scatter(bench: &mut Bencher, texture: Arc<dyn Texture>, boxed: bool) {
+        let sphere = Sphere::new(point3(0.0, 0.0, 0.0), 1.0, texture);
+        let ray = black_box(Ray::new(point3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 500.0, 0.0));
+        let rec = sphere.hit(ray, 0.001, 10.0).unwrap();
+        bench.iter(|| {
+            let rec = HitRecord { ..rec };
+            if boxed {
+                // How materials were looked up before, allocating on every hit
+                let material: Box<dyn Material> = Box::new(rec.texture.value(rec.uv));
+                black_box(material.scatter(ray, rec))
+            } else {
+                black_box(rec.texture.value(rec.uv).scatter(ray, rec))
+            }
+        });
+    }
+
+    fn image_texture() -> Arc<dyn Texture> {
+        let image = Arc::new(RgbImage::from_pixel(64, 64, Rgb([200, 100, 50])));
+        Arc::new(ImageTexture::new(&image))
+    }
+
+    #[bench]
+    fn bench_image_texture_scatter(bench: &mut Bencher) {
+        bench_scatter(bench, image_texture(), false);
+    }
+
+    #[bench]
+    fn bench_image_texture_scatter_boxed(bench: &mut Bencher) {
+        bench_scatter(bench, image_texture(), true);
+    }
+
+    #[bench]
+    fn bench_image_texture_color(bench: &mut Bencher) {
+        let texture = ImageTexture::new(&Arc::new(RgbImage::from_pixel(64, 64, Rgb([200, 100, 50]))));
+        let uv = black_box(vec2(0.3, 0.6));
+        bench.iter(|| black_box(texture.color(uv)));
+    }
+
+    #[bench]
+    fn bench_constant_scatter(bench: &mut Bencher) {
+        bench_scatter(bench, Arc::new(Lambertian::new(palette::Rgb::with_wp(0.5, 0.5, 0.5))), false);
     }
 }
@@ -0,0 +1,145 @@
+//! The order the pixels of a pass are rendered in.
+//!
+//! Passes are split into square tiles, which threads take up in order as they come free and which show up in
+//! progressive previews as soon as they are done. Starting from the center gets a first look at the subject of most
+//! images early, and following a Hilbert curve keeps the tiles in flight close together, so they share more of the scene
+//! in the caches.
+
+/// A rectangle of pixels, counted from the top left of the image.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// The indices of the pixels of the tile in an image `image_width` pixels wide, row by row from the top.
+    pub fn pixels(self, image_width: u32) -> impl Iterator<Item = u32> {
+        (self.y..self.y + self.height).flat_map(move |y| (self.x..self.x + self.width).map(move |x| y*image_width + x))
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TileOrder {
+    /// Row by row from the top left, as text is read.
+    Scanline,
+    /// Around and around the center, outwards.
+    Spiral,
+    /// Along a Hilbert curve from the top left, which never jumps but to skip tiles outside the image.
+    Hilbert,
+}
+
+impl Default for TileOrder {
+    fn default() -> TileOrder {
+        TileOrder::Scanline
+    }
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+
+    /// Split an image `width` by `height` pixels into tiles `size` pixels square, cut short at the right and bottom
+    /// edges, in this order.
+    ///
+    /// ```
+    /// # extern crate rayer;
+    /// # use rayer::tiles::*;
+    /// let tiles = TileOrder::Spiral.tiles(100, 60, 20);
+    /// assert_eq!(tiles.len(), 15);
+    /// assert_eq!(tiles[0], Tile { x: 40, y: 20, width: 20, height: 20 });
+    /// ```
+    pub fn tiles(self, width: u32, height: u32, size: u32) -> Vec<Tile> {
+        let size = size.max(1);
+        let (columns, rows) = ((width + size - 1)/size, (height + size - 1)/size);
+        let tile = |(column, row): (u32, u32)| {
+            let (x, y) = (column*size, row*size);
+            Tile { x, y, width: size.min(width - x), height: size.min(height - y) }
+        };
+        let mut cells: Vec<(u32, u32)> = match self {
+            TileOrder::Scanline | TileOrder::Spiral => (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row))).collect(),
+            TileOrder::Hilbert => {
+                let side = columns.max(rows).next_power_of_two();
+                (0..side*side).map(|d| hilbert(side, d)).filter(|&(column, row)| column < columns && row < rows).collect()
+            },
+        };
+        if self == TileOrder::Spiral {
+            // By the ring of tiles around the center, then clockwise from the top
+            let key = |&(column, row): &(u32, u32)| {
+                let dx = column as f32 + 0.5 - 0.5*columns as f32;
+                let dy = row as f32 + 0.5 - 0.5*rows as f32;
+                let ring = dx.abs().max(dy.abs()).round();
+                let angle = dx.atan2(-dy);
+                (ring, if angle < 0.0 { angle + 2.0*std::f32::consts::PI } else { angle })
+            };
+            cells.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        cells.into_iter().map(tile).collect()
+    }
+}
+
+/// The cell at distance `d` along a Hilbert curve filling a square `side` cells wide, `side` being a power of two.
+fn hilbert(side: u32, d: u32) -> (u32, u32) {
+    let (mut x, mut y, mut t) = (0, 0, d);
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (t/2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s*rx;
+        y += s*ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_pixel_once() {
+        for &order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            for &(width, height, size) in &[(100, 70, 16), (64, 64, 32), (5, 3, 8), (7, 40, 1)] {
+                let mut pixels: Vec<u32> = order.tiles(width, height, size).into_iter().flat_map(|tile| tile.pixels(width)).collect();
+                pixels.sort();
+                assert_eq!(pixels, (0..width*height).collect::<Vec<_>>(), "{:?} {}x{} by {}", order, width, height, size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_orders() {
+        let scanline = TileOrder::Scanline.tiles(100, 70, 16);
+        assert_eq!(scanline[0], Tile { x: 0, y: 0, width: 16, height: 16 });
+        assert_eq!(scanline[6], Tile { x: 96, y: 0, width: 4, height: 16 });
+        assert_eq!(scanline[7], Tile { x: 0, y: 16, width: 16, height: 16 });
+
+        // Outwards from the middle
+        let spiral = TileOrder::Spiral.tiles(90, 90, 10);
+        assert_eq!(spiral[0], Tile { x: 40, y: 40, width: 10, height: 10 });
+        let distance = |tile: &Tile| ((tile.x as i32 - 40).abs()).max((tile.y as i32 - 40).abs());
+        assert!(spiral.windows(2).all(|pair| distance(&pair[0]) <= distance(&pair[1])));
+
+        // Every step goes to a neighbouring tile when the grid is a power of two wide
+        let hilbert = TileOrder::Hilbert.tiles(64, 64, 8);
+        assert_eq!(hilbert[0], Tile { x: 0, y: 0, width: 8, height: 8 });
+        assert!(hilbert.windows(2).all(|pair| (pair[0].x as i32 - pair[1].x as i32).abs() + (pair[0].y as i32 - pair[1].y as i32).abs() == 8));
+    }
+}
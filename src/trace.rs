@@ -0,0 +1,156 @@
+//! Spans of work recorded for the chrome tracing format, to see how the threads spend their time
+//! without a native profiler. Open the written file in `chrome://tracing` or Perfetto.
+//!
+//! Recording is off until `enable` is called, and spans cost next to nothing while it is.
+//!
+//! ```
+//! # extern crate rayer;
+//! # use rayer::trace;
+//! trace::enable();
+//! {
+//!     let _span = trace::span("render", "pass").with_arg("index", 0);
+//! }
+//! let mut json = Vec::new();
+//! trace::write(&mut json).unwrap();
+//! assert!(String::from_utf8(json).unwrap().contains("\"name\":\"pass\""));
+//! ```
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+
+lazy_static! {
+    static ref START: Instant = Instant::now();
+    static ref EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+}
+
+thread_local! {
+    static THREAD: u64 = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone)]
+struct Event {
+    category: &'static str,
+    name: Cow<'static, str>,
+    thread: u64,
+    /// Microseconds since tracing was enabled.
+    start: u64,
+    duration: u64,
+    args: Vec<(&'static str, u64)>,
+}
+
+/// Start recording spans.
+pub fn enable() {
+    lazy_static::initialize(&START);
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A piece of work on the current thread, recorded from now until it is dropped.
+#[must_use = "the span ends when it is dropped"]
+pub struct Span {
+    event: Option<(Event, Instant)>,
+}
+
+/// Start a span named `name` in the group `category`, like "load" or "render".
+pub fn span<N: Into<Cow<'static, str>>>(category: &'static str, name: N) -> Span {
+    if !is_enabled() {
+        return Span { event: None };
+    }
+    let now = Instant::now();
+    let event = Event {
+        category,
+        name: name.into(),
+        thread: THREAD.with(|thread| *thread),
+        start: now.duration_since(*START).as_micros() as u64,
+        duration: 0,
+        args: Vec::new(),
+    };
+    Span { event: Some((event, now)) }
+}
+
+impl Span {
+    /// Attach a number to the span, shown when it is selected.
+    pub fn with_arg(mut self, key: &'static str, value: u64) -> Span {
+        if let Some((ref mut event, _)) = self.event {
+            event.args.push((key, value));
+        }
+        self
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some((mut event, start)) = self.event.take() {
+            event.duration = start.elapsed().as_micros() as u64;
+            EVENTS.lock().unwrap().push(event);
+        }
+    }
+}
+
+/// `s` escaped to go between the quotes of a JSON string.
+pub fn escape(s: &str) -> String {
+    s.chars().flat_map(|c| match c {
+        '"' => vec!['\\', '"'],
+        '\\' => vec!['\\', '\\'],
+        c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32).chars().collect(),
+        c => vec![c],
+    }).collect()
+}
+
+/// Write the spans finished so far as chrome tracing JSON and forget them.
+pub fn write<W: Write>(mut out: W) -> io::Result<()> {
+    let events = ::std::mem::replace(&mut *EVENTS.lock().unwrap(), Vec::new());
+    writeln!(out, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+    for (i, event) in events.iter().enumerate() {
+        let args: Vec<String> = event.args.iter().map(|&(key, value)| format!("\"{}\":{}", escape(key), value)).collect();
+        writeln!(
+            out,
+            "{{\"cat\":\"{}\",\"name\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{},\"dur\":{},\"args\":{{{}}}}}{}",
+            escape(event.category),
+            escape(&event.name),
+            event.thread,
+            event.start,
+            event.duration,
+            args.join(","),
+            if i + 1 < events.len() { "," } else { "" },
+        )?;
+    }
+    writeln!(out, "]}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_spans_on_threads() {
+        enable();
+        let other = thread::spawn(|| {
+            let _span = span("test", format!("other \"{}\"", 1));
+            THREAD.with(|thread| *thread)
+        }).join().unwrap();
+        {
+            let _span = span("test", "outer").with_arg("n", 7);
+            let _inner = span("test", "inner");
+        }
+        let mut json = Vec::new();
+        write(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        // Other tests may record spans at the same time
+        let line = |name: &str| json.lines().find(|line| line.contains(name)).unwrap().to_string();
+        assert!(line("outer").contains("\"args\":{\"n\":7}"));
+        assert!(line("other \\\"1\\\"").contains(&format!("\"tid\":{},", other)));
+        assert_ne!(line("outer"), line("inner"));
+        assert!(json.trim_end().ends_with("]}"));
+    }
+}
@@ -0,0 +1,114 @@
+//! Watching the files a scene is made from, so look development can render it again whenever one is saved.
+//!
+//! Editors often save by writing a new file and moving it over the old one, so it's the folders that are watched,
+//! for changes to the files in question.
+//!
+//! ```no_run
+//! # extern crate rayer;
+//! # use rayer::watch::Watcher;
+//! let watcher = Watcher::new(&["scene.json", "data/bunny.obj"]).unwrap();
+//! loop {
+//!     for file in watcher.wait() {
+//!         println!("{} changed", file.display());
+//!     }
+//! }
+//! ```
+
+use crossbeam_channel::{unbounded, Receiver};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::BTreeSet;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a change waits for more, as saving can take several writes.
+const SETTLE: Duration = Duration::from_millis(100);
+
+pub struct Watcher {
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    changes: Receiver<PathBuf>,
+}
+
+impl Watcher {
+    /// Watch `files`, which have to exist.
+    pub fn new<P: AsRef<Path>>(files: &[P]) -> Result<Watcher, Error> {
+        let files = files.iter().map(|file| file.as_ref().canonicalize()).collect::<Result<BTreeSet<_>, _>>()?;
+        let (sender, changes) = unbounded();
+        let watched = files.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => event,
+                _ => return,
+            };
+            for path in event.paths.into_iter().filter(|path| watched.contains(path)) {
+                // Nobody is waiting any more once the watcher is dropped
+                let _ = sender.send(path);
+            }
+        }).map_err(to_io)?;
+        let folders: BTreeSet<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+        for folder in folders {
+            watcher.watch(folder, RecursiveMode::NonRecursive).map_err(to_io)?;
+        }
+        Ok(Watcher { _watcher: watcher, changes })
+    }
+
+    /// The files as they change, every write on its own, to wait on along with other channels.
+    pub fn changes(&self) -> &Receiver<PathBuf> {
+        &self.changes
+    }
+
+    /// Block until a file changed, then until there were no more writes for a moment, and return the files changed.
+    pub fn wait(&self) -> Vec<PathBuf> {
+        match self.changes.recv() {
+            Ok(file) => self.settle(file),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// The file `first` received from `changes`, with any that change until the writes settle down.
+    pub fn settle(&self, first: PathBuf) -> Vec<PathBuf> {
+        let mut changed = BTreeSet::new();
+        changed.insert(first);
+        while let Ok(file) = self.changes.recv_timeout(SETTLE) {
+            changed.insert(file);
+        }
+        changed.into_iter().collect()
+    }
+}
+
+fn to_io(error: notify::Error) -> Error {
+    match error.kind {
+        notify::ErrorKind::Io(error) => error,
+        _ => Error::other(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_watcher() {
+        let folder = tempdir().unwrap();
+        let scene = folder.path().join("scene.json");
+        let other = folder.path().join("other.json");
+        fs::write(&scene, "{}").unwrap();
+        fs::write(&other, "{}").unwrap();
+        let watcher = Watcher::new(&[&scene]).unwrap();
+        // Only the watched file counts, however often it is written to. The writes are queued up once the watcher is
+        // made, so there is nothing to wait for.
+        fs::write(&other, "[]").unwrap();
+        fs::write(&scene, "{\"objects\": []}").unwrap();
+        fs::write(&scene, "{\"objects\": [], \"vfov\": 30}").unwrap();
+        let scene = scene.canonicalize().unwrap();
+        assert_eq!(watcher.wait(), vec![scene.clone()]);
+        // Events arriving after the writes settled down are still only about the watched file
+        while let Ok(file) = watcher.changes().recv_timeout(SETTLE) {
+            assert_eq!(file, scene);
+        }
+        assert!(Watcher::new(&[folder.path().join("missing.json")]).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+//! Renders the `furnace` scene, spheres of different materials in a uniform white environment, and checks that every
+//! sphere is as bright as the environment around it. Materials that neither emit nor absorb light have to vanish in
+//! it, so a sphere standing out darker loses energy somewhere along its paths, and one standing out brighter makes
+//! some up, like a sampling weight that is off.
+
+extern crate image;
+
+use image::Rgb32FImage;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const SAMPLES: u32 = 64;
+/// Where the scene puts the spheres: their centers in pixels, at the height of the middle of the image,
+/// and their radius, which the pixels checked stay within.
+const CENTERS: [u32; 5] = [16, 48, 80, 112, 144];
+const RADIUS: f32 = 14.4;
+const MATERIALS: [&str; 5] = ["white diffuse", "mirror", "rough metal", "glass", "coated white"];
+/// The largest relative difference between a sphere and the environment, a few times the noise left at these samples.
+const TOLERANCE: f32 = 0.02;
+
+fn render() -> Rgb32FImage {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("furnace");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("furnace.hdr");
+    let samples = SAMPLES.to_string();
+    let args = ["--scene", "furnace", "--seed", "1", "--samples", &samples, "--no-progressive", "--output", path.to_str().unwrap()];
+    let output = Command::new(env!("CARGO_BIN_EXE_rayer")).args(&args).output().unwrap();
+    assert!(output.status.success(), "rayer {}: {}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+    image::open(&path).unwrap().into_rgb32f()
+}
+
+/// The mean of the channels of the pixels whose centers `inside` picks by their distance from every sphere center.
+fn mean<F: Fn(&[f32]) -> bool>(image: &Rgb32FImage, inside: F) -> f32 {
+    let center_y = image.height() as f32/2.0;
+    let (sum, count) = image.enumerate_pixels()
+        .filter(|&(x, y, _)| {
+            let distances: Vec<f32> = CENTERS.iter()
+                .map(|&cx| ((x as f32 + 0.5 - cx as f32).powi(2) + (y as f32 + 0.5 - center_y).powi(2)).sqrt())
+                .collect();
+            inside(&distances)
+        })
+        .fold((0.0, 0), |(sum, count), (_, _, pixel)| (sum + pixel.0.iter().sum::<f32>()/3.0, count + 1));
+    assert!(count > 0);
+    sum/count as f32
+}
+
+#[test]
+fn test_furnace() {
+    let image = render();
+    assert_eq!(image.dimensions(), (160, 32));
+    let environment = mean(&image, |distances| distances.iter().all(|&d| d > RADIUS + 1.0));
+    assert!(environment > 0.5, "the environment is too dark to compare with, {}", environment);
+    let failures: Vec<String> = MATERIALS.iter().enumerate()
+        .filter_map(|(i, material)| {
+            let sphere = mean(&image, |distances| distances[i] < RADIUS - 1.0);
+            let difference = sphere/environment - 1.0;
+            if difference.abs() > TOLERANCE {
+                Some(format!("{} is {:+.1}% off the environment", material, 100.0*difference))
+            } else {
+                None
+            }
+        })
+        .collect();
+    assert!(failures.is_empty(), "spheres don't conserve energy:\n{}", failures.join("\n"));
+}
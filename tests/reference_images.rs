@@ -0,0 +1,133 @@
+//! Renders small versions of the built-in scenes and compares them with the reference images in `tests/references`,
+//! so changes to the BVH, materials or colors are checked for what the scenes look like and not just how fast they render.
+//!
+//! Renders are seeded to come out the same every run, but the comparison leaves room for noise, so changes to sampling pass
+//! as long as the images converge to the same thing. Rendering every scene takes a while, so the test is ignored by default:
+//!
+//! ```text
+//! cargo test --release --test reference_images -- --ignored
+//! ```
+//!
+//! A scene without a reference image fails. After adding a scene or an intended change to how scenes look,
+//! `RAYER_BLESS=1` writes the references of all of them.
+
+extern crate image;
+
+use image::Rgb32FImage;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const WIDTH: u32 = 64;
+const SAMPLES: u32 = 64;
+/// Pixels are averaged over blocks this wide before comparing, which evens out most of the noise.
+const BLOCK: u32 = 4;
+/// The largest root mean square difference between the tone mapped blocks of a render and its reference.
+const MAX_RMS: f32 = 0.02;
+/// The largest difference of a single block, which catches a change to a small part of the image.
+const MAX_BLOCK: f32 = 0.15;
+
+fn rayer(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rayer")).args(args).output().unwrap();
+    assert!(output.status.success(), "rayer {}: {}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn render(scene: &str, path: &Path) -> Rgb32FImage {
+    let (width, samples) = (WIDTH.to_string(), SAMPLES.to_string());
+    rayer(&["--scene", scene, "--seed", "1", "--width", &width, "--samples", &samples, "--no-progressive", "--output", path.to_str().unwrap()]);
+    image::open(path).unwrap().into_rgb32f()
+}
+
+/// The mean color of every block of pixels, tone mapped to [0,1) so fireflies and lights don't outweigh the rest.
+/// Pixels that aren't finite are left out.
+fn blocks(image: &Rgb32FImage) -> Vec<[f32; 3]> {
+    let (columns, rows) = ((image.width() + BLOCK - 1)/BLOCK, (image.height() + BLOCK - 1)/BLOCK);
+    let mut sums = vec![([0.0; 3], 0); (columns*rows) as usize];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if !pixel.0.iter().all(|c| c.is_finite()) {
+            continue;
+        }
+        let (sum, count) = &mut sums[((y/BLOCK)*columns + x/BLOCK) as usize];
+        for (s, &c) in sum.iter_mut().zip(&pixel.0) {
+            *s += c.max(0.0)/(1.0 + c.max(0.0));
+        }
+        *count += 1;
+    }
+    sums.into_iter().map(|(sum, count)| sum.map(|s| s/count.max(1) as f32)).collect()
+}
+
+/// Why a render doesn't look like its reference, if it doesn't.
+fn compare(render: &Rgb32FImage, reference: &Rgb32FImage) -> Result<(), String> {
+    if render.dimensions() != reference.dimensions() {
+        return Err(format!("{:?} pixels instead of {:?}", render.dimensions(), reference.dimensions()));
+    }
+    let differences: Vec<f32> = blocks(render).iter()
+        .zip(&blocks(reference))
+        .flat_map(|(a, b)| (0..3).map(move |c| (a[c] - b[c]).abs()))
+        .collect();
+    let rms = (differences.iter().map(|d| d*d).sum::<f32>()/differences.len() as f32).sqrt();
+    let max = differences.iter().cloned().fold(0.0, f32::max);
+    if rms > MAX_RMS || max > MAX_BLOCK {
+        return Err(format!("a difference of {:.4} on average and {:.4} at most", rms, max));
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_reference_images() {
+    let references = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/references");
+    let renders = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("reference_images");
+    let bless = env::var_os("RAYER_BLESS").is_some();
+    if bless {
+        fs::create_dir_all(&references).unwrap();
+    }
+    fs::create_dir_all(&renders).unwrap();
+
+    let listing = rayer(&["--list-scenes"]);
+    let mut failures = Vec::new();
+    for scene in listing.lines().filter_map(|line| line.split_whitespace().next()) {
+        let reference = references.join(format!("{}.hdr", scene));
+        let path = renders.join(format!("{}.hdr", scene));
+        let image = render(scene, &path);
+        if bless {
+            fs::copy(&path, &reference).unwrap();
+            eprintln!("{}: wrote {}", scene, reference.display());
+            continue;
+        }
+        if !reference.exists() {
+            failures.push(format!("{}: no reference at {}, set RAYER_BLESS=1 to write it", scene, reference.display()));
+            continue;
+        }
+        if let Err(difference) = compare(&image, &image::open(&reference).unwrap().into_rgb32f()) {
+            failures.push(format!("{}: {}, see {}", scene, difference, path.display()));
+        }
+    }
+    assert!(failures.is_empty(), "renders differ from their references:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn test_compare() {
+    let gradient = Rgb32FImage::from_fn(16, 16, |x, y| image::Rgb([x as f32/16.0, y as f32/16.0, 0.5]));
+    assert_eq!(compare(&gradient, &gradient), Ok(()));
+
+    // Noise that averages out over the blocks, a firefly and a broken pixel pass
+    let mut noisy = Rgb32FImage::from_fn(16, 16, |x, y| {
+        let noise = if (x + y) % 2 == 0 { 0.02 } else { -0.02 };
+        image::Rgb([x as f32/16.0 + noise, y as f32/16.0 - noise, 0.5])
+    });
+    noisy.put_pixel(3, 3, image::Rgb([1000.0, 1000.0, 1000.0]));
+    noisy.put_pixel(9, 3, image::Rgb([f32::NAN, 0.0, 0.0]));
+    assert_eq!(compare(&noisy, &gradient), Ok(()));
+
+    let tinted = Rgb32FImage::from_fn(16, 16, |x, y| image::Rgb([x as f32/16.0, y as f32/16.0, 0.6]));
+    assert!(compare(&tinted, &gradient).is_err());
+    let mut dark_corner = gradient.clone();
+    for (x, y) in (0..4).flat_map(|x| (0..4).map(move |y| (x, y))) {
+        dark_corner.put_pixel(x, y, image::Rgb([0.0, 0.0, 0.0]));
+    }
+    assert!(compare(&dark_corner, &gradient).is_err());
+    assert!(compare(&Rgb32FImage::new(16, 8), &gradient).is_err());
+}